@@ -0,0 +1,54 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{Data, DeriveInput, Fields, Index, spanned::Spanned};
+
+/// generates ArgEncode for a struct, which encodes its fields in declaration order
+pub fn derive_arg_encode(input: DeriveInput) -> syn::Result<TokenStream> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new(
+            input.span(),
+            "ArgEncode can only be derived for structs",
+        ));
+    };
+
+    let (encode, decode) = match &data.fields {
+        Fields::Named(fields) => {
+            let names = fields
+                .named
+                .iter()
+                .map(|field| field.ident.as_ref().unwrap())
+                .collect::<Vec<_>>();
+            (
+                quote! { #(crate::kernel::threading::argblock::ArgEncode::encode(&self.#names, block);)* },
+                quote! { Self { #(#names: reader.read()?,)* } },
+            )
+        }
+        Fields::Unnamed(fields) => {
+            let indices = (0..fields.unnamed.len())
+                .map(Index::from)
+                .collect::<Vec<_>>();
+            let reads = indices.iter().map(|_| quote! { reader.read()? });
+            (
+                quote! { #(crate::kernel::threading::argblock::ArgEncode::encode(&self.#indices, block);)* },
+                quote! { Self(#(#reads,)*) },
+            )
+        }
+        Fields::Unit => (quote! { _ = block; }, quote! { { _ = reader; Self } }),
+    };
+
+    Ok(quote! {
+        impl #impl_generics crate::kernel::threading::argblock::ArgEncode for #name #ty_generics #where_clause {
+            fn encode(&self, block: &mut crate::kernel::threading::argblock::ArgBlock) {
+                #encode
+            }
+
+            fn decode(
+                reader: &mut crate::kernel::threading::argblock::ArgReader<'_>,
+            ) -> Result<Self, crate::kernel::threading::argblock::ArgDecodeError> {
+                Ok(#decode)
+            }
+        }
+    })
+}
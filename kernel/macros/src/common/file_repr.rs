@@ -0,0 +1,103 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{DeriveInput, Expr, Ident, Meta, Token, punctuated::Punctuated, spanned::Spanned};
+
+/// options of #[file(...)]
+struct FileAttrs {
+    node: Option<Expr>,
+    read: bool,
+    write: bool,
+    waiter: Option<Ident>,
+}
+
+impl FileAttrs {
+    fn from_input(input: &DeriveInput) -> syn::Result<Self> {
+        let mut attrs = Self {
+            node: None,
+            read: false,
+            write: false,
+            waiter: None,
+        };
+        for attr in input
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("file"))
+        {
+            for meta in attr.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)? {
+                match meta {
+                    Meta::Path(p) if p.is_ident("read") => attrs.read = true,
+                    Meta::Path(p) if p.is_ident("write") => attrs.write = true,
+                    Meta::NameValue(nv) if nv.path.is_ident("node") => attrs.node = Some(nv.value),
+                    Meta::NameValue(nv) if nv.path.is_ident("waiter") => {
+                        let Expr::Path(p) = &nv.value else {
+                            return Err(syn::Error::new(nv.value.span(), "expected a method name"));
+                        };
+                        let Some(ident) = p.path.get_ident() else {
+                            return Err(syn::Error::new(p.span(), "expected a method name"));
+                        };
+                        attrs.waiter = Some(ident.clone());
+                    }
+                    other => return Err(syn::Error::new(other.span(), "unknown file option")),
+                }
+            }
+        }
+        Ok(attrs)
+    }
+}
+
+/// generates FileRepr and IOCapable, together with NotSupported stubs for Read and Write, unless the type implements them itself
+pub fn derive_file_repr(input: DeriveInput) -> syn::Result<TokenStream> {
+    let attrs = FileAttrs::from_input(&input)?;
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let node = attrs
+        .node
+        .map(|node| quote! { #node })
+        .unwrap_or_else(|| quote! { tinyos_abi::flags::NodeType::FILE });
+
+    let waiter = attrs.waiter.map(|waiter| {
+        quote! {
+            fn get_waiter(&self) -> Option<crate::kernel::threading::wait::QueuTypeCondition> {
+                self.#waiter()
+            }
+        }
+    });
+
+    let read = (!attrs.read).then(|| {
+        quote! {
+            impl #impl_generics crate::kernel::io::Read for #name #ty_generics #where_clause {
+                fn read(&self, _buf: &mut [u8], _offset: usize) -> crate::kernel::io::IOResult<usize> {
+                    Err(crate::kernel::io::IOError::simple(crate::kernel::fs::FSErrorKind::NotSupported))
+                }
+            }
+        }
+    });
+
+    let write = (!attrs.write).then(|| {
+        quote! {
+            impl #impl_generics crate::kernel::io::Write for #name #ty_generics #where_clause {
+                fn write(&self, _buf: &[u8], _offset: usize) -> crate::kernel::io::IOResult<usize> {
+                    Err(crate::kernel::io::IOError::simple(crate::kernel::fs::FSErrorKind::NotSupported))
+                }
+            }
+        }
+    });
+
+    Ok(quote! {
+        impl #impl_generics crate::kernel::fd::FileRepr for #name #ty_generics #where_clause {
+            fn fstat(&self) -> tinyos_abi::types::FStat {
+                let mut stat = tinyos_abi::types::FStat::default();
+                stat.node_type = #node;
+                stat
+            }
+
+            #waiter
+        }
+
+        impl #impl_generics crate::kernel::fd::IOCapable for #name #ty_generics #where_clause {}
+
+        #read
+        #write
+    })
+}
@@ -1,2 +1,4 @@
+pub mod arg_encode;
 pub mod args;
 pub mod fd_table;
+pub mod file_repr;
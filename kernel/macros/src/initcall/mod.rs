@@ -0,0 +1,96 @@
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{
+    Expr,
+    ExprLit,
+    ItemFn,
+    Lit,
+    MetaNameValue,
+    ReturnType,
+    Token,
+    parse::{Parse, ParseStream},
+    punctuated::Punctuated,
+    spanned::Spanned,
+};
+
+pub struct InitTaskAttrs {
+    stage: proc_macro2::Ident,
+    order: u32,
+}
+
+impl Parse for InitTaskAttrs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let span = input.span();
+        let mut stage = None;
+        let mut order = 0;
+        for attr in Punctuated::<MetaNameValue, Token![,]>::parse_terminated(input)? {
+            let Expr::Lit(ExprLit { lit, .. }) = &attr.value else {
+                return Err(syn::Error::new(attr.value.span(), "expected a literal"));
+            };
+            match lit {
+                Lit::Str(s) if attr.path.is_ident("stage") => {
+                    let ident = match s.value().as_str() {
+                        "early" => "Early",
+                        "fs" => "Fs",
+                        "drivers" => "Drivers",
+                        "shutdown" => "Shutdown",
+                        _ => {
+                            return Err(syn::Error::new(
+                                s.span(),
+                                "unknown stage, expected one of early, fs, drivers, shutdown",
+                            ));
+                        }
+                    };
+                    stage = Some(format_ident!("{}", ident));
+                }
+                Lit::Int(i) if attr.path.is_ident("order") => order = i.base10_parse()?,
+                _ => return Err(syn::Error::new(attr.span(), "unknown init_task option")),
+            }
+        }
+        let stage = stage.ok_or_else(|| syn::Error::new(span, "missing init stage"))?;
+        Ok(Self { stage, order })
+    }
+}
+
+/// keeps the function as is and places an InitCall for it into the .initcalls link section
+pub fn init_task_handler(attrs: InitTaskAttrs, func: ItemFn) -> syn::Result<TokenStream> {
+    let name = &func.sig.ident;
+    if !func.sig.inputs.is_empty() {
+        return Err(syn::Error::new(
+            func.sig.inputs.span(),
+            "init tasks cannot take arguments",
+        ));
+    }
+    let stage = &attrs.stage;
+    let order = attrs.order;
+    let wrapper = format_ident!("__initcall_{}", name);
+    let entry = format_ident!("__INITCALL_{}", name);
+
+    let call = match &func.sig.output {
+        ReturnType::Default => quote! {
+            #name();
+            Ok(())
+        },
+        ReturnType::Type(..) => quote! { #name().map_err(Into::into) },
+    };
+
+    Ok(quote! {
+        #func
+
+        #[doc(hidden)]
+        fn #wrapper() -> crate::KernelRes<()> {
+            #call
+        }
+
+        #[doc(hidden)]
+        #[allow(non_upper_case_globals)]
+        #[used]
+        #[unsafe(link_section = ".initcalls")]
+        static #entry: crate::kernel::init::InitCall = crate::kernel::init::InitCall {
+            name: concat!(module_path!(), "::", stringify!(#name)),
+            stage: crate::kernel::init::InitStage::#stage,
+            order: #order,
+            func: #wrapper,
+        };
+    })
+}
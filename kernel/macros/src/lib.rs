@@ -1,14 +1,20 @@
 #![allow(unused_doc_comments)]
 mod common;
+mod initcall;
 mod mem;
+mod syscall;
 mod test_gen;
 use common::{
+    arg_encode::derive_arg_encode,
     args::default_arg_parser,
     fd_table::{CompositeTagAttrs, derive_composite_fd_tag, derive_fd_table},
+    file_repr::derive_file_repr,
 };
+use initcall::{InitTaskAttrs, init_task_handler};
 use mem::addr::derive_addr;
 use proc_macro::TokenStream;
-use syn::{DeriveInput, ItemStruct, parse_macro_input};
+use syn::{DeriveInput, ItemFn, ItemStruct, parse_macro_input};
+use syscall::{SysCallAttrs, syscall_handler};
 use test_gen::kernel_test_handler;
 
 #[proc_macro_attribute]
@@ -36,6 +42,12 @@ pub fn kernel_test(attr: TokenStream, input: TokenStream) -> TokenStream {
     /// #[used]
     /// #[unsafe(link_section = .tests)]
     /// pub static test: KernelTest = KernelTest { ... };
+    ///
+    /// Tests may take arguments. Arguments marked #[fixture] are set up through crate::common::fixtures::Fixture before
+    /// and torn down after the test, all others are taken from cases(...), which generates one test per case:
+    ///
+    /// #[kernel_test(cases((1, 2), (2, 3)))]
+    /// fn test(a: u32, b: u32, #[fixture] fs: &FreshRamFS) {}
     kernel_test_handler(attr, input)
 }
 
@@ -44,12 +56,51 @@ pub fn with_default_args(attr: TokenStream, input: TokenStream) -> TokenStream {
     default_arg_parser(attr, input)
 }
 
+/// implements ArgEncode for a struct by encoding its fields in declaration order, such that it can be passed to a task
+/// in an ArgBlock. All fields must implement ArgEncode themselves
+/// ```ignore
+/// #[derive(ArgEncode)]
+/// struct Config {
+///     name: String,
+///     retries: u32,
+/// }
+/// ```
+#[proc_macro_derive(ArgEncode)]
+pub fn arg_encode(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    derive_arg_encode(input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
 #[proc_macro_derive(FDTable)]
 pub fn fdtable(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
     derive_fd_table(input).into()
 }
 
+/// implements FileRepr and IOCapable for a device.
+/// Read and Write are stubbed out with NotSupported, unless they are listed in #[file(...)], in which case the type implements them itself.
+/// ```ignore
+/// #[derive(Debug, FileRepr)]
+/// #[file(node = NodeType::FILE, read, waiter = waiter)]
+/// struct Device;
+///
+/// impl Read for Device { ... }
+///
+/// impl Device {
+///     fn waiter(&self) -> Option<QueuTypeCondition> { ... }
+/// }
+/// ```
+/// node defaults to NodeType::FILE
+#[proc_macro_derive(FileRepr, attributes(file))]
+pub fn file_repr(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    derive_file_repr(input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
 #[proc_macro_attribute]
 pub fn fd_composite_tag(attr: TokenStream, input: TokenStream) -> TokenStream {
     let attrs = parse_macro_input!(attr as CompositeTagAttrs);
@@ -57,6 +108,39 @@ pub fn fd_composite_tag(attr: TokenStream, input: TokenStream) -> TokenStream {
     derive_composite_fd_tag(attrs, input).into()
 }
 
+/// registers a syscall handler in the dispatch table.
+///
+/// #[syscall(number = SysCallDispatch::Close)]
+/// pub fn close(fd: FileDescriptor) -> SysCallRes<()> {}
+///
+/// The raw arguments are decoded into the parameter types (which must implement SysCallArg) and the return value is
+/// converted through SysCallRet. Numbers above MAX_SYSCALL are rejected at compile time
+#[proc_macro_attribute]
+pub fn syscall(attr: TokenStream, input: TokenStream) -> TokenStream {
+    let attrs = parse_macro_input!(attr as SysCallAttrs);
+    let input = parse_macro_input!(input as ItemFn);
+    syscall_handler(attrs, input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+/// registers a function to run during boot.
+///
+/// #[init_task(stage = "fs", order = 10)]
+/// fn init() {}
+///
+/// stage is one of early, fs, drivers or shutdown, which does not run during boot, but on poweroff.
+/// Within a stage, init tasks run by ascending order (default 0).
+/// The function may return () or a Result, whose error converts into KernelError
+#[proc_macro_attribute]
+pub fn init_task(attr: TokenStream, input: TokenStream) -> TokenStream {
+    let attrs = parse_macro_input!(attr as InitTaskAttrs);
+    let input = parse_macro_input!(input as ItemFn);
+    init_task_handler(attrs, input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,101 @@
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{
+    Expr,
+    FnArg,
+    ItemFn,
+    MetaNameValue,
+    ReturnType,
+    Token,
+    Type,
+    parse::{Parse, ParseStream},
+    punctuated::Punctuated,
+    spanned::Spanned,
+};
+
+pub struct SysCallAttrs {
+    number: Expr,
+}
+
+impl Parse for SysCallAttrs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let span = input.span();
+        let mut number = None;
+        for attr in Punctuated::<MetaNameValue, Token![,]>::parse_terminated(input)? {
+            if attr.path.is_ident("number") {
+                number = Some(attr.value);
+            } else {
+                return Err(syn::Error::new(attr.path.span(), "unknown syscall option"));
+            }
+        }
+        let number = number.ok_or_else(|| syn::Error::new(span, "missing syscall number"))?;
+        Ok(Self { number })
+    }
+}
+
+/// keeps the handler as is and generates a wrapper, which decodes the raw arguments into the parameter types of the handler
+/// and converts its return value, together with an entry in the .syscalls link section, which is used to build the dispatch table
+pub fn syscall_handler(attrs: SysCallAttrs, func: ItemFn) -> syn::Result<TokenStream> {
+    let name = &func.sig.ident;
+    let number = &attrs.number;
+    let wrapper = format_ident!("__syscall_{}", name);
+    let entry = format_ident!("__SYSCALL_{}", name);
+
+    let mut tys = Vec::new();
+    for arg in &func.sig.inputs {
+        match arg {
+            FnArg::Typed(arg) => tys.push(arg.ty.as_ref().clone()),
+            FnArg::Receiver(r) => {
+                return Err(syn::Error::new(
+                    r.span(),
+                    "syscall handlers cannot take self",
+                ));
+            }
+        }
+    }
+    let names: Vec<_> = (0..tys.len()).map(|i| format_ident!("arg{}", i)).collect();
+
+    let decode = if tys.is_empty() {
+        quote! { let _ = ctx; }
+    } else {
+        quote! {
+            let (#(#names,)*): (#(#tys,)*) = crate::kernel::abi::syscalls::args::decode(ctx)?;
+        }
+    };
+    let call = match &func.sig.output {
+        ReturnType::Type(_, ty) if matches!(ty.as_ref(), Type::Never(_)) => {
+            quote! { #name(#(#names),*) }
+        }
+        _ => quote! {
+            #name(#(#names),*).map(crate::kernel::abi::syscalls::SysCallRet::into_raw)
+        },
+    };
+
+    Ok(quote! {
+        #func
+
+        #[doc(hidden)]
+        fn #wrapper(
+            ctx: &crate::arch::context::SysCallCtx,
+        ) -> tinyos_abi::types::SysCallRes<u64> {
+            #decode
+            #call
+        }
+
+        const _: () = assert!(
+            (#number) as u64 <= tinyos_abi::consts::MAX_SYSCALL,
+            concat!("the number of syscall ", stringify!(#name), " is above MAX_SYSCALL")
+        );
+
+        #[doc(hidden)]
+        #[allow(non_upper_case_globals)]
+        #[used]
+        #[unsafe(link_section = ".syscalls")]
+        static #entry: crate::kernel::abi::syscalls::SysCallEntry =
+            crate::kernel::abi::syscalls::SysCallEntry {
+                number: (#number) as u64,
+                name: stringify!(#name),
+                handler: #wrapper,
+            };
+    })
+}
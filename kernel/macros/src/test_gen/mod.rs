@@ -4,10 +4,18 @@ use syn::{
     Expr,
     ExprAssign,
     ExprLit,
+    FnArg,
     Ident,
     ItemFn,
-    parse::{Parse, Parser},
+    LitStr,
+    Meta,
+    Pat,
+    Token,
+    Type,
+    TypeReference,
+    parse::Parser,
     punctuated::Punctuated,
+    spanned::Spanned,
 };
 use tiny_os_common::testing::TestConfig;
 
@@ -33,35 +41,223 @@ impl ToTokens for CABIFunc {
     }
 }
 
-impl Parse for CABIFunc {
-    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
-        let mut inner: ItemFn = input.parse()?;
+impl From<ItemFn> for CABIFunc {
+    fn from(mut inner: ItemFn) -> Self {
         let name = inner.sig.ident.clone();
         inner.sig.ident = format_ident!("{}_inner__", inner.sig.ident);
-        Ok(Self { inner, name })
+        Self { inner, name }
     }
 }
 
+/// how a #[fixture] argument is passed to the test
+enum FixturePass {
+    Ref,
+    Mut,
+    Value,
+}
+
+/// an argument of a parameterized test
+enum TestParam {
+    /// taken from the current case
+    Case,
+    /// set up before and torn down after the test
+    Fixture(Box<Type>, FixturePass),
+}
+
 pub fn kernel_test_handler(
     attr: proc_macro::TokenStream,
     input: proc_macro::TokenStream,
 ) -> proc_macro::TokenStream {
-    let func: CABIFunc = syn::parse_macro_input!(input as CABIFunc);
-    let attrs = syn::punctuated::Punctuated::<syn::Meta, syn::Token![,]>::parse_terminated
+    let func = syn::parse_macro_input!(input as ItemFn);
+    let attrs = Punctuated::<Meta, Token![,]>::parse_terminated
         .parse(attr)
         .expect("malformed attrs");
-    let name = func.name.clone();
-    let config = TestConfigParser::parse(attrs, &name);
-    let static_name = format_ident!("__STATIC_{}", name);
-    let get_name_name = format_ident!("__GET_NAME_{}", name);
 
-    quote! {
+    let mut cases = None;
+    let mut config_attrs = Punctuated::<Meta, Token![,]>::new();
+    for attr in attrs {
+        match attr {
+            Meta::List(list) if list.path.is_ident("cases") => {
+                match list.parse_args_with(Punctuated::<Expr, Token![,]>::parse_terminated) {
+                    Ok(parsed) => cases = Some(parsed.into_iter().collect::<Vec<_>>()),
+                    Err(e) => return e.to_compile_error().into(),
+                }
+            }
+            attr => config_attrs.push(attr),
+        }
+    }
+    let config = TestConfigParser::parse(config_attrs, &func.sig.ident);
+
+    if func.sig.inputs.is_empty() && cases.is_none() {
+        let func = CABIFunc::from(func);
+        let name = &func.name;
+        let entry = test_entry(name, quote! { stringify!(#name) }, &config);
+        return quote! {
+            #[cfg(feature = "test_run")]
+            #func
+
+            #entry
+        }
+        .into();
+    }
+
+    parameterized_test(func, cases, &config)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+/// generates one test per case, each of which sets up its fixtures, calls the test body and tears the fixtures down again
+fn parameterized_test(
+    mut func: ItemFn,
+    cases: Option<Vec<Expr>>,
+    config: &TestConfigParser,
+) -> syn::Result<TokenStream> {
+    let name = func.sig.ident.clone();
+    let inner_name = format_ident!("{}_inner__", name);
+    func.sig.ident = inner_name.clone();
+
+    let mut params = Vec::new();
+    for arg in func.sig.inputs.iter_mut() {
+        let FnArg::Typed(arg) = arg else {
+            return Err(syn::Error::new(arg.span(), "kernel tests cannot take self"));
+        };
+        let n_attrs = arg.attrs.len();
+        arg.attrs.retain(|attr| !attr.path().is_ident("fixture"));
+        if arg.attrs.len() == n_attrs {
+            params.push(TestParam::Case);
+            continue;
+        }
+        let (ty, pass) = match arg.ty.as_ref() {
+            Type::Reference(TypeReference {
+                mutability, elem, ..
+            }) => (
+                elem.as_ref().clone(),
+                if mutability.is_some() {
+                    FixturePass::Mut
+                } else {
+                    FixturePass::Ref
+                },
+            ),
+            ty => (ty.clone(), FixturePass::Value),
+        };
+        if !matches!(arg.pat.as_ref(), Pat::Ident(_) | Pat::Wild(_)) {
+            return Err(syn::Error::new(arg.pat.span(), "expected an identifier"));
+        }
+        params.push(TestParam::Fixture(Box::new(ty), pass));
+    }
+
+    let n_case_params = params
+        .iter()
+        .filter(|param| matches!(param, TestParam::Case))
+        .count();
+    let cases = match cases {
+        Some(cases) if n_case_params == 0 => {
+            return Err(syn::Error::new(
+                cases.first().map_or(name.span(), |case| case.span()),
+                "cases given, but all arguments are fixtures",
+            ));
+        }
+        Some(cases) => cases.into_iter().map(Some).collect(),
+        None if n_case_params != 0 => {
+            return Err(syn::Error::new(
+                func.sig.inputs.span(),
+                "arguments, which are not #[fixture], require cases(...)",
+            ));
+        }
+        None => vec![None],
+    };
+    let single_run = cases.len() == 1 && cases[0].is_none();
+
+    let mut generated = TokenStream::new();
+    for (i, case) in cases.into_iter().enumerate() {
+        let mut case_args = match case {
+            None => Vec::new(),
+            Some(case) if n_case_params == 1 => vec![case],
+            Some(Expr::Tuple(tuple)) if tuple.elems.len() == n_case_params => {
+                tuple.elems.into_iter().collect()
+            }
+            Some(case) => {
+                return Err(syn::Error::new(
+                    case.span(),
+                    format!("expected a tuple of {} values", n_case_params),
+                ));
+            }
+        }
+        .into_iter();
+
+        let mut setup = Vec::new();
+        let mut teardown = Vec::new();
+        let mut call_args = Vec::new();
+        for (j, param) in params.iter().enumerate() {
+            match param {
+                TestParam::Case => call_args.push(case_args.next().unwrap().into_token_stream()),
+                TestParam::Fixture(ty, pass) => {
+                    let var = format_ident!("__fixture{}", j);
+                    let init = quote! { <#ty as crate::common::fixtures::Fixture>::setup() };
+                    match pass {
+                        FixturePass::Ref => {
+                            setup.push(quote! { let #var = #init; });
+                            call_args.push(quote! { &#var });
+                        }
+                        FixturePass::Mut => {
+                            setup.push(quote! { let mut #var = #init; });
+                            call_args.push(quote! { &mut #var });
+                        }
+                        FixturePass::Value => {
+                            setup.push(quote! { let #var = #init; });
+                            call_args.push(quote! { #var });
+                            continue;
+                        }
+                    }
+                    teardown.push(quote! { crate::common::fixtures::Fixture::teardown(#var); });
+                }
+            }
+        }
+        // fixtures are torn down in reverse order of their setup
+        teardown.reverse();
+
+        let (case_name, display) = if single_run {
+            (name.clone(), quote! { stringify!(#name) })
+        } else {
+            let suffix = LitStr::new(&format!("[{}]", i), name.span());
+            (
+                format_ident!("{}_case{}", name, i),
+                quote! { concat!(stringify!(#name), #suffix) },
+            )
+        };
+        let entry = test_entry(&case_name, display, config);
+
+        generated.extend(quote! {
+            #[cfg(feature = "test_run")]
+            #[os_macros::with_default_args]
+            extern "C" fn #case_name() -> crate::kernel::threading::ProcessReturn {
+                #(#setup)*
+                #inner_name(#(#call_args),*);
+                #(#teardown)*
+                0
+            }
+
+            #entry
+        });
+    }
+
+    Ok(quote! {
         #[cfg(feature = "test_run")]
         #func
 
+        #generated
+    })
+}
+
+/// the .tests entry of the test function name, displayed as module_path::display
+fn test_entry(name: &Ident, display: TokenStream, config: &TestConfigParser) -> TokenStream {
+    let static_name = format_ident!("__STATIC_{}", name);
+    let get_name_name = format_ident!("__GET_NAME_{}", name);
+
+    quote! {
         #[cfg(feature = "test_run")]
         #[allow(non_upper_case_globals)]
-        const #get_name_name: &'static str = concat!(module_path!(), "::", stringify!(#name));
+        const #get_name_name: &'static str = concat!(module_path!(), "::", #display);
 
         #[cfg(feature = "test_run")]
         #[allow(non_upper_case_globals)]
@@ -73,7 +269,6 @@ pub fn kernel_test_handler(
             config: #config
         };
     }
-    .into()
 }
 
 #[derive(Default)]
@@ -3,7 +3,7 @@ pub mod x86;
 use core::{arch::asm, fmt::Arguments};
 
 #[cfg(target_arch = "x86_64")]
-pub use x86::{context, interrupt, mem};
+pub use x86::{context, interrupt, mem, percpu};
 
 pub fn early_init() {
     #[cfg(target_arch = "x86_64")]
@@ -25,6 +25,21 @@ pub fn hcf() -> ! {
     }
 }
 
+pub fn reboot() -> ! {
+    #[cfg(target_arch = "x86_64")]
+    x86::cpu::reboot();
+    #[cfg(not(any(target_arch = "x86_64")))]
+    compile_error!("arch not supported")
+}
+
+/// stops all other cpus for good, before the machine is powered off
+pub fn park_other_cpus() {
+    #[cfg(target_arch = "x86_64")]
+    x86::cpu::park_others();
+    #[cfg(not(any(target_arch = "x86_64")))]
+    compile_error!("arch not supported")
+}
+
 pub fn timer() {
     #[cfg(target_arch = "x86_64")]
     x86::interrupt::timer();
@@ -43,6 +58,59 @@ pub fn hlt() {
     }
 }
 
+pub fn hw_random() -> Option<u64> {
+    #[cfg(target_arch = "x86_64")]
+    return x86::random::hw_random();
+    #[cfg(not(any(target_arch = "x86_64")))]
+    compile_error!("arch not supported")
+}
+
+pub fn timestamp() -> u64 {
+    #[cfg(target_arch = "x86_64")]
+    return x86::random::timestamp();
+    #[cfg(not(any(target_arch = "x86_64")))]
+    compile_error!("arch not supported")
+}
+
+/// frequency of timestamp() in Hz. Falls back to 3 GHz, if it cannot be determined
+pub fn timestamp_frequency() -> u64 {
+    #[cfg(target_arch = "x86_64")]
+    return x86::cpu::tsc_frequency().unwrap_or(3_000_000_000);
+    #[cfg(not(any(target_arch = "x86_64")))]
+    compile_error!("arch not supported")
+}
+
+/// returns (cpu, numa node) of the calling cpu
+pub fn current_cpu() -> (u32, u32) {
+    #[cfg(target_arch = "x86_64")]
+    return (x86::cpu::current_cpu(), x86::cpu::current_node());
+    #[cfg(not(any(target_arch = "x86_64")))]
+    compile_error!("arch not supported")
+}
+
+pub fn cpuinfo() -> alloc::string::String {
+    #[cfg(target_arch = "x86_64")]
+    return x86::cpu::cpuinfo();
+    #[cfg(not(any(target_arch = "x86_64")))]
+    compile_error!("arch not supported")
+}
+
+/// the index of the current cpu in the cpu topology. Usable from any context, including interrupt handlers
+pub fn cpu_slot() -> usize {
+    #[cfg(target_arch = "x86_64")]
+    return x86::interrupt::stats::cpu_slot();
+    #[cfg(not(any(target_arch = "x86_64")))]
+    compile_error!("arch not supported")
+}
+
+/// per cpu interrupt counters, rendered in the format of /proc/interrupts
+pub fn interrupts() -> alloc::string::String {
+    #[cfg(target_arch = "x86_64")]
+    return x86::interrupt::stats::render_interrupts();
+    #[cfg(not(any(target_arch = "x86_64")))]
+    compile_error!("arch not supported")
+}
+
 pub fn current_page_tbl() -> (x86::mem::PhysFrame<x86::mem::Size4KiB>, x86::mem::Cr3Flags) {
     #[cfg(target_arch = "x86_64")]
     return x86::mem::Cr3::read();
@@ -58,6 +126,23 @@ pub fn _serial_print(args: Arguments) {
     compile_error!("arch not supported")
 }
 
+/// prints a line, which is dropped first, once the serial output cannot keep up
+#[doc(hidden)]
+pub fn _serial_debug_print(args: Arguments) {
+    #[cfg(target_arch = "x86_64")]
+    x86::serial::_print_level(x86::serial::LogLevel::Debug, args);
+    #[cfg(not(any(target_arch = "x86_64")))]
+    compile_error!("arch not supported")
+}
+
+/// the counters of the queued serial output, rendered in the format of /proc/kernel/serial
+pub fn serial_stats() -> alloc::string::String {
+    #[cfg(target_arch = "x86_64")]
+    return x86::serial::tx_stats().render();
+    #[cfg(not(any(target_arch = "x86_64")))]
+    compile_error!("arch not supported")
+}
+
 #[doc(hidden)]
 pub fn _raw_serial_print(slice: &[u8]) {
     #[cfg(target_arch = "x86_64")]
@@ -0,0 +1,28 @@
+use x86_64::instructions::{interrupts, port::Port};
+
+const INDEX: u16 = 0x70;
+const DATA: u16 = 0x71;
+/// set in the index port, nmis stay disabled while a register is accessed
+const NMI_DISABLE: u8 = 1 << 7;
+/// the registers of the rtc and the firmware come first, everything up to here is nvram
+pub const LAST_REGISTER: u8 = 0x7f;
+
+/// reads a byte of the battery backed cmos ram. Its contents survive resets
+pub fn read(register: u8) -> u8 {
+    debug_assert!(register <= LAST_REGISTER);
+    interrupts::without_interrupts(|| unsafe {
+        Port::<u8>::new(INDEX).write(NMI_DISABLE | register);
+        let value = Port::<u8>::new(DATA).read();
+        Port::<u8>::new(INDEX).write(0);
+        value
+    })
+}
+
+pub fn write(register: u8, value: u8) {
+    debug_assert!(register <= LAST_REGISTER);
+    interrupts::without_interrupts(|| unsafe {
+        Port::<u8>::new(INDEX).write(NMI_DISABLE | register);
+        Port::<u8>::new(DATA).write(value);
+        Port::<u8>::new(INDEX).write(0);
+    })
+}
@@ -0,0 +1,325 @@
+use alloc::boxed::Box;
+use core::{
+    arch::asm,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering},
+};
+
+use raw_cpuid::CpuId;
+use x86_64::registers::{
+    control::{Cr0, Cr0Flags, Cr4, Cr4Flags},
+    xcontrol::{XCr0, XCr0Flags},
+};
+
+use crate::arch::x86::interrupt::without_interrupts;
+
+/// bytes reserved for the extended state of a task. Fits x87, SSE and AVX in the standard xsave format (832 bytes)
+pub const FPU_AREA_SIZE: usize = 1024;
+
+const FCW_DEFAULT: u16 = 0x37F;
+const MXCSR_DEFAULT: u32 = 0x1F80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FpuMode {
+    /// x87 and SSE state through fxsave/fxrstor
+    FxSave,
+    /// all state components enabled in XCR0 through xsave/xrstor
+    XSave,
+}
+
+static XSAVE: AtomicBool = AtomicBool::new(false);
+static XSAVE_MASK: AtomicU64 = AtomicU64::new(0);
+static LAZY: AtomicBool = AtomicBool::new(true);
+// the area of the running task, and the area of the task whose state currently lives in the registers.
+// They only differ in lazy mode, until the running task touches the fpu. Only the boot processor is brought up
+static CURRENT: AtomicPtr<FpuArea> = AtomicPtr::new(ptr::null_mut());
+static OWNER: AtomicPtr<FpuArea> = AtomicPtr::new(ptr::null_mut());
+static SWITCHES: AtomicU64 = AtomicU64::new(0);
+static LAZY_RESTORES: AtomicU64 = AtomicU64::new(0);
+
+#[repr(C, align(64))]
+pub struct FpuArea([u8; FPU_AREA_SIZE]);
+
+impl FpuArea {
+    /// the state after finit: all exceptions masked and empty registers.
+    /// The xsave header is zeroed, thus xrstor puts every other component into its init state
+    pub fn new() -> Self {
+        let mut area = [0; FPU_AREA_SIZE];
+        area[0..2].copy_from_slice(&FCW_DEFAULT.to_le_bytes());
+        area[24..28].copy_from_slice(&MXCSR_DEFAULT.to_le_bytes());
+        Self(area)
+    }
+}
+
+impl Default for FpuArea {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// the saved simd and floating point registers of a task
+pub struct FpuState {
+    area: Box<FpuArea>,
+}
+
+impl FpuState {
+    pub fn new() -> Self {
+        Self {
+            area: Box::default(),
+        }
+    }
+
+    fn as_ptr(&self) -> *mut FpuArea {
+        ptr::from_ref(&*self.area).cast_mut()
+    }
+}
+
+impl Default for FpuState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl core::fmt::Debug for FpuState {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("FpuState")
+            .field("area", &self.as_ptr())
+            .finish()
+    }
+}
+
+impl Drop for FpuState {
+    fn drop(&mut self) {
+        // the registers may still hold the state of this task, which must not be saved into freed memory
+        _ = OWNER.compare_exchange(
+            self.as_ptr(),
+            ptr::null_mut(),
+            Ordering::AcqRel,
+            Ordering::Relaxed,
+        );
+    }
+}
+
+/// enables sse and, if supported, xsave with the AVX state component
+pub(in crate::arch::x86) fn init() {
+    unsafe {
+        Cr4::update(|cr4| {
+            cr4.insert(Cr4Flags::OSFXSR);
+            cr4.insert(Cr4Flags::OSXMMEXCPT_ENABLE);
+        });
+    }
+    let cpuid = CpuId::new();
+    let Some(features) = cpuid.get_feature_info() else {
+        return;
+    };
+    if !features.has_xsave() {
+        return;
+    }
+    let mut xcr0 = XCr0Flags::X87 | XCr0Flags::SSE;
+    if features.has_avx() {
+        xcr0 |= XCr0Flags::AVX;
+    }
+    unsafe {
+        Cr4::update(|cr4| cr4.insert(Cr4Flags::OSXSAVE));
+        XCr0::write(xcr0);
+    }
+    // the size reported for the enabled components only changes after XCR0 was written
+    let size = cpuid.get_extended_state_info().map_or(usize::MAX, |info| {
+        info.xsave_area_size_enabled_features() as usize
+    });
+    if size > FPU_AREA_SIZE {
+        xcr0 = XCr0Flags::X87 | XCr0Flags::SSE;
+        unsafe { XCr0::write(xcr0) };
+    }
+    XSAVE_MASK.store(xcr0.bits(), Ordering::Relaxed);
+    XSAVE.store(true, Ordering::Release);
+}
+
+pub fn mode() -> FpuMode {
+    if XSAVE.load(Ordering::Acquire) {
+        FpuMode::XSave
+    } else {
+        FpuMode::FxSave
+    }
+}
+
+/// the state components saved per task, as XCR0 bits
+pub fn saved_components() -> u64 {
+    match mode() {
+        FpuMode::XSave => XSAVE_MASK.load(Ordering::Relaxed),
+        FpuMode::FxSave => (XCr0Flags::X87 | XCr0Flags::SSE).bits(),
+    }
+}
+
+unsafe fn save(area: *mut FpuArea) {
+    if XSAVE.load(Ordering::Relaxed) {
+        let mask = XSAVE_MASK.load(Ordering::Relaxed);
+        unsafe {
+            asm!(
+                "xsave64 [{}]",
+                in(reg) area,
+                in("eax") mask as u32,
+                in("edx") (mask >> 32) as u32,
+                options(nostack, preserves_flags)
+            )
+        };
+    } else {
+        unsafe { asm!("fxsave64 [{}]", in(reg) area, options(nostack, preserves_flags)) };
+    }
+}
+
+unsafe fn restore(area: *const FpuArea) {
+    if XSAVE.load(Ordering::Relaxed) {
+        let mask = XSAVE_MASK.load(Ordering::Relaxed);
+        unsafe {
+            asm!(
+                "xrstor64 [{}]",
+                in(reg) area,
+                in("eax") mask as u32,
+                in("edx") (mask >> 32) as u32,
+                options(nostack, preserves_flags, readonly)
+            )
+        };
+    } else {
+        unsafe {
+            asm!("fxrstor64 [{}]", in(reg) area, options(nostack, preserves_flags, readonly))
+        };
+    }
+}
+
+fn clear_ts() {
+    unsafe { asm!("clts", options(nomem, nostack, preserves_flags)) };
+}
+
+fn set_ts() {
+    unsafe { Cr0::update(|cr0| cr0.insert(Cr0Flags::TASK_SWITCHED)) };
+}
+
+/// switches the fpu state from prev to next. Called with interrupts disabled, right before next is entered.
+/// In lazy mode nothing is saved or restored here. Instead CR0.TS is set, unless the registers already hold the state of next,
+/// such that the first fpu instruction of next traps into handle_device_not_available.
+/// The kernel itself is built without sse, thus only tasks using simd pay for the switch
+pub fn switch(prev: &FpuState, next: &FpuState) {
+    SWITCHES.fetch_add(1, Ordering::Relaxed);
+    let next = next.as_ptr();
+    CURRENT.store(next, Ordering::Release);
+    if LAZY.load(Ordering::Relaxed) {
+        if OWNER.load(Ordering::Acquire) == next {
+            clear_ts();
+        } else {
+            set_ts();
+        }
+        return;
+    }
+    let prev = prev.as_ptr();
+    if prev == next {
+        return;
+    }
+    unsafe {
+        save(prev);
+        restore(next);
+    }
+    OWNER.store(next, Ordering::Release);
+}
+
+/// handles a #NM exception. Saves the state of the previous owner of the registers and loads the one of the running task.
+/// Returns false, if the exception was not caused by lazy switching
+pub fn handle_device_not_available() -> bool {
+    if !Cr0::read().contains(Cr0Flags::TASK_SWITCHED) {
+        return false;
+    }
+    clear_ts();
+    let current = CURRENT.load(Ordering::Acquire);
+    let owner = OWNER.load(Ordering::Acquire);
+    if current.is_null() || owner == current {
+        return true;
+    }
+    unsafe {
+        if !owner.is_null() {
+            save(owner);
+        }
+        restore(current);
+    }
+    OWNER.store(current, Ordering::Release);
+    LAZY_RESTORES.fetch_add(1, Ordering::Relaxed);
+    true
+}
+
+/// switches between lazy and eager saving. When leaving lazy mode the running task takes over the registers
+pub fn set_lazy(lazy: bool) {
+    without_interrupts(|| {
+        if !lazy && LAZY.load(Ordering::Relaxed) {
+            clear_ts();
+            let current = CURRENT.load(Ordering::Acquire);
+            let owner = OWNER.load(Ordering::Acquire);
+            if !current.is_null() && owner != current {
+                unsafe {
+                    if !owner.is_null() {
+                        save(owner);
+                    }
+                    restore(current);
+                }
+                OWNER.store(current, Ordering::Release);
+            }
+        }
+        LAZY.store(lazy, Ordering::Relaxed);
+    });
+}
+
+pub fn is_lazy() -> bool {
+    LAZY.load(Ordering::Relaxed)
+}
+
+/// context switches seen by the fpu, and how many of them required restoring the state lazily
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FpuStats {
+    pub switches: u64,
+    pub lazy_restores: u64,
+}
+
+pub fn fpu_stats() -> FpuStats {
+    FpuStats {
+        switches: SWITCHES.load(Ordering::Relaxed),
+        lazy_restores: LAZY_RESTORES.load(Ordering::Relaxed),
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+    use crate::kernel::threading;
+
+    fn read_xmm0() -> u64 {
+        let value: u64;
+        unsafe { asm!("movq {}, xmm0", out(reg) value, options(nomem, nostack)) };
+        value
+    }
+
+    fn write_xmm0(value: u64) {
+        unsafe { asm!("movq xmm0, {}", in(reg) value, options(nomem, nostack)) };
+    }
+
+    #[kernel_test]
+    fn xmm_preserved_across_switches() {
+        for lazy in [true, false, true] {
+            set_lazy(lazy);
+            write_xmm0(0xdead_beef_1234);
+            let before = fpu_stats().switches;
+            while fpu_stats().switches < before + 4 {
+                threading::yield_now();
+            }
+            assert_eq!(read_xmm0(), 0xdead_beef_1234);
+        }
+        assert!(is_lazy());
+    }
+
+    #[kernel_test]
+    fn initial_state() {
+        let area = FpuArea::new();
+        assert_eq!(u16::from_le_bytes([area.0[0], area.0[1]]), FCW_DEFAULT);
+        assert_eq!(area.0[512..576], [0; 64]);
+        assert_ne!(saved_components() & XCr0Flags::SSE.bits(), 0);
+    }
+}
@@ -1,13 +1,14 @@
-use core::arch::global_asm;
+use alloc::vec::Vec;
+use core::{arch::global_asm, mem::offset_of};
 
-use lazy_static::lazy_static;
 use spin::Mutex;
+use tinyos_abi::consts::ARG_MAX;
 use x86_64::{registers::rflags::RFlags, structures::paging::OffsetPageTable};
 
 use super::interrupt::gdt::get_user_selectors;
 use crate::{
     arch::{
-        mem::{Mapper, Page, PageSize, PageTableFlags, Size4KiB, VirtAddr},
+        mem::{FrameDeallocator, Mapper, Page, PageSize, PageTableFlags, Size4KiB, VirtAddr},
         x86::{
             interrupt::{gdt::get_kernel_selectors, handlers::InterruptStackFrame},
             mem::PhysAddr,
@@ -29,18 +30,28 @@ use crate::{
         },
         threading::{
             ThreadingError,
-            task::{TaskData, TaskRepr},
+            schedule::preempt::without_preemption,
+            task::{Arg, TaskData, TaskRepr},
             trampoline::TaskExitInfo,
         },
     },
 };
 
+pub mod fpu;
+
 pub const KSTACK_AREA_START: VirtAddr = VirtAddr::new(0xffff_f000_c000_0000); // random location
+pub const KSTACK_AREA_SIZE: usize = 64 * 1024 * 1024 * 1024; // 64 GiB, stays within a single P4 entry
 pub const KSTACK_SIZE: usize = 64 * 1024; // 64 KiB //TODO maybe make this dynamic
-pub const MAX_KSTACKS: usize = 512; // random num (this is also max processes)
+pub const MAX_KSTACKS: usize = KSTACK_AREA_SIZE / KSTACK_SIZE;
+const KSTACK_FLAGS: PageTableFlags = PageTableFlags::WRITABLE
+    .union(PageTableFlags::PRESENT)
+    .union(PageTableFlags::NO_EXECUTE);
 
 pub const USER_STACK_START: VirtAddr = VirtAddr::new(0x0000_0000_1000_0000); // random location
 pub const USER_STACK_SIZE: usize = 1024 * 1024; // 1MiB
+/// the top USER_STACK_COMMITTED bytes of a user stack are mapped on allocation, the rest is backed on first touch.
+/// They hold argv and env, the auxiliary vector and the initial frame, which are written before the task runs
+pub const USER_STACK_COMMITTED: usize = 2 * ARG_MAX + 4 * Size4KiB::SIZE as usize;
 
 const _: () = {
     assert!(
@@ -51,6 +62,11 @@ const _: () = {
         KSTACK_SIZE.is_multiple_of(Size4KiB::SIZE as usize),
         "KSTACK_SIZE must be a page multiple"
     );
+    assert!(
+        (KSTACK_AREA_START.as_u64() >> 39)
+            == ((KSTACK_AREA_START.as_u64() + KSTACK_AREA_SIZE as u64 - 1) >> 39),
+        "the kstack area must not cross a P4 entry"
+    );
     assert!(
         USER_STACK_START.as_u64().is_multiple_of(Size4KiB::SIZE),
         "USER_STACK_START must be page-aligned"
@@ -59,10 +75,72 @@ const _: () = {
         USER_STACK_SIZE.is_multiple_of(Size4KiB::SIZE as usize),
         "USER_STACK_SIZE must be a page multiple"
     );
+    assert!(
+        USER_STACK_COMMITTED.is_multiple_of(Size4KiB::SIZE as usize)
+            && USER_STACK_COMMITTED < USER_STACK_SIZE,
+        "USER_STACK_COMMITTED must be a page multiple within the user stack"
+    );
 };
 
-lazy_static! {
-    static ref KSTACKS_IN_USAGE: Mutex<[bool; MAX_KSTACKS]> = Mutex::new([false; MAX_KSTACKS]);
+// a spin lock, which is only held briefly. Holders disable preemption, such that nobody spins on a
+// preempted holder
+static KSTACKS: Mutex<KStackSlots> = Mutex::new(KStackSlots::new());
+
+/// bitmap of the kstack slots in the kstack area.
+/// The bitmap only grows as far as the highest slot used so far, freed slots are reused lowest first.
+#[derive(Debug)]
+struct KStackSlots {
+    used: Vec<u64>,
+    // all words below hint are full
+    hint: usize,
+}
+
+impl KStackSlots {
+    const fn new() -> Self {
+        Self {
+            used: Vec::new(),
+            hint: 0,
+        }
+    }
+
+    fn alloc(&mut self) -> Option<usize> {
+        if let Some((word, bits)) = self
+            .used
+            .iter_mut()
+            .enumerate()
+            .skip(self.hint)
+            .find(|(_, bits)| **bits != u64::MAX)
+        {
+            let bit = (!*bits).trailing_zeros() as usize;
+            *bits |= 1 << bit;
+            self.hint = word;
+            return Some(word * u64::BITS as usize + bit);
+        }
+        let slot = self.used.len() * u64::BITS as usize;
+        if slot >= MAX_KSTACKS {
+            return None;
+        }
+        self.used.push(1);
+        self.hint = self.used.len() - 1;
+        Some(slot)
+    }
+
+    /// returns false if slot was not in use
+    fn free(&mut self, slot: usize) -> bool {
+        if !self.is_used(slot) {
+            return false;
+        }
+        let word = slot / u64::BITS as usize;
+        self.used[word] &= !(1 << (slot % u64::BITS as usize));
+        self.hint = self.hint.min(word);
+        true
+    }
+
+    fn is_used(&self, slot: usize) -> bool {
+        self.used
+            .get(slot / u64::BITS as usize)
+            .is_some_and(|bits| bits & (1 << (slot % u64::BITS as usize)) != 0)
+    }
 }
 
 #[derive(Debug, Default, Clone, PartialEq, Eq)]
@@ -81,6 +159,10 @@ pub struct SysCallCtx {
     pub r11: u64,
 
     pub rbp: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
 }
 
 impl SysCallCtx {
@@ -233,6 +315,200 @@ pub struct ReducedCpuInfo {
     rax: u64,
 }
 
+/// the frame pushed by the cpu on an interrupt from ring 3, without an error code
+#[derive(Default, Debug)]
+#[repr(C)]
+struct IretFrame {
+    rip: u64,
+    cs: u64,
+    rflags: u64,
+    rsp: u64,
+    ss: u64,
+}
+
+/// the flags user code may change itself. Others, like IOPL or NT, must never be taken over from untrusted registers
+const USER_RFLAGS: RFlags = RFlags::CARRY_FLAG
+    .union(RFlags::PARITY_FLAG)
+    .union(RFlags::AUXILIARY_CARRY_FLAG)
+    .union(RFlags::ZERO_FLAG)
+    .union(RFlags::SIGN_FLAG)
+    .union(RFlags::DIRECTION_FLAG)
+    .union(RFlags::OVERFLOW_FLAG)
+    .union(RFlags::ALIGNMENT_CHECK)
+    .union(RFlags::ID);
+
+/// the general purpose registers of a user thread, as they were when it entered the kernel
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UserRegs {
+    pub rax: u64,
+    pub rbx: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rbp: u64,
+    pub rsp: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub rip: u64,
+    pub rflags: u64,
+}
+
+impl UserRegs {
+    /// reads the registers of a user thread, which is inside a syscall, from the frames syscall_stub left at the top
+    /// of its kernel stack. Returns None, if the frame was not pushed on entry from ring 3.
+    ///
+    /// SAFETY: kstack_top must be the kernel stack top of a thread, which is currently not running and entered
+    /// the kernel through syscall_stub
+    pub unsafe fn from_syscall_frame(kstack_top: VirtAddr) -> Option<Self> {
+        let iret = kstack_top - size_of::<IretFrame>() as u64;
+        let ctx = iret - size_of::<SysCallCtx>() as u64;
+        let (iret, ctx) = unsafe { (&*iret.as_ptr::<IretFrame>(), &*ctx.as_ptr::<SysCallCtx>()) };
+        if iret.cs & 3 != 3 {
+            return None;
+        }
+        Some(Self {
+            rax: ctx.rax,
+            rbx: ctx.rbx,
+            rcx: ctx.rcx,
+            rdx: ctx.rdx,
+            rsi: ctx.rsi,
+            rdi: ctx.rdi,
+            rbp: ctx.rbp,
+            rsp: iret.rsp,
+            r8: ctx.r8,
+            r9: ctx.r9,
+            r10: ctx.r10,
+            r11: ctx.r11,
+            r12: ctx.r12,
+            r13: ctx.r13,
+            r14: ctx.r14,
+            r15: ctx.r15,
+            rip: iret.rip,
+            rflags: iret.rflags,
+        })
+    }
+
+    /// the registers a new user thread starts with, as pushed by init_usr_task below krsp
+    ///
+    /// SAFETY: krsp must be the kernel stack pointer of a user thread, which was built, but never ran
+    pub unsafe fn from_initial_frame(krsp: VirtAddr) -> Self {
+        let (info, iret) = unsafe { initial_frame(krsp) };
+        Self {
+            rax: info.rax,
+            rbx: info.rbx,
+            rcx: info.rcx,
+            rdx: info.rdx,
+            rsi: info.rsi,
+            rdi: info.rdi,
+            rbp: info.rbp,
+            rsp: iret.rsp,
+            r8: info.r8,
+            r9: info.r9,
+            r10: info.r10,
+            r11: info.r11,
+            r12: info.r12,
+            r13: info.r13,
+            r14: info.r14,
+            r15: info.r15,
+            rip: iret.rip,
+            rflags: iret.rflags,
+        }
+    }
+
+    /// overwrites the registers a new user thread starts with. Its segments and address space are kept.
+    /// Interrupts stay enabled in the thread, regardless of rflags, and only the flags user code may set are taken over.
+    ///
+    /// SAFETY: krsp must be the kernel stack pointer of a user thread, which was built, but never ran
+    pub unsafe fn apply_to_initial_frame(&self, krsp: VirtAddr) {
+        let (info, iret) = unsafe { initial_frame(krsp) };
+        info.rax = self.rax;
+        info.rbx = self.rbx;
+        info.rcx = self.rcx;
+        info.rdx = self.rdx;
+        info.rsi = self.rsi;
+        info.rdi = self.rdi;
+        info.rbp = self.rbp;
+        info.r8 = self.r8;
+        info.r9 = self.r9;
+        info.r10 = self.r10;
+        info.r11 = self.r11;
+        info.r12 = self.r12;
+        info.r13 = self.r13;
+        info.r14 = self.r14;
+        info.r15 = self.r15;
+        iret.rip = self.rip;
+        iret.rsp = self.rsp;
+        iret.rflags = ((RFlags::from_bits_truncate(self.rflags) & USER_RFLAGS)
+            | RFlags::INTERRUPT_FLAG)
+            .bits();
+    }
+}
+
+// switch_and_apply pops ReducedCpuInfo field by field, followed by the IretFrame, init_kernel_task and init_usr_task
+// push them in the same order. A field, which is moved, must be moved in the asm as well
+const _: () = {
+    let regs = [
+        offset_of!(ReducedCpuInfo, r8),
+        offset_of!(ReducedCpuInfo, r9),
+        offset_of!(ReducedCpuInfo, r10),
+        offset_of!(ReducedCpuInfo, r11),
+        offset_of!(ReducedCpuInfo, r12),
+        offset_of!(ReducedCpuInfo, r13),
+        offset_of!(ReducedCpuInfo, r14),
+        offset_of!(ReducedCpuInfo, r15),
+        offset_of!(ReducedCpuInfo, cr3),
+        offset_of!(ReducedCpuInfo, rbx),
+        offset_of!(ReducedCpuInfo, rcx),
+        offset_of!(ReducedCpuInfo, rdx),
+        offset_of!(ReducedCpuInfo, rsi),
+        offset_of!(ReducedCpuInfo, rdi),
+        offset_of!(ReducedCpuInfo, rbp),
+        offset_of!(ReducedCpuInfo, rax),
+    ];
+    let mut i = 0;
+    while i < regs.len() {
+        assert!(
+            regs[i] == i * size_of::<u64>(),
+            "ReducedCpuInfo must be laid out in the pop order of switch_and_apply"
+        );
+        i += 1;
+    }
+    assert!(size_of::<ReducedCpuInfo>() == regs.len() * size_of::<u64>());
+    assert!(
+        offset_of!(IretFrame, rip) == 0
+            && offset_of!(IretFrame, cs) == 8
+            && offset_of!(IretFrame, rflags) == 16
+            && offset_of!(IretFrame, rsp) == 24
+            && offset_of!(IretFrame, ss) == 32
+            && size_of::<IretFrame>() == 40,
+        "IretFrame must match the frame iretq pops"
+    );
+    // the fields read by the asm are pushed as a whole
+    assert!(size_of::<VirtAddr>() == 8 && size_of::<PhysAddr>() == 8);
+    assert!(
+        size_of::<Arg>() == 8,
+        "every task argument is passed in a register"
+    );
+};
+
+/// the frame at krsp of a thread, which never ran: its registers, followed by the frame it is started with
+unsafe fn initial_frame<'a>(krsp: VirtAddr) -> (&'a mut ReducedCpuInfo, &'a mut IretFrame) {
+    let iret = krsp + size_of::<ReducedCpuInfo>() as u64;
+    unsafe {
+        (
+            &mut *krsp.as_mut_ptr::<ReducedCpuInfo>(),
+            &mut *iret.as_mut_ptr::<IretFrame>(),
+        )
+    }
+}
+
 /*
 callee saved registers:
     rbp
@@ -282,11 +558,6 @@ global_asm!(
             call end_interrupt
             // now on tasks kstack, with state on stack
 
-            // pop xmm registers
-            pop r9
-            fxrstor [rsp]
-            mov rsp, r9
-
             pop r8
             pop r9
             pop r10
@@ -309,7 +580,7 @@ global_asm!(
 
         init_kernel_task:
             mov rax, rsp
-            mov rsp, [rdi + 8]
+            mov rsp, [rdi + {kinfo_kstack}]
 
 
             /// pushes return addr after trampoline
@@ -321,7 +592,7 @@ global_asm!(
             and rsp, -16
             push 0
 
-            push [rsi] // trampoline
+            push [rsi + {exit_trampoline}] // trampoline
             push rsi // task exit info
 
 
@@ -332,38 +603,30 @@ global_asm!(
             // now on tasks kstack
             // 1: push interrupt frame
             mov r8, rsp
-            push [rdi + 32] // ss
+            push [rdi + {kinfo_ss}] // ss
             push r8  // rsp before ss
-            push [rdi + 24] // rflags
-            push [rdi + 16] // cs
-            push [rdi] // rip
+            push [rdi + {kinfo_rflags}] // rflags
+            push [rdi + {kinfo_cs}] // cs
+            push [rdi + {kinfo_rip}] // rip
 
             // 2: push Cpu Context, such that it can be popped by switch_and_apply
             push 0 // rax
             push 0 // rbp
-            push [rdx + 0] // rdi
-            push [rdx + 8] // rsi
-            push [rdx + 16] // rdx
-            push [rdx + 24] // rcx
+            push [rdx + {arg0}] // rdi
+            push [rdx + {arg1}] // rsi
+            push [rdx + {arg2}] // rdx
+            push [rdx + {arg3}] // rcx
             push 0 // rbx
             mov rsi, cr3
             push rsi // cr3 // we should push the root addr saved in Ktaskinfo, but this triggers a triple fault???
-            // push [rdx + 40] // cr3
             push 0 // r15
             push 0
             push 0
             push 0
             push 0
             push 0
-            push [rdx + 40]
-            push [rdx + 32] // r8
-
-            // save xmm registers
-            mov r9, rsp
-            sub rsp, 512 + 16
-            and rsp, -16
-            fxsave [rsp]
-            push r9
+            push [rdx + {arg5}]
+            push [rdx + {arg4}] // r8
 
             // restore rsp
             mov rsi, rsp
@@ -373,7 +636,7 @@ global_asm!(
 
         init_usr_task:
             mov rax, rsp
-            mov rsp, [rdi + 48] // kernel stack top
+            mov rsp, [rdi + {uinfo_kstack}] // kernel stack top
 
 
             /// pushes return addr after trampoline
@@ -385,7 +648,7 @@ global_asm!(
             and rsp, -16
             push 0
 
-            push [rsi] // trampoline
+            push [rsi + {exit_trampoline}] // trampoline
             push rsi // task exit info
 
 
@@ -399,36 +662,29 @@ global_asm!(
             // now on tasks kstack
             // 1: push interrupt frame
             // user variables
-            push [rdi + 32] // ss
+            push [rdi + {uinfo_ss}] // ss
             push r8
-            push [rdi + 24] // rflags
-            push [rdi + 16] // cs
-            push [rdi]  // rip
+            push [rdi + {uinfo_rflags}] // rflags
+            push [rdi + {uinfo_cs}] // cs
+            push [rdi + {uinfo_rip}] // rip
 
             // 2: push Cpu Context, such that it can be popped by switch_and_apply
             push 0 // rax
             push 0 // rbp
-            push [rdx + 0] // rdi
-            push [rdx + 8] // rsi
-            push [rdx + 16] // rdx
-            push [rdx + 24] // rcx
+            push [rdx + {arg0}] // rdi
+            push [rdx + {arg1}] // rsi
+            push [rdx + {arg2}] // rdx
+            push [rdx + {arg3}] // rcx
             push 0 // rbx
-            push [rdi + 40] // cr3
+            push [rdi + {uinfo_cr3}] // cr3
             push 0 // r15
             push 0
             push 0
             push 0
             push 0
             push 0
-            push [rdx + 40]
-            push [rdx + 32] // r8
-
-            // save xmm registers
-            mov r9, rsp
-            sub rsp, 512 + 16
-            and rsp, -16
-            fxsave [rsp]
-            push r9
+            push [rdx + {arg5}]
+            push [rdx + {arg4}] // r8
 
             // restore rsp
             mov rsi, rsp
@@ -440,7 +696,7 @@ global_asm!(
             // usr task info in rdi
             // puts usr task rsp in r8
             mov r9, rsp
-            mov rsp, [rdi + 8]
+            mov rsp, [rdi + {uinfo_ustack}]
             // now on user stack
 
             // ensure alignemnt
@@ -476,6 +732,25 @@ global_asm!(
             mov rdi, rax
             ret // go to trampoline
    ",
+    kinfo_rip = const offset_of!(KTaskInfo, rip),
+    kinfo_kstack = const offset_of!(KTaskInfo, kstack_top),
+    kinfo_cs = const offset_of!(KTaskInfo, cs),
+    kinfo_rflags = const offset_of!(KTaskInfo, rflags),
+    kinfo_ss = const offset_of!(KTaskInfo, ss),
+    uinfo_rip = const offset_of!(UsrTaskInfo, rip),
+    uinfo_ustack = const offset_of!(UsrTaskInfo, usr_stack_top),
+    uinfo_cs = const offset_of!(UsrTaskInfo, u_cs),
+    uinfo_rflags = const offset_of!(UsrTaskInfo, u_rflags),
+    uinfo_ss = const offset_of!(UsrTaskInfo, u_ss),
+    uinfo_cr3 = const offset_of!(UsrTaskInfo, cr3),
+    uinfo_kstack = const offset_of!(UsrTaskInfo, kstack_top),
+    exit_trampoline = const offset_of!(TaskExitInfo, trampoline),
+    arg0 = const TaskData::arg_offset(0),
+    arg1 = const TaskData::arg_offset(1),
+    arg2 = const TaskData::arg_offset(2),
+    arg3 = const TaskData::arg_offset(3),
+    arg4 = const TaskData::arg_offset(4),
+    arg5 = const TaskData::arg_offset(5),
 );
 
 unsafe extern "C" {
@@ -570,59 +845,66 @@ impl UsrTaskInfo {
     }
 }
 
-pub fn allocate_kstack() -> Result<VirtAddr, ThreadingError> {
-    let flags = PageTableFlags::WRITABLE | PageTableFlags::PRESENT;
+/// layout of kstack slot idx: a guard page at the bottom, followed by the stack.
+/// Returns (base, end) of the slot
+fn kstack_slot(idx: usize) -> (VirtAddr, VirtAddr) {
+    let base = KSTACK_AREA_START + idx as u64 * KSTACK_SIZE as u64;
+    (base, base + KSTACK_SIZE as u64)
+}
 
-    let kstack_start_idx = {
-        let mut in_use = KSTACKS_IN_USAGE.lock();
-        in_use
-            .iter_mut()
-            .position(|pos| {
-                if !*pos {
-                    *pos = true;
-                    true
-                } else {
-                    false
-                }
-            })
-            .ok_or(ThreadingError::StackNotBuilt)?
-    };
-
-    let base =
-        (KSTACK_AREA_START + kstack_start_idx as u64 * KSTACK_SIZE as u64).align_up(Size4KiB::SIZE);
-    let start = (base + Size4KiB::SIZE).align_up(Size4KiB::SIZE);
-    let end = (base + KSTACK_SIZE as u64).align_up(Size4KiB::SIZE);
+/// allocates a kstack slot and maps all of it except the guard page.
+/// Kstacks are committed eagerly, as a fault on them could not take the page table or frame allocator locks, if the faulting code holds them
+pub fn allocate_kstack() -> Result<VirtAddr, ThreadingError> {
+    let idx = without_preemption(|| KSTACKS.lock().alloc()).ok_or(ThreadingError::StackNotBuilt)?;
+    let (base, end) = kstack_slot(idx);
+    let start = base + Size4KiB::SIZE;
 
+    if map_region(
+        start,
+        (end - start) as usize,
+        KSTACK_FLAGS,
+        &mut *PAGETABLE.lock(),
+    )
+    .is_err()
     {
-        map_region(start, (end - start) as usize, flags, &mut *PAGETABLE.lock())
-            .map_err(|_| ThreadingError::StackNotBuilt)?;
+        // map_region might have mapped some pages before failing
+        unmap_kstack(idx);
+        without_preemption(|| KSTACKS.lock().free(idx));
+        return Err(ThreadingError::StackNotBuilt);
     }
     let stack_top = VirtAddr::new((end.as_u64() - 8) & !0xF);
     Ok(stack_top)
 }
 
 pub fn free_kstack(top: VirtAddr) -> Result<(), ThreadingError> {
-    // assuming top is a properly aligned addr in the correct region
-    let end = (top + 1).align_up(Size4KiB::SIZE);
-
-    let base = end - KSTACK_SIZE as u64;
-    let start = (base + Size4KiB::SIZE).align_up(Size4KiB::SIZE);
-
-    let idx = (base.as_u64() - KSTACK_AREA_START.as_u64()) as usize / KSTACK_SIZE;
-
-    {
-        unmap_region(start, (end - start) as usize, &mut *PAGETABLE.lock())
-            .inspect_err(|e| eprintln!("{e:?}"))
-            .map_err(|_| ThreadingError::StackNotFreed)?;
+    if top < KSTACK_AREA_START || top >= KSTACK_AREA_START + KSTACK_AREA_SIZE as u64 {
+        return Err(ThreadingError::StackNotFreed);
     }
-    *KSTACKS_IN_USAGE
-        .lock()
-        .get_mut(idx)
-        .ok_or(ThreadingError::StackNotFreed)? = false;
-
+    let idx = (top - KSTACK_AREA_START) as usize / KSTACK_SIZE;
+    if !without_preemption(|| KSTACKS.lock().is_used(idx)) {
+        return Err(ThreadingError::StackNotFreed);
+    }
+    unmap_kstack(idx);
+    without_preemption(|| KSTACKS.lock().free(idx));
     Ok(())
 }
 
+/// unmaps all mapped pages of kstack slot idx and frees their frames
+fn unmap_kstack(idx: usize) {
+    let (base, end) = kstack_slot(idx);
+    let start: Page<Size4KiB> = Page::containing_address(base + Size4KiB::SIZE);
+    let end: Page<Size4KiB> = Page::containing_address(end);
+    let mut tbl = PAGETABLE.lock();
+    let mut alloc = get_frame_alloc().lock();
+    for page in Page::range(start, end) {
+        // map_region might have failed before mapping all pages
+        if let Ok((frame, flush)) = tbl.unmap(page) {
+            flush.flush();
+            unsafe { alloc.deallocate_frame(frame) };
+        }
+    }
+}
+
 /// assuming start is aligned
 pub fn allocate_userstack<M: Mapper<Size4KiB>>(
     tbl: &mut M,
@@ -637,8 +919,9 @@ pub fn allocate_userstack<M: Mapper<Size4KiB>>(
     let start = (base + Size4KiB::SIZE).align_up(Size4KiB::SIZE);
     let length = align_up(USER_STACK_SIZE, Size4KiB::SIZE as usize);
     let end = base + length as u64;
+    let committed = (end - USER_STACK_COMMITTED as u64).max(start);
     {
-        map_region(start, (end - start) as usize, flags, tbl)
+        map_region(committed, (end - committed) as usize, flags, tbl)
             .map_err(|_| ThreadingError::StackNotBuilt)?;
     }
 
@@ -663,8 +946,11 @@ pub fn copy_ustack_mappings_into<M: Mapper<Size4KiB>, M2: Mapper<Size4KiB>>(
     {
         let mut frame_allocator = get_frame_alloc().lock();
         for page in Page::range(start_page, end_page) {
+            // the pages below the committed top of the stack may not have been touched yet
+            let Ok(frame) = from.translate_page(page) else {
+                continue;
+            };
             unsafe {
-                let frame = from.translate_page(page).unwrap();
                 into.map_to(page, frame, flags, &mut *frame_allocator)
                     .unwrap()
                     .flush();
@@ -697,3 +983,107 @@ pub fn free_user_stack(top: VirtAddr, tbl: &mut TaskPageTable) -> Result<(), Thr
     .inspect_err(|e| eprintln!("{e:?}"))
     .map_err(|_| ThreadingError::StackNotFreed)
 }
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use alloc::vec;
+
+    use os_macros::kernel_test;
+
+    use super::*;
+    use crate::kernel::threading::task::Args;
+
+    #[kernel_test]
+    fn kstack_slots() {
+        let mut slots = KStackSlots::new();
+        for i in 0..100 {
+            assert_eq!(slots.alloc(), Some(i));
+        }
+        assert!(slots.free(3));
+        assert!(slots.free(70));
+        assert!(!slots.free(70));
+        assert_eq!(slots.alloc(), Some(3));
+        assert_eq!(slots.alloc(), Some(70));
+        assert_eq!(slots.alloc(), Some(100));
+    }
+
+    #[kernel_test]
+    fn kstack_eager_commit() {
+        let top = allocate_kstack().unwrap();
+        let (base, _) = kstack_slot((top - KSTACK_AREA_START) as usize / KSTACK_SIZE);
+        // the lowest usable page is mapped with the rest of the stack
+        let bottom = (base + Size4KiB::SIZE).as_mut_ptr::<u64>();
+        unsafe {
+            bottom.write_volatile(42);
+            assert_eq!(bottom.read_volatile(), 42);
+        }
+        free_kstack(top).unwrap();
+        assert!(free_kstack(top).is_err());
+    }
+
+    #[kernel_test]
+    fn initial_frame_roundtrip() {
+        // a synthetic kernel stack, which is never switched to
+        let mut stack = vec![0u64; 64];
+        let top = VirtAddr::from_ptr(stack.as_mut_ptr_range().end);
+        let entry = VirtAddr::new(0xdead_b000);
+        let info = KTaskInfo::new(entry, top);
+        let exit = TaskExitInfo::default();
+        let data = TaskData::new(Args::new(core::array::from_fn(|i| {
+            Arg::from_usize(0x100 + i)
+        })));
+        let krsp = unsafe { init_kernel_task(&info, &exit, &data) };
+        assert!(krsp > VirtAddr::from_ptr(stack.as_ptr()) && krsp < top);
+
+        let (regs, iret) = unsafe { initial_frame(krsp) };
+        assert_eq!(
+            [regs.rdi, regs.rsi, regs.rdx, regs.rcx, regs.r8, regs.r9],
+            [0x100, 0x101, 0x102, 0x103, 0x104, 0x105]
+        );
+        assert_eq!((regs.rax, regs.rbx, regs.rbp, regs.r15), (0, 0, 0, 0));
+        assert_eq!(
+            regs.cr3 & !0xfff,
+            crate::arch::current_page_tbl().0.start_address().as_u64()
+        );
+        assert_eq!(iret.rip, entry.as_u64());
+        assert_eq!(
+            (iret.cs, iret.rflags, iret.ss),
+            (info.cs, info.rflags, info.ss)
+        );
+        // the thread returns through return_trampoline_stub, which pops the exit info and jumps to its trampoline
+        let ret = krsp + (size_of::<ReducedCpuInfo>() + size_of::<IretFrame>()) as u64;
+        assert_eq!(iret.rsp, ret.as_u64());
+        let ret = unsafe { core::slice::from_raw_parts(ret.as_ptr::<u64>(), 3) };
+        assert_eq!(
+            ret,
+            [
+                return_trampoline_stub as usize as u64,
+                core::ptr::from_ref(&exit).addr() as u64,
+                exit.trampoline
+            ]
+        );
+
+        let user = UserRegs {
+            rax: 1,
+            rbx: 2,
+            rcx: 3,
+            rdx: 4,
+            rsi: 5,
+            rdi: 6,
+            rbp: 7,
+            rsp: 8,
+            r8: 9,
+            r9: 10,
+            r10: 11,
+            r11: 12,
+            r12: 13,
+            r13: 14,
+            r14: 15,
+            r15: 16,
+            rip: 17,
+            rflags: RFlags::INTERRUPT_FLAG.bits() | 0x2,
+        };
+        unsafe { user.apply_to_initial_frame(krsp) };
+        assert_eq!(unsafe { UserRegs::from_initial_frame(krsp) }, user);
+    }
+}
@@ -0,0 +1,227 @@
+use alloc::{string::String, vec::Vec};
+use core::fmt::Write;
+
+use acpi::platform::{Processor, ProcessorState};
+use conquer_once::spin::OnceCell;
+use raw_cpuid::CpuId;
+use x86_64::{
+    VirtAddr,
+    instructions::{interrupts, port::Port, tables::lidt},
+    structures::DescriptorTablePointer,
+};
+
+use crate::{
+    arch::x86::interrupt::{
+        irq,
+        lapic::{self, IpiDest},
+    },
+    serial_println,
+};
+
+const KEYBOARD_CONTROLLER: u16 = 0x64;
+const KEYBOARD_INPUT_FULL: u8 = 1 << 1;
+const PULSE_RESET: u8 = 0xfe;
+
+/// a processor as reported by the ACPI MADT
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuEntry {
+    pub uid: u32,
+    pub apic_id: u32,
+    pub is_bsp: bool,
+    pub enabled: bool,
+}
+
+static TOPOLOGY: OnceCell<Vec<CpuEntry>> = OnceCell::uninit();
+
+pub(super) fn init_topology(boot_processor: &Processor, application_processors: &[Processor]) {
+    TOPOLOGY.init_once(|| {
+        let mut cpus = Vec::with_capacity(application_processors.len() + 1);
+        cpus.push(cpu_entry(boot_processor));
+        cpus.extend(application_processors.iter().map(cpu_entry));
+        cpus
+    });
+}
+
+fn cpu_entry(processor: &Processor) -> CpuEntry {
+    CpuEntry {
+        uid: processor.processor_uid,
+        apic_id: processor.local_apic_id,
+        is_bsp: !processor.is_ap,
+        enabled: processor.state != ProcessorState::Disabled,
+    }
+}
+
+/// all processors of the machine, the boot processor first.
+/// If the topology is not known (yet), only the current cpu is reported
+pub fn topology() -> &'static [CpuEntry] {
+    static FALLBACK: OnceCell<[CpuEntry; 1]> = OnceCell::uninit();
+    if let Some(cpus) = TOPOLOGY.get() {
+        return cpus;
+    }
+    FALLBACK.get_or_init(|| {
+        [CpuEntry {
+            uid: 0,
+            apic_id: current_apic_id(),
+            is_bsp: true,
+            enabled: true,
+        }]
+    })
+}
+
+pub fn current_apic_id() -> u32 {
+    CpuId::new()
+        .get_feature_info()
+        .map_or(0, |info| info.initial_local_apic_id() as u32)
+}
+
+/// the logical number of the current cpu, ie its index in topology()
+pub fn current_cpu() -> u32 {
+    let apic_id = current_apic_id();
+    topology()
+        .iter()
+        .position(|cpu| cpu.apic_id == apic_id)
+        .unwrap_or_default() as u32
+}
+
+/// the numa node of the current cpu. NUMA is not supported, thus this is always 0
+pub fn current_node() -> u32 {
+    0
+}
+
+/// the tsc frequency in Hz as reported by cpuid, if available
+pub fn tsc_frequency() -> Option<u64> {
+    let cpuid = CpuId::new();
+    if let Some(tsc) = cpuid.get_tsc_info()
+        && let Some(freq) = tsc.tsc_frequency()
+    {
+        Some(freq)
+    } else {
+        cpuid
+            .get_processor_frequency_info()
+            .map(|base| base.processor_max_frequency() as u64 * 1_000_000)
+            .filter(|freq| *freq != 0)
+    }
+}
+
+/// renders cpu information in the format of /proc/cpuinfo. The cpuid information is that of the current cpu
+pub fn cpuinfo() -> String {
+    let cpuid = CpuId::new();
+    let vendor = cpuid.get_vendor_info();
+    let brand = cpuid.get_processor_brand_string();
+    let info = cpuid.get_feature_info();
+    let extended = cpuid.get_extended_feature_info();
+
+    let mut features = Vec::new();
+    if let Some(info) = &info {
+        for (has, name) in [
+            (info.has_fpu(), "fpu"),
+            (info.has_tsc(), "tsc"),
+            (info.has_apic(), "apic"),
+            (info.has_x2apic(), "x2apic"),
+            (info.has_sse(), "sse"),
+            (info.has_sse2(), "sse2"),
+            (info.has_sse3(), "sse3"),
+            (info.has_ssse3(), "ssse3"),
+            (info.has_sse41(), "sse4_1"),
+            (info.has_sse42(), "sse4_2"),
+            (info.has_popcnt(), "popcnt"),
+            (info.has_aesni(), "aes"),
+            (info.has_xsave(), "xsave"),
+            (info.has_avx(), "avx"),
+            (info.has_fma(), "fma"),
+            (info.has_rdrand(), "rdrand"),
+        ] {
+            if has {
+                features.push(name);
+            }
+        }
+    }
+    if let Some(extended) = &extended {
+        for (has, name) in [
+            (extended.has_avx2(), "avx2"),
+            (extended.has_bmi1(), "bmi1"),
+            (extended.has_bmi2(), "bmi2"),
+            (extended.has_smep(), "smep"),
+            (extended.has_smap(), "smap"),
+            (extended.has_rdseed(), "rdseed"),
+        ] {
+            if has {
+                features.push(name);
+            }
+        }
+    }
+
+    let mut out = String::new();
+    for (n, cpu) in topology().iter().enumerate() {
+        _ = writeln!(out, "processor\t: {}", n);
+        _ = writeln!(
+            out,
+            "vendor_id\t: {}",
+            vendor.as_ref().map_or("unknown", |v| v.as_str())
+        );
+        if let Some(info) = &info {
+            _ = writeln!(out, "cpu family\t: {}", info.family_id());
+            _ = writeln!(out, "model\t\t: {}", info.model_id());
+            _ = writeln!(out, "stepping\t: {}", info.stepping_id());
+        }
+        _ = writeln!(
+            out,
+            "model name\t: {}",
+            brand.as_ref().map_or("unknown", |b| b.as_str().trim())
+        );
+        match tsc_frequency() {
+            Some(freq) => _ = writeln!(out, "cpu MHz\t\t: {}", freq / 1_000_000),
+            None => _ = writeln!(out, "cpu MHz\t\t: unknown"),
+        }
+        _ = writeln!(out, "apicid\t\t: {}", cpu.apic_id);
+        _ = writeln!(out, "acpi uid\t: {}", cpu.uid);
+        _ = writeln!(out, "bsp\t\t: {}", if cpu.is_bsp { "yes" } else { "no" });
+        _ = writeln!(
+            out,
+            "online\t\t: {}",
+            if cpu.enabled { "yes" } else { "no" }
+        );
+        _ = writeln!(out, "flags\t\t: {}", features.join(" "));
+        _ = writeln!(out);
+    }
+    out
+}
+
+/// stops every cpu but the current one for good, by sending it an interrupt, whose handler halts with interrupts disabled
+pub fn park_others() {
+    // the vector is never freed, as the parked cpus never return from it
+    match irq::alloc_msi(1, "park", park) {
+        Ok(handle) => lapic::send_ipi(IpiDest::Others, handle.vector()),
+        Err(e) => serial_println!("could not park the other cpus: {}", e),
+    }
+}
+
+fn park(_vector: u8) {
+    interrupts::disable();
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// resets the machine by pulsing the reset line of the keyboard controller.
+/// If that does not work, a triple fault is forced by raising an exception without an idt
+pub fn reboot() -> ! {
+    interrupts::disable();
+    unsafe {
+        let mut controller = Port::<u8>::new(KEYBOARD_CONTROLLER);
+        for _ in 0..0x10000 {
+            if controller.read() & KEYBOARD_INPUT_FULL == 0 {
+                break;
+            }
+        }
+        controller.write(PULSE_RESET);
+        lidt(&DescriptorTablePointer {
+            limit: 0,
+            base: VirtAddr::zero(),
+        });
+        core::arch::asm!("int3");
+    }
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
@@ -0,0 +1,186 @@
+use core::{arch::global_asm, fmt::Display};
+
+use spin::Mutex;
+use x86_64::{
+    VirtAddr,
+    registers::rflags::RFlags,
+    structures::idt::{InterruptStackFrame, PageFaultErrorCode},
+};
+
+use super::gdt::{get_kernel_selectors, tss_kstack};
+use crate::{arch::x86::percpu, kernel::threading::fault::kill_faulting_task};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultKind {
+    DivideError,
+    InvalidOpcode,
+    GeneralProtection,
+    PageFault,
+}
+
+impl FaultKind {
+    /// the posix signal, the faulting process is killed with
+    pub fn signal(&self) -> u8 {
+        match self {
+            Self::DivideError => 8,
+            Self::InvalidOpcode => 4,
+            Self::GeneralProtection | Self::PageFault => 11,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::DivideError => "divide error",
+            Self::InvalidOpcode => "invalid opcode",
+            Self::GeneralProtection => "general protection fault",
+            Self::PageFault => "segmentation fault",
+        }
+    }
+}
+
+/// an exception raised by user code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UserFault {
+    pub kind: FaultKind,
+    pub tid: u64,
+    pub rip: u64,
+    pub rsp: u64,
+    pub error_code: u64,
+    /// the accessed address of a page fault
+    pub addr: Option<u64>,
+}
+
+impl UserFault {
+    pub(super) fn new(kind: FaultKind, stack_frame: &InterruptStackFrame, error_code: u64) -> Self {
+        Self {
+            kind,
+            tid: percpu::interrupted_tid(),
+            rip: stack_frame.instruction_pointer.as_u64(),
+            rsp: stack_frame.stack_pointer.as_u64(),
+            error_code,
+            addr: None,
+        }
+    }
+
+    pub fn page_fault_code(&self) -> Option<PageFaultErrorCode> {
+        (self.kind == FaultKind::PageFault)
+            .then(|| PageFaultErrorCode::from_bits_truncate(self.error_code))
+    }
+}
+
+impl Display for UserFault {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} in task {} at rip {:#x}, rsp {:#x}",
+            self.kind.as_str(),
+            self.tid,
+            self.rip,
+            self.rsp
+        )?;
+        if let Some(code) = self.page_fault_code() {
+            let access = if code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+                "instruction fetch from"
+            } else if code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+                "write to"
+            } else {
+                "read from"
+            };
+            let page = if code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+                "protected"
+            } else {
+                "unmapped"
+            };
+            write!(
+                f,
+                ": {} {} address {:#x}",
+                access,
+                page,
+                self.addr.unwrap_or_default()
+            )?;
+            if code.contains(PageFaultErrorCode::MALFORMED_TABLE) {
+                f.write_str(" (reserved bit set)")?;
+            }
+        } else if self.kind == FaultKind::GeneralProtection && self.error_code != 0 {
+            // a segment selector index, with the table in bits 1-2
+            write!(f, ": selector {:#x}", self.error_code)?;
+        }
+        Ok(())
+    }
+}
+
+// the fault of the task, which is about to be killed. Interrupts stay disabled between the exception handler
+// and the trampoline picking it up, thus one slot suffices, as only the boot processor is brought up
+static PENDING: Mutex<Option<UserFault>> = Mutex::new(None);
+
+global_asm!(
+    "
+        .global user_fault_trampoline
+
+        user_fault_trampoline:
+            // entered from a user mode exception, which did not load the kernel gs base
+            swapgs
+            and rsp, -16
+            call user_fault_exit
+            ud2
+    "
+);
+
+unsafe extern "C" {
+    fn user_fault_trampoline();
+}
+
+#[unsafe(no_mangle)]
+extern "C" fn user_fault_exit() -> ! {
+    let fault = PENDING
+        .lock()
+        .take()
+        .expect("user fault trampoline entered without a fault");
+    unsafe { super::enable() };
+    kill_faulting_task(fault)
+}
+
+/// makes the exception handler return into the kernel instead of the faulting user code.
+/// The task continues on its kernel stack in kill_faulting_task, which never returns
+pub(super) fn kill_on_return(stack_frame: &mut InterruptStackFrame, fault: UserFault) {
+    *PENDING.try_lock().expect("user fault pending twice") = Some(fault);
+    let (code, data) = get_kernel_selectors();
+    let kstack = tss_kstack();
+    unsafe {
+        stack_frame.as_mut().update(|frame| {
+            frame.instruction_pointer = VirtAddr::new(user_fault_trampoline as usize as u64);
+            frame.code_segment = code;
+            frame.stack_segment = data;
+            frame.stack_pointer = kstack;
+            // interrupts stay disabled until the trampoline swapped in the kernel gs base
+            frame.cpu_flags = RFlags::empty();
+        })
+    };
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use alloc::format;
+
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    #[kernel_test]
+    fn user_fault_report() {
+        let fault = UserFault {
+            kind: FaultKind::PageFault,
+            tid: 7,
+            rip: 0x40_1000,
+            rsp: 0x7fff_0000,
+            error_code: (PageFaultErrorCode::USER_MODE | PageFaultErrorCode::CAUSED_BY_WRITE)
+                .bits(),
+            addr: Some(0xdead_0000),
+        };
+        assert_eq!(fault.kind.signal(), 11);
+        assert_eq!(
+            format!("{}", fault),
+            "segmentation fault in task 7 at rip 0x401000, rsp 0x7fff0000: write to unmapped address 0xdead0000"
+        );
+    }
+}
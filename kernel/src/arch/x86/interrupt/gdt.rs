@@ -7,9 +7,18 @@ use x86_64::structures::{
     tss::TaskStateSegment,
 };
 
+use super::stats::{MAX_CPUS, cpu_slot};
 use crate::arch::x86::mem::VirtAddr;
 
 pub(super) const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+// page faults get their own stack, such that a kstack overflow into its guard page can still be reported
+pub(super) const PAGE_FAULT_IST_INDEX: u16 = 1;
+// nmis may arrive anywhere, even right after a syscall or interrupt entered the kernel on a user stack
+pub(super) const NMI_IST_INDEX: u16 = 2;
+
+const DOUBLE_FAULT_STACK_SIZE: usize = 4096 * 5;
+const PAGE_FAULT_STACK_SIZE: usize = 4096 * 5;
+const NMI_STACK_SIZE: usize = 4096 * 2;
 
 struct Selectors {
     code_selector: SegmentSelector,
@@ -19,29 +28,54 @@ struct Selectors {
     user_data_selector: SegmentSelector,
 }
 
-static TSS: OnceCell<Mutex<TaskStateSegment>> = OnceCell::uninit();
+/// the interrupt stacks of a single cpu. A shared ist stack would be overwritten as soon as two cpus take the same
+/// exception at once
+#[repr(C, align(16))]
+struct IstStacks {
+    double_fault: [u8; DOUBLE_FAULT_STACK_SIZE],
+    page_fault: [u8; PAGE_FAULT_STACK_SIZE],
+    nmi: [u8; NMI_STACK_SIZE],
+}
+
+static mut IST_STACKS: [IstStacks; MAX_CPUS] = [const {
+    IstStacks {
+        double_fault: [0; DOUBLE_FAULT_STACK_SIZE],
+        page_fault: [0; PAGE_FAULT_STACK_SIZE],
+        nmi: [0; NMI_STACK_SIZE],
+    }
+}; MAX_CPUS];
+
+// one tss and gdt per cpu, indexed like cpu::topology(). All gdts share the same layout, thus the selectors are the
+// same on every cpu
+static TSS: [OnceCell<Mutex<TaskStateSegment>>; MAX_CPUS] =
+    [const { OnceCell::uninit() }; MAX_CPUS];
 
-static GDT: OnceCell<(GlobalDescriptorTable, Selectors)> = OnceCell::uninit();
+static GDT: [OnceCell<(GlobalDescriptorTable, Selectors)>; MAX_CPUS] =
+    [const { OnceCell::uninit() }; MAX_CPUS];
 
-pub fn init_tss() -> &'static TaskStateSegment {
-    TSS.init_once(|| {
+/// initializes the tss of the cpu with index slot, pointing its ist entries at the interrupt stacks of that cpu
+fn init_tss(slot: usize) -> &'static TaskStateSegment {
+    TSS[slot].init_once(|| {
         Mutex::new({
+            let stacks = &raw const IST_STACKS;
+            let stacks = unsafe { &raw const (*stacks)[slot] };
             let mut tss = TaskStateSegment::new();
-            tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-                const STACK_SIZE: usize = 4096 * 5;
-                static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-                let stack_start = VirtAddr::from_ptr(&raw const STACK);
-                stack_start + STACK_SIZE as u64
-            };
+            tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+                VirtAddr::from_ptr(unsafe { &raw const (*stacks).double_fault })
+                    + DOUBLE_FAULT_STACK_SIZE as u64;
+            tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] =
+                VirtAddr::from_ptr(unsafe { &raw const (*stacks).page_fault })
+                    + PAGE_FAULT_STACK_SIZE as u64;
+            tss.interrupt_stack_table[NMI_IST_INDEX as usize] =
+                VirtAddr::from_ptr(unsafe { &raw const (*stacks).nmi }) + NMI_STACK_SIZE as u64;
             tss
         })
     });
-    unsafe { &*(&*TSS.get_unchecked().lock() as *const TaskStateSegment) }
+    unsafe { &*(&*TSS[slot].get_unchecked().lock() as *const TaskStateSegment) }
 }
 
-pub fn init_gdt(tss: &'static TaskStateSegment) {
-    GDT.init_once(|| {
+fn init_gdt(slot: usize, tss: &'static TaskStateSegment) {
+    GDT[slot].init_once(|| {
         let mut gdt = GlobalDescriptorTable::new();
         let code_selector = gdt.append(Descriptor::kernel_code_segment());
         let tss_selector = gdt.append(Descriptor::tss_segment(tss));
@@ -61,24 +95,39 @@ pub fn init_gdt(tss: &'static TaskStateSegment) {
     });
 }
 
+fn current_tss() -> &'static Mutex<TaskStateSegment> {
+    TSS[cpu_slot()].get().unwrap()
+}
+
 pub fn set_tss_kstack(stack: VirtAddr) {
-    TSS.get().unwrap().lock().privilege_stack_table[0] = stack;
+    current_tss().lock().privilege_stack_table[0] = stack;
+}
+
+/// the stack the current cpu switches to when entering the kernel from user mode
+pub fn tss_kstack() -> VirtAddr {
+    current_tss().lock().privilege_stack_table[0]
 }
 
 pub(super) fn init() {
+    init_cpu(0);
+}
+
+/// loads the gdt and tss of the cpu with index slot in cpu::topology(). Every cpu has to call this once while
+/// starting up, before it takes any interrupt
+pub(super) fn init_cpu(slot: usize) {
     use x86_64::instructions::{
         segmentation::{CS, SS, Segment},
         tables::load_tss,
     };
 
-    let tss = init_tss();
+    let tss = init_tss(slot);
 
-    assert!(ptr::eq(tss, &*TSS.get().unwrap().lock()));
+    assert!(ptr::eq(tss, &*TSS[slot].get().unwrap().lock()));
 
-    init_gdt(tss);
+    init_gdt(slot, tss);
 
     unsafe {
-        let gdt = GDT.get_unchecked();
+        let gdt = GDT[slot].get_unchecked();
         gdt.0.load();
         CS::set_reg(gdt.1.code_selector);
         SS::set_reg(gdt.1.data_selector);
@@ -86,19 +135,40 @@ pub(super) fn init() {
     }
 }
 
+// the bsp gdt is set up first and every other gdt has the same layout
 pub fn get_user_selectors() -> (SegmentSelector, SegmentSelector) {
     unsafe {
         (
-            GDT.get_unchecked().1.user_code_selector,
-            GDT.get_unchecked().1.user_data_selector,
+            GDT[0].get_unchecked().1.user_code_selector,
+            GDT[0].get_unchecked().1.user_data_selector,
         )
     }
 }
 pub fn get_kernel_selectors() -> (SegmentSelector, SegmentSelector) {
     unsafe {
         (
-            GDT.get_unchecked().1.code_selector,
-            GDT.get_unchecked().1.data_selector,
+            GDT[0].get_unchecked().1.code_selector,
+            GDT[0].get_unchecked().1.data_selector,
         )
     }
 }
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    #[kernel_test]
+    fn ist_stacks_per_cpu() {
+        let bsp = init_tss(0);
+        let other = init_tss(1);
+        assert!(!ptr::eq(bsp, other));
+        // copied out, as the tss is packed
+        let (bsp_ist, other_ist) = (bsp.interrupt_stack_table, other.interrupt_stack_table);
+        for idx in [DOUBLE_FAULT_IST_INDEX, PAGE_FAULT_IST_INDEX, NMI_IST_INDEX] {
+            assert_ne!(bsp_ist[idx as usize], other_ist[idx as usize]);
+        }
+        assert!(ptr::eq(current_tss(), TSS[0].get().unwrap()));
+    }
+}
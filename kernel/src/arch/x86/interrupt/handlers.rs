@@ -4,18 +4,29 @@ use core::{
 };
 
 pub use x86_64::{
+    PrivilegeLevel,
     instructions::port::Port,
     structures::idt::{InterruptStackFrame, PageFaultErrorCode},
 };
 
 use crate::{
-    arch::{context::SysCallCtx, x86::interrupt::pic::end_interrupt},
+    arch::{
+        context::{ReducedCpuInfo, SysCallCtx, fpu},
+        x86::interrupt::{
+            fault::{FaultKind, UserFault, kill_on_return},
+            idt::InterruptIndex,
+            pic::end_interrupt,
+            profile::timer_sample,
+            stats::count_interrupt,
+        },
+    },
     kernel::{
         abi::syscalls::syscall_handler,
-        fs::Path,
+        mem::paging::{resolve_cow_fault, resolve_demand_fault},
         threading::{
             self,
-            schedule::context_switch_local,
+            fault::report_kernel_fault,
+            schedule::{context_switch_local, preempt},
             wait::{QueueType, WaitEvent, post_event},
         },
     },
@@ -38,6 +49,9 @@ pub(super) extern "x86-interrupt" fn double_fault_handler(
 
 #[unsafe(no_mangle)]
 pub fn timer_interrupt_handler_local_(rsp: u64) {
+    count_interrupt(InterruptIndex::Timer as u8);
+    // the stub pushed the registers right below the interrupt frame, which starts with the rip
+    timer_sample(unsafe { *((rsp as usize + size_of::<ReducedCpuInfo>()) as *const u64) });
     if !threading::is_running() {
         return;
     }
@@ -54,6 +68,11 @@ pub fn timer_interrupt_handler_local_(rsp: u64) {
         serial_println!("could not push timer event");
     }
 
+    if !preempt::preemptible() {
+        preempt::defer_preemption();
+        return;
+    }
+
     unsafe { context_switch_local(rsp) }
 }
 
@@ -70,13 +89,22 @@ global_asm!(
         .global context_switch_stub
 
         interrupt_cleanup:
-            // reenables interrupts and iretqs
+            // restores the user gs base if returning to ring 3, reenables interrupts and iretqs
+            test qword ptr [rsp + 8], 3
+            jz .Lcleanup_to_kernel
+            swapgs
+        .Lcleanup_to_kernel:
             sti
             iretq
 
         timer_interrupt_stub_local:
             // TODO use funcs, maybe only push/pop in switch_and_apply
             cli
+            // load the kernel gs base if we interrupted user code
+            test qword ptr [rsp + 8], 3
+            jz .Ltimer_from_kernel
+            swapgs
+        .Ltimer_from_kernel:
             push rax
             push rbp
             push rdi
@@ -95,24 +123,11 @@ global_asm!(
             push r9
             push r8
 
-            // save current rsp
-            mov r9, rsp
-
-            // align stack, save rsp and save xmm registers
-            sub rsp, 512 + 16
-            and rsp, -16
-            fxsave [rsp]
-            push r9
-
+            // the fpu state is switched by context_switch_local, as the kernel itself does not use it
             mov rdi, rsp
             call timer_interrupt_handler_local_
             call end_interrupt
 
-            // pop xmm registers
-            pop r9
-            fxrstor [rsp]
-            mov rsp, r9
-
             pop r8
             pop r9
             pop r10
@@ -133,7 +148,17 @@ global_asm!(
             jmp interrupt_cleanup
 
         syscall_stub:
+            // interrupts are still disabled here, thus nothing can observe the user gs base in kernel mode
+            test qword ptr [rsp + 8], 3
+            jz .Lsyscall_from_kernel
+            swapgs
+        .Lsyscall_from_kernel:
             sti
+            // r12 - r15 are callee saved, but are pushed anyways, such that the frame holds all user registers
+            push r15
+            push r14
+            push r13
+            push r12
             push rbp
             push r11
             push rcx
@@ -160,7 +185,16 @@ global_asm!(
             pop rcx
             pop r11
             pop rbp
+            pop r12
+            pop r13
+            pop r14
+            pop r15
 
+            cli
+            test qword ptr [rsp + 8], 3
+            jz .Lsyscall_to_kernel
+            swapgs
+        .Lsyscall_to_kernel:
             iretq
     "
 );
@@ -177,25 +211,87 @@ unsafe extern "C" {
 }
 
 pub(super) extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    count_interrupt(InterruptIndex::Keyboard as u8);
     let mut port = Port::<u8>::new(0x60);
     let scancode: u8 = unsafe { port.read() };
     _ = crate::drivers::keyboard::put_scancode(scancode);
-    if post_event(WaitEvent::new(QueueType::KeyBoard)).is_err()
-        || post_event(WaitEvent::new(QueueType::file(Path::new(
-            "/proc/kernel/io/keyoard",
-        ))))
-        .is_err()
-    {
+    // readers of the keyboard devices wait on the keyboard queue
+    if post_event(WaitEvent::new(QueueType::KeyBoard)).is_err() {
         serial_println!("could not push keyboard event");
     }
     end_interrupt();
 }
 
-pub(super) extern "x86-interrupt" fn page_fault_handler(
+pub(super) extern "x86-interrupt" fn device_not_available_handler(
     stack_frame: InterruptStackFrame,
+) {
+    if !fpu::handle_device_not_available() {
+        panic!("EXCEPTION: DEVICE NOT AVAILABLE\n{:#?}", stack_frame);
+    }
+}
+
+fn from_user(stack_frame: &InterruptStackFrame) -> bool {
+    stack_frame.code_segment.rpl() == PrivilegeLevel::Ring3
+}
+
+pub(super) extern "x86-interrupt" fn divide_error_handler(mut stack_frame: InterruptStackFrame) {
+    if from_user(&stack_frame) {
+        let fault = UserFault::new(FaultKind::DivideError, &stack_frame, 0);
+        kill_on_return(&mut stack_frame, fault);
+        return;
+    }
+    report_kernel_fault(
+        FaultKind::DivideError,
+        stack_frame.instruction_pointer.as_u64(),
+    );
+    panic!("EXCEPTION: DIVIDE ERROR\n{:#?}", stack_frame);
+}
+
+pub(super) extern "x86-interrupt" fn invalid_opcode_handler(mut stack_frame: InterruptStackFrame) {
+    if from_user(&stack_frame) {
+        let fault = UserFault::new(FaultKind::InvalidOpcode, &stack_frame, 0);
+        kill_on_return(&mut stack_frame, fault);
+        return;
+    }
+    report_kernel_fault(
+        FaultKind::InvalidOpcode,
+        stack_frame.instruction_pointer.as_u64(),
+    );
+    panic!("EXCEPTION: INVALID OPCODE\n{:#?}", stack_frame);
+}
+
+pub(super) extern "x86-interrupt" fn page_fault_handler(
+    mut stack_frame: InterruptStackFrame,
     error_code: PageFaultErrorCode,
 ) {
     use x86_64::registers::control::Cr2;
+    count_interrupt(PAGE_FAULT_VECTOR);
+    if error_code
+        .contains(PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE)
+        && let Ok(addr) = Cr2::read()
+        && resolve_cow_fault(addr).is_handled()
+    {
+        return;
+    }
+    if !error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+        && let Ok(addr) = Cr2::read()
+        && resolve_demand_fault(addr).is_handled()
+    {
+        return;
+    }
+    if error_code.contains(PageFaultErrorCode::USER_MODE) {
+        let mut fault = UserFault::new(FaultKind::PageFault, &stack_frame, error_code.bits());
+        fault.addr = Some(Cr2::read_raw());
+        kill_on_return(&mut stack_frame, fault);
+        return;
+    }
+    #[cfg(feature = "kfence")]
+    if let Some(fault) = crate::kernel::mem::alloc::kfence::fault_report(
+        Cr2::read_raw(),
+        stack_frame.instruction_pointer.as_u64(),
+    ) {
+        panic!("{}", fault);
+    }
     panic!(
         "EXCEPTION Page fault:\naccessed address: {:?}\nerror code: {:?}\nstack_frame: {:?}",
         Cr2::read(),
@@ -205,9 +301,14 @@ pub(super) extern "x86-interrupt" fn page_fault_handler(
 }
 
 pub(super) extern "x86-interrupt" fn gpf_handler(
-    stack_frame: InterruptStackFrame,
+    mut stack_frame: InterruptStackFrame,
     error_code: u64,
 ) {
+    if from_user(&stack_frame) {
+        let fault = UserFault::new(FaultKind::GeneralProtection, &stack_frame, error_code);
+        kill_on_return(&mut stack_frame, fault);
+        return;
+    }
     panic!(
         "EXCEPTION: GENERAL PROTECTION FAULT\n{:#?}\nError Code: {:b}",
         stack_frame, error_code
@@ -215,8 +316,10 @@ pub(super) extern "x86-interrupt" fn gpf_handler(
 }
 
 pub(super) const SPURIOUS_VECTOR: u8 = 0xFF;
+pub(super) const PAGE_FAULT_VECTOR: u8 = 14;
 
 pub(super) extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    count_interrupt(SPURIOUS_VECTOR);
     // nothing to do
     // serial_println!("spurious interrupt");
 }
@@ -6,12 +6,17 @@ use crate::arch::{
     interrupt::{
         gdt::get_kernel_selectors,
         handlers::{syscall_stub, timer_interrupt_stub_local},
+        irq::{IRQ_BASE, IRQ_ENTRIES},
+        nmi::nmi_handler,
     },
     x86::interrupt::handlers::{
         SPURIOUS_VECTOR,
         breakpoint_handler,
+        device_not_available_handler,
+        divide_error_handler,
         double_fault_handler,
         gpf_handler,
+        invalid_opcode_handler,
         keyboard_interrupt_handler,
         page_fault_handler,
         spurious_interrupt_handler,
@@ -22,20 +27,36 @@ lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
         idt.breakpoint.set_handler_fn(breakpoint_handler);
+        idt.divide_error.set_handler_fn(divide_error_handler);
+        idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+        idt.device_not_available
+            .set_handler_fn(device_not_available_handler);
 
         unsafe {
             idt.double_fault
                 .set_handler_addr(VirtAddr::new(double_fault_handler as usize as u64))
                 .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
         }
-        idt.page_fault.set_handler_fn(page_fault_handler);
+        unsafe {
+            idt.page_fault
+                .set_handler_fn(page_fault_handler)
+                .set_stack_index(gdt::PAGE_FAULT_IST_INDEX);
+        }
         idt.general_protection_fault.set_handler_fn(gpf_handler);
+        unsafe {
+            idt.non_maskable_interrupt
+                .set_handler_fn(nmi_handler)
+                .set_stack_index(gdt::NMI_IST_INDEX);
+        }
         unsafe {
             idt[InterruptIndex::Timer as u8]
                 .set_handler_addr(VirtAddr::new(timer_interrupt_stub_local as usize as u64));
         }
         idt[InterruptIndex::Keyboard as u8].set_handler_fn(keyboard_interrupt_handler);
         idt[SPURIOUS_VECTOR].set_handler_fn(spurious_interrupt_handler);
+        for (vector, entry) in (IRQ_BASE..).zip(IRQ_ENTRIES) {
+            idt[vector].set_handler_fn(entry);
+        }
         unsafe {
             idt[InterruptIndex::Syscall as u8]
                 .set_handler_addr(VirtAddr::new(syscall_stub as usize as u64))
@@ -0,0 +1,224 @@
+use alloc::vec::Vec;
+
+use acpi::platform::interrupt::{
+    InterruptSourceOverride,
+    IoApic as MadtIoApic,
+    Polarity as MadtPolarity,
+    TriggerMode as MadtTriggerMode,
+};
+use spin::Mutex;
+
+use super::{irq::IrqError, without_interrupts};
+use crate::kernel::mem::mmio;
+
+// register select and data window, as u32 offsets from the base
+const IOREGSEL: usize = 0;
+const IOWIN: usize = 4;
+const WINDOW_LEN: usize = (IOWIN + 1) * size_of::<u32>();
+
+const IOAPICVER: u32 = 0x01;
+const IOREDTBL: u32 = 0x10;
+
+const ENTRY_ACTIVE_LOW: u64 = 1 << 13;
+const ENTRY_LEVEL: u64 = 1 << 15;
+const ENTRY_MASKED: u64 = 1 << 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerMode {
+    Edge,
+    Level,
+}
+
+/// an interrupt line wired to an input of some I/O APIC, identified by its global system interrupt (gsi)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IrqLine {
+    pub gsi: u32,
+    pub polarity: Polarity,
+    pub trigger: TriggerMode,
+}
+
+impl IrqLine {
+    /// a legacy ISA irq, like the keyboard (1) or COM1 (4).
+    /// ISA irqs are identity mapped to gsis and active high, edge triggered, unless the MADT overrides this
+    pub fn isa(irq: u8) -> Self {
+        with_routing(|routing| {
+            routing
+                .overrides
+                .iter()
+                .find(|(isa, _)| *isa == irq)
+                .map(|(_, line)| *line)
+        })
+        .unwrap_or(Self {
+            gsi: irq as u32,
+            polarity: Polarity::ActiveHigh,
+            trigger: TriggerMode::Edge,
+        })
+    }
+
+    /// a PCI INTx line, which is active low and level triggered
+    pub fn pci(gsi: u32) -> Self {
+        Self {
+            gsi,
+            polarity: Polarity::ActiveLow,
+            trigger: TriggerMode::Level,
+        }
+    }
+
+    fn entry(&self, vector: u8, dest: u8) -> u64 {
+        let mut entry = vector as u64 | ((dest as u64) << 56);
+        if self.polarity == Polarity::ActiveLow {
+            entry |= ENTRY_ACTIVE_LOW;
+        }
+        if self.trigger == TriggerMode::Level {
+            entry |= ENTRY_LEVEL;
+        }
+        entry
+    }
+}
+
+struct IoApic {
+    regs: *mut u32,
+    gsi_base: u32,
+    inputs: u32,
+}
+
+impl IoApic {
+    fn read(&self, reg: u32) -> u32 {
+        // SAFETY: regs points to the mapped register window, which is only accessed with ROUTING locked
+        unsafe {
+            self.regs.add(IOREGSEL).write_volatile(reg);
+            self.regs.add(IOWIN).read_volatile()
+        }
+    }
+
+    fn write(&self, reg: u32, value: u32) {
+        unsafe {
+            self.regs.add(IOREGSEL).write_volatile(reg);
+            self.regs.add(IOWIN).write_volatile(value);
+        }
+    }
+
+    fn input(&self, gsi: u32) -> Option<u32> {
+        gsi.checked_sub(self.gsi_base)
+            .filter(|input| *input < self.inputs)
+    }
+
+    fn entry(&self, input: u32) -> u64 {
+        let low = self.read(IOREDTBL + 2 * input) as u64;
+        let high = self.read(IOREDTBL + 2 * input + 1) as u64;
+        (high << 32) | low
+    }
+
+    fn set_entry(&self, input: u32, entry: u64) {
+        // mask the input first, so that it never fires with a half written entry
+        self.write(IOREDTBL + 2 * input, ENTRY_MASKED as u32);
+        self.write(IOREDTBL + 2 * input + 1, (entry >> 32) as u32);
+        self.write(IOREDTBL + 2 * input, entry as u32);
+    }
+}
+
+struct Routing {
+    ioapics: Vec<IoApic>,
+    overrides: Vec<(u8, IrqLine)>,
+}
+
+impl Routing {
+    fn find(&self, gsi: u32) -> Result<(&IoApic, u32), IrqError> {
+        self.ioapics
+            .iter()
+            .find_map(|ioapic| ioapic.input(gsi).map(|input| (ioapic, input)))
+            .ok_or(IrqError::NoSuchLine(gsi))
+    }
+}
+
+unsafe impl Send for Routing {}
+
+// the select/window register pair must never be used by two cpus or an interrupt handler at once,
+// thus all I/O APIC accesses go through this lock with interrupts disabled
+static ROUTING: Mutex<Routing> = Mutex::new(Routing {
+    ioapics: Vec::new(),
+    overrides: Vec::new(),
+});
+
+fn with_routing<R>(f: impl FnOnce(&mut Routing) -> R) -> R {
+    without_interrupts(|| f(&mut ROUTING.lock()))
+}
+
+fn line_from_override(entry: &InterruptSourceOverride) -> IrqLine {
+    IrqLine {
+        gsi: entry.global_system_interrupt,
+        polarity: match entry.polarity {
+            MadtPolarity::ActiveLow => Polarity::ActiveLow,
+            MadtPolarity::ActiveHigh | MadtPolarity::SameAsBus => Polarity::ActiveHigh,
+        },
+        trigger: match entry.trigger_mode {
+            MadtTriggerMode::Level => TriggerMode::Level,
+            MadtTriggerMode::Edge | MadtTriggerMode::SameAsBus => TriggerMode::Edge,
+        },
+    }
+}
+
+/// maps all I/O APICs of the MADT and masks all of their inputs
+pub(super) fn init(ioapics: &[MadtIoApic], overrides: &[InterruptSourceOverride]) {
+    with_routing(|routing| {
+        for madt in ioapics {
+            let addr = madt.address as u64;
+            let window = mmio::ioremap(addr, WINDOW_LEN).expect("I/O APIC mapping failed");
+            let mut ioapic = IoApic {
+                regs: window.leak().as_mut_ptr().cast(),
+                gsi_base: madt.global_system_interrupt_base,
+                inputs: 0,
+            };
+            ioapic.inputs = ((ioapic.read(IOAPICVER) >> 16) & 0xff) + 1;
+            for input in 0..ioapic.inputs {
+                ioapic.set_entry(input, ENTRY_MASKED);
+            }
+            routing.ioapics.push(ioapic);
+        }
+        routing.overrides = overrides
+            .iter()
+            .map(|entry| (entry.isa_source, line_from_override(entry)))
+            .collect();
+    })
+}
+
+/// routes line to vector on the cpu with the local apic id dest, using fixed delivery in physical destination mode
+pub fn route(line: IrqLine, vector: u8, dest: u32) -> Result<(), IrqError> {
+    let dest: u8 = dest.try_into().map_err(|_| IrqError::InvalidArg)?;
+    with_routing(|routing| {
+        let (ioapic, input) = routing.find(line.gsi)?;
+        ioapic.set_entry(input, line.entry(vector, dest));
+        Ok(())
+    })
+}
+
+pub fn mask(gsi: u32) -> Result<(), IrqError> {
+    with_routing(|routing| {
+        let (ioapic, input) = routing.find(gsi)?;
+        ioapic.set_entry(input, ioapic.entry(input) | ENTRY_MASKED);
+        Ok(())
+    })
+}
+
+pub fn unmask(gsi: u32) -> Result<(), IrqError> {
+    with_routing(|routing| {
+        let (ioapic, input) = routing.find(gsi)?;
+        ioapic.set_entry(input, ioapic.entry(input) & !ENTRY_MASKED);
+        Ok(())
+    })
+}
+
+/// the vector gsi is currently routed to, None if it is masked or not wired to any I/O APIC
+pub fn routed_vector(gsi: u32) -> Option<u8> {
+    with_routing(|routing| {
+        let (ioapic, input) = routing.find(gsi).ok()?;
+        let entry = ioapic.entry(input);
+        (entry & ENTRY_MASKED == 0).then_some(entry as u8)
+    })
+}
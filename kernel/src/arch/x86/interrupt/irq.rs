@@ -0,0 +1,227 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use spin::Mutex;
+use thiserror::Error;
+use x86_64::structures::idt::{HandlerFunc, InterruptStackFrame};
+
+use super::{
+    ioapic::{self, IrqLine},
+    pic::end_interrupt,
+    stats::count_interrupt,
+};
+use crate::arch::x86::cpu;
+
+/// the first vector handed out to drivers. Lower vectors are used by exceptions, the timer and the keyboard
+pub const IRQ_BASE: u8 = 0x30;
+/// number of vectors available to drivers
+pub const IRQ_VECTORS: usize = 32;
+
+const MSI_ADDRESS_BASE: u64 = 0xFEE0_0000;
+
+/// called in interrupt context with the vector, which fired. The local apic is acknowledged once it returns
+pub type IrqHandler = fn(u8);
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    #[error("gsi {0} is not wired to any I/O APIC")]
+    NoSuchLine(u32),
+    #[error("no free interrupt vector")]
+    NoVector,
+    #[error("invalid irq request")]
+    InvalidArg,
+}
+
+// the handler of each driver vector as a raw fn pointer, 0 if the vector is free
+static HANDLERS: [AtomicUsize; IRQ_VECTORS] = [const { AtomicUsize::new(0) }; IRQ_VECTORS];
+static NAMES: Mutex<[Option<&'static str>; IRQ_VECTORS]> = Mutex::new([None; IRQ_VECTORS]);
+
+extern "x86-interrupt" fn irq_entry<const N: usize>(_stack_frame: InterruptStackFrame) {
+    count_interrupt(IRQ_BASE + N as u8);
+    let handler = HANDLERS[N].load(Ordering::Acquire);
+    if handler != 0 {
+        // SAFETY: only IrqHandlers are stored in HANDLERS
+        let handler: IrqHandler = unsafe { core::mem::transmute(handler) };
+        handler(IRQ_BASE + N as u8);
+    }
+    end_interrupt();
+}
+
+macro_rules! irq_entries {
+    ($($n:literal)*) => {
+        [$(irq_entry::<$n> as HandlerFunc),*]
+    };
+}
+
+/// the idt entries of all driver vectors, starting at IRQ_BASE
+pub(super) static IRQ_ENTRIES: [HandlerFunc; IRQ_VECTORS] = irq_entries!(
+    0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 31
+);
+
+/// claims count consecutive vectors, aligned to count, for handler. Returns the first one
+fn claim(count: usize, name: &'static str, handler: IrqHandler) -> Result<u8, IrqError> {
+    if count == 0 || !count.is_power_of_two() || count > IRQ_VECTORS {
+        return Err(IrqError::InvalidArg);
+    }
+    let raw = handler as usize;
+    'blocks: for start in (0..IRQ_VECTORS).step_by(count) {
+        for idx in start..start + count {
+            if HANDLERS[idx]
+                .compare_exchange(0, raw, Ordering::AcqRel, Ordering::Relaxed)
+                .is_err()
+            {
+                for claimed in start..idx {
+                    HANDLERS[claimed].store(0, Ordering::Release);
+                }
+                continue 'blocks;
+            }
+        }
+        NAMES.lock()[start..start + count].fill(Some(name));
+        return Ok(IRQ_BASE + start as u8);
+    }
+    Err(IrqError::NoVector)
+}
+
+fn release(vector: u8, count: u8) {
+    let start = (vector - IRQ_BASE) as usize;
+    NAMES.lock()[start..start + count as usize].fill(None);
+    for handler in &HANDLERS[start..start + count as usize] {
+        handler.store(0, Ordering::Release);
+    }
+}
+
+/// interrupts are delivered to the boot processor
+fn irq_dest() -> u32 {
+    cpu::topology()
+        .iter()
+        .find(|cpu| cpu.is_bsp)
+        .map_or_else(cpu::current_apic_id, |cpu| cpu.apic_id)
+}
+
+/// the message a pci device writes to raise an interrupt, as programmed into its MSI capability or MSI-X table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsiMessage {
+    pub address: u64,
+    pub data: u32,
+}
+
+impl MsiMessage {
+    /// edge triggered, fixed delivery of vector to the cpu with the local apic id dest
+    pub fn new(vector: u8, dest: u32) -> Self {
+        Self {
+            address: MSI_ADDRESS_BASE | ((dest as u64 & 0xff) << 12),
+            data: vector as u32,
+        }
+    }
+}
+
+/// vectors allocated by request_irq or alloc_msi. They stay allocated until the handle is passed to free_irq
+#[derive(Debug, PartialEq, Eq)]
+pub struct IrqHandle {
+    vector: u8,
+    count: u8,
+    gsi: Option<u32>,
+}
+
+impl IrqHandle {
+    /// the first allocated vector
+    pub fn vector(&self) -> u8 {
+        self.vector
+    }
+
+    pub fn count(&self) -> u8 {
+        self.count
+    }
+
+    /// the message raising the idx-th vector of this handle.
+    /// Multi message MSI only takes the message of idx 0 and sets the low bits of data itself, MSI-X takes one message per table entry
+    pub fn msi_message(&self, idx: u8) -> Option<MsiMessage> {
+        (self.gsi.is_none() && idx < self.count)
+            .then(|| MsiMessage::new(self.vector + idx, irq_dest()))
+    }
+}
+
+/// allocates a vector for line and routes it there through the I/O APIC.
+/// handler is installed before the line is unmasked
+pub fn request_irq(
+    line: IrqLine,
+    name: &'static str,
+    handler: IrqHandler,
+) -> Result<IrqHandle, IrqError> {
+    let vector = claim(1, name, handler)?;
+    if let Err(e) = ioapic::route(line, vector, irq_dest()) {
+        release(vector, 1);
+        return Err(e);
+    }
+    Ok(IrqHandle {
+        vector,
+        count: 1,
+        gsi: Some(line.gsi),
+    })
+}
+
+/// allocates count vectors for the MSI or MSI-X capability of a pci device, see IrqHandle::msi_message.
+/// count must be a power of two of at most 32, as multi message MSI requires an aligned block of vectors
+pub fn alloc_msi(
+    count: u8,
+    name: &'static str,
+    handler: IrqHandler,
+) -> Result<IrqHandle, IrqError> {
+    let vector = claim(count as usize, name, handler)?;
+    Ok(IrqHandle {
+        vector,
+        count,
+        gsi: None,
+    })
+}
+
+/// masks the line of handle, if it has one, and frees its vectors
+pub fn free_irq(handle: IrqHandle) {
+    if let Some(gsi) = handle.gsi {
+        _ = ioapic::mask(gsi);
+    }
+    release(handle.vector, handle.count);
+}
+
+/// the name vector was requested with, if it is allocated
+pub fn irq_name(vector: u8) -> Option<&'static str> {
+    let idx = vector.checked_sub(IRQ_BASE)? as usize;
+    NAMES.lock().get(idx).copied().flatten()
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+    use crate::arch::x86::interrupt::idt::InterruptIndex;
+
+    fn nop(_vector: u8) {}
+
+    #[kernel_test]
+    fn keyboard_routed() {
+        assert_eq!(
+            ioapic::routed_vector(IrqLine::isa(1).gsi),
+            Some(InterruptIndex::Keyboard as u8)
+        );
+    }
+
+    #[kernel_test]
+    fn msi_vectors_aligned() {
+        let single = alloc_msi(1, "single", nop).unwrap();
+        let block = alloc_msi(4, "block", nop).unwrap();
+        assert_eq!((block.vector() - IRQ_BASE) % 4, 0);
+        assert_ne!(single.vector(), block.vector());
+        assert_eq!(irq_name(block.vector() + 3), Some("block"));
+
+        let msg = block.msi_message(2).unwrap();
+        assert_eq!(msg.address & 0xfff0_0000, MSI_ADDRESS_BASE);
+        assert_eq!(msg.data, (block.vector() + 2) as u32);
+        assert!(block.msi_message(4).is_none());
+        assert_eq!(alloc_msi(3, "odd", nop), Err(IrqError::InvalidArg));
+
+        let vector = block.vector();
+        free_irq(block);
+        free_irq(single);
+        assert_eq!(irq_name(vector), None);
+    }
+}
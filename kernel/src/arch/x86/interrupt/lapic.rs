@@ -0,0 +1,171 @@
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+
+use raw_cpuid::CpuId;
+use x86_64::registers::model_specific::Msr;
+
+use super::{pic::APICOffset, without_interrupts};
+use crate::kernel::mem::mmio;
+
+const IA32_APIC_BASE: u32 = 0x1B;
+const APIC_BASE_X2APIC: u64 = 1 << 10;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+// x2APIC registers are MSRs at this base plus the xAPIC offset / 16
+const X2APIC_MSR_BASE: u32 = 0x800;
+// the xAPIC register page
+const XAPIC_LEN: usize = 0x400;
+
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+const ICR_ASSERT: u32 = 1 << 14;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApicMode {
+    /// not set up yet, all accesses are ignored
+    Disabled,
+    /// memory mapped registers
+    XApic,
+    /// MSR based registers and 32 bit apic ids
+    X2Apic,
+}
+
+/// the destination of an inter processor interrupt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpiDest {
+    /// the cpu with this local apic id
+    Cpu(u32),
+    Current,
+    All,
+    Others,
+}
+
+// the mapped xAPIC registers. Accessed without locking, as the registers themselves are per cpu
+static XAPIC_BASE: AtomicPtr<u32> = AtomicPtr::new(core::ptr::null_mut());
+static X2APIC: AtomicBool = AtomicBool::new(false);
+
+pub fn mode() -> ApicMode {
+    if X2APIC.load(Ordering::Acquire) {
+        ApicMode::X2Apic
+    } else if XAPIC_BASE.load(Ordering::Acquire).is_null() {
+        ApicMode::Disabled
+    } else {
+        ApicMode::XApic
+    }
+}
+
+/// enables the local apic of the current cpu. x2APIC mode is used if the cpu supports it,
+/// otherwise the xAPIC registers at phys_base are mapped
+pub(super) fn init(phys_base: u64) {
+    let has_x2apic = CpuId::new()
+        .get_feature_info()
+        .is_some_and(|info| info.has_x2apic());
+    if has_x2apic {
+        let mut base = Msr::new(IA32_APIC_BASE);
+        // x2APIC mode may only be entered from enabled xAPIC mode
+        unsafe {
+            let value = base.read() | APIC_BASE_ENABLE;
+            base.write(value);
+            base.write(value | APIC_BASE_X2APIC);
+        }
+        X2APIC.store(true, Ordering::Release);
+    } else {
+        let regs = mmio::ioremap(phys_base, XAPIC_LEN).expect("APIC mapping failed");
+        XAPIC_BASE.store(regs.leak().as_mut_ptr().cast(), Ordering::Release);
+    }
+}
+
+fn x2apic_msr(reg: APICOffset) -> Msr {
+    Msr::new(X2APIC_MSR_BASE + (reg as u32 >> 4))
+}
+
+pub fn read(reg: APICOffset) -> u32 {
+    match mode() {
+        ApicMode::Disabled => 0,
+        ApicMode::XApic => unsafe {
+            XAPIC_BASE
+                .load(Ordering::Relaxed)
+                .offset(reg as isize / 4)
+                .read_volatile()
+        },
+        ApicMode::X2Apic => unsafe { x2apic_msr(reg).read() as u32 },
+    }
+}
+
+pub fn write(reg: APICOffset, value: u32) {
+    match mode() {
+        ApicMode::Disabled => {}
+        ApicMode::XApic => unsafe {
+            XAPIC_BASE
+                .load(Ordering::Relaxed)
+                .offset(reg as isize / 4)
+                .write_volatile(value)
+        },
+        ApicMode::X2Apic => unsafe { x2apic_msr(reg).write(value as u64) },
+    }
+}
+
+/// the id of the current local apic, or None if it is not set up yet
+pub fn id() -> Option<u32> {
+    match mode() {
+        ApicMode::Disabled => None,
+        ApicMode::XApic => Some(read(APICOffset::Ir) >> 24),
+        ApicMode::X2Apic => Some(read(APICOffset::Ir)),
+    }
+}
+
+/// signals the end of the interrupt currently being handled
+pub fn eoi() {
+    write(APICOffset::Eoi, 0);
+}
+
+/// sends a fixed interrupt with vector to dest
+pub fn send_ipi(dest: IpiDest, vector: u8) {
+    let (shorthand, target) = match dest {
+        IpiDest::Cpu(id) => (0, id),
+        IpiDest::Current => (1, 0),
+        IpiDest::All => (2, 0),
+        IpiDest::Others => (3, 0),
+    };
+    let low = vector as u32 | ICR_ASSERT | (shorthand << 18);
+    without_interrupts(|| match mode() {
+        ApicMode::Disabled => {}
+        ApicMode::XApic => {
+            write(APICOffset::Icr2, target << 24);
+            write(APICOffset::Icr1, low);
+            while read(APICOffset::Icr1) & ICR_DELIVERY_PENDING != 0 {
+                core::hint::spin_loop();
+            }
+        }
+        // the x2APIC icr is a single 64 bit register, which is written at once
+        ApicMode::X2Apic => unsafe {
+            x2apic_msr(APICOffset::Icr1).write(((target as u64) << 32) | low as u64)
+        },
+    });
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use core::sync::atomic::AtomicU8;
+
+    use os_macros::kernel_test;
+
+    use super::*;
+    use crate::arch::x86::{cpu, interrupt::irq};
+
+    static RECEIVED: AtomicU8 = AtomicU8::new(0);
+
+    fn on_ipi(vector: u8) {
+        RECEIVED.store(vector, Ordering::Release);
+    }
+
+    #[kernel_test]
+    fn self_ipi() {
+        assert_ne!(mode(), ApicMode::Disabled);
+        assert_eq!(id(), Some(cpu::current_apic_id()));
+
+        let handle = irq::alloc_msi(1, "self ipi", on_ipi).unwrap();
+        send_ipi(IpiDest::Current, handle.vector());
+        while RECEIVED.load(Ordering::Acquire) != handle.vector() {
+            core::hint::spin_loop();
+        }
+        irq::free_irq(handle);
+    }
+}
@@ -1,10 +1,17 @@
 pub use x86_64::instructions::interrupts::{are_enabled, without_interrupts};
 
 use crate::println;
+pub mod fault;
 pub mod gdt;
 pub mod handlers;
 mod idt;
+pub mod ioapic;
+pub mod irq;
+pub mod lapic;
+pub mod nmi;
 mod pic;
+pub mod profile;
+pub mod stats;
 use core::arch::asm;
 
 pub use pic::*;
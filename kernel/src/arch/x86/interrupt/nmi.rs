@@ -0,0 +1,206 @@
+use core::{
+    fmt::Arguments,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+use raw_cpuid::CpuId;
+use x86_64::{registers::model_specific::Msr, structures::idt::InterruptStackFrame};
+
+use super::{
+    idt::InterruptIndex,
+    lapic,
+    pic::APICOffset,
+    profile::{self, ProfileSource},
+    stats::{count_interrupt, total_interrupts},
+    without_interrupts,
+};
+use crate::arch::x86::{percpu, serial};
+
+pub(super) const NMI_VECTOR: u8 = 2;
+
+const IA32_PMC0: u32 = 0xC1;
+const IA32_PERFEVTSEL0: u32 = 0x186;
+const IA32_PERF_GLOBAL_CTRL: u32 = 0x38F;
+const IA32_PERF_GLOBAL_OVF_CTRL: u32 = 0x390;
+
+// unhalted core cycles in ring 0 and 3, raising an interrupt on overflow
+const EVTSEL_CYCLES: u64 = 0x3C | (1 << 16) | (1 << 17) | (1 << 20) | (1 << 22);
+const LVT_DELIVER_NMI: u32 = 0b100 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+// wrmsr to IA32_PMC0 only takes the low 32 bits and sign extends them
+const MAX_PERIOD: u64 = (1 << 31) - 1;
+
+static NMIS: AtomicU64 = AtomicU64::new(0);
+static HARD_LOCKUPS: AtomicU64 = AtomicU64::new(0);
+static LAST_RIP: AtomicU64 = AtomicU64::new(0);
+static LAST_TID: AtomicU64 = AtomicU64::new(0);
+
+static WATCHDOG: AtomicBool = AtomicBool::new(false);
+static PERIOD: AtomicU64 = AtomicU64::new(0);
+static COUNTER_MASK: AtomicU64 = AtomicU64::new(0);
+static PERFMON_V2: AtomicBool = AtomicBool::new(false);
+// timer interrupts seen at the last watchdog nmi, and whether none were seen since the one before
+static LAST_TIMER: AtomicU64 = AtomicU64::new(0);
+static STALLED: AtomicBool = AtomicBool::new(false);
+
+/// nmi counters and the location of the last nmi
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NmiStats {
+    pub nmis: u64,
+    pub hard_lockups: u64,
+    pub last_rip: u64,
+    pub last_tid: u64,
+}
+
+pub fn nmi_stats() -> NmiStats {
+    NmiStats {
+        nmis: NMIS.load(Ordering::Relaxed),
+        hard_lockups: HARD_LOCKUPS.load(Ordering::Relaxed),
+        last_rip: LAST_RIP.load(Ordering::Relaxed),
+        last_tid: LAST_TID.load(Ordering::Relaxed),
+    }
+}
+
+// the interrupted code may hold the serial lock, thus nmis bypass it, like panics do
+fn log(args: Arguments) {
+    unsafe { serial::_force_print(args) }
+}
+
+pub(super) extern "x86-interrupt" fn nmi_handler(stack_frame: InterruptStackFrame) {
+    count_interrupt(NMI_VECTOR);
+    NMIS.fetch_add(1, Ordering::Relaxed);
+    let rip = stack_frame.instruction_pointer.as_u64();
+    let tid = percpu::interrupted_tid();
+    LAST_RIP.store(rip, Ordering::Relaxed);
+    LAST_TID.store(tid, Ordering::Relaxed);
+
+    // both counters may have overflowed, thus the watchdog is checked regardless of a sample
+    let sampled = profile::nmi_sample(rip);
+    if watchdog_fired() {
+        check_lockup(rip, tid);
+        arm();
+        return;
+    }
+    if sampled {
+        return;
+    }
+    log(format_args!(
+        "NMI at rip {:#x} in task {} ({:?})\n",
+        rip,
+        tid,
+        stack_frame.code_segment.rpl()
+    ));
+}
+
+/// whether the watchdog counter overflowed, ie lost its top bit
+fn watchdog_fired() -> bool {
+    if !WATCHDOG.load(Ordering::Acquire) {
+        return false;
+    }
+    let mask = COUNTER_MASK.load(Ordering::Relaxed);
+    let value = unsafe { Msr::new(IA32_PMC0).read() } & mask;
+    value & !(mask >> 1) == 0
+}
+
+fn check_lockup(rip: u64, tid: u64) {
+    let timer = total_interrupts(InterruptIndex::Timer as u8);
+    // the timer is not running yet
+    if timer == 0 {
+        return;
+    }
+    if LAST_TIMER.swap(timer, Ordering::Relaxed) != timer {
+        STALLED.store(false, Ordering::Relaxed);
+        return;
+    }
+    // no timer interrupt was handled for a whole period, thus interrupts were disabled all the time
+    if !STALLED.swap(true, Ordering::Relaxed) {
+        HARD_LOCKUPS.fetch_add(1, Ordering::Relaxed);
+        log(format_args!(
+            "hard lockup: no timer interrupt for {} cycles, cpu stuck at rip {:#x} in task {}\n",
+            PERIOD.load(Ordering::Relaxed),
+            rip,
+            tid
+        ));
+    }
+}
+
+fn arm() {
+    let period = PERIOD.load(Ordering::Relaxed);
+    let mask = COUNTER_MASK.load(Ordering::Relaxed);
+    unsafe {
+        Msr::new(IA32_PMC0).write(period.wrapping_neg() & mask);
+        if PERFMON_V2.load(Ordering::Relaxed) {
+            Msr::new(IA32_PERF_GLOBAL_OVF_CTRL).write(1);
+        }
+    }
+    // delivering the nmi masks the lvt entry
+    lapic::write(APICOffset::LvtPmcr, LVT_DELIVER_NMI);
+}
+
+/// starts the hard lockup detector.
+/// The first performance counter counts unhalted cycles and raises an nmi every period cycles (at most 2^31 - 1).
+/// If no timer interrupt was handled between two of them, the interrupted rip and task are logged
+pub fn enable_watchdog(period: u64) -> Result<(), &'static str> {
+    let info = CpuId::new()
+        .get_performance_monitoring_info()
+        .ok_or("no performance monitoring")?;
+    if info.version_id() == 0 || info.number_of_counters() == 0 || info.is_core_cyc_ev_unavailable()
+    {
+        return Err("unhalted cycles can not be counted");
+    }
+    let width = info.counter_bit_width().min(63) as u32;
+    COUNTER_MASK.store((1 << width) - 1, Ordering::Relaxed);
+    PERIOD.store(period.clamp(1, MAX_PERIOD), Ordering::Relaxed);
+    PERFMON_V2.store(info.version_id() >= 2, Ordering::Relaxed);
+    LAST_TIMER.store(
+        total_interrupts(InterruptIndex::Timer as u8),
+        Ordering::Relaxed,
+    );
+    STALLED.store(false, Ordering::Relaxed);
+
+    without_interrupts(|| unsafe {
+        Msr::new(IA32_PERFEVTSEL0).write(0);
+        arm();
+        WATCHDOG.store(true, Ordering::Release);
+        Msr::new(IA32_PERFEVTSEL0).write(EVTSEL_CYCLES);
+        if PERFMON_V2.load(Ordering::Relaxed) {
+            let mut global = Msr::new(IA32_PERF_GLOBAL_CTRL);
+            global.write(global.read() | 1);
+        }
+    });
+    Ok(())
+}
+
+pub fn disable_watchdog() {
+    without_interrupts(|| {
+        unsafe { Msr::new(IA32_PERFEVTSEL0).write(0) };
+        // the profiler shares the lvt entry
+        if profile::profile_source() != ProfileSource::Pmu {
+            lapic::write(APICOffset::LvtPmcr, LVT_MASKED);
+        }
+        WATCHDOG.store(false, Ordering::Release);
+    });
+}
+
+pub fn watchdog_enabled() -> bool {
+    WATCHDOG.load(Ordering::Acquire)
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    #[kernel_test]
+    fn nmi_recorded() {
+        let before = nmi_stats();
+        // the handler cannot tell a software nmi from a real one
+        unsafe { core::arch::asm!("int 2") };
+        let after = nmi_stats();
+        assert_eq!(after.nmis, before.nmis + 1);
+        assert_ne!(after.last_rip, 0);
+        assert_eq!(after.last_tid, percpu::current_tid());
+        assert_eq!(after.hard_lockups, before.hard_lockups);
+    }
+}
@@ -4,33 +4,22 @@ use core::{
 };
 
 use acpi::AcpiTables;
-use lazy_static::lazy_static;
-use spin::Mutex;
 use x86_64::instructions::port::Port;
 
-use super::idt::InterruptIndex;
-use crate::{arch::x86::mem::*, bootinfo, println, serial_println};
-
-lazy_static! {
-    pub static ref LAPIC_ADDR: Mutex<LAPICAddress> = Mutex::new(LAPICAddress::new()); // Needs to be initialized
-}
+use super::{
+    idt::InterruptIndex,
+    ioapic::{self, IrqLine},
+    lapic,
+};
+use crate::{
+    arch::x86::{cpu, mem::*},
+    bootinfo,
+    println,
+    serial_println,
+};
 
 pub static CYCLES_PER_SECOND: AtomicU64 = AtomicU64::new(0);
 pub const CYCLES_PER_TICK: u32 = 1000000;
-pub struct LAPICAddress {
-    address: *mut u32,
-}
-
-unsafe impl Send for LAPICAddress {}
-unsafe impl Sync for LAPICAddress {}
-
-impl LAPICAddress {
-    pub fn new() -> Self {
-        Self {
-            address: core::ptr::null_mut(),
-        }
-    }
-}
 
 // https://wiki.osdev.org/APIC
 #[allow(non_camel_case_types)]
@@ -194,137 +183,57 @@ impl acpi::AcpiHandler for Foo {
     }
 }
 
-fn map_no_cache(
-    physical_address: u64,
-    mapper: &mut impl Mapper<Size4KiB>,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
-) -> VirtAddr {
-    let physical_address = PhysAddr::new(physical_address);
-    let page = Page::containing_address(VirtAddr::new(
-        physical_address.as_u64() + bootinfo::get_phys_offset(),
-    ));
-    let frame = PhysFrame::containing_address(physical_address);
-
-    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE;
-
-    unsafe {
-        mapper
-            .map_to(page, frame, flags, frame_allocator)
-            .expect("APIC mapping failed")
-            .flush();
-    }
-
-    page.start_address()
-}
-
-#[allow(unsafe_op_in_unsafe_fn)]
-unsafe fn init_io_apic(
-    io_apic_addr: usize,
-    mapper: &mut impl Mapper<Size4KiB>,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
-) {
-    let virt_addr = map_no_cache(io_apic_addr as u64, mapper, frame_allocator);
-
-    let ioapic_pointer = virt_addr.as_mut_ptr::<u32>();
-
-    ioapic_pointer.offset(0).write_volatile(0x12);
-    ioapic_pointer
-        .offset(4)
-        .write_volatile(InterruptIndex::Keyboard as u32);
-}
-
-#[allow(unsafe_op_in_unsafe_fn)]
-unsafe fn init_timer(lapic_pointer: *mut u32) {
-    let svr = lapic_pointer.offset(APICOffset::Svr as isize / 4);
-    svr.write_volatile(svr.read_volatile() | 0x100);
+fn init_timer() {
+    lapic::write(APICOffset::Svr, lapic::read(APICOffset::Svr) | 0x100);
 
     // Configure timer
     // Vector 0x20, masked (bit 16 = 1)
-    let lvt_timer = lapic_pointer.offset(APICOffset::LvtT as isize / 4);
-    lvt_timer.write_volatile(0x20 | (1 << 16));
+    lapic::write(APICOffset::LvtT, 0x20 | (1 << 16));
 
     // Set divider to 16
-    let tdcr = lapic_pointer.offset(APICOffset::Tdcr as isize / 4);
-    tdcr.write_volatile(0x3);
-
-    // Set initial count - smaller value for more frequent interrupts
+    lapic::write(APICOffset::Tdcr, 0x3);
 }
 
-pub unsafe fn set_timer_count(ptr: *mut u32, count: u32) {
-    unsafe {
-        let ticr = ptr.offset(APICOffset::Ticr as isize / 4);
-        ticr.write_volatile(count);
-    }
+pub fn set_timer_count(count: u32) {
+    lapic::write(APICOffset::Ticr, count);
 }
 
-pub unsafe fn enable_periodic_timer(ptr: *mut u32) {
-    unsafe {
-        let lvt_timer = ptr.offset(APICOffset::LvtT as isize / 4);
-        let mut val = lvt_timer.read_volatile();
-        val |= 1 << 17;
-        lvt_timer.write_volatile(val);
-    }
+pub fn enable_periodic_timer() {
+    lapic::write(APICOffset::LvtT, lapic::read(APICOffset::LvtT) | (1 << 17));
 }
 
-pub unsafe fn enable_one_shot_mode(ptr: *mut u32) {
-    unsafe {
-        let lvt_timer = ptr.offset(APICOffset::LvtT as isize / 4);
-        let mut val = lvt_timer.read_volatile();
-        val &= !(1 << 17);
-        lvt_timer.write_volatile(val);
-    }
+pub fn enable_one_shot_mode() {
+    lapic::write(APICOffset::LvtT, lapic::read(APICOffset::LvtT) & !(1 << 17));
 }
 
 pub fn enable_timer() {
-    let lapic_ptr = LAPIC_ADDR.lock().address;
-    unsafe {
-        let lvt_timer = lapic_ptr.offset(APICOffset::LvtT as isize / 4);
-        let mut val = lvt_timer.read_volatile();
-        val &= !(1 << 16);
-        lvt_timer.write_volatile(val);
-    }
+    lapic::write(APICOffset::LvtT, lapic::read(APICOffset::LvtT) & !(1 << 16));
 }
 
 pub fn disable_timer() {
-    let lapic_ptr = LAPIC_ADDR.lock().address;
-    unsafe {
-        let lvt_timer = lapic_ptr.offset(APICOffset::LvtT as isize / 4);
-        let mut val = lvt_timer.read_volatile();
-        val |= 1 << 16;
-        lvt_timer.write_volatile(val);
-    }
+    lapic::write(APICOffset::LvtT, lapic::read(APICOffset::LvtT) | (1 << 16));
 }
 
-pub unsafe fn calibrate_apic_timer(ptr: *mut u32) {
-    unsafe { enable_one_shot_mode(ptr) };
+pub fn calibrate_apic_timer() {
+    enable_one_shot_mode();
 
     let test_count = 10_000_000;
     enable_timer();
 
     // read tsc
     let tsc_start = rdtsc();
-    unsafe { set_timer_count(ptr, test_count) };
+    set_timer_count(test_count);
     // wait for timer to finish
-    unsafe {
-        let tccr = ptr.offset(APICOffset::Tccr as isize / 4);
-        while tccr.read_volatile() != 0 {}
-    };
+    while lapic::read(APICOffset::Tccr) != 0 {}
     // read tsc
     let tsc_end = rdtsc();
     disable_timer();
     let delta_tsc = tsc_end - tsc_start;
-    let cpuid = raw_cpuid::CpuId::new();
-    let tsz_freq = if let Some(tsc) = cpuid.get_tsc_info()
-        && let Some(freq) = tsc.tsc_frequency()
-    {
-        freq
-    } else if let Some(base) = cpuid.get_processor_frequency_info() {
-        base.processor_max_frequency() as u64 * 1_000_000
-    } else {
+    let tsz_freq = cpu::tsc_frequency().unwrap_or_else(|| {
         serial_println!("huhu");
         // TODO get actual freq, for noe just some random value (3 GHz)
         3_000_000_000
-    };
+    });
     let apic_ticks_per_s = (test_count as u64 * tsz_freq) / delta_tsc;
     CYCLES_PER_SECOND.store(apic_ticks_per_s, Ordering::Release);
 }
@@ -344,31 +253,21 @@ fn rdtsc() -> u64 {
     ((hi as u64) << 32) | lo as u64
 }
 
-#[allow(unsafe_op_in_unsafe_fn)]
-unsafe fn init_keyboard(lapic_pointer: *mut u32) {
-    let keyboard_register = lapic_pointer.offset(APICOffset::LvtLint1 as isize / 4);
-    keyboard_register.write_volatile(InterruptIndex::Keyboard as u32);
+fn init_keyboard() {
+    lapic::write(APICOffset::LvtLint1, InterruptIndex::Keyboard as u32);
 }
 
 fn drain_keyboard() {
     let _: u8 = unsafe { Port::new(0x60).read() };
 }
 
-#[allow(unsafe_op_in_unsafe_fn)]
-unsafe fn init_local_apic(
-    local_apic_addr: usize,
-    mapper: &mut impl Mapper<Size4KiB>,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
-) {
-    let virtual_address = map_no_cache(local_apic_addr as u64, mapper, frame_allocator);
-    let lapic_pointer = virtual_address.as_mut_ptr::<u32>();
-
-    LAPIC_ADDR.lock().address = lapic_pointer;
-    init_timer(lapic_pointer);
-    calibrate_apic_timer(lapic_pointer);
-    enable_periodic_timer(lapic_pointer);
-    set_timer_count(lapic_pointer, CYCLES_PER_TICK);
-    init_keyboard(lapic_pointer);
+fn init_local_apic(local_apic_addr: usize) {
+    lapic::init(local_apic_addr as u64);
+    init_timer();
+    calibrate_apic_timer();
+    enable_periodic_timer();
+    set_timer_count(CYCLES_PER_TICK);
+    init_keyboard();
     drain_keyboard();
 }
 
@@ -389,33 +288,30 @@ pub(super) fn init_apic() {
     println!("acpi parsed 0");
     let platform_info = acpi_table.platform_info().unwrap();
     println!("acpi parsed");
+    if let Some(processors) = &platform_info.processor_info {
+        cpu::init_topology(
+            &processors.boot_processor,
+            &processors.application_processors,
+        );
+    }
 
     // let phys_apic_base: u32 = acpi_table.find_table::<Madt>().unwrap().local_apic_address;
 
-    let mut page_table = crate::kernel::mem::paging::PAGETABLE.lock();
-    let mut frame_allocator = crate::kernel::mem::paging::get_frame_alloc().lock();
     match platform_info.interrupt_model {
         acpi::InterruptModel::Apic(apic) => {
-            let io_apic_addr = apic.io_apics[0].address;
-            unsafe {
-                init_io_apic(
-                    io_apic_addr as usize,
-                    &mut *page_table,
-                    &mut *frame_allocator,
-                );
-            };
+            ioapic::init(&apic.io_apics, &apic.interrupt_source_overrides);
+            ioapic::route(
+                IrqLine::isa(1),
+                InterruptIndex::Keyboard as u8,
+                cpu::current_apic_id(),
+            )
+            .expect("keyboard irq not wired to an I/O APIC");
             println!("io init");
 
             let local_apic_addr = apic.local_apic_address;
             // cross_println!("addr loc: {:#?}", local_apic_addr as *const u32);
             // cross_println!("phys: {:#?}", bootinfo::get_phys_offset() as *const u32);
-            unsafe {
-                init_local_apic(
-                    local_apic_addr as usize,
-                    &mut *page_table,
-                    &mut *frame_allocator,
-                )
-            };
+            init_local_apic(local_apic_addr as usize);
             println!("local init");
         }
         acpi::InterruptModel::Unknown => {
@@ -432,11 +328,5 @@ pub(super) fn init_apic() {
 
 #[unsafe(no_mangle)]
 pub fn end_interrupt() {
-    unsafe {
-        let lapic_ptr = LAPIC_ADDR.lock().address;
-        // serial_println!("addr: {:#?}", lapic_ptr);
-        lapic_ptr
-            .offset(APICOffset::Eoi as isize / 4)
-            .write_volatile(0);
-    }
+    lapic::eoi();
 }
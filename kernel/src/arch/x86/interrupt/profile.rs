@@ -0,0 +1,172 @@
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicU64, AtomicUsize, Ordering};
+
+use raw_cpuid::CpuId;
+use x86_64::registers::model_specific::Msr;
+
+use super::{lapic, pic::APICOffset, without_interrupts};
+
+// the watchdog uses the first counter, thus samples are taken with the second one
+const IA32_PMC1: u32 = 0xC2;
+const IA32_PERFEVTSEL1: u32 = 0x187;
+const IA32_PERF_GLOBAL_CTRL: u32 = 0x38F;
+const IA32_PERF_GLOBAL_OVF_CTRL: u32 = 0x390;
+
+// unhalted core cycles in ring 0 and 3, raising an interrupt on overflow
+const EVTSEL_CYCLES: u64 = 0x3C | (1 << 16) | (1 << 17) | (1 << 20) | (1 << 22);
+const LVT_DELIVER_NMI: u32 = 0b100 << 8;
+const MAX_PERIOD: u64 = (1 << 31) - 1;
+
+/// number of samples kept. Older samples are overwritten
+pub const MAX_SAMPLES: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ProfileSource {
+    Off,
+    /// an nmi every period unhalted cycles, which also samples code running with interrupts disabled
+    Pmu,
+    /// every timer interrupt, if the cpu has no usable performance counters
+    Timer,
+}
+
+static SOURCE: AtomicU8 = AtomicU8::new(ProfileSource::Off as u8);
+static SAMPLES: [AtomicU64; MAX_SAMPLES] = [const { AtomicU64::new(0) }; MAX_SAMPLES];
+// number of samples taken since profiling was started, the next slot is TAKEN % MAX_SAMPLES
+static TAKEN: AtomicUsize = AtomicUsize::new(0);
+static PERIOD: AtomicU64 = AtomicU64::new(0);
+static COUNTER_MASK: AtomicU64 = AtomicU64::new(0);
+static PERFMON_V2: AtomicBool = AtomicBool::new(false);
+
+pub fn profile_source() -> ProfileSource {
+    match SOURCE.load(Ordering::Acquire) {
+        1 => ProfileSource::Pmu,
+        2 => ProfileSource::Timer,
+        _ => ProfileSource::Off,
+    }
+}
+
+fn record(rip: u64) {
+    let slot = TAKEN.fetch_add(1, Ordering::Relaxed) % MAX_SAMPLES;
+    SAMPLES[slot].store(rip, Ordering::Relaxed);
+}
+
+/// called by the timer interrupt with the interrupted rip
+pub(super) fn timer_sample(rip: u64) {
+    if profile_source() == ProfileSource::Timer {
+        record(rip);
+    }
+}
+
+/// called by the nmi handler. Returns whether the sampling counter overflowed, ie whether the nmi was a sample
+pub(super) fn nmi_sample(rip: u64) -> bool {
+    if profile_source() != ProfileSource::Pmu {
+        return false;
+    }
+    let mask = COUNTER_MASK.load(Ordering::Relaxed);
+    let value = unsafe { Msr::new(IA32_PMC1).read() } & mask;
+    if value & !(mask >> 1) != 0 {
+        return false;
+    }
+    record(rip);
+    arm();
+    true
+}
+
+fn arm() {
+    let period = PERIOD.load(Ordering::Relaxed);
+    let mask = COUNTER_MASK.load(Ordering::Relaxed);
+    unsafe {
+        Msr::new(IA32_PMC1).write(period.wrapping_neg() & mask);
+        if PERFMON_V2.load(Ordering::Relaxed) {
+            Msr::new(IA32_PERF_GLOBAL_OVF_CTRL).write(1 << 1);
+        }
+    }
+    // delivering the nmi masks the lvt entry
+    lapic::write(APICOffset::LvtPmcr, LVT_DELIVER_NMI);
+}
+
+fn start_pmu(period: u64) -> Result<(), &'static str> {
+    let info = CpuId::new()
+        .get_performance_monitoring_info()
+        .ok_or("no performance monitoring")?;
+    if info.version_id() == 0 || info.number_of_counters() < 2 || info.is_core_cyc_ev_unavailable()
+    {
+        return Err("unhalted cycles can not be counted");
+    }
+    let width = info.counter_bit_width().min(63) as u32;
+    COUNTER_MASK.store((1 << width) - 1, Ordering::Relaxed);
+    PERIOD.store(period.clamp(1, MAX_PERIOD), Ordering::Relaxed);
+    PERFMON_V2.store(info.version_id() >= 2, Ordering::Relaxed);
+
+    without_interrupts(|| unsafe {
+        Msr::new(IA32_PERFEVTSEL1).write(0);
+        arm();
+        SOURCE.store(ProfileSource::Pmu as u8, Ordering::Release);
+        Msr::new(IA32_PERFEVTSEL1).write(EVTSEL_CYCLES);
+        if PERFMON_V2.load(Ordering::Relaxed) {
+            let mut global = Msr::new(IA32_PERF_GLOBAL_CTRL);
+            global.write(global.read() | (1 << 1));
+        }
+    });
+    Ok(())
+}
+
+/// discards all samples and starts sampling the rip every period unhalted cycles.
+/// Falls back to sampling on every timer interrupt, if the performance counters cannot be used. Returns the source in use
+pub fn start_profiling(period: u64) -> ProfileSource {
+    stop_profiling();
+    TAKEN.store(0, Ordering::Relaxed);
+    if start_pmu(period).is_ok() {
+        return ProfileSource::Pmu;
+    }
+    SOURCE.store(ProfileSource::Timer as u8, Ordering::Release);
+    ProfileSource::Timer
+}
+
+/// stops sampling. The samples taken are kept until profiling is started again
+pub fn stop_profiling() {
+    without_interrupts(|| {
+        if profile_source() == ProfileSource::Pmu {
+            unsafe { Msr::new(IA32_PERFEVTSEL1).write(0) };
+        }
+        SOURCE.store(ProfileSource::Off as u8, Ordering::Release);
+    });
+}
+
+/// the sampled rips, at most MAX_SAMPLES of the most recent ones
+pub fn samples() -> Vec<u64> {
+    let taken = TAKEN.load(Ordering::Relaxed).min(MAX_SAMPLES);
+    SAMPLES[..taken]
+        .iter()
+        .map(|sample| sample.load(Ordering::Relaxed))
+        .collect()
+}
+
+/// number of samples taken since profiling was started, including overwritten ones
+pub fn samples_taken() -> usize {
+    TAKEN.load(Ordering::Relaxed)
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+    use crate::kernel::threading;
+
+    #[kernel_test]
+    fn samples_recorded() {
+        let source = start_profiling(100_000);
+        assert_ne!(source, ProfileSource::Off);
+        assert_eq!(profile_source(), source);
+        while samples_taken() < 8 {
+            threading::yield_now();
+        }
+        stop_profiling();
+        let taken = samples_taken();
+        assert_eq!(profile_source(), ProfileSource::Off);
+        assert!(samples().iter().all(|rip| *rip != 0));
+        assert_eq!(samples_taken(), taken);
+    }
+}
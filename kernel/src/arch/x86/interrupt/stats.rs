@@ -0,0 +1,105 @@
+use alloc::{format, string::String};
+use core::fmt::Write as _;
+
+use super::{
+    handlers::{PAGE_FAULT_VECTOR, SPURIOUS_VECTOR},
+    idt::InterruptIndex,
+    irq::irq_name,
+    lapic,
+    nmi::NMI_VECTOR,
+};
+pub use crate::sync::counter::MAX_CPUS;
+use crate::{arch::x86::cpu, kernel::flight, sync::counter::PerCpuCounter};
+
+// number of interrupts per vector
+static COUNTS: [PerCpuCounter; 256] = [const { PerCpuCounter::new() }; 256];
+
+/// the index of the current cpu in cpu::topology(). cpuid is too slow to run on every interrupt
+/// and the x86-interrupt handlers may not access per cpu data, thus the local apic is asked instead
+pub fn cpu_slot() -> usize {
+    let Some(apic_id) = lapic::id() else {
+        return 0;
+    };
+    cpu::topology()
+        .iter()
+        .position(|cpu| cpu.apic_id == apic_id)
+        .unwrap_or_default()
+        .min(MAX_CPUS - 1)
+}
+
+/// counts an interrupt on vector for the current cpu. Called from interrupt handlers, thus it neither blocks nor allocates
+pub fn count_interrupt(vector: u8) {
+    COUNTS[vector as usize].inc();
+    if vector != InterruptIndex::Timer as u8 {
+        flight::record(flight::Event::Irq { vector });
+    }
+}
+
+/// number of interrupts on vector handled by the cpu with index cpu in cpu::topology()
+pub fn interrupt_count(vector: u8, cpu: usize) -> u64 {
+    COUNTS[vector as usize].per_cpu(cpu)
+}
+
+/// number of interrupts on vector handled by all cpus
+pub fn total_interrupts(vector: u8) -> u64 {
+    COUNTS[vector as usize].get()
+}
+
+fn vector_name(vector: u8) -> Option<&'static str> {
+    match vector {
+        NMI_VECTOR => Some("Non-maskable interrupts"),
+        PAGE_FAULT_VECTOR => Some("Page fault"),
+        v if v == InterruptIndex::Timer as u8 => Some("LAPIC timer"),
+        v if v == InterruptIndex::Keyboard as u8 => Some("IO-APIC keyboard"),
+        SPURIOUS_VECTOR => Some("Spurious interrupts"),
+        v => irq_name(v),
+    }
+}
+
+/// renders the interrupt counters in the format of /proc/interrupts: one column per cpu and one row per vector,
+/// which either fired or has a handler
+pub fn render_interrupts() -> String {
+    let cpus = cpu::topology().len().clamp(1, MAX_CPUS);
+    let mut out = String::from("    ");
+    for cpu in 0..cpus {
+        _ = write!(out, "{:>11}", format!("CPU{}", cpu));
+    }
+    out.push('\n');
+
+    for vector in 0..=u8::MAX {
+        let name = vector_name(vector);
+        if name.is_none() && total_interrupts(vector) == 0 {
+            continue;
+        }
+        _ = write!(out, "{:>3}:", vector);
+        for cpu in 0..cpus {
+            _ = write!(out, "{:>11}", interrupt_count(vector, cpu));
+        }
+        _ = writeln!(out, "   {}", name.unwrap_or("-"));
+    }
+    out
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    #[kernel_test]
+    fn timer_counted() {
+        let timer = InterruptIndex::Timer as u8;
+        let before = total_interrupts(timer);
+        while total_interrupts(timer) == before {
+            core::hint::spin_loop();
+        }
+
+        let rendered = render_interrupts();
+        assert!(rendered.trim_start().starts_with("CPU0"));
+        assert!(
+            rendered
+                .lines()
+                .any(|line| line.trim_start().starts_with("32:") && line.ends_with("LAPIC timer"))
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use core::{
+    arch::asm,
+    mem::offset_of,
+    ptr::null_mut,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicU64, Ordering},
+};
+
+use x86_64::{
+    VirtAddr,
+    registers::model_specific::{GsBase, KernelGsBase},
+};
+
+// GS-relative per cpu data.
+// While executing kernel code, GS_BASE points to the PerCpu of the current cpu. All kernel entry points, which may be
+// reached from ring 3 (timer and syscall stubs), execute swapgs if they interrupted user code, and the common return path
+// (interrupt_cleanup and the syscall return) swaps back before returning to ring 3. The user gs base is stashed in KERNEL_GS_BASE meanwhile.
+// The remaining x86-interrupt handlers do not swap, thus they must not access per cpu data.
+
+/// per cpu data, accessed through gs.
+#[repr(C)]
+#[derive(Debug)]
+pub struct PerCpu {
+    // gs:[0] always points to the PerCpu itself, such that a reference can be obtained
+    this: AtomicPtr<PerCpu>,
+    current_tid: AtomicU64,
+    // borrowed pointer to the current task. The task manager keeps the task alive while it is running
+    current_task: AtomicPtr<()>,
+    // nesting depth of sections, in which this cpu must not block
+    no_block: AtomicU32,
+    // nesting depth of sections, in which the current task must not be preempted
+    preempt_count: AtomicU32,
+    // set if a preemption was deferred, as preemption was disabled
+    need_resched: AtomicBool,
+    cpu: u32,
+}
+
+impl PerCpu {
+    const fn new(cpu: u32) -> Self {
+        Self {
+            this: AtomicPtr::new(null_mut()),
+            current_tid: AtomicU64::new(0),
+            current_task: AtomicPtr::new(null_mut()),
+            no_block: AtomicU32::new(0),
+            preempt_count: AtomicU32::new(0),
+            need_resched: AtomicBool::new(false),
+            cpu,
+        }
+    }
+
+    pub fn cpu(&self) -> u32 {
+        self.cpu
+    }
+
+    pub fn current_task(&self) -> *const () {
+        self.current_task.load(Ordering::Acquire)
+    }
+
+    pub fn enter_no_block(&self) {
+        self.no_block.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn leave_no_block(&self) {
+        self.no_block.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn may_block(&self) -> bool {
+        self.no_block.load(Ordering::Relaxed) == 0
+    }
+
+    pub fn preempt_disable(&self) {
+        self.preempt_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// returns true if preemption is enabled again
+    pub fn preempt_enable(&self) -> bool {
+        let prev = self.preempt_count.fetch_sub(1, Ordering::Relaxed);
+        debug_assert!(prev != 0, "unbalanced preempt_enable");
+        prev == 1
+    }
+
+    pub fn preempt_count(&self) -> u32 {
+        self.preempt_count.load(Ordering::Relaxed)
+    }
+
+    /// replaces the preemption nesting depth on a switch, returning the one of the previous task
+    pub fn swap_preempt_count(&self, count: u32) -> u32 {
+        self.preempt_count.swap(count, Ordering::Relaxed)
+    }
+
+    pub fn set_need_resched(&self) {
+        self.need_resched.store(true, Ordering::Relaxed);
+    }
+
+    pub fn take_need_resched(&self) -> bool {
+        self.need_resched.swap(false, Ordering::Relaxed)
+    }
+
+    /// sets the current task. task must stay valid until it is replaced
+    pub fn set_current(&self, tid: u64, task: *const ()) {
+        self.current_tid.store(tid, Ordering::Release);
+        self.current_task.store(task as *mut (), Ordering::Release);
+    }
+}
+
+static BSP: PerCpu = PerCpu::new(0);
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// sets up the per cpu data of the boot processor. Must run before any task is created
+pub(super) fn init() {
+    BSP.this
+        .store(&raw const BSP as *mut PerCpu, Ordering::Release);
+    GsBase::write(VirtAddr::from_ptr(&raw const BSP));
+    KernelGsBase::write(VirtAddr::zero());
+    READY.store(true, Ordering::Release);
+}
+
+/// whether gs holds the kernel gs base. Handlers, which do not swap gs, see the user gs base if they interrupted ring 3.
+/// Only the boot processor is brought up, thus its data is the only valid base
+fn has_kernel_gs() -> bool {
+    GsBase::read() == VirtAddr::from_ptr(&raw const BSP)
+}
+
+/// the per cpu data of the current cpu, or None if it is not yet set up
+pub fn this_cpu() -> Option<&'static PerCpu> {
+    if !READY.load(Ordering::Acquire) {
+        return None;
+    }
+    debug_assert!(
+        has_kernel_gs(),
+        "per cpu data accessed with the user gs base"
+    );
+    let ptr: *const PerCpu;
+    unsafe {
+        asm!(
+            "mov {}, gs:[{this}]",
+            out(reg) ptr,
+            this = const offset_of!(PerCpu, this),
+            options(nostack, preserves_flags, readonly)
+        );
+        Some(&*ptr)
+    }
+}
+
+/// the tid of the task running on the current cpu. Reads gs directly, without going through this_cpu()
+pub fn current_tid() -> u64 {
+    if !READY.load(Ordering::Acquire) {
+        return 0;
+    }
+    debug_assert!(
+        has_kernel_gs(),
+        "per cpu data accessed with the user gs base"
+    );
+    let tid: u64;
+    unsafe {
+        asm!(
+            "mov {}, gs:[{tid}]",
+            out(reg) tid,
+            tid = const offset_of!(PerCpu, current_tid),
+            options(nostack, preserves_flags, readonly)
+        );
+    }
+    tid
+}
+
+/// like current_tid, but without touching gs. For handlers, which may interrupt code running with the user gs base,
+/// like the NMI handler. Only the boot processor is brought up, thus its data is read directly
+pub fn interrupted_tid() -> u64 {
+    BSP.current_tid.load(Ordering::Acquire)
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    #[kernel_test]
+    fn percpu_gs() {
+        let cpu = this_cpu().unwrap();
+        assert!(core::ptr::eq(cpu, &BSP));
+        assert_eq!(GsBase::read(), VirtAddr::from_ptr(&raw const BSP));
+        assert_eq!(current_tid(), cpu.current_tid.load(Ordering::Acquire));
+        assert!(has_kernel_gs());
+    }
+}
@@ -0,0 +1,66 @@
+use core::sync::atomic::{AtomicU8, Ordering};
+
+const UNKNOWN: u8 = 0;
+const SUPPORTED: u8 = 1;
+const UNSUPPORTED: u8 = 2;
+
+static RDRAND: AtomicU8 = AtomicU8::new(UNKNOWN);
+
+// rdrand may transiently fail if the entropy source is drained, intel recommends 10 retries
+const RDRAND_RETRIES: usize = 10;
+
+fn has_rdrand() -> bool {
+    match RDRAND.load(Ordering::Relaxed) {
+        SUPPORTED => true,
+        UNSUPPORTED => false,
+        _ => {
+            let supported = raw_cpuid::CpuId::new()
+                .get_feature_info()
+                .is_some_and(|info| info.has_rdrand());
+            RDRAND.store(
+                if supported { SUPPORTED } else { UNSUPPORTED },
+                Ordering::Relaxed,
+            );
+            supported
+        }
+    }
+}
+
+/// returns 64 bits of hardware entropy, or None if rdrand is not available or failed
+pub fn hw_random() -> Option<u64> {
+    if !has_rdrand() {
+        return None;
+    }
+    for _ in 0..RDRAND_RETRIES {
+        let val: u64;
+        let ok: u8;
+        unsafe {
+            core::arch::asm!(
+                "rdrand {val}",
+                "setc {ok}",
+                val = out(reg) val,
+                ok = out(reg_byte) ok,
+                options(nomem, nostack)
+            );
+        }
+        if ok != 0 {
+            return Some(val);
+        }
+    }
+    None
+}
+
+/// reads the tsc without serializing. Cheap and monotonically increasing, useful as noise and for coarse time accounting
+pub fn timestamp() -> u64 {
+    let hi: u32;
+    let lo: u32;
+    unsafe {
+        core::arch::asm!(
+            "rdtsc",
+            out("eax") lo,
+            out("edx") hi,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+    ((hi as u64) << 32) | lo as u64
+}
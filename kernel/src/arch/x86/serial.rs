@@ -1,10 +1,47 @@
-use core::fmt::{Arguments, Write};
+use alloc::{format, string::String};
+use core::{
+    fmt::{self, Arguments, Write},
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+};
 
 use lazy_static::lazy_static;
+use os_macros::init_task;
 use uart_16550::SerialPort;
-use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::{interrupts::without_interrupts, port::Port};
 
-use crate::sync::locks::Mutex;
+use super::interrupt::{
+    ioapic::IrqLine,
+    irq::{self, IrqHandle},
+};
+use crate::{
+    KernelError,
+    KernelRes,
+    bootinfo,
+    sync::locks::{Mutex, SpinLockIrqSave},
+};
+
+// until the irq of COM1 is requested, every print waits until the uart took all of its bytes.
+// Afterwards prints are queued to TX and the uart is refilled from its THR empty interrupt, such that a print only
+// costs the formatting. Once the queue runs full, lines are dropped by level, see LogLevel
+
+/// kernel command line option, which keeps the serial output synchronous, e.g. to see the last lines before a hang
+pub const SYNC_OPTION: &str = "serial_sync";
+
+const COM1: u16 = 0x3F8;
+const COM1_IRQ: u8 = 4;
+// register offsets from COM1
+const THR: u16 = 0;
+const IER: u16 = 1;
+const IIR: u16 = 2;
+const LSR: u16 = 5;
+const IER_THR_EMPTY: u8 = 1 << 1;
+const LSR_THR_EMPTY: u8 = 1 << 5;
+/// bytes the transmit fifo takes at once, once it is empty
+const FIFO_DEPTH: usize = 16;
+
+const TX_CAPACITY: usize = 16 * 1024;
+/// beyond this fill of the queue, debug lines are dropped to keep room for the others
+const HIGH_WATER: usize = TX_CAPACITY * 3 / 4;
 
 lazy_static! {
     static ref SERIAL1: Mutex<SerialPort> = {
@@ -14,9 +51,222 @@ lazy_static! {
     };
 }
 
+// taken from the irq handler
+static TX: SpinLockIrqSave<TxRing> = SpinLockIrqSave::new(TxRing::<TX_CAPACITY>::new());
+static IRQ_DRIVEN: AtomicBool = AtomicBool::new(false);
+static TX_IRQ: spin::Mutex<Option<IrqHandle>> = spin::Mutex::new(None);
+
+static QUEUED: AtomicU64 = AtomicU64::new(0);
+static SENT: AtomicU64 = AtomicU64::new(0);
+static DROPPED_DEBUG: AtomicU64 = AtomicU64::new(0);
+static DROPPED_INFO: AtomicU64 = AtomicU64::new(0);
+/// the fullest the queue has been
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+/// how much a line matters, once the serial output cannot keep up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    /// dropped once the queue is filled beyond its high water mark
+    Debug,
+    /// dropped once it does not fit into the queue anymore
+    Info,
+    /// never dropped. If it does not fit, the printing cpu sends the oldest queued bytes itself, until it does
+    Error,
+}
+
+/// the counters of the queued serial output since boot
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TxStats {
+    pub irq_driven: bool,
+    pub queued: u64,
+    pub sent: u64,
+    pub dropped_debug: u64,
+    pub dropped_info: u64,
+    /// the bytes currently waiting in the queue
+    pub pending: usize,
+    pub peak: usize,
+    pub capacity: usize,
+}
+
+impl TxStats {
+    /// renders the counters in the format of /proc/kernel/serial
+    pub fn render(&self) -> String {
+        format!(
+            "mode\t{}\nqueued\t{}\nsent\t{}\ndropped_debug\t{}\ndropped_info\t{}\npending\t{}\npeak\t{}\ncapacity\t{}\n",
+            if self.irq_driven { "irq" } else { "sync" },
+            self.queued,
+            self.sent,
+            self.dropped_debug,
+            self.dropped_info,
+            self.pending,
+            self.peak,
+            self.capacity
+        )
+    }
+}
+
+pub fn tx_stats() -> TxStats {
+    TxStats {
+        irq_driven: IRQ_DRIVEN.load(Ordering::Acquire),
+        queued: QUEUED.load(Ordering::Relaxed),
+        sent: SENT.load(Ordering::Relaxed),
+        dropped_debug: DROPPED_DEBUG.load(Ordering::Relaxed),
+        dropped_info: DROPPED_INFO.load(Ordering::Relaxed),
+        pending: TX.lock().len,
+        peak: PEAK.load(Ordering::Relaxed),
+        capacity: TX_CAPACITY,
+    }
+}
+
+#[derive(Debug)]
+struct TxRing<const N: usize = TX_CAPACITY> {
+    buf: [u8; N],
+    head: usize,
+    len: usize,
+}
+
+impl<const N: usize> TxRing<N> {
+    const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, byte: u8) -> bool {
+        if self.len == N {
+            return false;
+        }
+        self.buf[(self.head + self.len) % N] = byte;
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let byte = self.buf[self.head];
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(byte)
+    }
+
+    /// drops the bytes pushed after the ring held len bytes
+    fn truncate(&mut self, len: usize) {
+        self.len = self.len.min(len);
+    }
+}
+
+/// appends a single line to the ring, such that it can be dropped as a whole
+struct LineWriter<'a, const N: usize = TX_CAPACITY> {
+    ring: &'a mut TxRing<N>,
+    level: LogLevel,
+    written: usize,
+}
+
+impl<const N: usize> Write for LineWriter<'_, N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            while !self.ring.push(byte) {
+                if self.level < LogLevel::Error {
+                    return Err(fmt::Error);
+                }
+                if let Some(oldest) = self.ring.pop() {
+                    send_polled(oldest);
+                }
+            }
+            self.written += 1;
+        }
+        Ok(())
+    }
+}
+
+fn port(offset: u16) -> Port<u8> {
+    Port::new(COM1 + offset)
+}
+
+fn thr_empty() -> bool {
+    unsafe { port(LSR).read() & LSR_THR_EMPTY != 0 }
+}
+
+fn send_polled(byte: u8) {
+    while !thr_empty() {
+        core::hint::spin_loop();
+    }
+    unsafe { port(THR).write(byte) };
+    SENT.fetch_add(1, Ordering::Relaxed);
+}
+
+/// refills the fifo, if the uart took all of it, and keeps the THR empty interrupt enabled while bytes are queued
+fn kick(ring: &mut TxRing) {
+    if thr_empty() {
+        for byte in (0..FIFO_DEPTH).map_while(|_| ring.pop()) {
+            unsafe { port(THR).write(byte) };
+            SENT.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    let ier = if ring.len > 0 { IER_THR_EMPTY } else { 0 };
+    unsafe { port(IER).write(ier) };
+}
+
+fn tx_irq(_vector: u8) {
+    // reading the identification clears a pending THR empty interrupt
+    _ = unsafe { port(IIR).read() };
+    kick(&mut TX.lock());
+}
+
+fn queue(level: LogLevel, args: Arguments) {
+    let mut ring = TX.lock();
+    if level == LogLevel::Debug && ring.len >= HIGH_WATER {
+        DROPPED_DEBUG.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+    let start = ring.len;
+    let mut line = LineWriter {
+        ring: &mut ring,
+        level,
+        written: 0,
+    };
+    if line.write_fmt(args).is_err() {
+        ring.truncate(start);
+        match level {
+            LogLevel::Debug => DROPPED_DEBUG.fetch_add(1, Ordering::Relaxed),
+            _ => DROPPED_INFO.fetch_add(1, Ordering::Relaxed),
+        };
+        return;
+    }
+    QUEUED.fetch_add(line.written as u64, Ordering::Relaxed);
+    PEAK.fetch_max(ring.len, Ordering::Relaxed);
+    kick(&mut ring);
+}
+
+/// switches to queued output, see the top of this file
+#[init_task(stage = "fs")]
+fn start_irq_driven_tx() -> KernelRes<()> {
+    if bootinfo::cmdline_option(SYNC_OPTION).is_some() {
+        return Ok(());
+    }
+    let handle = irq::request_irq(IrqLine::isa(COM1_IRQ), "com1", tx_irq)
+        .map_err(|_| KernelError::Unexpected("the irq of COM1 could not be requested"))?;
+    *TX_IRQ.lock() = Some(handle);
+    // the lock makes sure, that no synchronous print is halfway through, while the mode changes
+    let _serial = SERIAL1.lock();
+    IRQ_DRIVEN.store(true, Ordering::Release);
+    Ok(())
+}
+
 #[doc(hidden)]
 pub fn _print(args: Arguments) {
-    use core::fmt::Write;
+    _print_level(LogLevel::Info, args)
+}
+
+#[doc(hidden)]
+pub fn _print_level(level: LogLevel, args: Arguments) {
+    if IRQ_DRIVEN.load(Ordering::Acquire) {
+        return queue(level, args);
+    }
     SERIAL1
         .lock()
         .write_fmt(args)
@@ -25,6 +275,22 @@ pub fn _print(args: Arguments) {
 
 #[doc(hidden)]
 pub fn _try_print(args: Arguments) -> Result<(), SerialErr> {
+    if IRQ_DRIVEN.load(Ordering::Acquire) {
+        // dropping the line is better than waiting for the lock
+        let mut ring = TX.try_lock().ok_or(SerialErr::IsLocked)?;
+        let start = ring.len;
+        let mut line = LineWriter {
+            ring: &mut ring,
+            level: LogLevel::Info,
+            written: 0,
+        };
+        if line.write_fmt(args).is_err() {
+            ring.truncate(start);
+            return Err(SerialErr::WriteErr);
+        }
+        kick(&mut ring);
+        return Ok(());
+    }
     SERIAL1
         .try_lock()
         .map(|mut s| s.write_fmt(args).map_err(|_| SerialErr::WriteErr))
@@ -37,8 +303,24 @@ pub enum SerialErr {
     WriteErr,
 }
 
+/// prints slice as is. It is output of the terminal, which is never dropped
 #[doc(hidden)]
 pub fn _raw_print(slice: &[u8]) {
+    if IRQ_DRIVEN.load(Ordering::Acquire) {
+        // the bytes of a terminal need not be utf-8, thus they are pushed one by one
+        let mut ring = TX.lock();
+        for &byte in slice {
+            while !ring.push(byte) {
+                if let Some(oldest) = ring.pop() {
+                    send_polled(oldest);
+                }
+            }
+        }
+        QUEUED.fetch_add(slice.len() as u64, Ordering::Relaxed);
+        PEAK.fetch_max(ring.len, Ordering::Relaxed);
+        kick(&mut ring);
+        return;
+    }
     let mut lock = SERIAL1.lock();
     for byte in slice {
         lock.send(*byte);
@@ -64,3 +346,56 @@ pub unsafe fn _force_print(input: Arguments) {
         _ = guard.write_fmt(input);
     })
 }
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    #[kernel_test]
+    fn ring_drops_whole_lines() {
+        let mut ring = TxRing::<16>::new();
+        for _ in 0..12 {
+            assert!(ring.push(b'x'));
+        }
+        let start = ring.len;
+        let mut line = LineWriter {
+            ring: &mut ring,
+            level: LogLevel::Info,
+            written: 0,
+        };
+        assert!(line.write_str("hello\n").is_err());
+        ring.truncate(start);
+        assert_eq!(ring.len, 12);
+
+        let mut line = LineWriter {
+            ring: &mut ring,
+            level: LogLevel::Info,
+            written: 0,
+        };
+        assert!(line.write_str("ok\n").is_ok());
+        assert_eq!(line.written, 3);
+        assert_eq!(ring.len, 15);
+        for _ in 0..12 {
+            assert_eq!(ring.pop(), Some(b'x'));
+        }
+        assert_eq!(ring.pop(), Some(b'o'));
+        assert_eq!(ring.pop(), Some(b'k'));
+        assert_eq!(ring.pop(), Some(b'\n'));
+        assert_eq!(ring.pop(), None);
+    }
+
+    #[kernel_test]
+    fn stats_count_dropped_lines() {
+        let before = tx_stats();
+        _print_level(LogLevel::Debug, format_args!("serial test line\n"));
+        let after = tx_stats();
+        assert_eq!(after.capacity, TX_CAPACITY);
+        if after.irq_driven {
+            assert!(after.queued > before.queued || after.dropped_debug > before.dropped_debug);
+        }
+        assert!(after.peak <= TX_CAPACITY);
+        assert!(after.render().starts_with("mode\t"));
+    }
+}
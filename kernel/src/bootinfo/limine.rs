@@ -0,0 +1,97 @@
+use core::time::Duration;
+
+use limine::memory_map::EntryType;
+
+use super::{BootInfo, BootModule, FramebufferInfo, MemoryKind, MemoryRegion};
+use crate::requests::*;
+
+/// boot information passed through limine requests
+pub struct Limine;
+
+pub(super) fn init() {
+    assert!(BASE_REVISION.is_supported());
+}
+
+impl BootInfo for Limine {
+    fn name(&self) -> &'static str {
+        "limine"
+    }
+
+    fn phys_offset(&self) -> u64 {
+        HHDM_REQUEST
+            .get_response()
+            .expect("could not get physical offset")
+            .offset()
+    }
+
+    fn rsdp(&self) -> Option<usize> {
+        RSDP_REQUEST.get_response().map(|r| r.address())
+    }
+
+    fn boot_time(&self) -> Option<Duration> {
+        BOOT_TIME_REQUEST.get_response().map(|r| r.timestamp())
+    }
+
+    fn stack_size(&self) -> u64 {
+        if STACK_SIZE_REQUEST.get_response().is_some() {
+            STACK_SIZE_REQUEST.size()
+        } else {
+            4096 * 5
+        }
+    }
+
+    fn memory_region(&self, idx: usize) -> Option<MemoryRegion> {
+        let entry = MMAP_REQUEST.get_response()?.entries().get(idx)?;
+        let kind = match entry.entry_type {
+            EntryType::USABLE => MemoryKind::Usable,
+            EntryType::BOOTLOADER_RECLAIMABLE => MemoryKind::BootloaderReclaimable,
+            EntryType::ACPI_RECLAIMABLE => MemoryKind::AcpiReclaimable,
+            EntryType::ACPI_NVS => MemoryKind::AcpiNvs,
+            EntryType::BAD_MEMORY => MemoryKind::BadMemory,
+            EntryType::FRAMEBUFFER => MemoryKind::Framebuffer,
+            _ => MemoryKind::Reserved,
+        };
+        Some(MemoryRegion {
+            base: entry.base,
+            length: entry.length,
+            kind,
+        })
+    }
+
+    fn framebuffer(&self) -> Option<FramebufferInfo> {
+        let fb = FRAMEBUFFER_REQUEST.get_response()?.framebuffers().next()?;
+        Some(FramebufferInfo {
+            addr: fb.addr(),
+            width: fb.width(),
+            height: fb.height(),
+            pitch: fb.pitch(),
+            bpp: fb.bpp(),
+            red_mask_size: fb.red_mask_size(),
+            red_mask_shift: fb.red_mask_shift(),
+            green_mask_size: fb.green_mask_size(),
+            green_mask_shift: fb.green_mask_shift(),
+            blue_mask_size: fb.blue_mask_size(),
+            blue_mask_shift: fb.blue_mask_shift(),
+        })
+    }
+
+    fn module(&self, idx: usize) -> Option<BootModule> {
+        let file = MODULE_REQUEST.get_response()?.modules().get(idx)?;
+        Some(BootModule {
+            addr: file.addr(),
+            len: file.size() as usize,
+            name: file.path().to_str().unwrap_or_default(),
+        })
+    }
+
+    fn cmdline(&self) -> Option<&'static str> {
+        CMDLINE_REQUEST
+            .get_response()
+            .and_then(|r| r.cmdline().to_str().ok())
+    }
+
+    fn kernel_file(&self) -> Option<&'static [u8]> {
+        let file = EXECUTABLE_FILE_REQUEST.get_response()?.file();
+        Some(unsafe { core::slice::from_raw_parts(file.addr(), file.size() as usize) })
+    }
+}
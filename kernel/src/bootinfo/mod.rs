@@ -0,0 +1,242 @@
+use core::time::Duration;
+
+use conquer_once::spin::OnceCell;
+use lazy_static::lazy_static;
+
+pub use self::{limine::Limine, multiboot2::Multiboot2};
+
+mod limine;
+mod multiboot2;
+
+/// the information the kernel needs from the bootloader, independent of the boot protocol it was loaded with
+pub trait BootInfo: Sync {
+    fn name(&self) -> &'static str;
+    /// the virtual address, at which physical memory is mapped
+    fn phys_offset(&self) -> u64;
+    /// the physical address of the acpi rsdp
+    fn rsdp(&self) -> Option<usize>;
+    fn boot_time(&self) -> Option<Duration>;
+    fn stack_size(&self) -> u64;
+    /// the idx-th entry of the physical memory map
+    fn memory_region(&self, idx: usize) -> Option<MemoryRegion>;
+    fn framebuffer(&self) -> Option<FramebufferInfo>;
+    /// the idx-th file loaded alongside the kernel
+    fn module(&self, idx: usize) -> Option<BootModule>;
+    fn cmdline(&self) -> Option<&'static str>;
+    /// the unmodified kernel elf, including its symbol table
+    fn kernel_file(&self) -> Option<&'static [u8]>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryKind {
+    Usable,
+    /// used by the bootloader, usable once its data is no longer needed
+    BootloaderReclaimable,
+    AcpiReclaimable,
+    AcpiNvs,
+    BadMemory,
+    Framebuffer,
+    Reserved,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub base: u64,
+    pub length: u64,
+    pub kind: MemoryKind,
+}
+
+/// a linear framebuffer set up by the bootloader
+#[derive(Debug, Clone, Copy)]
+pub struct FramebufferInfo {
+    addr: *mut u8,
+    width: u64,
+    height: u64,
+    pitch: u64,
+    bpp: u16,
+    red_mask_size: u8,
+    red_mask_shift: u8,
+    green_mask_size: u8,
+    green_mask_shift: u8,
+    blue_mask_size: u8,
+    blue_mask_shift: u8,
+}
+
+unsafe impl Send for FramebufferInfo {}
+unsafe impl Sync for FramebufferInfo {}
+
+impl FramebufferInfo {
+    /// the virtual address of the first pixel
+    pub fn addr(&self) -> *mut u8 {
+        self.addr
+    }
+
+    pub fn width(&self) -> u64 {
+        self.width
+    }
+
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    /// bytes per row
+    pub fn pitch(&self) -> u64 {
+        self.pitch
+    }
+
+    /// bits per pixel
+    pub fn bpp(&self) -> u16 {
+        self.bpp
+    }
+
+    pub fn red_mask_size(&self) -> u8 {
+        self.red_mask_size
+    }
+
+    pub fn red_mask_shift(&self) -> u8 {
+        self.red_mask_shift
+    }
+
+    pub fn green_mask_size(&self) -> u8 {
+        self.green_mask_size
+    }
+
+    pub fn green_mask_shift(&self) -> u8 {
+        self.green_mask_shift
+    }
+
+    pub fn blue_mask_size(&self) -> u8 {
+        self.blue_mask_size
+    }
+
+    pub fn blue_mask_shift(&self) -> u8 {
+        self.blue_mask_shift
+    }
+}
+
+/// a file loaded by the bootloader alongside the kernel, like an initrd
+#[derive(Debug, Clone, Copy)]
+pub struct BootModule {
+    addr: *const u8,
+    len: usize,
+    name: &'static str,
+}
+
+unsafe impl Send for BootModule {}
+unsafe impl Sync for BootModule {}
+
+impl BootModule {
+    /// the path or command line the module was loaded with
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn bytes(&self) -> &'static [u8] {
+        unsafe { core::slice::from_raw_parts(self.addr, self.len) }
+    }
+}
+
+static PROTOCOL: OnceCell<&'static dyn BootInfo> = OnceCell::uninit();
+
+/// the protocol the kernel was booted with. Limine, unless an other entry point selected one before
+pub fn protocol() -> &'static dyn BootInfo {
+    *PROTOCOL.get_or_init(|| &Limine)
+}
+
+fn set_protocol(protocol: &'static dyn BootInfo) {
+    PROTOCOL.init_once(|| protocol);
+}
+
+/// selects the boot protocol. Must be called first thing in kmain
+pub fn get() {
+    if PROTOCOL.is_initialized() {
+        return;
+    }
+    limine::init();
+    set_protocol(&Limine);
+}
+
+pub fn stack_size() -> u64 {
+    protocol().stack_size()
+}
+
+pub struct UsableMRegion {
+    pub start: u64,
+    pub length: u64,
+}
+
+pub fn boot_time() -> Duration {
+    protocol().boot_time().unwrap_or_default()
+}
+
+pub fn rdsp_addr() -> usize {
+    protocol().rsdp().expect("no rsdp passed by the bootloader")
+}
+
+pub fn cmdline() -> Option<&'static str> {
+    protocol().cmdline()
+}
+
+/// the value of option key, given as key=value on the kernel command line. A bare key yields an empty value
+pub fn cmdline_option(key: &str) -> Option<&'static str> {
+    cmdline()?
+        .split_ascii_whitespace()
+        .find_map(|option| match option.split_once('=') {
+            Some((name, value)) => (name == key).then_some(value),
+            None => (option == key).then_some(""),
+        })
+}
+
+pub fn kernel_file() -> Option<&'static [u8]> {
+    protocol().kernel_file()
+}
+
+pub fn memory_map() -> impl Iterator<Item = MemoryRegion> {
+    (0..).map_while(|idx| protocol().memory_region(idx))
+}
+
+pub fn modules() -> impl Iterator<Item = BootModule> {
+    (0..).map_while(|idx| protocol().module(idx))
+}
+
+pub fn usable_mmap_entries() -> impl Iterator<Item = UsableMRegion> {
+    memory_map().filter_map(|e| match e.kind {
+        MemoryKind::Usable => Some(UsableMRegion {
+            start: if e.base >= 0x100000000 {
+                e.base + get_phys_offset()
+            } else {
+                e.base
+            },
+            length: e.length,
+        }),
+        _ => None,
+    })
+}
+
+/// returns the virtual address, at which the kernel mapping of physical memory starts
+pub fn get_phys_offset() -> u64 {
+    protocol().phys_offset()
+}
+
+pub fn get_framebuffer() -> Option<FramebufferInfo> {
+    protocol().framebuffer()
+}
+
+lazy_static! {
+    pub static ref FIRST_FRAMEBUFFER: FramebufferInfo = get_framebuffer().unwrap();
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    #[kernel_test]
+    fn boot_memory_map() {
+        assert!(memory_map().any(|region| region.kind == MemoryKind::Usable));
+        assert!(usable_mmap_entries().all(|region| region.length > 0));
+        assert_ne!(get_phys_offset(), 0);
+        assert!(FIRST_FRAMEBUFFER.width() > 0 && FIRST_FRAMEBUFFER.bpp() == 32);
+    }
+}
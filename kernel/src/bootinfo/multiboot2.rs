@@ -0,0 +1,384 @@
+use core::{arch::global_asm, ffi::CStr, time::Duration};
+
+use conquer_once::spin::OnceCell;
+
+use super::{BootInfo, BootModule, FramebufferInfo, MemoryKind, MemoryRegion, set_protocol};
+
+/// the magic value a multiboot2 loader passes in eax
+pub const BOOTLOADER_MAGIC: u32 = 0x36d7_6289;
+const HEADER_MAGIC: u32 = 0xe852_50d6;
+
+// the boot stub maps the kernel image, which the loader put at its physical load address, to its link address
+// and the first 4 GiB of physical memory to PHYS_OFFSET, using 2 MiB pages
+const KERNEL_VMA: u64 = 0xffff_ffff_8000_0000;
+const KERNEL_PHYS: u64 = 0x20_0000;
+const PHYS_OFFSET: u64 = 0xffff_8000_0000_0000;
+const MAPPED_PHYS: u64 = 0x1_0000_0000;
+const BOOT_STACK_SIZE: u64 = 64 * 1024;
+
+const TAG_END: u32 = 0;
+const TAG_CMDLINE: u32 = 1;
+const TAG_MODULE: u32 = 3;
+const TAG_MMAP: u32 = 6;
+const TAG_FRAMEBUFFER: u32 = 8;
+const TAG_ACPI_OLD: u32 = 14;
+const TAG_ACPI_NEW: u32 = 15;
+
+const FRAMEBUFFER_RGB: u8 = 1;
+
+global_asm!(
+    "
+    .set MB2_VMA, {vma}
+    .set MB2_PHYS, {phys}
+
+    .section .multiboot2, \"a\"
+    .align 8
+    mb2_header_start:
+        .long {magic}
+        .long 0
+        .long mb2_header_end - mb2_header_start
+        .long 0x100000000 - ({magic} + (mb2_header_end - mb2_header_start))
+
+        // entry address tag, as the elf entry point is the limine entry kmain at its virtual address
+        .align 8
+        .short 3, 0
+        .long 12
+        .long multiboot2_entry32 - MB2_VMA + MB2_PHYS
+
+        // framebuffer tag, prefering 32 bits per pixel
+        .align 8
+        .short 5, 1
+        .long 20
+        .long 0, 0, 32
+
+        .align 8
+        .short 0, 0
+        .long 8
+    mb2_header_end:
+
+    .section .text.multiboot2, \"ax\"
+    .code32
+    multiboot2_entry32:
+        // paging is disabled, thus every symbol is accessed at its physical address
+        cli
+        movl %eax, %esi
+
+        // the first 4 GiB with 2 MiB pages, used for the identity and the physical memory mapping
+        movl $(mb2_pd_low - MB2_VMA + MB2_PHYS), %edi
+        xorl %ecx, %ecx
+    1:
+        movl %ecx, %eax
+        shll $21, %eax
+        orl $0x83, %eax
+        movl %eax, (%edi, %ecx, 8)
+        movl %ecx, %eax
+        shrl $11, %eax
+        movl %eax, 4(%edi, %ecx, 8)
+        incl %ecx
+        cmpl $2048, %ecx
+        jne 1b
+
+        movl $(mb2_pdpt_low - MB2_VMA + MB2_PHYS), %edi
+        movl $(mb2_pd_low - MB2_VMA + MB2_PHYS + 3), %eax
+        xorl %ecx, %ecx
+    2:
+        movl %eax, (%edi, %ecx, 8)
+        addl $4096, %eax
+        incl %ecx
+        cmpl $4, %ecx
+        jne 2b
+
+        // the kernel image at -2 GiB
+        movl $(mb2_pd_kernel - MB2_VMA + MB2_PHYS), %edi
+        xorl %ecx, %ecx
+    3:
+        movl %ecx, %eax
+        shll $21, %eax
+        addl $(MB2_PHYS + 0x83), %eax
+        movl %eax, (%edi, %ecx, 8)
+        incl %ecx
+        cmpl $512, %ecx
+        jne 3b
+
+        movl $(mb2_pdpt_high - MB2_VMA + MB2_PHYS), %edi
+        movl $(mb2_pd_kernel - MB2_VMA + MB2_PHYS + 3), %eax
+        movl %eax, 510 * 8(%edi)
+
+        movl $(mb2_pml4 - MB2_VMA + MB2_PHYS), %edi
+        movl $(mb2_pdpt_low - MB2_VMA + MB2_PHYS + 3), %eax
+        movl %eax, (%edi)
+        movl %eax, 256 * 8(%edi)
+        movl $(mb2_pdpt_high - MB2_VMA + MB2_PHYS + 3), %eax
+        movl %eax, 511 * 8(%edi)
+        movl %edi, %cr3
+
+        // pae
+        movl %cr4, %eax
+        orl $0x20, %eax
+        movl %eax, %cr4
+        // long mode and no execute
+        movl $0xC0000080, %ecx
+        rdmsr
+        orl $0x900, %eax
+        wrmsr
+        // paging, write protect and protected mode
+        movl %cr0, %eax
+        orl $0x80010001, %eax
+        movl %eax, %cr0
+
+        lgdt (mb2_gdt_ptr - MB2_VMA + MB2_PHYS)
+        ljmp $0x08, $(multiboot2_entry64 - MB2_VMA + MB2_PHYS)
+
+    .code64
+    multiboot2_entry64:
+        movw $0x10, %ax
+        movw %ax, %ds
+        movw %ax, %es
+        movw %ax, %ss
+        movw %ax, %fs
+        movw %ax, %gs
+
+        movabsq $mb2_stack_top, %rsp
+        movl %ebx, %edi
+        // rsi holds the magic
+        movabsq $multiboot2_main, %rax
+        callq *%rax
+        ud2
+
+    .section .rodata.multiboot2, \"a\"
+    .align 16
+    mb2_gdt:
+        .quad 0
+        .quad 0x00af9a000000ffff
+        .quad 0x00cf92000000ffff
+    mb2_gdt_ptr:
+        .short mb2_gdt_ptr - mb2_gdt - 1
+        .long mb2_gdt - MB2_VMA + MB2_PHYS
+
+    .section .bss.multiboot2, \"aw\", @nobits
+    .align 4096
+    .global mb2_pml4
+    mb2_pml4:
+        .skip 4096
+    mb2_pdpt_low:
+        .skip 4096
+    mb2_pdpt_high:
+        .skip 4096
+    mb2_pd_low:
+        .skip 4 * 4096
+    mb2_pd_kernel:
+        .skip 4096
+    mb2_stack_bottom:
+        .skip {stack_size}
+    mb2_stack_top:
+
+    .text
+    ",
+    vma = const KERNEL_VMA,
+    phys = const KERNEL_PHYS,
+    magic = const HEADER_MAGIC,
+    stack_size = const BOOT_STACK_SIZE,
+    options(att_syntax)
+);
+
+unsafe extern "C" {
+    static mut mb2_pml4: [u64; 512];
+    static __kernel_end: u8;
+    fn kmain() -> !;
+}
+
+/// boot information passed through the multiboot2 information structure, as with GRUB
+pub struct Multiboot2 {
+    // the physical address of the information structure
+    info: u64,
+    // everything below this physical address is used by the kernel image, the information structure or modules
+    reserved_end: u64,
+}
+
+static MULTIBOOT2: OnceCell<Multiboot2> = OnceCell::uninit();
+
+/// the rust entry of the multiboot2 boot stub, running on the boot stack at the link address of the kernel
+#[unsafe(no_mangle)]
+extern "C" fn multiboot2_main(info: u32, magic: u32) -> ! {
+    assert_eq!(magic, BOOTLOADER_MAGIC, "not loaded by a multiboot2 loader");
+    // the identity mapping was only needed to enter long mode
+    unsafe {
+        (*(&raw mut mb2_pml4))[0] = 0;
+        x86_64::instructions::tlb::flush_all();
+    }
+    let mut boot = Multiboot2 {
+        info: info as u64,
+        reserved_end: 0,
+    };
+    boot.reserved_end = boot.find_reserved_end();
+    set_protocol(MULTIBOOT2.get_or_init(|| boot));
+    unsafe { kmain() }
+}
+
+#[derive(Clone, Copy)]
+struct Tag {
+    typ: u32,
+    // virtual address of the tag header
+    addr: u64,
+    size: u32,
+}
+
+impl Tag {
+    fn read<T: Copy>(&self, offset: usize) -> T {
+        unsafe { ((self.addr as usize + offset) as *const T).read_unaligned() }
+    }
+
+    fn str(&self, offset: usize) -> Option<&'static str> {
+        unsafe { CStr::from_ptr((self.addr as usize + offset) as *const core::ffi::c_char) }
+            .to_str()
+            .ok()
+    }
+}
+
+impl Multiboot2 {
+    fn tags(&self) -> impl Iterator<Item = Tag> {
+        let start = self.info + PHYS_OFFSET;
+        let total = unsafe { (start as *const u32).read() } as u64;
+        let mut next = start + 8;
+        core::iter::from_fn(move || {
+            if next + 8 > start + total {
+                return None;
+            }
+            let tag = Tag {
+                typ: unsafe { (next as *const u32).read() },
+                addr: next,
+                size: unsafe { ((next + 4) as *const u32).read() },
+            };
+            if tag.typ == TAG_END || tag.size < 8 {
+                return None;
+            }
+            next += (tag.size as u64).next_multiple_of(8);
+            Some(tag)
+        })
+    }
+
+    fn tag(&self, typ: u32) -> Option<Tag> {
+        self.tags().find(|tag| tag.typ == typ)
+    }
+
+    fn find_reserved_end(&self) -> u64 {
+        let kernel_end = (&raw const __kernel_end) as u64 - KERNEL_VMA + KERNEL_PHYS;
+        let info_end =
+            self.info + unsafe { ((self.info + PHYS_OFFSET) as *const u32).read() } as u64;
+        let modules_end = self
+            .tags()
+            .filter(|tag| tag.typ == TAG_MODULE)
+            .map(|tag| tag.read::<u32>(12) as u64)
+            .max()
+            .unwrap_or_default();
+        kernel_end.max(info_end).max(modules_end)
+    }
+
+    /// a raw entry of the loaders memory map
+    fn raw_region(&self, idx: usize) -> Option<MemoryRegion> {
+        let tag = self.tag(TAG_MMAP)?;
+        let entry_size = tag.read::<u32>(8) as usize;
+        let offset = 16 + idx * entry_size;
+        if entry_size == 0 || offset + 24 > tag.size as usize {
+            return None;
+        }
+        let kind = match tag.read::<u32>(offset + 16) {
+            1 => MemoryKind::Usable,
+            3 => MemoryKind::AcpiReclaimable,
+            4 => MemoryKind::AcpiNvs,
+            5 => MemoryKind::BadMemory,
+            _ => MemoryKind::Reserved,
+        };
+        Some(MemoryRegion {
+            base: tag.read(offset),
+            length: tag.read(offset + 8),
+            kind,
+        })
+    }
+}
+
+impl BootInfo for Multiboot2 {
+    fn name(&self) -> &'static str {
+        "multiboot2"
+    }
+
+    fn phys_offset(&self) -> u64 {
+        PHYS_OFFSET
+    }
+
+    fn rsdp(&self) -> Option<usize> {
+        // the rsdp is copied into the tag
+        let tag = self.tag(TAG_ACPI_NEW).or_else(|| self.tag(TAG_ACPI_OLD))?;
+        Some((tag.addr - PHYS_OFFSET + 8) as usize)
+    }
+
+    fn boot_time(&self) -> Option<Duration> {
+        None
+    }
+
+    fn stack_size(&self) -> u64 {
+        BOOT_STACK_SIZE
+    }
+
+    /// the memory map of the loader. The loader does not mark memory it or the kernel uses, thus usable memory
+    /// below reserved_end is reported as BootloaderReclaimable, and memory beyond the physical memory mapping as Reserved
+    fn memory_region(&self, idx: usize) -> Option<MemoryRegion> {
+        let mut region = self.raw_region(idx)?;
+        if region.kind != MemoryKind::Usable {
+            return Some(region);
+        }
+        let end = (region.base + region.length).min(MAPPED_PHYS);
+        let start = region.base.max(self.reserved_end);
+        if start >= end {
+            region.kind = if region.base >= MAPPED_PHYS {
+                MemoryKind::Reserved
+            } else {
+                MemoryKind::BootloaderReclaimable
+            };
+            return Some(region);
+        }
+        region.base = start;
+        region.length = end - start;
+        Some(region)
+    }
+
+    fn framebuffer(&self) -> Option<FramebufferInfo> {
+        let tag = self.tag(TAG_FRAMEBUFFER)?;
+        if tag.read::<u8>(29) != FRAMEBUFFER_RGB {
+            return None;
+        }
+        Some(FramebufferInfo {
+            addr: (tag.read::<u64>(8) + PHYS_OFFSET) as *mut u8,
+            pitch: tag.read::<u32>(16) as u64,
+            width: tag.read::<u32>(20) as u64,
+            height: tag.read::<u32>(24) as u64,
+            bpp: tag.read::<u8>(28) as u16,
+            red_mask_shift: tag.read(32),
+            red_mask_size: tag.read(33),
+            green_mask_shift: tag.read(34),
+            green_mask_size: tag.read(35),
+            blue_mask_shift: tag.read(36),
+            blue_mask_size: tag.read(37),
+        })
+    }
+
+    fn module(&self, idx: usize) -> Option<BootModule> {
+        let tag = self.tags().filter(|tag| tag.typ == TAG_MODULE).nth(idx)?;
+        let start = tag.read::<u32>(8) as u64;
+        let end = tag.read::<u32>(12) as u64;
+        Some(BootModule {
+            addr: (start + PHYS_OFFSET) as *const u8,
+            len: end.saturating_sub(start) as usize,
+            name: tag.str(16).unwrap_or_default(),
+        })
+    }
+
+    fn cmdline(&self) -> Option<&'static str> {
+        self.tag(TAG_CMDLINE)?.str(8)
+    }
+
+    // grub only loads the sections of the kernel
+    fn kernel_file(&self) -> Option<&'static [u8]> {
+        None
+    }
+}
@@ -0,0 +1,87 @@
+use alloc::sync::Arc;
+use core::{
+    ops::Deref,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+use crate::kernel::{
+    fs::ramfs::RamFS,
+    threading::{self, JoinHandle},
+};
+
+/// state shared by a kernel test, which is set up before and torn down after each run.
+/// Tests receive fixtures through #[fixture] arguments, see #[kernel_test]
+pub trait Fixture: Sized {
+    fn setup() -> Self;
+
+    fn teardown(self) {}
+}
+
+/// a fresh RamFS, which is not mounted anywhere
+#[derive(Debug)]
+pub struct FreshRamFS(RamFS);
+
+impl Fixture for FreshRamFS {
+    fn setup() -> Self {
+        Self(RamFS::new())
+    }
+}
+
+impl Deref for FreshRamFS {
+    type Target = RamFS;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// a spawned task, which keeps yielding until the test is done.
+/// Teardown stops and joins it, dropping it only stops it
+#[derive(Debug)]
+pub struct HelperTask {
+    stop: Arc<AtomicBool>,
+    iterations: Arc<AtomicU64>,
+    handle: JoinHandle<()>,
+}
+
+impl HelperTask {
+    pub fn handle(&self) -> &JoinHandle<()> {
+        &self.handle
+    }
+
+    /// number of times the helper was scheduled so far
+    pub fn iterations(&self) -> u64 {
+        self.iterations.load(Ordering::Relaxed)
+    }
+}
+
+impl Fixture for HelperTask {
+    fn setup() -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let iterations = Arc::new(AtomicU64::new(0));
+        let (stop_ptr, iterations_ptr) = (stop.clone(), iterations.clone());
+        let handle = threading::spawn(move || {
+            while !stop_ptr.load(Ordering::Acquire) {
+                iterations_ptr.fetch_add(1, Ordering::Relaxed);
+                threading::yield_now();
+            }
+        })
+        .expect("failed to spawn helper task");
+        Self {
+            stop,
+            iterations,
+            handle,
+        }
+    }
+
+    fn teardown(self) {
+        self.stop.store(true, Ordering::Release);
+        self.handle.wait().expect("helper task failed");
+    }
+}
+
+impl Drop for HelperTask {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+    }
+}
@@ -1,3 +1,5 @@
+#[cfg(feature = "test_run")]
+pub mod fixtures;
 pub mod logging;
 pub mod serial;
 use tiny_os_common::testing::{TestCase, TestConfig, TestRunner, TestingError, kernel::RawStr};
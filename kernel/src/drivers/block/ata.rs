@@ -0,0 +1,339 @@
+use alloc::{format, string::String, sync::Arc};
+
+use bitflags::bitflags;
+use x86_64::instructions::port::{Port, PortReadOnly, PortWriteOnly};
+
+use super::{BlockDevice, BlockError, SECTOR_SIZE, check_access, register};
+use crate::{
+    drivers::model::{Device, DeviceId, Driver, DriverError, Match},
+    serial_println,
+    sync::locks::Mutex,
+};
+
+// legacy IDE controllers in compatibility mode, as emulated by QEMU's piix
+const CHANNELS: [(u16, u16); 2] = [(0x1f0, 0x3f6), (0x170, 0x376)];
+// status polls before a command is considered lost
+const TIMEOUT_POLLS: usize = 1_000_000;
+// the largest transfer of a single command, a sector count of 0 means 256 with LBA28
+const MAX_SECTORS: usize = 256;
+const LBA28_LIMIT: u64 = 1 << 28;
+
+const CMD_READ: u8 = 0x20;
+const CMD_READ_EXT: u8 = 0x24;
+const CMD_WRITE: u8 = 0x30;
+const CMD_WRITE_EXT: u8 = 0x34;
+const CMD_FLUSH: u8 = 0xe7;
+const CMD_FLUSH_EXT: u8 = 0xea;
+const CMD_IDENTIFY: u8 = 0xec;
+
+// device control register: no interrupts, the driver polls
+const CONTROL_NIEN: u8 = 1 << 1;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct Status: u8 {
+        const ERR = 1 << 0;
+        const DRQ = 1 << 3;
+        const DF = 1 << 5;
+        const BSY = 1 << 7;
+    }
+}
+
+/// the task file registers of one IDE channel
+struct Channel {
+    data: Port<u16>,
+    features: PortWriteOnly<u8>,
+    sector_count: Port<u8>,
+    lba_low: Port<u8>,
+    lba_mid: Port<u8>,
+    lba_high: Port<u8>,
+    drive: Port<u8>,
+    status: PortReadOnly<u8>,
+    command: PortWriteOnly<u8>,
+    alt_status: PortReadOnly<u8>,
+    control: PortWriteOnly<u8>,
+}
+
+impl Channel {
+    const fn new(io: u16, control: u16) -> Self {
+        Self {
+            data: Port::new(io),
+            features: PortWriteOnly::new(io + 1),
+            sector_count: Port::new(io + 2),
+            lba_low: Port::new(io + 3),
+            lba_mid: Port::new(io + 4),
+            lba_high: Port::new(io + 5),
+            drive: Port::new(io + 6),
+            status: PortReadOnly::new(io + 7),
+            command: PortWriteOnly::new(io + 7),
+            alt_status: PortReadOnly::new(control),
+            control: PortWriteOnly::new(control),
+        }
+    }
+
+    fn status(&mut self) -> Status {
+        Status::from_bits_retain(unsafe { self.status.read() })
+    }
+
+    // reading the alternate status takes ~100ns, the drive needs 400ns to present its status after a select
+    fn delay(&mut self) {
+        for _ in 0..4 {
+            let _: u8 = unsafe { self.alt_status.read() };
+        }
+    }
+
+    fn select(&mut self, slave: bool, lba_bits: u8) {
+        unsafe {
+            self.drive
+                .write(0xe0 | ((slave as u8) << 4) | (lba_bits & 0x0f))
+        };
+        self.delay();
+    }
+
+    fn wait_idle(&mut self) -> Result<Status, BlockError> {
+        for _ in 0..TIMEOUT_POLLS {
+            let status = self.status();
+            if !status.contains(Status::BSY) {
+                return Ok(status);
+            }
+        }
+        Err(BlockError::TimedOut)
+    }
+
+    /// waits until the drive is ready to transfer the next sector
+    fn wait_data(&mut self) -> Result<(), BlockError> {
+        for _ in 0..TIMEOUT_POLLS {
+            let status = self.status();
+            if status.contains(Status::BSY) {
+                continue;
+            }
+            if status.intersects(Status::ERR | Status::DF) {
+                return Err(BlockError::Io);
+            }
+            if status.contains(Status::DRQ) {
+                return Ok(());
+            }
+        }
+        Err(BlockError::TimedOut)
+    }
+
+    /// loads the registers for a transfer of count sectors at lba and issues command
+    fn issue(&mut self, slave: bool, lba: u64, count: usize, lba48: bool, command: u8) {
+        let lba = lba.to_le_bytes();
+        if lba48 {
+            self.select(slave, 0);
+            unsafe {
+                // the high bytes go first, both share the same registers
+                self.sector_count.write((count >> 8) as u8);
+                self.lba_low.write(lba[3]);
+                self.lba_mid.write(lba[4]);
+                self.lba_high.write(lba[5]);
+                self.sector_count.write(count as u8);
+                self.lba_low.write(lba[0]);
+                self.lba_mid.write(lba[1]);
+                self.lba_high.write(lba[2]);
+            }
+        } else {
+            self.select(slave, lba[3]);
+            unsafe {
+                self.features.write(0);
+                self.sector_count.write(count as u8);
+                self.lba_low.write(lba[0]);
+                self.lba_mid.write(lba[1]);
+                self.lba_high.write(lba[2]);
+            }
+        }
+        unsafe { self.command.write(command) };
+    }
+
+    /// runs IDENTIFY DEVICE. Returns None, if there is no ATA drive, like for empty slots and ATAPI drives
+    fn identify(&mut self, slave: bool) -> Option<[u16; 256]> {
+        // a floating bus reads all ones, there is no controller
+        if self.status().bits() == 0xff {
+            return None;
+        }
+        unsafe { self.control.write(CONTROL_NIEN) };
+        self.select(slave, 0);
+        unsafe {
+            self.sector_count.write(0);
+            self.lba_low.write(0);
+            self.lba_mid.write(0);
+            self.lba_high.write(0);
+            self.command.write(CMD_IDENTIFY);
+        }
+        if self.status().is_empty() {
+            return None;
+        }
+        self.wait_idle().ok()?;
+        // packet devices identify themselves through a signature in the lba registers
+        if unsafe { self.lba_mid.read() != 0 || self.lba_high.read() != 0 } {
+            return None;
+        }
+        self.wait_data().ok()?;
+        let mut identity = [0; 256];
+        for word in identity.iter_mut() {
+            *word = unsafe { self.data.read() };
+        }
+        Some(identity)
+    }
+}
+
+/// a drive on a legacy IDE channel, driven by polled PIO.
+/// Slow, but available nearly everywhere, thus the fallback if no faster controller is present
+#[derive(Debug)]
+pub struct AtaDrive {
+    name: String,
+    channel: Arc<Mutex<Channel>>,
+    slave: bool,
+    sectors: u64,
+    lba48: bool,
+    model: String,
+}
+
+impl core::fmt::Debug for Channel {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Channel").finish_non_exhaustive()
+    }
+}
+
+impl AtaDrive {
+    fn new(name: String, channel: Arc<Mutex<Channel>>, slave: bool, identity: &[u16; 256]) -> Self {
+        // words 83 bit 10: LBA48 supported, words 100..104: LBA48 sectors, words 60..62: LBA28 sectors
+        let lba48 = identity[83] & (1 << 10) != 0;
+        let sectors = if lba48 {
+            identity[100..104]
+                .iter()
+                .rev()
+                .fold(0, |acc, word| (acc << 16) | *word as u64)
+        } else {
+            ((identity[61] as u64) << 16) | identity[60] as u64
+        };
+        // words 27..47 hold the model as ascii, with the bytes of each word swapped
+        let model = identity[27..47]
+            .iter()
+            .flat_map(|word| word.to_be_bytes())
+            .map(char::from)
+            .collect::<String>()
+            .trim()
+            .into();
+        Self {
+            name,
+            channel,
+            slave,
+            sectors,
+            lba48,
+            model,
+        }
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+}
+
+impl BlockDevice for AtaDrive {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn num_blocks(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        check_access(self, lba, buf.len())?;
+        let lba48 = self.lba48 && lba + (buf.len() / SECTOR_SIZE) as u64 > LBA28_LIMIT;
+        let mut channel = self.channel.lock();
+        for (idx, chunk) in buf.chunks_mut(MAX_SECTORS * SECTOR_SIZE).enumerate() {
+            let start = lba + (idx * MAX_SECTORS) as u64;
+            let count = chunk.len() / SECTOR_SIZE;
+            channel.wait_idle()?;
+            let command = if lba48 { CMD_READ_EXT } else { CMD_READ };
+            channel.issue(self.slave, start, count, lba48, command);
+            for sector in chunk.chunks_exact_mut(SECTOR_SIZE) {
+                channel.delay();
+                channel.wait_data()?;
+                for bytes in sector.chunks_exact_mut(2) {
+                    bytes.copy_from_slice(&unsafe { channel.data.read() }.to_le_bytes());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        check_access(self, lba, buf.len())?;
+        let lba48 = self.lba48 && lba + (buf.len() / SECTOR_SIZE) as u64 > LBA28_LIMIT;
+        let mut channel = self.channel.lock();
+        for (idx, chunk) in buf.chunks(MAX_SECTORS * SECTOR_SIZE).enumerate() {
+            let start = lba + (idx * MAX_SECTORS) as u64;
+            let count = chunk.len() / SECTOR_SIZE;
+            channel.wait_idle()?;
+            let command = if lba48 { CMD_WRITE_EXT } else { CMD_WRITE };
+            channel.issue(self.slave, start, count, lba48, command);
+            for sector in chunk.chunks_exact(SECTOR_SIZE) {
+                channel.delay();
+                channel.wait_data()?;
+                for bytes in sector.chunks_exact(2) {
+                    unsafe { channel.data.write(u16::from_le_bytes([bytes[0], bytes[1]])) };
+                }
+            }
+        }
+        // the data may still sit in the write cache of the drive
+        channel.wait_idle()?;
+        unsafe {
+            channel
+                .command
+                .write(if lba48 { CMD_FLUSH_EXT } else { CMD_FLUSH })
+        };
+        channel.delay();
+        if channel.wait_idle()?.intersects(Status::ERR | Status::DF) {
+            return Err(BlockError::Io);
+        }
+        Ok(())
+    }
+}
+
+pub static DRIVER: AtaDriver = AtaDriver;
+
+/// probes the master and slave drive of a legacy IDE channel and registers the ATA drives found as hda..hdd
+#[derive(Debug)]
+pub struct AtaDriver;
+
+impl Driver for AtaDriver {
+    fn name(&self) -> &'static str {
+        "ata"
+    }
+
+    fn match_table(&self) -> &'static [Match] {
+        &[Match::Platform("ide0"), Match::Platform("ide1")]
+    }
+
+    fn probe(&self, device: &Arc<dyn Device>) -> Result<(), DriverError> {
+        let idx = match device.id() {
+            DeviceId::Platform("ide0") => 0,
+            DeviceId::Platform("ide1") => 1,
+            _ => return Err(DriverError::NoDevice("not an IDE channel")),
+        };
+        let (io, control) = CHANNELS[idx];
+        let channel = Arc::new(Mutex::new(Channel::new(io, control)));
+        let mut found = false;
+        for slave in [false, true] {
+            let Some(identity) = channel.lock().identify(slave) else {
+                continue;
+            };
+            let name = format!("hd{}", char::from(b'a' + (2 * idx + slave as usize) as u8));
+            let drive = AtaDrive::new(name, channel.clone(), slave, &identity);
+            if drive.sectors == 0 {
+                continue;
+            }
+            serial_println!("ata: {} is {:?}", drive.name, drive.model);
+            register(Arc::new(drive));
+            found = true;
+        }
+        if !found {
+            return Err(DriverError::NoDevice("no drives attached"));
+        }
+        Ok(())
+    }
+}
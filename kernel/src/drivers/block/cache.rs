@@ -0,0 +1,537 @@
+use alloc::{
+    boxed::Box,
+    collections::btree_map::BTreeMap,
+    format,
+    string::String,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use conquer_once::spin::OnceCell;
+use os_macros::{FileRepr, init_task};
+
+use super::{BlockDevice, BlockError, check_access};
+use crate::{
+    drivers::wait_manager,
+    eprintln,
+    kernel::{
+        fs::FSResult,
+        io::{IOResult, Read, read_rendered},
+        threading::{
+            self,
+            pool::ThreadPool,
+            wait::{QueuTypeCondition, QueueType, condition::WaitCondition},
+        },
+        time::Instant,
+    },
+    register_device_file,
+    sync::locks::Mutex,
+};
+
+pub const PAGE_SIZE: usize = 4096;
+/// the pages cached per disk, evicting the least recently used one beyond
+pub const CACHE_PAGES: usize = 256;
+/// the pages read ahead of the first sequential access, doubled on every further one up to MAX_READAHEAD
+pub const INITIAL_READAHEAD: u64 = 4;
+pub const MAX_READAHEAD: u64 = 32;
+/// dirty pages are written back at this interval
+pub const WRITEBACK_INTERVAL: Duration = Duration::from_secs(1);
+/// the sequential readers tracked per disk
+const STREAMS: usize = 8;
+
+static CACHES: Mutex<Vec<Weak<PageCache>>> = Mutex::new(Vec::new());
+/// runs the read-ahead, such that readers do not wait for it
+static WORKQUEUE: OnceCell<Option<ThreadPool>> = OnceCell::uninit();
+
+#[derive(Debug)]
+struct CachedPage {
+    data: Box<[u8]>,
+    dirty: bool,
+    /// the clock of the cache at the last access
+    used: u64,
+}
+
+/// a sequential reader. The reads of an open file look alike, thus a stream follows a file without knowing it
+#[derive(Debug, Clone, Copy)]
+struct Stream {
+    /// the page after the last one read
+    next: u64,
+    window: u64,
+    /// the page after the last one read ahead
+    ahead: u64,
+    used: u64,
+}
+
+/// counters of a page cache
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// pages loaded by the read-ahead
+    pub readahead: u64,
+    pub written_back: u64,
+    pub cached: u64,
+    pub dirty: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    readahead: AtomicU64,
+    written_back: AtomicU64,
+}
+
+/// caches the blocks of a disk in pages. Sequential reads are detected and the following pages are loaded in the
+/// background. Writes only dirty the cached pages, which are written back in batches of adjacent pages by a timer
+#[derive(Debug)]
+pub struct PageCache {
+    device: Arc<dyn BlockDevice>,
+    this: Weak<Self>,
+    pages: Mutex<BTreeMap<u64, CachedPage>>,
+    streams: Mutex<Vec<Stream>>,
+    clock: AtomicU64,
+    counters: Counters,
+}
+
+impl PageCache {
+    /// the cache is written back by the writeback task, as long as it is alive
+    pub fn new(device: Arc<dyn BlockDevice>) -> Arc<Self> {
+        let cache = Arc::new_cyclic(|this| Self {
+            device,
+            this: this.clone(),
+            pages: Mutex::new(BTreeMap::new()),
+            streams: Mutex::new(Vec::with_capacity(STREAMS)),
+            clock: AtomicU64::new(0),
+            counters: Counters::default(),
+        });
+        CACHES.lock().push(Arc::downgrade(&cache));
+        cache
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let pages = self.pages.lock();
+        CacheStats {
+            hits: self.counters.hits.load(Ordering::Relaxed),
+            misses: self.counters.misses.load(Ordering::Relaxed),
+            readahead: self.counters.readahead.load(Ordering::Relaxed),
+            written_back: self.counters.written_back.load(Ordering::Relaxed),
+            cached: pages.len() as u64,
+            dirty: pages.values().filter(|page| page.dirty).count() as u64,
+        }
+    }
+
+    /// writes all dirty pages back, adjacent pages in a single request. Returns the number of pages written
+    pub fn write_back(&self) -> Result<usize, BlockError> {
+        let dirty: Vec<(u64, Box<[u8]>)> = self
+            .pages
+            .lock()
+            .iter_mut()
+            .filter(|(_, page)| page.dirty)
+            .map(|(idx, page)| {
+                page.dirty = false;
+                (*idx, page.data.clone())
+            })
+            .collect();
+        let mut written = 0;
+        let mut rest = dirty.as_slice();
+        while let Some((first, _)) = rest.first() {
+            let run = rest
+                .iter()
+                .zip(*first..)
+                .take_while(|((idx, _), expected)| idx == expected)
+                .count();
+            let (batch, tail) = rest.split_at(run);
+            rest = tail;
+            let data = batch
+                .iter()
+                .flat_map(|(_, data)| data.iter())
+                .copied()
+                .collect::<Vec<_>>();
+            if let Err(e) = self
+                .device
+                .write_blocks(first * self.blocks_per_page(), &data)
+            {
+                // retried on the next writeback, unless the pages were evicted in between
+                let mut pages = self.pages.lock();
+                for (idx, _) in batch.iter().chain(rest) {
+                    if let Some(page) = pages.get_mut(idx) {
+                        page.dirty = true;
+                    }
+                }
+                return Err(e);
+            }
+            written += run;
+        }
+        self.counters
+            .written_back
+            .fetch_add(written as u64, Ordering::Relaxed);
+        Ok(written)
+    }
+
+    fn blocks_per_page(&self) -> u64 {
+        (PAGE_SIZE / self.device.block_size()).max(1) as u64
+    }
+
+    fn page_bytes(&self) -> usize {
+        self.blocks_per_page() as usize * self.device.block_size()
+    }
+
+    fn num_pages(&self) -> u64 {
+        self.device.num_blocks().div_ceil(self.blocks_per_page())
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// reads count pages starting at first from the device. The last page of the disk may be short
+    fn read_pages(&self, first: u64, count: u64) -> Result<Vec<u8>, BlockError> {
+        let lba = first * self.blocks_per_page();
+        let blocks = (count * self.blocks_per_page()).min(self.device.num_blocks() - lba);
+        let mut data = vec![0; blocks as usize * self.device.block_size()];
+        self.device.read_blocks(lba, &mut data)?;
+        Ok(data)
+    }
+
+    /// evicts the least recently used pages, until there is room for another one. Dirty pages are written back first
+    fn make_room(&self, pages: &mut BTreeMap<u64, CachedPage>) -> Result<(), BlockError> {
+        while pages.len() >= CACHE_PAGES {
+            let Some((&idx, page)) = pages.iter().min_by_key(|(_, page)| page.used) else {
+                break;
+            };
+            if page.dirty {
+                self.device
+                    .write_blocks(idx * self.blocks_per_page(), &page.data)?;
+                self.counters.written_back.fetch_add(1, Ordering::Relaxed);
+            }
+            pages.remove(&idx);
+        }
+        Ok(())
+    }
+
+    /// runs f on page, which is loaded from the device if it is not cached.
+    /// Without load, a missing page is zeroed instead, as f overwrites it entirely
+    fn with_page<R>(
+        &self,
+        idx: u64,
+        load: bool,
+        f: impl FnOnce(&mut CachedPage) -> R,
+    ) -> Result<R, BlockError> {
+        if let Some(page) = self.pages.lock().get_mut(&idx) {
+            self.counters.hits.fetch_add(1, Ordering::Relaxed);
+            page.used = self.tick();
+            return Ok(f(page));
+        }
+        self.counters.misses.fetch_add(1, Ordering::Relaxed);
+        let data = if load {
+            self.read_pages(idx, 1)?
+        } else {
+            let (lba, end) = (idx * self.blocks_per_page(), self.device.num_blocks());
+            vec![0; (end - lba).min(self.blocks_per_page()) as usize * self.device.block_size()]
+        };
+        let mut pages = self.pages.lock();
+        self.make_room(&mut pages)?;
+        // another reader may have loaded it meanwhile, whose copy may be dirty already
+        let page = pages.entry(idx).or_insert(CachedPage {
+            data: data.into_boxed_slice(),
+            dirty: false,
+            used: 0,
+        });
+        page.used = self.tick();
+        Ok(f(page))
+    }
+
+    /// loads the pages from first on, which are not cached yet. Adjacent pages are read at once
+    fn prefetch(&self, first: u64, count: u64) -> Result<(), BlockError> {
+        let end = (first + count).min(self.num_pages());
+        let missing: Vec<u64> = {
+            let pages = self.pages.lock();
+            (first..end)
+                .filter(|idx| !pages.contains_key(idx))
+                .collect()
+        };
+        let mut rest = missing.as_slice();
+        while let Some(&start) = rest.first() {
+            let run = rest
+                .iter()
+                .zip(start..)
+                .take_while(|(idx, expected)| *idx == expected)
+                .count();
+            rest = &rest[run..];
+            let data = self.read_pages(start, run as u64)?;
+            let mut pages = self.pages.lock();
+            for (idx, chunk) in (start..).zip(data.chunks(self.page_bytes())) {
+                self.make_room(&mut pages)?;
+                if pages.contains_key(&idx) {
+                    continue;
+                }
+                pages.insert(
+                    idx,
+                    CachedPage {
+                        data: chunk.into(),
+                        dirty: false,
+                        used: self.tick(),
+                    },
+                );
+                self.counters.readahead.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        Ok(())
+    }
+
+    /// records a read of the pages first to last and schedules the read-ahead, if it continues a stream
+    fn read_ahead(&self, first: u64, last: u64) {
+        let next = last + 1;
+        let tick = self.tick();
+        let mut streams = self.streams.lock();
+        let range = if let Some(stream) = streams
+            .iter_mut()
+            // small reads continue within the last page of the stream
+            .find(|stream| stream.next == first || stream.next == first + 1)
+        {
+            stream.window = (stream.window * 2).min(MAX_READAHEAD);
+            stream.next = next;
+            stream.used = tick;
+            // the read-ahead is refilled, once the reader consumed half of it
+            (stream.ahead < next + stream.window / 2).then(|| {
+                let from = stream.ahead.max(next);
+                stream.ahead = next + stream.window;
+                (from, stream.ahead - from)
+            })
+        } else {
+            let stream = Stream {
+                next,
+                window: INITIAL_READAHEAD,
+                ahead: next,
+                used: tick,
+            };
+            if streams.len() < STREAMS {
+                streams.push(stream);
+            } else if let Some(oldest) = streams.iter_mut().min_by_key(|stream| stream.used) {
+                *oldest = stream;
+            }
+            None
+        };
+        drop(streams);
+        if let Some((from, count)) = range
+            && from < self.num_pages()
+        {
+            self.schedule_prefetch(from, count);
+        }
+    }
+
+    fn schedule_prefetch(&self, first: u64, count: u64) {
+        // disks are probed, before there are threads to run the read-ahead
+        if !threading::is_running() {
+            return;
+        }
+        let (Some(this), Some(workqueue)) = (self.this.upgrade(), workqueue()) else {
+            return;
+        };
+        // the read-ahead is only a hint, it is dropped if the workqueue is busy
+        _ = workqueue.try_execute(move || {
+            if let Err(e) = this.prefetch(first, count) {
+                eprintln!("read-ahead of {} failed: {}", this.name(), e);
+            }
+        });
+    }
+
+    fn render(&self) -> String {
+        let stats = self.stats();
+        format!(
+            "hits\t{}\nmisses\t{}\nreadahead\t{}\nwritten_back\t{}\ncached\t{}\ndirty\t{}\n",
+            stats.hits,
+            stats.misses,
+            stats.readahead,
+            stats.written_back,
+            stats.cached,
+            stats.dirty
+        )
+    }
+}
+
+impl BlockDevice for PageCache {
+    fn name(&self) -> &str {
+        self.device.name()
+    }
+
+    fn block_size(&self) -> usize {
+        self.device.block_size()
+    }
+
+    fn num_blocks(&self) -> u64 {
+        self.device.num_blocks()
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        if check_access(self, lba, buf.len())? == 0 {
+            return Ok(());
+        }
+        let (start, page_bytes) = (lba as usize * self.block_size(), self.page_bytes());
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = start + done;
+            let (idx, in_page) = ((pos / page_bytes) as u64, pos % page_bytes);
+            let n = (page_bytes - in_page).min(buf.len() - done);
+            let dst = &mut buf[done..done + n];
+            self.with_page(idx, true, |page| {
+                dst.copy_from_slice(&page.data[in_page..in_page + n])
+            })?;
+            done += n;
+        }
+        self.read_ahead(
+            (start / page_bytes) as u64,
+            ((start + buf.len() - 1) / page_bytes) as u64,
+        );
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        check_access(self, lba, buf.len())?;
+        let (start, page_bytes) = (lba as usize * self.block_size(), self.page_bytes());
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = start + done;
+            let (idx, in_page) = ((pos / page_bytes) as u64, pos % page_bytes);
+            let n = (page_bytes - in_page).min(buf.len() - done);
+            let src = &buf[done..done + n];
+            // the last page of the disk may be short
+            let whole = in_page == 0
+                && (n == page_bytes
+                    || (start + buf.len()) as u64 == self.num_blocks() * self.block_size() as u64);
+            self.with_page(idx, !whole, |page| {
+                page.data[in_page..in_page + n].copy_from_slice(src);
+                page.dirty = true;
+            })?;
+            done += n;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        self.write_back()?;
+        self.device.flush()
+    }
+}
+
+fn workqueue() -> Option<&'static ThreadPool> {
+    WORKQUEUE
+        .get_or_init(|| ThreadPool::new(1, 16).ok())
+        .as_ref()
+}
+
+/// writes the dirty pages of all disks back
+pub fn flush_all() {
+    let caches: Vec<Arc<PageCache>> = {
+        let mut caches = CACHES.lock();
+        caches.retain(|cache| cache.strong_count() > 0);
+        caches.iter().filter_map(Weak::upgrade).collect()
+    };
+    for cache in caches {
+        if let Err(e) = cache.write_back() {
+            eprintln!("writeback of {} failed: {}", cache.name(), e);
+        }
+    }
+}
+
+#[init_task(stage = "drivers", order = 45)]
+fn start_writeback() {
+    _ = threading::spawn(|| {
+        loop {
+            wait_manager::wait_self(&[QueuTypeCondition::with_cond(
+                QueueType::Timer,
+                WaitCondition::Time(Instant::now() + WRITEBACK_INTERVAL),
+            )]);
+            flush_all();
+        }
+    });
+}
+
+/// /proc/block/<disk>/cache: the counters of the page cache of a disk
+#[derive(Debug, FileRepr)]
+#[file(read)]
+pub struct CacheStatFile {
+    cache: Arc<PageCache>,
+}
+
+impl Read for CacheStatFile {
+    fn read(&self, buf: &mut [u8], offset: usize) -> IOResult<usize> {
+        read_rendered(self.cache.render(), buf, offset)
+    }
+}
+
+pub fn register_stats(cache: &Arc<PageCache>) -> FSResult<()> {
+    let path = format!("/block/{}/cache", cache.name());
+    register_device_file!(
+        Arc::new(CacheStatFile {
+            cache: cache.clone()
+        }),
+        path.as_str()
+    )
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::{super::ramdisk::RamDisk, *};
+    use crate::drivers::block::SECTOR_SIZE;
+
+    const PAGE_BLOCKS: u64 = (PAGE_SIZE / SECTOR_SIZE) as u64;
+
+    #[kernel_test]
+    fn write_behind() {
+        let disk = Arc::new(RamDisk::new("cache_test", 8 * PAGE_BLOCKS as usize));
+        let cache = PageCache::new(disk.clone());
+        let mut buf = vec![0; SECTOR_SIZE];
+
+        cache.write_blocks(1, &[0xaa; SECTOR_SIZE]).unwrap();
+        // a whole page is not read before it is overwritten
+        cache
+            .write_blocks(PAGE_BLOCKS, &vec![0xbb; PAGE_SIZE])
+            .unwrap();
+        let stats = cache.stats();
+        assert_eq!((stats.misses, stats.dirty), (2, 2));
+        disk.read_blocks(1, &mut buf).unwrap();
+        assert!(buf.iter().all(|b| *b == 0));
+        cache.read_blocks(1, &mut buf).unwrap();
+        assert!(buf.iter().all(|b| *b == 0xaa));
+        assert_eq!(cache.stats().hits, 1);
+
+        // both pages are adjacent, thus written at once
+        assert_eq!(cache.write_back(), Ok(2));
+        assert_eq!(cache.stats().dirty, 0);
+        disk.read_blocks(1, &mut buf).unwrap();
+        assert!(buf.iter().all(|b| *b == 0xaa));
+        disk.read_blocks(PAGE_BLOCKS + 3, &mut buf).unwrap();
+        assert!(buf.iter().all(|b| *b == 0xbb));
+        assert_eq!(cache.write_back(), Ok(0));
+    }
+
+    #[kernel_test]
+    fn read_ahead() {
+        let disk = Arc::new(RamDisk::new("readahead_test", 8 * PAGE_BLOCKS as usize));
+        let cache = PageCache::new(disk);
+        let mut buf = vec![0; PAGE_SIZE];
+        cache.read_blocks(2 * PAGE_BLOCKS, &mut buf).unwrap();
+        cache.read_blocks(3 * PAGE_BLOCKS, &mut buf).unwrap();
+        assert_eq!(cache.stats().readahead, 0);
+        for _ in 0..10000 {
+            if cache.stats().readahead == 4 {
+                break;
+            }
+            threading::yield_now();
+        }
+        // pages 4 to 7, up to the end of the disk
+        assert_eq!(cache.stats().readahead, 4);
+        let misses = cache.stats().misses;
+        cache.read_blocks(5 * PAGE_BLOCKS, &mut buf).unwrap();
+        assert_eq!(cache.stats().misses, misses);
+    }
+}
@@ -0,0 +1,319 @@
+use alloc::{format, string::String, sync::Arc, vec, vec::Vec};
+use core::fmt::Debug;
+
+use cache::PageCache;
+use os_macros::FileRepr;
+use queue::RequestQueue;
+use thiserror::Error;
+use tinyos_abi::{flags::NodeType, types::FStat};
+
+use crate::{
+    eprintln,
+    kernel::{
+        fd::{FileRepr, IOCapable},
+        fs::{FSErrorKind, FSResult},
+        io::{IOError, IOResult, Read, Write, read_rendered},
+    },
+    register_device_file,
+    serial_println,
+    sync::locks::RwLock,
+};
+
+pub mod ata;
+pub mod cache;
+pub mod partition;
+pub mod queue;
+pub mod ramdisk;
+
+pub const SECTOR_SIZE: usize = 512;
+
+static DEVICES: RwLock<Vec<Arc<dyn BlockDevice>>> = RwLock::new(Vec::new());
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    #[error("access beyond the end of the device")]
+    OutOfRange,
+    #[error("buffer is not a multiple of the block size")]
+    Unaligned,
+    #[error("the device reported an error")]
+    Io,
+    #[error("the device did not respond in time")]
+    TimedOut,
+}
+
+impl From<BlockError> for IOError {
+    fn from(value: BlockError) -> Self {
+        match value {
+            BlockError::OutOfRange => IOError::simple(FSErrorKind::EOF),
+            BlockError::Unaligned => {
+                IOError::with_message(FSErrorKind::Other, "unaligned block access")
+            }
+            BlockError::Io => IOError::with_message(FSErrorKind::Other, "block device error"),
+            BlockError::TimedOut => IOError::simple(FSErrorKind::TimedOut),
+        }
+    }
+}
+
+/// a device addressed in fixed size blocks, like a disk or a partition of one
+pub trait BlockDevice: Debug + Send + Sync {
+    /// the name of the device in /dev
+    fn name(&self) -> &str;
+
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn num_blocks(&self) -> u64;
+
+    /// reads buf.len() / block_size() blocks starting at block lba. buf.len() must be a multiple of block_size()
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError>;
+
+    /// writes buf.len() / block_size() blocks starting at block lba. buf.len() must be a multiple of block_size()
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError>;
+
+    /// makes all blocks written so far durable. Devices, which write through, need not override this
+    fn flush(&self) -> Result<(), BlockError> {
+        Ok(())
+    }
+}
+
+/// checks that buf covers whole blocks within the device, starting at lba. Returns the number of blocks
+pub fn check_access(device: &dyn BlockDevice, lba: u64, len: usize) -> Result<u64, BlockError> {
+    if len % device.block_size() != 0 {
+        return Err(BlockError::Unaligned);
+    }
+    let count = (len / device.block_size()) as u64;
+    if lba
+        .checked_add(count)
+        .is_none_or(|end| end > device.num_blocks())
+    {
+        return Err(BlockError::OutOfRange);
+    }
+    Ok(count)
+}
+
+/// registers device at /dev/<name> and each partition found on it at /dev/<name><index>.
+/// All of them share a page cache and a request queue in front of device
+pub fn register(device: Arc<dyn BlockDevice>) {
+    let queue = Arc::new(RequestQueue::new(device));
+    if let Err(e) = queue::register_stats(&queue) {
+        eprintln!("could not register the queue of {}: {}", queue.name(), e);
+    }
+    let cache = PageCache::new(queue);
+    if let Err(e) = cache::register_stats(&cache) {
+        eprintln!("could not register the cache of {}: {}", cache.name(), e);
+    }
+    let device = cache as Arc<dyn BlockDevice>;
+    serial_println!(
+        "block device {}: {} blocks of {} bytes",
+        device.name(),
+        device.num_blocks(),
+        device.block_size()
+    );
+    let partitions = partition::scan(&device).unwrap_or_else(|e| {
+        eprintln!("could not read the partitions of {}: {}", device.name(), e);
+        Vec::new()
+    });
+    for device in [device].into_iter().chain(
+        partitions
+            .into_iter()
+            .map(|p| Arc::new(p) as Arc<dyn BlockDevice>),
+    ) {
+        if let Err(e) = register_file(&device) {
+            eprintln!("could not register block device {}: {}", device.name(), e);
+            continue;
+        }
+        DEVICES.write().push(device);
+    }
+}
+
+fn register_file(device: &Arc<dyn BlockDevice>) -> FSResult<()> {
+    let path = format!("/dev/{}", device.name());
+    register_device_file!(
+        Arc::new(BlockFile::new(device.clone())) as Arc<dyn FileRepr>,
+        path.as_str()
+    )?;
+    let path = format!("/block/{}/info", device.name());
+    register_device_file!(
+        Arc::new(BlockInfoFile {
+            device: device.clone()
+        }),
+        path.as_str()
+    )
+}
+
+/// all registered disks and partitions
+pub fn devices() -> Vec<Arc<dyn BlockDevice>> {
+    DEVICES.read().clone()
+}
+
+pub fn find(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    DEVICES
+        .read()
+        .iter()
+        .find(|device| device.name() == name)
+        .cloned()
+}
+
+/// byte addressed view of a block device. Partial blocks are written by reading, patching and writing back the whole block
+#[derive(Debug)]
+pub struct BlockFile {
+    device: Arc<dyn BlockDevice>,
+}
+
+impl BlockFile {
+    pub fn new(device: Arc<dyn BlockDevice>) -> Self {
+        Self { device }
+    }
+
+    fn size(&self) -> usize {
+        self.device.num_blocks() as usize * self.device.block_size()
+    }
+}
+
+impl Read for BlockFile {
+    fn read(&self, buf: &mut [u8], offset: usize) -> IOResult<usize> {
+        let len = buf.len().min(self.size().saturating_sub(offset));
+        if len == 0 {
+            return Ok(0);
+        }
+        let block_size = self.device.block_size();
+        let first = offset / block_size;
+        let last = (offset + len).div_ceil(block_size);
+        let mut blocks = vec![0; (last - first) * block_size];
+        self.device.read_blocks(first as u64, &mut blocks)?;
+        let start = offset - first * block_size;
+        buf[..len].copy_from_slice(&blocks[start..start + len]);
+        Ok(len)
+    }
+}
+
+impl Write for BlockFile {
+    fn write(&self, buf: &[u8], offset: usize) -> IOResult<usize> {
+        let len = buf.len().min(self.size().saturating_sub(offset));
+        if len == 0 {
+            return if buf.is_empty() {
+                Ok(0)
+            } else {
+                Err(IOError::simple(FSErrorKind::StorageFull))
+            };
+        }
+        let block_size = self.device.block_size();
+        let first = offset / block_size;
+        let last = (offset + len).div_ceil(block_size);
+        let mut blocks = vec![0; (last - first) * block_size];
+        let start = offset - first * block_size;
+        // only the partially written blocks at either end need to be read back
+        if start != 0 {
+            self.device
+                .read_blocks(first as u64, &mut blocks[..block_size])?;
+        }
+        if (start + len) % block_size != 0 && (last - first > 1 || start == 0) {
+            let tail = blocks.len() - block_size;
+            self.device
+                .read_blocks((last - 1) as u64, &mut blocks[tail..])?;
+        }
+        blocks[start..start + len].copy_from_slice(&buf[..len]);
+        self.device.write_blocks(first as u64, &blocks)?;
+        Ok(len)
+    }
+}
+
+impl FileRepr for BlockFile {
+    fn fstat(&self) -> FStat {
+        let mut stat = FStat::default();
+        stat.size = self.size();
+        stat.node_type = NodeType::FILE;
+        stat
+    }
+}
+
+impl IOCapable for BlockFile {}
+
+/// /proc/block/<name>/info: the geometry of a disk or partition
+#[derive(Debug, FileRepr)]
+#[file(read)]
+pub struct BlockInfoFile {
+    device: Arc<dyn BlockDevice>,
+}
+
+impl BlockInfoFile {
+    fn render(&self) -> String {
+        format!(
+            "blocks\t{}\nblock_size\t{}\nbytes\t{}\n",
+            self.device.num_blocks(),
+            self.device.block_size(),
+            self.device.num_blocks() * self.device.block_size() as u64
+        )
+    }
+}
+
+impl Read for BlockInfoFile {
+    fn read(&self, buf: &mut [u8], offset: usize) -> IOResult<usize> {
+        read_rendered(self.render(), buf, offset)
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::{ramdisk::RamDisk, *};
+
+    #[kernel_test]
+    fn unaligned_file_access() {
+        let disk = Arc::new(RamDisk::new("ram_block_test", 4));
+        let file = BlockFile::new(disk.clone());
+        assert_eq!(file.fstat().size, 4 * SECTOR_SIZE);
+
+        // spans the end of block 0 and the start of block 1
+        let data = [0xab; 20];
+        assert_eq!(file.write(&data, SECTOR_SIZE - 10).unwrap(), 20);
+        // within a single block
+        assert_eq!(file.write(b"tiny", 2 * SECTOR_SIZE + 3).unwrap(), 4);
+
+        let mut buf = [0; 24];
+        assert_eq!(file.read(&mut buf, SECTOR_SIZE - 12).unwrap(), 24);
+        assert_eq!(&buf[..2], &[0, 0]);
+        assert_eq!(&buf[2..22], &data);
+        assert_eq!(&buf[22..], &[0, 0]);
+        assert_eq!(file.read(&mut buf[..4], 2 * SECTOR_SIZE + 3).unwrap(), 4);
+        assert_eq!(&buf[..4], b"tiny");
+
+        // clamped at the end of the device
+        assert_eq!(file.read(&mut buf, 4 * SECTOR_SIZE - 4).unwrap(), 4);
+        assert_eq!(file.read(&mut buf, 4 * SECTOR_SIZE).unwrap(), 0);
+        assert!(file.write(b"x", 4 * SECTOR_SIZE).is_err());
+        assert_eq!(
+            disk.read_blocks(3, &mut [0; SECTOR_SIZE * 2]),
+            Err(BlockError::OutOfRange)
+        );
+        assert_eq!(disk.read_blocks(0, &mut [0; 3]), Err(BlockError::Unaligned));
+    }
+    #[kernel_test]
+    fn flush_through_layers() {
+        let disk = Arc::new(RamDisk::new("ram_flush_test", 16));
+        let cache = cache::PageCache::new(disk.clone());
+        let partition = partition::Partition::new(cache, 1, 8, 8);
+        partition.write_blocks(1, &[0x11; SECTOR_SIZE]).unwrap();
+
+        let mut buf = [0; SECTOR_SIZE];
+        disk.read_blocks(9, &mut buf).unwrap();
+        assert_eq!(buf, [0; SECTOR_SIZE]);
+        partition.flush().unwrap();
+        disk.read_blocks(9, &mut buf).unwrap();
+        assert_eq!(buf, [0x11; SECTOR_SIZE]);
+
+        let info = BlockInfoFile {
+            device: Arc::new(partition),
+        };
+        assert_eq!(
+            info.render(),
+            format!(
+                "blocks\t8\nblock_size\t{0}\nbytes\t{1}\n",
+                SECTOR_SIZE,
+                8 * SECTOR_SIZE
+            )
+        );
+    }
+}
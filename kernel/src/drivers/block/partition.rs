@@ -0,0 +1,235 @@
+use alloc::{format, string::String, sync::Arc, vec, vec::Vec};
+
+use super::{BlockDevice, BlockError, SECTOR_SIZE, check_access};
+
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+const MBR_ENTRIES: usize = 446;
+const MBR_ENTRY_SIZE: usize = 16;
+// partition types, which do not hold a filesystem themselves
+const MBR_EMPTY: u8 = 0x00;
+const MBR_EXTENDED: [u8; 3] = [0x05, 0x0f, 0x85];
+const MBR_GPT_PROTECTIVE: u8 = 0xee;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+// upper bound on the entries read, the spec requires room for at least 128
+const GPT_MAX_ENTRIES: u32 = 128;
+
+/// a contiguous range of blocks on a disk, exposed as a block device of its own
+#[derive(Debug)]
+pub struct Partition {
+    disk: Arc<dyn BlockDevice>,
+    name: String,
+    /// the number of the partition, starting at 1
+    index: usize,
+    start: u64,
+    blocks: u64,
+}
+
+impl Partition {
+    pub fn new(disk: Arc<dyn BlockDevice>, index: usize, start: u64, blocks: u64) -> Self {
+        Self {
+            name: format!("{}{}", disk.name(), index),
+            disk,
+            index,
+            start,
+            blocks,
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// first block of the partition on the disk
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+}
+
+impl BlockDevice for Partition {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_size(&self) -> usize {
+        self.disk.block_size()
+    }
+
+    fn num_blocks(&self) -> u64 {
+        self.blocks
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        check_access(self, lba, buf.len())?;
+        self.disk.read_blocks(self.start + lba, buf)
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        check_access(self, lba, buf.len())?;
+        self.disk.write_blocks(self.start + lba, buf)
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        self.disk.flush()
+    }
+}
+
+/// reads the partition table of disk. Understands MBR, without logical partitions, and GPT.
+/// Partitions reaching beyond the end of the disk are skipped. A disk without a partition table has no partitions,
+/// neither has a disk with blocks too small to hold an MBR
+pub fn scan(disk: &Arc<dyn BlockDevice>) -> Result<Vec<Partition>, BlockError> {
+    if disk.num_blocks() == 0 || disk.block_size() < SECTOR_SIZE {
+        return Ok(Vec::new());
+    }
+    let mut mbr = vec![0; disk.block_size()];
+    disk.read_blocks(0, &mut mbr)?;
+    if mbr[510..512] != MBR_SIGNATURE {
+        return Ok(Vec::new());
+    }
+
+    let mut ranges = Vec::new();
+    for (idx, entry) in mbr[MBR_ENTRIES..MBR_ENTRIES + 4 * MBR_ENTRY_SIZE]
+        .chunks_exact(MBR_ENTRY_SIZE)
+        .enumerate()
+    {
+        let kind = entry[4];
+        if kind == MBR_GPT_PROTECTIVE {
+            return scan_gpt(disk);
+        }
+        if kind == MBR_EMPTY || MBR_EXTENDED.contains(&kind) {
+            continue;
+        }
+        let start = u32::from_le_bytes(entry[8..12].try_into().unwrap()) as u64;
+        let blocks = u32::from_le_bytes(entry[12..16].try_into().unwrap()) as u64;
+        ranges.push((idx + 1, start, blocks));
+    }
+    Ok(into_partitions(disk, ranges))
+}
+
+fn scan_gpt(disk: &Arc<dyn BlockDevice>) -> Result<Vec<Partition>, BlockError> {
+    let block_size = disk.block_size();
+    let mut header = vec![0; block_size];
+    disk.read_blocks(1, &mut header)?;
+    if &header[..8] != GPT_SIGNATURE {
+        return Ok(Vec::new());
+    }
+    let read_u32 = |at: usize| u32::from_le_bytes(header[at..at + 4].try_into().unwrap());
+    let entries_lba = u64::from_le_bytes(header[72..80].try_into().unwrap());
+    let entries = read_u32(80).min(GPT_MAX_ENTRIES) as usize;
+    let entry_size = read_u32(84) as usize;
+    if entry_size < 128 || entry_size > block_size {
+        return Err(BlockError::Io);
+    }
+
+    let table_blocks = (entries * entry_size).div_ceil(block_size);
+    let mut table = vec![0; table_blocks * block_size];
+    disk.read_blocks(entries_lba, &mut table)?;
+
+    let ranges = table
+        .chunks_exact(entry_size)
+        .take(entries)
+        .enumerate()
+        // an all zero type guid marks an unused entry
+        .filter(|(_, entry)| entry[..16].iter().any(|byte| *byte != 0))
+        // entries ending at u64::MAX can not be described by their length and are skipped
+        .filter_map(|(idx, entry)| {
+            let first = u64::from_le_bytes(entry[32..40].try_into().unwrap());
+            let last = u64::from_le_bytes(entry[40..48].try_into().unwrap());
+            Some((idx + 1, first, last.checked_add(1)?.saturating_sub(first)))
+        })
+        .collect();
+    Ok(into_partitions(disk, ranges))
+}
+
+fn into_partitions(disk: &Arc<dyn BlockDevice>, ranges: Vec<(usize, u64, u64)>) -> Vec<Partition> {
+    ranges
+        .into_iter()
+        .filter(|(_, start, blocks)| {
+            *blocks > 0
+                && start
+                    .checked_add(*blocks)
+                    .is_some_and(|end| end <= disk.num_blocks())
+        })
+        .map(|(index, start, blocks)| Partition::new(disk.clone(), index, start, blocks))
+        .collect()
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+    use crate::drivers::block::ramdisk::RamDisk;
+
+    fn mbr_entry(mbr: &mut [u8], slot: usize, kind: u8, start: u32, blocks: u32) {
+        let entry = &mut mbr[MBR_ENTRIES + slot * MBR_ENTRY_SIZE..][..MBR_ENTRY_SIZE];
+        entry[4] = kind;
+        entry[8..12].copy_from_slice(&start.to_le_bytes());
+        entry[12..16].copy_from_slice(&blocks.to_le_bytes());
+    }
+
+    #[kernel_test]
+    fn mbr_partitions() {
+        let disk: Arc<dyn BlockDevice> = Arc::new(RamDisk::new("mbr_test", 64));
+        assert!(scan(&disk).unwrap().is_empty());
+
+        let mut mbr = [0; SECTOR_SIZE];
+        mbr[510..].copy_from_slice(&MBR_SIGNATURE);
+        mbr_entry(&mut mbr, 0, 0x83, 8, 16);
+        mbr_entry(&mut mbr, 1, 0x05, 24, 8);
+        // reaches past the end of the disk
+        mbr_entry(&mut mbr, 2, 0x83, 60, 8);
+        mbr_entry(&mut mbr, 3, 0x0c, 32, 32);
+        disk.write_blocks(0, &mbr).unwrap();
+
+        let partitions = scan(&disk).unwrap();
+        let found: Vec<_> = partitions
+            .iter()
+            .map(|p| (p.name(), p.start(), p.num_blocks()))
+            .collect();
+        assert_eq!(found, [("mbr_test1", 8, 16), ("mbr_test4", 32, 32)]);
+
+        // partitions are offset into the disk and may not leave their range
+        let block = [0x5a; SECTOR_SIZE];
+        partitions[0].write_blocks(1, &block).unwrap();
+        let mut buf = [0; SECTOR_SIZE];
+        disk.read_blocks(9, &mut buf).unwrap();
+        assert_eq!(buf, block);
+        assert_eq!(
+            partitions[0].read_blocks(16, &mut buf),
+            Err(BlockError::OutOfRange)
+        );
+    }
+
+    #[kernel_test]
+    fn gpt_partitions() {
+        let disk: Arc<dyn BlockDevice> = Arc::new(RamDisk::new("gpt_test", 64));
+        let mut mbr = [0; SECTOR_SIZE];
+        mbr[510..].copy_from_slice(&MBR_SIGNATURE);
+        mbr_entry(&mut mbr, 0, MBR_GPT_PROTECTIVE, 1, 63);
+        disk.write_blocks(0, &mbr).unwrap();
+
+        let mut header = [0; SECTOR_SIZE];
+        header[..8].copy_from_slice(GPT_SIGNATURE);
+        header[72..80].copy_from_slice(&2u64.to_le_bytes());
+        header[80..84].copy_from_slice(&4u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+        disk.write_blocks(1, &header).unwrap();
+
+        let mut table = [0; SECTOR_SIZE];
+        for (slot, (first, last)) in [(0, (34u64, 41u64)), (1, (34, u64::MAX)), (2, (42, 63))] {
+            let entry = &mut table[slot * 128..][..128];
+            entry[..16].fill(0xaf);
+            entry[32..40].copy_from_slice(&first.to_le_bytes());
+            entry[40..48].copy_from_slice(&last.to_le_bytes());
+        }
+        disk.write_blocks(2, &table).unwrap();
+
+        let found: Vec<_> = scan(&disk)
+            .unwrap()
+            .iter()
+            .map(|p| (p.index(), p.start(), p.num_blocks()))
+            .collect();
+        assert_eq!(found, [(1, 34, 8), (3, 42, 22)]);
+    }
+}
@@ -0,0 +1,381 @@
+use alloc::{collections::btree_map::BTreeMap, format, string::String, sync::Arc, vec, vec::Vec};
+use core::{
+    slice,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use os_macros::FileRepr;
+
+use super::{BlockDevice, BlockError, check_access};
+use crate::{
+    arch,
+    kernel::{
+        fs::FSResult,
+        io::{IOResult, Read, read_rendered},
+        threading,
+    },
+    register_device_file,
+    sync::locks::Mutex,
+};
+
+/// reads are waited for, thus they expire much earlier than writes
+pub const READ_DEADLINE_MS: u64 = 50;
+pub const WRITE_DEADLINE_MS: u64 = 500;
+/// upper bound on the blocks of merged requests, which are sent to the device at once
+pub const MAX_MERGE_BLOCKS: u64 = 256;
+
+/// a single read or write, submitted by a caller waiting for its result
+#[derive(Debug)]
+struct Request {
+    id: u64,
+    write: bool,
+    lba: u64,
+    count: u64,
+    /// the buffer of the caller, which stays borrowed until the result is set
+    buf: *mut u8,
+    len: usize,
+    /// timestamp, after which the request is served before anything else
+    deadline: u64,
+    result: Mutex<Option<Result<(), BlockError>>>,
+}
+
+// the buffer is only accessed by the dispatcher, while its owner waits for the result
+unsafe impl Send for Request {}
+unsafe impl Sync for Request {}
+
+impl Request {
+    fn end(&self) -> u64 {
+        self.lba + self.count
+    }
+
+    /// SAFETY: only the dispatcher may access the buffer, before the result is set
+    unsafe fn buf<'a>(&self) -> &'a mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.buf, self.len) }
+    }
+
+    fn complete(&self, result: Result<(), BlockError>) {
+        *self.result.lock() = Some(result);
+    }
+}
+
+#[derive(Debug, Default)]
+struct Pending {
+    /// by start block, then submission order
+    sorted: BTreeMap<(u64, u64), Arc<Request>>,
+    /// the block after the last dispatched one, the elevator continues from here
+    head: u64,
+}
+
+/// counters of a request queue
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct QueueStats {
+    pub requests: u64,
+    /// requests served by the device call of another request
+    pub merged: u64,
+    /// calls to the device
+    pub dispatched: u64,
+    /// requests served out of order, as their deadline expired
+    pub expired: u64,
+    pub read_blocks: u64,
+    pub written_blocks: u64,
+    pub depth: u64,
+    pub max_depth: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    requests: AtomicU64,
+    merged: AtomicU64,
+    dispatched: AtomicU64,
+    expired: AtomicU64,
+    read_blocks: AtomicU64,
+    written_blocks: AtomicU64,
+    max_depth: AtomicU64,
+}
+
+/// sits between the users of a disk and its driver. Requests are sorted by block and served in one direction,
+/// like an elevator, unless the oldest one waited past its deadline. Adjacent requests of the same direction are
+/// merged into a single device access.
+/// There is no dispatch thread, the first waiting submitter dispatches the queue until its own request is served
+#[derive(Debug)]
+pub struct RequestQueue {
+    device: Arc<dyn BlockDevice>,
+    pending: Mutex<Pending>,
+    dispatching: Mutex<()>,
+    next_id: AtomicU64,
+    counters: Counters,
+}
+
+impl RequestQueue {
+    pub fn new(device: Arc<dyn BlockDevice>) -> Self {
+        Self {
+            device,
+            pending: Mutex::new(Pending::default()),
+            dispatching: Mutex::new(()),
+            next_id: AtomicU64::new(0),
+            counters: Counters::default(),
+        }
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        let c = &self.counters;
+        QueueStats {
+            requests: c.requests.load(Ordering::Relaxed),
+            merged: c.merged.load(Ordering::Relaxed),
+            dispatched: c.dispatched.load(Ordering::Relaxed),
+            expired: c.expired.load(Ordering::Relaxed),
+            read_blocks: c.read_blocks.load(Ordering::Relaxed),
+            written_blocks: c.written_blocks.load(Ordering::Relaxed),
+            depth: self.pending.lock().sorted.len() as u64,
+            max_depth: c.max_depth.load(Ordering::Relaxed),
+        }
+    }
+
+    fn submit(&self, write: bool, lba: u64, buf: *mut u8, len: usize) -> Result<(), BlockError> {
+        let count = check_access(self, lba, len)?;
+        if count == 0 {
+            return Ok(());
+        }
+        let request = self.enqueue(write, lba, count, buf, len);
+        loop {
+            if let Some(result) = request.result.lock().take() {
+                return result;
+            }
+            if let Some(_dispatching) = self.dispatching.try_lock() {
+                while request.result.lock().is_none() && self.dispatch_one() {}
+                continue;
+            }
+            threading::yield_now();
+        }
+    }
+
+    fn enqueue(&self, write: bool, lba: u64, count: u64, buf: *mut u8, len: usize) -> Arc<Request> {
+        let deadline_ms = if write {
+            WRITE_DEADLINE_MS
+        } else {
+            READ_DEADLINE_MS
+        };
+        let request = Arc::new(Request {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            write,
+            lba,
+            count,
+            buf,
+            len,
+            deadline: arch::timestamp() + arch::timestamp_frequency() / 1000 * deadline_ms,
+            result: Mutex::new(None),
+        });
+        let mut pending = self.pending.lock();
+        pending.sorted.insert((lba, request.id), request.clone());
+        self.counters.requests.fetch_add(1, Ordering::Relaxed);
+        self.counters
+            .max_depth
+            .fetch_max(pending.sorted.len() as u64, Ordering::Relaxed);
+        request
+    }
+
+    /// serves the next request and everything merged into it. Returns false, if the queue was empty
+    fn dispatch_one(&self) -> bool {
+        let batch = self.next_batch(&mut self.pending.lock());
+        let Some(first) = batch.first() else {
+            return false;
+        };
+        let (write, lba) = (first.write, first.lba);
+        self.counters.dispatched.fetch_add(1, Ordering::Relaxed);
+        self.counters
+            .merged
+            .fetch_add(batch.len() as u64 - 1, Ordering::Relaxed);
+        let blocks = batch.iter().map(|request| request.count).sum();
+        if write {
+            self.counters
+                .written_blocks
+                .fetch_add(blocks, Ordering::Relaxed);
+        } else {
+            self.counters
+                .read_blocks
+                .fetch_add(blocks, Ordering::Relaxed);
+        }
+
+        // SAFETY: the owners of the buffers wait until the result is set
+        let result = if let [request] = batch.as_slice() {
+            let buf = unsafe { request.buf() };
+            if write {
+                self.device.write_blocks(lba, buf)
+            } else {
+                self.device.read_blocks(lba, buf)
+            }
+        } else {
+            let mut merged = vec![0; batch.iter().map(|request| request.len).sum()];
+            if write {
+                let mut offset = 0;
+                for request in &batch {
+                    merged[offset..offset + request.len].copy_from_slice(unsafe { request.buf() });
+                    offset += request.len;
+                }
+                self.device.write_blocks(lba, &merged)
+            } else {
+                let result = self.device.read_blocks(lba, &mut merged);
+                if result.is_ok() {
+                    let mut offset = 0;
+                    for request in &batch {
+                        unsafe { request.buf() }
+                            .copy_from_slice(&merged[offset..offset + request.len]);
+                        offset += request.len;
+                    }
+                }
+                result
+            }
+        };
+        for request in batch {
+            request.complete(result);
+        }
+        true
+    }
+
+    /// takes the next request off the queue, followed by the requests directly adjacent to it
+    fn next_batch(&self, pending: &mut Pending) -> Vec<Arc<Request>> {
+        let Some(oldest) = pending.sorted.values().min_by_key(|request| request.id) else {
+            return Vec::new();
+        };
+        let key = if arch::timestamp() >= oldest.deadline {
+            self.counters.expired.fetch_add(1, Ordering::Relaxed);
+            (oldest.lba, oldest.id)
+        } else {
+            // the elevator wraps around to the lowest block
+            *pending
+                .sorted
+                .range((pending.head, 0)..)
+                .next()
+                .or_else(|| pending.sorted.first_key_value())
+                .map(|(key, _)| key)
+                .unwrap()
+        };
+        let first = pending.sorted.remove(&key).unwrap();
+        let (write, mut end, mut blocks) = (first.write, first.end(), first.count);
+        let mut batch = vec![first];
+        while let Some((&key, request)) = pending.sorted.range((end, 0)..).next()
+            && request.lba == end
+            && request.write == write
+            && blocks + request.count <= MAX_MERGE_BLOCKS
+        {
+            end = request.end();
+            blocks += request.count;
+            batch.extend(pending.sorted.remove(&key));
+        }
+        pending.head = end;
+        batch
+    }
+
+    fn render(&self) -> String {
+        let stats = self.stats();
+        format!(
+            "requests\t{}\nmerged\t{}\ndispatched\t{}\nexpired\t{}\nread_blocks\t{}\nwritten_blocks\t{}\ndepth\t{}\nmax_depth\t{}\n",
+            stats.requests,
+            stats.merged,
+            stats.dispatched,
+            stats.expired,
+            stats.read_blocks,
+            stats.written_blocks,
+            stats.depth,
+            stats.max_depth
+        )
+    }
+}
+
+impl BlockDevice for RequestQueue {
+    fn name(&self) -> &str {
+        self.device.name()
+    }
+
+    fn block_size(&self) -> usize {
+        self.device.block_size()
+    }
+
+    fn num_blocks(&self) -> u64 {
+        self.device.num_blocks()
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.submit(false, lba, buf.as_mut_ptr(), buf.len())
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        // the dispatcher only reads from the buffers of writes
+        self.submit(true, lba, buf.as_ptr().cast_mut(), buf.len())
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        // submit only returns once the request was dispatched, so nothing of the caller is pending anymore
+        self.device.flush()
+    }
+}
+
+/// /proc/block/<disk>/queue: the counters of the request queue of a disk
+#[derive(Debug, FileRepr)]
+#[file(read)]
+pub struct QueueStatFile {
+    queue: Arc<RequestQueue>,
+}
+
+impl Read for QueueStatFile {
+    fn read(&self, buf: &mut [u8], offset: usize) -> IOResult<usize> {
+        read_rendered(self.queue.render(), buf, offset)
+    }
+}
+
+pub fn register_stats(queue: &Arc<RequestQueue>) -> FSResult<()> {
+    let path = format!("/block/{}/queue", queue.name());
+    register_device_file!(
+        Arc::new(QueueStatFile {
+            queue: queue.clone()
+        }),
+        path.as_str()
+    )
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::{super::ramdisk::RamDisk, *};
+    use crate::drivers::block::SECTOR_SIZE;
+
+    #[kernel_test]
+    fn merged_and_sorted() {
+        let disk = Arc::new(RamDisk::new("queue_test", 16));
+        let queue = RequestQueue::new(disk.clone());
+
+        let data = vec![0x5a; 2 * SECTOR_SIZE];
+        queue.write_blocks(4, &data).unwrap();
+        let mut buf = vec![0; 4 * SECTOR_SIZE];
+        queue.read_blocks(3, &mut buf).unwrap();
+        assert!(buf[..SECTOR_SIZE].iter().all(|b| *b == 0));
+        assert!(buf[SECTOR_SIZE..3 * SECTOR_SIZE].iter().all(|b| *b == 0x5a));
+        assert_eq!(queue.stats().dispatched, 2);
+
+        // queued out of order, as if by concurrent callers. The two adjacent reads are served at once
+        let mut bufs = vec![vec![0u8; SECTOR_SIZE]; 3];
+        let [a, b, c] = bufs.as_mut_slice() else {
+            unreachable!()
+        };
+        let late = queue.enqueue(false, 5, 1, b.as_mut_ptr(), b.len());
+        let far = queue.enqueue(false, 10, 1, c.as_mut_ptr(), c.len());
+        let early = queue.enqueue(false, 4, 1, a.as_mut_ptr(), a.len());
+        assert_eq!(queue.stats().depth, 3);
+        queue.pending.lock().head = 0;
+        assert!(queue.dispatch_one());
+        assert_eq!(*early.result.lock(), Some(Ok(())));
+        assert_eq!(*late.result.lock(), Some(Ok(())));
+        assert!(far.result.lock().is_none());
+        assert!(queue.dispatch_one());
+        assert!(!queue.dispatch_one());
+        assert!(bufs[0].iter().chain(&bufs[1]).all(|b| *b == 0x5a));
+        assert!(bufs[2].iter().all(|b| *b == 0));
+
+        let stats = queue.stats();
+        assert_eq!((stats.requests, stats.merged, stats.dispatched), (5, 1, 4));
+        assert_eq!((stats.read_blocks, stats.written_blocks), (7, 2));
+        assert_eq!((stats.depth, stats.max_depth), (0, 3));
+        assert_eq!(queue.read_blocks(15, &mut buf), Err(BlockError::OutOfRange));
+    }
+}
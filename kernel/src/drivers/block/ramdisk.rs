@@ -0,0 +1,60 @@
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+
+use os_macros::init_task;
+
+use super::{BlockDevice, BlockError, SECTOR_SIZE, check_access, register};
+use crate::{bootinfo, eprintln, sync::locks::Mutex};
+
+/// ramdisk=<blocks> on the command line registers an empty ram disk of that many blocks as ram0
+pub const RAMDISK_OPTION: &str = "ramdisk";
+
+/// a block device in memory. Its contents are lost on reboot
+#[derive(Debug)]
+pub struct RamDisk {
+    name: String,
+    data: Mutex<Vec<u8>>,
+}
+
+impl RamDisk {
+    pub fn new(name: &str, blocks: usize) -> Self {
+        Self {
+            name: name.into(),
+            data: Mutex::new(vec![0; blocks * SECTOR_SIZE]),
+        }
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn num_blocks(&self) -> u64 {
+        (self.data.lock().len() / SECTOR_SIZE) as u64
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        check_access(self, lba, buf.len())?;
+        let start = lba as usize * SECTOR_SIZE;
+        buf.copy_from_slice(&self.data.lock()[start..start + buf.len()]);
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        check_access(self, lba, buf.len())?;
+        let start = lba as usize * SECTOR_SIZE;
+        self.data.lock()[start..start + buf.len()].copy_from_slice(buf);
+        Ok(())
+    }
+}
+
+#[init_task(stage = "drivers", order = 50)]
+fn register_ramdisk() {
+    let Some(blocks) = bootinfo::cmdline_option(RAMDISK_OPTION) else {
+        return;
+    };
+    match blocks.parse::<usize>() {
+        Ok(blocks) if blocks > 0 => register(Arc::new(RamDisk::new("ram0", blocks))),
+        _ => eprintln!("{}={} is not a number of blocks", RAMDISK_OPTION, blocks),
+    }
+}
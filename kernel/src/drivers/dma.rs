@@ -0,0 +1,95 @@
+use core::cell::UnsafeCell;
+
+use crate::{
+    arch::mem::{
+        FrameAllocator,
+        FrameDeallocator,
+        PageSize,
+        PhysFrame,
+        Size4KiB,
+        Translate,
+        VirtAddr,
+    },
+    bootinfo,
+    kernel::mem::paging::{PAGETABLE, get_frame_alloc},
+};
+
+/// memory shared with a device. It lives in the kernel image, which is loaded physically contiguous,
+/// as the frame allocator cannot hand out more than a single frame at once
+#[repr(C, align(4096))]
+pub struct DmaBuffer<const N: usize>(UnsafeCell<[u8; N]>);
+
+// SAFETY: the buffer is only accessed through raw pointers by its single owning driver
+unsafe impl<const N: usize> Sync for DmaBuffer<N> {}
+
+impl<const N: usize> DmaBuffer<N> {
+    pub const fn new() -> Self {
+        Self(UnsafeCell::new([0; N]))
+    }
+
+    pub const fn len(&self) -> usize {
+        N
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        N == 0
+    }
+
+    pub fn as_mut_ptr(&self) -> *mut u8 {
+        self.0.get().cast()
+    }
+
+    /// the physical address of the buffer. None, if its pages are not physically contiguous
+    pub fn phys_addr(&self) -> Option<u64> {
+        let start = VirtAddr::from_ptr(self.as_mut_ptr());
+        let table = PAGETABLE.lock();
+        let phys = table.translate_addr(start)?;
+        (Size4KiB::SIZE..N as u64)
+            .step_by(Size4KiB::SIZE as usize)
+            .all(|page| table.translate_addr(start + page) == Some(phys + page))
+            .then_some(phys.as_u64())
+    }
+}
+
+impl<const N: usize> Default for DmaBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// a single zeroed page shared with a device. Unlike a DmaBuffer, it is taken from the frame allocator and returned on drop,
+/// which suffices for structures, which never cross a page
+#[derive(Debug)]
+pub struct DmaPage {
+    frame: PhysFrame<Size4KiB>,
+}
+
+impl DmaPage {
+    pub fn new() -> Option<Self> {
+        let frame = get_frame_alloc().lock().allocate_frame()?;
+        Some(Self { frame })
+    }
+
+    pub const fn len(&self) -> usize {
+        Size4KiB::SIZE as usize
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        false
+    }
+
+    pub fn phys_addr(&self) -> u64 {
+        self.frame.start_address().as_u64()
+    }
+
+    /// the page through the direct map of physical memory
+    pub fn as_mut_ptr(&self) -> *mut u8 {
+        (self.phys_addr() + bootinfo::get_phys_offset()) as *mut u8
+    }
+}
+
+impl Drop for DmaPage {
+    fn drop(&mut self) {
+        unsafe { get_frame_alloc().lock().deallocate_frame(self.frame) };
+    }
+}
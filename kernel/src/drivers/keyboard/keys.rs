@@ -1,13 +1,41 @@
-use pc_keyboard::{EventDecoder, HandleControl, Keyboard, ScancodeSet1, layouts};
+use pc_keyboard::{EventDecoder, HandleControl, Keyboard, Modifiers, ScancodeSet1, layouts};
 
 use super::KeyboardError;
 use crate::sync::locks::Mutex;
 
-pub static KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> = Mutex::new(Keyboard::new(
-    ScancodeSet1::new(),
-    layouts::Us104Key,
-    HandleControl::MapLettersToUnicode,
-));
+pub type Decoder = Keyboard<layouts::Us104Key, ScancodeSet1>;
+
+pub static KEYBOARD: Mutex<Decoder> = Mutex::new(new_decoder());
+
+/// a decoder with its own modifier state, for consumers which see every scancode
+pub const fn new_decoder() -> Decoder {
+    Keyboard::new(
+        ScancodeSet1::new(),
+        layouts::Us104Key,
+        HandleControl::MapLettersToUnicode,
+    )
+}
+
+/// the modifiers terminal shortcuts are made of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct HeldModifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+}
+
+impl From<&Modifiers> for HeldModifiers {
+    fn from(value: &Modifiers) -> Self {
+        Self {
+            shift: value.lshift || value.rshift,
+            ctrl: value.lctrl || value.rctrl,
+        }
+    }
+}
+
+/// the modifiers held according to the shared decoder
+pub fn held_modifiers() -> HeldModifiers {
+    KEYBOARD.lock().get_modifiers().into()
+}
 
 pub fn parse_scancode(scancode: u8) -> Result<pc_keyboard::DecodedKey, KeyboardError> {
     let mut keyboard = KEYBOARD.lock();
@@ -4,8 +4,8 @@ use thiserror::Error;
 
 mod keys;
 mod queue;
-pub use keys::parse_scancode;
-pub use queue::{KEYBOARD_BUFFER, STDIN_QUEUE_SIZE, put_scancode};
+pub use keys::{Decoder, HeldModifiers, held_modifiers, new_decoder, parse_scancode};
+pub use queue::{KEYBOARD_BUFFER, STDIN_QUEUE_SIZE, put_scancode, put_scancodes};
 
 #[derive(Error, Debug)]
 pub enum KeyboardError {
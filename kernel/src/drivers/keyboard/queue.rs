@@ -8,6 +8,7 @@ use crossbeam::queue::ArrayQueue;
 use lazy_static::lazy_static;
 
 use super::KeyboardError;
+use crate::kernel::threading::wait::{QueueType, WaitEvent, post_event};
 
 pub const STDIN_QUEUE_SIZE: usize = 50;
 
@@ -57,8 +58,13 @@ impl KeyboardBuffer {
         n
     }
 
+    /// number of scancodes put since boot, ie the cursor of the next one
+    pub fn written(&self) -> usize {
+        self.count.load(Ordering::Acquire)
+    }
+
     pub fn get_current(&self) -> usize {
-        self.count.load(Ordering::Acquire) - 1
+        self.count.load(Ordering::Acquire).saturating_sub(1)
     }
 
     pub fn cursor_is_valid(&self, cursor: usize) -> bool {
@@ -83,6 +89,14 @@ pub fn put_scancode(code: u8) {
     KEYBOARD_BUFFER.put(code)
 }
 
+/// queues the scancodes of a keyboard other than the ps/2 one, e.g. a usb keyboard, and wakes the readers
+pub fn put_scancodes(codes: &[u8]) {
+    for code in codes {
+        KEYBOARD_BUFFER.put(*code);
+    }
+    _ = post_event(WaitEvent::new(QueueType::KeyBoard));
+}
+
 unsafe impl Sync for KeyboardBuffer {}
 unsafe impl Send for KeyboardBuffer {}
 
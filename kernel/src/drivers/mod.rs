@@ -1,14 +1,46 @@
-use tty::start_tty_backend;
+use model::Driver;
 
-use crate::drivers::{resource::start_resource_manager, wait_manager::start_wait_managment};
+use crate::kernel::init::{InitStage, run_stage};
 
+#[cfg(feature = "disk")]
+pub mod block;
+pub mod dma;
 pub mod keyboard;
+pub mod model;
+pub mod mouse;
+pub mod pci;
 pub mod resource;
+#[cfg(feature = "sound")]
+pub mod sound;
 pub mod tty;
+#[cfg(feature = "usb")]
+pub mod usb;
+#[cfg(feature = "virtio")]
+pub mod virtio;
 pub mod wait_manager;
+#[cfg(feature = "watchdog")]
+pub mod watchdog;
 
+/// starts all background driver tasks, registered through #[init_task(stage = "drivers")]
 pub fn start_drivers() {
-    start_tty_backend();
-    start_wait_managment();
-    start_resource_manager();
+    run_stage(InitStage::Drivers);
+}
+
+/// the drivers built into the kernel, as selected by the enabled features.
+/// They probe in the order of their dependencies, not in this one
+static BUILTIN_DRIVERS: &[&dyn Driver] = &[
+    #[cfg(feature = "disk")]
+    &block::ata::DRIVER,
+    #[cfg(feature = "virtio")]
+    &virtio::rng::DRIVER,
+    #[cfg(feature = "sound")]
+    &sound::ac97::DRIVER,
+    #[cfg(feature = "sound")]
+    &sound::speaker::DRIVER,
+    #[cfg(feature = "usb")]
+    &usb::xhci::DRIVER,
+];
+
+pub fn builtin_drivers() -> &'static [&'static dyn Driver] {
+    BUILTIN_DRIVERS
 }
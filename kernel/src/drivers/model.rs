@@ -0,0 +1,812 @@
+use alloc::{
+    collections::btree_set::BTreeSet,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::fmt::{Debug, Display, Write as _};
+
+use os_macros::{FileRepr, init_task};
+use thiserror::Error;
+use tinyos_abi::flags::NodeType;
+
+use super::pci::{self, PciDevice};
+use crate::{
+    eprintln,
+    impl_empty_read,
+    impl_file_for_wr,
+    kernel::{
+        fd::FileRepr,
+        fs::{
+            FSResult,
+            Path,
+            procfs::{self, DeviceHandle},
+        },
+        io::{IOResult, Read, Write, read_rendered},
+    },
+    register_device_file,
+    serial_println,
+    sync::locks::RwLock,
+};
+
+// devices are enumerated by their bus and bound to the first driver, whose match table fits and whose probe succeeds.
+// Drivers probe in the order of their dependencies: a driver only probes, once all drivers it depends on are done,
+// which is the case, when none of their devices asked to be retried later.
+// Devices may vanish, when a rescan does not find them anymore. They are unbound and their files in /proc are detached,
+// such that files, which are still open on them, fail instead of reaching a device, which is gone
+
+/// directory below /proc, which holds a directory per bus and a directory per device in it
+pub const DEVICES_DIR: &str = "/devices";
+/// writing anything to it rescans all buses
+pub const RESCAN_FILE: &str = "/devices/rescan";
+
+static REGISTRY: Registry = Registry::new(true);
+static PLATFORM_BUS: PlatformBus = PlatformBus;
+static RESCAN: Rescan = Rescan;
+// the devices every pc has, which cannot be enumerated
+const PLATFORM_DEVICES: &[&str] = &["pcspkr", "ide0", "ide1"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BusKind {
+    Pci,
+    Platform,
+}
+
+impl Display for BusKind {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            Self::Pci => "pci",
+            Self::Platform => "platform",
+        })
+    }
+}
+
+/// what a device identifies itself as. Drivers match against it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceId {
+    Pci {
+        vendor: u16,
+        device: u16,
+        class: u8,
+        subclass: u8,
+    },
+    Platform(&'static str),
+}
+
+impl Display for DeviceId {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Pci {
+                vendor,
+                device,
+                class,
+                subclass,
+            } => write!(
+                f,
+                "{:04x}:{:04x} class {:02x}.{:02x}",
+                vendor, device, class, subclass
+            ),
+            Self::Platform(name) => f.write_str(name),
+        }
+    }
+}
+
+/// an entry in the match table of a driver
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Match {
+    Pci { vendor: u16, device: u16 },
+    PciClass { class: u8, subclass: u8 },
+    Platform(&'static str),
+}
+
+impl Match {
+    pub fn matches(&self, id: &DeviceId) -> bool {
+        match (*self, *id) {
+            (
+                Self::Pci { vendor, device },
+                DeviceId::Pci {
+                    vendor: v,
+                    device: d,
+                    ..
+                },
+            ) => vendor == v && device == d,
+            (
+                Self::PciClass { class, subclass },
+                DeviceId::Pci {
+                    class: c,
+                    subclass: s,
+                    ..
+                },
+            ) => class == c && subclass == s,
+            (Self::Platform(name), DeviceId::Platform(other)) => name == other,
+            _ => false,
+        }
+    }
+}
+
+pub trait Device: Debug + Send + Sync {
+    /// unique on its bus
+    fn name(&self) -> String;
+
+    fn bus(&self) -> BusKind;
+
+    fn id(&self) -> DeviceId;
+
+    /// the pci function behind the device, if it sits on the pci bus
+    fn pci(&self) -> Option<&PciDevice> {
+        None
+    }
+}
+
+pub trait Bus: Debug + Send + Sync {
+    fn kind(&self) -> BusKind;
+
+    /// enumerates the devices currently present
+    fn scan(&self) -> Vec<Arc<dyn Device>>;
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum DriverError {
+    /// the driver does not handle this device after all, the next matching driver is tried
+    #[error("no usable device: {0}")]
+    NoDevice(&'static str),
+    /// something the driver needs is not available yet, the probe is retried after other drivers probed
+    #[error("probe deferred")]
+    Defer,
+    #[error("{0}")]
+    Failed(String),
+}
+
+impl DriverError {
+    pub fn failed(e: impl Display) -> Self {
+        Self::Failed(e.to_string())
+    }
+}
+
+pub trait Driver: Debug + Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn match_table(&self) -> &'static [Match];
+
+    /// drivers, which must be done probing, before this one probes
+    fn depends_on(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// takes over device, which matched the match table
+    fn probe(&self, device: &Arc<dyn Device>) -> Result<(), DriverError>;
+
+    /// releases device, after which it is unbound
+    fn remove(&self, _device: &Arc<dyn Device>) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindState {
+    /// no driver took the device (yet)
+    Unbound,
+    Bound(&'static str),
+    /// the probe of the driver was deferred and not retried successfully
+    Deferred(&'static str),
+    /// the probe of the driver failed
+    Failed(&'static str),
+}
+
+impl Display for BindState {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Unbound => f.write_str("unbound"),
+            Self::Bound(driver) => write!(f, "bound {}", driver),
+            Self::Deferred(driver) => write!(f, "deferred {}", driver),
+            Self::Failed(driver) => write!(f, "failed {}", driver),
+        }
+    }
+}
+
+/// a device known to the driver model and its binding
+#[derive(Debug)]
+pub struct DeviceNode {
+    device: Arc<dyn Device>,
+    state: RwLock<BindState>,
+    // the files in /proc, which refer to the device
+    handles: RwLock<Vec<Arc<DeviceHandle>>>,
+}
+
+impl DeviceNode {
+    pub fn device(&self) -> &Arc<dyn Device> {
+        &self.device
+    }
+
+    pub fn state(&self) -> BindState {
+        *self.state.read()
+    }
+
+    /// path below /proc
+    pub fn path(&self) -> String {
+        format!(
+            "{}/{}/{}",
+            DEVICES_DIR,
+            self.device.bus(),
+            self.device.name()
+        )
+    }
+
+    fn is(&self, bus: BusKind, name: &str) -> bool {
+        self.device.bus() == bus && self.device.name() == name
+    }
+}
+
+#[derive(Debug)]
+pub struct Registry {
+    buses: RwLock<Vec<&'static dyn Bus>>,
+    drivers: RwLock<Vec<&'static dyn Driver>>,
+    devices: RwLock<Vec<Arc<DeviceNode>>>,
+    // whether devices are exposed in /proc, only the global registry is
+    expose: bool,
+}
+
+impl Registry {
+    pub const fn new(expose: bool) -> Self {
+        Self {
+            buses: RwLock::new(Vec::new()),
+            drivers: RwLock::new(Vec::new()),
+            devices: RwLock::new(Vec::new()),
+            expose,
+        }
+    }
+
+    pub fn register_bus(&self, bus: &'static dyn Bus) {
+        self.buses.write().push(bus);
+    }
+
+    pub fn register_driver(&self, driver: &'static dyn Driver) {
+        self.drivers.write().push(driver);
+    }
+
+    pub fn drivers(&self) -> Vec<&'static dyn Driver> {
+        self.drivers.read().clone()
+    }
+
+    pub fn devices(&self) -> Vec<Arc<DeviceNode>> {
+        self.devices.read().clone()
+    }
+
+    pub fn find(&self, bus: BusKind, name: &str) -> Option<Arc<DeviceNode>> {
+        self.devices
+            .read()
+            .iter()
+            .find(|node| node.is(bus, name))
+            .cloned()
+    }
+
+    /// adds device, unless a device of the same name is known on its bus. It is not probed
+    pub fn add(&self, device: Arc<dyn Device>) -> Option<Arc<DeviceNode>> {
+        let mut devices = self.devices.write();
+        if devices
+            .iter()
+            .any(|node| node.is(device.bus(), &device.name()))
+        {
+            return None;
+        }
+        let node = Arc::new(DeviceNode {
+            device,
+            state: RwLock::new(BindState::Unbound),
+            handles: RwLock::new(Vec::new()),
+        });
+        devices.push(node.clone());
+        drop(devices);
+        if self.expose
+            && let Err(e) = expose(&node)
+        {
+            eprintln!("could not expose device {}: {}", node.path(), e);
+        }
+        Some(node)
+    }
+
+    /// adds the devices found on all buses, which are not known yet
+    pub fn scan(&self) {
+        let buses = self.buses.read().clone();
+        for bus in buses {
+            for device in bus.scan() {
+                self.add(device);
+            }
+        }
+    }
+
+    /// scans all buses again. Devices, which are gone or were replaced by another device, are removed,
+    /// new devices are probed. Returns how many devices were added and removed
+    pub fn rescan(&self) -> (usize, usize) {
+        let (mut added, mut removed) = (0, 0);
+        let buses = self.buses.read().clone();
+        for bus in buses {
+            let present = bus.scan();
+            let gone: Vec<String> = self
+                .devices()
+                .iter()
+                .filter(|node| {
+                    node.device.bus() == bus.kind()
+                        && !present.iter().any(|device| {
+                            node.is(device.bus(), &device.name()) && node.device.id() == device.id()
+                        })
+                })
+                .map(|node| node.device.name())
+                .collect();
+            for name in gone {
+                removed += self.remove(bus.kind(), &name).is_some() as usize;
+            }
+            for device in present {
+                added += self.add(device).is_some() as usize;
+            }
+        }
+        self.probe_all();
+        (added, removed)
+    }
+
+    /// probes all unbound devices in the order of the driver dependencies.
+    /// Drivers with unmet dependencies do not probe at all
+    pub fn probe_all(&self) {
+        let mut pending = self.drivers();
+        let mut done = BTreeSet::new();
+        loop {
+            let mut progress = false;
+            pending.retain(|driver| {
+                if !driver.depends_on().iter().all(|dep| done.contains(dep)) {
+                    return true;
+                }
+                let (bound, deferred) = self.probe_driver(*driver);
+                progress |= bound > 0 || deferred == 0;
+                if deferred == 0 {
+                    done.insert(driver.name());
+                }
+                deferred > 0
+            });
+            if pending.is_empty() || !progress {
+                break;
+            }
+        }
+        for driver in pending {
+            eprintln!(
+                "driver {} did not finish probing, it depends on {:?}",
+                driver.name(),
+                driver.depends_on()
+            );
+        }
+    }
+
+    /// probes the matching unbound devices with driver. Returns how many were bound and deferred
+    fn probe_driver(&self, driver: &'static dyn Driver) -> (usize, usize) {
+        let (mut bound, mut deferred) = (0, 0);
+        for node in self.devices() {
+            let state = node.state();
+            if matches!(state, BindState::Bound(_))
+                || state == BindState::Failed(driver.name())
+                || !driver
+                    .match_table()
+                    .iter()
+                    .any(|entry| entry.matches(&node.device.id()))
+            {
+                continue;
+            }
+            // the lock is not held during the probe, as drivers may register further devices
+            let state = match driver.probe(&node.device) {
+                Ok(()) => {
+                    bound += 1;
+                    BindState::Bound(driver.name())
+                }
+                Err(DriverError::NoDevice(_)) => BindState::Unbound,
+                Err(DriverError::Defer) => {
+                    deferred += 1;
+                    BindState::Deferred(driver.name())
+                }
+                Err(e) => {
+                    eprintln!("{} failed to probe {}: {}", driver.name(), node.path(), e);
+                    BindState::Failed(driver.name())
+                }
+            };
+            *node.state.write() = state;
+        }
+        (bound, deferred)
+    }
+
+    /// unbinds the device from its driver and forgets it. Its files in /proc are removed
+    pub fn remove(&self, bus: BusKind, name: &str) -> Option<Arc<DeviceNode>> {
+        let node = {
+            let mut devices = self.devices.write();
+            let idx = devices.iter().position(|node| node.is(bus, name))?;
+            devices.remove(idx)
+        };
+        let state = core::mem::replace(&mut *node.state.write(), BindState::Unbound);
+        if let BindState::Bound(name) = state
+            && let Some(driver) = self
+                .drivers
+                .read()
+                .iter()
+                .find(|driver| driver.name() == name)
+        {
+            driver.remove(&node.device);
+        }
+        if self.expose {
+            for handle in node.handles.write().drain(..) {
+                handle.detach();
+            }
+            procfs::registry().deregister_dir(Path::new(&node.path()));
+        }
+        Some(node)
+    }
+
+    /// unbinds and forgets all devices, the newest first, as devices found through another one are added after it
+    pub fn remove_all(&self) {
+        for node in self.devices().iter().rev() {
+            self.remove(node.device.bus(), &node.device.name());
+        }
+    }
+}
+
+/// the global driver model, whose devices appear in /proc/devices
+pub fn registry() -> &'static Registry {
+    &REGISTRY
+}
+
+/// adds a device, which appeared after boot, and binds it to a driver
+pub fn add_device(device: Arc<dyn Device>) -> Option<Arc<DeviceNode>> {
+    let node = REGISTRY.add(device)?;
+    REGISTRY.probe_all();
+    Some(node)
+}
+
+/// scans all buses again, see Registry::rescan
+pub fn rescan() -> (usize, usize) {
+    REGISTRY.rescan()
+}
+
+fn expose(node: &Arc<DeviceNode>) -> FSResult<()> {
+    for attr in [DeviceAttr::Id, DeviceAttr::Driver] {
+        let path = format!("{}/{}", node.path(), attr.name());
+        // the file only holds the node through the handle, which is detached on removal
+        let handle = DeviceHandle::new(Arc::new(DeviceFile {
+            node: node.clone(),
+            attr,
+        }) as Arc<dyn FileRepr>);
+        register_device_file!(handle.clone(), path.as_str())?;
+        node.handles.write().push(handle);
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DeviceAttr {
+    Id,
+    Driver,
+}
+
+impl DeviceAttr {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Id => "id",
+            Self::Driver => "driver",
+        }
+    }
+}
+
+/// an attribute of a device in /proc/devices/<bus>/<device>
+#[derive(Debug, FileRepr)]
+#[file(read)]
+struct DeviceFile {
+    node: Arc<DeviceNode>,
+    attr: DeviceAttr,
+}
+
+impl Read for DeviceFile {
+    fn read(&self, buf: &mut [u8], offset: usize) -> IOResult<usize> {
+        let mut rendered = String::new();
+        _ = match self.attr {
+            DeviceAttr::Id => writeln!(rendered, "{}", self.node.device.id()),
+            DeviceAttr::Driver => writeln!(rendered, "{}", self.node.state()),
+        };
+        read_rendered(rendered, buf, offset)
+    }
+}
+
+/// /proc/devices/rescan: writing anything rescans all buses, e.g. after a device was unplugged
+#[derive(Debug, Default, Clone, Copy)]
+struct Rescan;
+
+impl Write for Rescan {
+    fn write(&self, buf: &[u8], _offset: usize) -> IOResult<usize> {
+        let (added, removed) = rescan();
+        serial_println!("rescan: {} devices added, {} removed", added, removed);
+        Ok(buf.len())
+    }
+}
+
+impl_empty_read!(Rescan);
+impl_file_for_wr!(Rescan: NodeType::FILE);
+
+/// a function on the pci bus
+#[derive(Debug)]
+pub struct PciFunction(PciDevice);
+
+impl Device for PciFunction {
+    fn name(&self) -> String {
+        self.0.addr.to_string()
+    }
+
+    fn bus(&self) -> BusKind {
+        BusKind::Pci
+    }
+
+    fn id(&self) -> DeviceId {
+        DeviceId::Pci {
+            vendor: self.0.vendor,
+            device: self.0.device,
+            class: self.0.class,
+            subclass: self.0.subclass,
+        }
+    }
+
+    fn pci(&self) -> Option<&PciDevice> {
+        Some(&self.0)
+    }
+}
+
+#[derive(Debug)]
+pub struct PciBus;
+
+impl Bus for PciBus {
+    fn kind(&self) -> BusKind {
+        BusKind::Pci
+    }
+
+    fn scan(&self) -> Vec<Arc<dyn Device>> {
+        pci::scan()
+            .into_iter()
+            .map(|device| Arc::new(PciFunction(device)) as Arc<dyn Device>)
+            .collect()
+    }
+}
+
+/// a device at a fixed, well known location, which cannot be enumerated
+#[derive(Debug)]
+pub struct PlatformDevice(&'static str);
+
+impl Device for PlatformDevice {
+    fn name(&self) -> String {
+        self.0.into()
+    }
+
+    fn bus(&self) -> BusKind {
+        BusKind::Platform
+    }
+
+    fn id(&self) -> DeviceId {
+        DeviceId::Platform(self.0)
+    }
+}
+
+#[derive(Debug)]
+pub struct PlatformBus;
+
+impl Bus for PlatformBus {
+    fn kind(&self) -> BusKind {
+        BusKind::Platform
+    }
+
+    fn scan(&self) -> Vec<Arc<dyn Device>> {
+        PLATFORM_DEVICES
+            .iter()
+            .map(|name| Arc::new(PlatformDevice(name)) as Arc<dyn Device>)
+            .collect()
+    }
+}
+
+static PCI_BUS: PciBus = PciBus;
+
+/// enumerates all buses and binds the builtin drivers
+#[init_task(stage = "fs", order = 12)]
+fn probe_devices() {
+    REGISTRY.register_bus(&PCI_BUS);
+    REGISTRY.register_bus(&PLATFORM_BUS);
+    for driver in super::builtin_drivers() {
+        REGISTRY.register_driver(*driver);
+    }
+    if let Err(e) = register_device_file!(&RESCAN, RESCAN_FILE) {
+        eprintln!("could not register {}: {}", RESCAN_FILE, e);
+    }
+    REGISTRY.scan();
+    REGISTRY.probe_all();
+    for node in REGISTRY.devices() {
+        serial_println!("{}: {}", node.path(), node.state());
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    static PROBES: RwLock<Vec<&'static str>> = RwLock::new(Vec::new());
+
+    #[derive(Debug)]
+    struct TestDriver {
+        name: &'static str,
+        matches: &'static [Match],
+        depends_on: &'static [&'static str],
+        // the probe is deferred, until this driver has probed
+        waits_for: Option<&'static str>,
+        removed: AtomicUsize,
+    }
+
+    impl TestDriver {
+        const fn new(
+            name: &'static str,
+            matches: &'static [Match],
+            depends_on: &'static [&'static str],
+            waits_for: Option<&'static str>,
+        ) -> Self {
+            Self {
+                name,
+                matches,
+                depends_on,
+                waits_for,
+                removed: AtomicUsize::new(0),
+            }
+        }
+    }
+
+    impl Driver for TestDriver {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn match_table(&self) -> &'static [Match] {
+            self.matches
+        }
+
+        fn depends_on(&self) -> &'static [&'static str] {
+            self.depends_on
+        }
+
+        fn probe(&self, _device: &Arc<dyn Device>) -> Result<(), DriverError> {
+            let mut probes = PROBES.write();
+            probes.push(self.name);
+            if let Some(other) = self.waits_for
+                && !probes.contains(&other)
+            {
+                return Err(DriverError::Defer);
+            }
+            Ok(())
+        }
+
+        fn remove(&self, _device: &Arc<dyn Device>) {
+            self.removed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[derive(Debug)]
+    struct TestBus;
+
+    impl Bus for TestBus {
+        fn kind(&self) -> BusKind {
+            BusKind::Platform
+        }
+
+        fn scan(&self) -> Vec<Arc<dyn Device>> {
+            ["test-a", "test-b", "test-c", "test-d"]
+                .into_iter()
+                .map(|name| Arc::new(PlatformDevice(name)) as Arc<dyn Device>)
+                .collect()
+        }
+    }
+
+    static PLUGGED: AtomicBool = AtomicBool::new(true);
+
+    #[derive(Debug)]
+    struct HotplugBus;
+
+    impl Bus for HotplugBus {
+        fn kind(&self) -> BusKind {
+            BusKind::Platform
+        }
+
+        fn scan(&self) -> Vec<Arc<dyn Device>> {
+            if PLUGGED.load(Ordering::Relaxed) {
+                alloc::vec![Arc::new(PlatformDevice("test-e")) as Arc<dyn Device>]
+            } else {
+                Vec::new()
+            }
+        }
+    }
+
+    static BUS: TestBus = TestBus;
+    static HOTPLUG_BUS: HotplugBus = HotplugBus;
+    static A: TestDriver = TestDriver::new("a", &[Match::Platform("test-a")], &["b"], None);
+    static B: TestDriver = TestDriver::new("b", &[Match::Platform("test-b")], &[], None);
+    // depends on a driver, which does not exist
+    static C: TestDriver = TestDriver::new("c", &[Match::Platform("test-c")], &["x"], None);
+    // defers, until b probed
+    static D: TestDriver = TestDriver::new("d", &[Match::Platform("test-d")], &[], Some("b"));
+    static E: TestDriver = TestDriver::new("e", &[Match::Platform("test-e")], &[], None);
+
+    #[kernel_test]
+    fn ordered_probe() {
+        let registry = Registry::new(false);
+        registry.register_bus(&BUS);
+        for driver in [&A, &D, &B, &C] {
+            registry.register_driver(driver);
+        }
+        PROBES.write().clear();
+        registry.scan();
+        // scanning again does not duplicate devices
+        registry.scan();
+        assert_eq!(registry.devices().len(), 4);
+
+        registry.probe_all();
+        assert_eq!(*PROBES.read(), ["d", "b", "a", "d"]);
+        let state = |name| registry.find(BusKind::Platform, name).unwrap().state();
+        assert_eq!(state("test-a"), BindState::Bound("a"));
+        assert_eq!(state("test-b"), BindState::Bound("b"));
+        assert_eq!(state("test-c"), BindState::Unbound);
+        assert_eq!(state("test-d"), BindState::Bound("d"));
+
+        assert!(registry.remove(BusKind::Platform, "test-a").is_some());
+        assert_eq!(A.removed.load(Ordering::Relaxed), 1);
+        assert!(registry.find(BusKind::Platform, "test-a").is_none());
+        assert!(registry.remove(BusKind::Platform, "test-a").is_none());
+
+        registry.remove_all();
+        assert!(registry.devices().is_empty());
+        assert_eq!(B.removed.load(Ordering::Relaxed), 1);
+        assert_eq!(D.removed.load(Ordering::Relaxed), 1);
+        // test-c was never bound
+        assert_eq!(C.removed.load(Ordering::Relaxed), 0);
+
+        assert!(
+            Match::Pci {
+                vendor: 1,
+                device: 2
+            }
+            .matches(&DeviceId::Pci {
+                vendor: 1,
+                device: 2,
+                class: 3,
+                subclass: 4
+            })
+        );
+        assert!(
+            !Match::PciClass {
+                class: 3,
+                subclass: 5
+            }
+            .matches(&DeviceId::Platform("test-a"))
+        );
+    }
+
+    #[kernel_test]
+    fn hot_unplug() {
+        let registry = Registry::new(false);
+        registry.register_bus(&HOTPLUG_BUS);
+        registry.register_driver(&E);
+        let state = || {
+            registry
+                .find(BusKind::Platform, "test-e")
+                .map(|node| node.state())
+        };
+
+        assert_eq!(registry.rescan(), (1, 0));
+        assert_eq!(state(), Some(BindState::Bound("e")));
+        assert_eq!(registry.rescan(), (0, 0));
+
+        PLUGGED.store(false, Ordering::Relaxed);
+        assert_eq!(registry.rescan(), (0, 1));
+        assert_eq!(E.removed.load(Ordering::Relaxed), 1);
+        assert_eq!(state(), None);
+
+        // plugging it back in binds it again
+        PLUGGED.store(true, Ordering::Relaxed);
+        assert_eq!(registry.rescan(), (1, 0));
+        assert_eq!(state(), Some(BindState::Bound("e")));
+    }
+}
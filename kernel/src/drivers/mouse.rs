@@ -0,0 +1,83 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use conquer_once::spin::OnceCell;
+use tinyos_abi::flags::NodeType;
+
+use crate::{
+    create_device_file,
+    data_structures::channel::{self, Receiver, Sender, TrySendError},
+    eprintln,
+    impl_empty_write,
+    impl_file_for_wr,
+    kernel::io::{IOResult, Read},
+};
+
+pub const MOUSE_FILE: &str = "/dev/mouse";
+/// the bytes of a single event in /dev/mouse
+pub const PACKET_SIZE: usize = 4;
+// the oldest events are dropped beyond this, nobody reads them anymore
+const QUEUE_SIZE: usize = 256;
+
+static EVENTS: OnceCell<(Sender<MouseEvent>, Receiver<MouseEvent>)> = OnceCell::uninit();
+static MOUSE: Mouse = Mouse;
+static REGISTERED: AtomicBool = AtomicBool::new(false);
+
+/// relative movement and the held buttons, bit 0 is the left, bit 1 the right and bit 2 the middle button
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    pub buttons: u8,
+    pub dx: i8,
+    pub dy: i8,
+    pub wheel: i8,
+}
+
+impl MouseEvent {
+    pub fn to_bytes(&self) -> [u8; PACKET_SIZE] {
+        [self.buttons, self.dx as u8, self.dy as u8, self.wheel as u8]
+    }
+}
+
+/// registers /dev/mouse, once the first mouse appears
+pub fn attach() {
+    if REGISTERED.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    if let Err(e) = create_device_file!(&MOUSE, MOUSE_FILE) {
+        eprintln!("could not register {}: {}", MOUSE_FILE, e);
+    }
+}
+
+fn events() -> &'static (Sender<MouseEvent>, Receiver<MouseEvent>) {
+    EVENTS.get_or_init(|| channel::bounded(QUEUE_SIZE))
+}
+
+pub fn push(event: MouseEvent) {
+    let (sender, receiver) = events();
+    if let Err(TrySendError::Full(event)) = sender.try_send(event) {
+        _ = receiver.try_recv();
+        _ = sender.try_send(event);
+    }
+}
+
+/// /dev/mouse: reads return the queued events of all mice as packets of buttons, dx, dy and wheel.
+/// The movements are signed bytes. Reads do not block and only return whole packets
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Mouse;
+
+impl Read for Mouse {
+    fn read(&self, buf: &mut [u8], _offset: usize) -> IOResult<usize> {
+        let (_, receiver) = events();
+        let mut n = 0;
+        for packet in buf.chunks_exact_mut(PACKET_SIZE) {
+            let Ok(event) = receiver.try_recv() else {
+                break;
+            };
+            packet.copy_from_slice(&event.to_bytes());
+            n += PACKET_SIZE;
+        }
+        Ok(n)
+    }
+}
+
+impl_empty_write!(Mouse);
+impl_file_for_wr!(Mouse: NodeType::FILE);
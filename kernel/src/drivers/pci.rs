@@ -0,0 +1,226 @@
+use alloc::vec::Vec;
+use core::fmt::Display;
+
+use conquer_once::spin::OnceCell;
+use x86_64::instructions::port::Port;
+
+use crate::{serial_println, sync::locks::Mutex};
+
+// configuration mechanism #1: the address of a config dword is written to CONFIG_ADDRESS, the dword is then accessed at CONFIG_DATA
+const CONFIG_ADDRESS: u16 = 0xcf8;
+const CONFIG_DATA: u16 = 0xcfc;
+const CONFIG_ENABLE: u32 = 1 << 31;
+
+const VENDOR_NONE: u16 = 0xffff;
+const HEADER_MULTIFUNCTION: u8 = 1 << 7;
+const HEADER_TYPE_MASK: u8 = 0x7f;
+const HEADER_GENERAL: u8 = 0x00;
+
+const REG_ID: u8 = 0x00;
+const REG_COMMAND: u8 = 0x04;
+const REG_CLASS: u8 = 0x08;
+const REG_HEADER: u8 = 0x0c;
+const REG_BAR0: u8 = 0x10;
+const REG_INTERRUPT: u8 = 0x3c;
+
+const COMMAND_IO: u16 = 1 << 0;
+const COMMAND_MEMORY: u16 = 1 << 1;
+const COMMAND_BUS_MASTER: u16 = 1 << 2;
+
+const BAR_IO: u32 = 1 << 0;
+const BAR_64BIT: u32 = 0b10 << 1;
+const BAR_PREFETCHABLE: u32 = 1 << 3;
+
+static CONFIG: Mutex<(Port<u32>, Port<u32>)> =
+    Mutex::new((Port::new(CONFIG_ADDRESS), Port::new(CONFIG_DATA)));
+static DEVICES: OnceCell<Vec<PciDevice>> = OnceCell::uninit();
+
+/// the location of a function on the pci bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciAddress {
+    pub const fn new(bus: u8, device: u8, function: u8) -> Self {
+        Self {
+            bus,
+            device,
+            function,
+        }
+    }
+
+    /// reads the config dword containing offset
+    pub fn read_u32(&self, offset: u8) -> u32 {
+        let mut config = CONFIG.lock();
+        unsafe {
+            config.0.write(self.config_address(offset));
+            config.1.read()
+        }
+    }
+
+    pub fn write_u32(&self, offset: u8, value: u32) {
+        let mut config = CONFIG.lock();
+        unsafe {
+            config.0.write(self.config_address(offset));
+            config.1.write(value);
+        }
+    }
+
+    pub fn read_u16(&self, offset: u8) -> u16 {
+        (self.read_u32(offset) >> ((offset & 2) * 8)) as u16
+    }
+
+    pub fn write_u16(&self, offset: u8, value: u16) {
+        let shift = (offset & 2) * 8;
+        let dword = self.read_u32(offset) & !(0xffff << shift);
+        self.write_u32(offset, dword | ((value as u32) << shift));
+    }
+
+    pub fn read_u8(&self, offset: u8) -> u8 {
+        (self.read_u32(offset) >> ((offset & 3) * 8)) as u8
+    }
+
+    fn config_address(&self, offset: u8) -> u32 {
+        CONFIG_ENABLE
+            | ((self.bus as u32) << 16)
+            | ((self.device as u32) << 11)
+            | ((self.function as u32) << 8)
+            | (offset & 0xfc) as u32
+    }
+}
+
+impl Display for PciAddress {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+/// a base address register, as configured by the firmware
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    Io(u16),
+    Memory { addr: u64, prefetchable: bool },
+}
+
+/// a function on the pci bus with a general device header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciDevice {
+    pub addr: PciAddress,
+    pub vendor: u16,
+    pub device: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+}
+
+impl PciDevice {
+    fn probe(addr: PciAddress) -> Option<Self> {
+        let id = addr.read_u32(REG_ID);
+        if id as u16 == VENDOR_NONE {
+            return None;
+        }
+        let class = addr.read_u32(REG_CLASS);
+        Some(Self {
+            addr,
+            vendor: id as u16,
+            device: (id >> 16) as u16,
+            class: (class >> 24) as u8,
+            subclass: (class >> 16) as u8,
+            prog_if: (class >> 8) as u8,
+        })
+    }
+
+    /// reads base address register idx. A 64 bit memory bar occupies idx and idx + 1
+    pub fn bar(&self, idx: u8) -> Option<Bar> {
+        if idx >= 6 {
+            return None;
+        }
+        let offset = REG_BAR0 + 4 * idx;
+        let raw = self.addr.read_u32(offset);
+        if raw & BAR_IO != 0 {
+            let port = (raw & !0b11) as u16;
+            return (port != 0).then_some(Bar::Io(port));
+        }
+        let mut addr = (raw & !0xf) as u64;
+        if raw & BAR_64BIT != 0 {
+            if idx == 5 {
+                return None;
+            }
+            addr |= (self.addr.read_u32(offset + 4) as u64) << 32;
+        }
+        (addr != 0).then_some(Bar::Memory {
+            addr,
+            prefetchable: raw & BAR_PREFETCHABLE != 0,
+        })
+    }
+
+    /// the legacy interrupt line, as routed by the firmware
+    pub fn interrupt_line(&self) -> u8 {
+        self.addr.read_u8(REG_INTERRUPT)
+    }
+
+    /// enables decoding of the io and memory bars and lets the device access memory on its own
+    pub fn enable_bus_master(&self) {
+        let command = self.addr.read_u16(REG_COMMAND);
+        self.addr.write_u16(
+            REG_COMMAND,
+            command | COMMAND_IO | COMMAND_MEMORY | COMMAND_BUS_MASTER,
+        );
+    }
+}
+
+impl Display for PciDevice {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} {:04x}:{:04x} class {:02x}.{:02x}.{:02x}",
+            self.addr, self.vendor, self.device, self.class, self.subclass, self.prog_if
+        )
+    }
+}
+
+/// scans all buses by brute force. Bridges are not followed, as every bus number is probed anyway.
+/// Each call enumerates anew, such that removed functions are no longer listed
+pub fn scan() -> Vec<PciDevice> {
+    let mut devices = Vec::new();
+    for bus in 0..=u8::MAX {
+        for device in 0..32 {
+            let addr = PciAddress::new(bus, device, 0);
+            if addr.read_u16(REG_ID) == VENDOR_NONE {
+                continue;
+            }
+            // the header type is the third byte of the dword at 0x0c
+            let header = addr.read_u8(REG_HEADER + 2);
+            let functions = if header & HEADER_MULTIFUNCTION != 0 {
+                8
+            } else {
+                1
+            };
+            for function in 0..functions {
+                let addr = PciAddress::new(bus, device, function);
+                if addr.read_u8(REG_HEADER + 2) & HEADER_TYPE_MASK != HEADER_GENERAL {
+                    continue;
+                }
+                if let Some(device) = PciDevice::probe(addr) {
+                    serial_println!("pci: {}", device);
+                    devices.push(device);
+                }
+            }
+        }
+    }
+    devices
+}
+
+/// all devices on the pci bus, as found by the first scan
+pub fn devices() -> &'static [PciDevice] {
+    DEVICES.get_or_init(scan)
+}
+
+pub fn find(vendor: u16, device: u16) -> impl Iterator<Item = &'static PciDevice> {
+    devices()
+        .iter()
+        .filter(move |dev| dev.vendor == vendor && dev.device == device)
+}
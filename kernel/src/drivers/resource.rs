@@ -1,9 +1,12 @@
+use os_macros::init_task;
+
 use crate::kernel::threading::{
     self,
     schedule::{Scheduler, get_scheduler},
     tls,
 };
 
+#[init_task(stage = "drivers", order = 20)]
 pub fn start_resource_manager() {
     threading::spawn(|| {
         loop {
@@ -17,7 +20,7 @@ pub fn start_resource_manager() {
             // this does not currently work, as someone needs to call reschedule in order for a woken thread to continue.
             // let conditons = &[QueuTypeCondition::with_cond(
             //     QueueType::Timer,
-            //     WaitCondition::Time(Duration::from_millis(50) + current_time()),
+            //     WaitCondition::Time(Instant::now() + Duration::from_millis(50)),
             // )];
 
             // wait_manager::add_wait(&tls::task_data().current_pid(), conditons);
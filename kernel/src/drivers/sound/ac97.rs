@@ -0,0 +1,200 @@
+use alloc::{boxed::Box, sync::Arc};
+
+use x86_64::instructions::port::Port;
+
+use super::{CHANNELS, PcmOutput, SoundError, remove_output, set_output};
+use crate::drivers::{
+    dma::DmaBuffer,
+    model::{Device, Driver, DriverError, Match},
+    pci::{Bar, PciDevice},
+};
+
+// the intel ich audio controller, as emulated by QEMU's -device AC97
+const VENDOR: u16 = 0x8086;
+const DEVICE_ID: u16 = 0x2415;
+
+// native audio mixer registers of the codec, in bar 0
+const NAM_RESET: u16 = 0x00;
+const NAM_MASTER_VOLUME: u16 = 0x02;
+const NAM_PCM_OUT_VOLUME: u16 = 0x18;
+// 0 attenuation on both channels, unmuted
+const VOLUME_FULL: u16 = 0x0000;
+const VOLUME_0DB: u16 = 0x0808;
+
+// native audio bus master registers, in bar 1. Only the pcm out box is used
+const PO_BDBAR: u16 = 0x10;
+const PO_CIV: u16 = 0x14;
+const PO_LVI: u16 = 0x15;
+const PO_SR: u16 = 0x16;
+const PO_CR: u16 = 0x1b;
+const GLOB_CNT: u16 = 0x2c;
+
+const CR_RUN: u8 = 1 << 0;
+const CR_RESET: u8 = 1 << 1;
+const SR_HALTED: u16 = 1 << 0;
+// the interrupt status bits, cleared by writing them
+const SR_CLEAR: u16 = 0b111 << 2;
+const GLOB_CNT_COLD_RESET: u32 = 1 << 1;
+const RESET_POLLS: usize = 100_000;
+
+// the controller cycles through a list of 32 buffer descriptors, each pointing to one of PERIODS buffers
+const BDL_ENTRIES: usize = 32;
+const BDL_ENTRY_SIZE: usize = 8;
+const PERIODS: usize = 8;
+const PERIOD_FRAMES: usize = 1024;
+const PERIOD_SAMPLES: usize = PERIOD_FRAMES * CHANNELS;
+const PERIOD_BYTES: usize = PERIOD_SAMPLES * size_of::<i16>();
+// periods queued ahead of the one playing. Must stay below PERIODS, such that the one playing is never refilled
+const QUEUED: usize = 4;
+
+static BDL: DmaBuffer<{ BDL_ENTRIES * BDL_ENTRY_SIZE }> = DmaBuffer::new();
+static PERIOD_MEMORY: DmaBuffer<{ PERIODS * PERIOD_BYTES }> = DmaBuffer::new();
+
+/// pcm output through an ac'97 controller at its fixed rate of 48kHz.
+/// The controller is polled: the mixer task tops up the queued periods, interrupts stay disabled
+#[derive(Debug)]
+pub struct Ac97 {
+    nabm: u16,
+    // the next buffer descriptor to fill
+    next: usize,
+}
+
+impl Ac97 {
+    fn new(pci: &PciDevice) -> Result<Self, SoundError> {
+        let (Some(Bar::Io(nam)), Some(Bar::Io(nabm))) = (pci.bar(0), pci.bar(1)) else {
+            return Err(SoundError::NoBar);
+        };
+        // the controller only takes 32 bit addresses
+        let periods = PERIOD_MEMORY
+            .phys_addr()
+            .filter(|addr| addr + (PERIOD_MEMORY.len() as u64) <= u32::MAX as u64)
+            .ok_or(SoundError::Unreachable)?;
+        let bdl = BDL
+            .phys_addr()
+            .filter(|addr| *addr <= u32::MAX as u64)
+            .ok_or(SoundError::Unreachable)?;
+        pci.enable_bus_master();
+
+        let ac97 = Self { nabm, next: 0 };
+        unsafe {
+            Port::<u32>::new(nabm + GLOB_CNT).write(GLOB_CNT_COLD_RESET);
+            // any write resets the codec
+            Port::<u16>::new(nam + NAM_RESET).write(0);
+            Port::<u16>::new(nam + NAM_MASTER_VOLUME).write(VOLUME_FULL);
+            Port::<u16>::new(nam + NAM_PCM_OUT_VOLUME).write(VOLUME_0DB);
+        }
+        ac97.reset_box()?;
+
+        for idx in 0..BDL_ENTRIES {
+            let addr = periods as u32 + ((idx % PERIODS) * PERIOD_BYTES) as u32;
+            let mut entry = [0; BDL_ENTRY_SIZE];
+            entry[..4].copy_from_slice(&addr.to_le_bytes());
+            // the length is counted in samples, the control bits stay clear as nothing waits for interrupts
+            entry[4..6].copy_from_slice(&(PERIOD_SAMPLES as u16).to_le_bytes());
+            unsafe {
+                BDL.as_mut_ptr()
+                    .add(idx * BDL_ENTRY_SIZE)
+                    .copy_from_nonoverlapping(entry.as_ptr(), BDL_ENTRY_SIZE)
+            };
+        }
+        unsafe { Port::<u32>::new(nabm + PO_BDBAR).write(bdl as u32) };
+        Ok(ac97)
+    }
+
+    fn reset_box(&self) -> Result<(), SoundError> {
+        let mut control = Port::<u8>::new(self.nabm + PO_CR);
+        unsafe { control.write(CR_RESET) };
+        for _ in 0..RESET_POLLS {
+            if unsafe { control.read() } & CR_RESET == 0 {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+        Err(SoundError::TimedOut)
+    }
+}
+
+impl Drop for Ac97 {
+    fn drop(&mut self) {
+        // stops the dma engine, the period memory is handed to the next controller
+        unsafe { Port::<u8>::new(self.nabm + PO_CR).write(0) };
+    }
+}
+
+fn period(idx: usize) -> &'static mut [i16] {
+    // SAFETY: the period is neither played, nor handed out elsewhere, as QUEUED < PERIODS
+    unsafe {
+        core::slice::from_raw_parts_mut(
+            PERIOD_MEMORY
+                .as_mut_ptr()
+                .add((idx % PERIODS) * PERIOD_BYTES)
+                .cast(),
+            PERIOD_SAMPLES,
+        )
+    }
+}
+
+impl PcmOutput for Ac97 {
+    fn name(&self) -> &str {
+        "ac97"
+    }
+
+    fn period_frames(&self) -> usize {
+        PERIOD_FRAMES
+    }
+
+    fn refill(&mut self, fill: &mut dyn FnMut(&mut [i16])) -> Result<(), SoundError> {
+        let current = unsafe { Port::<u8>::new(self.nabm + PO_CIV).read() } as usize;
+        let mut filled = false;
+        // the controller never passes the last valid index, which trails next, thus next is always ahead of current
+        while (self.next + BDL_ENTRIES - current) % BDL_ENTRIES < QUEUED {
+            fill(period(self.next));
+            self.next = (self.next + 1) % BDL_ENTRIES;
+            filled = true;
+        }
+        if !filled {
+            return Ok(());
+        }
+        let last = (self.next + BDL_ENTRIES - 1) % BDL_ENTRIES;
+        unsafe {
+            Port::<u8>::new(self.nabm + PO_LVI).write(last as u8);
+            let mut status = Port::<u16>::new(self.nabm + PO_SR);
+            // the controller halts, once it played the last valid buffer
+            if status.read() & SR_HALTED != 0 {
+                status.write(SR_CLEAR);
+                Port::<u8>::new(self.nabm + PO_CR).write(CR_RUN);
+            }
+        }
+        Ok(())
+    }
+}
+
+pub static DRIVER: Ac97Driver = Ac97Driver;
+
+#[derive(Debug)]
+pub struct Ac97Driver;
+
+impl Driver for Ac97Driver {
+    fn name(&self) -> &'static str {
+        "ac97"
+    }
+
+    fn match_table(&self) -> &'static [Match] {
+        &[Match::Pci {
+            vendor: VENDOR,
+            device: DEVICE_ID,
+        }]
+    }
+
+    fn probe(&self, device: &Arc<dyn Device>) -> Result<(), DriverError> {
+        let pci = device
+            .pci()
+            .ok_or(DriverError::NoDevice("not a pci device"))?;
+        let ac97 = Ac97::new(pci).map_err(DriverError::failed)?;
+        set_output(Box::new(ac97)).map_err(DriverError::failed)
+    }
+
+    fn remove(&self, _device: &Arc<dyn Device>) {
+        remove_output();
+    }
+}
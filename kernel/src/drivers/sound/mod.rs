@@ -0,0 +1,282 @@
+use alloc::{
+    boxed::Box,
+    collections::{VecDeque, btree_map::BTreeMap},
+    sync::Arc,
+    vec,
+};
+use core::{
+    fmt::Debug,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use os_macros::init_task;
+use thiserror::Error;
+use tinyos_abi::flags::NodeType;
+
+use crate::{
+    KernelRes,
+    create_device_file,
+    drivers::wait_manager,
+    eprintln,
+    impl_empty_read,
+    impl_file_for_wr,
+    kernel::{
+        config::{self, SubsystemHooks},
+        fs::{
+            FSErrorKind,
+            FSResult,
+            OpenOptions,
+            Path,
+            procfs::{self, DeviceHandle},
+        },
+        io::{IOError, IOResult, Write},
+        threading::{
+            self,
+            task::{ProcessID, TaskRepr},
+            tls,
+            wait::{QueuTypeCondition, QueueType, condition::WaitCondition},
+        },
+        time::Instant,
+    },
+    serial_println,
+    sync::locks::Mutex,
+};
+
+pub mod ac97;
+pub mod speaker;
+
+// /dev/dsp takes interleaved signed 16 bit little endian stereo frames at SAMPLE_RATE, there is no format negotiation.
+// Every process writing to it gets a stream of its own, the streams are mixed by a kernel task, which feeds the output
+
+pub const DSP_FILE: &str = "/dev/dsp";
+pub const SAMPLE_RATE: u32 = 48_000;
+pub const CHANNELS: usize = 2;
+pub const FRAME_BYTES: usize = CHANNELS * size_of::<i16>();
+// the queue of each stream holds half a second, writers wait for the mixer beyond that
+const STREAM_CAPACITY: usize = SAMPLE_RATE as usize / 2 * CHANNELS;
+
+static MIXER: Mutex<Mixer> = Mutex::new(Mixer::new());
+static OUTPUT: Mutex<Option<Box<dyn PcmOutput>>> = Mutex::new(None);
+// the handle behind /dev/dsp, detached once the output goes away
+static DSP_HANDLE: Mutex<Option<Arc<DeviceHandle>>> = Mutex::new(None);
+/// bumped whenever the subsystem stops, which ends the mixer of the previous start
+static MIXER_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SoundError {
+    #[error("the device has no usable io bars")]
+    NoBar,
+    #[error("the buffers are not reachable by the device")]
+    Unreachable,
+    #[error("the device did not respond in time")]
+    TimedOut,
+}
+
+/// a pcm output device, which is fed in periods of interleaved stereo samples at SAMPLE_RATE
+pub trait PcmOutput: Debug + Send {
+    fn name(&self) -> &str;
+
+    /// the number of frames in one period
+    fn period_frames(&self) -> usize;
+
+    /// hands each period, which the device can take right now, to fill and queues it for playback
+    fn refill(&mut self, fill: &mut dyn FnMut(&mut [i16])) -> Result<(), SoundError>;
+}
+
+/// sums the queued samples of all streams
+#[derive(Debug, Default)]
+pub struct Mixer {
+    streams: BTreeMap<ProcessID, VecDeque<i16>>,
+}
+
+impl Mixer {
+    pub const fn new() -> Self {
+        Self {
+            streams: BTreeMap::new(),
+        }
+    }
+
+    /// queues the whole frames in bytes to the stream of client. Returns the number of frames, which fit
+    pub fn queue(&mut self, client: ProcessID, bytes: &[u8]) -> usize {
+        let stream = self.streams.entry(client).or_default();
+        let frames = ((STREAM_CAPACITY - stream.len()) / CHANNELS).min(bytes.len() / FRAME_BYTES);
+        stream.extend(
+            bytes[..frames * FRAME_BYTES]
+                .chunks_exact(size_of::<i16>())
+                .map(|sample| i16::from_le_bytes([sample[0], sample[1]])),
+        );
+        frames
+    }
+
+    /// mixes the next out.len() samples of all streams into out. Streams running dry are padded with silence and removed
+    pub fn mix(&mut self, out: &mut [i16]) {
+        let mut sums = vec![0i32; out.len()];
+        self.streams.retain(|_, stream| {
+            for (sum, sample) in sums
+                .iter_mut()
+                .zip(stream.drain(..out.len().min(stream.len())))
+            {
+                *sum += sample as i32;
+            }
+            !stream.is_empty()
+        });
+        for (out, sum) in out.iter_mut().zip(sums) {
+            *out = sum.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        }
+    }
+
+    pub fn streams(&self) -> usize {
+        self.streams.len()
+    }
+
+    /// drops the queued samples of all streams
+    pub fn clear(&mut self) {
+        self.streams.clear();
+    }
+}
+
+/// /dev/dsp: the pcm output. Reads are always at eof, writes fail once the output was removed
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Dsp;
+
+impl Write for Dsp {
+    fn write(&self, buf: &[u8], _offset: usize) -> IOResult<usize> {
+        let client = tls::task_data()
+            .current_thread()
+            .map(|task| task.pid())
+            .unwrap_or_default();
+        let frames = buf.len() / FRAME_BYTES;
+        let mut written = 0;
+        loop {
+            if OUTPUT.lock().is_none() {
+                return Err(IOError::with_message(
+                    FSErrorKind::NotFound,
+                    "the output was removed",
+                ));
+            }
+            written += MIXER
+                .lock()
+                .queue(client, &buf[written * FRAME_BYTES..frames * FRAME_BYTES]);
+            if written == frames {
+                return Ok(written * FRAME_BYTES);
+            }
+            wait_manager::wait_self(&[QueuTypeCondition::with_cond(
+                QueueType::Timer,
+                WaitCondition::Time(Instant::now() + Duration::from_millis(10)),
+            )]);
+        }
+    }
+}
+
+impl_empty_read!(Dsp);
+impl_file_for_wr!(Dsp: NodeType::FILE);
+
+/// registers /dev/dsp, which is played through output, once the mixer runs. Only a single output is supported
+pub fn set_output(output: Box<dyn PcmOutput>) -> FSResult<()> {
+    let rw = OpenOptions::READ | OpenOptions::WRITE | OpenOptions::CREATE_ALL;
+    let handle = DeviceHandle::new(Arc::new(Dsp));
+    create_device_file!(handle.clone(), DSP_FILE, rw)?;
+    serial_println!("sound: playing through {}", output.name());
+    *OUTPUT.lock() = Some(output);
+    *DSP_HANDLE.lock() = Some(handle);
+    Ok(())
+}
+
+/// stops playing through the current output and removes /dev/dsp. Files, which are still open on it, fail from then on
+pub fn remove_output() -> Option<Box<dyn PcmOutput>> {
+    let output = OUTPUT.lock().take()?;
+    if let Some(handle) = DSP_HANDLE.lock().take() {
+        handle.detach();
+    }
+    _ = procfs::registry().deregister(Path::new(DSP_FILE));
+    MIXER.lock().clear();
+    serial_println!("sound: {} removed", output.name());
+    Some(output)
+}
+
+/// the mixer may be started and stopped at runtime, see config::register
+#[init_task(stage = "drivers", order = 40)]
+fn register_mixer() -> KernelRes<()> {
+    config::register(
+        "sound",
+        SubsystemHooks {
+            start: start_mixer,
+            stop: stop_mixer,
+        },
+    )
+}
+
+/// starts the kernel task, which mixes all streams into the output
+fn start_mixer() -> KernelRes<()> {
+    let Some(period_frames) = OUTPUT.lock().as_ref().map(|output| output.period_frames()) else {
+        return Ok(());
+    };
+    // refilled twice per period, such that the queue of the device never runs dry
+    let interval = Duration::from_micros(period_frames as u64 * 1_000_000 / SAMPLE_RATE as u64 / 2);
+    let generation = MIXER_GENERATION.load(Ordering::Acquire);
+    threading::spawn(move || {
+        while MIXER_GENERATION.load(Ordering::Acquire) == generation {
+            let mut output = OUTPUT.lock();
+            // the task ends with the output
+            let Some(current) = output.as_mut() else {
+                return;
+            };
+            if let Err(e) = current.refill(&mut |period| MIXER.lock().mix(period)) {
+                eprintln!("sound: {} failed: {}", current.name(), e);
+                return;
+            }
+            drop(output);
+            wait_manager::wait_self(&[QueuTypeCondition::with_cond(
+                QueueType::Timer,
+                WaitCondition::Time(Instant::now() + interval),
+            )]);
+        }
+    })?;
+    Ok(())
+}
+
+/// stops mixing. The output and the queued streams are kept, writers to /dev/dsp wait until the mixer is started
+/// again
+fn stop_mixer() -> KernelRes<()> {
+    MIXER_GENERATION.fetch_add(1, Ordering::AcqRel);
+    Ok(())
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use alloc::{vec, vec::Vec};
+
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    fn frames(samples: &[i16]) -> Vec<u8> {
+        samples.iter().flat_map(|s| s.to_le_bytes()).collect()
+    }
+
+    #[kernel_test]
+    fn mixing_streams() {
+        let mut mixer = Mixer::new();
+        let (a, b) = (ProcessID(1), ProcessID(2));
+        // the trailing half frame is not taken
+        assert_eq!(mixer.queue(a, &frames(&[100, -100, 30_000, -30_000, 7])), 2);
+        assert_eq!(mixer.queue(b, &frames(&[1, 2, 10_000, -10_000])), 2);
+        assert_eq!(mixer.queue(a, &frames(&[5, 5])), 1);
+        assert_eq!(mixer.streams(), 2);
+
+        let mut out = [0; 4];
+        mixer.mix(&mut out);
+        // sums are clipped instead of wrapping
+        assert_eq!(out, [101, -98, i16::MAX, i16::MIN]);
+        assert_eq!(mixer.streams(), 1);
+        mixer.mix(&mut out);
+        assert_eq!(out, [5, 5, 0, 0]);
+        assert_eq!(mixer.streams(), 0);
+
+        // a full stream takes nothing more
+        let second = frames(&vec![1; SAMPLE_RATE as usize * CHANNELS]);
+        assert_eq!(mixer.queue(a, &second), STREAM_CAPACITY / CHANNELS);
+        assert_eq!(mixer.queue(a, &second), 0);
+    }
+}
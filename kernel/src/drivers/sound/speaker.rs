@@ -0,0 +1,138 @@
+use alloc::sync::Arc;
+use core::time::Duration;
+
+use tinyos_abi::flags::NodeType;
+use x86_64::instructions::port::{Port, PortWriteOnly};
+
+use crate::{
+    create_device_file,
+    drivers::{
+        model::{Device, Driver, DriverError, Match},
+        wait_manager,
+    },
+    impl_empty_read,
+    impl_file_for_wr,
+    kernel::{
+        fs::{FSErrorKind, OpenOptions},
+        io::{IOError, IOResult, Write},
+        threading::wait::{QueuTypeCondition, QueueType, condition::WaitCondition},
+        time::Instant,
+    },
+    sync::locks::Mutex,
+};
+
+// the pc speaker is driven by channel 2 of the pit, gated through the keyboard controller port b
+const PIT_FREQUENCY: u32 = 1_193_182;
+const PIT_CHANNEL2: u16 = 0x42;
+const PIT_COMMAND: u16 = 0x43;
+const PORT_B: u16 = 0x61;
+// channel 2, low then high byte of the divisor, square wave generator
+const CHANNEL2_SQUARE_WAVE: u8 = 0b1011_0110;
+const SPEAKER_ENABLE: u8 = 0b11;
+
+pub const BEEP_FILE: &str = "/dev/beep";
+pub const MIN_FREQUENCY: u32 = 20;
+pub const MAX_FREQUENCY: u32 = 20_000;
+const DEFAULT_DURATION: Duration = Duration::from_millis(100);
+// beeps are meant as signals, not to block the writer for long
+const MAX_DURATION: Duration = Duration::from_secs(5);
+
+pub static BEEP: Beep = Beep;
+static SPEAKER: Mutex<Speaker> = Mutex::new(Speaker {
+    command: PortWriteOnly::new(PIT_COMMAND),
+    channel2: PortWriteOnly::new(PIT_CHANNEL2),
+    port_b: Port::new(PORT_B),
+});
+
+struct Speaker {
+    command: PortWriteOnly<u8>,
+    channel2: PortWriteOnly<u8>,
+    port_b: Port<u8>,
+}
+
+/// the pit divisor for frequency, which is clamped to the audible range
+pub fn divisor(frequency: u32) -> u16 {
+    (PIT_FREQUENCY / frequency.clamp(MIN_FREQUENCY, MAX_FREQUENCY)) as u16
+}
+
+/// plays a square wave of frequency Hz, until stop_tone is called
+pub fn start_tone(frequency: u32) {
+    let [low, high] = divisor(frequency).to_le_bytes();
+    let mut speaker = SPEAKER.lock();
+    unsafe {
+        speaker.command.write(CHANNEL2_SQUARE_WAVE);
+        speaker.channel2.write(low);
+        speaker.channel2.write(high);
+        let gate = speaker.port_b.read();
+        speaker.port_b.write(gate | SPEAKER_ENABLE);
+    }
+}
+
+pub fn stop_tone() {
+    let mut speaker = SPEAKER.lock();
+    unsafe {
+        let gate = speaker.port_b.read();
+        speaker.port_b.write(gate & !SPEAKER_ENABLE);
+    }
+}
+
+/// plays a tone of frequency Hz for duration. The caller sleeps meanwhile
+pub fn beep(frequency: u32, duration: Duration) {
+    start_tone(frequency);
+    wait_manager::wait_self(&[QueuTypeCondition::with_cond(
+        QueueType::Timer,
+        WaitCondition::Time(Instant::now() + duration.min(MAX_DURATION)),
+    )]);
+    stop_tone();
+}
+
+/// /dev/beep: writing '<frequency in Hz> [<duration in ms>]' beeps through the pc speaker. The duration defaults to 100ms
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Beep;
+
+impl Write for Beep {
+    fn write(&self, buf: &[u8], _offset: usize) -> IOResult<usize> {
+        let invalid = || IOError::with_message(FSErrorKind::Other, "invalid value");
+        let text = str::from_utf8(buf.trim_ascii()).map_err(|_| invalid())?;
+        let mut words = text.split_ascii_whitespace();
+        let frequency = words
+            .next()
+            .and_then(|word| word.parse::<u32>().ok())
+            .ok_or_else(invalid)?;
+        let duration = match words.next() {
+            Some(millis) => Duration::from_millis(millis.parse().map_err(|_| invalid())?),
+            None => DEFAULT_DURATION,
+        };
+        beep(frequency, duration);
+        Ok(buf.len())
+    }
+}
+
+impl_empty_read!(Beep);
+impl_file_for_wr!(Beep: NodeType::FILE);
+
+pub static DRIVER: SpeakerDriver = SpeakerDriver;
+
+/// registers /dev/beep for the pc speaker
+#[derive(Debug)]
+pub struct SpeakerDriver;
+
+impl Driver for SpeakerDriver {
+    fn name(&self) -> &'static str {
+        "pcspkr"
+    }
+
+    fn match_table(&self) -> &'static [Match] {
+        &[Match::Platform("pcspkr")]
+    }
+
+    fn probe(&self, _device: &Arc<dyn Device>) -> Result<(), DriverError> {
+        create_device_file!(
+            &BEEP,
+            BEEP_FILE,
+            OpenOptions::WRITE | OpenOptions::CREATE_ALL
+        )
+        .map(|_| ())
+        .map_err(DriverError::failed)
+    }
+}
@@ -1,23 +1,82 @@
+use core::time::Duration;
+
+use os_macros::init_task;
 use pc_keyboard::{DecodedKey, KeyCode};
 
 use crate::{
+    drivers::{
+        keyboard::{KEYBOARD_BUFFER, STDIN_QUEUE_SIZE, new_decoder},
+        wait_manager,
+    },
     kernel::{
         devices::tty::{
             TTYSink,
-            sink::{FBBACKEND, SERIALBACKEND},
+            clipboard,
+            sink::{FBBACKEND, SERIALBACKEND, TTYOUTPUTQUEUE, output_condition},
+            source::scancode_waiter,
         },
-        threading,
+        threading::{
+            self,
+            wait::{
+                QueuTypeCondition,
+                QueueHandle,
+                QueueType,
+                condition::WaitCondition,
+                queues::GenericWaitQueue,
+            },
+        },
+        time::Instant,
     },
     serial_println,
 };
 
-//TODO add wake up logic
+/// upper bound on the delay of output, whose wakeup event was lost
+const FLUSH_TIMEOUT: Duration = Duration::from_millis(50);
+
+#[init_task(stage = "drivers", order = 20)]
 pub fn start_tty_backend() {
+    wait_manager::add_queue(
+        QueueHandle::from_borrowed(TTYOUTPUTQUEUE.get_or_init(GenericWaitQueue::new)),
+        QueueType::TTYOutput,
+    );
     _ = threading::spawn(move || {
         loop {
             SERIALBACKEND.get().unwrap().flush();
             FBBACKEND.get().unwrap().flush();
-            threading::yield_now();
+            wait_manager::wait_self(&[
+                output_condition(),
+                QueuTypeCondition::with_cond(
+                    QueueType::Timer,
+                    WaitCondition::Time(Instant::now() + FLUSH_TIMEOUT),
+                ),
+            ]);
+        }
+    })
+    .unwrap();
+}
+
+/// sees every key once, to run the shortcuts of the terminal. Readers of stdin skip them
+#[init_task(stage = "drivers", order = 20)]
+pub fn start_tty_input() {
+    _ = threading::spawn(move || {
+        let mut decoder = new_decoder();
+        let mut cursor = KEYBOARD_BUFFER.written();
+        let mut scancodes = [0; STDIN_QUEUE_SIZE];
+        loop {
+            if !KEYBOARD_BUFFER.cursor_is_valid(cursor) {
+                // scancodes were overwritten before we got to them
+                cursor = cursor.max(KEYBOARD_BUFFER.written().saturating_sub(STDIN_QUEUE_SIZE));
+            }
+            let n = KEYBOARD_BUFFER.readn(cursor, &mut scancodes);
+            cursor += n;
+            for &scancode in &scancodes[..n] {
+                if let Ok(Some(event)) = decoder.add_byte(scancode)
+                    && let Some(key) = decoder.process_keyevent(event)
+                {
+                    clipboard::handle_key(key, decoder.get_modifiers().into());
+                }
+            }
+            wait_manager::wait_self(&[scancode_waiter(cursor)]);
         }
     })
     .unwrap();
@@ -0,0 +1,179 @@
+use alloc::{boxed::Box, vec, vec::Vec};
+
+use super::{Interface, ReportHandler, SetupPacket};
+use crate::drivers::{
+    keyboard,
+    mouse::{self, MouseEvent},
+};
+
+// only devices offering the boot protocol are supported, their reports have a fixed layout and need no report descriptor
+const CLASS_HID: u8 = 3;
+const SUBCLASS_BOOT: u8 = 1;
+const PROTOCOL_KEYBOARD: u8 = 1;
+const PROTOCOL_MOUSE: u8 = 2;
+const REQUEST_SET_IDLE: u8 = 0x0a;
+const REQUEST_SET_PROTOCOL: u8 = 0x0b;
+const BOOT_PROTOCOL: u16 = 0;
+
+/// modifier byte, followed by a reserved byte and up to 6 usages of held keys
+const KEYBOARD_REPORT_LEN: usize = 8;
+/// reported in all key slots, if more keys are held than fit into a report
+const ERROR_ROLLOVER: u8 = 0x01;
+const EXTENDED: u8 = 0xe0;
+const RELEASE: u8 = 0x80;
+const FIRST_USAGE: u8 = 0x04;
+
+// set 1 scancodes of the modifier bits: left control, shift, alt and gui, then the right ones
+const MODIFIERS: [u16; 8] = [
+    0x001d, 0x002a, 0x0038, 0xe05b, 0xe01d, 0x0036, 0xe038, 0xe05c,
+];
+
+// set 1 scancodes of the keyboard usages starting at FIRST_USAGE ('a'). Extended codes carry the 0xe0 prefix in the high byte
+#[rustfmt::skip]
+const USAGES: [u16; 97] = [
+    0x001e, 0x0030, 0x002e, 0x0020, 0x0012, 0x0021, 0x0022, 0x0023,
+    0x0017, 0x0024, 0x0025, 0x0026, 0x0032, 0x0031, 0x0018, 0x0019,
+    0x0010, 0x0013, 0x001f, 0x0014, 0x0016, 0x002f, 0x0011, 0x002d,
+    0x0015, 0x002c, 0x0002, 0x0003, 0x0004, 0x0005, 0x0006, 0x0007,
+    0x0008, 0x0009, 0x000a, 0x000b, 0x001c, 0x0001, 0x000e, 0x000f,
+    0x0039, 0x000c, 0x000d, 0x001a, 0x001b, 0x002b, 0x002b, 0x0027,
+    0x0028, 0x0029, 0x0033, 0x0034, 0x0035, 0x003a, 0x003b, 0x003c,
+    0x003d, 0x003e, 0x003f, 0x0040, 0x0041, 0x0042, 0x0043, 0x0044,
+    0x0057, 0x0058, 0xe037, 0x0046, 0x0000, 0xe052, 0xe047, 0xe049,
+    0xe053, 0xe04f, 0xe051, 0xe04d, 0xe04b, 0xe050, 0xe048, 0x0045,
+    0xe035, 0x0037, 0x004a, 0x004e, 0xe01c, 0x004f, 0x0050, 0x0051,
+    0x004b, 0x004c, 0x004d, 0x0047, 0x0048, 0x0049, 0x0052, 0x0053,
+    0x0056,
+];
+
+/// takes over boot keyboards and mice. They are switched to the boot protocol and only report changes
+pub fn bind(interface: &Interface) -> Option<(Vec<SetupPacket>, Box<dyn ReportHandler>)> {
+    if interface.class != CLASS_HID || interface.subclass != SUBCLASS_BOOT {
+        return None;
+    }
+    let handler: Box<dyn ReportHandler> = match interface.protocol {
+        PROTOCOL_KEYBOARD => Box::new(Keyboard::default()),
+        PROTOCOL_MOUSE => {
+            mouse::attach();
+            Box::new(Mouse)
+        }
+        _ => return None,
+    };
+    let requests = vec![
+        SetupPacket::class_interface(REQUEST_SET_PROTOCOL, BOOT_PROTOCOL, interface.number),
+        SetupPacket::class_interface(REQUEST_SET_IDLE, 0, interface.number),
+    ];
+    Some((requests, handler))
+}
+
+/// feeds the keyboard queue, as if the keys were pressed on a ps/2 keyboard
+#[derive(Debug, Default)]
+pub struct Keyboard {
+    previous: [u8; KEYBOARD_REPORT_LEN],
+}
+
+impl Keyboard {
+    /// the set 1 scancodes for the changes since the previous report: releases first, then presses
+    pub fn scancodes(&mut self, report: &[u8]) -> Vec<u8> {
+        let Some(report) = report.first_chunk::<KEYBOARD_REPORT_LEN>() else {
+            return Vec::new();
+        };
+        if report[2..].iter().all(|usage| *usage == ERROR_ROLLOVER) {
+            return Vec::new();
+        }
+        let (old, new) = (self.previous, *report);
+        let mut codes = Vec::new();
+        for (bit, code) in MODIFIERS.iter().enumerate() {
+            let (was, is) = (old[0] & 1 << bit != 0, new[0] & 1 << bit != 0);
+            if was != is {
+                push_scancode(&mut codes, *code, was);
+            }
+        }
+        for usage in old[2..].iter().filter(|usage| !new[2..].contains(usage)) {
+            push_scancode(&mut codes, scancode(*usage), true);
+        }
+        for usage in new[2..].iter().filter(|usage| !old[2..].contains(usage)) {
+            push_scancode(&mut codes, scancode(*usage), false);
+        }
+        self.previous = new;
+        codes
+    }
+}
+
+impl ReportHandler for Keyboard {
+    fn report(&mut self, data: &[u8]) {
+        let codes = self.scancodes(data);
+        if !codes.is_empty() {
+            keyboard::put_scancodes(&codes);
+        }
+    }
+}
+
+/// 0 for usages without a scancode, which includes the empty slots of a report
+fn scancode(usage: u8) -> u16 {
+    usage
+        .checked_sub(FIRST_USAGE)
+        .and_then(|idx| USAGES.get(idx as usize))
+        .copied()
+        .unwrap_or(0)
+}
+
+fn push_scancode(codes: &mut Vec<u8>, code: u16, release: bool) {
+    if code == 0 {
+        return;
+    }
+    if code >> 8 == EXTENDED as u16 {
+        codes.push(EXTENDED);
+    }
+    codes.push(code as u8 | if release { RELEASE } else { 0 });
+}
+
+/// feeds /dev/mouse
+#[derive(Debug, Default)]
+pub struct Mouse;
+
+impl ReportHandler for Mouse {
+    fn report(&mut self, data: &[u8]) {
+        let [buttons, dx, dy, rest @ ..] = data else {
+            return;
+        };
+        mouse::push(MouseEvent {
+            buttons: *buttons,
+            dx: *dx as i8,
+            dy: *dy as i8,
+            // the wheel is optional in the boot protocol
+            wheel: rest.first().map_or(0, |wheel| *wheel as i8),
+        });
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    #[kernel_test]
+    fn keyboard_reports() {
+        let mut keyboard = Keyboard::default();
+        // 'a' pressed
+        assert_eq!(keyboard.scancodes(&[0, 0, 0x04, 0, 0, 0, 0, 0]), [0x1e]);
+        // held left shift and 'b' in addition, 'a' still held
+        assert_eq!(
+            keyboard.scancodes(&[0b10, 0, 0x04, 0x05, 0, 0, 0, 0]),
+            [0x2a, 0x30]
+        );
+        // too many keys, nothing changes
+        assert!(keyboard.scancodes(&[0b10, 0, 1, 1, 1, 1, 1, 1]).is_empty());
+        // everything released, the arrow up pressed
+        assert_eq!(
+            keyboard.scancodes(&[0, 0, 0x52, 0, 0, 0, 0, 0]),
+            [0xaa, 0x9e, 0xb0, 0xe0, 0x48]
+        );
+        assert_eq!(keyboard.scancodes(&[0, 0, 0, 0, 0, 0, 0, 0]), [0xe0, 0xc8]);
+        // short reports are ignored
+        assert!(keyboard.scancodes(&[0, 0, 0x04]).is_empty());
+        assert_eq!(scancode(0x65), 0);
+        assert_eq!(scancode(0x03), 0);
+    }
+}
@@ -0,0 +1,318 @@
+use alloc::{boxed::Box, vec::Vec};
+use core::fmt::Debug;
+
+use thiserror::Error;
+
+use crate::kernel::mem::mmio::MmioError;
+
+pub mod hid;
+pub mod xhci;
+
+// standard requests and descriptor types, see chapter 9 of the usb 2.0 specification
+pub const REQUEST_GET_DESCRIPTOR: u8 = 0x06;
+pub const REQUEST_SET_CONFIGURATION: u8 = 0x09;
+pub const DESCRIPTOR_DEVICE: u8 = 1;
+pub const DESCRIPTOR_CONFIGURATION: u8 = 2;
+pub const DESCRIPTOR_INTERFACE: u8 = 4;
+pub const DESCRIPTOR_ENDPOINT: u8 = 5;
+pub const DEVICE_DESCRIPTOR_LEN: usize = 18;
+pub const CONFIGURATION_HEADER_LEN: usize = 9;
+
+// bits of SetupPacket::request_type
+const REQUEST_DEVICE_TO_HOST: u8 = 1 << 7;
+const REQUEST_CLASS: u8 = 1 << 5;
+const REQUEST_TO_INTERFACE: u8 = 1;
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsbError {
+    #[error("the controller has no memory bar")]
+    NoBar,
+    #[error("the registers could not be mapped: {0}")]
+    Unmapped(#[from] MmioError),
+    #[error("out of memory for controller structures")]
+    NoMemory,
+    #[error("the controller did not respond in time")]
+    TimedOut,
+    #[error("the command failed with completion code {0}")]
+    Command(u8),
+    #[error("the transfer failed with completion code {0}")]
+    Transfer(u8),
+    #[error("the transfer does not fit into a page")]
+    TooLarge,
+    #[error("malformed descriptor")]
+    BadDescriptor,
+    #[error("the device is not connected")]
+    Disconnected,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Out,
+    In,
+}
+
+/// the 8 bytes of the setup stage of a control transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SetupPacket {
+    pub request_type: u8,
+    pub request: u8,
+    pub value: u16,
+    pub index: u16,
+    pub length: u16,
+}
+
+impl SetupPacket {
+    pub const fn get_descriptor(kind: u8, index: u8, length: u16) -> Self {
+        Self {
+            request_type: REQUEST_DEVICE_TO_HOST,
+            request: REQUEST_GET_DESCRIPTOR,
+            value: (kind as u16) << 8 | index as u16,
+            index: 0,
+            length,
+        }
+    }
+
+    pub const fn set_configuration(value: u8) -> Self {
+        Self {
+            request_type: 0,
+            request: REQUEST_SET_CONFIGURATION,
+            value: value as u16,
+            index: 0,
+            length: 0,
+        }
+    }
+
+    /// a class specific request without data to interface
+    pub const fn class_interface(request: u8, value: u16, interface: u8) -> Self {
+        Self {
+            request_type: REQUEST_CLASS | REQUEST_TO_INTERFACE,
+            request,
+            value,
+            index: interface as u16,
+            length: 0,
+        }
+    }
+
+    /// the direction of the data stage
+    pub fn direction(&self) -> Direction {
+        if self.request_type & REQUEST_DEVICE_TO_HOST != 0 {
+            Direction::In
+        } else {
+            Direction::Out
+        }
+    }
+
+    /// the packet as it goes over the wire, in little endian
+    pub fn to_u64(&self) -> u64 {
+        self.request_type as u64
+            | (self.request as u64) << 8
+            | (self.value as u64) << 16
+            | (self.index as u64) << 32
+            | (self.length as u64) << 48
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceDescriptor {
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+    pub max_packet_size0: u8,
+    pub vendor: u16,
+    pub product: u16,
+    pub configurations: u8,
+}
+
+impl DeviceDescriptor {
+    pub fn parse(bytes: &[u8]) -> Result<Self, UsbError> {
+        if bytes.len() < DEVICE_DESCRIPTOR_LEN || bytes[1] != DESCRIPTOR_DEVICE {
+            return Err(UsbError::BadDescriptor);
+        }
+        Ok(Self {
+            class: bytes[4],
+            subclass: bytes[5],
+            protocol: bytes[6],
+            max_packet_size0: bytes[7],
+            vendor: u16::from_le_bytes([bytes[8], bytes[9]]),
+            product: u16::from_le_bytes([bytes[10], bytes[11]]),
+            configurations: bytes[17],
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferType {
+    Control,
+    Isochronous,
+    Bulk,
+    Interrupt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EndpointDescriptor {
+    pub address: u8,
+    pub attributes: u8,
+    pub max_packet_size: u16,
+    pub interval: u8,
+}
+
+impl EndpointDescriptor {
+    pub fn number(&self) -> u8 {
+        self.address & 0xf
+    }
+
+    pub fn direction(&self) -> Direction {
+        if self.address & 1 << 7 != 0 {
+            Direction::In
+        } else {
+            Direction::Out
+        }
+    }
+
+    pub fn transfer_type(&self) -> TransferType {
+        match self.attributes & 0b11 {
+            0 => TransferType::Control,
+            1 => TransferType::Isochronous,
+            2 => TransferType::Bulk,
+            _ => TransferType::Interrupt,
+        }
+    }
+
+    /// the bytes of a single packet. High speed endpoints encode additional transactions per microframe above
+    pub fn packet_size(&self) -> u16 {
+        self.max_packet_size & 0x7ff
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interface {
+    pub number: u8,
+    pub alternate: u8,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+    pub endpoints: Vec<EndpointDescriptor>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Configuration {
+    pub value: u8,
+    pub interfaces: Vec<Interface>,
+}
+
+impl Configuration {
+    /// the length of the configuration descriptor including all descriptors following it
+    pub fn total_length(header: &[u8]) -> Result<u16, UsbError> {
+        if header.len() < CONFIGURATION_HEADER_LEN || header[1] != DESCRIPTOR_CONFIGURATION {
+            return Err(UsbError::BadDescriptor);
+        }
+        Ok(u16::from_le_bytes([header[2], header[3]]))
+    }
+
+    /// parses a configuration descriptor and the interface and endpoint descriptors following it.
+    /// Class specific descriptors are skipped
+    pub fn parse(bytes: &[u8]) -> Result<Self, UsbError> {
+        let total = Self::total_length(bytes)? as usize;
+        let bytes = bytes.get(..total).ok_or(UsbError::BadDescriptor)?;
+        let mut config = Self {
+            value: bytes[5],
+            interfaces: Vec::new(),
+        };
+        let mut rest = &bytes[bytes[0] as usize..];
+        while let [len, kind, ..] = *rest {
+            let len = len as usize;
+            if len < 2 || len > rest.len() {
+                return Err(UsbError::BadDescriptor);
+            }
+            let descriptor = &rest[..len];
+            match kind {
+                DESCRIPTOR_INTERFACE if len >= 9 => config.interfaces.push(Interface {
+                    number: descriptor[2],
+                    alternate: descriptor[3],
+                    class: descriptor[5],
+                    subclass: descriptor[6],
+                    protocol: descriptor[7],
+                    endpoints: Vec::new(),
+                }),
+                DESCRIPTOR_ENDPOINT if len >= 7 => config
+                    .interfaces
+                    .last_mut()
+                    .ok_or(UsbError::BadDescriptor)?
+                    .endpoints
+                    .push(EndpointDescriptor {
+                        address: descriptor[2],
+                        attributes: descriptor[3],
+                        max_packet_size: u16::from_le_bytes([descriptor[4], descriptor[5]]),
+                        interval: descriptor[6],
+                    }),
+                DESCRIPTOR_INTERFACE | DESCRIPTOR_ENDPOINT => return Err(UsbError::BadDescriptor),
+                _ => {}
+            }
+            rest = &rest[len..];
+        }
+        Ok(config)
+    }
+}
+
+/// consumes the reports of the interrupt in endpoint of an interface
+pub trait ReportHandler: Debug + Send {
+    fn report(&mut self, data: &[u8]);
+}
+
+/// a class driver taking over interface. Returns the requests to send to the device, before its endpoint is polled,
+/// and the handler of its reports
+pub fn bind_interface(interface: &Interface) -> Option<(Vec<SetupPacket>, Box<dyn ReportHandler>)> {
+    hid::bind(interface)
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use alloc::vec;
+
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    #[kernel_test]
+    fn configuration_descriptor() {
+        // a boot keyboard, as emulated by QEMU's usb-kbd
+        let bytes = [
+            9, 2, 34, 0, 1, 1, 0, 0xa0, 50, // configuration
+            9, 4, 0, 0, 1, 3, 1, 1, 0, // interface
+            9, 0x21, 0x11, 1, 0, 1, 0x22, 63, 0, // hid class descriptor
+            7, 5, 0x81, 3, 8, 0, 7, // endpoint
+        ];
+        assert_eq!(Configuration::total_length(&bytes[..9]), Ok(34));
+        let config = Configuration::parse(&bytes).unwrap();
+        assert_eq!(config.value, 1);
+        assert_eq!(config.interfaces.len(), 1);
+        let interface = &config.interfaces[0];
+        assert_eq!(
+            (interface.class, interface.subclass, interface.protocol),
+            (3, 1, 1)
+        );
+        let endpoint = interface.endpoints[0];
+        assert_eq!(endpoint.number(), 1);
+        assert_eq!(endpoint.direction(), Direction::In);
+        assert_eq!(endpoint.transfer_type(), TransferType::Interrupt);
+        assert_eq!((endpoint.packet_size(), endpoint.interval), (8, 7));
+
+        // truncated and malformed descriptors are rejected
+        assert_eq!(
+            Configuration::parse(&bytes[..20]),
+            Err(UsbError::BadDescriptor)
+        );
+        let mut broken = bytes;
+        broken[27] = 0;
+        assert_eq!(Configuration::parse(&broken), Err(UsbError::BadDescriptor));
+        let endpoint_first = vec![9, 2, 16, 0, 1, 1, 0, 0, 0, 7, 5, 0x81, 3, 8, 0, 7];
+        assert_eq!(
+            Configuration::parse(&endpoint_first),
+            Err(UsbError::BadDescriptor)
+        );
+
+        let setup = SetupPacket::get_descriptor(DESCRIPTOR_DEVICE, 0, 18);
+        assert_eq!(setup.direction(), Direction::In);
+        assert_eq!(setup.to_u64(), 0x0012_0000_0100_0680);
+    }
+}
@@ -0,0 +1,1033 @@
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc, vec, vec::Vec};
+use core::{
+    ptr,
+    sync::atomic::{Ordering, fence},
+    time::Duration,
+};
+
+use os_macros::init_task;
+
+use super::{
+    CONFIGURATION_HEADER_LEN,
+    Configuration,
+    DESCRIPTOR_CONFIGURATION,
+    DESCRIPTOR_DEVICE,
+    DEVICE_DESCRIPTOR_LEN,
+    DeviceDescriptor,
+    Direction,
+    EndpointDescriptor,
+    ReportHandler,
+    SetupPacket,
+    TransferType,
+    UsbError,
+    bind_interface,
+};
+use crate::{
+    drivers::{
+        dma::DmaPage,
+        model::{Device, Driver, DriverError, Match},
+        pci::{Bar, PciDevice},
+        wait_manager,
+    },
+    eprintln,
+    kernel::{
+        mem::mmio::{self, MmioRegion},
+        threading::{
+            self,
+            wait::{QueuTypeCondition, QueueType, condition::WaitCondition},
+        },
+        time::Instant,
+    },
+    serial_println,
+    sync::locks::Mutex,
+};
+
+// usb host controllers share the class, the interface tells them apart
+const CLASS_SERIAL_BUS: u8 = 0x0c;
+const SUBCLASS_USB: u8 = 0x03;
+const PROG_IF_XHCI: u8 = 0x30;
+
+// capability registers at the start of bar 0
+const CAP_LENGTH: usize = 0x00;
+const CAP_HCSPARAMS1: usize = 0x04;
+const CAP_HCSPARAMS2: usize = 0x08;
+const CAP_HCCPARAMS1: usize = 0x10;
+const CAP_DBOFF: usize = 0x14;
+const CAP_RTSOFF: usize = 0x18;
+const HCC_CONTEXT_64: u32 = 1 << 2;
+
+// operational registers, following the capability registers
+const OP_USBCMD: usize = 0x00;
+const OP_USBSTS: usize = 0x04;
+const OP_CRCR: usize = 0x18;
+const OP_DCBAAP: usize = 0x30;
+const OP_CONFIG: usize = 0x38;
+const OP_PORTSC: usize = 0x400;
+const PORT_STRIDE: usize = 0x10;
+const CMD_RUN: u32 = 1 << 0;
+const CMD_RESET: u32 = 1 << 1;
+const STS_HALTED: u32 = 1 << 0;
+const STS_NOT_READY: u32 = 1 << 11;
+
+const PORT_CONNECTED: u32 = 1 << 0;
+const PORT_ENABLED: u32 = 1 << 1;
+const PORT_RESET: u32 = 1 << 4;
+const PORT_POWER: u32 = 1 << 9;
+const PORT_SPEED_SHIFT: u32 = 10;
+const PORT_SPEED_MASK: u32 = 0xf;
+const PORT_RESET_CHANGE: u32 = 1 << 21;
+// the enabled bit and the change bits are cleared by writing 1, writes must keep them 0 to leave them alone
+const PORT_WRITE_CLEARS: u32 = PORT_ENABLED | 0x7f << 17;
+
+// the only interrupter, in the runtime registers. Its interrupts stay disabled, the event ring is polled
+const IR0: usize = 0x20;
+const IR_ERSTSZ: usize = 0x08;
+const IR_ERSTBA: usize = 0x10;
+const IR_ERDP: usize = 0x18;
+const ERDP_BUSY: u64 = 1 << 3;
+
+const SPEED_FULL: u8 = 1;
+const SPEED_LOW: u8 = 2;
+const SPEED_HIGH: u8 = 3;
+
+// transfer request blocks
+const TRB_NORMAL: u32 = 1;
+const TRB_SETUP: u32 = 2;
+const TRB_DATA: u32 = 3;
+const TRB_STATUS: u32 = 4;
+const TRB_LINK: u32 = 6;
+const TRB_ENABLE_SLOT: u32 = 9;
+const TRB_DISABLE_SLOT: u32 = 10;
+const TRB_ADDRESS_DEVICE: u32 = 11;
+const TRB_CONFIGURE_ENDPOINT: u32 = 12;
+const TRB_EVALUATE_CONTEXT: u32 = 13;
+const TRB_TRANSFER_EVENT: u32 = 32;
+const TRB_COMMAND_COMPLETION: u32 = 33;
+const TRB_PORT_STATUS_CHANGE: u32 = 34;
+const TRB_TYPE_SHIFT: u32 = 10;
+const TRB_CYCLE: u32 = 1 << 0;
+const LINK_TOGGLE_CYCLE: u32 = 1 << 1;
+const TRB_SHORT_PACKET_OK: u32 = 1 << 2;
+const TRB_INTERRUPT_ON_COMPLETION: u32 = 1 << 5;
+const TRB_IMMEDIATE_DATA: u32 = 1 << 6;
+const TRB_DIRECTION_IN: u32 = 1 << 16;
+const SETUP_NO_DATA: u32 = 0;
+const SETUP_OUT_DATA: u32 = 2 << 16;
+const SETUP_IN_DATA: u32 = 3 << 16;
+const COMPLETION_SUCCESS: u8 = 1;
+const COMPLETION_SHORT_PACKET: u8 = 13;
+
+// every ring fills exactly one page, the last trb links back to the first
+const RING_TRBS: usize = 256;
+
+// endpoint context types
+const EP_CONTROL: u32 = 4;
+const EP_ERROR_RETRIES: u32 = 3;
+const DCI_CONTROL: u8 = 1;
+// the most device slots used, independent of what the controller supports
+const MAX_SLOTS: u32 = 32;
+
+const TIMEOUT_POLLS: usize = 10_000_000;
+const POLL_INTERVAL: Duration = Duration::from_millis(8);
+
+static CONTROLLERS: Mutex<Vec<Xhci>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+struct Trb {
+    parameter: u64,
+    status: u32,
+    control: u32,
+}
+
+impl Trb {
+    const fn new(kind: u32, parameter: u64, status: u32, flags: u32) -> Self {
+        Self {
+            parameter,
+            status,
+            control: kind << TRB_TYPE_SHIFT | flags,
+        }
+    }
+
+    fn kind(&self) -> u32 {
+        (self.control >> TRB_TYPE_SHIFT) & 0x3f
+    }
+
+    fn completion_code(&self) -> u8 {
+        (self.status >> 24) as u8
+    }
+
+    fn slot_id(&self) -> u8 {
+        (self.control >> 24) as u8
+    }
+
+    /// the endpoint of a transfer event
+    fn endpoint(&self) -> u8 {
+        ((self.control >> 16) & 0x1f) as u8
+    }
+
+    /// the bytes not transferred of a transfer event
+    fn residual(&self) -> usize {
+        (self.status & 0xff_ffff) as usize
+    }
+}
+
+/// a ring of trbs in a single page, produced by the driver. Only a handful of trbs are ever in flight, as all
+/// transfers but the interrupt ones are waited for, thus the ring never catches up with the controller
+#[derive(Debug)]
+struct Ring {
+    page: DmaPage,
+    enqueue: usize,
+    cycle: bool,
+}
+
+impl Ring {
+    fn new() -> Result<Self, UsbError> {
+        Ok(Self {
+            page: DmaPage::new().ok_or(UsbError::NoMemory)?,
+            enqueue: 0,
+            cycle: true,
+        })
+    }
+
+    fn phys_addr(&self) -> u64 {
+        self.page.phys_addr()
+    }
+
+    /// places trb at the enqueue pointer and returns its physical address
+    fn push(&mut self, trb: Trb) -> u64 {
+        let addr = self.write(self.enqueue, trb);
+        self.enqueue += 1;
+        if self.enqueue == RING_TRBS - 1 {
+            // the link is handed over with the current cycle, the controller toggles its cycle when following it
+            self.write(
+                self.enqueue,
+                Trb::new(TRB_LINK, self.phys_addr(), 0, LINK_TOGGLE_CYCLE),
+            );
+            self.enqueue = 0;
+            self.cycle = !self.cycle;
+        }
+        addr
+    }
+
+    fn write(&self, idx: usize, trb: Trb) -> u64 {
+        let control = (trb.control & !TRB_CYCLE) | self.cycle as u32;
+        unsafe {
+            let slot = self.page.as_mut_ptr().cast::<Trb>().add(idx);
+            ptr::write_volatile(&raw mut (*slot).parameter, trb.parameter);
+            ptr::write_volatile(&raw mut (*slot).status, trb.status);
+            // the controller owns the trb, as soon as the cycle bit matches
+            fence(Ordering::Release);
+            ptr::write_volatile(&raw mut (*slot).control, control);
+        }
+        self.phys_addr() + (idx * size_of::<Trb>()) as u64
+    }
+}
+
+/// the ring of events, produced by the controller, and its single segment table
+#[derive(Debug)]
+struct EventRing {
+    page: DmaPage,
+    table: DmaPage,
+    dequeue: usize,
+    cycle: bool,
+}
+
+impl EventRing {
+    fn new() -> Result<Self, UsbError> {
+        let ring = Self {
+            page: DmaPage::new().ok_or(UsbError::NoMemory)?,
+            table: DmaPage::new().ok_or(UsbError::NoMemory)?,
+            dequeue: 0,
+            cycle: true,
+        };
+        unsafe {
+            let entry = ring.table.as_mut_ptr();
+            ptr::write_volatile(entry.cast::<u64>(), ring.page.phys_addr());
+            ptr::write_volatile(entry.add(8).cast::<u32>(), RING_TRBS as u32);
+        }
+        Ok(ring)
+    }
+
+    fn pop(&mut self) -> Option<Trb> {
+        let slot = unsafe { self.page.as_mut_ptr().cast::<Trb>().add(self.dequeue) };
+        let control = unsafe { ptr::read_volatile(&raw const (*slot).control) };
+        if (control & TRB_CYCLE != 0) != self.cycle {
+            return None;
+        }
+        fence(Ordering::Acquire);
+        let trb = unsafe { ptr::read_volatile(slot) };
+        self.dequeue += 1;
+        if self.dequeue == RING_TRBS {
+            self.dequeue = 0;
+            self.cycle = !self.cycle;
+        }
+        Some(trb)
+    }
+
+    fn dequeue_addr(&self) -> u64 {
+        self.page.phys_addr() + (self.dequeue * size_of::<Trb>()) as u64
+    }
+}
+
+/// the mapped register space of a controller
+#[derive(Debug)]
+struct Registers {
+    region: MmioRegion,
+    operational: usize,
+    runtime: usize,
+    doorbells: usize,
+}
+
+impl Registers {
+    fn read(&self, offset: usize) -> u32 {
+        self.region.read(offset)
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        self.region.write(offset, value);
+    }
+
+    // 64 bit registers are written as two halves, the low one first
+    fn write64(&self, offset: usize, value: u64) {
+        self.write(offset, value as u32);
+        self.write(offset + 4, (value >> 32) as u32);
+    }
+
+    fn op(&self, offset: usize) -> usize {
+        self.operational + offset
+    }
+
+    fn port(&self, port: u8) -> usize {
+        self.op(OP_PORTSC + (port as usize - 1) * PORT_STRIDE)
+    }
+
+    /// writes the port status, without clearing any change bit except those in set
+    fn write_port(&self, port: u8, set: u32) {
+        let status = self.read(self.port(port)) & !PORT_WRITE_CLEARS;
+        self.write(self.port(port), status | set);
+    }
+
+    fn ring_doorbell(&self, slot: u8, target: u8) {
+        self.write(self.doorbells + 4 * slot as usize, target as u32);
+    }
+
+    fn wait_until(&self, done: impl Fn(&Self) -> bool) -> Result<(), UsbError> {
+        for _ in 0..TIMEOUT_POLLS {
+            if done(self) {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+        Err(UsbError::TimedOut)
+    }
+}
+
+/// an endpoint besides the default control one
+#[derive(Debug)]
+struct Endpoint {
+    dci: u8,
+    descriptor: EndpointDescriptor,
+    ring: Ring,
+    buffer: DmaPage,
+    // consumes the reports of interrupt in endpoints, which are polled continuously
+    handler: Option<Box<dyn ReportHandler>>,
+}
+
+/// an addressed device on a root hub port
+#[derive(Debug)]
+struct Slot {
+    id: u8,
+    port: u8,
+    speed: u8,
+    output: DmaPage,
+    input: DmaPage,
+    control: Ring,
+    buffer: DmaPage,
+    endpoints: Vec<Endpoint>,
+}
+
+impl Slot {
+    fn endpoint(&mut self, dci: u8) -> Option<&mut Endpoint> {
+        self.endpoints
+            .iter_mut()
+            .find(|endpoint| endpoint.dci == dci)
+    }
+}
+
+/// an xhci controller. It is polled, its interrupts stay disabled. Hubs are not supported,
+/// only devices on the root hub ports are enumerated
+#[derive(Debug)]
+pub struct Xhci {
+    pci: PciDevice,
+    regs: Registers,
+    ports: u8,
+    context_size: usize,
+    dcbaa: DmaPage,
+    _scratchpads: Vec<DmaPage>,
+    commands: Ring,
+    events: EventRing,
+    // events, which arrived while waiting for another one
+    stashed: VecDeque<Trb>,
+    slots: Vec<Slot>,
+}
+
+impl Xhci {
+    pub fn new(pci: PciDevice) -> Result<Self, UsbError> {
+        let Some(Bar::Memory { addr, .. }) = pci.bar(0) else {
+            return Err(UsbError::NoBar);
+        };
+        pci.enable_bus_master();
+        let caps = mmio::ioremap(addr, CAP_RTSOFF + 4)?;
+        let read_cap = |offset| caps.read::<u32>(offset);
+        let operational = (read_cap(CAP_LENGTH) & 0xff) as usize;
+        let params1 = read_cap(CAP_HCSPARAMS1);
+        let params2 = read_cap(CAP_HCSPARAMS2);
+        let slots = (params1 & 0xff).min(MAX_SLOTS);
+        let ports = (params1 >> 24) as u8;
+        let doorbells = (read_cap(CAP_DBOFF) & !0b11) as usize;
+        let runtime = (read_cap(CAP_RTSOFF) & !0x1f) as usize;
+        let context_size = if read_cap(CAP_HCCPARAMS1) & HCC_CONTEXT_64 != 0 {
+            64
+        } else {
+            32
+        };
+        let len = (operational + OP_PORTSC + ports as usize * PORT_STRIDE)
+            .max(runtime + IR0 + 0x20)
+            .max(doorbells + 4 * (slots as usize + 1));
+        // the capabilities are part of the full register space, their mapping is given up first
+        drop(caps);
+        let regs = Registers {
+            region: mmio::ioremap(addr, len)?,
+            operational,
+            runtime,
+            doorbells,
+        };
+
+        // halt and reset the controller, whatever the firmware left running
+        regs.write(regs.op(OP_USBCMD), regs.read(regs.op(OP_USBCMD)) & !CMD_RUN);
+        regs.wait_until(|regs| regs.read(regs.op(OP_USBSTS)) & STS_HALTED != 0)?;
+        regs.write(regs.op(OP_USBCMD), CMD_RESET);
+        regs.wait_until(|regs| {
+            regs.read(regs.op(OP_USBCMD)) & CMD_RESET == 0
+                && regs.read(regs.op(OP_USBSTS)) & STS_NOT_READY == 0
+        })?;
+        regs.write(regs.op(OP_CONFIG), slots);
+
+        let dcbaa = DmaPage::new().ok_or(UsbError::NoMemory)?;
+        let scratchpad_count = ((params2 >> 27) & 0x1f) | ((params2 >> 21) & 0x1f) << 5;
+        let mut scratchpads = Vec::new();
+        if scratchpad_count > 0 {
+            // the first entry of the device context base addresses points to the array of scratchpad pages
+            let array = DmaPage::new().ok_or(UsbError::NoMemory)?;
+            for idx in 0..scratchpad_count as usize {
+                let page = DmaPage::new().ok_or(UsbError::NoMemory)?;
+                unsafe {
+                    ptr::write_volatile(array.as_mut_ptr().cast::<u64>().add(idx), page.phys_addr())
+                };
+                scratchpads.push(page);
+            }
+            unsafe { ptr::write_volatile(dcbaa.as_mut_ptr().cast::<u64>(), array.phys_addr()) };
+            scratchpads.push(array);
+        }
+        regs.write64(regs.op(OP_DCBAAP), dcbaa.phys_addr());
+
+        let commands = Ring::new()?;
+        regs.write64(regs.op(OP_CRCR), commands.phys_addr() | TRB_CYCLE as u64);
+        let events = EventRing::new()?;
+        regs.write(regs.runtime + IR0 + IR_ERSTSZ, 1);
+        regs.write64(regs.runtime + IR0 + IR_ERDP, events.page.phys_addr());
+        regs.write64(regs.runtime + IR0 + IR_ERSTBA, events.table.phys_addr());
+
+        regs.write(regs.op(OP_USBCMD), CMD_RUN);
+        regs.wait_until(|regs| regs.read(regs.op(OP_USBSTS)) & STS_HALTED == 0)?;
+
+        let mut xhci = Self {
+            pci,
+            regs,
+            ports,
+            context_size,
+            dcbaa,
+            _scratchpads: scratchpads,
+            commands,
+            events,
+            stashed: VecDeque::new(),
+            slots: Vec::new(),
+        };
+        for port in 1..=ports {
+            xhci.update_port(port);
+        }
+        Ok(xhci)
+    }
+
+    pub fn pci(&self) -> &PciDevice {
+        &self.pci
+    }
+
+    /// the number of addressed devices
+    pub fn devices(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// dispatches all pending events: reports of interrupt endpoints are handed to their handlers
+    /// and devices are enumerated or dropped, as they come and go
+    pub fn poll(&mut self) {
+        while let Some(event) = self.stashed.pop_front().or_else(|| self.next_event()) {
+            match event.kind() {
+                TRB_TRANSFER_EVENT => self.complete_report(&event),
+                TRB_PORT_STATUS_CHANGE => self.update_port((event.parameter >> 24) as u8),
+                _ => {}
+            }
+        }
+    }
+
+    fn next_event(&mut self) -> Option<Trb> {
+        let event = self.events.pop()?;
+        self.regs.write64(
+            self.regs.runtime + IR0 + IR_ERDP,
+            self.events.dequeue_addr() | ERDP_BUSY,
+        );
+        Some(event)
+    }
+
+    /// polls for an event matching found, stashing all others
+    fn wait_event(&mut self, found: impl Fn(&Trb) -> bool) -> Result<Trb, UsbError> {
+        for _ in 0..TIMEOUT_POLLS {
+            match self.next_event() {
+                Some(event) if found(&event) => return Ok(event),
+                Some(event) => self.stashed.push_back(event),
+                None => core::hint::spin_loop(),
+            }
+        }
+        Err(UsbError::TimedOut)
+    }
+
+    fn command(&mut self, trb: Trb) -> Result<Trb, UsbError> {
+        let addr = self.commands.push(trb);
+        self.regs.ring_doorbell(0, 0);
+        let event = self.wait_event(|event| {
+            event.kind() == TRB_COMMAND_COMPLETION && event.parameter == addr
+        })?;
+        match event.completion_code() {
+            COMPLETION_SUCCESS => Ok(event),
+            code => Err(UsbError::Command(code)),
+        }
+    }
+
+    fn wait_transfer(&mut self, slot: u8, dci: u8) -> Result<Trb, UsbError> {
+        let event = self.wait_event(|event| {
+            event.kind() == TRB_TRANSFER_EVENT && event.slot_id() == slot && event.endpoint() == dci
+        })?;
+        match event.completion_code() {
+            COMPLETION_SUCCESS | COMPLETION_SHORT_PACKET => Ok(event),
+            code => Err(UsbError::Transfer(code)),
+        }
+    }
+
+    fn slot_idx(&self, id: u8) -> Result<usize, UsbError> {
+        self.slots
+            .iter()
+            .position(|slot| slot.id == id)
+            .ok_or(UsbError::Disconnected)
+    }
+
+    /// a control transfer on the default endpoint of slot. The data stage uses data, if the request has one
+    pub fn control(
+        &mut self,
+        slot: u8,
+        setup: SetupPacket,
+        data: &mut [u8],
+    ) -> Result<(), UsbError> {
+        let idx = self.slot_idx(slot)?;
+        let len = (setup.length as usize).min(data.len());
+        let device = &mut self.slots[idx];
+        if len > device.buffer.len() {
+            return Err(UsbError::TooLarge);
+        }
+        let (transfer_type, status_direction) = match (len, setup.direction()) {
+            (0, _) => (SETUP_NO_DATA, TRB_DIRECTION_IN),
+            (_, Direction::In) => (SETUP_IN_DATA, 0),
+            (_, Direction::Out) => (SETUP_OUT_DATA, TRB_DIRECTION_IN),
+        };
+        device.control.push(Trb::new(
+            TRB_SETUP,
+            setup.to_u64(),
+            8,
+            TRB_IMMEDIATE_DATA | transfer_type,
+        ));
+        if len > 0 {
+            let direction = if setup.direction() == Direction::In {
+                TRB_DIRECTION_IN
+            } else {
+                unsafe {
+                    device
+                        .buffer
+                        .as_mut_ptr()
+                        .copy_from_nonoverlapping(data.as_ptr(), len)
+                };
+                0
+            };
+            device.control.push(Trb::new(
+                TRB_DATA,
+                device.buffer.phys_addr(),
+                len as u32,
+                direction,
+            ));
+        }
+        device.control.push(Trb::new(
+            TRB_STATUS,
+            0,
+            0,
+            TRB_INTERRUPT_ON_COMPLETION | status_direction,
+        ));
+        self.regs.ring_doorbell(slot, DCI_CONTROL);
+        self.wait_transfer(slot, DCI_CONTROL)?;
+        if len > 0 && setup.direction() == Direction::In {
+            let device = &self.slots[idx];
+            unsafe {
+                data.as_mut_ptr()
+                    .copy_from_nonoverlapping(device.buffer.as_mut_ptr(), len)
+            };
+        }
+        Ok(())
+    }
+
+    /// a bulk transfer on the endpoint with address. Returns the number of bytes transferred
+    pub fn bulk(&mut self, slot: u8, address: u8, data: &mut [u8]) -> Result<usize, UsbError> {
+        let idx = self.slot_idx(slot)?;
+        let endpoint = self.slots[idx]
+            .endpoints
+            .iter_mut()
+            .find(|endpoint| {
+                endpoint.descriptor.address == address
+                    && endpoint.descriptor.transfer_type() == TransferType::Bulk
+            })
+            .ok_or(UsbError::Disconnected)?;
+        if data.len() > endpoint.buffer.len() {
+            return Err(UsbError::TooLarge);
+        }
+        let direction = endpoint.descriptor.direction();
+        if direction == Direction::Out {
+            unsafe {
+                endpoint
+                    .buffer
+                    .as_mut_ptr()
+                    .copy_from_nonoverlapping(data.as_ptr(), data.len())
+            };
+        }
+        endpoint.ring.push(Trb::new(
+            TRB_NORMAL,
+            endpoint.buffer.phys_addr(),
+            data.len() as u32,
+            TRB_INTERRUPT_ON_COMPLETION | TRB_SHORT_PACKET_OK,
+        ));
+        let dci = endpoint.dci;
+        self.regs.ring_doorbell(slot, dci);
+        let event = self.wait_transfer(slot, dci)?;
+        let transferred = data.len().saturating_sub(event.residual());
+        if direction == Direction::In {
+            let endpoint = self.slots[idx]
+                .endpoint(dci)
+                .ok_or(UsbError::Disconnected)?;
+            unsafe {
+                data.as_mut_ptr()
+                    .copy_from_nonoverlapping(endpoint.buffer.as_mut_ptr(), transferred)
+            };
+        }
+        Ok(transferred)
+    }
+
+    /// queues a transfer for the next report of an interrupt in endpoint
+    fn queue_report(&mut self, slot: u8, dci: u8) {
+        let Some(endpoint) = self
+            .slots
+            .iter_mut()
+            .find(|device| device.id == slot)
+            .and_then(|device| device.endpoint(dci))
+        else {
+            return;
+        };
+        endpoint.ring.push(Trb::new(
+            TRB_NORMAL,
+            endpoint.buffer.phys_addr(),
+            endpoint.descriptor.packet_size() as u32,
+            TRB_INTERRUPT_ON_COMPLETION | TRB_SHORT_PACKET_OK,
+        ));
+        self.regs.ring_doorbell(slot, dci);
+    }
+
+    fn complete_report(&mut self, event: &Trb) {
+        let (slot, dci) = (event.slot_id(), event.endpoint());
+        let Some(endpoint) = self
+            .slots
+            .iter_mut()
+            .find(|device| device.id == slot)
+            .and_then(|device| device.endpoint(dci))
+        else {
+            return;
+        };
+        let Some(handler) = endpoint.handler.as_mut() else {
+            return;
+        };
+        if matches!(
+            event.completion_code(),
+            COMPLETION_SUCCESS | COMPLETION_SHORT_PACKET
+        ) {
+            let len = (endpoint.descriptor.packet_size() as usize).saturating_sub(event.residual());
+            // SAFETY: the controller is done with the buffer, until the next transfer is queued
+            let report = unsafe { core::slice::from_raw_parts(endpoint.buffer.as_mut_ptr(), len) };
+            handler.report(report);
+        }
+        self.queue_report(slot, dci);
+    }
+
+    /// enumerates a device, which was connected to port, or drops the one, which was disconnected
+    fn update_port(&mut self, port: u8) {
+        if port == 0 || port > self.ports {
+            return;
+        }
+        let status = self.regs.read(self.regs.port(port));
+        // acknowledges all changes
+        self.regs
+            .write_port(port, status & PORT_WRITE_CLEARS & !PORT_ENABLED);
+        let known = self.slots.iter().position(|slot| slot.port == port);
+        match (status & PORT_CONNECTED != 0, known) {
+            (true, None) => {
+                if let Err(e) = self.enumerate(port) {
+                    eprintln!("xhci {}: port {}: {}", self.pci.addr, port, e);
+                }
+            }
+            (false, Some(idx)) => {
+                // the memory of the slot is freed, once it is disabled
+                let slot = self.slots.remove(idx);
+                _ = self.command(Trb::new(TRB_DISABLE_SLOT, 0, 0, (slot.id as u32) << 24));
+                unsafe {
+                    ptr::write_volatile(
+                        self.dcbaa.as_mut_ptr().cast::<u64>().add(slot.id as usize),
+                        0,
+                    )
+                };
+                serial_println!("xhci {}: device on port {} removed", self.pci.addr, port);
+            }
+            _ => {}
+        }
+    }
+
+    fn reset_port(&self, port: u8) -> Result<(), UsbError> {
+        let status = self.regs.read(self.regs.port(port));
+        if status & PORT_POWER == 0 {
+            self.regs.write_port(port, PORT_POWER);
+        }
+        // usb 3 ports are enabled right on connection, usb 2 ports need a reset
+        if status & PORT_ENABLED != 0 {
+            return Ok(());
+        }
+        self.regs.write_port(port, PORT_RESET);
+        self.regs
+            .wait_until(|regs| regs.read(regs.port(port)) & PORT_RESET_CHANGE != 0)?;
+        self.regs.write_port(port, PORT_RESET_CHANGE);
+        if self.regs.read(self.regs.port(port)) & PORT_ENABLED == 0 {
+            return Err(UsbError::Disconnected);
+        }
+        Ok(())
+    }
+
+    fn context(&self, page: &DmaPage, idx: usize) -> *mut u32 {
+        unsafe { page.as_mut_ptr().add(idx * self.context_size).cast() }
+    }
+
+    fn write_context(&self, page: &DmaPage, idx: usize, dwords: &[u32]) {
+        let context = self.context(page, idx);
+        for (i, dword) in dwords.iter().enumerate() {
+            unsafe { ptr::write_volatile(context.add(i), *dword) };
+        }
+    }
+
+    /// fills the input context with the add flags, the slot context and the context of the default endpoint
+    fn prepare_input(&self, device: &Slot, add: u32, entries: u32, max_packet: u16) {
+        unsafe { ptr::write_bytes(device.input.as_mut_ptr(), 0, device.input.len()) };
+        // the input control context precedes the device contexts
+        self.write_context(&device.input, 0, &[0, add]);
+        self.write_context(
+            &device.input,
+            1,
+            &[
+                (device.speed as u32) << 20 | entries << 27,
+                (device.port as u32) << 16,
+            ],
+        );
+        let ring = device.control.phys_addr() | device.control.cycle as u64;
+        self.write_context(
+            &device.input,
+            1 + DCI_CONTROL as usize,
+            &[
+                0,
+                EP_ERROR_RETRIES << 1 | EP_CONTROL << 3 | (max_packet as u32) << 16,
+                ring as u32,
+                (ring >> 32) as u32,
+                8,
+            ],
+        );
+    }
+
+    fn enumerate(&mut self, port: u8) -> Result<(), UsbError> {
+        self.reset_port(port)?;
+        let speed =
+            ((self.regs.read(self.regs.port(port)) >> PORT_SPEED_SHIFT) & PORT_SPEED_MASK) as u8;
+        let id = self.command(Trb::new(TRB_ENABLE_SLOT, 0, 0, 0))?.slot_id();
+        let device = Slot {
+            id,
+            port,
+            speed,
+            output: DmaPage::new().ok_or(UsbError::NoMemory)?,
+            input: DmaPage::new().ok_or(UsbError::NoMemory)?,
+            control: Ring::new()?,
+            buffer: DmaPage::new().ok_or(UsbError::NoMemory)?,
+            endpoints: Vec::new(),
+        };
+        unsafe {
+            ptr::write_volatile(
+                self.dcbaa.as_mut_ptr().cast::<u64>().add(id as usize),
+                device.output.phys_addr(),
+            )
+        };
+        // the real packet size of the default endpoint is only known from the device descriptor
+        let max_packet = match speed {
+            SPEED_LOW | SPEED_FULL => 8,
+            SPEED_HIGH => 64,
+            _ => 512,
+        };
+        self.prepare_input(&device, 0b11, 1, max_packet);
+        let input = device.input.phys_addr();
+        self.slots.push(device);
+        let result = self.configure(id, input, max_packet);
+        if result.is_err() {
+            // the slot is given back, the device stays unusable until it is plugged in again.
+            // Its memory is only freed, once the controller let go of it
+            let slot = self.slots.remove(self.slot_idx(id)?);
+            _ = self.command(Trb::new(TRB_DISABLE_SLOT, 0, 0, (id as u32) << 24));
+            drop(slot);
+        }
+        result
+    }
+
+    /// addresses the device in slot id, selects its first configuration and binds its interfaces
+    fn configure(&mut self, id: u8, input: u64, max_packet: u16) -> Result<(), UsbError> {
+        self.command(Trb::new(TRB_ADDRESS_DEVICE, input, 0, (id as u32) << 24))?;
+
+        let mut bytes = [0; DEVICE_DESCRIPTOR_LEN];
+        self.control(
+            id,
+            SetupPacket::get_descriptor(DESCRIPTOR_DEVICE, 0, 8),
+            &mut bytes,
+        )?;
+        if bytes[7] as u16 != max_packet && bytes[7] != 0 {
+            let device = &self.slots[self.slot_idx(id)?];
+            self.prepare_input(device, 0b10, 1, bytes[7] as u16);
+            self.command(Trb::new(TRB_EVALUATE_CONTEXT, input, 0, (id as u32) << 24))?;
+        }
+        self.control(
+            id,
+            SetupPacket::get_descriptor(DESCRIPTOR_DEVICE, 0, DEVICE_DESCRIPTOR_LEN as u16),
+            &mut bytes,
+        )?;
+        let descriptor = DeviceDescriptor::parse(&bytes)?;
+
+        let mut header = [0; CONFIGURATION_HEADER_LEN];
+        self.control(
+            id,
+            SetupPacket::get_descriptor(DESCRIPTOR_CONFIGURATION, 0, header.len() as u16),
+            &mut header,
+        )?;
+        let mut bytes = vec![0; Configuration::total_length(&header)? as usize];
+        self.control(
+            id,
+            SetupPacket::get_descriptor(DESCRIPTOR_CONFIGURATION, 0, bytes.len() as u16),
+            &mut bytes,
+        )?;
+        let config = Configuration::parse(&bytes)?;
+        self.control(id, SetupPacket::set_configuration(config.value), &mut [])?;
+        serial_println!(
+            "xhci {}: {:04x}:{:04x} on port {}",
+            self.pci.addr,
+            descriptor.vendor,
+            descriptor.product,
+            self.slots[self.slot_idx(id)?].port
+        );
+
+        for interface in config
+            .interfaces
+            .iter()
+            .filter(|interface| interface.alternate == 0)
+        {
+            let Some((requests, handler)) = bind_interface(interface) else {
+                continue;
+            };
+            for request in requests {
+                self.control(id, request, &mut [])?;
+            }
+            let Some(report) = interface.endpoints.iter().find(|endpoint| {
+                endpoint.transfer_type() == TransferType::Interrupt
+                    && endpoint.direction() == Direction::In
+            }) else {
+                continue;
+            };
+            let dci = self.add_endpoint(id, *report, Some(handler))?;
+            self.queue_report(id, dci);
+        }
+        Ok(())
+    }
+
+    /// configures an endpoint of the device in slot id and returns its device context index
+    fn add_endpoint(
+        &mut self,
+        id: u8,
+        descriptor: EndpointDescriptor,
+        handler: Option<Box<dyn ReportHandler>>,
+    ) -> Result<u8, UsbError> {
+        let dci = descriptor.number() * 2 + (descriptor.direction() == Direction::In) as u8;
+        let endpoint = Endpoint {
+            dci,
+            descriptor,
+            ring: Ring::new()?,
+            buffer: DmaPage::new().ok_or(UsbError::NoMemory)?,
+            handler,
+        };
+        let idx = self.slot_idx(id)?;
+        let device = &self.slots[idx];
+        let entries = device
+            .endpoints
+            .iter()
+            .map(|endpoint| endpoint.dci)
+            .chain([dci])
+            .max()
+            .unwrap_or(dci) as u32;
+        let ep_type = match (descriptor.transfer_type(), descriptor.direction()) {
+            (TransferType::Isochronous, Direction::Out) => 1,
+            (TransferType::Bulk, Direction::Out) => 2,
+            (TransferType::Interrupt, Direction::Out) => 3,
+            (TransferType::Control, _) => EP_CONTROL,
+            (TransferType::Isochronous, Direction::In) => 5,
+            (TransferType::Bulk, Direction::In) => 6,
+            (TransferType::Interrupt, Direction::In) => 7,
+        };
+        // intervals are given as exponents of 125us. Low and full speed devices specify theirs in frames of 1ms
+        let interval = match (device.speed, descriptor.transfer_type()) {
+            (_, TransferType::Bulk | TransferType::Control) => 0,
+            (SPEED_LOW | SPEED_FULL, TransferType::Interrupt) => {
+                (descriptor.interval.max(1) as u32 * 8).ilog2().clamp(3, 10)
+            }
+            _ => (descriptor.interval.clamp(1, 16) - 1) as u32,
+        };
+        let packet = descriptor.packet_size() as u32;
+        let max_packet = self.slot_max_packet(device);
+        self.prepare_input(device, 1 | 1 << dci, entries, max_packet);
+        let ring = endpoint.ring.phys_addr() | TRB_CYCLE as u64;
+        self.write_context(
+            &device.input,
+            1 + dci as usize,
+            &[
+                interval << 16,
+                EP_ERROR_RETRIES << 1 | ep_type << 3 | packet << 16,
+                ring as u32,
+                (ring >> 32) as u32,
+                packet | packet << 16,
+            ],
+        );
+        let input = device.input.phys_addr();
+        self.command(Trb::new(
+            TRB_CONFIGURE_ENDPOINT,
+            input,
+            0,
+            (id as u32) << 24,
+        ))?;
+        self.slots[idx].endpoints.push(endpoint);
+        Ok(dci)
+    }
+
+    /// the packet size of the default endpoint, as the controller knows it from the output context
+    fn slot_max_packet(&self, device: &Slot) -> u16 {
+        let dword = unsafe {
+            ptr::read_volatile(self.context(&device.output, DCI_CONTROL as usize).add(1))
+        };
+        (dword >> 16) as u16
+    }
+}
+
+impl Drop for Xhci {
+    fn drop(&mut self) {
+        // the controller must stop using the rings, before their pages are freed
+        self.regs.write(
+            self.regs.op(OP_USBCMD),
+            self.regs.read(self.regs.op(OP_USBCMD)) & !CMD_RUN,
+        );
+        _ = self
+            .regs
+            .wait_until(|regs| regs.read(regs.op(OP_USBSTS)) & STS_HALTED != 0);
+    }
+}
+
+pub static DRIVER: XhciDriver = XhciDriver;
+
+/// sets up xhci controllers and enumerates the devices on their root hubs
+#[derive(Debug)]
+pub struct XhciDriver;
+
+impl Driver for XhciDriver {
+    fn name(&self) -> &'static str {
+        "xhci"
+    }
+
+    fn match_table(&self) -> &'static [Match] {
+        &[Match::PciClass {
+            class: CLASS_SERIAL_BUS,
+            subclass: SUBCLASS_USB,
+        }]
+    }
+
+    fn probe(&self, device: &Arc<dyn Device>) -> Result<(), DriverError> {
+        let pci = device
+            .pci()
+            .ok_or(DriverError::NoDevice("not a pci device"))?;
+        if pci.prog_if != PROG_IF_XHCI {
+            return Err(DriverError::NoDevice("not an xhci controller"));
+        }
+        let xhci = Xhci::new(*pci).map_err(DriverError::failed)?;
+        serial_println!(
+            "xhci at {}: {} ports, {} devices",
+            pci.addr,
+            xhci.ports,
+            xhci.devices()
+        );
+        CONTROLLERS.lock().push(xhci);
+        Ok(())
+    }
+
+    fn remove(&self, device: &Arc<dyn Device>) {
+        let Some(pci) = device.pci() else {
+            return;
+        };
+        CONTROLLERS
+            .lock()
+            .retain(|xhci| xhci.pci().addr != pci.addr);
+    }
+}
+
+/// polls all controllers for reports and devices coming and going
+#[init_task(stage = "drivers", order = 50)]
+fn start_polling() {
+    if CONTROLLERS.lock().is_empty() {
+        return;
+    }
+    _ = threading::spawn(|| {
+        loop {
+            for xhci in CONTROLLERS.lock().iter_mut() {
+                xhci.poll();
+            }
+            wait_manager::wait_self(&[QueuTypeCondition::with_cond(
+                QueueType::Timer,
+                WaitCondition::Time(Instant::now() + POLL_INTERVAL),
+            )]);
+        }
+    });
+}
@@ -0,0 +1,302 @@
+use alloc::vec::Vec;
+use core::{
+    ptr,
+    sync::atomic::{Ordering, fence},
+};
+
+use bitflags::bitflags;
+use thiserror::Error;
+use x86_64::instructions::port::Port;
+
+use super::{
+    dma::DmaBuffer,
+    pci::{Bar, PciDevice},
+};
+
+pub mod rng;
+
+pub const VENDOR: u16 = 0x1af4;
+
+// the legacy virtio-pci register layout in bar 0, without msi-x
+const REG_DEVICE_FEATURES: u16 = 0x00;
+const REG_GUEST_FEATURES: u16 = 0x04;
+const REG_QUEUE_PFN: u16 = 0x08;
+const REG_QUEUE_SIZE: u16 = 0x0c;
+const REG_QUEUE_SELECT: u16 = 0x0e;
+const REG_QUEUE_NOTIFY: u16 = 0x10;
+const REG_STATUS: u16 = 0x12;
+const REG_ISR: u16 = 0x13;
+
+// legacy queues are laid out with a fixed alignment of the used ring
+const QUEUE_ALIGN: usize = 4096;
+const DESC_F_WRITE: u16 = 1 << 1;
+
+// completions are polled, as the devices are not wired to interrupts
+const TIMEOUT_POLLS: usize = 10_000_000;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Status: u8 {
+        const ACKNOWLEDGE = 1 << 0;
+        const DRIVER = 1 << 1;
+        const DRIVER_OK = 1 << 2;
+        const FEATURES_OK = 1 << 3;
+        const FAILED = 1 << 7;
+    }
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VirtioError {
+    #[error("the device has no legacy io bar")]
+    NoIoBar,
+    #[error("the queue does not exist")]
+    NoQueue,
+    #[error("the queue does not fit into the memory provided")]
+    QueueTooLarge,
+    #[error("the memory is not physically contiguous")]
+    NotContiguous,
+    #[error("all descriptors are in use")]
+    QueueFull,
+    #[error("the device did not respond in time")]
+    TimedOut,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+/// bytes needed by a legacy split queue of size entries
+pub const fn queue_bytes(size: usize) -> usize {
+    let driver = size * size_of::<Descriptor>() + (3 + size) * size_of::<u16>();
+    let device = 3 * size_of::<u16>() + size * size_of::<UsedElem>();
+    driver.next_multiple_of(QUEUE_ALIGN) + device.next_multiple_of(QUEUE_ALIGN)
+}
+
+/// a split virtqueue. Each buffer is a chain of a single descriptor
+#[derive(Debug)]
+pub struct VirtQueue {
+    index: u16,
+    size: u16,
+    desc: *mut Descriptor,
+    // flags, idx, ring[size], used_event
+    avail: *mut u16,
+    // flags, idx, then ring[size] at used_ring
+    used: *mut u16,
+    used_ring: *mut UsedElem,
+    free: Vec<u16>,
+    avail_idx: u16,
+    last_used: u16,
+}
+
+// SAFETY: the rings are owned by the queue, the device only accesses them as specified
+unsafe impl Send for VirtQueue {}
+
+impl VirtQueue {
+    /// # Safety
+    /// memory must be zeroed, at least queue_bytes(size) long, page aligned and not used otherwise
+    unsafe fn new(index: u16, size: u16, memory: *mut u8) -> Self {
+        let desc = memory.cast::<Descriptor>();
+        let avail = unsafe { desc.add(size as usize) }.cast::<u16>();
+        let used_offset = (size as usize * size_of::<Descriptor>()
+            + (3 + size as usize) * size_of::<u16>())
+        .next_multiple_of(QUEUE_ALIGN);
+        let used = unsafe { memory.add(used_offset) }.cast::<u16>();
+        Self {
+            index,
+            size,
+            desc,
+            avail,
+            used,
+            used_ring: unsafe { used.add(2) }.cast(),
+            free: (0..size).rev().collect(),
+            avail_idx: 0,
+            last_used: 0,
+        }
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// hands the buffer at phys to the device. With writable set the device fills it, otherwise it reads from it.
+    /// Returns the descriptor id, which identifies the buffer on completion
+    pub fn push(&mut self, phys: u64, len: u32, writable: bool) -> Result<u16, VirtioError> {
+        let id = self.free.pop().ok_or(VirtioError::QueueFull)?;
+        let desc = Descriptor {
+            addr: phys,
+            len,
+            flags: if writable { DESC_F_WRITE } else { 0 },
+            next: 0,
+        };
+        unsafe {
+            ptr::write_volatile(self.desc.add(id as usize), desc);
+            let slot = 2 + (self.avail_idx % self.size) as usize;
+            ptr::write_volatile(self.avail.add(slot), id);
+        }
+        self.avail_idx = self.avail_idx.wrapping_add(1);
+        // the descriptor must be visible before the index, which publishes it
+        fence(Ordering::SeqCst);
+        unsafe { ptr::write_volatile(self.avail.add(1), self.avail_idx) };
+        Ok(id)
+    }
+
+    /// takes the next buffer returned by the device. Returns its descriptor id and the bytes written to it
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        let used_idx = unsafe { ptr::read_volatile(self.used.add(1)) };
+        if used_idx == self.last_used {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+        let elem = unsafe {
+            ptr::read_volatile(self.used_ring.add((self.last_used % self.size) as usize))
+        };
+        self.last_used = self.last_used.wrapping_add(1);
+        let id = elem.id as u16;
+        self.free.push(id);
+        Some((id, elem.len))
+    }
+}
+
+/// a virtio device behind the legacy pci transport, which exposes its registers in io bar 0
+#[derive(Debug)]
+pub struct LegacyDevice {
+    pci: PciDevice,
+    io: u16,
+}
+
+impl LegacyDevice {
+    /// resets the device and announces the driver
+    pub fn new(pci: PciDevice) -> Result<Self, VirtioError> {
+        let Some(Bar::Io(io)) = pci.bar(0) else {
+            return Err(VirtioError::NoIoBar);
+        };
+        pci.enable_bus_master();
+        let device = Self { pci, io };
+        device.reset();
+        device.set_status(Status::ACKNOWLEDGE | Status::DRIVER);
+        Ok(device)
+    }
+
+    pub fn pci(&self) -> &PciDevice {
+        &self.pci
+    }
+
+    /// resets the device, it stops using its queues afterwards
+    pub fn reset(&self) {
+        self.set_status(Status::empty());
+    }
+
+    pub fn status(&self) -> Status {
+        Status::from_bits_retain(unsafe { Port::<u8>::new(self.io + REG_STATUS).read() })
+    }
+
+    pub fn set_status(&self, status: Status) {
+        unsafe { Port::<u8>::new(self.io + REG_STATUS).write(status.bits()) };
+    }
+
+    pub fn device_features(&self) -> u32 {
+        unsafe { Port::<u32>::new(self.io + REG_DEVICE_FEATURES).read() }
+    }
+
+    /// acknowledges the subset of the device features understood by the driver
+    pub fn set_features(&self, features: u32) {
+        let features = features & self.device_features();
+        unsafe { Port::<u32>::new(self.io + REG_GUEST_FEATURES).write(features) };
+    }
+
+    /// sets up queue index in memory. The size of legacy queues is fixed by the device
+    pub fn setup_queue<const N: usize>(
+        &self,
+        index: u16,
+        memory: &'static DmaBuffer<N>,
+    ) -> Result<VirtQueue, VirtioError> {
+        let size = unsafe {
+            Port::<u16>::new(self.io + REG_QUEUE_SELECT).write(index);
+            Port::<u16>::new(self.io + REG_QUEUE_SIZE).read()
+        };
+        if size == 0 {
+            return Err(VirtioError::NoQueue);
+        }
+        if queue_bytes(size as usize) > memory.len() {
+            return Err(VirtioError::QueueTooLarge);
+        }
+        let phys = memory.phys_addr().ok_or(VirtioError::NotContiguous)?;
+        unsafe {
+            ptr::write_bytes(memory.as_mut_ptr(), 0, memory.len());
+            Port::<u32>::new(self.io + REG_QUEUE_PFN).write((phys / QUEUE_ALIGN as u64) as u32);
+        }
+        Ok(unsafe { VirtQueue::new(index, size, memory.as_mut_ptr()) })
+    }
+
+    /// marks the driver as ready, the device may use its queues afterwards
+    pub fn finish_setup(&self) {
+        self.set_status(self.status() | Status::DRIVER_OK);
+    }
+
+    pub fn notify(&self, queue: &VirtQueue) {
+        unsafe { Port::<u16>::new(self.io + REG_QUEUE_NOTIFY).write(queue.index) };
+    }
+
+    /// notifies the device and polls, until it returns a buffer
+    pub fn wait_used(&self, queue: &mut VirtQueue) -> Result<(u16, u32), VirtioError> {
+        self.notify(queue);
+        for _ in 0..TIMEOUT_POLLS {
+            if let Some(used) = queue.pop_used() {
+                // reading the isr acknowledges the interrupt, which may have been raised
+                let _: u8 = unsafe { Port::<u8>::new(self.io + REG_ISR).read() };
+                return Ok(used);
+            }
+            core::hint::spin_loop();
+        }
+        Err(VirtioError::TimedOut)
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    #[kernel_test]
+    fn split_queue() {
+        static MEMORY: DmaBuffer<{ 2 * QUEUE_ALIGN }> = DmaBuffer::new();
+        assert_eq!(queue_bytes(8), 2 * QUEUE_ALIGN);
+        assert_eq!(queue_bytes(256), 3 * QUEUE_ALIGN);
+        assert!(MEMORY.phys_addr().is_some());
+
+        let mut queue = unsafe { VirtQueue::new(0, 8, MEMORY.as_mut_ptr()) };
+        let ids = (0..8)
+            .map(|i| queue.push(0x1000 * i, 16, true).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(queue.push(0, 16, true), Err(VirtioError::QueueFull));
+        assert_eq!(queue.pop_used(), None);
+
+        // play the device: return the third buffer
+        unsafe {
+            ptr::write_volatile(
+                queue.used_ring,
+                UsedElem {
+                    id: ids[2] as u32,
+                    len: 12,
+                },
+            );
+            ptr::write_volatile(queue.used.add(1), 1);
+        }
+        assert_eq!(queue.pop_used(), Some((ids[2], 12)));
+        assert_eq!(queue.pop_used(), None);
+        assert_eq!(queue.push(0, 16, false), Ok(ids[2]));
+    }
+}
@@ -0,0 +1,144 @@
+use alloc::sync::Arc;
+use core::time::Duration;
+
+use os_macros::init_task;
+
+use super::{LegacyDevice, VENDOR, VirtQueue, VirtioError, queue_bytes};
+use crate::{
+    drivers::{
+        dma::DmaBuffer,
+        model::{Device, Driver, DriverError, Match},
+        wait_manager,
+    },
+    eprintln,
+    kernel::{
+        crypto::entropy,
+        threading::{
+            self,
+            wait::{QueuTypeCondition, QueueType, condition::WaitCondition},
+        },
+        time::Instant,
+    },
+    serial_println,
+    sync::locks::Mutex,
+};
+
+// the transitional device id, which still offers the legacy transport
+const DEVICE_ID: u16 = 0x1005;
+// bytes requested per harvest, this fills the pool at once
+const REQUEST_SIZE: usize = 64;
+const RESEED_INTERVAL: Duration = Duration::from_secs(30);
+
+// large enough for the biggest queue a legacy device may have
+static QUEUE_MEMORY: DmaBuffer<{ queue_bytes(256) }> = DmaBuffer::new();
+static BUFFER: DmaBuffer<REQUEST_SIZE> = DmaBuffer::new();
+static RNG: Mutex<Option<VirtioRng>> = Mutex::new(None);
+
+/// a virtio entropy device. It has a single queue, into which the driver places buffers for the device to fill
+#[derive(Debug)]
+struct VirtioRng {
+    device: LegacyDevice,
+    queue: VirtQueue,
+}
+
+impl VirtioRng {
+    fn new(device: LegacyDevice) -> Result<Self, VirtioError> {
+        // the device has no features, which the driver would need to understand
+        device.set_features(0);
+        let queue = device.setup_queue(0, &QUEUE_MEMORY)?;
+        device.finish_setup();
+        Ok(Self { device, queue })
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, VirtioError> {
+        let len = buf.len().min(BUFFER.len());
+        let phys = BUFFER.phys_addr().ok_or(VirtioError::NotContiguous)?;
+        self.queue.push(phys, len as u32, true)?;
+        let (_, written) = self.device.wait_used(&mut self.queue)?;
+        let written = (written as usize).min(len);
+        // SAFETY: the device is done with the buffer and only this driver uses it
+        let data = unsafe { core::slice::from_raw_parts(BUFFER.as_mut_ptr(), written) };
+        buf[..written].copy_from_slice(data);
+        Ok(written)
+    }
+}
+
+/// whether a virtio-rng device was found
+pub fn available() -> bool {
+    RNG.lock().is_some()
+}
+
+/// reads from the device into the entropy pool. Returns the number of bytes mixed in, each credited with 8 bits
+pub fn harvest() -> Result<usize, VirtioError> {
+    let mut bytes = [0; REQUEST_SIZE];
+    let n = match RNG.lock().as_mut() {
+        Some(rng) => rng.read(&mut bytes)?,
+        None => return Ok(0),
+    };
+    entropy::add_entropy(&bytes[..n], 8 * n);
+    Ok(n)
+}
+
+pub static DRIVER: VirtioRngDriver = VirtioRngDriver;
+
+/// sets up the first virtio-rng device and seeds the entropy pool from it, before any binary runs
+#[derive(Debug)]
+pub struct VirtioRngDriver;
+
+impl Driver for VirtioRngDriver {
+    fn name(&self) -> &'static str {
+        "virtio-rng"
+    }
+
+    fn match_table(&self) -> &'static [Match] {
+        &[Match::Pci {
+            vendor: VENDOR,
+            device: DEVICE_ID,
+        }]
+    }
+
+    fn probe(&self, device: &Arc<dyn Device>) -> Result<(), DriverError> {
+        let pci = device
+            .pci()
+            .ok_or(DriverError::NoDevice("not a pci device"))?;
+        let mut slot = RNG.lock();
+        if slot.is_some() {
+            return Err(DriverError::NoDevice("only a single device is used"));
+        }
+        *slot = Some(
+            LegacyDevice::new(*pci)
+                .and_then(VirtioRng::new)
+                .map_err(DriverError::failed)?,
+        );
+        drop(slot);
+        let n = harvest().map_err(DriverError::failed)?;
+        serial_println!("virtio-rng at {}: seeded with {} bytes", pci.addr, n);
+        Ok(())
+    }
+
+    fn remove(&self, _device: &Arc<dyn Device>) {
+        // the reseeding task keeps running, but harvests nothing until another device is probed
+        if let Some(rng) = RNG.lock().take() {
+            rng.device.reset();
+        }
+    }
+}
+
+/// keeps mixing fresh device output into the pool
+#[init_task(stage = "drivers", order = 40)]
+fn start_reseeding() {
+    if !available() {
+        return;
+    }
+    _ = threading::spawn(|| {
+        loop {
+            wait_manager::wait_self(&[QueuTypeCondition::with_cond(
+                QueueType::Timer,
+                WaitCondition::Time(Instant::now() + RESEED_INTERVAL),
+            )]);
+            if let Err(e) = harvest() {
+                eprintln!("virtio-rng: {}", e);
+            }
+        }
+    });
+}
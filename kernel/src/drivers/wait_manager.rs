@@ -1,8 +1,10 @@
 use conquer_once::spin::OnceCell;
+use os_macros::init_task;
 
 use crate::{
     kernel::threading::{
         self,
+        schedule::{assert_may_block, stats},
         task::ThreadID,
         tls,
         wait::{
@@ -10,7 +12,16 @@ use crate::{
             QueueHandle,
             QueueType,
             WaitObserver,
-            queues::{KEYBOARDQUEUE, KeyBoardQueue, TIMERQUEUE, TimeWaitQueue},
+            queues::{
+                GenericWaitQueue,
+                KEYBOARDQUEUE,
+                KeyBoardQueue,
+                MOUNTQUEUE,
+                PARKQUEUE,
+                ParkQueue,
+                TIMERQUEUE,
+                TimeWaitQueue,
+            },
         },
     },
     sync::locks::RwLock,
@@ -30,15 +41,19 @@ pub fn remove_queue(queue_type: &QueueType) {
     WAIT_MANAGER.get().unwrap().write().remove_queue(queue_type);
 }
 
+/// blocks the current thread until one of the conditions is met. Sleeping and blocking I/O end up here
 pub fn wait_self(queue_data: &[QueuTypeCondition]) -> Option<()> {
+    assert_may_block();
     let r = WAIT_MANAGER
         .get()?
         .read()
         .enqueue(&tls::task_data().current_tid(), queue_data);
-    threading::yield_now();
+    stats::count_sleep();
+    threading::reschedule();
     Some(r)
 }
 
+#[init_task(stage = "drivers", order = 10)]
 pub fn start_wait_managment() {
     threading::wait::init();
     let mut manager = WaitObserver::new();
@@ -54,6 +69,18 @@ pub fn start_wait_managment() {
             QueueType::KeyBoard,
         )
         .unwrap();
+    manager
+        .add_queue(
+            QueueHandle::from_borrowed(PARKQUEUE.get_or_init(ParkQueue::new)),
+            QueueType::Park,
+        )
+        .unwrap();
+    manager
+        .add_queue(
+            QueueHandle::from_borrowed(MOUNTQUEUE.get_or_init(GenericWaitQueue::new)),
+            QueueType::Mounts,
+        )
+        .unwrap();
     WAIT_MANAGER.init_once(move || RwLock::new(manager));
 
     threading::spawn(move || {
@@ -0,0 +1,218 @@
+use alloc::{format, string::String};
+use core::{
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use os_macros::init_task;
+use tinyos_abi::flags::NodeType;
+
+use crate::{
+    KernelRes,
+    arch,
+    bootinfo,
+    create_device_file,
+    drivers::wait_manager,
+    eprintln,
+    impl_file_for_wr,
+    kernel::{
+        config::{self, SubsystemHooks},
+        fs::{FSErrorKind, OpenOptions, Path, procfs},
+        io::{IOError, IOResult, Read, Write, read_rendered},
+        reboot::{self, RebootReason},
+        threading::{
+            self,
+            tls::TaskList,
+            wait::{QueuTypeCondition, QueueType, condition::WaitCondition},
+        },
+        time::Instant,
+    },
+};
+
+pub const WATCHDOG_FILE: &str = "/dev/watchdog";
+/// the timeout in seconds, given as watchdog=<secs> on the kernel command line
+pub const TIMEOUT_OPTION: &str = "watchdog";
+pub const DEFAULT_TIMEOUT: u64 = 60;
+/// written to /dev/watchdog to disarm it. There is no close hook, thus a clean shutdown has to say so explicitly
+const MAGIC_CLOSE: &[u8] = b"V";
+// the timeout is given in seconds, checking more often is pointless
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+static ARMED: AtomicBool = AtomicBool::new(false);
+static TIMEOUT: AtomicU64 = AtomicU64::new(DEFAULT_TIMEOUT);
+/// the instant, at which the watchdog fires, in ns since boot
+static DEADLINE: AtomicU64 = AtomicU64::new(0);
+static WATCHDOG: Watchdog = Watchdog;
+/// bumped whenever the subsystem stops, which ends the checking task of the previous start
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// arms the watchdog, or pushes its deadline back by the timeout
+pub fn ping() {
+    let deadline = Instant::now() + Duration::from_secs(TIMEOUT.load(Ordering::Relaxed));
+    DEADLINE.store(deadline.as_nanos(), Ordering::Relaxed);
+    ARMED.store(true, Ordering::Release);
+}
+
+pub fn disarm() {
+    ARMED.store(false, Ordering::Release);
+}
+
+pub fn armed() -> bool {
+    ARMED.load(Ordering::Acquire)
+}
+
+pub fn timeout() -> u64 {
+    TIMEOUT.load(Ordering::Relaxed)
+}
+
+/// the seconds left until the watchdog fires, if it is armed
+pub fn remaining() -> Option<u64> {
+    armed().then(|| deadline().duration_since(Instant::now()).as_secs())
+}
+
+fn expired() -> bool {
+    armed() && Instant::now() >= deadline()
+}
+
+fn deadline() -> Instant {
+    Instant::from_nanos(DEADLINE.load(Ordering::Relaxed))
+}
+
+/// logs what was running and resets the machine
+fn fire() -> ! {
+    eprintln!(
+        "watchdog: no ping for {}s, rebooting\n{}{}",
+        timeout(),
+        TaskList.render(),
+        arch::interrupts()
+    );
+    reboot::reboot(RebootReason::Watchdog)
+}
+
+/// /dev/watchdog: a write arms the watchdog and pushes its deadline back. If userspace stops writing for the timeout,
+/// the kernel dumps the tasks and interrupt counters and reboots.
+/// Writing timeout=<secs> changes the timeout and pings, writing V disarms it. Reads render the current state
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Watchdog;
+
+impl Watchdog {
+    fn render(&self) -> String {
+        format!(
+            "armed\t{}\ntimeout\t{}\nremaining\t{}\n",
+            armed() as u8,
+            timeout(),
+            remaining().unwrap_or(0)
+        )
+    }
+}
+
+impl Read for Watchdog {
+    fn read(&self, buf: &mut [u8], offset: usize) -> IOResult<usize> {
+        read_rendered(self.render(), buf, offset)
+    }
+}
+
+impl Write for Watchdog {
+    fn write(&self, buf: &[u8], _offset: usize) -> IOResult<usize> {
+        let cmd = buf.trim_ascii();
+        if cmd == MAGIC_CLOSE {
+            disarm();
+            return Ok(buf.len());
+        }
+        if let Some(secs) = cmd.strip_prefix(b"timeout=") {
+            let secs = str::from_utf8(secs)
+                .ok()
+                .and_then(|secs| secs.parse::<u64>().ok())
+                .filter(|secs| *secs > 0)
+                .ok_or(IOError::with_message(
+                    FSErrorKind::Other,
+                    "the timeout must be a positive number of seconds",
+                ))?;
+            TIMEOUT.store(secs, Ordering::Relaxed);
+        }
+        ping();
+        Ok(buf.len())
+    }
+}
+
+impl_file_for_wr!(Watchdog: NodeType::FILE);
+
+/// the watchdog may be started and stopped at runtime, see config::register
+#[init_task(stage = "drivers", order = 60)]
+fn register_watchdog() -> KernelRes<()> {
+    if let Some(secs) = bootinfo::cmdline_option(TIMEOUT_OPTION) {
+        match secs.parse::<u64>() {
+            Ok(secs) if secs > 0 => TIMEOUT.store(secs, Ordering::Relaxed),
+            _ => eprintln!("watchdog: invalid timeout {:?}", secs),
+        }
+    }
+    config::register(
+        "watchdog",
+        SubsystemHooks {
+            start: start_watchdog,
+            stop: stop_watchdog,
+        },
+    )
+}
+
+/// registers /dev/watchdog and starts checking its deadline. The watchdog stays disarmed until the first write
+fn start_watchdog() -> KernelRes<()> {
+    let rw = OpenOptions::READ | OpenOptions::WRITE | OpenOptions::CREATE_ALL;
+    create_device_file!(&WATCHDOG, WATCHDOG_FILE, rw)?;
+    let generation = GENERATION.load(Ordering::Acquire);
+    threading::spawn(move || {
+        while GENERATION.load(Ordering::Acquire) == generation {
+            wait_manager::wait_self(&[QueuTypeCondition::with_cond(
+                QueueType::Timer,
+                WaitCondition::Time(Instant::now() + CHECK_INTERVAL),
+            )]);
+            if GENERATION.load(Ordering::Acquire) == generation && expired() {
+                fire();
+            }
+        }
+    })?;
+    Ok(())
+}
+
+/// disarms the watchdog and removes /dev/watchdog. Files, which are still open on it, keep working, but
+/// nothing checks the deadline anymore
+fn stop_watchdog() -> KernelRes<()> {
+    disarm();
+    GENERATION.fetch_add(1, Ordering::AcqRel);
+    procfs::registry().deregister(Path::new(WATCHDOG_FILE))?;
+    Ok(())
+}
+
+/// a slow flush must not reset the machine halfway through the shutdown
+#[init_task(stage = "shutdown")]
+fn disarm_watchdog() {
+    disarm();
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    #[kernel_test]
+    fn ping_and_disarm() {
+        let timeout = timeout();
+        assert_eq!(Watchdog.write(b"timeout=3600\n", 0).unwrap(), 13);
+        assert!(armed());
+        assert_eq!(super::timeout(), 3600);
+        assert!(remaining().unwrap() > 3590);
+        assert!(!expired());
+
+        let mut buf = [0; 64];
+        let n = Watchdog.read(&mut buf, 0).unwrap();
+        assert!(buf[..n].starts_with(b"armed\t1\ntimeout\t3600\n"));
+
+        assert!(Watchdog.write(b"timeout=0", 0).is_err());
+        assert!(Watchdog.write(b"timeout=x", 0).is_err());
+        assert_eq!(Watchdog.write(b"V", 0).unwrap(), 1);
+        assert!(!armed());
+        assert_eq!(remaining(), None);
+        TIMEOUT.store(timeout, Ordering::Relaxed);
+    }
+}
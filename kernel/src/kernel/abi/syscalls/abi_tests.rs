@@ -0,0 +1,390 @@
+use alloc::{format, string::String, vec::Vec};
+use core::{arch::global_asm, mem::offset_of, ptr, slice, time::Duration};
+
+use os_macros::kernel_test;
+use tinyos_abi::{
+    consts::{MAX_SYSCALL, PATH_MAX, VDSO_START},
+    types::{SysCallDispatch, SysErrCode},
+};
+
+use super::registered_syscalls;
+use crate::{
+    KernelError,
+    arch::mem::{
+        FrameAllocator,
+        FrameDeallocator,
+        Mapper,
+        Page,
+        PageSize,
+        PageTableFlags,
+        PhysFrame,
+        Size4KiB,
+        VirtAddr,
+    },
+    kernel::{
+        elf::ElfError,
+        fd::FDTableError,
+        fs::{FSError, FSErrorKind},
+        mem::paging::{BORROWED, get_frame_alloc, get_hhdm_addr},
+        threading::{
+            self,
+            ThreadingError,
+            schedule::add_built_task,
+            task::{TaskBuilder, TaskRepr, TaskState},
+            tls,
+        },
+        time::Instant,
+    },
+};
+
+// ABI tests: a probe process issues every syscall from ring 3 through int 0x80, exactly like userspace does, with
+// edge-case arguments, and records the raw return registers. The recorded error codes are compared against those
+// documented in syscalls.txt. Any refactoring of a syscall, which changes its number, its argument decoding or its
+// error codes, thus fails here.
+
+const PAGE_SIZE: usize = Size4KiB::SIZE as usize;
+const PROBE_CODE: u64 = 0x4000_0000;
+const PROBE_DATA: u64 = PROBE_CODE + Size4KiB::SIZE;
+/// as many as fit into the data page next to the header
+const MAX_CASES: usize = 55;
+/// upper bound on the runtime of the probe
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// syscalls, which are not probed: they end the probe or are not implemented
+const NOT_PROBED: &[u64] = &[
+    SysCallDispatch::Exit as u64,
+    SysCallDispatch::ThreadExit as u64,
+    SysCallDispatch::EventFD as u64,
+];
+
+/// a single syscall. ret and err receive rax and rdx after the call
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct Case {
+    number: u64,
+    args: [u64; 6],
+    ret: u64,
+    err: u64,
+}
+
+/// the data page of the probe
+#[repr(C)]
+struct ProbeData {
+    /// set by the probe before it exits
+    done: u64,
+    count: u64,
+    /// user memory for arguments, which must point somewhere valid
+    scratch: [u8; 64],
+    cases: [Case; MAX_CASES],
+}
+
+const _: () = assert!(size_of::<ProbeData>() <= PAGE_SIZE);
+
+// runs all cases in order, then exits with status 0
+global_asm!(
+    "
+    .pushsection .rodata.abi_probe, \"a\"
+    .global __abi_probe_start
+    .global __abi_probe_end
+
+    __abi_probe_start:
+        mov rbx, {data}
+        mov r12, [rbx + {count}]
+        lea r13, [rbx + {cases}]
+    1:
+        test r12, r12
+        jz 2f
+        mov rax, [r13 + {number}]
+        mov rdi, [r13 + {args}]
+        mov rsi, [r13 + {args} + 8]
+        mov rdx, [r13 + {args} + 16]
+        mov r10, [r13 + {args} + 24]
+        mov r9, [r13 + {args} + 32]
+        mov r8, [r13 + {args} + 40]
+        int 0x80
+        mov [r13 + {ret}], rax
+        mov [r13 + {err}], rdx
+        add r13, {case_size}
+        dec r12
+        jmp 1b
+    2:
+        mov qword ptr [rbx + {done}], 1
+        mov rax, {exit}
+        xor edi, edi
+        int 0x80
+        ud2
+
+    __abi_probe_end:
+    .popsection
+    ",
+    data = const PROBE_DATA,
+    done = const offset_of!(ProbeData, done),
+    count = const offset_of!(ProbeData, count),
+    cases = const offset_of!(ProbeData, cases),
+    number = const offset_of!(Case, number),
+    args = const offset_of!(Case, args),
+    ret = const offset_of!(Case, ret),
+    err = const offset_of!(Case, err),
+    case_size = const size_of::<Case>(),
+    exit = const SysCallDispatch::Exit as u64,
+);
+
+unsafe extern "C" {
+    static __abi_probe_start: u8;
+    static __abi_probe_end: u8;
+}
+
+fn probe_code() -> &'static [u8] {
+    // SAFETY: both symbols are defined in the same section above
+    unsafe {
+        let start = &raw const __abi_probe_start;
+        let end = &raw const __abi_probe_end;
+        slice::from_raw_parts(start, end.addr() - start.addr())
+    }
+}
+
+fn frame_ptr<T>(frame: PhysFrame<Size4KiB>) -> *mut T {
+    (get_hhdm_addr() + frame.start_address().as_u64()) as *mut T
+}
+
+fn case(number: u64, args: &[u64], err: SysErrCode) -> (Case, SysErrCode) {
+    let mut case = Case {
+        number,
+        ..Default::default()
+    };
+    case.args[..args.len()].copy_from_slice(args);
+    (case, err)
+}
+
+/// the cases of the probe with their documented error code
+fn cases() -> Vec<(Case, SysErrCode)> {
+    use SysCallDispatch as S;
+    use SysErrCode as E;
+
+    let bad_fd = u32::MAX as u64;
+    let kernel = get_hhdm_addr();
+    let scratch = PROBE_DATA + offset_of!(ProbeData, scratch) as u64;
+    let missing = u64::MAX;
+    let no_resource = 99;
+
+    let mut cases = Vec::from([
+        case(S::Open as u64, &[0, 1, 0], E::AddrNotValid),
+        case(
+            S::Open as u64,
+            &[scratch, PATH_MAX as u64 + 1, 0],
+            E::InvalidArg,
+        ),
+        case(S::Close as u64, &[bad_fd], E::BadFd),
+        case(S::Read as u64, &[bad_fd, 0, 0, 0], E::BadFd),
+        case(S::Read as u64, &[0, kernel, 8, 0], E::AddrNotValid),
+        case(S::Write as u64, &[bad_fd, 0, 0], E::BadFd),
+        case(S::Write as u64, &[1, kernel, 8], E::AddrNotValid),
+        case(S::Yield as u64, &[], E::NoErr),
+        case(S::Kill as u64, &[missing, 0], E::NoProcess),
+        case(S::Mmap as u64, &[4096, 0, 0, i32::MAX as u64], E::BadFd),
+        case(S::Munmap as u64, &[0, 4096], E::AddrNotValid),
+        case(S::Munmap as u64, &[VDSO_START, 4096], E::AccessDenied),
+        case(S::Fork as u64, &[], E::OpDenied),
+        case(S::WaitTime as u64, &[0], E::NoErr),
+        case(S::GetPID as u64, &[], E::NoErr),
+        case(S::Seek as u64, &[bad_fd, 0], E::BadFd),
+        case(S::Dup as u64, &[bad_fd, u64::MAX], E::BadFd),
+        case(S::Spawn as u64, &[kernel, 8], E::AddrNotValid),
+        // scratch holds no elf image
+        case(S::Spawn as u64, &[scratch, 2], E::BadMsg),
+        // scratch holds invalid utf8
+        case(S::Dbg as u64, &[scratch, 2], E::InvalidArg),
+        case(S::Execve as u64, &[0, 0, 0, 0], E::AddrNotValid),
+        case(S::ThreadCreate as u64, &[0, 0], E::AddrNotValid),
+        case(S::ThreadCancel as u64, &[missing], E::NoProcess),
+        case(S::ThreadJoin as u64, &[missing, 0, 0, 0], E::NoChild),
+        case(S::WaitPID as u64, &[missing, 0, 0, 0], E::NotSupported),
+        case(S::Time as u64, &[], E::NoErr),
+        case(S::GetTID as u64, &[], E::NoErr),
+        case(S::GetPgrID as u64, &[], E::NoErr),
+        case(S::Pipe as u64, &[0, 0], E::AddrNotValid),
+        case(S::SpawnProcess as u64, &[0, 1, 0, 0, 0, 0], E::AddrNotValid),
+        case(S::FStat as u64, &[bad_fd, 0], E::AddrNotValid),
+        case(S::SetPerm as u64, &[bad_fd, 0, missing], E::InvalidArg),
+        case(S::Dup2 as u64, &[bad_fd, bad_fd], E::BadFd),
+        case(S::Dup3 as u64, &[3, 3, 0], E::InvalidArg),
+        // an undefined flag bit
+        case(S::Dup3 as u64, &[0, 1, 1 << 31], E::InvalidArg),
+        case(S::GetRLimit as u64, &[no_resource], E::InvalidArg),
+        case(S::SetRLimit as u64, &[no_resource, 0], E::InvalidArg),
+        case(S::SendFile as u64, &[bad_fd, bad_fd, 0, 1], E::BadFd),
+        case(S::GetCpu as u64, &[0, 0], E::NoErr),
+        case(S::WaitId as u64, &[0, 0, 0, 0, 0], E::InvalidArg),
+        case(S::MemInfo as u64, &[0, 0], E::AddrNotValid),
+        case(S::TcGetPgrp as u64, &[], E::NoErr),
+        case(S::TcSetPgrp as u64, &[missing], E::NoProcess),
+        case(S::GetRandom as u64, &[0, 1, 0], E::AddrNotValid),
+        case(S::SetTimes as u64, &[0, 1, 0], E::AddrNotValid),
+        case(S::GetXattr as u64, &[0, 1, 0, 0, 0, 0], E::AddrNotValid),
+        case(S::SetXattr as u64, &[0, 1, 0, 0, 0, 0], E::AddrNotValid),
+        case(S::RemoveXattr as u64, &[0, 1, 0, 0], E::AddrNotValid),
+        case(S::ListXattr as u64, &[0, 1, 0, 0], E::AddrNotValid),
+        case(S::SendFd as u64, &[bad_fd, 0], E::BadFd),
+        case(S::RecvFd as u64, &[bad_fd, 0], E::BadFd),
+        case(S::RingSetup as u64, &[3, 0], E::InvalidArg),
+        case(S::RingEnter as u64, &[bad_fd, 0, 0, 0], E::BadFd),
+    ]);
+    // numbers without a syscall
+    for number in [11, MAX_SYSCALL + 1, u64::MAX] {
+        cases.push(case(number, &[], E::BadRqstD));
+    }
+    cases
+}
+
+/// runs cases in a new user process and returns the recorded cases
+fn run_probe(cases: &[Case]) -> Vec<Case> {
+    assert!(cases.len() <= MAX_CASES);
+    let (code, data) = {
+        let mut alloc = get_frame_alloc().lock();
+        alloc.allocate_frame().zip(alloc.allocate_frame()).unwrap()
+    };
+    // SAFETY: the frames were just allocated and are reachable through the hhdm
+    unsafe {
+        ptr::write_bytes(frame_ptr::<u8>(code), 0xcc, PAGE_SIZE);
+        ptr::copy_nonoverlapping(probe_code().as_ptr(), frame_ptr(code), probe_code().len());
+        ptr::write_bytes(frame_ptr::<u8>(data), 0, PAGE_SIZE);
+        let probe = &mut *frame_ptr::<ProbeData>(data);
+        probe.count = cases.len() as u64;
+        probe.scratch[..2].copy_from_slice(&[0xff, 0xfe]);
+        probe.cases[..cases.len()].copy_from_slice(cases);
+    }
+
+    let task = unsafe { TaskBuilder::from_addr(VirtAddr::new(PROBE_CODE)) }
+        .unwrap()
+        .with_name("abi-probe".into())
+        .as_usr()
+        .unwrap()
+        .build();
+    // both frames are borrowed, such that they outlive the address space of the probe
+    let user = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | BORROWED;
+    for (addr, frame, flags) in [
+        (PROBE_CODE, code, user),
+        (
+            PROBE_DATA,
+            data,
+            user | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+        ),
+    ] {
+        let mut alloc = get_frame_alloc().lock();
+        unsafe {
+            task.pagedir().map_to(
+                Page::containing_address(VirtAddr::new(addr)),
+                frame,
+                flags,
+                &mut *alloc,
+            )
+        }
+        .unwrap()
+        .flush();
+    }
+
+    let tid = task.tid();
+    add_built_task(task);
+    let task = tls::task_data().thread(&tid).unwrap();
+    let deadline = Instant::now() + PROBE_TIMEOUT;
+    while task.state() != TaskState::Zombie {
+        assert!(Instant::now() < deadline, "the abi probe did not finish");
+        threading::yield_now();
+    }
+
+    // SAFETY: the probe exited, thus nothing accesses the frames anymore
+    let probe = unsafe { &*frame_ptr::<ProbeData>(data) };
+    assert_eq!(probe.done, 1, "the abi probe was killed");
+    let recorded = probe.cases[..cases.len()].to_vec();
+    let mut alloc = get_frame_alloc().lock();
+    unsafe {
+        alloc.deallocate_frame(code);
+        alloc.deallocate_frame(data);
+    }
+    recorded
+}
+
+#[kernel_test]
+fn every_syscall_probed() {
+    let probed: Vec<u64> = cases().iter().map(|(case, _)| case.number).collect();
+    for entry in registered_syscalls() {
+        assert!(
+            probed.contains(&entry.number) || NOT_PROBED.contains(&entry.number),
+            "syscall {} ({}) has no abi test",
+            entry.number,
+            entry.name
+        );
+    }
+}
+
+#[kernel_test]
+fn documented_error_codes() {
+    let cases = cases();
+    let input: Vec<Case> = cases.iter().map(|(case, _)| *case).collect();
+    let recorded = run_probe(&input);
+
+    let failures: Vec<String> = recorded
+        .iter()
+        .zip(&cases)
+        .filter(|(recorded, (_, expected))| recorded.err != *expected as u64)
+        .map(|(recorded, (case, expected))| {
+            format!(
+                "syscall {} with {:x?}: expected {:?}, got {:?}",
+                case.number,
+                case.args,
+                expected,
+                SysErrCode::try_from(recorded.err)
+            )
+        })
+        .collect();
+    assert!(failures.is_empty(), "abi broken:\n{}", failures.join("\n"));
+}
+
+#[kernel_test]
+fn kernel_errors_map_to_codes() {
+    use SysErrCode as E;
+
+    let fs = |kind| E::from(FSError::simple(kind));
+    assert_eq!(fs(FSErrorKind::NotFound), E::NoFile);
+    assert_eq!(fs(FSErrorKind::NotADir), E::NotADir);
+    assert_eq!(fs(FSErrorKind::IsADir), E::IsADir);
+    assert_eq!(fs(FSErrorKind::DirNotEmpty), E::DirNotEmpty);
+    assert_eq!(fs(FSErrorKind::Deadlock), E::Deadlock);
+    assert_eq!(fs(FSErrorKind::InProgress), E::InProgress);
+    assert_eq!(fs(FSErrorKind::NotSupported), E::NotSupported);
+    assert_eq!(fs(FSErrorKind::EOF), E::IO);
+    assert_eq!(
+        E::from(FSError::with_message(FSErrorKind::PermissionDenied, "ro")),
+        E::AccessDenied
+    );
+
+    assert_eq!(E::from(FDTableError::Exhausted(3)), E::TooManyFiles);
+    assert_eq!(E::from(FDTableError::NotOpen(3)), E::BadFd);
+
+    assert_eq!(E::from(ElfError::Truncated), E::BadMsg);
+    assert_eq!(E::from(ElfError::Unsupported), E::NotSupported);
+    assert_eq!(E::from(ThreadingError::Elf(ElfError::Io)), E::IO);
+    assert_eq!(E::from(ThreadingError::StackNotBuilt), E::OOM);
+    assert_eq!(E::from(ThreadingError::Timeout), E::TimerExp);
+    assert_eq!(E::from(ThreadingError::Detached), E::NoChild);
+
+    assert_eq!(
+        E::from(KernelError::IO(FSError::simple(FSErrorKind::StorageFull))),
+        E::DiskFull
+    );
+    assert_eq!(
+        E::from(KernelError::Threading(ThreadingError::PageDirNotBuilt)),
+        E::OOM
+    );
+    assert_eq!(E::from(KernelError::Unexpected("")), E::Cancelled);
+}
+
+#[kernel_test]
+fn error_codes_roundtrip() {
+    for code in 0..64 {
+        match SysErrCode::try_from(code) {
+            Ok(err) => assert_eq!(err as u64, code),
+            Err(_) => assert!(code == 4 || code > SysErrCode::InProgress as u64),
+        }
+    }
+}
@@ -0,0 +1,681 @@
+use core::{ptr::NonNull, str};
+
+use tinyos_abi::{
+    consts::{ARG_MAX, PATH_MAX},
+    flags::{
+        NodePermissions,
+        OpenOptions,
+        PageTableFlags,
+        RandomFlags,
+        TaskWaitOptions,
+        WaitOptions,
+    },
+    types::{FatPtr, SysCallRes, SysErrCode},
+};
+
+use crate::{
+    arch::{
+        context::SysCallCtx,
+        mem::{PageSize, PageTableFlags as HwFlags, Size4KiB, VirtAddr},
+    },
+    kernel::{
+        mem::{
+            addr::UserVirtAddr,
+            paging::{
+                COW,
+                FaultResolution,
+                active_page_flags,
+                resolve_cow_fault,
+                resolve_demand_fault,
+            },
+        },
+        threading::reschedule,
+    },
+};
+
+// Typed decoding of syscall arguments.
+// Every syscall argument type implements SysCallArg, which consumes one or more raw argument registers.
+// Pointers to user memory are decoded into the User* types below, which validate the whole range against the address
+// space of the calling task. Thus every syscall rejects bad user memory the same way, with SysErrCode::AddrNotValid,
+// before touching it.
+
+/// number of argument registers
+pub const MAX_ARGS: usize = 6;
+
+/// reads the raw arguments of a syscall in order
+#[derive(Debug)]
+pub struct ArgDecoder<'a> {
+    ctx: &'a SysCallCtx,
+    next: usize,
+}
+
+impl<'a> ArgDecoder<'a> {
+    pub fn new(ctx: &'a SysCallCtx) -> Self {
+        Self { ctx, next: 0 }
+    }
+
+    /// the next raw argument
+    pub fn raw(&mut self) -> SysCallRes<u64> {
+        let val = match self.next {
+            0 => self.ctx.first(),
+            1 => self.ctx.second(),
+            2 => self.ctx.third(),
+            3 => self.ctx.fourth(),
+            4 => self.ctx.fifth(),
+            5 => self.ctx.sixth(),
+            _ => return Err(SysErrCode::InvalidArg),
+        };
+        self.next += 1;
+        Ok(val)
+    }
+
+    pub fn decode<T: SysCallArg>(&mut self) -> SysCallRes<T> {
+        T::decode(self)
+    }
+}
+
+/// decodes all arguments of a syscall, typically into a tuple
+pub fn decode<T: SysCallArg>(ctx: &SysCallCtx) -> SysCallRes<T> {
+    ArgDecoder::new(ctx).decode()
+}
+
+/// a type, which can be decoded from the raw syscall arguments
+pub trait SysCallArg: Sized {
+    fn decode(args: &mut ArgDecoder<'_>) -> SysCallRes<Self>;
+}
+
+macro_rules! impl_arg_cast {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            impl SysCallArg for $ty {
+                fn decode(args: &mut ArgDecoder<'_>) -> SysCallRes<Self> {
+                    Ok(args.raw()? as $ty)
+                }
+            }
+        )*
+    };
+}
+
+impl_arg_cast!(u64, u32, u16, u8, usize, i64, i32, isize);
+
+macro_rules! impl_arg_flags {
+    ($($ty:ty: $repr:ty),* $(,)?) => {
+        $(
+            impl SysCallArg for $ty {
+                fn decode(args: &mut ArgDecoder<'_>) -> SysCallRes<Self> {
+                    Ok(<$ty>::from_bits_truncate(args.raw()? as $repr))
+                }
+            }
+        )*
+    };
+}
+
+impl_arg_flags!(
+    OpenOptions: u32,
+    WaitOptions: u16,
+    TaskWaitOptions: u16,
+    PageTableFlags: u64,
+    NodePermissions: u8,
+    RandomFlags: u32,
+);
+
+/// raw pointers are passed through unchecked. They must only be used as addresses, never dereferenced
+impl<T> SysCallArg for *const T {
+    fn decode(args: &mut ArgDecoder<'_>) -> SysCallRes<Self> {
+        Ok(args.raw()? as usize as *const T)
+    }
+}
+
+impl<T> SysCallArg for *mut T {
+    fn decode(args: &mut ArgDecoder<'_>) -> SysCallRes<Self> {
+        Ok(args.raw()? as usize as *mut T)
+    }
+}
+
+macro_rules! impl_arg_tuple {
+    ($($name:ident),*) => {
+        impl<$($name: SysCallArg),*> SysCallArg for ($($name,)*) {
+            #[allow(unused_variables)]
+            fn decode(args: &mut ArgDecoder<'_>) -> SysCallRes<Self> {
+                Ok(($($name::decode(args)?,)*))
+            }
+        }
+    };
+}
+
+impl_arg_tuple!();
+impl_arg_tuple!(A);
+impl_arg_tuple!(A, B);
+impl_arg_tuple!(A, B, C);
+impl_arg_tuple!(A, B, C, D);
+impl_arg_tuple!(A, B, C, D, E);
+impl_arg_tuple!(A, B, C, D, E, F);
+
+/// runs the fault resolver resolve on addr until it does not hit a contended lock. Unlike the page fault handler,
+/// syscalls may give up the cpu, such that the lock holder can make progress
+fn resolve_blocking(resolve: fn(VirtAddr) -> FaultResolution, addr: VirtAddr) -> bool {
+    loop {
+        match resolve(addr) {
+            FaultResolution::Contended => reschedule(),
+            res => return res == FaultResolution::Resolved,
+        }
+    }
+}
+
+/// checks that addr..addr + bytes is mapped user memory in the current address space, which is writable if write is set.
+/// Empty ranges are always valid
+pub fn check_user_range(addr: usize, bytes: usize, write: bool) -> SysCallRes<()> {
+    if bytes == 0 {
+        return Ok(());
+    }
+    if addr == 0 || UserVirtAddr::try_new_range(addr as u64, bytes as u64).is_none() {
+        return Err(SysErrCode::AddrNotValid);
+    }
+    let end = addr + bytes;
+    let mut wanted = HwFlags::PRESENT | HwFlags::USER_ACCESSIBLE;
+    if write {
+        wanted |= HwFlags::WRITABLE;
+    }
+    let page_size = Size4KiB::SIZE as usize;
+    let mut page = addr & !(page_size - 1);
+    while page < end {
+        let page_addr = VirtAddr::new(page as u64);
+        let mut flags = active_page_flags(page_addr);
+        // pages of a vma are only backed on first touch
+        if flags.is_none() && resolve_blocking(resolve_demand_fault, page_addr) {
+            flags = active_page_flags(page_addr);
+        }
+        // copy on write pages are copied right away, as the kernel might not fault on writing to them
+        let valid = flags.is_some_and(|flags| flags.contains(wanted))
+            || (write
+                && flags.is_some_and(|flags| flags.contains(HwFlags::USER_ACCESSIBLE | COW))
+                && resolve_blocking(resolve_cow_fault, page_addr));
+        if !valid {
+            return Err(SysErrCode::AddrNotValid);
+        }
+        page += page_size;
+    }
+    Ok(())
+}
+
+fn check_user<T>(ptr: *const T, len: usize, write: bool) -> SysCallRes<()> {
+    if len != 0 && !ptr.is_aligned() {
+        return Err(SysErrCode::AddrNotValid);
+    }
+    let bytes = len
+        .checked_mul(size_of::<T>())
+        .ok_or(SysErrCode::AddrNotValid)?;
+    check_user_range(ptr.addr(), bytes, write)
+}
+
+/// a valid, readable pointer to a T in user memory
+#[derive(Debug)]
+pub struct UserRef<T> {
+    ptr: NonNull<T>,
+}
+
+impl<T> UserRef<T> {
+    pub fn new(ptr: *const T) -> SysCallRes<Self> {
+        check_user(ptr, 1, false)?;
+        Ok(Self {
+            ptr: NonNull::new(ptr as *mut T).ok_or(SysErrCode::AddrNotValid)?,
+        })
+    }
+
+    pub fn get(&self) -> &T {
+        // SAFETY: the pointer was validated on creation
+        unsafe { self.ptr.as_ref() }
+    }
+
+    pub fn as_ptr(&self) -> *const T {
+        self.ptr.as_ptr()
+    }
+}
+
+impl<T> SysCallArg for UserRef<T> {
+    fn decode(args: &mut ArgDecoder<'_>) -> SysCallRes<Self> {
+        Self::new(args.decode()?)
+    }
+}
+
+/// a valid, writable pointer to a T in user memory
+#[derive(Debug)]
+pub struct UserMut<T> {
+    ptr: NonNull<T>,
+}
+
+impl<T> UserMut<T> {
+    pub fn new(ptr: *mut T) -> SysCallRes<Self> {
+        check_user(ptr, 1, true)?;
+        Ok(Self {
+            ptr: NonNull::new(ptr).ok_or(SysErrCode::AddrNotValid)?,
+        })
+    }
+
+    pub fn write(&mut self, val: T) {
+        // SAFETY: the pointer was validated on creation. The old value is user data, thus it is not dropped
+        unsafe { self.ptr.write(val) }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        // SAFETY: the pointer was validated on creation
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T> SysCallArg for UserMut<T> {
+    fn decode(args: &mut ArgDecoder<'_>) -> SysCallRes<Self> {
+        Self::new(args.decode()?)
+    }
+}
+
+/// nullable pointer arguments. Null decodes to None, any other pointer must be valid
+impl<T> SysCallArg for Option<UserRef<T>> {
+    fn decode(args: &mut ArgDecoder<'_>) -> SysCallRes<Self> {
+        let ptr: *const T = args.decode()?;
+        if ptr.is_null() {
+            Ok(None)
+        } else {
+            UserRef::new(ptr).map(Some)
+        }
+    }
+}
+
+impl<T> SysCallArg for Option<UserMut<T>> {
+    fn decode(args: &mut ArgDecoder<'_>) -> SysCallRes<Self> {
+        let ptr: *mut T = args.decode()?;
+        if ptr.is_null() {
+            Ok(None)
+        } else {
+            UserMut::new(ptr).map(Some)
+        }
+    }
+}
+
+/// a valid, readable slice in user memory. Decoded from two arguments: the pointer and the number of ELEMENTS
+#[derive(Debug)]
+pub struct UserSlice<T> {
+    ptr: NonNull<T>,
+    len: usize,
+}
+
+impl<T> UserSlice<T> {
+    pub fn new(ptr: *const T, len: usize) -> SysCallRes<Self> {
+        check_user(ptr, len, false)?;
+        let ptr = if len == 0 {
+            NonNull::dangling()
+        } else {
+            NonNull::new(ptr as *mut T).ok_or(SysErrCode::AddrNotValid)?
+        };
+        Ok(Self { ptr, len })
+    }
+
+    /// validates a FatPtr, which was itself read from user memory
+    pub fn from_fat(fat: &FatPtr<T>) -> SysCallRes<Self> {
+        Self::new(fat.thin, fat.size)
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        // SAFETY: the range was validated on creation
+        unsafe { core::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<T> SysCallArg for UserSlice<T> {
+    fn decode(args: &mut ArgDecoder<'_>) -> SysCallRes<Self> {
+        let (ptr, len) = args.decode()?;
+        Self::new(ptr, len)
+    }
+}
+
+/// a valid, writable slice in user memory. Decoded from two arguments: the pointer and the number of ELEMENTS
+#[derive(Debug)]
+pub struct UserSliceMut<T> {
+    ptr: NonNull<T>,
+    len: usize,
+}
+
+impl<T> UserSliceMut<T> {
+    pub fn new(ptr: *mut T, len: usize) -> SysCallRes<Self> {
+        check_user(ptr, len, true)?;
+        let ptr = if len == 0 {
+            NonNull::dangling()
+        } else {
+            NonNull::new(ptr).ok_or(SysErrCode::AddrNotValid)?
+        };
+        Ok(Self { ptr, len })
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        // SAFETY: the range was validated on creation
+        unsafe { core::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        // SAFETY: the range was validated on creation
+        unsafe { core::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<T> SysCallArg for UserSliceMut<T> {
+    fn decode(args: &mut ArgDecoder<'_>) -> SysCallRes<Self> {
+        let (ptr, len) = args.decode()?;
+        Self::new(ptr, len)
+    }
+}
+
+/// checks, that bytes are valid utf8 of at most max bytes
+pub fn parse_str(bytes: &[u8], max: usize) -> SysCallRes<&str> {
+    if bytes.len() > max {
+        return Err(SysErrCode::InvalidArg);
+    }
+    str::from_utf8(bytes).map_err(|_| SysErrCode::InvalidArg)
+}
+
+/// the string in front of the first 0 byte of bytes, which must lie within the first max + 1 bytes
+pub fn parse_cstr(bytes: &[u8], max: usize) -> SysCallRes<&str> {
+    let len = bytes
+        .iter()
+        .take(max.saturating_add(1))
+        .position(|b| *b == 0)
+        .ok_or(SysErrCode::InvalidArg)?;
+    parse_str(&bytes[..len], max)
+}
+
+/// a valid utf8 string of at most MAX bytes in user memory. Decoded from two arguments: the pointer and the length in bytes.
+/// Strings, which are too long or not valid utf8, are rejected with SysErrCode::InvalidArg
+#[derive(Debug)]
+pub struct UserStr<const MAX: usize = PATH_MAX> {
+    bytes: UserSlice<u8>,
+}
+
+impl UserStr {
+    pub fn new(ptr: *const u8, len: usize) -> SysCallRes<Self> {
+        Self::new_bounded(ptr, len)
+    }
+
+    /// validates a FatPtr, which was itself read from user memory
+    pub fn from_fat(fat: &FatPtr<u8>) -> SysCallRes<Self> {
+        Self::new(fat.thin, fat.size)
+    }
+}
+
+impl<const MAX: usize> UserStr<MAX> {
+    /// like new, but with any bound
+    pub fn new_bounded(ptr: *const u8, len: usize) -> SysCallRes<Self> {
+        // checked first, such that huge lengths are not walked page by page
+        if len > MAX {
+            return Err(SysErrCode::InvalidArg);
+        }
+        let bytes = UserSlice::new(ptr, len)?;
+        parse_str(bytes.as_slice(), MAX)?;
+        Ok(Self { bytes })
+    }
+
+    pub fn as_str(&self) -> &str {
+        // SAFETY: the bytes were checked on creation
+        unsafe { str::from_utf8_unchecked(self.bytes.as_slice()) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+impl<const MAX: usize> SysCallArg for UserStr<MAX> {
+    fn decode(args: &mut ArgDecoder<'_>) -> SysCallRes<Self> {
+        let (ptr, len) = args.decode()?;
+        Self::new_bounded(ptr, len)
+    }
+}
+
+/// a valid, 0 terminated utf8 string of at most MAX bytes, without the terminator, in user memory.
+/// Decoded from a single pointer. Memory is only validated up to the terminator, thus the string may end right before an unmapped page
+#[derive(Debug)]
+pub struct UserCStr<const MAX: usize = PATH_MAX> {
+    bytes: UserSlice<u8>,
+}
+
+impl UserCStr {
+    pub fn new(ptr: *const u8) -> SysCallRes<Self> {
+        Self::new_bounded(ptr)
+    }
+}
+
+impl<const MAX: usize> UserCStr<MAX> {
+    /// like new, but with any bound
+    pub fn new_bounded(ptr: *const u8) -> SysCallRes<Self> {
+        if ptr.is_null() {
+            return Err(SysErrCode::AddrNotValid);
+        }
+        let page_size = Size4KiB::SIZE as usize;
+        let mut len = 0;
+        // validates the rest of one page at a time, until the terminator is found
+        loop {
+            let addr = ptr
+                .addr()
+                .checked_add(len)
+                .ok_or(SysErrCode::AddrNotValid)?;
+            let chunk = (page_size - addr % page_size).min(MAX + 1 - len);
+            check_user_range(addr, chunk, false)?;
+            // SAFETY: the range was just validated
+            let bytes = unsafe { core::slice::from_raw_parts(ptr.add(len), chunk) };
+            if let Some(end) = bytes.iter().position(|b| *b == 0) {
+                len += end;
+                break;
+            }
+            len += chunk;
+            if len > MAX {
+                return Err(SysErrCode::InvalidArg);
+            }
+        }
+        let bytes = UserSlice::new(ptr, len)?;
+        parse_str(bytes.as_slice(), MAX)?;
+        Ok(Self { bytes })
+    }
+
+    pub fn as_str(&self) -> &str {
+        // SAFETY: the bytes were checked on creation
+        unsafe { str::from_utf8_unchecked(self.bytes.as_slice()) }
+    }
+
+    /// the length without the terminator
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+impl<const MAX: usize> SysCallArg for UserCStr<MAX> {
+    fn decode(args: &mut ArgDecoder<'_>) -> SysCallRes<Self> {
+        Self::new_bounded(args.decode()?)
+    }
+}
+
+/// a list of 0 terminated utf8 strings in user memory, like the arguments or the environment of a new process.
+/// It holds at most ARG_MAX bytes and the terminator of the last entry is optional
+#[derive(Debug)]
+pub struct UserStrList {
+    bytes: UserSlice<u8>,
+}
+
+impl UserStrList {
+    pub fn new(ptr: *const u8, len: usize) -> SysCallRes<Self> {
+        Ok(Self {
+            bytes: UserStr::<ARG_MAX>::new_bounded(ptr, len)?.bytes,
+        })
+    }
+
+    /// validates a FatPtr, which was itself read from user memory
+    pub fn from_fat(fat: &FatPtr<u8>) -> SysCallRes<Self> {
+        Self::new(fat.thin, fat.size)
+    }
+
+    /// the raw list, including the terminators
+    pub fn as_bytes(&self) -> &[u8] {
+        self.bytes.as_slice()
+    }
+
+    pub fn as_str(&self) -> &str {
+        // SAFETY: the bytes were checked on creation
+        unsafe { str::from_utf8_unchecked(self.bytes.as_slice()) }
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &str> {
+        self.as_str().split_terminator('\0')
+    }
+
+    /// the length in bytes, including the terminators
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+impl SysCallArg for UserStrList {
+    fn decode(args: &mut ArgDecoder<'_>) -> SysCallRes<Self> {
+        let (ptr, len) = args.decode()?;
+        Self::new(ptr, len)
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use alloc::{boxed::Box, vec};
+
+    use os_macros::kernel_test;
+
+    use super::*;
+    use crate::kernel::crypto::entropy;
+
+    #[kernel_test]
+    fn decode_args() {
+        let ctx = SysCallCtx {
+            rdi: 3,
+            rsi: u64::MAX,
+            rdx: (OpenOptions::READ | OpenOptions::WRITE).bits() as u64,
+            ..Default::default()
+        };
+        let (fd, timeout, flags): (u32, i64, OpenOptions) = decode(&ctx).unwrap();
+        assert_eq!(fd, 3);
+        assert_eq!(timeout, -1);
+        assert_eq!(flags, OpenOptions::READ | OpenOptions::WRITE);
+
+        let mut decoder = ArgDecoder::new(&ctx);
+        for _ in 0..MAX_ARGS {
+            assert!(decoder.raw().is_ok());
+        }
+        assert_eq!(decoder.raw(), Err(SysErrCode::InvalidArg));
+    }
+
+    #[kernel_test]
+    fn reject_bad_user_memory() {
+        // null, kernel and overflowing ranges
+        assert_eq!(
+            UserSlice::<u8>::new(core::ptr::null(), 1).err(),
+            Some(SysErrCode::AddrNotValid)
+        );
+        assert_eq!(
+            check_user_range(usize::MAX - 1, 16, false),
+            Err(SysErrCode::AddrNotValid)
+        );
+        let kernel = Box::new(0_u64);
+        assert_eq!(
+            UserRef::new(&*kernel as *const u64).err(),
+            Some(SysErrCode::AddrNotValid)
+        );
+        let buf = vec![0_u8; 16];
+        assert_eq!(
+            UserSlice::new(buf.as_ptr(), buf.len()).err(),
+            Some(SysErrCode::AddrNotValid)
+        );
+
+        // empty ranges and null optional pointers are fine
+        assert!(
+            UserSlice::<u8>::new(core::ptr::null(), 0)
+                .unwrap()
+                .is_empty()
+        );
+        let ctx = SysCallCtx::default();
+        let (info,): (Option<UserMut<u32>>,) = decode(&ctx).unwrap();
+        assert!(info.is_none());
+    }
+
+    #[kernel_test]
+    fn fuzz_strings() {
+        // bytes, which are likely to end up at interesting positions: terminators, ascii, utf8 lead and continuation bytes
+        const INTERESTING: [u8; 6] = [0, b'a', 0x7f, 0x80, 0xc3, 0xff];
+        for _ in 0..2_000 {
+            let mut bytes = [0_u8; 48];
+            entropy::fill(&mut bytes);
+            let seed = entropy::random_u64();
+            let len = (seed % (bytes.len() as u64 + 1)) as usize;
+            let max = ((seed >> 8) % 40) as usize;
+            for byte in bytes.iter_mut().filter(|byte| **byte & 1 == 0) {
+                *byte = INTERESTING[*byte as usize % INTERESTING.len()];
+            }
+            let bytes = &bytes[..len];
+
+            match parse_str(bytes, max) {
+                Ok(s) => assert_eq!(s.as_bytes(), bytes),
+                Err(e) => {
+                    assert_eq!(e, SysErrCode::InvalidArg);
+                    assert!(len > max || str::from_utf8(bytes).is_err());
+                }
+            }
+            match parse_cstr(bytes, max) {
+                Ok(s) => {
+                    assert!(s.len() <= max && !s.contains('\0'));
+                    assert_eq!(bytes[s.len()], 0);
+                }
+                Err(e) => assert_eq!(e, SysErrCode::InvalidArg),
+            }
+
+            // random pointers never reach user memory in a kernel thread
+            let ptr = seed as usize as *const u8;
+            assert!(UserStr::new(ptr, len.max(1)).is_err());
+            assert!(UserCStr::new(ptr).is_err());
+            assert!(UserStrList::new(ptr, len.max(1)).is_err());
+        }
+
+        assert_eq!(
+            UserStr::new(core::ptr::null(), PATH_MAX + 1).err(),
+            Some(SysErrCode::InvalidArg)
+        );
+        assert_eq!(
+            UserStrList::new(core::ptr::null(), ARG_MAX + 1).err(),
+            Some(SysErrCode::InvalidArg)
+        );
+        assert_eq!(parse_cstr(b"abc", 8), Err(SysErrCode::InvalidArg));
+        assert_eq!(parse_cstr(b"abc\0def", 3), Ok("abc"));
+        assert_eq!(parse_cstr(b"abcd\0", 3), Err(SysErrCode::InvalidArg));
+        let empty = UserStrList::new(core::ptr::null(), 0).unwrap();
+        assert_eq!(empty.entries().count(), 0);
+    }
+}
@@ -3,49 +3,86 @@ use alloc::{
     sync::Arc,
     vec::{self, Vec},
 };
-use core::{str, sync::atomic::Ordering, time::Duration};
+use core::{sync::atomic::Ordering, time::Duration};
 
+use os_macros::syscall;
 use tinyos_abi::{
+    consts::{DBG_MAX, RING_MAX_DATA, RING_MAX_ENTRIES, SIGCONT, SIGSTOP, UTIME_NOW, UTIME_OMIT},
     flags::{
         NodePermissions,
         OpenOptions,
         PageTableFlags,
+        RandomFlags,
+        SpawnFlags,
         TaskStateChange,
         TaskWaitOptions,
         WaitOptions,
     },
-    types::{FDAction, FStat, FatPtr, FileDescriptor, SysCallRes, SysErrCode},
+    types::{
+        ChildInfo,
+        FDAction,
+        FStat,
+        FatPtr,
+        FileDescriptor,
+        FileTimes,
+        IdType,
+        MemInfo,
+        Resource,
+        SpawnAttr,
+        SysCallDispatch,
+        SysCallRes,
+        SysErrCode,
+    },
 };
 
 use crate::{
     arch::{
+        self,
         interrupt::gdt::get_kernel_selectors,
         mem::{PageSize, Size4KiB, VirtAddr},
-        x86::current_time,
     },
     args,
     drivers::wait_manager::{add_queue, remove_queue, wait_self},
     eprintln,
     kernel::{
-        abi::syscalls::utils::{__sys_yield, valid_ptr},
-        devices::tty::Pipe,
-        fd::{FPerms, File, FileBuilder, FileRepr},
+        abi::syscalls::{
+            args::{UserMut, UserRef, UserSlice, UserSliceMut, UserStr, UserStrList},
+            utils::__sys_yield,
+        },
+        crypto::{entropy, manifest},
+        devices::tty::{Pipe, session},
+        elf::vdso,
+        fd::{FDFlags, FPerms, File, FileBuilder, FileHandle, FileRepr},
         fs::{
             self,
             Path,
             PathBuf,
             builtin_bins::{BUILTIN_MARKER, execute},
         },
-        io::Read,
+        io::{Read, ring::Ring},
         mem::{
+            addr::UserVirtAddr,
             align_up,
-            paging::{map_region, map_region_into, unmap_region},
+            paging::{active_page_flags, map_region_into, unmap_region},
+            vma::{self, Vma, VmaBacking},
         },
         threading::{
             self,
+            children::ChildSelector,
+            namespace,
             schedule::{self, add_built_task, current_task},
             spawn_fn,
-            task::{Arg, Args, ProcessID, TaskBuilder, TaskRepr, TaskState},
+            task::{
+                Arg,
+                Args,
+                Init,
+                ProcessGroupID,
+                ProcessID,
+                Task,
+                TaskBuilder,
+                TaskRepr,
+                TaskState,
+            },
             tls,
             trampoline::TaskExitInfo,
             wait::{
@@ -58,6 +95,7 @@ use crate::{
                 queues::{GenericWaitQueue, WaitQueue},
             },
         },
+        time::{Instant, uptime},
     },
     println,
     serial_print,
@@ -67,20 +105,20 @@ use crate::{
 // all lengths denote the number of ELEMENTS, not the number of bytes.
 
 // TODO we should likely check if the corresponding file is already open in the task. If this is true, we should hand out the corresponding fd.
-// However this necessitates that we also store the Path either in File or in FDMap.
-pub fn open(path: *const u8, len: usize, flags: OpenOptions) -> SysCallRes<FileDescriptor> {
-    if !valid_ptr(path, len) {
-        return Err(SysErrCode::AddrNotValid);
-    }
-    let p = unsafe { str::from_raw_parts(path, len) };
-    let p = Path::new(p);
-    let f = fs::open(p, flags).map_err(|e| e.into())?;
-    Ok(tls::task_data()
+// However this necessitates that we also store the Path either in File or in FDTable.
+#[syscall(number = SysCallDispatch::Open)]
+pub fn open(path: UserStr, flags: OpenOptions) -> SysCallRes<FileDescriptor> {
+    let current = tls::task_data()
         .current_thread()
-        .ok_or(SysErrCode::NoProcess)?
-        .add_next_file(f))
+        .ok_or(SysErrCode::NoProcess)?;
+    let p = current.core.resolve(Path::new(path.as_str()));
+    let f = fs::open(&p, flags)?;
+    current
+        .add_next_file(FileHandle::from(f).with_fd_flags(flags.into()))
+        .map_err(|e| e.into())
 }
 
+#[syscall(number = SysCallDispatch::Close)]
 pub fn close(fd: FileDescriptor) -> SysCallRes<()> {
     tls::task_data()
         .current_thread()
@@ -90,14 +128,12 @@ pub fn close(fd: FileDescriptor) -> SysCallRes<()> {
     Ok(())
 }
 
-pub fn read(fd: FileDescriptor, buf: *mut u8, len: usize, timeout: i64) -> SysCallRes<isize> {
-    if !valid_ptr(buf, len) {
-        return Err(SysErrCode::AddrNotValid);
-    }
+#[syscall(number = SysCallDispatch::Read)]
+pub fn read(fd: FileDescriptor, mut buf: UserSliceMut<u8>, timeout: i64) -> SysCallRes<isize> {
     let current_task = tls::task_data()
         .current_thread()
         .ok_or(SysErrCode::NoProcess)?;
-    let b = unsafe { &mut *core::ptr::slice_from_raw_parts_mut(buf, len) };
+    let b = buf.as_mut_slice();
 
     let file = current_task.fd(fd).ok_or(SysErrCode::BadFd)?;
 
@@ -106,7 +142,7 @@ pub fn read(fd: FileDescriptor, buf: *mut u8, len: usize, timeout: i64) -> SysCa
         return Ok(0);
     }
 
-    let n = file.read_continuous(b).map_err(|e| e.into())?;
+    let n = file.read_lending(b)?;
     if n > 0 || timeout == 0 {
         return Ok(n as isize);
     }
@@ -114,7 +150,7 @@ pub fn read(fd: FileDescriptor, buf: *mut u8, len: usize, timeout: i64) -> SysCa
     // fast path failed, we will now try to wait until timeout
     // OR until the watched file is updated, if the path is known
     let mut conditions = Vec::new();
-    let until = Duration::from_millis(timeout as u64) + current_time();
+    let until = Instant::now() + Duration::from_millis(timeout as u64);
     if timeout > 0 {
         conditions.push(QueuTypeCondition::with_cond(
             QueueType::Timer,
@@ -134,9 +170,9 @@ pub fn read(fd: FileDescriptor, buf: *mut u8, len: usize, timeout: i64) -> SysCa
     }
 
     loop {
-        let n = file.read_continuous(b).map_err(|e| e.into())?;
+        let n = file.read_lending(b)?;
 
-        if n == 0 && (timeout < 0 || until > current_time()) {
+        if n == 0 && (timeout < 0 || until > Instant::now()) {
             wait_self(&conditions).ok_or(SysErrCode::WouldBlock)?;
         } else {
             // TODO we do not want to do this for EVERY queue. Some files (like keyboard) may be queried very often.
@@ -152,21 +188,19 @@ pub fn read(fd: FileDescriptor, buf: *mut u8, len: usize, timeout: i64) -> SysCa
     }
 }
 
-pub fn write(fd: FileDescriptor, buf: *const u8, len: usize) -> SysCallRes<isize> {
-    if !valid_ptr(buf, len) {
-        return Err(SysErrCode::AddrNotValid);
-    }
-    let b = unsafe { &*core::ptr::slice_from_raw_parts(buf, len) };
+#[syscall(number = SysCallDispatch::Write)]
+pub fn write(fd: FileDescriptor, buf: UserSlice<u8>) -> SysCallRes<isize> {
+    let b = buf.as_slice();
     let n = tls::task_data()
         .current_thread()
         .ok_or(SysErrCode::NoProcess)?
         .fd(fd)
         .ok_or(SysErrCode::BadFd)?
-        .write_continuous(b)
-        .map_err(|e| e.into())?;
+        .write_continuous(b)?;
     Ok(n as isize)
 }
 
+#[syscall(number = SysCallDispatch::Seek)]
 pub fn seek(fd: FileDescriptor, offset: usize) -> SysCallRes<()> {
     tls::task_data()
         .current_thread()
@@ -178,22 +212,86 @@ pub fn seek(fd: FileDescriptor, offset: usize) -> SysCallRes<()> {
     Ok(())
 }
 
-pub fn dup(old_fd: FileDescriptor, new_fd: i32) -> SysCallRes<FileDescriptor> {
+#[syscall(number = SysCallDispatch::SendFile)]
+pub fn send_file(
+    out_fd: FileDescriptor,
+    in_fd: FileDescriptor,
+    offset: Option<UserMut<usize>>,
+    len: usize,
+) -> SysCallRes<usize> {
     let current = tls::task_data()
         .current_thread()
         .ok_or(SysErrCode::NoProcess)?;
-    let next_fd = if new_fd >= 0 {
-        new_fd as FileDescriptor
+    let input = current.fd(in_fd).ok_or(SysErrCode::BadFd)?;
+    let output = current.fd(out_fd).ok_or(SysErrCode::BadFd)?;
+
+    if let Some(mut offset) = offset {
+        let offset = offset.get_mut();
+        let n = input.send_to(*offset, &output, len)?;
+        *offset += n;
+        Ok(n)
     } else {
-        current.next_fd()
-    };
+        input.send_continuous(&output, len).map_err(|e| e.into())
+    }
+}
+
+#[syscall(number = SysCallDispatch::GetCpu)]
+pub fn get_cpu(cpu: Option<UserMut<u32>>, node: Option<UserMut<u32>>) -> SysCallRes<()> {
+    let (current_cpu, current_node) = arch::current_cpu();
+    if let Some(mut cpu) = cpu {
+        cpu.write(current_cpu);
+    }
+    if let Some(mut node) = node {
+        node.write(current_node);
+    }
+    Ok(())
+}
+
+#[syscall(number = SysCallDispatch::Dup)]
+pub fn dup(old_fd: FileDescriptor, new_fd: i32) -> SysCallRes<FileDescriptor> {
+    let new_fd = (new_fd >= 0).then_some(new_fd as FileDescriptor);
+    tls::task_data()
+        .current_thread()
+        .ok_or(SysErrCode::NoProcess)?
+        .dup_fd(old_fd, new_fd, FDFlags::empty())
+        .map_err(|e| e.into())
+}
+
+#[syscall(number = SysCallDispatch::Dup2)]
+pub fn dup2(old_fd: FileDescriptor, new_fd: FileDescriptor) -> SysCallRes<FileDescriptor> {
+    let current = tls::task_data()
+        .current_thread()
+        .ok_or(SysErrCode::NoProcess)?;
+    if old_fd == new_fd {
+        // nothing to replace, but old_fd still needs to be valid
+        return current.fd(old_fd).map(|_| new_fd).ok_or(SysErrCode::BadFd);
+    }
+    current
+        .dup_fd(old_fd, Some(new_fd), FDFlags::empty())
+        .map_err(|e| e.into())
+}
 
-    let old = current.fd(old_fd).ok_or(SysErrCode::BadFd)?;
-    current.add_fd(next_fd, old);
-    Ok(next_fd)
+#[syscall(number = SysCallDispatch::Dup3)]
+pub fn dup3(
+    old_fd: FileDescriptor,
+    new_fd: FileDescriptor,
+    flags: u32,
+) -> SysCallRes<FileDescriptor> {
+    // undefined bits are rejected, instead of being dropped by the generic flag decoding
+    let flags = OpenOptions::from_bits(flags).ok_or(SysErrCode::InvalidArg)?;
+    if old_fd == new_fd || !flags.difference(OpenOptions::CLOEXEC).is_empty() {
+        return Err(SysErrCode::InvalidArg);
+    }
+    tls::task_data()
+        .current_thread()
+        .ok_or(SysErrCode::NoProcess)?
+        .dup_fd(old_fd, Some(new_fd), flags.into())
+        .map_err(|e| e.into())
 }
 
+#[syscall(number = SysCallDispatch::Yield)]
 pub fn yield_now() -> SysCallRes<()> {
+    schedule::stats::count_yield();
     let (cs, ss) = get_kernel_selectors();
     unsafe {
         __sys_yield(cs.0 as u64, ss.0 as u64);
@@ -204,6 +302,7 @@ pub fn yield_now() -> SysCallRes<()> {
 // This should kill the current PROCESS
 // TODO fix
 // --> need process exit first
+#[syscall(number = SysCallDispatch::Exit)]
 pub fn exit(status: i64) -> ! {
     post_event(WaitEvent::with_data(
         QueueType::Thread(tls::task_data().current_tid()),
@@ -218,19 +317,25 @@ pub fn exit(status: i64) -> ! {
 // This should kill the specified PROCESS
 // TODO fix
 // --> need process exit first
-pub fn kill(pid: u64, _signal: i64) -> SysCallRes<()> {
-    tls::task_data()
-        .kill_process(&pid.into())
-        .ok_or(SysErrCode::NoProcess)
+#[syscall(number = SysCallDispatch::Kill)]
+pub fn kill(pid: u64, signal: i64) -> SysCallRes<()> {
+    let pid = to_global_pid(pid)?;
+    match signal {
+        SIGSTOP => tls::task_data().stop_process(&pid),
+        SIGCONT => tls::task_data().continue_process(&pid),
+        _ => tls::task_data().kill_process(&pid),
+    }
+    .ok_or(SysErrCode::NoProcess)
 }
 
 // TODO zero out memory if necessary
+#[syscall(number = SysCallDispatch::Mmap)]
 pub fn mmap(len: usize, addr: *mut u8, flags: PageTableFlags, fd: i32) -> SysCallRes<*mut u8> {
     // TODO add a more sophisticated approach for managing address spaces
     let current = tls::task_data()
         .current_thread()
         .ok_or(SysErrCode::NoProcess)?;
-    let addr = if !valid_ptr(addr, len) {
+    let addr = if UserVirtAddr::from_ptr(addr, len).is_none() {
         serial_println!("assigning new mmap ptr");
         current
             .next_addr()
@@ -243,17 +348,21 @@ pub fn mmap(len: usize, addr: *mut u8, flags: PageTableFlags, fd: i32) -> SysCal
         addr
     };
 
-    let base_addr = VirtAddr::from_ptr(addr).align_up(Size4KiB::SIZE);
+    // aligning may push the end of the region out of user space
+    let base_addr = UserVirtAddr::try_new_range(
+        VirtAddr::from_ptr(addr).align_up(Size4KiB::SIZE).as_u64(),
+        len as u64,
+    )
+    .ok_or(SysErrCode::AddrNotAvail)?
+    .as_virt();
     serial_println!("mmap at addr {:#x}", base_addr.as_u64());
 
     if fd >= 0 {
         // map file stored at fd into memory.
         // as the file is opened already, the mapping already exists in this address space.
         // we must copy it to the specified user accesible address
-        let (from, true_len) = current
-            .fd(fd as FileDescriptor)
-            .ok_or(SysErrCode::BadFd)?
-            .as_raw_parts();
+        let file = current.fd(fd as FileDescriptor).ok_or(SysErrCode::BadFd)?;
+        let (from, true_len) = file.as_raw_parts();
 
         serial_println!(
             "trying to map file to addr {:#x}, from {:#x}",
@@ -284,6 +393,17 @@ pub fn mmap(len: usize, addr: *mut u8, flags: PageTableFlags, fd: i32) -> SysCal
             }
             Ok(v) => {
                 serial_println!("the addr is: {:#x}", v);
+                // the memory of a ring is owned by it, thus the vma keeps it alive until the ring is unmapped
+                let backing = match file.as_ring().and_then(Ring::mapping) {
+                    Some(ring) => VmaBacking::Ring(ring),
+                    None => VmaBacking::File(file.get_path().map(PathBuf::from)),
+                };
+                current.core.vmas.write().insert(Vma::new(
+                    v,
+                    len.min(true_len),
+                    flags | PageTableFlags::PRESENT,
+                    backing,
+                ));
                 return Ok(v.as_mut_ptr());
             }
         }
@@ -293,14 +413,20 @@ pub fn mmap(len: usize, addr: *mut u8, flags: PageTableFlags, fd: i32) -> SysCal
             base_addr.as_u64(),
             len
         );
-        // map new (anonymous) region initialized with 0
-        if let Err(e) = map_region(
+        // map new (anonymous) region initialized with 0. Its pages are only backed on first touch, where they are
+        // charged to the resource group of the process, see paging::resolve_demand_fault
+        let vma = Vma::new(
             base_addr,
             len,
             flags | PageTableFlags::PRESENT,
-            current.pagedir(),
-        ) {
-            serial_println!("got an err during mmmap: {:?}", e);
+            VmaBacking::Anonymous,
+        );
+        let mut vmas = current.core.vmas.write();
+        if vmas
+            .iter()
+            .any(|other| other.start() < vma.end() && vma.start() < other.end())
+        {
+            serial_println!("mmap overlaps an existing mapping");
             // try to free space in task mmmap space again
             _ = current.next_addr().compare_exchange(
                 align_up(addr as usize, Size4KiB::SIZE as usize) + len,
@@ -310,46 +436,81 @@ pub fn mmap(len: usize, addr: *mut u8, flags: PageTableFlags, fd: i32) -> SysCal
             );
             return Err(SysErrCode::AddrNotAvail);
         }
+        vmas.insert(vma);
     }
     Ok(base_addr.as_mut_ptr())
 }
 
+#[syscall(number = SysCallDispatch::Munmap)]
 pub fn munmap(addr: *mut u8, len: usize) -> SysCallRes<()> {
     // TODO this should free the underlying memory iff it was anonmyously mapped, ie iff it is not shared elsewhere
-    if !valid_ptr(addr, len) {
-        return Err(SysErrCode::AddrNotValid);
+    let base = UserVirtAddr::from_ptr(addr, len)
+        .ok_or(SysErrCode::AddrNotValid)?
+        .as_virt()
+        .align_up(Size4KiB::SIZE);
+    // the pages of the vdso are shared with every other process
+    if vdso::overlaps(base, len) {
+        return Err(SysErrCode::AccessDenied);
     }
-
-    let base = VirtAddr::from_ptr(addr).align_up(Size4KiB::SIZE);
     let current = tls::task_data()
         .current_thread()
         .ok_or(SysErrCode::NoProcess)?;
 
-    unmap_region(base, len, current.pagedir()).map_err(|_| SysErrCode::AddrNotAvail)
+    // the vmas go first, such that no page of the range is faulted in again while it is unmapped.
+    // They are dropped after the pages are unmapped, as they may own the mapped memory, like for rings
+    let (anonymous, removed) = {
+        let mut vmas = current.core.vmas.write();
+        let anonymous = vmas
+            .find(base)
+            .is_some_and(|vma| vma.backing() == &VmaBacking::Anonymous);
+        (anonymous, vmas.remove(base, len))
+    };
+    // only the pages touched since mmap were backed and charged
+    let pages = (base.as_u64()..base.as_u64() + len as u64)
+        .step_by(Size4KiB::SIZE as usize)
+        .filter(|&page| anonymous && active_page_flags(VirtAddr::new(page)).is_some())
+        .count();
+    if unmap_region(base, len, current.pagedir()).is_err() {
+        // pages of the range may still be mapped, thus the memory owned by their vmas is leaked rather than freed
+        core::mem::forget(removed);
+        return Err(SysErrCode::AddrNotAvail);
+    }
+    if anonymous {
+        let released = current
+            .core
+            .charged_frames
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |charged| {
+                Some(charged.saturating_sub(pages))
+            })
+            .unwrap();
+        current
+            .core
+            .resource_group
+            .read()
+            .uncharge_frames(released.min(pages));
+    }
+    drop(removed);
+    Ok(())
 }
 
 // TODO handle args
 /// spawns a new thread in a new address space from some provided binary.
-pub fn spawn(elf_data: *const u8, len: usize) -> SysCallRes<()> {
-    if !valid_ptr(elf_data, len) {
-        return Err(SysErrCode::AddrNotValid);
-    }
-    let bytes = unsafe { &*core::ptr::slice_from_raw_parts(elf_data, len) };
-    let task = TaskBuilder::from_bytes(bytes)
-        .map_err(|_| SysErrCode::BadMsg)?
+#[syscall(number = SysCallDispatch::Spawn)]
+pub fn spawn(elf_data: UserSlice<u8>) -> SysCallRes<()> {
+    let task = TaskBuilder::from_bytes(elf_data.as_slice())?
         .with_default_files(false)
-        .as_usr()
-        .map_err(|_| SysErrCode::BadMsg)?
+        .as_usr()?
         .build();
     schedule::add_built_task(task);
 
     Ok(())
 }
 
+#[syscall(number = SysCallDispatch::WaitTime)]
 pub fn waittime(duration: u64) -> SysCallRes<()> {
     let conditions = &[QueuTypeCondition::with_cond(
         QueueType::Timer,
-        WaitCondition::Time(Duration::from_millis(duration) + current_time()),
+        WaitCondition::Time(Instant::now() + Duration::from_millis(duration)),
     )];
     wait_self(conditions).ok_or(SysErrCode::WouldBlock)
 }
@@ -363,6 +524,7 @@ pub fn waittime(duration: u64) -> SysCallRes<()> {
 // - any child exit?
 // ...?
 // for now just allow W_EXIT
+#[syscall(number = SysCallDispatch::WaitPID)]
 pub fn wait_pid(
     id: u64,
     timeout: i64,
@@ -370,14 +532,15 @@ pub fn wait_pid(
     tw_flags: TaskWaitOptions,
 ) -> SysCallRes<TaskStateChange> {
     if !tw_flags.contains(TaskWaitOptions::W_EXIT) {
-        return Err(SysErrCode::Cancelled);
+        return Err(SysErrCode::NotSupported);
     }
     if timeout == 0 {
         return Ok(TaskStateChange::empty());
     }
+    let id = to_global_pid(id)?.0;
     let mut conditions = Vec::new();
     if timeout > 0 {
-        let until = Duration::from_millis(timeout as u64) + current_time();
+        let until = Instant::now() + Duration::from_millis(timeout as u64);
         conditions.push(QueuTypeCondition::with_cond(
             QueueType::Timer,
             WaitCondition::Time(until),
@@ -422,6 +585,7 @@ pub fn wait_pid(
             {
                 Some(TaskState::Running) | Some(TaskState::Ready) => TaskStateChange::WAKEUP,
                 Some(TaskState::Blocking) | Some(TaskState::Sleeping) => TaskStateChange::BLOCK,
+                Some(TaskState::Stopped) => TaskStateChange::STOP,
                 None | Some(TaskState::Zombie) => TaskStateChange::EXIT,
             }
         });
@@ -429,28 +593,112 @@ pub fn wait_pid(
     r
 }
 
+/// waits for a state change of a child of the current process, see syscalls.txt
+#[syscall(number = SysCallDispatch::WaitId)]
+pub fn wait_id(
+    id_type: u64,
+    id: u64,
+    mut info: Option<UserMut<ChildInfo>>,
+    w_flags: WaitOptions,
+    tw_flags: TaskWaitOptions,
+) -> SysCallRes<()> {
+    let wanted = tw_flags
+        & (TaskWaitOptions::W_EXIT | TaskWaitOptions::W_STOP | TaskWaitOptions::W_CONTINUE);
+    if wanted.is_empty() {
+        return Err(SysErrCode::InvalidArg);
+    }
+    let current = tls::task_data()
+        .current_thread()
+        .ok_or(SysErrCode::NoProcess)?;
+    let ns = current.core.pid_ns.as_deref();
+    let id_type = IdType::try_from(id_type).map_err(|_| SysErrCode::InvalidArg)?;
+    let id = match id_type {
+        IdType::Pid => {
+            namespace::global_pid(ns, ProcessID(id))
+                .ok_or(SysErrCode::NoChild)?
+                .0
+        }
+        _ => id,
+    };
+    let selector = ChildSelector::new(id_type, id);
+    let parent = current.pid();
+    let consume = !w_flags.contains(WaitOptions::NOWAIT);
+
+    // the wait condition can only encode the parent and the wanted changes, thus a change of a child,
+    // which is not selected, causes a spurious wakeup, after which we simply wait again
+    let conditions = [QueuTypeCondition::with_cond(
+        QueueType::Children(parent),
+        WaitCondition::Generic(
+            (parent.0 << 16) | wanted.bits() as u64,
+            Box::into_raw(Box::new(|val: u64| {
+                tls::task_data().has_pending_child(
+                    &ProcessID(val >> 16),
+                    TaskWaitOptions::from_bits_truncate(val as u16),
+                )
+            })),
+        ),
+    )];
+    loop {
+        let (pid, event) = match tls::task_data().collect_child(&parent, &selector, wanted, consume)
+        {
+            None => return Err(SysErrCode::NoChild),
+            Some(Some(change)) => change,
+            Some(None) => {
+                if w_flags.contains(WaitOptions::NOBLOCK) {
+                    return Err(SysErrCode::WouldBlock);
+                }
+                let q_type = QueueType::Children(parent);
+                add_queue(
+                    QueueHandle::from_owned(Box::new(GenericWaitQueue::new()) as Box<dyn WaitQueue>),
+                    q_type.clone(),
+                );
+                let r = wait_self(&conditions);
+                remove_queue(&q_type);
+                r.ok_or(SysErrCode::NoProcess)?;
+                continue;
+            }
+        };
+        if let Some(info) = &mut info {
+            // children always live in the namespace of their parent or one nested in it
+            info.write(event.info(namespace::local_pid(ns, pid).unwrap_or(pid)));
+        }
+        return Ok(());
+    }
+}
+
+#[syscall(number = SysCallDispatch::EventFD)]
 pub fn eventfd() -> SysCallRes<FileDescriptor> {
     todo!()
 }
 
+#[syscall(number = SysCallDispatch::GetPID)]
 pub fn get_pid() -> SysCallRes<u64> {
-    Ok(tls::task_data()
+    let current = tls::task_data()
         .current_thread()
-        .ok_or(SysErrCode::NoProcess)?
-        .pid()
-        .0)
+        .ok_or(SysErrCode::NoProcess)?;
+    namespace::local_pid(current.core.pid_ns.as_deref(), current.pid())
+        .map(|pid| pid.0)
+        .ok_or(SysErrCode::NoProcess)
 }
 
-pub fn serial(buf: *const u8, len: usize) -> SysCallRes<()> {
-    if !valid_ptr(buf, len) {
-        return Err(SysErrCode::AddrNotValid);
-    }
-    let str = unsafe { str::from_raw_parts(buf, len) };
-    serial_print!("{}", str);
+/// translates pid, as seen from the pid namespace of the caller, into the global pid.
+/// Processes outside of the namespace do not exist for the caller
+fn to_global_pid(pid: u64) -> SysCallRes<ProcessID> {
+    let current = tls::task_data()
+        .current_thread()
+        .ok_or(SysErrCode::NoProcess)?;
+    namespace::global_pid(current.core.pid_ns.as_deref(), ProcessID(pid))
+        .ok_or(SysErrCode::NoProcess)
+}
+
+#[syscall(number = SysCallDispatch::Dbg)]
+pub fn serial(msg: UserStr<DBG_MAX>) -> SysCallRes<()> {
+    serial_print!("{}", msg.as_str());
     Ok(())
 }
 
 // TODO
+#[syscall(number = SysCallDispatch::Fork)]
 pub fn fork() -> SysCallRes<bool> {
     // procedure:
     // - copy relevant structures from current task into new task (devices, privilege, ....)
@@ -461,128 +709,211 @@ pub fn fork() -> SysCallRes<bool> {
     Err(SysErrCode::OpDenied)
 }
 
+#[syscall(number = SysCallDispatch::Execve)]
 pub fn execve(
-    path: *const u8,
-    len: usize,
-    arg: *const FatPtr<u8>,
-    env: *const FatPtr<u8>,
+    path: UserStr,
+    arg: UserRef<FatPtr<u8>>,
+    env: UserRef<FatPtr<u8>>,
 ) -> SysCallRes<u64> {
-    Err(SysErrCode::OpDenied)
+    let arg_data = UserStrList::from_fat(arg.get())?;
+    let env_data = UserStrList::from_fat(env.get())?;
+    let current = current_task().map_err(|_| SysErrCode::NoProcess)?;
+    let path = current
+        .core
+        .resolve_binary(Path::new(path.as_str()))
+        .ok_or(SysErrCode::NoFile)?;
+    let new = open_executable(&path, &arg_data, &env_data)?.close_exec_files();
+    spawn_executable(&current, new, &arg_data, &env_data)
 }
 
-// essentially posix_spawn
-pub fn spawn_process(
-    path: *const u8,
-    len: usize,
-    arg: *const FatPtr<u8>,
-    env: *const FatPtr<u8>,
-    fd_actions: *const FatPtr<FDAction>,
-) -> SysCallRes<u64> {
-    if !valid_ptr(path, len)
-        || !valid_ptr(arg, 1)
-        || !valid_ptr(env, 1)
-        || !valid_ptr(fd_actions, 1)
-    {
-        return Err(SysErrCode::AddrNotValid);
-    }
+/// an opened executable, to be spawned by execve and spawn_process
+struct Executable<'a> {
+    builder: TaskBuilder<Task, Init<'a>>,
+    is_builtin: bool,
+}
 
-    let arg_data = unsafe { &*arg };
-    let env_data = unsafe { &*env };
-    let actions = unsafe { &*fd_actions };
+impl Executable<'_> {
+    fn close_exec_files(mut self) -> Self {
+        self.builder = self.builder.close_exec_files();
+        self
+    }
+}
 
-    let path = unsafe { str::from_raw_parts(path, len) };
-    let bin = fs::open(Path::new(path), OpenOptions::READ | OpenOptions::EXECUTE)
-        .map_err(|e| e.into())?;
-    let mut buf = Vec::new();
-    let bytes = bin.read_to_end(&mut buf, 0).map_err(|e| e.into())?;
-    let is_builtin = bytes == BUILTIN_MARKER.len() && &buf[..bytes] == BUILTIN_MARKER;
+/// opens the binary at path, which must be executable by the caller, with the files of the caller.
+/// Elf images are not read here, but streamed in while the task is built, thus they may live on any filesystem
+fn open_executable<'a>(
+    path: &Path,
+    arg_data: &UserStrList,
+    env_data: &UserStrList,
+) -> SysCallRes<Executable<'a>> {
+    let bin = fs::open(path, OpenOptions::READ | OpenOptions::EXECUTE)?;
+    // not every filesystem checks the execute permission on open
+    if !bin.fstat().permissions.x() {
+        return Err(SysErrCode::AccessDenied);
+    }
+    let mut marker = [0; BUILTIN_MARKER.len() + 1];
+    let bytes = bin.read(&mut marker, 0)?;
+    let is_builtin = &marker[..bytes] == BUILTIN_MARKER;
+    // builtins run in kernel mode, which a nosuid mount must not grant
+    if is_builtin {
+        if fs::mount_options_at(path)?.contains(fs::MountOptions::NO_SUID) {
+            return Err(SysErrCode::AccessDenied);
+        }
+        // the image of a builtin only holds the marker, its code is part of the kernel. Pinning the image in the
+        // manifest thus pins which paths may run as builtins
+        let mut image = Vec::new();
+        let n = bin.read_to_end(&mut image, 0)?;
+        manifest::verify_privileged(path, &image[..n]).map_err(|_| SysErrCode::AccessDenied)?;
+    }
 
     // builtin bins (mainly for testing, ...)
-    let mut new = if is_builtin {
+    let builder = if is_builtin {
         // copy args to heap
-        let arg_container = if valid_ptr(arg_data.thin, arg_data.size) {
-            let slice = unsafe { core::slice::from_raw_parts(arg_data.thin, arg_data.size) };
-            Some(slice.to_vec().into_boxed_slice())
-        } else {
-            None
-        };
-        let env_container = if valid_ptr(env_data.thin, env_data.size) {
-            let slice = unsafe { core::slice::from_raw_parts(env_data.thin, env_data.size) };
-            Some(slice.to_vec().into_boxed_slice())
-        } else {
-            None
-        };
+        let arg_container =
+            (!arg_data.is_empty()).then(|| arg_data.as_bytes().to_vec().into_boxed_slice());
+        let env_container =
+            (!env_data.is_empty()).then(|| env_data.as_bytes().to_vec().into_boxed_slice());
 
-        if let Some(v) = &arg_container {
-            serial_print!("received {}", unsafe {
-                alloc::str::from_boxed_utf8_unchecked(v.clone())
-            });
+        if !arg_data.is_empty() {
+            serial_print!("received {}", arg_data.as_str());
         }
 
-        TaskBuilder::from_fn(execute)
-            .map_err(|_| SysErrCode::NoChild)?
+        TaskBuilder::from_fn(execute)?
             .with_args(args!(
-                Path::new(path).to_owned(),
-                arg_data.size,
+                path.to_owned(),
+                arg_data.len(),
                 arg_container,
-                env_data.size,
+                env_data.len(),
                 env_container,
             ))
             .with_default_files(true)
     } else {
         // normal path
-        TaskBuilder::from_bytes(&buf[..bytes])
-            .map_err(|_| SysErrCode::BadMsg)?
-            .with_default_files(true)
+        TaskBuilder::from_file(bin)?.with_default_files(true)
     };
+    Ok(Executable {
+        builder: builder.with_env(env_data.as_bytes()),
+        is_builtin,
+    })
+}
+
+/// loads the image of new and adds it. Returns its pid as seen from the caller
+fn spawn_executable(
+    current: &Task,
+    new: Executable<'_>,
+    arg_data: &UserStrList,
+    env_data: &UserStrList,
+) -> SysCallRes<u64> {
+    let new = if new.is_builtin {
+        new.builder.as_kernel()?.build()
+    } else {
+        new.builder
+            // images, which cannot be parsed, fail with BadMsg, those needing a dynamic linker with NotSupported
+            .as_usr()?
+            .allocate_arg_env(
+                arg_data.len(),
+                arg_data.as_bytes().as_ptr(),
+                env_data.len(),
+                env_data.as_bytes().as_ptr(),
+            )
+            .build()
+    };
+
+    let id = new.pid();
+    add_built_task(new);
+    // the child is registered in the pid namespace of the caller once added
+    namespace::local_pid(current.core.pid_ns.as_deref(), id)
+        .map(|pid| pid.0)
+        .ok_or(SysErrCode::NoProcess)
+}
+
+// essentially posix_spawn
+#[syscall(number = SysCallDispatch::SpawnProcess)]
+pub fn spawn_process(
+    path: UserStr,
+    arg: UserRef<FatPtr<u8>>,
+    env: UserRef<FatPtr<u8>>,
+    fd_actions: UserRef<FatPtr<FDAction>>,
+    attr: Option<UserRef<SpawnAttr>>,
+) -> SysCallRes<u64> {
+    let arg_data = UserStrList::from_fat(arg.get())?;
+    let env_data = UserStrList::from_fat(env.get())?;
+    let actions = fd_actions.get();
+    let current = current_task().map_err(|_| SysErrCode::NoProcess)?;
+
+    let path = current
+        .core
+        .resolve_binary(Path::new(path.as_str()))
+        .ok_or(SysErrCode::NoFile)?;
+    let Executable {
+        builder: mut new,
+        is_builtin,
+    } = open_executable(&path, &arg_data, &env_data)?;
+
+    // spawning runs a new image, so close-on-exec files are not inherited
+    new = new.close_exec_files();
 
     if !actions.thin.is_null() {
-        let actions = unsafe { &*core::ptr::slice_from_raw_parts(actions.thin, actions.size) };
+        let actions = UserSlice::from_fat(actions)?;
 
-        for action in actions {
+        for action in actions.as_slice() {
             match action {
                 FDAction::Open(config, fd) => {
-                    let path = unsafe { str::from_raw_parts(config.path.thin, config.path.size) };
-                    new = new.with_file(
-                        *fd,
-                        fs::open(Path::new(path), config.flags).map_err(|e| e.into())?,
-                    );
+                    let path = UserStr::from_fat(&config.path)?;
+                    let path = current.core.resolve(Path::new(path.as_str()));
+                    new = new.with_file(*fd, fs::open(&path, config.flags)?);
                 }
                 FDAction::Close(fd) => new = new.remove_file(*fd),
                 FDAction::Dup(from, to) => {
                     let current = new.get_file(*from).ok_or(SysErrCode::NoFile)?;
-                    new = new.with_file(*to, current)
+                    new = new.with_file(*to, current.with_fd_flags(FDFlags::empty()))
                 }
                 FDAction::Clear => new = new.clear_files(),
                 FDAction::Inherit(parent, child) => {
-                    let current = current_task()
-                        .map_err(|_| SysErrCode::NoProcess)?
-                        .fd(*parent)
-                        .ok_or(SysErrCode::NoFile)?;
-                    new = new.with_file(*child, current);
+                    let file = current.fd(*parent).ok_or(SysErrCode::NoFile)?;
+                    new = new.with_file(*child, file.with_fd_flags(FDFlags::empty()));
                 }
             }
         }
     }
 
-    let new = if is_builtin {
-        new.as_kernel().map_err(|_| SysErrCode::Cancelled)?.build()
-    } else {
-        new.as_usr()
-            .map_err(|_| SysErrCode::Cancelled)?
-            .allocate_arg_env(arg_data.size, arg_data.thin, env_data.size, env_data.thin)
-            .build()
-    };
+    if let Some(attr) = attr {
+        let attr = attr.get();
+        if attr
+            .flags
+            .contains(SpawnFlags::SETPGROUP | SpawnFlags::NEWPGROUP)
+        {
+            return Err(SysErrCode::InvalidArg);
+        }
+        if attr.flags.contains(SpawnFlags::SETPGROUP) {
+            let pgrid = ProcessGroupID(attr.pgroup);
+            if !tls::task_data().get_tree().read().contains_key(&pgrid) {
+                return Err(SysErrCode::NoProcess);
+            }
+            new = new.with_pgrid(pgrid);
+        } else if attr.flags.contains(SpawnFlags::NEWPGROUP) {
+            new = new.with_pgrid(tls::task_data().next_pgrid());
+        }
+        if attr.flags.contains(SpawnFlags::SETCWD) {
+            let cwd = UserStr::from_fat(&attr.cwd)?;
+            let cwd = current.core.resolve(Path::new(cwd.as_str()));
+            // fails for missing paths and files
+            fs::read_dir(&cwd)?;
+            new = new.with_cwd(cwd);
+        }
+        new = new.with_namespaces(attr.clone_flags);
+    }
 
-    let id = new.pid().0;
-    add_built_task(new);
-    Ok(id)
+    let executable = Executable {
+        builder: new,
+        is_builtin,
+    };
+    spawn_executable(&current, executable, &arg_data, &env_data)
 }
 
+#[syscall(number = SysCallDispatch::ThreadCreate)]
 pub fn thread_create(start_routine: *const (), args: *const ()) -> SysCallRes<u64> {
-    if !valid_ptr(start_routine, 0) {
-        return Err(SysErrCode::AddrNotValid);
-    }
+    let entry = UserVirtAddr::from_ptr(start_routine, 0).ok_or(SysErrCode::AddrNotValid)?;
     let current = tls::task_data()
         .current_thread()
         .ok_or(SysErrCode::NoProcess)?;
@@ -590,10 +921,8 @@ pub fn thread_create(start_routine: *const (), args: *const ()) -> SysCallRes<u6
     let mut fn_args = Args::default();
     *fn_args.get_mut(0) = Arg::from_ptr(args as *mut ());
 
-    let task = unsafe { TaskBuilder::from_addr(VirtAddr::from_ptr(start_routine)) }
-        .map_err(|_| SysErrCode::AddrNotValid)?
-        .like_existing_usr(&current)
-        .map_err(|_| SysErrCode::BadMsg)?
+    let task = unsafe { TaskBuilder::from_addr(entry.as_virt()) }?
+        .like_existing_usr(&current)?
         .with_args(fn_args)
         .build();
     let tid = task.tid().get_inner();
@@ -601,10 +930,12 @@ pub fn thread_create(start_routine: *const (), args: *const ()) -> SysCallRes<u6
     Ok(tid)
 }
 
+#[syscall(number = SysCallDispatch::ThreadExit)]
 pub fn thread_exit() -> ! {
     exit(0)
 }
 
+#[syscall(number = SysCallDispatch::ThreadCancel)]
 pub fn thread_cancel(id: u64) -> SysCallRes<i64> {
     let r = tls::task_data()
         .kill(&id.into(), 0)
@@ -617,6 +948,7 @@ pub fn thread_cancel(id: u64) -> SysCallRes<i64> {
     r
 }
 
+#[syscall(number = SysCallDispatch::ThreadJoin)]
 pub fn thread_join(
     id: u64,
     timeout: i64,
@@ -626,12 +958,21 @@ pub fn thread_join(
     let task = tls::task_data()
         .thread(&id.into())
         .ok_or(SysErrCode::NoChild)?;
+    if w_flags.contains(WaitOptions::DETACH) {
+        task.metadata.detached.store(true, Ordering::Release);
+        drop(task);
+        _ = tls::task_data().reap_detached(&id.into());
+        return Ok(TaskStateChange::empty());
+    }
+    if task.metadata.detached.load(Ordering::Acquire) {
+        return Err(SysErrCode::InvalidArg);
+    }
     if timeout == 0 {
         return Ok(TaskStateChange::empty());
     }
     let mut conditions = Vec::new();
     if timeout > 0 {
-        let until = Duration::from_millis(timeout as u64) + current_time();
+        let until = Instant::now() + Duration::from_millis(timeout as u64);
         conditions.push(QueuTypeCondition::with_cond(
             QueueType::Timer,
             WaitCondition::Time(until),
@@ -657,11 +998,13 @@ pub fn thread_join(
             TaskState::Running | TaskState::Ready => TaskStateChange::WAKEUP,
             TaskState::Blocking | TaskState::Sleeping => TaskStateChange::BLOCK,
             TaskState::Zombie => TaskStateChange::EXIT,
+            TaskState::Stopped => TaskStateChange::STOP,
         });
     remove_queue(&q_type);
     r
 }
 
+#[syscall(number = SysCallDispatch::GetTID)]
 pub fn get_tid() -> SysCallRes<u64> {
     tls::task_data()
         .current_thread()
@@ -669,11 +1012,13 @@ pub fn get_tid() -> SysCallRes<u64> {
         .map(|t| t.tid().get_inner())
 }
 
+#[syscall(number = SysCallDispatch::Time)]
 pub fn time() -> SysCallRes<u64> {
     // TODO this should return a u128, but this requires splitting across registers / ptr
-    Ok(current_time().as_millis() as u64)
+    Ok(uptime().as_millis() as u64)
 }
 
+#[syscall(number = SysCallDispatch::GetPgrID)]
 pub fn get_pgrid() -> SysCallRes<u64> {
     tls::task_data()
         .current_thread()
@@ -681,10 +1026,8 @@ pub fn get_pgrid() -> SysCallRes<u64> {
         .ok_or(SysErrCode::NoProcess)
 }
 
-pub fn pipe(fds: *mut [u32; 2], cap: isize) -> SysCallRes<()> {
-    if !valid_ptr(fds, 1) {
-        return Err(SysErrCode::AddrNotValid);
-    }
+#[syscall(number = SysCallDispatch::Pipe)]
+pub fn pipe(mut fds: UserMut<[FileDescriptor; 2]>, cap: isize) -> SysCallRes<()> {
     let current_task = tls::task_data()
         .current_thread()
         .ok_or(SysErrCode::NoProcess)?;
@@ -697,33 +1040,56 @@ pub fn pipe(fds: *mut [u32; 2], cap: isize) -> SysCallRes<()> {
         .with_perms(FPerms::WRITE)
         .finish();
 
-    let read_fd = current_task.next_fd();
-    current_task.add_fd(read_fd, reader);
-
-    let write_fd = current_task.next_fd();
-    current_task.add_fd(write_fd, writer);
+    let read_fd = current_task.add_next_file(reader)?;
+    let write_fd = current_task.add_next_file(writer).map_err(|e| {
+        _ = current_task.remove_fd(read_fd);
+        SysErrCode::from(e)
+    })?;
 
-    let arr = unsafe { &mut *fds };
-    arr[0] = read_fd;
-    arr[1] = write_fd;
+    fds.write([read_fd, write_fd]);
     Ok(())
 }
 
-pub fn fstat(fd: FileDescriptor, buf: *mut FStat) -> SysCallRes<()> {
-    if !valid_ptr(buf, 1) {
-        return Err(SysErrCode::AddrNotValid);
+#[syscall(number = SysCallDispatch::GetRLimit)]
+pub fn get_rlimit(resource: u64) -> SysCallRes<u64> {
+    let resource: Resource = resource.try_into().map_err(|_| SysErrCode::InvalidArg)?;
+    let current = tls::task_data()
+        .current_thread()
+        .ok_or(SysErrCode::NoProcess)?;
+    match resource {
+        Resource::NoFile => Ok(current.core.fd_table.read().limit() as u64),
+    }
+}
+
+#[syscall(number = SysCallDispatch::SetRLimit)]
+pub fn set_rlimit(resource: u64, value: u64) -> SysCallRes<()> {
+    let resource: Resource = resource.try_into().map_err(|_| SysErrCode::InvalidArg)?;
+    let current = tls::task_data()
+        .current_thread()
+        .ok_or(SysErrCode::NoProcess)?;
+    match resource {
+        Resource::NoFile => current
+            .core
+            .fd_table
+            .write()
+            .set_limit(value.try_into().map_err(|_| SysErrCode::InvalidArg)?)
+            .map_err(|e| e.into()),
     }
+}
 
+#[syscall(number = SysCallDispatch::FStat)]
+pub fn fstat(fd: FileDescriptor, mut buf: UserMut<FStat>) -> SysCallRes<()> {
     let f = tls::task_data()
         .current_thread()
         .ok_or(SysErrCode::NoProcess)?
         .fd(fd)
         .ok_or(SysErrCode::BadFd)?;
 
-    unsafe { *buf = f.fstat() };
+    buf.write(f.fstat());
     Ok(())
 }
 
+#[syscall(number = SysCallDispatch::SetPerm)]
 pub fn set_perm(fd: FileDescriptor, perms: NodePermissions, strategy: u64) -> SysCallRes<()> {
     let strategy = strategy.try_into().map_err(|_| SysErrCode::InvalidArg)?;
 
@@ -737,3 +1103,205 @@ pub fn set_perm(fd: FileDescriptor, perms: NodePermissions, strategy: u64) -> Sy
 
     Ok(())
 }
+
+/// reports the memory usage of process pid, or of the calling process if pid is 0
+#[syscall(number = SysCallDispatch::MemInfo)]
+pub fn mem_info(pid: u64, mut buf: UserMut<MemInfo>) -> SysCallRes<()> {
+    let info = if pid == 0 {
+        let current = tls::task_data()
+            .current_thread()
+            .ok_or(SysErrCode::NoProcess)?;
+        vma::mem_info(&current.core)
+    } else {
+        let processes = tls::task_data().processes().read();
+        let core = processes
+            .get(&ProcessID(pid))
+            .ok_or(SysErrCode::NoProcess)?;
+        vma::mem_info(core)
+    };
+
+    buf.write(info);
+    Ok(())
+}
+
+/// returns the process group in the foreground of the terminal, or u64::MAX if none was set
+#[syscall(number = SysCallDispatch::TcGetPgrp)]
+pub fn tc_get_pgrp() -> SysCallRes<u64> {
+    Ok(session::foreground().map_or(u64::MAX, |pgrid| pgrid.0))
+}
+
+/// puts process group pgrid into the foreground of the terminal. Only the foreground group may read from it
+#[syscall(number = SysCallDispatch::TcSetPgrp)]
+pub fn tc_set_pgrp(pgrid: u64) -> SysCallRes<()> {
+    let pgrid = ProcessGroupID(pgrid);
+    if !tls::task_data().get_tree().read().contains_key(&pgrid) {
+        return Err(SysErrCode::NoProcess);
+    }
+    session::set_foreground(Some(pgrid));
+    Ok(())
+}
+
+/// fills buf with output of the entropy pool. If the pool was not seeded yet, the virtio-rng device is read first,
+/// if the kernel is built with it, or, with RandomFlags::NONBLOCK, the call fails with WouldBlock
+#[syscall(number = SysCallDispatch::GetRandom)]
+pub fn get_random(mut buf: UserSliceMut<u8>, flags: RandomFlags) -> SysCallRes<usize> {
+    if !entropy::is_seeded() {
+        if flags.contains(RandomFlags::NONBLOCK) {
+            return Err(SysErrCode::WouldBlock);
+        }
+        #[cfg(feature = "virtio")]
+        _ = crate::drivers::virtio::rng::harvest();
+    }
+    let buf = buf.as_mut_slice();
+    entropy::fill(buf);
+    Ok(buf.len())
+}
+
+/// sets the access and modification time of the file at path, see syscalls.txt
+#[syscall(number = SysCallDispatch::SetTimes)]
+pub fn set_times(path: UserStr, times: Option<UserRef<FileTimes>>) -> SysCallRes<()> {
+    let current = tls::task_data()
+        .current_thread()
+        .ok_or(SysErrCode::NoProcess)?;
+    let path = current.core.resolve(Path::new(path.as_str()));
+    let file = fs::open(&path, OpenOptions::empty())?;
+    if !file.fstat().permissions.w() {
+        return Err(SysErrCode::AccessDenied);
+    }
+
+    let now = uptime().as_secs();
+    let time = |time| match time {
+        UTIME_OMIT => None,
+        UTIME_NOW => Some(now),
+        time => Some(time),
+    };
+    let (access, modify) = times.map_or((Some(now), Some(now)), |times| {
+        let times = times.get();
+        (time(times.access), time(times.modify))
+    });
+    file.set_times(access, modify).map_err(|e| e.into())
+}
+
+/// copies the value of the extended attribute name of the node at path into buf, see syscalls.txt
+#[syscall(number = SysCallDispatch::GetXattr)]
+pub fn get_xattr(path: UserStr, name: UserStr, mut buf: UserSliceMut<u8>) -> SysCallRes<usize> {
+    let current = tls::task_data()
+        .current_thread()
+        .ok_or(SysErrCode::NoProcess)?;
+    let path = current.core.resolve(Path::new(path.as_str()));
+    let value = fs::get_xattr(&path, name.as_str())?;
+    let len = value.len().min(buf.len());
+    buf.as_mut_slice()[..len].copy_from_slice(&value[..len]);
+    Ok(value.len())
+}
+
+#[syscall(number = SysCallDispatch::SetXattr)]
+pub fn set_xattr(path: UserStr, name: UserStr, value: UserSlice<u8>) -> SysCallRes<()> {
+    let current = tls::task_data()
+        .current_thread()
+        .ok_or(SysErrCode::NoProcess)?;
+    let path = current.core.resolve(Path::new(path.as_str()));
+    fs::set_xattr(&path, name.as_str(), value.as_slice()).map_err(|e| e.into())
+}
+
+#[syscall(number = SysCallDispatch::RemoveXattr)]
+pub fn remove_xattr(path: UserStr, name: UserStr) -> SysCallRes<()> {
+    let current = tls::task_data()
+        .current_thread()
+        .ok_or(SysErrCode::NoProcess)?;
+    let path = current.core.resolve(Path::new(path.as_str()));
+    fs::remove_xattr(&path, name.as_str()).map_err(|e| e.into())
+}
+
+/// copies the names of all extended attributes of the node at path into buf, each terminated by a 0 byte
+#[syscall(number = SysCallDispatch::ListXattr)]
+pub fn list_xattr(path: UserStr, mut buf: UserSliceMut<u8>) -> SysCallRes<usize> {
+    let current = tls::task_data()
+        .current_thread()
+        .ok_or(SysErrCode::NoProcess)?;
+    let path = current.core.resolve(Path::new(path.as_str()));
+    let names = fs::list_xattr(&path)?;
+    let mut list = Vec::new();
+    for name in names {
+        list.extend_from_slice(name.as_bytes());
+        list.push(0);
+    }
+    let len = list.len().min(buf.len());
+    buf.as_mut_slice()[..len].copy_from_slice(&list[..len]);
+    Ok(list.len())
+}
+
+/// sends fd through channel, which is the write end of a pipe for now. The task, which receives it with recv_fd,
+/// shares the open file description with the sender, like with SCM_RIGHTS
+#[syscall(number = SysCallDispatch::SendFd)]
+pub fn send_fd(channel: FileDescriptor, fd: FileDescriptor) -> SysCallRes<()> {
+    let current = tls::task_data()
+        .current_thread()
+        .ok_or(SysErrCode::NoProcess)?;
+    let channel = current.fd(channel).ok_or(SysErrCode::BadFd)?;
+    let handle = current
+        .fd(fd)
+        .ok_or(SysErrCode::BadFd)?
+        .with_fd_flags(FDFlags::empty());
+    channel.send_fd(handle).map_err(|e| e.into())
+}
+
+/// takes the oldest descriptor sent through channel and installs it at the lowest free fd. Never blocks.
+/// A descriptor, which does not fit into the fd table anymore, is closed
+#[syscall(number = SysCallDispatch::RecvFd)]
+pub fn recv_fd(channel: FileDescriptor, flags: OpenOptions) -> SysCallRes<FileDescriptor> {
+    if !flags.difference(OpenOptions::CLOEXEC).is_empty() {
+        return Err(SysErrCode::InvalidArg);
+    }
+    let current = tls::task_data()
+        .current_thread()
+        .ok_or(SysErrCode::NoProcess)?;
+    let handle = current
+        .fd(channel)
+        .ok_or(SysErrCode::BadFd)?
+        .recv_fd()?
+        .ok_or(SysErrCode::WouldBlock)?;
+    current
+        .add_next_file(handle.with_fd_flags(flags.into()))
+        .map_err(|e| e.into())
+}
+
+/// creates a submission ring with entries slots, a power of two, and a data area of data_len bytes. The returned fd is
+/// mapped with mmap to access the ring, which starts with a RingHeader
+#[syscall(number = SysCallDispatch::RingSetup)]
+pub fn ring_setup(entries: u32, data_len: usize) -> SysCallRes<FileDescriptor> {
+    if !entries.is_power_of_two() || entries > RING_MAX_ENTRIES || data_len > RING_MAX_DATA {
+        return Err(SysErrCode::InvalidArg);
+    }
+    let current = tls::task_data()
+        .current_thread()
+        .ok_or(SysErrCode::NoProcess)?;
+    let ring = Ring::new(entries, data_len).ok_or(SysErrCode::OOM)?;
+    let f = FileBuilder::new(ring as Arc<dyn FileRepr>)
+        .with_perms(FPerms::READ | FPerms::WRITE)
+        .finish();
+    current.add_next_file(f).map_err(|e| e.into())
+}
+
+/// takes up to to_submit submissions off the ring fd and waits until min_complete completions are ready or timeout
+/// millis passed. A negative timeout waits without limit. Returns the number of submissions taken
+#[syscall(number = SysCallDispatch::RingEnter)]
+pub fn ring_enter(
+    fd: FileDescriptor,
+    to_submit: u32,
+    min_complete: u32,
+    timeout: i64,
+) -> SysCallRes<u32> {
+    let file = tls::task_data()
+        .current_thread()
+        .ok_or(SysErrCode::NoProcess)?
+        .fd(fd)
+        .ok_or(SysErrCode::BadFd)?;
+    let ring = file.as_ring().ok_or(SysErrCode::InvalidArg)?;
+    let submitted = ring.submit(to_submit)?;
+    if min_complete > 0 && timeout != 0 {
+        let timeout = (timeout > 0).then(|| Duration::from_millis(timeout as u64));
+        ring.wait(min_complete, timeout)?;
+    }
+    Ok(submitted)
+}
@@ -1,161 +1,139 @@
+use conquer_once::spin::OnceCell;
 use tinyos_abi::{
     consts::MAX_SYSCALL,
-    flags::{NodePermissions, OpenOptions, PageTableFlags, TaskWaitOptions, WaitOptions},
-    types::{FDAction, FStat, FatPtr, FileDescriptor, SysCallDispatch, SysErrCode},
+    flags::TaskStateChange,
+    types::{SysCallRes, SysErrCode},
 };
 
 use crate::{
     arch::context::SysCallCtx,
     eprintln,
-    kernel::abi::syscalls::funcs::{
-        close,
-        dup,
-        eventfd,
-        execve,
-        exit,
-        fork,
-        fstat,
-        get_pgrid,
-        get_pid,
-        get_tid,
-        kill,
-        mmap,
-        munmap,
-        open,
-        pipe,
-        read,
-        seek,
-        serial,
-        set_perm,
-        spawn,
-        spawn_process,
-        thread_cancel,
-        thread_create,
-        thread_exit,
-        thread_join,
-        time,
-        wait_pid,
-        waittime,
-        write,
-        yield_now,
+    kernel::{
+        flight,
+        threading::{schedule::stats, tls},
     },
-    println,
-    serial_println,
 };
 
+#[cfg(feature = "test_run")]
+mod abi_tests;
+pub mod args;
 pub mod funcs;
 pub mod utils;
 
 // all syscalls return their first return value in rax (x86_64) and their error value in rdx (x86_64)
 
+// The dispatch table is generated from the handlers in funcs: #[syscall(number = ...)] places a SysCallEntry for every
+// handler into the .syscalls link section, from which the table is built on first use.
+
+/// a syscall handler, registered through #[syscall]
+#[derive(Debug)]
+#[repr(C)]
+pub struct SysCallEntry {
+    pub number: u64,
+    pub name: &'static str,
+    pub handler: fn(&SysCallCtx) -> SysCallRes<u64>,
+}
+
+type DispatchTable = [Option<&'static SysCallEntry>; MAX_SYSCALL as usize + 1];
+
+static DISPATCH: OnceCell<DispatchTable> = OnceCell::uninit();
+
+unsafe extern "C" {
+    static __syscalls_start: SysCallEntry;
+    static __syscalls_end: SysCallEntry;
+}
+
+/// all handlers in the .syscalls link section
+pub fn registered_syscalls() -> &'static [SysCallEntry] {
+    // SAFETY: the linker script places all entries contiguously between __syscalls_start and __syscalls_end
+    unsafe {
+        let start = &raw const __syscalls_start;
+        let end = &raw const __syscalls_end;
+        let count = (end.addr() - start.addr()) / size_of::<SysCallEntry>();
+        core::slice::from_raw_parts(start, count)
+    }
+}
+
+fn build_dispatch_table() -> DispatchTable {
+    let mut table: DispatchTable = [None; MAX_SYSCALL as usize + 1];
+    for entry in registered_syscalls() {
+        // the number is checked against MAX_SYSCALL at compile time
+        if let Some(prev) = table[entry.number as usize].replace(entry) {
+            panic!(
+                "syscall {} is registered twice, by {} and {}",
+                entry.number, prev.name, entry.name
+            );
+        }
+    }
+    table
+}
+
+/// the handler of syscall number, if any
+pub fn syscall_entry(number: u64) -> Option<&'static SysCallEntry> {
+    DISPATCH
+        .get_or_init(build_dispatch_table)
+        .get(number as usize)
+        .copied()
+        .flatten()
+}
+
+/// conversion of syscall return values into the raw return register
+pub trait SysCallRet {
+    fn into_raw(self) -> u64;
+}
+
+macro_rules! impl_ret_cast {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            impl SysCallRet for $ty {
+                fn into_raw(self) -> u64 {
+                    self as u64
+                }
+            }
+        )*
+    };
+}
+
+impl_ret_cast!(u64, u32, usize, i64, i32, isize, bool);
+
+impl SysCallRet for () {
+    fn into_raw(self) -> u64 {
+        0
+    }
+}
+
+impl SysCallRet for *mut u8 {
+    fn into_raw(self) -> u64 {
+        self as usize as u64
+    }
+}
+
+impl SysCallRet for TaskStateChange {
+    fn into_raw(self) -> u64 {
+        self.bits() as u64
+    }
+}
+
 pub extern "C" fn syscall_handler(args: &mut SysCallCtx) {
-    let dispatch = args.num();
-    if dispatch > MAX_SYSCALL {
+    let Some(entry) = syscall_entry(args.num()) else {
         eprintln!(
             "tried to call a syscall with an invalid number: {}. Only 0..{} are valid.",
             args.num(),
             MAX_SYSCALL
         );
+        // the error is returned in rax as well, for callers predating the error register
         args.ret(SysErrCode::BadRqstD as u64);
+        args.ret2(SysErrCode::BadRqstD as u64);
         return;
-    }
-    let dispatch = unsafe { core::mem::transmute(dispatch) };
-
-    let res = match dispatch {
-        SysCallDispatch::Open => open(
-            args.first() as usize as *const u8,
-            args.second() as usize,
-            OpenOptions::from_bits_truncate(args.third() as u32),
-        )
-        .map(|r| r as u64),
-        SysCallDispatch::Close => close(args.first() as u32).map(|_| 0),
-        SysCallDispatch::Read => read(
-            args.first() as u32,
-            args.second() as usize as *mut u8,
-            args.third() as usize,
-            args.fourth() as i64,
-        )
-        .map(|r| r as u64),
-        SysCallDispatch::Write => write(
-            args.first() as u32,
-            args.second() as usize as *const u8,
-            args.third() as usize,
-        )
-        .map(|r| r as u64),
-        SysCallDispatch::Yield => yield_now().map(|_| 0),
-        SysCallDispatch::Exit => exit(args.first() as i64),
-        SysCallDispatch::Kill => kill(args.first(), args.second() as i64).map(|_| 0),
-        SysCallDispatch::Mmap => mmap(
-            args.first() as usize,
-            args.second() as usize as *mut u8,
-            PageTableFlags::from_bits_truncate(args.third()),
-            args.fourth() as i32,
-        )
-        .map(|r| r as usize as u64),
-        SysCallDispatch::Munmap => {
-            munmap(args.first() as usize as *mut u8, args.second() as usize).map(|_| 0)
-        }
-        SysCallDispatch::Fork => fork().map(|r| r as u64),
-        SysCallDispatch::WaitTime => waittime(args.first()).map(|_| 0),
-        SysCallDispatch::GetPID => get_pid().map(|r| r),
-        SysCallDispatch::Seek => seek(args.first() as u32, args.second() as usize).map(|_| 0),
-        SysCallDispatch::Dup => dup(args.first() as u32, args.second() as i32).map(|r| r as u64),
-        SysCallDispatch::Spawn => {
-            spawn(args.first() as *const u8, args.second() as usize).map(|_| 0)
-        }
-        SysCallDispatch::Dbg => {
-            serial(args.first() as *const u8, args.second() as usize).map(|_| 0)
-        }
-        SysCallDispatch::Execve => execve(
-            args.first() as *const u8,
-            args.second() as usize,
-            args.third() as *const FatPtr<u8>,
-            args.fourth() as *const FatPtr<u8>,
-        ),
-        SysCallDispatch::ThreadCreate => {
-            thread_create(args.first() as *const (), args.second() as *const ()).map(|r| r)
-        }
-        SysCallDispatch::ThreadExit => thread_exit(),
-        SysCallDispatch::ThreadCancel => thread_cancel(args.first()).map(|r| r as u64),
-        SysCallDispatch::ThreadJoin => thread_join(
-            args.first(),
-            args.second() as i64,
-            WaitOptions::from_bits_truncate(args.third() as u16),
-            TaskWaitOptions::from_bits_truncate(args.fourth() as u16),
-        )
-        .map(|r| r.bits() as u64),
-        SysCallDispatch::WaitPID => wait_pid(
-            args.first(),
-            args.second() as i64,
-            WaitOptions::from_bits_truncate(args.third() as u16),
-            TaskWaitOptions::from_bits_truncate(args.fourth() as u16),
-        )
-        .map(|r| r.bits() as u64),
-        SysCallDispatch::EventFD => eventfd().map(|r| r as u64),
-        SysCallDispatch::Time => time().map(|r| r),
-        SysCallDispatch::GetTID => get_tid().map(|r| r),
-        SysCallDispatch::GetPgrID => get_pgrid().map(|r| r),
-        SysCallDispatch::Pipe => {
-            pipe(args.first() as *mut [u32; 2], args.second() as isize).map(|_| 0)
-        }
-        SysCallDispatch::SpawnProcess => spawn_process(
-            args.first() as *const u8,
-            args.second() as usize,
-            args.third() as *const FatPtr<u8>,
-            args.fourth() as *const FatPtr<u8>,
-            args.fifth() as *const FatPtr<FDAction>,
-        ),
-        SysCallDispatch::FStat => {
-            fstat(args.first() as FileDescriptor, args.second() as *mut FStat).map(|_| 0)
-        }
-        SysCallDispatch::SetPerm => set_perm(
-            args.first() as FileDescriptor,
-            NodePermissions::from_bits_truncate(args.second() as u8),
-            args.third(),
-        )
-        .map(|_| 0),
     };
 
+    stats::count_syscall();
+    flight::record(flight::Event::Syscall {
+        tid: tls::task_data().current_tid().get_inner(),
+        number: args.num(),
+    });
+    let res = (entry.handler)(args);
+
     // in case of err we return the error value in ret2 and do not touch ret1
     // in case of success we return the return value in ret1 and return success value in ret2
     res.inspect_err(|e| args.ret2(*e as u64)).inspect(|r| {
@@ -163,3 +141,32 @@ pub extern "C" fn syscall_handler(args: &mut SysCallCtx) {
         args.ret2(SysErrCode::NoErr as u64);
     });
 }
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    /// numbers without a syscall
+    const UNASSIGNED: &[u64] = &[11];
+
+    #[kernel_test]
+    fn dispatch_table_complete() {
+        let mut registered = [0_usize; MAX_SYSCALL as usize + 1];
+        for entry in registered_syscalls() {
+            registered[entry.number as usize] += 1;
+        }
+        for (number, count) in registered.iter().enumerate() {
+            let expected = usize::from(!UNASSIGNED.contains(&(number as u64)));
+            assert_eq!(
+                *count, expected,
+                "syscall {} is registered {} times",
+                number, count
+            );
+        }
+        assert!(syscall_entry(11).is_none());
+        assert!(syscall_entry(MAX_SYSCALL + 1).is_none());
+        assert_eq!(syscall_entry(0).map(|entry| entry.number), Some(0));
+    }
+}
@@ -1,15 +1,6 @@
 use core::arch::global_asm;
 
-use crate::kernel::{mem::paging::get_hhdm_addr, threading::schedule::context_switch_local};
-
-/// returns true if the buffer is entirely in user space.
-/// len is assumed to be the numebr of ELEMENTS T.
-pub fn valid_ptr<T>(ptr: *const T, len: usize) -> bool {
-    let base = ptr.addr();
-    !ptr.is_null()
-        && base < get_hhdm_addr() as usize
-        && base + (len * size_of::<T>()) < get_hhdm_addr() as usize
-}
+use crate::kernel::threading::schedule::context_switch_local;
 
 global_asm!(
     "
@@ -48,22 +39,8 @@ global_asm!(
             push r9
             push r8
 
-            // save current rsp
-            mov r9, rsp
-
-            // align stack, save rsp and save xmm registers
-            sub rsp, 512 + 16
-            and rsp, -16
-            fxsave [rsp]
-            push r9
-
             mov rdi, rsp
-            call context_switch_local
-
-            // pop xmm registers
-            pop r9
-            fxrstor [rsp]
-            mov rsp, r9
+            call context_switch_voluntary
 
             pop r8
             pop r9
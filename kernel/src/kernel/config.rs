@@ -0,0 +1,334 @@
+use alloc::{collections::btree_map::BTreeMap, string::String};
+use core::fmt::Write as _;
+
+use os_macros::init_task;
+use tinyos_abi::flags::NodeType;
+
+use crate::{
+    KernelError,
+    KernelRes,
+    bootinfo,
+    eprintln,
+    impl_file_for_wr,
+    kernel::{
+        fs::FSErrorKind,
+        io::{IOError, IOResult, Read, Write, read_rendered},
+    },
+    serial_println,
+    sync::locks::Mutex,
+};
+
+/// kernel command line option: a comma separated list of subsystems, which are not started at boot, but only once
+/// started through /proc/kernel/subsystems
+pub const DEFER_OPTION: &str = "defer";
+pub const SUBSYSTEMS_FILE: &str = "/kernel/subsystems";
+
+// the subsystems, which can be started and stopped after boot, by name
+static RUNTIME: Mutex<BTreeMap<&'static str, Runtime>> = Mutex::new(BTreeMap::new());
+
+/// a subsystem, which is left out of the kernel at build time unless the cargo feature of the same name is enabled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Subsystem {
+    pub name: &'static str,
+    pub enabled: bool,
+}
+
+/// all optional subsystems. Features, which only change the behaviour of the kernel, like kfence, are not listed
+pub static SUBSYSTEMS: &[Subsystem] = &[
+    Subsystem {
+        name: "disk",
+        enabled: cfg!(feature = "disk"),
+    },
+    Subsystem {
+        name: "sound",
+        enabled: cfg!(feature = "sound"),
+    },
+    Subsystem {
+        name: "usb",
+        enabled: cfg!(feature = "usb"),
+    },
+    Subsystem {
+        name: "virtio",
+        enabled: cfg!(feature = "virtio"),
+    },
+    Subsystem {
+        name: "watchdog",
+        enabled: cfg!(feature = "watchdog"),
+    },
+    Subsystem {
+        name: "ksh",
+        enabled: cfg!(feature = "ksh"),
+    },
+];
+
+/// the names of the subsystems built into the kernel
+pub fn enabled() -> impl Iterator<Item = &'static str> {
+    SUBSYSTEMS
+        .iter()
+        .filter(|subsystem| subsystem.enabled)
+        .map(|subsystem| subsystem.name)
+}
+
+/// whether the subsystem name is built into the kernel. Unknown subsystems are not
+pub fn is_enabled(name: &str) -> bool {
+    SUBSYSTEMS
+        .iter()
+        .any(|subsystem| subsystem.enabled && subsystem.name == name)
+}
+
+/// starts and stops a subsystem after boot. Neither of them may register, start or stop subsystems itself
+#[derive(Debug, Clone, Copy)]
+pub struct SubsystemHooks {
+    pub start: fn() -> KernelRes<()>,
+    /// releases everything start set up, such that start can run again
+    pub stop: fn() -> KernelRes<()>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubsystemState {
+    Running,
+    Stopped,
+}
+
+impl SubsystemState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Running => "running",
+            Self::Stopped => "stopped",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Runtime {
+    hooks: SubsystemHooks,
+    state: SubsystemState,
+}
+
+/// makes the built in subsystem name startable and stoppable at runtime and starts it, unless it is deferred on
+/// the command line. Called once by the init task of the subsystem instead of setting it up directly
+pub fn register(name: &'static str, hooks: SubsystemHooks) -> KernelRes<()> {
+    if !is_enabled(name) {
+        return Err(KernelError::Unexpected("the subsystem is not built in"));
+    }
+    let mut runtime = RUNTIME.lock();
+    if runtime.contains_key(name) {
+        return Err(KernelError::Unexpected(
+            "the subsystem is already registered",
+        ));
+    }
+    runtime.insert(
+        name,
+        Runtime {
+            hooks,
+            state: SubsystemState::Stopped,
+        },
+    );
+    drop(runtime);
+    if deferred(name) {
+        serial_println!("{} deferred until started", name);
+        return Ok(());
+    }
+    start(name)
+}
+
+/// whether the start of name at boot is deferred through DEFER_OPTION
+pub fn deferred(name: &str) -> bool {
+    bootinfo::cmdline_option(DEFER_OPTION)
+        .is_some_and(|names| names.split(',').any(|deferred| deferred == name))
+}
+
+/// the state of the registered subsystem name
+pub fn state(name: &str) -> Option<SubsystemState> {
+    RUNTIME.lock().get(name).map(|runtime| runtime.state)
+}
+
+/// starts the registered subsystem name. Starting a running subsystem does nothing
+pub fn start(name: &str) -> KernelRes<()> {
+    transition(name, SubsystemState::Running)
+}
+
+/// stops the registered subsystem name. Stopping a stopped subsystem does nothing
+pub fn stop(name: &str) -> KernelRes<()> {
+    transition(name, SubsystemState::Stopped)
+}
+
+// the registry stays locked during the hook, such that starts and stops of a subsystem do not interleave
+fn transition(name: &str, to: SubsystemState) -> KernelRes<()> {
+    let mut registry = RUNTIME.lock();
+    let runtime = registry.get_mut(name).ok_or(KernelError::Unexpected(
+        "the subsystem cannot be started or stopped",
+    ))?;
+    if runtime.state == to {
+        return Ok(());
+    }
+    match to {
+        SubsystemState::Running => (runtime.hooks.start)()?,
+        SubsystemState::Stopped => (runtime.hooks.stop)()?,
+    }
+    runtime.state = to;
+    serial_println!("subsystem {}: {}", name, to.as_str());
+    Ok(())
+}
+
+/// /proc/kernel/subsystems: one line per optional subsystem with whether it is built in and, if it can be started
+/// and stopped at runtime, its state. Writing start <name> or stop <name> starts or stops it.
+/// There are no users, every process able to open the file for writing may do so
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Subsystems;
+
+impl Subsystems {
+    fn render(&self) -> String {
+        let mut rendered = String::new();
+        for subsystem in SUBSYSTEMS {
+            _ = writeln!(
+                rendered,
+                "{}\t{}\t{}",
+                subsystem.name,
+                if subsystem.enabled {
+                    "built-in"
+                } else {
+                    "absent"
+                },
+                state(subsystem.name).map_or("static", |state| state.as_str())
+            );
+        }
+        rendered
+    }
+}
+
+impl Read for Subsystems {
+    fn read(&self, buf: &mut [u8], offset: usize) -> IOResult<usize> {
+        read_rendered(self.render(), buf, offset)
+    }
+}
+
+impl Write for Subsystems {
+    fn write(&self, buf: &[u8], _offset: usize) -> IOResult<usize> {
+        let cmd =
+            str::from_utf8(buf.trim_ascii()).map_err(|_| IOError::simple(FSErrorKind::Other))?;
+        let res = match cmd.split_once(' ') {
+            Some(("start", name)) => start(name.trim()),
+            Some(("stop", name)) => stop(name.trim()),
+            _ => {
+                return Err(IOError::with_message(
+                    FSErrorKind::Other,
+                    "expected start <name> or stop <name>",
+                ));
+            }
+        };
+        res.map_err(|e| {
+            eprintln!("{}: {}", cmd, e);
+            IOError::with_message(
+                FSErrorKind::Other,
+                "the subsystem could not be started or stopped",
+            )
+        })?;
+        Ok(buf.len())
+    }
+}
+
+impl_file_for_wr!(Subsystems: NodeType::FILE);
+
+#[init_task(stage = "fs")]
+fn print_subsystems() {
+    serial_println!("subsystems:");
+    for subsystem in SUBSYSTEMS {
+        serial_println!(
+            "\t{}{}",
+            if subsystem.enabled { '+' } else { '-' },
+            subsystem.name
+        );
+    }
+}
+
+/// the deferred subsystems are started by name, thus unknown names are most likely typos
+#[init_task(stage = "fs")]
+fn check_deferred() {
+    let Some(names) = bootinfo::cmdline_option(DEFER_OPTION) else {
+        return;
+    };
+    for name in names.split(',').filter(|name| !is_enabled(name)) {
+        eprintln!("cannot defer {}, it is not built in", name);
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    static STARTS: AtomicUsize = AtomicUsize::new(0);
+    static FAIL_START: AtomicBool = AtomicBool::new(false);
+
+    fn test_start() -> KernelRes<()> {
+        if FAIL_START.load(Ordering::Relaxed) {
+            return Err(KernelError::Unexpected("test start failed"));
+        }
+        STARTS.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn test_stop() -> KernelRes<()> {
+        Ok(())
+    }
+
+    #[kernel_test]
+    fn subsystems() {
+        assert_eq!(is_enabled("disk"), cfg!(feature = "disk"));
+        assert_eq!(is_enabled("ksh"), cfg!(feature = "ksh"));
+        assert!(!is_enabled("net"));
+        assert!(enabled().all(is_enabled));
+        assert_eq!(
+            enabled().count(),
+            SUBSYSTEMS.iter().filter(|s| s.enabled).count()
+        );
+    }
+
+    #[kernel_test]
+    fn start_and_stop() {
+        let hooks = SubsystemHooks {
+            start: test_start,
+            stop: test_stop,
+        };
+        assert!(register("net", hooks).is_err());
+        // registered directly, as test is not a subsystem of the kernel
+        RUNTIME.lock().insert(
+            "test",
+            Runtime {
+                hooks,
+                state: SubsystemState::Stopped,
+            },
+        );
+
+        assert!(start("test").is_ok());
+        assert!(start("test").is_ok());
+        assert_eq!(STARTS.load(Ordering::Relaxed), 1);
+        assert_eq!(state("test"), Some(SubsystemState::Running));
+        assert_eq!(Subsystems.write(b"stop test\n", 0).unwrap(), 10);
+        assert_eq!(state("test"), Some(SubsystemState::Stopped));
+
+        FAIL_START.store(true, Ordering::Relaxed);
+        assert!(Subsystems.write(b"start test", 0).is_err());
+        assert_eq!(state("test"), Some(SubsystemState::Stopped));
+        FAIL_START.store(false, Ordering::Relaxed);
+
+        assert!(Subsystems.write(b"start nothing", 0).is_err());
+        assert!(Subsystems.write(b"test", 0).is_err());
+        assert!(stop("nothing").is_err());
+        RUNTIME.lock().remove("test");
+
+        let mut buf = [0; 256];
+        let n = Subsystems.read(&mut buf, 0).unwrap();
+        let rendered = str::from_utf8(&buf[..n]).unwrap();
+        assert_eq!(rendered.lines().count(), SUBSYSTEMS.len());
+        assert!(rendered.contains(if cfg!(feature = "usb") {
+            "usb\tbuilt-in\tstatic\n"
+        } else {
+            "usb\tabsent\tstatic\n"
+        }));
+    }
+}
@@ -0,0 +1,117 @@
+use super::sha256::{DIGEST_SIZE, Sha256};
+use crate::{arch, sync::locks::Mutex};
+
+// the pool is a single sha256 chaining value. Input is hashed into it, output is derived from a key, which is
+// extracted and replaced under the lock, such that earlier output cannot be reconstructed from a later state
+
+/// bits of credited entropy, after which the pool counts as seeded
+pub const SEED_BITS: usize = 256;
+
+static POOL: Mutex<Pool> = Mutex::new(Pool::new());
+
+#[derive(Debug)]
+struct Pool {
+    state: [u8; DIGEST_SIZE],
+    credited: usize,
+}
+
+impl Pool {
+    const fn new() -> Self {
+        Self {
+            state: [0; DIGEST_SIZE],
+            credited: 0,
+        }
+    }
+
+    fn mix(&mut self, data: &[u8]) {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.state);
+        hasher.update(data);
+        self.state = hasher.finalize().0;
+    }
+
+    /// returns a fresh key and moves the pool on to an unrelated state
+    fn extract(&mut self) -> [u8; DIGEST_SIZE] {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.state);
+        let mut next = hasher.clone();
+        hasher.update(b"extract");
+        next.update(b"next");
+        self.state = next.finalize().0;
+        hasher.finalize().0
+    }
+}
+
+/// mixes data into the pool and credits it with bits of entropy.
+/// Sources of unknown quality are mixed in with 0 bits, which never hurts
+pub fn add_entropy(data: &[u8], bits: usize) {
+    let mut pool = POOL.lock();
+    pool.mix(data);
+    pool.credited = pool.credited.saturating_add(bits);
+}
+
+/// whether at least SEED_BITS of entropy were credited
+pub fn is_seeded() -> bool {
+    credited_bits() >= SEED_BITS
+}
+
+pub fn credited_bits() -> usize {
+    POOL.lock().credited
+}
+
+/// fills buf with output of the pool.
+/// The timestamp and rdrand, if available, are mixed in on each call, without being credited
+pub fn fill(buf: &mut [u8]) {
+    let mut noise = [0; 2 * size_of::<u64>()];
+    noise[..8].copy_from_slice(&arch::timestamp().to_le_bytes());
+    noise[8..].copy_from_slice(&arch::hw_random().unwrap_or_default().to_le_bytes());
+    let key = {
+        let mut pool = POOL.lock();
+        pool.mix(&noise);
+        pool.extract()
+    };
+    // the output is generated without holding the lock, as buf may be large
+    for (counter, chunk) in buf.chunks_mut(DIGEST_SIZE).enumerate() {
+        let mut hasher = Sha256::new();
+        hasher.update(&key);
+        hasher.update(&(counter as u64).to_le_bytes());
+        chunk.copy_from_slice(&hasher.finalize().0[..chunk.len()]);
+    }
+}
+
+pub fn random_u64() -> u64 {
+    let mut bytes = [0; size_of::<u64>()];
+    fill(&mut bytes);
+    u64::from_le_bytes(bytes)
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    #[kernel_test]
+    fn pool_output() {
+        let mut pool = Pool::new();
+        let mut other = Pool::new();
+        pool.mix(b"seed");
+        other.mix(b"seed");
+        // the same input yields the same keys, but never the same key twice
+        let key = pool.extract();
+        assert_eq!(key, other.extract());
+        assert_ne!(key, pool.extract());
+        other.mix(b"more");
+        assert_ne!(pool.extract(), other.extract());
+
+        // uncredited, the tests must not make the pool look seeded
+        add_entropy(b"test input", 0);
+        let mut a = [0; 100];
+        let mut b = [0; 100];
+        fill(&mut a);
+        fill(&mut b);
+        assert_ne!(a, b);
+        // consecutive blocks of one output differ
+        assert_ne!(a[..DIGEST_SIZE], a[DIGEST_SIZE..2 * DIGEST_SIZE]);
+    }
+}
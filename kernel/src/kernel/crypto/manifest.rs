@@ -0,0 +1,232 @@
+use alloc::{collections::btree_map::BTreeMap, vec::Vec};
+use core::str::FromStr;
+
+use conquer_once::spin::OnceCell;
+use os_macros::init_task;
+use thiserror::Error;
+use tinyos_abi::{
+    flags::{NodePermissions, OpenOptions},
+    types::PermUpdateStrategy,
+};
+
+use super::sha256::{Digest, sha256};
+use crate::{
+    bootinfo,
+    eprintln,
+    kernel::{
+        fd::FileRepr,
+        fs::{self, Path, PathBuf},
+        io::{Read, Write},
+    },
+    serial_println,
+};
+
+// binaries, which run in kernel mode, can be pinned to known contents through a manifest in the initramfs.
+// The manifest is loaded once at boot, such that later writes to it have no effect.
+// It is trusted, as it comes from the boot medium, thus it only holds hashes and no signatures
+
+/// where the manifest is placed in the initramfs. A boot module named manifest is copied here at boot
+pub const MANIFEST_FILE: &str = "/ram/etc/manifest";
+/// kernel command line option, which selects the enforcement: verify=off|warn|enforce
+pub const VERIFY_OPTION: &str = "verify";
+
+static ENFORCEMENT: OnceCell<Enforcement> = OnceCell::uninit();
+static MANIFEST: OnceCell<Manifest> = OnceCell::uninit();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Enforcement {
+    /// binaries are not verified
+    #[default]
+    Off,
+    /// failed verifications are logged, but the binary runs anyway
+    Warn,
+    /// binaries, which fail verification, are refused
+    Enforce,
+}
+
+impl FromStr for Enforcement {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Self::Off),
+            "warn" => Ok(Self::Warn),
+            "enforce" => Ok(Self::Enforce),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Error, Debug, PartialEq, Eq, Clone)]
+pub enum VerifyError {
+    #[error("no manifest was loaded")]
+    NoManifest,
+    #[error("{0} is not listed in the manifest")]
+    NotListed(PathBuf),
+    #[error("the hash of {0} does not match the manifest")]
+    Mismatch(PathBuf),
+    #[error("malformed manifest entry in line {0}")]
+    Malformed(usize),
+}
+
+/// absolute paths mapped to the sha256 of their contents.
+/// The format is the one of sha256sum: one '<hex digest>  <path>' entry per line. Empty lines and lines starting with # are skipped
+#[derive(Debug, Default, Clone)]
+pub struct Manifest {
+    entries: BTreeMap<PathBuf, Digest>,
+}
+
+impl Manifest {
+    pub fn parse(text: &str) -> Result<Self, VerifyError> {
+        let mut entries = BTreeMap::new();
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (digest, path) = line
+                .split_once(char::is_whitespace)
+                .ok_or(VerifyError::Malformed(idx + 1))?;
+            // sha256sum marks binary mode with a leading *
+            let path = path.trim_start().trim_start_matches('*');
+            let digest = digest
+                .parse::<Digest>()
+                .map_err(|_| VerifyError::Malformed(idx + 1))?;
+            if !path.starts_with('/') {
+                return Err(VerifyError::Malformed(idx + 1));
+            }
+            entries.insert(PathBuf::from(path), digest);
+        }
+        Ok(Self { entries })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// checks that data, read from path, matches the entry of path
+    pub fn verify(&self, path: &Path, data: &[u8]) -> Result<(), VerifyError> {
+        let expected = self
+            .entries
+            .get(path)
+            .ok_or_else(|| VerifyError::NotListed(path.into()))?;
+        if sha256(data) != *expected {
+            return Err(VerifyError::Mismatch(path.into()));
+        }
+        Ok(())
+    }
+}
+
+pub fn enforcement() -> Enforcement {
+    *ENFORCEMENT.get_or_init(|| {
+        bootinfo::cmdline_option(VERIFY_OPTION)
+            .and_then(|value| value.parse().ok())
+            .unwrap_or_default()
+    })
+}
+
+pub fn manifest() -> Option<&'static Manifest> {
+    MANIFEST.get()
+}
+
+/// verifies a binary, which is about to run in kernel mode, against the manifest, as configured by the command line.
+/// data is the image read from path. Builtins share the same image, the builtin marker, thus their entries only decide
+/// which paths may run as builtins. Only fails with enforcement set to enforce
+pub fn verify_privileged(path: &Path, data: &[u8]) -> Result<(), VerifyError> {
+    let enforcement = enforcement();
+    if enforcement == Enforcement::Off {
+        return Ok(());
+    }
+    let res = manifest()
+        .ok_or(VerifyError::NoManifest)
+        .and_then(|manifest| manifest.verify(path, data));
+    match (res, enforcement) {
+        (Err(e), Enforcement::Enforce) => Err(e),
+        (Err(e), _) => {
+            eprintln!("running unverified privileged binary {}: {}", path, e);
+            Ok(())
+        }
+        (Ok(()), _) => Ok(()),
+    }
+}
+
+// runs after the initial and builtin binaries were placed into the initramfs
+#[init_task(stage = "fs", order = 40)]
+fn load_manifest() {
+    let path = Path::new(MANIFEST_FILE);
+    if let Some(module) = bootinfo::modules().find(|module| module.name().ends_with("manifest"))
+        && let Ok(file) = fs::open(path, OpenOptions::CREATE_ALL | OpenOptions::WRITE)
+    {
+        if let Err(e) = file.write_all(module.bytes(), 0) {
+            eprintln!("could not copy the manifest into the initramfs.\n{}", e);
+        }
+        file.update_perms(NodePermissions::read(), PermUpdateStrategy::OVERWRITE);
+    }
+
+    let mut text = Vec::new();
+    let Ok(file) = fs::open(path, OpenOptions::READ) else {
+        if enforcement() != Enforcement::Off {
+            eprintln!(
+                "no manifest at {}, privileged binaries cannot be verified",
+                path
+            );
+        }
+        return;
+    };
+    let Ok(n) = file.read_to_end(&mut text, 0) else {
+        eprintln!("could not read the manifest");
+        return;
+    };
+    match str::from_utf8(&text[..n])
+        .map_err(|_| VerifyError::Malformed(0))
+        .and_then(Manifest::parse)
+    {
+        Ok(manifest) => {
+            serial_println!("loaded manifest with {} entries", manifest.len());
+            MANIFEST.init_once(|| manifest);
+        }
+        Err(e) => eprintln!("could not load the manifest: {}", e),
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use alloc::format;
+
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    #[kernel_test]
+    fn manifest_entries() {
+        let text = format!(
+            "# pinned binaries\n{}  /ram/bin/shutdown\n\n{} */ram/bin/serial\n",
+            sha256(b"tiny_builtin"),
+            sha256(b"serial"),
+        );
+        let manifest = Manifest::parse(&text).unwrap();
+        assert_eq!(manifest.len(), 2);
+        assert_eq!(
+            manifest.verify(Path::new("/ram/bin/shutdown"), b"tiny_builtin"),
+            Ok(())
+        );
+        assert_eq!(
+            manifest.verify(Path::new("/ram/bin/serial"), b"tiny_builtin"),
+            Err(VerifyError::Mismatch(PathBuf::from("/ram/bin/serial")))
+        );
+        assert_eq!(
+            manifest.verify(Path::new("/ram/bin/other"), b"tiny_builtin"),
+            Err(VerifyError::NotListed(PathBuf::from("/ram/bin/other")))
+        );
+        assert_eq!(
+            Manifest::parse("abc /ram/bin/shutdown").unwrap_err(),
+            VerifyError::Malformed(1)
+        );
+        assert_eq!("enforce".parse(), Ok(Enforcement::Enforce));
+        assert!("on".parse::<Enforcement>().is_err());
+    }
+}
@@ -0,0 +1,3 @@
+pub mod entropy;
+pub mod manifest;
+pub mod sha256;
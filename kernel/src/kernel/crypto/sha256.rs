@@ -0,0 +1,209 @@
+use core::{
+    fmt::{Display, LowerHex},
+    str::FromStr,
+};
+
+const BLOCK_SIZE: usize = 64;
+pub const DIGEST_SIZE: usize = 32;
+
+const H0: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// a sha256 hash
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Digest(pub [u8; DIGEST_SIZE]);
+
+impl LowerHex for Digest {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Digest {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:x}", self)
+    }
+}
+
+impl FromStr for Digest {
+    type Err = ();
+
+    /// parses 64 hex digits, as printed by sha256sum
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != 2 * DIGEST_SIZE || !s.is_ascii() {
+            return Err(());
+        }
+        let mut digest = [0; DIGEST_SIZE];
+        for (byte, hex) in digest.iter_mut().zip(s.as_bytes().chunks_exact(2)) {
+            // is_ascii guarantees char boundaries
+            *byte = u8::from_str_radix(str::from_utf8(hex).map_err(|_| ())?, 16).map_err(|_| ())?;
+        }
+        Ok(Self(digest))
+    }
+}
+
+/// incremental sha256, as specified in FIPS 180-4
+#[derive(Debug, Clone)]
+pub struct Sha256 {
+    state: [u32; 8],
+    block: [u8; BLOCK_SIZE],
+    // bytes buffered in block
+    filled: usize,
+    // total bytes hashed
+    len: u64,
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sha256 {
+    pub const fn new() -> Self {
+        Self {
+            state: H0,
+            block: [0; BLOCK_SIZE],
+            filled: 0,
+            len: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+        while !data.is_empty() {
+            let n = (BLOCK_SIZE - self.filled).min(data.len());
+            self.block[self.filled..self.filled + n].copy_from_slice(&data[..n]);
+            self.filled += n;
+            data = &data[n..];
+            if self.filled == BLOCK_SIZE {
+                self.compress();
+                self.filled = 0;
+            }
+        }
+    }
+
+    pub fn finalize(mut self) -> Digest {
+        let bits = self.len.wrapping_mul(8);
+        self.block[self.filled] = 0x80;
+        self.filled += 1;
+        // the length does not fit into this block anymore
+        if self.filled > BLOCK_SIZE - 8 {
+            self.block[self.filled..].fill(0);
+            self.compress();
+            self.filled = 0;
+        }
+        self.block[self.filled..BLOCK_SIZE - 8].fill(0);
+        self.block[BLOCK_SIZE - 8..].copy_from_slice(&bits.to_be_bytes());
+        self.compress();
+
+        let mut digest = [0; DIGEST_SIZE];
+        for (bytes, word) in digest.chunks_exact_mut(4).zip(self.state) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+        Digest(digest)
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+        for (word, bytes) in w.iter_mut().zip(self.block.chunks_exact(4)) {
+            *word = u32::from_be_bytes(bytes.try_into().unwrap());
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for (k, w) in K.into_iter().zip(w) {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(k)
+                .wrapping_add(w);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (state, word) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(word);
+        }
+    }
+}
+
+pub fn sha256(data: &[u8]) -> Digest {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hasher.finalize()
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use alloc::{format, vec};
+
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    #[kernel_test]
+    fn known_digests() {
+        assert_eq!(
+            format!("{}", sha256(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            format!("{}", sha256(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        // padding spills into a second block
+        assert_eq!(
+            format!(
+                "{}",
+                sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")
+            ),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[kernel_test]
+    fn incremental_updates() {
+        let data = vec![0x61; 1000];
+        let mut hasher = Sha256::new();
+        for chunk in data.chunks(7) {
+            hasher.update(chunk);
+        }
+        let digest = hasher.finalize();
+        assert_eq!(digest, sha256(&data));
+        assert_eq!(format!("{}", digest).parse::<Digest>(), Ok(digest));
+        assert!("abc".parse::<Digest>().is_err());
+    }
+}
@@ -0,0 +1,218 @@
+use alloc::{collections::btree_map::BTreeMap, format, string::String, vec::Vec};
+use core::fmt::Write as _;
+
+use os_macros::FileRepr;
+use tinyos_abi::flags::NodeType;
+
+use crate::{
+    arch::{
+        self,
+        interrupt::{
+            nmi::{disable_watchdog, enable_watchdog, nmi_stats, watchdog_enabled},
+            profile::{profile_source, samples, samples_taken, start_profiling, stop_profiling},
+        },
+    },
+    impl_file_for_wr,
+    kernel::{
+        elf::ksyms,
+        fs::FSErrorKind,
+        io::{IOError, IOResult, Read, Write, read_rendered},
+    },
+};
+
+pub const CPU_INFO_FILE: &str = "/cpuinfo";
+
+/// /proc/cpuinfo: cpuid information and the acpi processor topology, rendered on every read
+#[derive(Debug, Default, Clone, Copy, FileRepr)]
+#[file(read)]
+pub struct CpuInfo;
+
+impl Read for CpuInfo {
+    fn read(&self, buf: &mut [u8], offset: usize) -> IOResult<usize> {
+        read_rendered(arch::cpuinfo(), buf, offset)
+    }
+}
+
+pub const INTERRUPTS_FILE: &str = "/interrupts";
+
+/// /proc/interrupts: the number of interrupts per vector and cpu, rendered on every read
+#[derive(Debug, Default, Clone, Copy, FileRepr)]
+#[file(read)]
+pub struct Interrupts;
+
+impl Read for Interrupts {
+    fn read(&self, buf: &mut [u8], offset: usize) -> IOResult<usize> {
+        read_rendered(arch::interrupts(), buf, offset)
+    }
+}
+
+pub const SERIAL_FILE: &str = "/kernel/serial";
+
+/// /proc/kernel/serial: whether the serial output is queued and how many bytes and lines went through or were dropped
+#[derive(Debug, Default, Clone, Copy, FileRepr)]
+#[file(read)]
+pub struct SerialStats;
+
+impl Read for SerialStats {
+    fn read(&self, buf: &mut [u8], offset: usize) -> IOResult<usize> {
+        read_rendered(arch::serial_stats(), buf, offset)
+    }
+}
+
+pub const NMI_WATCHDOG_FILE: &str = "/kernel/nmi_watchdog";
+
+/// /proc/kernel/nmi_watchdog: nmi counters and the state of the hard lockup detector.
+/// Writing 1 starts the detector with a period of half a second worth of cycles, writing 0 stops it
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NmiWatchdog;
+
+impl NmiWatchdog {
+    fn render(&self) -> String {
+        let stats = nmi_stats();
+        format!(
+            "enabled\t{}\nnmis\t{}\nhard_lockups\t{}\nlast_rip\t{:#x}\nlast_tid\t{}\n",
+            watchdog_enabled() as u8,
+            stats.nmis,
+            stats.hard_lockups,
+            stats.last_rip,
+            stats.last_tid
+        )
+    }
+}
+
+impl Read for NmiWatchdog {
+    fn read(&self, buf: &mut [u8], offset: usize) -> IOResult<usize> {
+        read_rendered(self.render(), buf, offset)
+    }
+}
+
+impl Write for NmiWatchdog {
+    fn write(&self, buf: &[u8], _offset: usize) -> IOResult<usize> {
+        match buf.trim_ascii() {
+            b"0" => disable_watchdog(),
+            b"1" => enable_watchdog(arch::timestamp_frequency() / 2)
+                .map_err(|_| IOError::simple(FSErrorKind::NotSupported))?,
+            _ => return Err(IOError::simple(FSErrorKind::Other)),
+        }
+        Ok(buf.len())
+    }
+}
+
+impl_file_for_wr!(NmiWatchdog: NodeType::FILE);
+
+pub const PROFILE_FILE: &str = "/profile";
+
+/// /proc/profile: the sampled rips of the profiler, aggregated by kernel symbol.
+/// Writing 1 starts sampling once per millisecond worth of cycles, writing 0 stops it
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Profile;
+
+impl Profile {
+    fn render(&self) -> String {
+        let samples = samples();
+        let mut per_symbol: BTreeMap<&str, usize> = BTreeMap::new();
+        for rip in &samples {
+            let name = ksyms::resolve(*rip).map_or("[unknown]", |(sym, _)| sym.name);
+            *per_symbol.entry(name).or_default() += 1;
+        }
+        let mut per_symbol: Vec<(&str, usize)> = per_symbol.into_iter().collect();
+        per_symbol.sort_by(|a, b| b.1.cmp(&a.1));
+
+        let mut rendered = format!(
+            "source\t{:?}\nsamples\t{} ({} taken)\n",
+            profile_source(),
+            samples.len(),
+            samples_taken()
+        );
+        for (name, count) in per_symbol {
+            let permyriad = count * 10000 / samples.len();
+            _ = writeln!(
+                rendered,
+                "{:>8} {:>3}.{:02}% {}",
+                count,
+                permyriad / 100,
+                permyriad % 100,
+                ksyms::demangle(name)
+            );
+        }
+        rendered
+    }
+}
+
+impl Read for Profile {
+    fn read(&self, buf: &mut [u8], offset: usize) -> IOResult<usize> {
+        read_rendered(self.render(), buf, offset)
+    }
+}
+
+impl Write for Profile {
+    fn write(&self, buf: &[u8], _offset: usize) -> IOResult<usize> {
+        match buf.trim_ascii() {
+            b"0" => stop_profiling(),
+            b"1" => {
+                start_profiling(arch::timestamp_frequency() / 1000);
+            }
+            _ => return Err(IOError::simple(FSErrorKind::Other)),
+        }
+        Ok(buf.len())
+    }
+}
+
+impl_file_for_wr!(Profile: NodeType::FILE);
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use alloc::vec;
+
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    #[kernel_test]
+    fn cpu_info() {
+        let (cpu, node) = arch::current_cpu();
+        assert_eq!(node, 0);
+        assert!((cpu as usize) < arch::x86::cpu::topology().len());
+
+        let mut buf = vec![0; 4096];
+        let n = CpuInfo.read(&mut buf, 0).unwrap();
+        let info = core::str::from_utf8(&buf[..n]).unwrap();
+        assert!(info.starts_with("processor\t: 0"));
+        assert!(info.contains("vendor_id"));
+        assert!(info.contains("flags"));
+    }
+
+    #[kernel_test]
+    fn interrupts() {
+        let mut buf = vec![0; 4096];
+        let n = Interrupts.read(&mut buf, 0).unwrap();
+        let rendered = core::str::from_utf8(&buf[..n]).unwrap();
+        assert!(rendered.lines().next().unwrap().contains("CPU0"));
+        assert!(rendered.contains("LAPIC timer"));
+    }
+
+    #[kernel_test]
+    fn nmi_watchdog() {
+        let mut buf = vec![0; 256];
+        let n = NmiWatchdog.read(&mut buf, 0).unwrap();
+        let rendered = core::str::from_utf8(&buf[..n]).unwrap();
+        assert!(rendered.starts_with("enabled\t0\n"));
+        assert!(NmiWatchdog.write(b"2", 0).is_err());
+        assert_eq!(NmiWatchdog.write(b"0\n", 0).unwrap(), 2);
+    }
+
+    #[kernel_test]
+    fn profile() {
+        assert_eq!(Profile.write(b"1", 0).unwrap(), 1);
+        while samples_taken() < 16 {
+            crate::kernel::threading::yield_now();
+        }
+        assert_eq!(Profile.write(b"0", 0).unwrap(), 1);
+        let mut buf = vec![0; 4096];
+        let n = Profile.read(&mut buf, 0).unwrap();
+        let rendered = core::str::from_utf8(&buf[..n]).unwrap();
+        assert!(rendered.starts_with("source\tOff\n"));
+        assert!(rendered.lines().count() > 2);
+        assert!(Profile.write(b"on", 0).is_err());
+    }
+}
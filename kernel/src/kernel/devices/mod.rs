@@ -1,21 +1,108 @@
-use crate::create_device_file;
+use os_macros::{FileRepr, init_task};
 
+use crate::{
+    create_device_file,
+    kernel::{
+        config::{SUBSYSTEMS_FILE, Subsystems},
+        fd::FDTableView,
+        fs::{
+            OpenOptions,
+            vfs::{MOUNTS_FILE, MountList},
+        },
+        io::{IOResult, Read, Write},
+        mem::paging::{FRAMES_FILE, Frames},
+        threading::{
+            group,
+            schedule::{
+                latency::{SCHED_LAT_FILE, SchedLat},
+                stats::{SCHED_STAT_FILE, SCHED_TOP_FILE, SchedStat, SchedTop},
+            },
+            tls::TaskList,
+        },
+    },
+};
+
+pub mod cpu;
 pub mod graphics;
+pub mod pseudo;
 pub mod tty;
 
+pub use cpu::*;
+pub use pseudo::*;
+
 pub static NULL: Null = Null;
 pub const NULL_FILE: &str = "/kernel/null";
 
+pub static FD_TABLES: FDTableView = FDTableView;
+pub const FD_TABLES_FILE: &str = "/kernel/fds";
+
+pub static CPU_INFO: CpuInfo = CpuInfo;
+pub static INTERRUPTS: Interrupts = Interrupts;
+pub static SERIAL_STATS: SerialStats = SerialStats;
+pub static NMI_WATCHDOG: NmiWatchdog = NmiWatchdog;
+pub static PROFILE: Profile = Profile;
+pub static FRAMES: Frames = Frames;
+
+pub static TASKS: TaskList = TaskList;
+pub const TASKS_FILE: &str = "/tasks";
+
+pub static MOUNTS: MountList = MountList;
+
+pub static SUBSYSTEMS: Subsystems = Subsystems;
+
+pub static SCHED_STAT: SchedStat = SchedStat;
+pub static SCHED_TOP: SchedTop = SchedTop;
+pub static SCHED_LAT: SchedLat = SchedLat;
+
+pub static DEV_ZERO: Zero = Zero;
+pub static DEV_FULL: Full = Full;
+pub static DEV_RANDOM: Random = Random::new();
+
 fn init_() {
     _ = create_device_file!(&NULL, NULL_FILE);
+    _ = create_device_file!(&FD_TABLES, FD_TABLES_FILE);
+    _ = create_device_file!(&CPU_INFO, CPU_INFO_FILE);
+    _ = create_device_file!(&INTERRUPTS, INTERRUPTS_FILE);
+    _ = create_device_file!(&SERIAL_STATS, SERIAL_FILE);
+    _ = create_device_file!(&FRAMES, FRAMES_FILE);
+    _ = create_device_file!(&TASKS, TASKS_FILE);
+    _ = create_device_file!(&MOUNTS, MOUNTS_FILE);
+    _ = create_device_file!(&SCHED_STAT, SCHED_STAT_FILE);
+    _ = create_device_file!(&SCHED_TOP, SCHED_TOP_FILE);
+    _ = create_device_file!(&SCHED_LAT, SCHED_LAT_FILE);
+
+    let rw = OpenOptions::READ | OpenOptions::WRITE | OpenOptions::CREATE_ALL;
+    _ = create_device_file!(&NULL, DEV_NULL_FILE, rw);
+    _ = create_device_file!(&DEV_ZERO, DEV_ZERO_FILE, rw);
+    _ = create_device_file!(&DEV_FULL, DEV_FULL_FILE, rw);
+    _ = create_device_file!(&DEV_RANDOM, DEV_RANDOM_FILE, rw);
+    _ = create_device_file!(&NMI_WATCHDOG, NMI_WATCHDOG_FILE, rw);
+    _ = create_device_file!(&PROFILE, PROFILE_FILE, rw);
+    _ = create_device_file!(&SUBSYSTEMS, SUBSYSTEMS_FILE, rw);
+    group::init();
 }
 
+#[init_task(stage = "fs", order = 10)]
 pub fn init() {
     init_();
     tty::init();
     graphics::init();
 }
 
-// a placeholder device, which simply does nothing
-#[derive(Clone, Copy, Debug, Default)]
+// a placeholder device, which simply does nothing. It also backs /dev/null: reads are always at eof,
+// writes are accepted and discarded
+#[derive(Clone, Copy, Debug, Default, FileRepr)]
+#[file(read, write)]
 pub struct Null;
+
+impl Read for Null {
+    fn read(&self, _buf: &mut [u8], _offset: usize) -> IOResult<usize> {
+        Ok(0)
+    }
+}
+
+impl Write for Null {
+    fn write(&self, buf: &[u8], _offset: usize) -> IOResult<usize> {
+        Ok(buf.len())
+    }
+}
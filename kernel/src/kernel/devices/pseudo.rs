@@ -0,0 +1,111 @@
+use tinyos_abi::flags::NodeType;
+
+use crate::{
+    impl_file_for_wr,
+    kernel::{
+        crypto::entropy,
+        fs::FSErrorKind,
+        io::{IOError, IOResult, Read, Write},
+    },
+};
+
+pub const DEV_NULL_FILE: &str = "/dev/null";
+pub const DEV_ZERO_FILE: &str = "/dev/zero";
+pub const DEV_FULL_FILE: &str = "/dev/full";
+pub const DEV_RANDOM_FILE: &str = "/dev/random";
+
+/// /dev/zero: an infinite stream of zeros, writes are accepted and discarded
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Zero;
+
+impl Read for Zero {
+    fn read(&self, buf: &mut [u8], _offset: usize) -> IOResult<usize> {
+        buf.fill(0);
+        Ok(buf.len())
+    }
+}
+
+impl Write for Zero {
+    fn write(&self, buf: &[u8], _offset: usize) -> IOResult<usize> {
+        Ok(buf.len())
+    }
+}
+
+impl_file_for_wr!(Zero: NodeType::FILE);
+
+/// /dev/full: reads like /dev/zero, every write fails with StorageFull (ENOSPC)
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Full;
+
+impl Read for Full {
+    fn read(&self, buf: &mut [u8], offset: usize) -> IOResult<usize> {
+        Zero.read(buf, offset)
+    }
+}
+
+impl Write for Full {
+    fn write(&self, _buf: &[u8], _offset: usize) -> IOResult<usize> {
+        Err(IOError::simple(FSErrorKind::StorageFull))
+    }
+}
+
+impl_file_for_wr!(Full: NodeType::FILE);
+
+/// /dev/random: output of the kernel entropy pool, which is seeded by virtio-rng if present and mixes in rdrand and the tsc on every read.
+/// Writes are mixed into the pool, without being credited as entropy
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Random;
+
+impl Random {
+    pub const fn new() -> Self {
+        Self
+    }
+}
+
+impl Read for Random {
+    fn read(&self, buf: &mut [u8], _offset: usize) -> IOResult<usize> {
+        entropy::fill(buf);
+        Ok(buf.len())
+    }
+}
+
+impl Write for Random {
+    fn write(&self, buf: &[u8], _offset: usize) -> IOResult<usize> {
+        entropy::add_entropy(buf, 0);
+        Ok(buf.len())
+    }
+}
+
+impl_file_for_wr!(Random: NodeType::FILE);
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+    use crate::kernel::devices::Null;
+
+    #[kernel_test]
+    fn pseudo_devices() {
+        let mut buf = [0xff; 13];
+        assert_eq!(Null.read(&mut buf, 0).unwrap(), 0);
+        assert_eq!(Null.write(&buf, 0).unwrap(), buf.len());
+
+        assert_eq!(Zero.read(&mut buf, 42).unwrap(), buf.len());
+        assert!(buf.iter().all(|b| *b == 0));
+
+        buf.fill(0xff);
+        assert_eq!(Full.read(&mut buf, 0).unwrap(), buf.len());
+        assert!(buf.iter().all(|b| *b == 0));
+        assert_eq!(
+            *Full.write(&buf, 0).unwrap_err().kind(),
+            FSErrorKind::StorageFull
+        );
+
+        let random = Random::new();
+        let mut other = [0; 13];
+        assert_eq!(random.read(&mut buf, 0).unwrap(), buf.len());
+        assert_eq!(random.read(&mut other, 0).unwrap(), other.len());
+        assert_ne!(buf, other);
+    }
+}
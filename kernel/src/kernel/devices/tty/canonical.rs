@@ -0,0 +1,130 @@
+use alloc::{collections::vec_deque::VecDeque, string::String};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use os_macros::FileRepr;
+
+use super::{TTYSink, sink::FBBACKEND};
+use crate::{
+    kernel::{
+        fs::FSErrorKind,
+        io::{IOError, IOResult, Read, Write, read_rendered},
+    },
+    sync::locks::Mutex,
+    term::logic::editor::LineEditor,
+};
+
+/// /proc/kernel/io/canonical: 1 while stdin is line edited, 0 while it is passed through raw. Writing either switches
+pub const CANONICAL_FILE: &str = "/kernel/io/canonical";
+
+static CANONICAL: AtomicBool = AtomicBool::new(false);
+
+// a single session is assumed, thus a single editor serves every reader of the console
+static EDITOR: Mutex<LineEditor> = Mutex::new(LineEditor::new());
+
+/// lines entered, but not read yet, each terminated by a newline
+static LINES: Mutex<VecDeque<u8>> = Mutex::new(VecDeque::new());
+
+pub fn is_canonical() -> bool {
+    CANONICAL.load(Ordering::Acquire)
+}
+
+pub fn set_canonical(canonical: bool) {
+    CANONICAL.store(canonical, Ordering::Release);
+}
+
+/// whether a complete line is waiting to be read
+pub fn has_line() -> bool {
+    !LINES.lock().is_empty()
+}
+
+/// reads a line in canonical mode. The raw input is taken from read_raw until it runs dry, passed through the line
+/// editor and echoed to the screen. A read returns at most a single line, including its newline, and 0 until one is complete
+pub fn read_line(
+    buf: &mut [u8],
+    mut read_raw: impl FnMut(&mut [u8]) -> IOResult<usize>,
+) -> IOResult<usize> {
+    let mut raw = [0; 64];
+    let mut echo = String::new();
+    loop {
+        let n = read_raw(&mut raw)?;
+        if n == 0 {
+            break;
+        }
+        let mut editor = EDITOR.lock();
+        for &byte in &raw[..n] {
+            if let Some(line) = editor.feed(byte, &mut echo) {
+                let mut lines = LINES.lock();
+                lines.extend(line.as_bytes());
+                lines.push_back(b'\n');
+            }
+        }
+    }
+    if !echo.is_empty()
+        && let Some(sink) = FBBACKEND.get()
+    {
+        TTYSink::write(&**sink, echo.as_bytes());
+    }
+    let mut lines = LINES.lock();
+    let mut n = 0;
+    while n < buf.len()
+        && let Some(byte) = lines.pop_front()
+    {
+        buf[n] = byte;
+        n += 1;
+        if byte == b'\n' {
+            break;
+        }
+    }
+    Ok(n)
+}
+
+#[derive(Debug, Default, Clone, Copy, FileRepr)]
+#[file(read, write)]
+pub struct Canonical;
+
+impl Read for Canonical {
+    fn read(&self, buf: &mut [u8], offset: usize) -> IOResult<usize> {
+        let rendered: &[u8] = if is_canonical() { b"1\n" } else { b"0\n" };
+        read_rendered(rendered, buf, offset)
+    }
+}
+
+impl Write for Canonical {
+    fn write(&self, buf: &[u8], _offset: usize) -> IOResult<usize> {
+        match buf.trim_ascii() {
+            b"1" => set_canonical(true),
+            b"0" => set_canonical(false),
+            _ => {
+                return Err(IOError::with_message(FSErrorKind::Other, "expected 1 or 0"));
+            }
+        }
+        Ok(buf.len())
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    #[kernel_test]
+    fn lines_are_edited() {
+        let mut input: &[u8] = b"ls /prc\x1b[Do\nps\nre";
+        let mut read_raw = |raw: &mut [u8]| -> IOResult<usize> {
+            let n = raw.len().min(input.len()).min(5);
+            raw[..n].copy_from_slice(&input[..n]);
+            input = &input[n..];
+            Ok(n)
+        };
+        let mut buf = [0; 16];
+        assert_eq!(read_line(&mut buf, &mut read_raw).unwrap(), 9);
+        assert_eq!(&buf[..9], b"ls /proc\n");
+        assert_eq!(read_line(&mut buf, &mut read_raw).unwrap(), 3);
+        assert_eq!(&buf[..3], b"ps\n");
+        // the rest of the line is kept in the editor
+        assert_eq!(read_line(&mut buf, &mut read_raw).unwrap(), 0);
+        assert!(!has_line());
+        EDITOR.lock().feed(b'\n', &mut String::new());
+    }
+}
@@ -0,0 +1,142 @@
+use alloc::vec::Vec;
+
+use pc_keyboard::{DecodedKey, KeyCode};
+use tinyos_abi::{flags::NodeType, types::FStat};
+
+use super::source::inject_input;
+use crate::{
+    drivers::keyboard::HeldModifiers,
+    kernel::{
+        fd::{FileRepr, IOCapable},
+        io::{IOResult, Read, Write},
+    },
+    sync::locks::Mutex,
+    term,
+};
+
+pub const CLIPBOARD_FILE: &str = "/kernel/clipboard";
+
+// ctrl + letter, as decoded with HandleControl::MapLettersToUnicode
+const CTRL_V: char = '\u{16}';
+
+static CLIPBOARD: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+
+/// replaces the contents of the clipboard
+pub fn copy(bytes: &[u8]) {
+    let mut clipboard = CLIPBOARD.lock();
+    clipboard.clear();
+    clipboard.extend_from_slice(bytes);
+}
+
+pub fn contents() -> Vec<u8> {
+    CLIPBOARD.lock().clone()
+}
+
+/// injects the clipboard into the terminal input, as if it was typed
+pub fn paste() {
+    let contents = contents();
+    if !contents.is_empty() {
+        inject_input(&contents);
+    }
+}
+
+/// whether key is a shortcut of the terminal itself, which is not passed on to readers
+pub fn is_terminal_key(key: &DecodedKey, modifiers: HeldModifiers) -> bool {
+    match key {
+        DecodedKey::RawKey(
+            KeyCode::ArrowLeft | KeyCode::ArrowRight | KeyCode::ArrowUp | KeyCode::ArrowDown,
+        ) => modifiers.shift,
+        DecodedKey::Unicode(CTRL_V) => modifiers.shift,
+        _ => false,
+    }
+}
+
+/// runs the terminal shortcuts: shift + arrows select text, which is copied to the clipboard, and ctrl + shift + v pastes it.
+/// Any other key drops the selection. Must see every key exactly once
+pub fn handle_key(key: DecodedKey, modifiers: HeldModifiers) {
+    let selection = match key {
+        _ if !is_terminal_key(&key, modifiers) => {
+            term::clear_selection();
+            return;
+        }
+        DecodedKey::RawKey(KeyCode::ArrowLeft) => term::move_selection(-1, 0),
+        DecodedKey::RawKey(KeyCode::ArrowRight) => term::move_selection(1, 0),
+        DecodedKey::RawKey(KeyCode::ArrowUp) => term::move_selection(0, -1),
+        DecodedKey::RawKey(KeyCode::ArrowDown) => term::move_selection(0, 1),
+        _ => {
+            paste();
+            return;
+        }
+    };
+    if let Some(text) = selection {
+        copy(text.as_bytes());
+    }
+}
+
+/// /proc/kernel/clipboard: reads return the clipboard, writes at offset replace everything from there on
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Clipboard;
+
+impl Read for Clipboard {
+    fn read(&self, buf: &mut [u8], offset: usize) -> IOResult<usize> {
+        let clipboard = CLIPBOARD.lock();
+        let Some(rest) = clipboard.get(offset..) else {
+            return Ok(0);
+        };
+        let len = rest.len().min(buf.len());
+        buf[..len].copy_from_slice(&rest[..len]);
+        Ok(len)
+    }
+}
+
+impl Write for Clipboard {
+    fn write(&self, buf: &[u8], offset: usize) -> IOResult<usize> {
+        let mut clipboard = CLIPBOARD.lock();
+        clipboard.resize(offset, 0);
+        clipboard.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+}
+
+impl FileRepr for Clipboard {
+    fn fstat(&self) -> FStat {
+        let mut stat = FStat::default();
+        stat.size = CLIPBOARD.lock().len();
+        stat.node_type = NodeType::FILE;
+        stat
+    }
+}
+
+impl IOCapable for Clipboard {}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+    use crate::kernel::devices::tty::{TTYSource, source::STDIN_FILE_FACTORY_FILE};
+
+    #[kernel_test]
+    fn paste_into_stdin() {
+        assert_eq!(Clipboard.write(b"hello world", 0).unwrap(), 11);
+        assert_eq!(Clipboard.write(b"there", 6).unwrap(), 5);
+        let mut buf = [0; 16];
+        assert_eq!(Clipboard.read(&mut buf, 0).unwrap(), 11);
+        assert_eq!(&buf[..11], b"hello there");
+
+        let shift = HeldModifiers {
+            shift: true,
+            ctrl: true,
+        };
+        assert!(is_terminal_key(&DecodedKey::Unicode(CTRL_V), shift));
+        assert!(!is_terminal_key(
+            &DecodedKey::Unicode(CTRL_V),
+            HeldModifiers::default()
+        ));
+        handle_key(DecodedKey::Unicode(CTRL_V), shift);
+        let stdin = STDIN_FILE_FACTORY_FILE.get().unwrap();
+        assert_eq!(stdin.read_buf(&mut buf, 0).unwrap(), 11);
+        assert_eq!(&buf[..11], b"hello there");
+        copy(b"");
+    }
+}
@@ -48,6 +48,10 @@ pub fn __write_debug(input: &str) {
     todo!()
 }
 
+pub fn __serial_debug_stub(input: Arguments) {
+    arch::_serial_debug_print(input);
+}
+
 // force prints something to serial
 pub fn __serial_stub(input: Arguments) {
     arch::_serial_print(input);
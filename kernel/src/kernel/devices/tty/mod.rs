@@ -8,25 +8,37 @@ use hashbrown::HashMap;
 use tinyos_abi::{flags::NodeType, types::FStat};
 
 use crate::{
-    impl_empty_read,
-    impl_empty_write,
+    create_device_file,
     impl_file_for_wr,
     kernel::{
         devices::Null,
-        fd::{FPerms, FileMetadata, FileRepr, IOCapable, new_fstat},
+        fd::{FPerms, FileHandle, FileMetadata, FileRepr, IOCapable, new_fstat},
+        fs::{FSErrorKind, OpenOptions},
         io::{IOError, IOResult, Read, Write},
         threading::wait::{QueuTypeCondition, QueueType},
     },
     sync::{get_next_lock_var, locks::Mutex},
 };
 
+pub mod canonical;
+pub mod clipboard;
 pub mod io;
+pub mod ratelimit;
+pub mod session;
 pub mod sink;
 pub mod source;
 
+pub static DEV_TTY: session::DevTty = session::DevTty;
+pub static CLIPBOARD: clipboard::Clipboard = clipboard::Clipboard;
+pub static CANONICAL_MODE: canonical::Canonical = canonical::Canonical;
+
 pub fn init() {
     sink::init_tty_sinks();
     source::init_source_tty();
+    let rw = OpenOptions::READ | OpenOptions::WRITE | OpenOptions::CREATE_ALL;
+    _ = create_device_file!(&DEV_TTY, session::DEV_TTY_FILE, rw);
+    _ = create_device_file!(&CLIPBOARD, clipboard::CLIPBOARD_FILE, rw);
+    _ = create_device_file!(&CANONICAL_MODE, canonical::CANONICAL_FILE, rw);
 }
 
 pub trait TTYSink: Debug + Send + Sync {
@@ -48,9 +60,14 @@ pub trait TTYSource: Debug + Send + Sync {
     }
 }
 
+/// the number of descriptors, which may be queued in a pipe at once
+pub const MAX_IN_FLIGHT: usize = 16;
+
 #[derive(Debug)]
 pub struct Pipe {
     buf: Mutex<VecDeque<u8>>,
+    /// descriptors sent through the pipe, which were not received yet
+    in_flight: Mutex<VecDeque<FileHandle>>,
     cap: usize,
     lock_descriptor: u64,
     readers: AtomicUsize,
@@ -62,6 +79,7 @@ impl Pipe {
         let lock_descriptor = get_next_lock_var();
         Self {
             buf: Mutex::default(),
+            in_flight: Mutex::default(),
             cap: if cap >= 0 { cap as usize } else { usize::MAX },
             lock_descriptor,
             readers: 0.into(),
@@ -84,8 +102,15 @@ impl Pipe {
             self.writers
                 .fetch_sub(1, core::sync::atomic::Ordering::Release);
         } else if mode.contains(FPerms::READ) {
-            self.readers
-                .fetch_sub(1, core::sync::atomic::Ordering::Release);
+            let readers = self
+                .readers
+                .fetch_sub(1, core::sync::atomic::Ordering::AcqRel);
+            if readers == 1 {
+                // nobody is left to receive the queued descriptors. They are dropped outside of the lock,
+                // as they may be pipe ends themselves
+                let in_flight = core::mem::take(&mut *self.in_flight.lock());
+                drop(in_flight);
+            }
         }
     }
 }
@@ -117,6 +142,18 @@ impl Read for Pipe {
             .for_each(|(buf_, item)| *buf_ = item);
         Ok(len)
     }
+
+    /// buf is only taken back as a whole, if it still fits into the capacity of the pipe. Otherwise StorageFull is returned
+    fn unread(&self, buf: &[u8]) -> IOResult<()> {
+        let mut internal = self.buf.lock();
+        if buf.len() > self.cap.saturating_sub(internal.len()) {
+            return Err(IOError::simple(crate::kernel::fs::FSErrorKind::StorageFull));
+        }
+        for &byte in buf.iter().rev() {
+            internal.push_front(byte);
+        }
+        Ok(())
+    }
 }
 
 impl IOCapable for Pipe {}
@@ -134,6 +171,19 @@ impl FileRepr for Pipe {
         )))
     }
 
+    fn send_fd(&self, handle: FileHandle) -> IOResult<()> {
+        let mut in_flight = self.in_flight.lock();
+        if in_flight.len() >= MAX_IN_FLIGHT {
+            return Err(IOError::simple(FSErrorKind::StorageFull));
+        }
+        in_flight.push_back(handle);
+        Ok(())
+    }
+
+    fn recv_fd(&self) -> IOResult<Option<FileHandle>> {
+        Ok(self.in_flight.lock().pop_front())
+    }
+
     fn on_open(&self, meta: crate::kernel::fd::FileMetadata) {
         self.inc_handles(meta.perms);
     }
@@ -198,17 +248,6 @@ impl TTYSource for Null {
     }
 }
 
-impl_empty_read!(Null);
-impl_empty_write!(Null);
-
-impl FileRepr for Null {
-    fn fstat(&self) -> FStat {
-        FStat::default()
-    }
-}
-
-impl IOCapable for Null {}
-
 #[macro_export]
 macro_rules! print {
     () => {};
@@ -232,9 +271,13 @@ macro_rules! dbg {
 #[macro_export]
 macro_rules! eprint {
     () => {};
-    ($($arg:tt)*) => {
-        $crate::kernel::devices::tty::io::__write_stderr(format_args!("\x1b[31m[KERR]\x1b[0m {}", format_args!($($arg)*)))
-    };
+    ($($arg:tt)*) => {{
+        static LIMIT: $crate::kernel::devices::tty::ratelimit::RateLimit =
+            $crate::kernel::devices::tty::ratelimit::RateLimit::new();
+        if LIMIT.check() {
+            $crate::kernel::devices::tty::io::__write_stderr(format_args!("\x1b[31m[KERR]\x1b[0m {}", format_args!($($arg)*)))
+        }
+    }};
 }
 
 #[macro_export]
@@ -258,9 +301,30 @@ macro_rules! serial_println {
 }
 #[macro_export]
 macro_rules! serial_print {
+    () => {};
+    ($($arg:tt)*) => {{
+        static LIMIT: $crate::kernel::devices::tty::ratelimit::RateLimit =
+            $crate::kernel::devices::tty::ratelimit::RateLimit::new();
+        if LIMIT.check() {
+            $crate::kernel::devices::tty::io::__serial_stub(format_args!("\x1b[34m[KINFO]\x1b[0m {}", format_args!($($arg)*)))
+        }
+    }};
+}
+/// like serial_println, but the line is dropped first, once the serial output cannot keep up
+#[macro_export]
+macro_rules! serial_debugln {
+    () => {
+        $crate::serial_debug!("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::serial_debug!("{}\n", format_args!($($arg)*))
+    };
+}
+#[macro_export]
+macro_rules! serial_debug {
     () => {};
     ($($arg:tt)*) => {
-        $crate::kernel::devices::tty::io::__serial_stub(format_args!("\x1b[34m[KINFO]\x1b[0m {}", format_args!($($arg)*)))
+        $crate::kernel::devices::tty::io::__serial_debug_stub(format_args!("\x1b[90m[KDEBUG]\x1b[0m {}", format_args!($($arg)*)))
     };
 }
 #[macro_export]
@@ -0,0 +1,120 @@
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use os_macros::init_task;
+
+use crate::{
+    drivers::wait_manager,
+    kernel::{
+        threading::{
+            self,
+            wait::{QueuTypeCondition, QueueType, condition::WaitCondition},
+        },
+        time::Instant,
+    },
+    serial_println,
+};
+
+/// the number of messages a call site may log at once
+pub const BURST: u64 = 20;
+/// a call site may log one more message after each interval
+const REFILL_MS: u64 = 100;
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// the number of messages dropped by all call sites since boot
+static SUPPRESSED: AtomicU64 = AtomicU64::new(0);
+
+/// a token bucket of a single call site of eprint or serial_print, such that a call site in a loop or an interrupt
+/// handler cannot flood the terminal and serial output
+#[derive(Debug)]
+pub struct RateLimit {
+    /// the time in ms, at which the bucket is full again
+    full_at: AtomicU64,
+}
+
+impl RateLimit {
+    pub const fn new() -> Self {
+        Self {
+            full_at: AtomicU64::new(0),
+        }
+    }
+
+    /// takes a token, returns false if the message is to be dropped.
+    /// Until the clock is initialized, it does not advance and every message is let through
+    pub fn check(&self) -> bool {
+        let now = Instant::now();
+        if now == Instant::BOOT {
+            return true;
+        }
+        self.check_at(now.since_boot().as_millis() as u64)
+    }
+
+    fn check_at(&self, now: u64) -> bool {
+        let allowed = self
+            .full_at
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |full_at| {
+                // each taken token moves full_at one refill further into the future
+                (full_at <= now + (BURST - 1) * REFILL_MS).then(|| full_at.max(now) + REFILL_MS)
+            })
+            .is_ok();
+        if !allowed {
+            SUPPRESSED.fetch_add(1, Ordering::Relaxed);
+        }
+        allowed
+    }
+}
+
+/// the number of log messages dropped since boot
+pub fn suppressed() -> u64 {
+    SUPPRESSED.load(Ordering::Relaxed)
+}
+
+/// logs the number of messages dropped during each interval, in which any were
+#[init_task(stage = "drivers", order = 25)]
+fn start_suppression_report() {
+    _ = threading::spawn(|| {
+        let mut reported = 0;
+        loop {
+            wait_manager::wait_self(&[QueuTypeCondition::with_cond(
+                QueueType::Timer,
+                WaitCondition::Time(Instant::now() + REPORT_INTERVAL),
+            )]);
+            let total = suppressed();
+            if total > reported {
+                serial_println!(
+                    "log: suppressed {} messages in the last {}s",
+                    total - reported,
+                    REPORT_INTERVAL.as_secs()
+                );
+                reported = total;
+            }
+        }
+    });
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    #[kernel_test]
+    fn token_bucket() {
+        let limit = RateLimit::new();
+        let start = 1000;
+        let before = suppressed();
+        assert!((0..BURST).all(|_| limit.check_at(start)));
+        assert!(!limit.check_at(start));
+        assert!(!limit.check_at(start + REFILL_MS / 2));
+        // one token per interval
+        assert!(limit.check_at(start + REFILL_MS));
+        assert!(!limit.check_at(start + REFILL_MS));
+        // an idle call site regains its full burst, but no more
+        let later = start + 100 * REFILL_MS;
+        assert!((0..BURST).all(|_| limit.check_at(later)));
+        assert!(!limit.check_at(later));
+        assert!(suppressed() - before >= 4);
+    }
+}
@@ -0,0 +1,110 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use tinyos_abi::{flags::NodeType, types::FStat};
+
+use super::{TTYSink, TTYSource, sink::FBBACKEND, source::STDIN_FILE_FACTORY_FILE};
+use crate::kernel::{
+    fd::{FileRepr, IOCapable},
+    fs::FSErrorKind,
+    io::{IOError, IOResult, Read, Write},
+    threading::{task::ProcessGroupID, wait::QueuTypeCondition},
+};
+
+// a single session is assumed, thus the console is the controlling terminal of every process
+
+pub const DEV_TTY_FILE: &str = "/dev/tty";
+
+const NO_FOREGROUND: u64 = u64::MAX;
+
+static FOREGROUND: AtomicU64 = AtomicU64::new(NO_FOREGROUND);
+
+/// the process group, which receives the terminal input. None, until a group is put into the foreground, in which case every process may read
+pub fn foreground() -> Option<ProcessGroupID> {
+    let pgrid = FOREGROUND.load(Ordering::Acquire);
+    (pgrid != NO_FOREGROUND).then_some(ProcessGroupID(pgrid))
+}
+
+pub fn set_foreground(pgrid: Option<ProcessGroupID>) {
+    FOREGROUND.store(
+        pgrid.map_or(NO_FOREGROUND, |pgrid| pgrid.0),
+        Ordering::Release,
+    );
+}
+
+pub fn is_foreground(pgrid: ProcessGroupID) -> bool {
+    foreground().is_none_or(|foreground| foreground == pgrid)
+}
+
+/// fails for readers outside of the foreground group. Without signals they cannot be stopped with SIGTTIN, thus they get an io error instead
+pub fn check_read(pgrid: ProcessGroupID) -> IOResult<()> {
+    if is_foreground(pgrid) {
+        Ok(())
+    } else {
+        Err(IOError::with_message(
+            FSErrorKind::Other,
+            "read from the terminal by a background process group",
+        ))
+    }
+}
+
+/// /dev/tty: the controlling terminal of the caller. Reads are its stdin, writes go to its screen
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DevTty;
+
+impl Read for DevTty {
+    fn read(&self, buf: &mut [u8], offset: usize) -> IOResult<usize> {
+        STDIN_FILE_FACTORY_FILE
+            .get()
+            .ok_or(IOError::simple(FSErrorKind::NotFound))?
+            .read_buf(buf, offset)
+    }
+}
+
+impl Write for DevTty {
+    fn write(&self, buf: &[u8], _offset: usize) -> IOResult<usize> {
+        let sink = FBBACKEND
+            .get()
+            .ok_or(IOError::simple(FSErrorKind::NotFound))?;
+        TTYSink::write(&**sink, buf);
+        Ok(buf.len())
+    }
+}
+
+impl FileRepr for DevTty {
+    fn fstat(&self) -> FStat {
+        let mut stat = FStat::default();
+        stat.node_type = NodeType::FILE;
+        stat
+    }
+
+    fn get_waiter(&self) -> Option<QueuTypeCondition> {
+        STDIN_FILE_FACTORY_FILE.get()?.get_waiter()
+    }
+}
+
+impl IOCapable for DevTty {}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+    use crate::kernel::threading::{task::TaskRepr, tls};
+
+    #[kernel_test]
+    fn background_reads_fail() {
+        let pgrid = tls::task_data().current_thread().unwrap().pgrid();
+        assert!(check_read(pgrid).is_ok());
+
+        set_foreground(Some(ProcessGroupID(pgrid.0 + 1)));
+        assert!(!is_foreground(pgrid));
+        let mut buf = [0; 4];
+        let err = DevTty.read(&mut buf, 0).unwrap_err();
+        assert_eq!(*err.kind(), FSErrorKind::Other);
+
+        set_foreground(Some(pgrid));
+        assert!(DevTty.read(&mut buf, 0).is_ok());
+        set_foreground(None);
+        assert_eq!(foreground(), None);
+    }
+}
@@ -1,23 +1,43 @@
-use alloc::sync::Arc;
+use alloc::{collections::vec_deque::VecDeque, string::String, sync::Arc};
+use core::{
+    ptr,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
 use conquer_once::spin::OnceCell;
-use crossbeam::queue::SegQueue;
 use tinyos_abi::flags::NodeType;
 
 use super::TTYSink;
 use crate::{
-    arch,
+    arch::{self, interrupt},
     create_device_file,
     impl_empty_read,
     impl_file_for_wr,
     impl_write_for_tty,
-    kernel::devices::tty::TTYSource,
+    kernel::{
+        devices::tty::TTYSource,
+        threading::{
+            self,
+            wait::{
+                QueuTypeCondition,
+                QueueType,
+                WaitEvent,
+                condition::WaitCondition,
+                post_event,
+                queues::GenericWaitQueue,
+            },
+        },
+    },
+    sync::{SpinWaiter, locks::GenericMutex},
     term::_print,
 };
 
 pub static SERIALBACKEND: OnceCell<Arc<SerialBackend>> = OnceCell::uninit();
 pub static FBBACKEND: OnceCell<Arc<FbBackend>> = OnceCell::uninit();
 
+/// the flusher waits on this queue, writers wake it once their buffer becomes non-empty
+pub static TTYOUTPUTQUEUE: OnceCell<GenericWaitQueue> = OnceCell::uninit();
+
 pub const SERIAL_FILE: &str = "/kernel/io/serial";
 pub const FBBACKEND_FILE: &str = "/kernel/io/fbbackend";
 
@@ -29,30 +49,136 @@ pub fn init_tty_sinks() {
     _ = create_device_file!(FBBACKEND.get().unwrap().clone(), FBBACKEND_FILE);
 }
 
-// the read_locks are only necessary if multiple instances of these Backends are alive at once, as ChunkedArrayQueue is mpsc. Currently this is not the case.
+/// bytes buffered per tty, before writers have to wait for the flusher
+pub const OUTPUT_BUFFER_SIZE: usize = 16 * 1024;
+/// bytes rendered by a single flush
+pub const FLUSH_BATCH: usize = 1024;
+
+/// output ring of a tty. Writers only append, the bytes are rendered in batches by the tty flusher task
+#[derive(Debug)]
+pub struct OutputBuffer {
+    // taken with interrupts disabled, thus it must never yield
+    buffer: GenericMutex<VecDeque<u8>, SpinWaiter>,
+    dropped: AtomicU64,
+}
+
+impl OutputBuffer {
+    pub fn new() -> Self {
+        Self {
+            // allocated once, such that appending never allocates
+            buffer: GenericMutex::new(VecDeque::with_capacity(OUTPUT_BUFFER_SIZE)),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// appends bytes and wakes the flusher, if the buffer was empty.
+    /// If the buffer is full, the writer yields to the flusher, or drops the rest if it cannot be preempted
+    pub fn append(&self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            let (pushed, was_empty) = interrupt::without_interrupts(|| {
+                let mut buffer = self.buffer.lock();
+                let was_empty = buffer.is_empty();
+                let pushed = OUTPUT_BUFFER_SIZE
+                    .saturating_sub(buffer.len())
+                    .min(bytes.len());
+                buffer.extend(&bytes[..pushed]);
+                (pushed, was_empty)
+            });
+            if was_empty && pushed > 0 {
+                wake_flusher();
+            }
+            bytes = &bytes[pushed..];
+            if bytes.is_empty() {
+                break;
+            }
+            if !threading::is_running() || !interrupt::are_enabled() {
+                self.dropped
+                    .fetch_add(bytes.len() as u64, Ordering::Relaxed);
+                break;
+            }
+            wake_flusher();
+            threading::yield_now();
+        }
+    }
+
+    /// moves up to buf.len() bytes out of the buffer. Returns the number of bytes moved
+    pub fn drain(&self, buf: &mut [u8]) -> usize {
+        interrupt::without_interrupts(|| {
+            let mut buffer = self.buffer.lock();
+            let len = buf.len().min(buffer.len());
+            buf.iter_mut()
+                .zip(buffer.drain(..len))
+                .for_each(|(buf_, byte)| *buf_ = byte);
+            len
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        interrupt::without_interrupts(|| self.buffer.lock().is_empty())
+    }
+
+    /// number of bytes, which were dropped as the buffer was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for OutputBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn wake_flusher() {
+    if !threading::is_running() {
+        return;
+    }
+    // a lost event only delays the flush until the flusher times out
+    _ = post_event(WaitEvent::new(QueueType::TTYOutput));
+}
+
+/// whether any tty has buffered output
+pub fn output_pending() -> bool {
+    SERIALBACKEND.get().is_some_and(|b| !b.buffer.is_empty())
+        || FBBACKEND.get().is_some_and(|b| !b.buffer.is_empty())
+}
+
+static OUTPUT_PENDING: fn(u64) -> bool = |_| output_pending();
+
+/// the condition the flusher waits for
+pub fn output_condition() -> QueuTypeCondition {
+    QueuTypeCondition::with_cond(
+        QueueType::TTYOutput,
+        WaitCondition::Generic(0, ptr::from_ref::<dyn Fn(u64) -> bool>(&OUTPUT_PENDING)),
+    )
+}
+
 #[derive(Debug)]
 pub struct SerialBackend {
-    buffer: SegQueue<u8>,
+    buffer: OutputBuffer,
 }
 
 impl SerialBackend {
     pub fn new() -> Arc<Self> {
         Arc::new(Self {
-            buffer: SegQueue::new(),
+            buffer: OutputBuffer::new(),
         })
     }
 }
 
 impl TTYSink for SerialBackend {
     fn write(&self, bytes: &[u8]) {
-        for byte in bytes {
-            self.buffer.push(*byte);
-        }
+        self.buffer.append(bytes);
     }
 
     fn flush(&self) {
-        while let Some(byte) = self.buffer.pop() {
-            arch::_raw_serial_print(&[byte]);
+        let mut batch = [0; FLUSH_BATCH];
+        loop {
+            let len = self.buffer.drain(&mut batch);
+            if len == 0 {
+                break;
+            }
+            arch::_raw_serial_print(&batch[..len]);
         }
     }
 }
@@ -63,27 +189,34 @@ impl_file_for_wr!(SerialBackend: NodeType::FILE);
 
 #[derive(Debug)]
 pub struct FbBackend {
-    buffer: SegQueue<u8>,
+    buffer: OutputBuffer,
 }
 
 impl FbBackend {
     pub fn new() -> Arc<Self> {
         Arc::new(Self {
-            buffer: SegQueue::new(),
+            buffer: OutputBuffer::new(),
         })
     }
 }
 
 impl TTYSink for FbBackend {
     fn write(&self, bytes: &[u8]) {
-        for byte in bytes {
-            self.buffer.push(*byte);
-        }
+        self.buffer.append(bytes);
     }
 
     fn flush(&self) {
-        while let Some(byte) = self.buffer.pop() {
-            _print(format_args!("{}", char::from_u32(byte as u32).unwrap()));
+        let mut batch = [0; FLUSH_BATCH];
+        let mut chars = String::with_capacity(FLUSH_BATCH);
+        loop {
+            let len = self.buffer.drain(&mut batch);
+            if len == 0 {
+                break;
+            }
+            chars.clear();
+            chars.extend(batch[..len].iter().map(|byte| char::from(*byte)));
+            // the whole batch is rendered under a single lock of the terminal
+            _print(format_args!("{}", chars));
         }
     }
 }
@@ -91,3 +224,33 @@ impl TTYSink for FbBackend {
 impl_write_for_tty!(FbBackend);
 impl_empty_read!(FbBackend);
 impl_file_for_wr!(FbBackend: NodeType::FILE);
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    #[kernel_test]
+    fn output_buffer_batches() {
+        let buffer = OutputBuffer::new();
+        buffer.append(b"hello ");
+        buffer.append(b"world");
+        assert!(!buffer.is_empty());
+        let mut batch = [0; 8];
+        assert_eq!(buffer.drain(&mut batch), 8);
+        assert_eq!(&batch, b"hello wo");
+        assert_eq!(buffer.drain(&mut batch), 3);
+        assert_eq!(&batch[..3], b"rld");
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.drain(&mut batch), 0);
+
+        // appending never exceeds the capacity reserved up front
+        let big = [b'x'; OUTPUT_BUFFER_SIZE + 16];
+        buffer.append(&big[..OUTPUT_BUFFER_SIZE]);
+        let mut batch = [0; 16];
+        assert_eq!(buffer.drain(&mut batch), 16);
+        buffer.append(&big[..16]);
+        assert_eq!(buffer.dropped(), 0);
+    }
+}
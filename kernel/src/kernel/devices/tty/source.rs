@@ -1,31 +1,38 @@
-use alloc::{boxed::Box, collections::btree_map::BTreeMap, sync::Arc};
-use core::sync::atomic::{AtomicUsize, Ordering};
+use alloc::{
+    boxed::Box,
+    collections::{btree_map::BTreeMap, vec_deque::VecDeque},
+    sync::Arc,
+};
+use core::{
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 use conquer_once::spin::OnceCell;
 use crossbeam::queue::ArrayQueue;
-use tinyos_abi::flags::NodeType;
+use tinyos_abi::{flags::NodeType, types::FStat};
 
-use super::TTYSource;
+use super::{TTYSource, canonical, clipboard, session};
 use crate::{
     drivers::{
-        keyboard::{KEYBOARD_BUFFER, STDIN_QUEUE_SIZE, parse_scancode},
+        keyboard::{KEYBOARD_BUFFER, STDIN_QUEUE_SIZE, held_modifiers, parse_scancode},
         tty::map_key,
     },
     impl_empty_write,
-    impl_file_for_wr,
     impl_read_for_tty,
     kernel::{
         devices::tty::TTYSink,
-        fd::{FileRepr, FileReprFactory},
+        fd::{FileRepr, FileReprFactory, IOCapable},
         fs::FSError,
         threading::{
             task::{ProcessID, TaskRepr},
             tls,
+            wait::{QueuTypeCondition, QueueType, WaitEvent, condition::WaitCondition, post_event},
         },
     },
     register_device_file,
     serial_println,
-    sync::locks::RwLock,
+    sync::locks::{Mutex, RwLock},
 };
 
 pub static KEYBOARDBACKEND: OnceCell<Arc<KeyboardBackend>> = OnceCell::uninit();
@@ -46,6 +53,56 @@ pub fn init_source_tty() {
     );
 }
 
+/// input injected by the terminal itself, like pasted text. The foreground reads it before any keyboard input
+static INJECTED: Mutex<VecDeque<u8>> = Mutex::new(VecDeque::new());
+
+/// queues bytes as stdin input and wakes the readers
+pub fn inject_input(bytes: &[u8]) {
+    INJECTED.lock().extend(bytes);
+    _ = post_event(WaitEvent::new(QueueType::KeyBoard));
+}
+
+fn take_injected(buf: &mut [u8]) -> usize {
+    let mut injected = INJECTED.lock();
+    let len = buf.len().min(injected.len());
+    buf.iter_mut()
+        .zip(injected.drain(..len))
+        .for_each(|(buf_, byte)| *buf_ = byte);
+    len
+}
+
+/// blocks a reader on the keyboard queue, until callback(val) holds.
+/// The keyboard interrupt signals the queue on every scancode
+fn keyboard_waiter(val: u64, callback: &'static fn(u64) -> bool) -> QueuTypeCondition {
+    QueuTypeCondition::with_cond(
+        QueueType::KeyBoard,
+        WaitCondition::Generic(val, ptr::from_ref::<dyn Fn(u64) -> bool>(callback)),
+    )
+}
+
+// reevaluated on every wakeup, as the stdin of the process is read in between
+static STDIN_PENDING: fn(u64) -> bool = |pid| {
+    // background readers must not block, their read fails instead
+    let Some(pgrid) = tls::task_data().pgrid(&ProcessID(pid)) else {
+        return true;
+    };
+    !session::is_foreground(pgrid)
+        || !INJECTED.lock().is_empty()
+        || canonical::has_line()
+        || STDIN_FILE_FACTORY_FILE
+            .get()
+            .and_then(|factory| factory.delegate(&ProcessID(pid), OwnedStdin::has_input))
+            // not read from yet, thus the next read sets up the cursor
+            .unwrap_or(true)
+};
+
+static SCANCODE_PENDING: fn(u64) -> bool = |cursor| !KEYBOARD_BUFFER.is_up_to_date(cursor as usize);
+
+/// waits until a scancode is put at cursor
+pub fn scancode_waiter(cursor: usize) -> QueuTypeCondition {
+    keyboard_waiter(cursor as u64, &SCANCODE_PENDING)
+}
+
 // TODO cleanup open_files once process exits
 // this is most easily done once process hooks are implemented
 
@@ -89,16 +146,42 @@ impl FileReprFactory for StdInFileFactory {
 
 impl TTYSource for StdInFileFactory {
     fn read(&self) -> Option<u8> {
-        let pid = tls::task_data().current_thread()?.pid();
+        let current = tls::task_data().current_thread()?;
+        session::check_read(current.pgrid()).ok()?;
+        if let Some(byte) = INJECTED.lock().pop_front() {
+            return Some(byte);
+        }
+        let pid = current.pid();
         self.ensure_init(pid);
         self.delegate(&pid, |stdin| stdin.read()).flatten()
     }
 
     fn read_buf(&self, buf: &mut [u8], offset: usize) -> crate::kernel::io::IOResult<usize> {
-        let pid = tls::task_data()
+        let current = tls::task_data()
             .current_thread()
-            .ok_or(FSError::simple(crate::kernel::fs::FSErrorKind::NotFound))?
-            .pid();
+            .ok_or(FSError::simple(crate::kernel::fs::FSErrorKind::NotFound))?;
+        // only the foreground process group receives input
+        session::check_read(current.pgrid())?;
+        let pid = current.pid();
+        if canonical::is_canonical() {
+            return canonical::read_line(buf, |raw| self.read_raw(pid, raw, offset));
+        }
+        self.read_raw(pid, buf, offset)
+    }
+}
+
+impl StdInFileFactory {
+    /// the input of pid, as typed
+    fn read_raw(
+        &self,
+        pid: ProcessID,
+        buf: &mut [u8],
+        offset: usize,
+    ) -> crate::kernel::io::IOResult<usize> {
+        let injected = take_injected(buf);
+        if injected > 0 {
+            return Ok(injected);
+        }
         self.ensure_init(pid);
         self.delegate(&pid, |stdin| stdin.read_buf(buf, offset))
             .ok_or(FSError::simple(crate::kernel::fs::FSErrorKind::NotFound))
@@ -108,7 +191,21 @@ impl TTYSource for StdInFileFactory {
 
 impl_empty_write!(StdInFileFactory);
 impl_read_for_tty!(StdInFileFactory);
-impl_file_for_wr!(StdInFileFactory: NodeType::FILE);
+
+impl IOCapable for StdInFileFactory {}
+
+impl FileRepr for StdInFileFactory {
+    fn fstat(&self) -> FStat {
+        let mut stat = FStat::default();
+        stat.node_type = NodeType::FILE;
+        stat
+    }
+
+    fn get_waiter(&self) -> Option<QueuTypeCondition> {
+        let pid = tls::task_data().current_thread()?.pid();
+        Some(keyboard_waiter(pid.0, &STDIN_PENDING))
+    }
+}
 
 #[derive(Debug)]
 pub struct OwnedStdin {
@@ -129,6 +226,11 @@ impl OwnedStdin {
             cursor: KEYBOARD_BUFFER.get_current().into(),
         }
     }
+
+    /// whether scancodes arrived, which were not read yet
+    pub fn has_input(&self) -> bool {
+        !KEYBOARD_BUFFER.is_up_to_date(self.cursor.load(Ordering::Relaxed))
+    }
 }
 
 impl TTYSource for OwnedStdin {
@@ -168,7 +270,10 @@ impl TTYSource for OwnedStdin {
 
         let mut n_mapped = 0;
         for &byte in &intermediate_buf[..n_read] {
-            if let Ok(res) = parse_scancode(byte) {
+            // the shortcuts of the terminal are handled by the tty input task
+            if let Ok(res) = parse_scancode(byte)
+                && !clipboard::is_terminal_key(&res, held_modifiers())
+            {
                 let mapped_bytes = map_key(res, buf);
                 if mapped_bytes < 0 {
                     break;
@@ -183,7 +288,23 @@ impl TTYSource for OwnedStdin {
 
 impl_empty_write!(OwnedStdin);
 impl_read_for_tty!(OwnedStdin);
-impl_file_for_wr!(OwnedStdin: NodeType::FILE);
+
+impl IOCapable for OwnedStdin {}
+
+impl FileRepr for OwnedStdin {
+    fn fstat(&self) -> FStat {
+        let mut stat = FStat::default();
+        stat.node_type = NodeType::FILE;
+        stat
+    }
+
+    fn get_waiter(&self) -> Option<QueuTypeCondition> {
+        Some(keyboard_waiter(
+            self.cursor.load(Ordering::Relaxed) as u64,
+            &SCANCODE_PENDING,
+        ))
+    }
+}
 
 impl Default for OwnedStdin {
     fn default() -> Self {
@@ -219,7 +340,10 @@ impl TTYSource for KeyboardBackend {
 
         let mut n_mapped = 0;
         for &byte in &intermediate_buf[..n_read] {
-            if let Ok(res) = parse_scancode(byte) {
+            // the shortcuts of the terminal are handled by the tty input task
+            if let Ok(res) = parse_scancode(byte)
+                && !clipboard::is_terminal_key(&res, held_modifiers())
+            {
                 let mapped_bytes = map_key(res, buf);
                 if mapped_bytes < 0 {
                     break;
@@ -234,4 +358,49 @@ impl TTYSource for KeyboardBackend {
 
 impl_read_for_tty!(KeyboardBackend);
 impl_empty_write!(KeyboardBackend);
-impl_file_for_wr!(KeyboardBackend: NodeType::FILE);
+
+impl IOCapable for KeyboardBackend {}
+
+impl FileRepr for KeyboardBackend {
+    fn fstat(&self) -> FStat {
+        let mut stat = FStat::default();
+        stat.node_type = NodeType::FILE;
+        stat
+    }
+
+    fn get_waiter(&self) -> Option<QueuTypeCondition> {
+        Some(keyboard_waiter(
+            KEYBOARD_BUFFER.get_current() as u64 + 1,
+            &SCANCODE_PENDING,
+        ))
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+    use crate::drivers::keyboard::put_scancode;
+
+    #[kernel_test]
+    fn stdin_waits_for_scancodes() {
+        let stdin = OwnedStdin::new();
+        stdin
+            .cursor
+            .store(KEYBOARD_BUFFER.get_current() + 1, Ordering::Relaxed);
+        let waiter = stdin.get_waiter().unwrap();
+        assert_eq!(waiter.q_type, QueueType::KeyBoard);
+        assert!(!stdin.has_input());
+        assert!(!waiter.cond.is_given());
+
+        // 'a' pressed
+        put_scancode(0x1e);
+        assert!(stdin.has_input());
+        assert!(waiter.cond.is_given());
+        let mut buf = [0; 4];
+        assert_eq!(stdin.read_buf(&mut buf, 0).unwrap(), 1);
+        assert_eq!(buf[0], b'a');
+        assert!(!stdin.has_input());
+    }
+}
@@ -0,0 +1,165 @@
+use alloc::{string::String, vec::Vec};
+
+use conquer_once::spin::OnceCell;
+use elf::{ElfBytes, endian::AnyEndian};
+
+use crate::bootinfo;
+
+/// a function symbol of the kernel image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KernelSymbol {
+    pub addr: u64,
+    pub size: u64,
+    /// the mangled name, as found in the symbol table
+    pub name: &'static str,
+}
+
+static SYMBOLS: OnceCell<Vec<KernelSymbol>> = OnceCell::uninit();
+
+fn load() -> Vec<KernelSymbol> {
+    let Some(data) = bootinfo::kernel_file() else {
+        return Vec::new();
+    };
+    let Ok(file) = ElfBytes::<AnyEndian>::minimal_parse(data) else {
+        return Vec::new();
+    };
+    let Ok(Some((symbols, strings))) = file.symbol_table() else {
+        return Vec::new();
+    };
+    let mut symbols: Vec<KernelSymbol> = symbols
+        .iter()
+        .filter(|sym| sym.st_symtype() == elf::abi::STT_FUNC && sym.st_value != 0)
+        .filter_map(|sym| {
+            Some(KernelSymbol {
+                addr: sym.st_value,
+                size: sym.st_size,
+                name: strings.get(sym.st_name as usize).ok()?,
+            })
+        })
+        .collect();
+    symbols.sort_unstable_by_key(|sym| sym.addr);
+    symbols
+}
+
+/// the function symbols of the kernel, sorted by address. Empty, if the bootloader did not pass the kernel file
+pub fn symbols() -> &'static [KernelSymbol] {
+    SYMBOLS.get_or_init(load)
+}
+
+/// the symbol containing addr and the offset of addr into it
+pub fn resolve(addr: u64) -> Option<(&'static KernelSymbol, u64)> {
+    let symbols = symbols();
+    let idx = symbols
+        .partition_point(|sym| sym.addr <= addr)
+        .checked_sub(1)?;
+    let sym = &symbols[idx];
+    let offset = addr - sym.addr;
+    // symbols without a size are assumed to extend up to the next one
+    (sym.size == 0 || offset < sym.size).then_some((sym, offset))
+}
+
+/// demangles a legacy rust symbol, dropping the hash. Other names are returned as is
+pub fn demangle(name: &str) -> String {
+    let Some(mut rest) = name.strip_prefix("_ZN") else {
+        return name.into();
+    };
+    let mut segments = Vec::new();
+    while !rest.starts_with('E') {
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let Some(len) = rest[..digits].parse::<usize>().ok() else {
+            return name.into();
+        };
+        let Some(segment) = rest.get(digits..digits + len) else {
+            return name.into();
+        };
+        segments.push(segment);
+        rest = &rest[digits + len..];
+    }
+    if let Some(hash) = segments.last()
+        && hash.len() == 17
+        && hash.starts_with('h')
+        && hash[1..].bytes().all(|b| b.is_ascii_hexdigit())
+    {
+        segments.pop();
+    }
+
+    let mut demangled = String::with_capacity(name.len());
+    for (i, segment) in segments.into_iter().enumerate() {
+        if i > 0 {
+            demangled.push_str("::");
+        }
+        // identifiers may not start with an escape, thus those are prefixed with an underscore
+        let mut segment = segment
+            .strip_prefix('_')
+            .filter(|s| s.starts_with('$'))
+            .unwrap_or(segment);
+        while !segment.is_empty() {
+            if let Some(s) = segment.strip_prefix("..") {
+                demangled.push_str("::");
+                segment = s;
+            } else if segment.starts_with('$')
+                && let Some(end) = segment[1..].find('$')
+                && let Some(c) = unescape(&segment[1..end + 1])
+            {
+                demangled.push(c);
+                segment = &segment[end + 2..];
+            } else {
+                let c = segment.chars().next().unwrap();
+                demangled.push(c);
+                segment = &segment[c.len_utf8()..];
+            }
+        }
+    }
+    demangled
+}
+
+fn unescape(escape: &str) -> Option<char> {
+    match escape {
+        "SP" => Some('@'),
+        "BP" => Some('*'),
+        "RF" => Some('&'),
+        "LT" => Some('<'),
+        "GT" => Some('>'),
+        "LP" => Some('('),
+        "RP" => Some(')'),
+        "C" => Some(','),
+        _ => {
+            let code = u32::from_str_radix(escape.strip_prefix('u')?, 16).ok()?;
+            char::from_u32(code)
+        }
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    #[kernel_test]
+    fn demangled_names() {
+        assert_eq!(
+            demangle("_ZN4core3fmt5write17h0123456789abcdefE"),
+            "core::fmt::write"
+        );
+        assert_eq!(
+            demangle(
+                "_ZN60_$LT$alloc..vec..Vec$LT$T$GT$$u20$as$u20$core..ops..Drop$GT$4drop17h0123456789abcdefE"
+            ),
+            "<alloc::vec::Vec<T> as core::ops::Drop>::drop"
+        );
+        assert_eq!(demangle("memcpy"), "memcpy");
+        assert_eq!(demangle("_ZN3foo"), "_ZN3foo");
+    }
+
+    #[kernel_test]
+    fn resolve_own_symbol() {
+        let Some(sym) = symbols().first() else {
+            return;
+        };
+        assert_eq!(resolve(sym.addr).map(|(_, offset)| offset), Some(0));
+        let (sym, offset) = resolve(resolve as usize as u64 + 1).unwrap();
+        assert_eq!(offset, 1);
+        assert!(demangle(sym.name).ends_with("ksyms::resolve"));
+    }
+}
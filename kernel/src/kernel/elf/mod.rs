@@ -1,4 +1,30 @@
-use elf::endian::AnyEndian;
+use alloc::{vec, vec::Vec};
+
+use elf::{
+    abi::{
+        DT_NEEDED,
+        DT_NULL,
+        DT_REL,
+        DT_RELA,
+        DT_RELAENT,
+        DT_RELASZ,
+        EI_NIDENT,
+        ET_DYN,
+        ET_EXEC,
+        PT_DYNAMIC,
+        PT_INTERP,
+        PT_LOAD,
+        R_X86_64_NONE,
+        R_X86_64_RELATIVE,
+    },
+    dynamic::DynamicTable,
+    endian::AnyEndian,
+    file::{self, ELF64_EHDR_TAILSIZE, FileHeader},
+    parse::ParseAt,
+    relocation::{Rela, RelaIterator},
+    segment::{ProgramHeader, SegmentTable},
+};
+use tinyos_abi::types::SysErrCode;
 use x86_64::structures::paging::Translate;
 
 use crate::{
@@ -19,69 +45,335 @@ use crate::{
         },
     },
     kernel::{
-        mem::paging::{
-            APageTable,
-            PAGETABLE,
-            TaskPageTable,
-            get_frame_alloc,
-            get_kernel_pagetbl_root,
+        fd::File,
+        io::Read,
+        mem::{
+            addr::UserVirtAddr,
+            paging::{
+                APageTable,
+                PAGETABLE,
+                TaskPageTable,
+                get_frame_alloc,
+                get_kernel_pagetbl_root,
+            },
+            vma::{Vma, VmaBacking},
         },
         threading::{task::TaskRepr, tls},
     },
     serial_println,
 };
 
-pub fn apply<M1: Mapper<Size4KiB>>(
-    bytes: &elf::ElfBytes<AnyEndian>,
-    data: &[u8],
-    table: &mut M1,
-) -> Result<(), ElfError> {
-    serial_println!("writing elf data into memory...");
-    let headers = bytes.segments().ok_or(ElfError::Unknown)?;
-    for header in headers.iter() {
-        let addr = VirtAddr::new(header.p_vaddr);
-        let mapper = PageMapper::init(&addr, header.p_memsz);
-        let active_table_root: PhysFrame<Size4KiB> = if let Some(current) =
-            tls::task_data().current_thread()
-            && let Some(task_tbl) = current.pagedir().try_get_owned()
+pub mod ksyms;
+pub mod vdso;
+
+/// bytes of a segment read and copied at once, such that images are never held in memory as a whole
+const CHUNK_SIZE: u64 = 16 * Size4KiB::SIZE;
+/// upper bound on the size of the program header table
+const MAX_PHDRS_SIZE: usize = 64 * 1024;
+/// upper bound on the size of the dynamic section
+const MAX_DYNAMIC_SIZE: usize = 64 * 1024;
+/// upper bound on the size of the relocation table, which is read as a whole
+const MAX_RELA_SIZE: usize = 16 * 1024 * 1024;
+/// where position independent executables are loaded
+pub const DYN_BASE: u64 = 0x5555_5555_4000;
+
+/// where an image is loaded from. Files are read piecewise, thus they may live on any filesystem of the vfs
+pub enum ElfSource<'data> {
+    Bytes(&'data [u8]),
+    File(File),
+}
+
+impl ElfSource<'_> {
+    /// fills buf with the bytes at offset. Fails, if the image ends before
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> Result<(), ElfError> {
+        let offset = usize::try_from(offset).map_err(|_| ElfError::Truncated)?;
+        match self {
+            Self::Bytes(data) => {
+                let bytes = offset
+                    .checked_add(buf.len())
+                    .and_then(|end| data.get(offset..end))
+                    .ok_or(ElfError::Truncated)?;
+                buf.copy_from_slice(bytes);
+            }
+            Self::File(file) => {
+                let mut done = 0;
+                while done < buf.len() {
+                    let n = file
+                        .read(&mut buf[done..], offset + done)
+                        .map_err(|_| ElfError::Io)?;
+                    if n == 0 {
+                        return Err(ElfError::Truncated);
+                    }
+                    done += n;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// a word of a loaded image, which is patched after its segment is copied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Relocation {
+    addr: u64,
+    value: u64,
+}
+
+/// the entry point and the loadable segments of an image, parsed without reading the rest of it.
+/// Position independent executables (ET_DYN) are moved to DYN_BASE, all addresses of the image already include it
+#[derive(Debug, Clone)]
+pub struct ElfImage {
+    pub entry: UserVirtAddr,
+    /// the offset of the image from the addresses it was linked at, 0 for ET_EXEC
+    pub base: u64,
+    segments: Vec<ProgramHeader>,
+    /// sorted by addr
+    relocations: Vec<Relocation>,
+}
+
+impl ElfImage {
+    /// reads the file header and the program headers. Segments, which do not lie entirely in user space, are rejected
+    pub fn parse(source: &ElfSource<'_>) -> Result<Self, ElfError> {
+        let mut ehdr = [0; EI_NIDENT + ELF64_EHDR_TAILSIZE];
+        source.read_exact_at(&mut ehdr, 0)?;
+        let ident =
+            file::parse_ident::<AnyEndian>(&ehdr[..EI_NIDENT]).map_err(|_| ElfError::Parse)?;
+        let ehdr =
+            FileHeader::parse_tail(ident, &ehdr[EI_NIDENT..]).map_err(|_| ElfError::Parse)?;
+
+        let phdrs_size = ehdr.e_phnum as usize * ehdr.e_phentsize as usize;
+        if phdrs_size > MAX_PHDRS_SIZE
+            || ehdr.e_phentsize as usize != ProgramHeader::size_for(ehdr.class)
         {
-            task_tbl.lock().root
-        } else {
-            get_kernel_pagetbl_root().clone()
+            return Err(ElfError::Malformed);
+        }
+        let mut phdrs = vec![0; phdrs_size];
+        source.read_exact_at(&mut phdrs, ehdr.e_phoff)?;
+        let table = SegmentTable::new(ehdr.endianness, ehdr.class, &phdrs);
+        let base = match ehdr.e_type {
+            ET_EXEC => 0,
+            // an interpreter would be needed to load the shared libraries of the image
+            ET_DYN if table.iter().any(|header| header.p_type == PT_INTERP) => {
+                return Err(ElfError::Unsupported);
+            }
+            ET_DYN => DYN_BASE,
+            _ => return Err(ElfError::Malformed),
         };
+        let segments: Vec<ProgramHeader> = table
+            .iter()
+            .filter(|header| header.p_type == PT_LOAD && header.p_memsz > 0)
+            .map(|mut header| {
+                header.p_vaddr = header
+                    .p_vaddr
+                    .checked_add(base)
+                    .ok_or(ElfError::Malformed)?;
+                let in_user_space =
+                    UserVirtAddr::try_new_range(header.p_vaddr, header.p_memsz).is_some();
+                if header.p_filesz > header.p_memsz || !in_user_space {
+                    return Err(ElfError::Malformed);
+                }
+                Ok(header)
+            })
+            .collect::<Result<_, _>>()?;
+        let relocations = match table.iter().find(|header| header.p_type == PT_DYNAMIC) {
+            Some(dynamic) if base != 0 => {
+                read_relocations(source, &ehdr, &dynamic, &segments, base)?
+            }
+            _ => Vec::new(),
+        };
+        let entry = ehdr.e_entry.checked_add(base).ok_or(ElfError::Malformed)?;
+        Ok(Self {
+            entry: UserVirtAddr::try_new(entry).ok_or(ElfError::Malformed)?,
+            base,
+            segments,
+            relocations,
+        })
+    }
 
-        let global_table = &mut *PAGETABLE.lock();
-        // lock frame alloc to ensure we do not deadlock during interrupt disabled context
-        let _alloc = get_frame_alloc().lock();
+    /// maps the segments into table and copies their contents from source, one chunk at a time
+    pub fn load<M1: Mapper<Size4KiB>>(
+        &self,
+        source: &ElfSource<'_>,
+        table: &mut M1,
+    ) -> Result<(), ElfError> {
+        serial_println!("writing elf data into memory...");
+        let mut buf = vec![0; CHUNK_SIZE as usize];
+        for header in &self.segments {
+            let flags = get_pagetableflags(header.p_flags);
+            let start = UserVirtAddr::try_new_range(header.p_vaddr, header.p_memsz)
+                .ok_or(ElfError::Malformed)?;
+            let mut done = 0;
+            while done < header.p_memsz {
+                let len = (header.p_memsz - done).min(CHUNK_SIZE);
+                let file_len = header.p_filesz.saturating_sub(done).min(len) as usize;
+                // read before switching tables, as the file may block
+                source.read_exact_at(&mut buf[..file_len], header.p_offset + done)?;
+                buf[file_len..len as usize].fill(0);
+                let data_len = self
+                    .relocate(header.p_vaddr + done, &mut buf[..len as usize])
+                    .max(file_len);
+                write_segment(start + done, len, &buf[..data_len], flags, table);
+                done += len;
+            }
+        }
+        Ok(())
+    }
 
-        // SAFETY: This is safe, if we can ensure that interrupts will be restored upon ret
-        // This is the case, even if we panic
-        unsafe {
-            interrupt::disable();
-            Cr3::write(get_kernel_pagetbl_root().clone(), Cr3Flags::empty());
+    /// applies the relocations, which touch addr..addr + buf.len(), to buf, which holds the contents of the image there.
+    /// Returns the end of the last byte patched
+    fn relocate(&self, addr: u64, buf: &mut [u8]) -> usize {
+        let end = addr + buf.len() as u64;
+        // a relocation may straddle the start of buf
+        let first = self
+            .relocations
+            .partition_point(|reloc| reloc.addr + size_of::<u64>() as u64 <= addr);
+        let mut patched = 0;
+        for reloc in self.relocations[first..]
+            .iter()
+            .take_while(|reloc| reloc.addr < end)
+        {
+            for (at, byte) in (reloc.addr..).zip(reloc.value.to_le_bytes()) {
+                if (addr..end).contains(&at) {
+                    let idx = (at - addr) as usize;
+                    buf[idx] = byte;
+                    patched = patched.max(idx + 1);
+                }
+            }
         }
-        drop(_alloc);
+        patched
+    }
 
-        mapper.map(table, get_pagetableflags(header.p_flags), global_table);
-        copy_to_mem(
-            &addr,
-            &data[header.p_offset as usize..header.p_offset as usize + header.p_filesz as usize],
-        );
+    /// the vmas of all segments mapped by load
+    pub fn vmas(&self) -> Vec<Vma> {
+        self.segments
+            .iter()
+            .map(|header| {
+                Vma::new(
+                    VirtAddr::new(header.p_vaddr),
+                    header.p_memsz as usize,
+                    get_pagetableflags(header.p_flags),
+                    VmaBacking::Elf,
+                )
+            })
+            .collect()
+    }
+}
 
-        if header.p_memsz > header.p_filesz {
-            zero_mem(
-                &(addr + header.p_filesz),
-                (header.p_memsz - header.p_filesz) as usize,
-            );
+/// reads the relocations of a position independent image from its dynamic section. segments are the loadable segments
+/// of the image, already moved by base. Only relative relocations are supported, others need a dynamic linker
+fn read_relocations(
+    source: &ElfSource<'_>,
+    ehdr: &FileHeader<AnyEndian>,
+    dynamic: &ProgramHeader,
+    segments: &[ProgramHeader],
+    base: u64,
+) -> Result<Vec<Relocation>, ElfError> {
+    if dynamic.p_filesz as usize > MAX_DYNAMIC_SIZE {
+        return Err(ElfError::Malformed);
+    }
+    let mut buf = vec![0; dynamic.p_filesz as usize];
+    source.read_exact_at(&mut buf, dynamic.p_offset)?;
+    let (mut rela, mut rela_size) = (None, 0);
+    for entry in DynamicTable::new(ehdr.endianness, ehdr.class, &buf).iter() {
+        match entry.d_tag {
+            DT_NULL => break,
+            DT_RELA => rela = Some(entry.d_ptr()),
+            DT_RELASZ => rela_size = entry.d_val(),
+            DT_RELAENT if entry.d_val() as usize != Rela::size_for(ehdr.class) => {
+                return Err(ElfError::Malformed);
+            }
+            DT_REL | DT_NEEDED => return Err(ElfError::Unsupported),
+            _ => {}
         }
-        mapper.unmap(global_table);
+    }
+    let Some(rela) = rela else {
+        return Ok(Vec::new());
+    };
+    if rela_size as usize > MAX_RELA_SIZE {
+        return Err(ElfError::Malformed);
+    }
+    // the table is addressed like the loaded image, thus it is found through the segment holding it
+    let rela = rela.checked_add(base).ok_or(ElfError::Malformed)?;
+    let offset = segments
+        .iter()
+        .find(|header| {
+            header.p_vaddr <= rela
+                && rela.saturating_add(rela_size) <= header.p_vaddr + header.p_filesz
+        })
+        .map(|header| header.p_offset + (rela - header.p_vaddr))
+        .ok_or(ElfError::Malformed)?;
+    let mut buf = vec![0; rela_size as usize];
+    source.read_exact_at(&mut buf, offset)?;
 
-        unsafe {
-            Cr3::write(active_table_root, Cr3Flags::empty());
-            interrupt::enable();
-        }
+    let word = size_of::<u64>() as u64;
+    let mut relocations = RelaIterator::new(ehdr.endianness, ehdr.class, &buf)
+        .filter(|entry| entry.r_type != R_X86_64_NONE)
+        .map(|entry| {
+            if entry.r_type != R_X86_64_RELATIVE {
+                return Err(ElfError::Unsupported);
+            }
+            let addr = entry
+                .r_offset
+                .checked_add(base)
+                .ok_or(ElfError::Malformed)?;
+            if !segments.iter().any(|header| {
+                header.p_vaddr <= addr
+                    && addr.saturating_add(word) <= header.p_vaddr + header.p_memsz
+            }) {
+                return Err(ElfError::Malformed);
+            }
+            Ok(Relocation {
+                addr,
+                value: base.wrapping_add_signed(entry.r_addend),
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    relocations.sort_unstable_by_key(|reloc| reloc.addr);
+    Ok(relocations)
+}
+
+/// maps len bytes at addr into table, copies data to their start and zeroes the rest
+fn write_segment<M1: Mapper<Size4KiB>>(
+    addr: UserVirtAddr,
+    len: u64,
+    data: &[u8],
+    flags: PageTableFlags,
+    table: &mut M1,
+) {
+    let addr = addr.as_virt();
+    let mapper = PageMapper::init(&addr, len);
+    let active_table_root: PhysFrame<Size4KiB> = if let Some(current) =
+        tls::task_data().current_thread()
+        && let Some(task_tbl) = current.pagedir().try_get_owned()
+    {
+        task_tbl.lock().root
+    } else {
+        get_kernel_pagetbl_root().clone()
+    };
+
+    let global_table = &mut *PAGETABLE.lock();
+    // lock frame alloc to ensure we do not deadlock during interrupt disabled context
+    let _alloc = get_frame_alloc().lock();
+
+    // SAFETY: This is safe, if we can ensure that interrupts will be restored upon ret
+    // This is the case, even if we panic
+    unsafe {
+        interrupt::disable();
+        Cr3::write(get_kernel_pagetbl_root().clone(), Cr3Flags::empty());
+    }
+    drop(_alloc);
+
+    mapper.map(table, flags, global_table);
+    copy_to_mem(&addr, data);
+    if len > data.len() as u64 {
+        zero_mem(&(addr + data.len() as u64), len as usize - data.len());
+    }
+    mapper.unmap(global_table);
+
+    unsafe {
+        Cr3::write(active_table_root, Cr3Flags::empty());
+        interrupt::enable();
     }
-    Ok(())
 }
 
 fn get_pagetableflags(elf_flags: u32) -> PageTableFlags {
@@ -119,14 +411,20 @@ impl PageMapper {
     ) {
         let mut alloc = get_frame_alloc().lock();
         for page in Page::range_inclusive(self.start, self.end) {
-            if new.translate_page(page).is_ok() {
-                continue;
-            }
-            let frame = alloc.allocate_frame().unwrap();
+            // pages shared with a previous chunk or segment are already mapped in new
+            let frame = match new.translate_page(page) {
+                Ok(frame) => frame,
+                Err(_) => {
+                    let frame = alloc.allocate_frame().unwrap();
+                    unsafe {
+                        _ = new
+                            .map_to(page, frame, flags, &mut *alloc)
+                            .map(|f| f.flush());
+                    }
+                    frame
+                }
+            };
             unsafe {
-                _ = new
-                    .map_to(page, frame, flags, &mut *alloc)
-                    .map(|f| f.flush());
                 _ = old
                     .map_to(page, frame, flags | PageTableFlags::WRITABLE, &mut *alloc)
                     .map(|f| f.flush());
@@ -156,4 +454,182 @@ fn zero_mem(start: &VirtAddr, len: usize) {
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum ElfError {
     Unknown,
+    /// the headers could not be parsed
+    Parse,
+    /// the headers are well formed, but describe an image, which cannot be loaded
+    Malformed,
+    /// the image ends before a header or segment does
+    Truncated,
+    /// the image needs a dynamic linker, like for shared libraries or symbol relocations
+    Unsupported,
+    Io,
+}
+
+impl From<ElfError> for SysErrCode {
+    fn from(err: ElfError) -> Self {
+        match err {
+            ElfError::Unknown | ElfError::Parse | ElfError::Malformed | ElfError::Truncated => {
+                Self::BadMsg
+            }
+            ElfError::Unsupported => Self::NotSupported,
+            ElfError::Io => Self::IO,
+        }
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+    use crate::kernel::mem::{addr::USER_END, paging::get_hhdm_addr};
+
+    /// an x86_64 executable with a single loadable segment of 0x20 bytes, 0x10 of which are in the file
+    fn image(vaddr: u64) -> Vec<u8> {
+        let mut data = vec![0; 64 + 56 + 0x10];
+        data[..8].copy_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+        data[16..18].copy_from_slice(&2u16.to_le_bytes());
+        data[18..20].copy_from_slice(&0x3eu16.to_le_bytes());
+        data[20..24].copy_from_slice(&1u32.to_le_bytes());
+        data[24..32].copy_from_slice(&(vaddr + 4).to_le_bytes());
+        data[32..40].copy_from_slice(&64u64.to_le_bytes());
+        data[52..54].copy_from_slice(&64u16.to_le_bytes());
+        data[54..56].copy_from_slice(&56u16.to_le_bytes());
+        data[56..58].copy_from_slice(&1u16.to_le_bytes());
+
+        let phdr = &mut data[64..120];
+        phdr[..4].copy_from_slice(&PT_LOAD.to_le_bytes());
+        phdr[4..8].copy_from_slice(&(elf::abi::PF_R | elf::abi::PF_X).to_le_bytes());
+        phdr[8..16].copy_from_slice(&120u64.to_le_bytes());
+        phdr[16..24].copy_from_slice(&vaddr.to_le_bytes());
+        phdr[32..40].copy_from_slice(&0x10u64.to_le_bytes());
+        phdr[40..48].copy_from_slice(&0x20u64.to_le_bytes());
+        data
+    }
+
+    /// a position independent executable linked at 0 with a segment of a page, holding the headers, a dynamic section and
+    /// two relocations of type r_type at 0x120 and 0x128
+    fn pie_image(r_type: u32) -> Vec<u8> {
+        let mut data = vec![0; 0x130];
+        data[..8].copy_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+        data[16..18].copy_from_slice(&ET_DYN.to_le_bytes());
+        data[18..20].copy_from_slice(&0x3eu16.to_le_bytes());
+        data[20..24].copy_from_slice(&1u32.to_le_bytes());
+        data[24..32].copy_from_slice(&0x40u64.to_le_bytes());
+        data[32..40].copy_from_slice(&64u64.to_le_bytes());
+        data[52..54].copy_from_slice(&64u16.to_le_bytes());
+        data[54..56].copy_from_slice(&56u16.to_le_bytes());
+        data[56..58].copy_from_slice(&2u16.to_le_bytes());
+
+        let phdr = &mut data[64..120];
+        phdr[..4].copy_from_slice(&PT_LOAD.to_le_bytes());
+        phdr[4..8].copy_from_slice(&(elf::abi::PF_R | elf::abi::PF_W).to_le_bytes());
+        phdr[32..40].copy_from_slice(&0x130u64.to_le_bytes());
+        phdr[40..48].copy_from_slice(&0x1000u64.to_le_bytes());
+        let phdr = &mut data[120..176];
+        phdr[..4].copy_from_slice(&PT_DYNAMIC.to_le_bytes());
+        phdr[8..16].copy_from_slice(&0xb0u64.to_le_bytes());
+        phdr[16..24].copy_from_slice(&0xb0u64.to_le_bytes());
+        phdr[32..40].copy_from_slice(&0x40u64.to_le_bytes());
+
+        let dynamic = [
+            (DT_RELA, 0xf0),
+            (DT_RELASZ, 0x30),
+            (DT_RELAENT, 0x18),
+            (DT_NULL, 0),
+        ];
+        for (i, (tag, val)) in dynamic.into_iter().enumerate() {
+            let entry = &mut data[0xb0 + i * 16..0xc0 + i * 16];
+            entry[..8].copy_from_slice(&tag.to_le_bytes());
+            entry[8..].copy_from_slice(&(val as u64).to_le_bytes());
+        }
+        for (i, (offset, addend)) in [(0x128u64, 0x10i64), (0x120, 0x100)]
+            .into_iter()
+            .enumerate()
+        {
+            let entry = &mut data[0xf0 + i * 0x18..0x108 + i * 0x18];
+            entry[..8].copy_from_slice(&offset.to_le_bytes());
+            entry[8..16].copy_from_slice(&(r_type as u64).to_le_bytes());
+            entry[16..].copy_from_slice(&addend.to_le_bytes());
+        }
+        data
+    }
+
+    #[kernel_test]
+    fn relocate_pie() {
+        let data = pie_image(R_X86_64_RELATIVE);
+        let parsed = ElfImage::parse(&ElfSource::Bytes(&data)).unwrap();
+        assert_eq!(parsed.base, DYN_BASE);
+        assert_eq!(parsed.entry.as_u64(), DYN_BASE + 0x40);
+        assert_eq!(parsed.vmas()[0].start().as_u64(), DYN_BASE);
+        assert_eq!(
+            parsed.relocations,
+            [
+                Relocation {
+                    addr: DYN_BASE + 0x120,
+                    value: DYN_BASE + 0x100
+                },
+                Relocation {
+                    addr: DYN_BASE + 0x128,
+                    value: DYN_BASE + 0x10
+                },
+            ]
+        );
+
+        let mut buf = [0; 0x10];
+        assert_eq!(parsed.relocate(DYN_BASE + 0x120, &mut buf), 0x10);
+        assert_eq!(buf[..8], (DYN_BASE + 0x100).to_le_bytes());
+        assert_eq!(buf[8..], (DYN_BASE + 0x10).to_le_bytes());
+        // a relocation straddling the start of a chunk is applied partially
+        let mut buf = [0; 4];
+        assert_eq!(parsed.relocate(DYN_BASE + 0x124, &mut buf), 4);
+        assert_eq!(buf, (DYN_BASE + 0x100).to_le_bytes()[4..]);
+        assert_eq!(parsed.relocate(DYN_BASE + 0x200, &mut buf), 0);
+
+        // symbol relocations need a dynamic linker
+        let data = pie_image(elf::abi::R_X86_64_64);
+        assert_eq!(
+            ElfImage::parse(&ElfSource::Bytes(&data)).map(|_| ()),
+            Err(ElfError::Unsupported)
+        );
+    }
+
+    #[kernel_test]
+    fn parse_headers() {
+        let data = image(0x40_0000);
+        let parsed = ElfImage::parse(&ElfSource::Bytes(&data)).unwrap();
+        assert_eq!(parsed.entry.as_u64(), 0x40_0004);
+        assert_eq!(parsed.base, 0);
+        let vmas = parsed.vmas();
+        assert_eq!(vmas.len(), 1);
+
+        // the segment contents are not needed for the headers, but loading them fails
+        let headers_only = &data[..120];
+        let parsed = ElfImage::parse(&ElfSource::Bytes(headers_only)).unwrap();
+        let mut buf = [0; 0x10];
+        assert_eq!(
+            ElfSource::Bytes(headers_only).read_exact_at(&mut buf, parsed.segments[0].p_offset),
+            Err(ElfError::Truncated)
+        );
+        assert_eq!(
+            ElfImage::parse(&ElfSource::Bytes(&data[..40])).map(|_| ()),
+            Err(ElfError::Truncated)
+        );
+        assert_eq!(
+            ElfImage::parse(&ElfSource::Bytes(&data[1..])).map(|_| ()),
+            Err(ElfError::Parse)
+        );
+        // segments may not be mapped over the kernel
+        let kernel = image(get_hhdm_addr());
+        assert_eq!(
+            ElfImage::parse(&ElfSource::Bytes(&kernel)).map(|_| ()),
+            Err(ElfError::Malformed)
+        );
+        // nor across the end of user space
+        let across = image(USER_END - 0x10);
+        assert_eq!(
+            ElfImage::parse(&ElfSource::Bytes(&across)).map(|_| ()),
+            Err(ElfError::Malformed)
+        );
+    }
 }
@@ -0,0 +1,341 @@
+use core::{arch::global_asm, mem::offset_of, ptr, slice};
+
+use conquer_once::spin::OnceCell;
+use os_macros::init_task;
+use tinyos_abi::{
+    consts::{
+        AT_ENTRY,
+        AT_PAGESZ,
+        AT_SYSINFO_EHDR,
+        CLOCK_MONOTONIC,
+        VDSO_MAGIC,
+        VDSO_PAGES,
+        VDSO_START,
+    },
+    types::AuxEntry,
+};
+
+use crate::{
+    KernelError,
+    KernelRes,
+    arch::mem::{
+        FrameAllocator,
+        Mapper,
+        Page,
+        PageSize,
+        PageTableFlags,
+        PhysFrame,
+        Size4KiB,
+        VirtAddr,
+    },
+    bootinfo,
+    kernel::{
+        mem::{
+            paging::{BORROWED, get_frame_alloc, get_hhdm_addr},
+            vma::{Vma, VmaBacking},
+        },
+        threading::ThreadingError,
+        time,
+    },
+};
+
+const PAGE_SIZE: usize = Size4KiB::SIZE as usize;
+const DATA_PAGE: u64 = VDSO_START;
+const PROCESS_PAGE: u64 = VDSO_START + Size4KiB::SIZE;
+/// passed as AT_SYSINFO_EHDR
+pub const CODE_PAGE: u64 = VDSO_START + 2 * Size4KiB::SIZE;
+
+/// the kernel data read by the vDSO, shared by all processes
+#[repr(C)]
+#[derive(Debug)]
+struct VdsoData {
+    /// the timestamp, at which the monotonic clock starts
+    boot_timestamp: u64,
+    /// nanoseconds per timestamp cycle as a 32.32 fixed point number, such that the vDSO does not divide by the frequency
+    ns_mult: u64,
+    /// nanoseconds since the unix epoch at boot_timestamp
+    boot_realtime: u64,
+}
+
+impl VdsoData {
+    fn nanos_since_boot(&self, timestamp: u64) -> u64 {
+        (((timestamp - self.boot_timestamp) as u128 * self.ns_mult as u128) >> 32) as u64
+    }
+}
+
+/// the data of a single process, mapped behind the shared data
+#[repr(C)]
+#[derive(Debug)]
+struct VdsoProcess {
+    /// as seen from the pid namespace of the process
+    pid: u64,
+}
+
+// The code is copied into a page mapped right behind the data pages, thus both are addressed relative to the code.
+// The page starts with a VdsoHeader
+global_asm!(
+    "
+    .pushsection .rodata.vdso, \"a\"
+    .global __vdso_start
+    .global __vdso_end
+
+    __vdso_start:
+    .Lvdso_base:
+        .quad {magic}
+        .quad .Lclock_gettime - .Lvdso_base
+        .quad .Lgetpid - .Lvdso_base
+
+    // clock in rdi, *mut Timespec in rsi
+    .Lclock_gettime:
+        cmp rdi, {monotonic}
+        ja 2f
+        lea r8, [rip + .Lvdso_base - {data}]
+        rdtsc
+        shl rdx, 32
+        or rax, rdx
+        sub rax, [r8 + {boot_timestamp}]
+        mul qword ptr [r8 + {ns_mult}]
+        shrd rax, rdx, 32
+        // CLOCK_REALTIME is 0
+        test rdi, rdi
+        jnz 1f
+        add rax, [r8 + {boot_realtime}]
+    1:
+        xor edx, edx
+        mov rcx, 1000000000
+        div rcx
+        mov [rsi], rax
+        mov [rsi + 8], rdx
+        xor eax, eax
+        ret
+    2:
+        mov rax, -1
+        ret
+
+    .Lgetpid:
+        lea r8, [rip + .Lvdso_base - {process}]
+        mov rax, [r8 + {pid}]
+        ret
+
+    __vdso_end:
+    .popsection
+    ",
+    magic = const VDSO_MAGIC,
+    monotonic = const CLOCK_MONOTONIC,
+    data = const CODE_PAGE - DATA_PAGE,
+    process = const CODE_PAGE - PROCESS_PAGE,
+    boot_timestamp = const offset_of!(VdsoData, boot_timestamp),
+    ns_mult = const offset_of!(VdsoData, ns_mult),
+    boot_realtime = const offset_of!(VdsoData, boot_realtime),
+    pid = const offset_of!(VdsoProcess, pid),
+);
+
+unsafe extern "C" {
+    static __vdso_start: u8;
+    static __vdso_end: u8;
+}
+
+/// the code of the vDSO, as it is copied into its code page
+fn code() -> &'static [u8] {
+    // SAFETY: both symbols are defined in the same section above
+    unsafe {
+        let start = &raw const __vdso_start;
+        let end = &raw const __vdso_end;
+        slice::from_raw_parts(start, end.addr() - start.addr())
+    }
+}
+
+/// the frames shared by all processes
+#[derive(Debug)]
+struct Vdso {
+    data: PhysFrame<Size4KiB>,
+    code: PhysFrame<Size4KiB>,
+}
+
+static VDSO: OnceCell<Vdso> = OnceCell::uninit();
+
+fn frame_ptr<T>(frame: PhysFrame<Size4KiB>) -> *mut T {
+    (get_hhdm_addr() + frame.start_address().as_u64()) as *mut T
+}
+
+fn ns_mult(frequency: u64) -> u64 {
+    ((1_000_000_000u128 << 32) / frequency.max(1) as u128) as u64
+}
+
+/// sets up the shared pages, before the first user process is started
+#[init_task(stage = "fs", order = 5)]
+fn init() -> KernelRes<()> {
+    let (data, code_frame) = {
+        let mut alloc = get_frame_alloc().lock();
+        alloc
+            .allocate_frame()
+            .zip(alloc.allocate_frame())
+            .ok_or(KernelError::Unexpected("no frames left for the vdso"))?
+    };
+    debug_assert!(code().len() <= PAGE_SIZE);
+    // SAFETY: the frames were just allocated and are reachable through the hhdm
+    unsafe {
+        ptr::write_bytes(frame_ptr::<u8>(data), 0, PAGE_SIZE);
+        frame_ptr::<VdsoData>(data).write(VdsoData {
+            boot_timestamp: time::boot_timestamp(),
+            ns_mult: ns_mult(time::timestamp_frequency()),
+            boot_realtime: bootinfo::boot_time().as_nanos() as u64,
+        });
+        // int3 behind the code
+        ptr::write_bytes(frame_ptr::<u8>(code_frame), 0xcc, PAGE_SIZE);
+        ptr::copy_nonoverlapping(code().as_ptr(), frame_ptr(code_frame), code().len());
+    }
+    VDSO.init_once(|| Vdso {
+        data,
+        code: code_frame,
+    });
+    Ok(())
+}
+
+/// maps the vDSO into the address space of a new process. pid is the pid of the process, as seen from its namespace.
+/// Processes started before the vDSO is set up run without it
+pub fn map_into<M: Mapper<Size4KiB>>(tbl: &mut M, pid: u64) -> Result<(), ThreadingError> {
+    let Some(vdso) = VDSO.get() else {
+        return Ok(());
+    };
+    map(tbl, vdso, pid, PageTableFlags::USER_ACCESSIBLE).map(|_| ())
+}
+
+/// maps the shared pages borrowed and a new page with the data of the process, which is returned
+fn map<M: Mapper<Size4KiB>>(
+    tbl: &mut M,
+    vdso: &Vdso,
+    pid: u64,
+    access: PageTableFlags,
+) -> Result<PhysFrame<Size4KiB>, ThreadingError> {
+    let mut alloc = get_frame_alloc().lock();
+    let process = alloc
+        .allocate_frame()
+        .ok_or(ThreadingError::PageDirNotBuilt)?;
+    // SAFETY: the frame was just allocated and is reachable through the hhdm
+    unsafe {
+        ptr::write_bytes(frame_ptr::<u8>(process), 0, PAGE_SIZE);
+        frame_ptr::<VdsoProcess>(process).write(VdsoProcess { pid });
+    }
+    let flags = PageTableFlags::PRESENT | access;
+    for (addr, frame, flags) in [
+        (
+            DATA_PAGE,
+            vdso.data,
+            flags | PageTableFlags::NO_EXECUTE | BORROWED,
+        ),
+        (PROCESS_PAGE, process, flags | PageTableFlags::NO_EXECUTE),
+        (CODE_PAGE, vdso.code, flags | BORROWED),
+    ] {
+        unsafe {
+            tbl.map_to(
+                Page::containing_address(VirtAddr::new(addr)),
+                frame,
+                flags,
+                &mut *alloc,
+            )
+        }
+        .map_err(|_| ThreadingError::PageDirNotBuilt)?
+        .flush();
+    }
+    Ok(process)
+}
+
+/// the vmas of the vDSO, if it is set up
+pub fn vmas() -> impl Iterator<Item = Vma> {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    VDSO.get().into_iter().flat_map(move |_| {
+        [
+            Vma::new(
+                VirtAddr::new(DATA_PAGE),
+                (CODE_PAGE - DATA_PAGE) as usize,
+                flags | PageTableFlags::NO_EXECUTE,
+                VmaBacking::Vdso,
+            ),
+            Vma::new(VirtAddr::new(CODE_PAGE), PAGE_SIZE, flags, VmaBacking::Vdso),
+        ]
+    })
+}
+
+/// whether [start, start + len) touches the vDSO, which must not be unmapped
+pub fn overlaps(start: VirtAddr, len: usize) -> bool {
+    start.as_u64() < VDSO_START + VDSO_PAGES * Size4KiB::SIZE
+        && start.as_u64().saturating_add(len as u64) > VDSO_START
+}
+
+/// the auxiliary vector of a new process starting at entry
+pub fn auxv(entry: VirtAddr) -> [AuxEntry; 4] {
+    let aux = |key, val| AuxEntry { key, val };
+    [
+        aux(AT_PAGESZ, Size4KiB::SIZE),
+        aux(AT_ENTRY, entry.as_u64()),
+        if VDSO.is_initialized() {
+            aux(AT_SYSINFO_EHDR, CODE_PAGE)
+        } else {
+            AuxEntry::default()
+        },
+        AuxEntry::default(),
+    ]
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use core::mem;
+
+    use os_macros::kernel_test;
+    use tinyos_abi::{
+        consts::CLOCK_REALTIME,
+        types::{Timespec, VdsoHeader},
+    };
+
+    use super::*;
+    use crate::{
+        arch::{self, mem::FrameDeallocator},
+        kernel::mem::paging::{PAGETABLE, unmap_region_from},
+    };
+
+    #[kernel_test]
+    fn clock_and_pid() {
+        let header = unsafe { code().as_ptr().cast::<VdsoHeader>().read_unaligned() };
+        assert_eq!(header.magic, VDSO_MAGIC);
+        assert!(header.clock_gettime < code().len() as u64);
+        assert!(header.getpid < code().len() as u64);
+        let aux = auxv(VirtAddr::new(0x1000));
+        assert!(aux.contains(&AuxEntry {
+            key: AT_SYSINFO_EHDR,
+            val: CODE_PAGE
+        }));
+        assert_eq!(aux.last(), Some(&AuxEntry::default()));
+        assert!(overlaps(VirtAddr::new(CODE_PAGE + 8), 1));
+        assert!(!overlaps(VirtAddr::new(VDSO_START - 4096), 4096));
+
+        // mapped without user access, the vDSO runs in the kernel as well
+        let vdso = VDSO.get().unwrap();
+        let process = map(&mut *PAGETABLE.lock(), vdso, 42, PageTableFlags::empty()).unwrap();
+        let clock_gettime: extern "sysv64" fn(u64, *mut Timespec) -> i64 =
+            unsafe { mem::transmute(CODE_PAGE + header.clock_gettime) };
+        let getpid: extern "sysv64" fn() -> u64 =
+            unsafe { mem::transmute(CODE_PAGE + header.getpid) };
+        let data = unsafe { &*frame_ptr::<VdsoData>(vdso.data) };
+
+        assert_eq!(getpid(), 42);
+        let mut ts = Timespec::default();
+        let before = data.nanos_since_boot(arch::timestamp());
+        assert_eq!(clock_gettime(CLOCK_MONOTONIC, &mut ts), 0);
+        let after = data.nanos_since_boot(arch::timestamp());
+        let nanos = ts.secs * 1_000_000_000 + ts.nanos;
+        assert!(before <= nanos && nanos <= after);
+        assert!(ts.nanos < 1_000_000_000);
+        assert_eq!(clock_gettime(CLOCK_REALTIME, &mut ts), 0);
+        assert!(ts.secs >= bootinfo::boot_time().as_secs());
+        assert_eq!(clock_gettime(CLOCK_MONOTONIC + 1, &mut ts), -1);
+
+        unmap_region_from(
+            VirtAddr::new(VDSO_START),
+            (VDSO_PAGES * Size4KiB::SIZE) as usize,
+            &mut *PAGETABLE.lock(),
+        )
+        .unwrap();
+        unsafe { get_frame_alloc().lock().deallocate_frame(process) };
+    }
+}
@@ -1,15 +1,10 @@
-use alloc::{
-    boxed::Box,
-    collections::btree_map::{BTreeMap, Values},
-    string::String,
-    sync::Arc,
-    vec::Vec,
-};
+use alloc::{boxed::Box, collections::btree_map::Values, string::String, sync::Arc, vec::Vec};
 use core::{
     fmt::{self, Debug},
     ops::Deref,
     ptr::null_mut,
-    sync::atomic::{AtomicUsize, Ordering},
+    slice,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
 };
 
 use bitflags::bitflags;
@@ -23,20 +18,65 @@ use tinyos_abi::{
 };
 
 use crate::{
-    arch::{self, x86::current_time},
+    arch::{
+        self,
+        mem::{PageSize, PhysFrame, Size4KiB, VirtAddr},
+    },
     eprintln,
     kernel::{
         fs::{FSError, FSErrorKind, OpenOptions, Path, PathBuf},
-        io::{IOResult, Read, Write},
+        io::{IOResult, Read, Write, ring::Ring},
+        mem::paging,
         threading::wait::{QueuTypeCondition, QueueType},
+        time,
     },
 };
 
-pub type FDMap = BTreeMap<FileDescriptor, FileHandle>;
+mod table;
+pub use table::*;
+
+const PAGE_SIZE: usize = Size4KiB::SIZE as usize;
 
+/// reads of fewer bytes are copied by File::read_lending, as remapping pages costs more than copying them
+pub const LEND_MIN_LEN: usize = 4 * PAGE_SIZE;
+
+/// a file descriptor entry. The wrapped File is the open file description (in the posix sense):
+/// handles created through dup, dup2 or by inheriting files into a new task all point to the same File,
+/// and thus share its cursor and perms. Only open-like paths create a new description (see FileHandle::reopen).
 #[derive(Debug)]
 pub struct FileHandle {
     f: Arc<File>,
+    flags: FDFlags,
+}
+
+impl FileHandle {
+    pub fn description(&self) -> &Arc<File> {
+        &self.f
+    }
+
+    pub fn shares_description_with(&self, other: &FileHandle) -> bool {
+        Arc::ptr_eq(&self.f, &other.f)
+    }
+
+    /// creates a new open file description for the same underlying file, starting at offset 0.
+    /// Returns None if the file repr is not shareable
+    pub fn reopen(&self) -> Option<Self> {
+        self.f.try_clone_without_offset().map(Self::from)
+    }
+
+    /// fd flags belong to the descriptor, not to the underlying File, so every table entry carries its own copy
+    pub fn with_fd_flags(mut self, flags: FDFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    pub fn fd_flags(&self) -> FDFlags {
+        self.flags
+    }
+
+    pub fn close_on_exec(&self) -> bool {
+        self.flags.contains(FDFlags::CLOEXEC)
+    }
 }
 
 impl<T> AsRef<T> for FileHandle
@@ -74,13 +114,19 @@ impl Clone for FileHandle {
             cursor: self.f.cursor.clone(),
             perms: self.f.perms.clone(),
         });
-        Self { f: self.f.clone() }
+        Self {
+            f: self.f.clone(),
+            flags: self.flags,
+        }
     }
 }
 
 impl From<Arc<File>> for FileHandle {
     fn from(value: Arc<File>) -> Self {
-        Self { f: value }
+        Self {
+            f: value,
+            flags: FDFlags::empty(),
+        }
     }
 }
 
@@ -108,6 +154,11 @@ pub trait FileRepr: Debug + IOCapable + Send + Sync {
         Err(FSError::simple(FSErrorKind::NotSupported))
     }
 
+    /// sets the access and modification times in secs since startup. None keeps the time
+    fn set_times(&self, access: Option<u64>, modify: Option<u64>) -> IOResult<()> {
+        Err(FSError::simple(FSErrorKind::NotSupported))
+    }
+
     fn as_raw_parts(&self) -> (*mut u8, usize) {
         eprintln!(
             "called default FileRepr::as_raw_parts implementation. This is not what you want."
@@ -119,6 +170,27 @@ pub trait FileRepr: Debug + IOCapable + Send + Sync {
         None
     }
 
+    /// queues handle, such that a reader of this file may take it out with recv_fd, like SCM_RIGHTS on unix sockets
+    fn send_fd(&self, handle: FileHandle) -> IOResult<()> {
+        Err(FSError::simple(FSErrorKind::NotSupported))
+    }
+
+    /// takes the oldest handle queued by send_fd, if there is one
+    fn recv_fd(&self) -> IOResult<Option<FileHandle>> {
+        Err(FSError::simple(FSErrorKind::NotSupported))
+    }
+
+    /// the frames backing up to pages whole pages of the file from offset on, which must be page aligned, each with a
+    /// reference added for the caller. Files, which can not lend their pages, return none, such that readers copy
+    fn lend_frames(&self, offset: usize, pages: usize) -> Vec<PhysFrame<Size4KiB>> {
+        Vec::new()
+    }
+
+    /// the submission ring behind this file, see ring_setup
+    fn as_ring(&self) -> Option<&Ring> {
+        None
+    }
+
     fn on_open(&self, _meta: FileMetadata) {}
     /// runs when ANY handle around this file clones
     fn on_clone(&self, _meta: FileMetadata) {}
@@ -177,16 +249,95 @@ macro_rules!  impl_file_for_wr {
 
 // size == 0, for size == usize::MAX, use Fstat::default()
 pub fn new_fstat() -> FStat {
-    let now = current_time().as_secs();
+    let now = time::uptime().as_secs();
     FStat {
         t_create: now,
         t_mod: now,
+        t_access: now,
+        t_change: now,
         size: 0,
         permissions: NodePermissions::rw(),
         node_type: NodeType::VOID,
     }
 }
 
+/// the timestamps of a node in secs since startup. They are updated without locking the node, such that reads may
+/// update the access time
+#[derive(Debug)]
+pub struct NodeTimes {
+    create: u64,
+    access: AtomicU64,
+    modify: AtomicU64,
+    change: AtomicU64,
+}
+
+impl NodeTimes {
+    pub fn new() -> Self {
+        let now = time::uptime().as_secs();
+        Self {
+            create: now,
+            access: AtomicU64::new(now),
+            modify: AtomicU64::new(now),
+            change: AtomicU64::new(now),
+        }
+    }
+
+    /// the contents were read
+    pub fn accessed(&self) {
+        self.access
+            .store(time::uptime().as_secs(), Ordering::Relaxed);
+    }
+
+    /// the contents were written, which changes the node as well
+    pub fn modified(&self) {
+        let now = time::uptime().as_secs();
+        self.modify.store(now, Ordering::Relaxed);
+        self.change.store(now, Ordering::Relaxed);
+    }
+
+    /// the metadata, like the permissions, changed
+    pub fn changed(&self) {
+        self.change
+            .store(time::uptime().as_secs(), Ordering::Relaxed);
+    }
+
+    /// sets the access and modification times explicitly. None keeps the time
+    pub fn set(&self, access: Option<u64>, modify: Option<u64>) {
+        if let Some(access) = access {
+            self.access.store(access, Ordering::Relaxed);
+        }
+        if let Some(modify) = modify {
+            self.modify.store(modify, Ordering::Relaxed);
+        }
+        self.changed();
+    }
+
+    /// writes the timestamps into stat
+    pub fn apply(&self, stat: &mut FStat) {
+        stat.t_create = self.create;
+        stat.t_access = self.access.load(Ordering::Relaxed);
+        stat.t_mod = self.modify.load(Ordering::Relaxed);
+        stat.t_change = self.change.load(Ordering::Relaxed);
+    }
+}
+
+impl Default for NodeTimes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clone for NodeTimes {
+    fn clone(&self) -> Self {
+        Self {
+            create: self.create,
+            access: AtomicU64::new(self.access.load(Ordering::Relaxed)),
+            modify: AtomicU64::new(self.modify.load(Ordering::Relaxed)),
+            change: AtomicU64::new(self.change.load(Ordering::Relaxed)),
+        }
+    }
+}
+
 // this is very hacky, we should do the append/truncate stuff ONLY on file creation, not on with_perms. Should not be a FilePerm. TODO
 bitflags! {
     #[derive(Clone, Debug, PartialEq, Eq)]
@@ -227,6 +378,23 @@ impl From<OpenOptions> for FPerms {
     }
 }
 
+bitflags! {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct FDFlags: u8 {
+        const CLOEXEC = 1 << 0;
+    }
+}
+
+impl From<OpenOptions> for FDFlags {
+    fn from(value: OpenOptions) -> Self {
+        if value.contains(OpenOptions::CLOEXEC) {
+            Self::CLOEXEC
+        } else {
+            Self::empty()
+        }
+    }
+}
+
 pub enum MaybeOwned<T: ?Sized> {
     Owned(Box<T>),
     Shared(Arc<T>),
@@ -444,6 +612,43 @@ impl File {
         Ok(n)
     }
 
+    /// like read_continuous, but the whole pages of buf, which must be user memory of the current task, are replaced by
+    /// the frames of the file mapped copy on write, if it lends them. This needs buf to start at the same offset into a
+    /// page as the cursor. Reads shorter than LEND_MIN_LEN and the partial pages at both ends are copied
+    pub fn read_lending(&self, buf: &mut [u8]) -> super::io::IOResult<usize> {
+        let offset = self.cursor.get();
+        let addr = buf.as_ptr() as usize;
+        // the bytes up to the first page boundary of buf
+        let head = addr.wrapping_neg() % PAGE_SIZE;
+        if buf.len() < LEND_MIN_LEN || addr % PAGE_SIZE != offset % PAGE_SIZE || !self.may_read() {
+            return self.read_continuous(buf);
+        }
+        let frames = self
+            .repr
+            .lend_frames(offset + head, (buf.len() - head) / PAGE_SIZE);
+        if frames.is_empty() {
+            return self.read_continuous(buf);
+        }
+        let n = self.read(&mut buf[..head], offset)?;
+        let lent = frames.len() * PAGE_SIZE;
+        if n < head || paging::lend_into(VirtAddr::from_ptr(buf[head..].as_ptr()), &frames).is_err()
+        {
+            // lend_into drops the references itself
+            if n < head {
+                let mut alloc = paging::get_frame_alloc().lock();
+                for frame in frames {
+                    paging::free_frame(frame, &mut *alloc);
+                }
+            }
+            self.cursor.advance(n);
+            return self.read_continuous(&mut buf[n..]).map(|m| n + m);
+        }
+        let tail = self.read(&mut buf[head + lent..], offset + head + lent)?;
+        let n = head + lent + tail;
+        self.cursor.advance(n);
+        Ok(n)
+    }
+
     pub fn write_continuous(&self, buf: &[u8]) -> super::io::IOResult<usize> {
         let n = self.write(buf, self.cursor.get())?;
         self.cursor.advance(n);
@@ -454,6 +659,15 @@ impl File {
         self.cursor.inner.store(offset, Ordering::Release);
     }
 
+    pub fn cursor(&self) -> usize {
+        self.cursor.get()
+    }
+
+    /// the permissions self was opened with
+    pub fn perms(&self) -> &FPerms {
+        &self.perms
+    }
+
     pub fn may_write(&self) -> bool {
         self.perms.contains(FPerms::WRITE)
     }
@@ -474,7 +688,9 @@ impl File {
         Ok(buf)
     }
 
-    pub fn try_clone_without_offset(&self) -> Option<Self> {
+    // this creates a new open file description and should thus only be used by open-like paths.
+    // dup and inheritance must share the existing File instead
+    pub(crate) fn try_clone_without_offset(&self) -> Option<Self> {
         Some(Self {
             repr: self.repr.try_clone()?,
             cursor: FCursor::default(),
@@ -486,6 +702,81 @@ impl File {
     pub fn is_at_end(&self) -> bool {
         self.repr.fstat().size <= self.cursor.get()
     }
+
+    /// copies up to len bytes starting at offset of self to the cursor of out, without going through a user buffer.
+    /// Returns the number of bytes which were actually written to out. Errors are only returned if nothing could be transferred.
+    /// Whole pages of files, which lend them, are written to out straight from the page cache. The rest is copied through
+    /// a bounce buffer, whose bytes, which were read but not taken by out, are handed back to self, such that streams do not lose them
+    pub fn send_to(&self, offset: usize, out: &File, len: usize) -> IOResult<usize> {
+        if !self.may_read() || !out.may_write() {
+            return Err(FSError::simple(FSErrorKind::PermissionDenied));
+        }
+        let mut sent = 0;
+        let in_page = offset % PAGE_SIZE;
+        let frames = self
+            .repr
+            .lend_frames(offset - in_page, (in_page + len).div_ceil(PAGE_SIZE));
+        let mut stopped = None;
+        for frame in &frames {
+            let start = (offset + sent) % PAGE_SIZE;
+            let end = PAGE_SIZE.min(start + len - sent);
+            let page = unsafe {
+                slice::from_raw_parts(
+                    (paging::get_hhdm_addr() + frame.start_address().as_u64()) as *const u8,
+                    PAGE_SIZE,
+                )
+            };
+            let (written, failed) = write_out(out, &page[start..end]);
+            sent += written;
+            if written < end - start {
+                stopped = Some(failed);
+                break;
+            }
+        }
+        if !frames.is_empty() {
+            let mut alloc = paging::get_frame_alloc().lock();
+            for frame in frames {
+                paging::free_frame(frame, &mut *alloc);
+            }
+        }
+        if let Some(failed) = stopped {
+            return match failed {
+                Some(e) if sent == 0 => Err(e),
+                _ => Ok(sent),
+            };
+        }
+
+        let mut chunk = [0; SEND_CHUNK_SIZE];
+        while sent < len {
+            let to_read = (len - sent).min(SEND_CHUNK_SIZE);
+            let n = match self.repr.read(&mut chunk[..to_read], offset + sent) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if sent == 0 => return Err(e),
+                Err(_) => break,
+            };
+            let (written, failed) = write_out(out, &chunk[..n]);
+            sent += written;
+            if written < n {
+                // out cannot take more right now (full pipe, full disk, ...). Streams dropped the rest of the chunk
+                // when it was read, thus it is handed back
+                _ = self.repr.unread(&chunk[written..n]);
+                if let Some(e) = failed
+                    && sent == 0
+                {
+                    return Err(e);
+                }
+                break;
+            }
+        }
+        Ok(sent)
+    }
+
+    pub fn send_continuous(&self, out: &File, len: usize) -> IOResult<usize> {
+        let n = self.send_to(self.cursor.get(), out, len)?;
+        self.cursor.advance(n);
+        Ok(n)
+    }
 }
 
 impl FileRepr for File {
@@ -497,18 +788,49 @@ impl FileRepr for File {
         self.repr.update_perms(perms, strategy);
     }
 
+    fn set_times(&self, access: Option<u64>, modify: Option<u64>) -> IOResult<()> {
+        self.repr.set_times(access, modify)
+    }
+
     fn as_raw_parts(&self) -> (*mut u8, usize) {
         let (ptr, len) = self.repr.as_raw_parts();
         let offset = self.cursor.get().min(len);
         (unsafe { ptr.offset(offset as isize) }, len - offset)
     }
 
+    /// the condition of the file itself, if it can tell when it becomes readable. Otherwise changes to its path are waited for
     fn get_waiter(&self) -> Option<QueuTypeCondition> {
-        if let Some(path) = &self.path {
-            Some(QueuTypeCondition::new(QueueType::file(path)))
-        } else {
-            self.repr.get_waiter()
+        self.repr.get_waiter().or_else(|| {
+            self.path
+                .as_ref()
+                .map(|path| QueuTypeCondition::new(QueueType::file(path)))
+        })
+    }
+
+    fn send_fd(&self, handle: FileHandle) -> IOResult<()> {
+        if !self.may_write() {
+            return Err(FSError::simple(FSErrorKind::PermissionDenied));
+        }
+        // a file queued in itself would keep itself alive forever
+        if core::ptr::addr_eq(&*handle.repr, &*self.repr) {
+            return Err(FSError::simple(FSErrorKind::Deadlock));
+        }
+        self.repr.send_fd(handle)
+    }
+
+    fn recv_fd(&self) -> IOResult<Option<FileHandle>> {
+        if !self.may_read() {
+            return Err(FSError::simple(FSErrorKind::PermissionDenied));
         }
+        self.repr.recv_fd()
+    }
+
+    fn as_ring(&self) -> Option<&Ring> {
+        self.repr.as_ring()
+    }
+
+    fn lend_frames(&self, offset: usize, pages: usize) -> Vec<PhysFrame<Size4KiB>> {
+        self.repr.lend_frames(offset, pages)
     }
 }
 
@@ -525,6 +847,10 @@ impl Read for File {
     fn read_to_end(&self, buf: &mut Vec<u8>, offset: usize) -> super::io::IOResult<usize> {
         self.repr.read_to_end(buf, offset)
     }
+
+    fn unread(&self, buf: &[u8]) -> super::io::IOResult<()> {
+        self.repr.unread(buf)
+    }
 }
 
 impl Write for File {
@@ -559,6 +885,21 @@ impl Drop for File {
     }
 }
 
+const SEND_CHUNK_SIZE: usize = 512;
+
+/// writes as much of buf to the cursor of out, as it takes. Returns the number of bytes written and the error, which stopped it early
+fn write_out(out: &File, buf: &[u8]) -> (usize, Option<FSError>) {
+    let mut written = 0;
+    while written < buf.len() {
+        match out.write_continuous(&buf[written..]) {
+            Ok(0) => break,
+            Ok(w) => written += w,
+            Err(e) => return (written, Some(e)),
+        }
+    }
+    (written, None)
+}
+
 #[derive(Debug, Default)]
 pub struct FCursor {
     inner: AtomicUsize,
@@ -581,3 +922,166 @@ impl Clone for FCursor {
         }
     }
 }
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+    use crate::kernel::devices::{
+        Null,
+        Zero,
+        tty::{MAX_IN_FLIGHT, Pipe},
+    };
+
+    #[kernel_test]
+    fn shared_description() {
+        let f: FileHandle = FileBuilder::new(Arc::new(Null) as Arc<dyn FileRepr>)
+            .with_perms(FPerms::READ)
+            .finish()
+            .into();
+        let dup = f.clone().with_fd_flags(FDFlags::CLOEXEC);
+        assert!(f.shares_description_with(&dup));
+
+        f.set_cursor(4);
+        assert_eq!(dup.cursor.get(), 4);
+
+        let reopened = dup.reopen().unwrap();
+        assert!(!reopened.shares_description_with(&f));
+        assert_eq!(reopened.cursor.get(), 0);
+        assert_eq!(reopened.fd_flags(), FDFlags::empty());
+    }
+
+    #[kernel_test]
+    fn fd_limits() {
+        let null = || -> FileHandle {
+            FileBuilder::new(Arc::new(Null) as Arc<dyn FileRepr>)
+                .finish()
+                .into()
+        };
+        let mut table = FDTable::new();
+        table.set_limit(130).unwrap();
+        for fd in 0..130 {
+            assert_eq!(table.insert_next(null()), Ok(fd));
+        }
+        assert_eq!(table.insert_next(null()), Err(FDTableError::Exhausted(130)));
+        assert_eq!(
+            table.insert(130, null()).err(),
+            Some(FDTableError::OutOfRange(130))
+        );
+
+        assert!(table.remove(&64).is_some());
+        assert!(table.remove(&3).is_some());
+        assert_eq!(table.insert_next(null()), Ok(3));
+        assert_eq!(table.insert_next(null()), Ok(64));
+
+        assert_eq!(
+            table.set_limit(MAX_FDS + 1),
+            Err(FDTableError::InvalidLimit(MAX_FDS + 1))
+        );
+    }
+
+    #[kernel_test]
+    fn send_file() {
+        let zero = FileBuilder::new(Arc::new(Zero) as Arc<dyn FileRepr>)
+            .with_perms(FPerms::READ)
+            .finish();
+        let pipe = FileBuilder::new(Arc::new(Pipe::new(1000)) as Arc<dyn FileRepr>)
+            .with_perms(FPerms::WRITE)
+            .finish();
+
+        assert_eq!(zero.send_to(7, &pipe, 600).unwrap(), 600);
+        assert_eq!(zero.cursor.get(), 0);
+        // the pipe only has room for 400 more bytes
+        assert_eq!(zero.send_continuous(&pipe, 600).unwrap(), 400);
+        assert_eq!(zero.cursor.get(), 400);
+        assert!(zero.send_continuous(&pipe, 1).is_err());
+
+        let null = FileBuilder::new(Arc::new(Null) as Arc<dyn FileRepr>).finish();
+        assert_eq!(
+            *null.send_to(0, &zero, 1).unwrap_err().kind(),
+            FSErrorKind::PermissionDenied
+        );
+    }
+
+    #[kernel_test]
+    fn send_file_short_writes() {
+        let data: Vec<u8> = (0..100).collect();
+        let src = Arc::new(Pipe::new(100));
+        src.write(&data, 0).unwrap();
+        let src = FileBuilder::new(src as Arc<dyn FileRepr>)
+            .with_perms(FPerms::READ)
+            .finish();
+        let dst = FileBuilder::new(Arc::new(Pipe::new(30)) as Arc<dyn FileRepr>)
+            .with_perms(FPerms::WRITE)
+            .finish();
+
+        // the destination only takes 30 bytes, the other 70 stay in the source pipe
+        assert_eq!(src.send_continuous(&dst, 100).unwrap(), 30);
+        let mut rest = [0; 100];
+        assert_eq!(src.read(&mut rest, 0).unwrap(), 70);
+        assert_eq!(&rest[..70], &data[30..]);
+    }
+
+    #[kernel_test]
+    fn pipe_unread_respects_capacity() {
+        let pipe = Pipe::new(4);
+        pipe.write(b"abcd", 0).unwrap();
+        assert_eq!(
+            *pipe.unread(b"x").unwrap_err().kind(),
+            FSErrorKind::StorageFull
+        );
+        let mut buf = [0; 2];
+        assert_eq!(pipe.read(&mut buf, 0).unwrap(), 2);
+        pipe.unread(&buf).unwrap();
+        let mut all = [0; 4];
+        assert_eq!(pipe.read(&mut all, 0).unwrap(), 4);
+        assert_eq!(&all, b"abcd");
+    }
+
+    #[kernel_test]
+    fn pass_descriptors() {
+        let pipe = Arc::new(Pipe::new(16));
+        let reader: FileHandle = FileBuilder::new(pipe.clone() as Arc<dyn FileRepr>)
+            .with_perms(FPerms::READ)
+            .finish()
+            .into();
+        let writer: FileHandle = FileBuilder::new(pipe as Arc<dyn FileRepr>)
+            .with_perms(FPerms::WRITE)
+            .finish()
+            .into();
+        let zero: FileHandle = FileBuilder::new(Arc::new(Zero) as Arc<dyn FileRepr>)
+            .with_perms(FPerms::READ)
+            .finish()
+            .into();
+
+        assert!(reader.recv_fd().unwrap().is_none());
+        writer.send_fd(zero.clone()).unwrap();
+        // the received handle shares the description, thus its cursor
+        zero.set_cursor(3);
+        let received = reader.recv_fd().unwrap().unwrap();
+        assert!(received.shares_description_with(&zero));
+        assert_eq!(received.cursor.get(), 3);
+        assert!(reader.recv_fd().unwrap().is_none());
+
+        assert_eq!(
+            *reader.send_fd(zero.clone()).unwrap_err().kind(),
+            FSErrorKind::PermissionDenied
+        );
+        assert_eq!(
+            *writer.send_fd(reader.clone()).unwrap_err().kind(),
+            FSErrorKind::Deadlock
+        );
+        for _ in 0..MAX_IN_FLIGHT {
+            writer.send_fd(zero.clone()).unwrap();
+        }
+        assert_eq!(
+            *writer.send_fd(zero.clone()).unwrap_err().kind(),
+            FSErrorKind::StorageFull
+        );
+        assert_eq!(
+            *zero.recv_fd().unwrap_err().kind(),
+            FSErrorKind::NotSupported
+        );
+    }
+}
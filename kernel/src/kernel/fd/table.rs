@@ -0,0 +1,218 @@
+use alloc::{
+    collections::btree_map::{self, BTreeMap},
+    string::String,
+};
+use core::fmt::Write as _;
+
+use os_macros::FileRepr;
+use thiserror::Error;
+use tinyos_abi::types::SysErrCode;
+
+use super::{FileDescriptor, FileHandle};
+use crate::kernel::{
+    io::{Read, read_rendered},
+    threading::tls,
+};
+
+const WORD_BITS: usize = u64::BITS as usize;
+const N_WORDS: usize = WORD_BITS;
+
+/// hard upper bound for the fd limit of a process. This is the capacity of the two level bitmap
+pub const MAX_FDS: FileDescriptor = (N_WORDS * WORD_BITS) as FileDescriptor;
+pub const DEFAULT_FD_LIMIT: FileDescriptor = 1024;
+
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+pub enum FDTableError {
+    #[error("all fds below the limit {0} are in use")]
+    Exhausted(FileDescriptor),
+    #[error("fd {0} is outside of the fd limit")]
+    OutOfRange(FileDescriptor),
+    #[error("fd {0} is not open")]
+    NotOpen(FileDescriptor),
+    #[error("the limit {0} exceeds the maximum of {MAX_FDS} fds")]
+    InvalidLimit(FileDescriptor),
+}
+
+impl From<FDTableError> for SysErrCode {
+    fn from(err: FDTableError) -> Self {
+        match err {
+            FDTableError::Exhausted(_) => Self::TooManyFiles,
+            FDTableError::OutOfRange(_) | FDTableError::NotOpen(_) => Self::BadFd,
+            FDTableError::InvalidLimit(_) => Self::InvalidArg,
+        }
+    }
+}
+
+/// the fd table of a process.
+/// Open fds are tracked in a two level bitmap, such that the lowest free fd can be found in O(1):
+/// bit i of used[w] is set if fd w * 64 + i is open, bit w of full is set if used[w] has no free bits left.
+#[derive(Debug)]
+pub struct FDTable {
+    files: BTreeMap<FileDescriptor, FileHandle>,
+    used: [u64; N_WORDS],
+    full: u64,
+    limit: FileDescriptor,
+}
+
+impl FDTable {
+    pub fn new() -> Self {
+        Self {
+            files: BTreeMap::new(),
+            used: [0; N_WORDS],
+            full: 0,
+            limit: DEFAULT_FD_LIMIT,
+        }
+    }
+
+    pub fn limit(&self) -> FileDescriptor {
+        self.limit
+    }
+
+    /// sets the maximum number of fds. Already open fds above the new limit stay open, but no new fds above it can be created
+    pub fn set_limit(&mut self, limit: FileDescriptor) -> Result<(), FDTableError> {
+        if limit > MAX_FDS {
+            return Err(FDTableError::InvalidLimit(limit));
+        }
+        self.limit = limit;
+        Ok(())
+    }
+
+    pub fn get(&self, fd: &FileDescriptor) -> Option<&FileHandle> {
+        self.files.get(fd)
+    }
+
+    /// inserts f at fd. If fd was open, the old handle is returned in Some
+    pub fn insert(
+        &mut self,
+        fd: FileDescriptor,
+        f: FileHandle,
+    ) -> Result<Option<FileHandle>, FDTableError> {
+        if fd >= self.limit {
+            return Err(FDTableError::OutOfRange(fd));
+        }
+        self.mark_used(fd);
+        Ok(self.files.insert(fd, f))
+    }
+
+    /// inserts f at the lowest free fd
+    pub fn insert_next(&mut self, f: FileHandle) -> Result<FileDescriptor, FDTableError> {
+        let fd = self.lowest_free()?;
+        self.mark_used(fd);
+        _ = self.files.insert(fd, f);
+        Ok(fd)
+    }
+
+    pub fn remove(&mut self, fd: &FileDescriptor) -> Option<FileHandle> {
+        let f = self.files.remove(fd)?;
+        self.mark_free(*fd);
+        Some(f)
+    }
+
+    pub fn clear(&mut self) {
+        self.files.clear();
+        self.used = [0; N_WORDS];
+        self.full = 0;
+    }
+
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&FileDescriptor, &FileHandle) -> bool,
+    {
+        let (used, full) = (&mut self.used, &mut self.full);
+        self.files.retain(|fd, handle| {
+            let keep = f(fd, handle);
+            if !keep {
+                let (word, bit) = split(*fd);
+                used[word] &= !(1 << bit);
+                *full &= !(1 << word);
+            }
+            keep
+        });
+    }
+
+    pub fn iter(&self) -> btree_map::Iter<'_, FileDescriptor, FileHandle> {
+        self.files.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    pub fn lowest_free(&self) -> Result<FileDescriptor, FDTableError> {
+        let word = (!self.full).trailing_zeros() as usize;
+        if word >= N_WORDS {
+            return Err(FDTableError::Exhausted(self.limit));
+        }
+        let bit = (!self.used[word]).trailing_zeros() as usize;
+        let fd = (word * WORD_BITS + bit) as FileDescriptor;
+        if fd >= self.limit {
+            return Err(FDTableError::Exhausted(self.limit));
+        }
+        Ok(fd)
+    }
+
+    fn mark_used(&mut self, fd: FileDescriptor) {
+        let (word, bit) = split(fd);
+        self.used[word] |= 1 << bit;
+        if self.used[word] == u64::MAX {
+            self.full |= 1 << word;
+        }
+    }
+
+    fn mark_free(&mut self, fd: FileDescriptor) {
+        let (word, bit) = split(fd);
+        self.used[word] &= !(1 << bit);
+        self.full &= !(1 << word);
+    }
+}
+
+impl Default for FDTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> IntoIterator for &'a FDTable {
+    type IntoIter = btree_map::Iter<'a, FileDescriptor, FileHandle>;
+    type Item = (&'a FileDescriptor, &'a FileHandle);
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+fn split(fd: FileDescriptor) -> (usize, usize) {
+    let fd = fd as usize;
+    (fd / WORD_BITS, fd % WORD_BITS)
+}
+
+/// procfs view of the fd tables of all processes, mainly useful for debugging fd leaks
+#[derive(Debug, Default, Clone, Copy, FileRepr)]
+#[file(read)]
+pub struct FDTableView;
+
+impl FDTableView {
+    fn render(&self) -> String {
+        let mut out = String::from("pid\tfd\tflags\tpath\n");
+        for (pid, core) in tls::task_data().processes().read().iter() {
+            let table = core.fd_table.read();
+            for (fd, f) in table.iter() {
+                let flags = if f.close_on_exec() { "cloexec" } else { "-" };
+                let path = f.get_path().map(|p| p.as_str()).unwrap_or("anon");
+                _ = writeln!(out, "{}\t{}\t{}\t{}", pid.0, fd, flags, path);
+            }
+            _ = writeln!(out, "{}\t{}/{} open", pid.0, table.len(), table.limit());
+        }
+        out
+    }
+}
+
+impl Read for FDTableView {
+    fn read(&self, buf: &mut [u8], offset: usize) -> crate::kernel::io::IOResult<usize> {
+        read_rendered(self.render(), buf, offset)
+    }
+}
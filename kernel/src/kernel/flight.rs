@@ -0,0 +1,194 @@
+use core::{
+    fmt::Display,
+    sync::atomic::{AtomicU64, Ordering, fence},
+};
+
+use crate::{
+    arch::interrupt::{handlers::current_tick, lapic},
+    kernel::devices::tty::io::__serial_stub,
+};
+
+/// the number of events kept. Older ones are overwritten
+pub const CAPACITY: usize = 256;
+
+/// an event of the flight recorder, which is always on and dumped on panic
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// the cpu switched from one task to another, either because the first one yielded or because it was preempted
+    Switch {
+        from: u64,
+        to: u64,
+        voluntary: bool,
+    },
+    Syscall {
+        tid: u64,
+        number: u64,
+    },
+    /// any interrupt but the timer, which would push everything else out of the buffer
+    Irq {
+        vector: u8,
+    },
+}
+
+impl Event {
+    fn encode(&self) -> (u64, u64, u64) {
+        match *self {
+            Self::Switch {
+                from,
+                to,
+                voluntary,
+            } => (1 | ((voluntary as u64) << 8), from, to),
+            Self::Syscall { tid, number } => (2, tid, number),
+            Self::Irq { vector } => (3, vector as u64, 0),
+        }
+    }
+
+    fn decode(kind: u64, a: u64, b: u64) -> Option<Self> {
+        match kind & 0xff {
+            1 => Some(Self::Switch {
+                from: a,
+                to: b,
+                voluntary: (kind >> 8) & 1 != 0,
+            }),
+            2 => Some(Self::Syscall { tid: a, number: b }),
+            3 => Some(Self::Irq { vector: a as u8 }),
+            _ => None,
+        }
+    }
+}
+
+/// a recorded event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record {
+    /// the position of the event in the sequence of all recorded events
+    pub seq: u64,
+    pub tick: u64,
+    /// the apic id of the cpu, which recorded the event
+    pub cpu: u32,
+    pub event: Event,
+}
+
+impl Display for Record {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "#{} tick {} cpu {}: ", self.seq, self.tick, self.cpu)?;
+        match self.event {
+            Event::Switch {
+                from,
+                to,
+                voluntary,
+            } => write!(
+                f,
+                "switch {} -> {}{}",
+                from,
+                to,
+                if voluntary { " (yield)" } else { "" }
+            ),
+            Event::Syscall { tid, number } => write!(f, "syscall {} by task {}", number, tid),
+            Event::Irq { vector } => write!(f, "irq {}", vector),
+        }
+    }
+}
+
+/// a slot of the ring. seq is 0 while the slot is written and the sequence number + 1 of its event afterwards
+struct Slot {
+    seq: AtomicU64,
+    tick: AtomicU64,
+    kind: AtomicU64,
+    a: AtomicU64,
+    b: AtomicU64,
+}
+
+impl Slot {
+    const fn new() -> Self {
+        Self {
+            seq: AtomicU64::new(0),
+            tick: AtomicU64::new(0),
+            kind: AtomicU64::new(0),
+            a: AtomicU64::new(0),
+            b: AtomicU64::new(0),
+        }
+    }
+}
+
+static HEAD: AtomicU64 = AtomicU64::new(0);
+static SLOTS: [Slot; CAPACITY] = [const { Slot::new() }; CAPACITY];
+
+/// records event. Called from interrupt handlers and the scheduler, thus it neither blocks nor allocates
+pub fn record(event: Event) {
+    let (kind, a, b) = event.encode();
+    let cpu = lapic::id().unwrap_or_default() as u64;
+    let seq = HEAD.fetch_add(1, Ordering::Relaxed);
+    let slot = &SLOTS[seq as usize % CAPACITY];
+    slot.seq.store(0, Ordering::Relaxed);
+    fence(Ordering::Release);
+    slot.tick.store(current_tick(), Ordering::Relaxed);
+    slot.kind.store(kind | (cpu << 32), Ordering::Relaxed);
+    slot.a.store(a, Ordering::Relaxed);
+    slot.b.store(b, Ordering::Relaxed);
+    slot.seq.store(seq + 1, Ordering::Release);
+}
+
+/// the recorded events, oldest first. Events, which are overwritten while they are read, are skipped
+pub fn records() -> impl Iterator<Item = Record> {
+    let head = HEAD.load(Ordering::Acquire);
+    (head.saturating_sub(CAPACITY as u64)..head).filter_map(|seq| {
+        let slot = &SLOTS[seq as usize % CAPACITY];
+        let before = slot.seq.load(Ordering::Acquire);
+        let tick = slot.tick.load(Ordering::Relaxed);
+        let kind = slot.kind.load(Ordering::Relaxed);
+        let a = slot.a.load(Ordering::Relaxed);
+        let b = slot.b.load(Ordering::Relaxed);
+        fence(Ordering::Acquire);
+        let after = slot.seq.load(Ordering::Relaxed);
+        if before != seq + 1 || after != before {
+            return None;
+        }
+        Some(Record {
+            seq,
+            tick,
+            cpu: (kind >> 32) as u32,
+            event: Event::decode(kind & 0xffff_ffff, a, b)?,
+        })
+    })
+}
+
+/// prints all recorded events to serial. Used on panic, thus it does not allocate and bypasses the log rate limits
+pub fn dump() {
+    __serial_stub(format_args!(
+        "flight recorder, last {} events:\n",
+        HEAD.load(Ordering::Relaxed).min(CAPACITY as u64)
+    ));
+    for record in records() {
+        __serial_stub(format_args!("  {}\n", record));
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    #[kernel_test]
+    fn records_in_order() {
+        let events = [
+            Event::Switch {
+                from: 7,
+                to: 9,
+                voluntary: true,
+            },
+            Event::Syscall { tid: 9, number: 42 },
+            Event::Irq { vector: 0xfe },
+        ];
+        for event in events {
+            record(event);
+        }
+        // other cpus and interrupts may record in between
+        let mut found = records().filter(|record| events.contains(&record.event));
+        let seqs: [u64; 3] =
+            core::array::from_fn(|i| found.find(|record| record.event == events[i]).unwrap().seq);
+        assert!(seqs.is_sorted());
+        assert!(records().count() <= CAPACITY);
+        assert!(records().is_sorted_by_key(|record| record.seq));
+    }
+}
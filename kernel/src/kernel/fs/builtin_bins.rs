@@ -4,7 +4,7 @@
 
 use alloc::{boxed::Box, str, vec::Vec};
 
-use os_macros::with_default_args;
+use os_macros::{init_task, with_default_args};
 use tinyos_abi::{
     consts::STDIN_FILENO,
     flags::{NodePermissions, OpenOptions, UnlinkOptions},
@@ -14,12 +14,12 @@ use tinyos_abi::{
 use crate::{
     drivers::wait_manager::{add_queue, remove_queue, wait_self},
     eprintln,
-    exit_qemu,
     kernel::{
         fd::FileRepr,
         fs::{self, Path, PathBuf},
         init::INCLUDED_BINS,
         io::Write,
+        shutdown,
         threading::{
             schedule::current_task,
             task::{Arg, TaskRepr},
@@ -42,6 +42,7 @@ pub trait Executable {
     fn init();
 }
 
+#[init_task(stage = "fs", order = 30)]
 pub fn init() {
     ShutDown::init();
     Serial::init();
@@ -87,8 +88,7 @@ impl Executable for ShutDown {
 
     fn execute(argv: Option<Box<[u8]>>, envp: Option<Box<[u8]>>) -> usize {
         println!("shutting down system...");
-        exit_qemu(crate::QemuExitCode::Success);
-        unreachable!()
+        shutdown::poweroff(crate::QemuExitCode::Success)
     }
 }
 
@@ -0,0 +1,35 @@
+use alloc::format;
+
+use crate::kernel::{
+    fd::FileBuilder,
+    fs::{DEVFS_PATH, FS, FSResult, OpenOptions, PROCFS_PATH, Path, PathBuf, UnlinkOptions, fs},
+};
+
+/// devfs does not store any nodes itself, it is a view into the dev subtree of procfs.
+/// A device registered at /dev/x via create_device_file! is thus reachable at both /proc/dev/x and /dev/x
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DevFS;
+
+impl DevFS {
+    pub fn new() -> Self {
+        Self
+    }
+
+    fn backing_path(path: &Path) -> PathBuf {
+        PathBuf::from(format!("{}{}{}", PROCFS_PATH, DEVFS_PATH, path.as_str()))
+    }
+}
+
+impl FS for DevFS {
+    fn open(&self, path: &Path, options: OpenOptions) -> FSResult<FileBuilder> {
+        fs().open(&Self::backing_path(path), options)
+    }
+
+    fn unlink(&self, path: &Path, options: UnlinkOptions) -> FSResult<FileBuilder> {
+        fs().unlink(&Self::backing_path(path), options)
+    }
+
+    fn flush(&self, path: &Path) -> FSResult<()> {
+        fs().flush(&Self::backing_path(path))
+    }
+}
@@ -1,8 +1,12 @@
-use alloc::{string::String, sync::Arc};
+use alloc::{
+    string::String,
+    sync::Arc,
+    vec::{self, Vec},
+};
 
 use crate::kernel::{
     fd::File,
-    fs::{FS, FSResult, OpenOptions, Path, PathBuf, UnlinkOptions, fs, vfs},
+    fs::{FS, FSResult, MountOptions, OpenOptions, Path, PathBuf, UnlinkOptions, fs, vfs},
     io::{Read, Write},
 };
 
@@ -19,8 +23,48 @@ pub fn lsdir(path: &Path) -> FSResult<String> {
     Ok(buf)
 }
 
-pub fn mount(path: PathBuf, fs: Arc<dyn FS>) -> FSResult<()> {
-    vfs::get().mount(path, fs)
+/// iterator over the entries of a directory, yielding the full path of every entry
+#[derive(Debug)]
+pub struct ReadDir {
+    dir: PathBuf,
+    names: vec::IntoIter<String>,
+}
+
+impl Iterator for ReadDir {
+    type Item = PathBuf;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let name = self.names.next()?;
+        let mut path = self.dir.clone();
+        path.push(name.as_str());
+        Some(path)
+    }
+}
+
+/// lists the entries of the directory at path.
+/// Entries are snapshotted at the time of the call, later changes to the directory are not reflected
+pub fn read_dir(path: &Path) -> FSResult<ReadDir> {
+    let listing = lsdir(path)?;
+    let names = listing
+        .split('\t')
+        // the vfs root lists its mount points with their leading separator
+        .map(|name| name.trim_start_matches('/'))
+        .filter(|name| !name.is_empty())
+        .map(String::from)
+        .collect::<Vec<_>>();
+    Ok(ReadDir {
+        dir: path.trim_trailing_sep().to_owned(),
+        names: names.into_iter(),
+    })
+}
+
+pub fn mount(path: PathBuf, fs: Arc<dyn FS>, options: MountOptions) -> FSResult<()> {
+    vfs::get().mount(path, fs, options)
+}
+
+/// the options of the mount, which path lies on
+pub fn mount_options_at(path: &Path) -> FSResult<MountOptions> {
+    vfs::get().options_at(path)
 }
 
 pub fn unmount(path: &Path) -> FSResult<()> {
@@ -37,6 +81,22 @@ pub fn close(path: &Path, file: File) -> FSResult<()> {
     Ok(())
 }
 
+pub fn get_xattr(path: &Path, name: &str) -> FSResult<Vec<u8>> {
+    fs().get_xattr(path, name)
+}
+
+pub fn set_xattr(path: &Path, name: &str, value: &[u8]) -> FSResult<()> {
+    fs().set_xattr(path, name, value)
+}
+
+pub fn remove_xattr(path: &Path, name: &str) -> FSResult<()> {
+    fs().remove_xattr(path, name)
+}
+
+pub fn list_xattr(path: &Path) -> FSResult<Vec<String>> {
+    fs().list_xattr(path)
+}
+
 pub fn rm(path: &Path, options: UnlinkOptions) -> FSResult<()> {
     fs().unlink(path, options)?;
     Ok(())
@@ -0,0 +1,194 @@
+use alloc::vec::Vec;
+
+use crate::kernel::fs::{
+    Component,
+    FSError,
+    FSErrorKind,
+    FSResult,
+    OpenOptions,
+    Path,
+    PathBuf,
+    open,
+    read_dir,
+};
+
+/// returns all existing paths matching pattern.
+/// Each component of pattern may contain the wildcards `*` (any sequence), `?` (any single char) and `[...]` (a char class, `[!...]` negates it).
+/// Wildcards never match a separator, and a leading '.' of an entry must be matched explicitly.
+/// Paths are returned in the order of their directories, which is not sorted.
+pub fn glob(pattern: &Path) -> FSResult<Vec<PathBuf>> {
+    if pattern.is_relative() {
+        return Err(FSError::with_message(
+            FSErrorKind::InvalidPath,
+            "glob patterns must be absolute",
+        ));
+    }
+    let mut candidates: Vec<PathBuf> = Vec::new();
+
+    for component in pattern.components() {
+        match component {
+            Component::RootDir => candidates.push(PathBuf::from("/")),
+            Component::Normal(part) if is_pattern(part) => {
+                candidates = candidates
+                    .iter()
+                    // entries which are not dirs or cannot be listed simply do not match
+                    .filter_map(|dir| read_dir(dir).ok())
+                    .flatten()
+                    .filter(|entry| glob_match(part, entry.file()))
+                    .collect();
+            }
+            component => candidates
+                .iter_mut()
+                .for_each(|path| path.push(component.as_str())),
+        }
+        if candidates.is_empty() {
+            break;
+        }
+    }
+
+    candidates.retain(|path| open(path, OpenOptions::empty()).is_ok());
+    if pattern.has_trailing_sep() {
+        candidates.retain(|path| read_dir(path).is_ok());
+    }
+    Ok(candidates)
+}
+
+fn is_pattern(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+/// matches a single path component against pattern
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    if name.starts_with('.') && !pattern.starts_with('.') {
+        return false;
+    }
+    let pattern = pattern.as_bytes();
+    let name = name.as_bytes();
+    let (mut p, mut n) = (0, 0);
+    // position of the last '*' in pattern and the position in name it currently consumes up to
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        let step = match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, n));
+                p += 1;
+                continue;
+            }
+            Some(b'?') => Some(1),
+            Some(b'[') => match_class(&pattern[p..], name[n]),
+            Some(c) => (*c == name[n]).then_some(1),
+            None => None,
+        };
+        match step {
+            Some(len) => {
+                p += len;
+                n += 1;
+            }
+            None => {
+                // let the last '*' consume one more char and retry
+                let Some((star, consumed)) = backtrack else {
+                    return false;
+                };
+                backtrack = Some((star, consumed + 1));
+                p = star + 1;
+                n = consumed + 1;
+            }
+        }
+    }
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+/// matches c against the class at the start of pattern. Returns the length of the class in pattern if c matches.
+/// An unterminated class is treated as a literal '['
+fn match_class(pattern: &[u8], c: u8) -> Option<usize> {
+    debug_assert_eq!(pattern[0], b'[');
+    let mut i = 1;
+    let negated = matches!(pattern.get(i), Some(b'!' | b'^'));
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        match pattern.get(i) {
+            None => return (c == b'[').then_some(1),
+            // a ']' directly after the opening bracket is a literal
+            Some(b']') if !first => break,
+            Some(&lo) => {
+                if pattern.get(i + 1) == Some(&b'-')
+                    && let Some(&hi) = pattern.get(i + 2)
+                    && hi != b']'
+                {
+                    matched |= (lo..=hi).contains(&c);
+                    i += 3;
+                } else {
+                    matched |= lo == c;
+                    i += 1;
+                }
+            }
+        }
+        first = false;
+    }
+    (matched != negated).then_some(i + 1)
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use alloc::sync::Arc;
+
+    use os_macros::kernel_test;
+
+    use super::*;
+    use crate::kernel::fs::{FS, MountOptions, mount, ramfs::RamFS, unmount};
+
+    #[kernel_test]
+    fn glob_match_() {
+        assert!(glob_match("*.elf", "init.elf"));
+        assert!(!glob_match("*.elf", "init.elf.bak"));
+        assert!(glob_match("a*b*c", "aXXbYYbc"));
+        assert!(glob_match("?at", "cat"));
+        assert!(!glob_match("?at", "at"));
+        assert!(glob_match("[bc]at", "bat"));
+        assert!(glob_match("[a-c]at", "cat"));
+        assert!(!glob_match("[!a-c]at", "cat"));
+        assert!(glob_match("[]]", "]"));
+        assert!(glob_match("[", "["));
+        assert!(!glob_match("*", ".hidden"));
+        assert!(glob_match(".*", ".hidden"));
+    }
+
+    #[kernel_test]
+    fn glob_() {
+        mount(
+            Path::new("/globfs").into(),
+            Arc::new(RamFS::new()) as Arc<dyn FS>,
+            MountOptions::empty(),
+        )
+        .unwrap();
+        for path in [
+            "/globfs/bin/a.elf",
+            "/globfs/bin/b.elf",
+            "/globfs/bin/c.txt",
+            "/globfs/etc/a.elf",
+        ] {
+            open(Path::new(path), OpenOptions::CREATE_ALL).unwrap();
+        }
+
+        let mut found = glob(Path::new("/globfs/*/a.elf")).unwrap();
+        found.sort();
+        assert_eq!(
+            found,
+            [
+                PathBuf::from("/globfs/bin/a.elf"),
+                PathBuf::from("/globfs/etc/a.elf")
+            ]
+        );
+        assert_eq!(glob(Path::new("/globfs/bin/?.elf")).unwrap().len(), 2);
+        assert_eq!(glob(Path::new("/glob*/bin/c.txt")).unwrap().len(), 1);
+        assert!(glob(Path::new("/globfs/bin/*.rs")).unwrap().is_empty());
+        assert!(glob(Path::new("/globfs/nope/a.elf")).unwrap().is_empty());
+
+        unmount(Path::new("/globfs")).unwrap();
+    }
+}
@@ -1,45 +1,70 @@
 pub mod builtin_bins;
+mod devfs;
+mod glob;
 mod path;
 pub mod procfs;
-mod ramfs;
-mod vfs;
+pub(crate) mod ramfs;
+pub(crate) mod vfs;
 
-use alloc::{boxed::Box, sync::Arc};
+use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
 use core::{
     error,
     fmt::{Debug, Write},
 };
 
 use bitflags::bitflags;
+use conquer_once::spin::OnceCell;
 pub use path::*;
 use thiserror::Error;
 use tinyos_abi::types::SysErrCode;
 mod fs_util;
 pub use fs_util::*;
+pub use glob::*;
+use os_macros::init_task;
 pub use tinyos_abi::flags::{OpenOptions, UnlinkOptions};
+pub use vfs::MountOptions;
 
 use crate::kernel::fd::{File, FileBuilder};
 
 pub const PROCFS_PATH: &str = "/proc";
 pub const RAMFS_PATH: &str = "/ram";
+pub const DEVFS_PATH: &str = "/dev";
 
+static RAMFS: OnceCell<Arc<ramfs::RamFS>> = OnceCell::uninit();
+
+#[init_task(stage = "fs")]
 pub fn init() {
     procfs::init();
     vfs::init();
     mount(
         Path::new(RAMFS_PATH).into(),
-        Arc::new(ramfs::RamFS::new()) as Arc<dyn FS>,
+        ramfs().clone() as Arc<dyn FS>,
+        MountOptions::empty(),
     )
     .expect("failed to mount ramfs");
+    // neither holds anything to execute
     mount(
         Path::new(PROCFS_PATH).into(),
         Arc::new(procfs::ProcFS::new()) as Arc<dyn FS>,
+        MountOptions::NO_EXEC | MountOptions::NO_SUID,
     )
     .expect("failed to mount procfs");
+    mount(
+        Path::new(DEVFS_PATH).into(),
+        Arc::new(devfs::DevFS::new()) as Arc<dyn FS>,
+        MountOptions::NO_EXEC | MountOptions::NO_SUID,
+    )
+    .expect("failed to mount devfs");
 }
 
-pub fn fs() -> &'static impl FS {
-    vfs::get().as_ref()
+/// the RamFS mounted at RAMFS_PATH, for snapshots of it
+pub(crate) fn ramfs() -> &'static Arc<ramfs::RamFS> {
+    RAMFS.get_or_init(|| Arc::new(ramfs::RamFS::new()))
+}
+
+/// the vfs of the mount namespace of the current task
+pub fn fs() -> Arc<impl FS> {
+    vfs::get()
 }
 
 pub type FSResult<T> = Result<T, FSError>;
@@ -48,6 +73,25 @@ pub trait FS: Debug + Send + Sync {
     fn open(&self, path: &Path, options: OpenOptions) -> FSResult<FileBuilder>;
     fn unlink(&self, path: &Path, options: UnlinkOptions) -> FSResult<FileBuilder>;
     fn flush(&self, path: &Path) -> FSResult<()>;
+
+    /// the value of the extended attribute name of the node at path
+    fn get_xattr(&self, path: &Path, name: &str) -> FSResult<Vec<u8>> {
+        Err(FSError::simple(FSErrorKind::NotSupported))
+    }
+
+    /// sets the extended attribute name of the node at path, replacing any previous value
+    fn set_xattr(&self, path: &Path, name: &str, value: &[u8]) -> FSResult<()> {
+        Err(FSError::simple(FSErrorKind::NotSupported))
+    }
+
+    fn remove_xattr(&self, path: &Path, name: &str) -> FSResult<()> {
+        Err(FSError::simple(FSErrorKind::NotSupported))
+    }
+
+    /// the names of all extended attributes of the node at path
+    fn list_xattr(&self, path: &Path) -> FSResult<Vec<String>> {
+        Err(FSError::simple(FSErrorKind::NotSupported))
+    }
 }
 
 #[derive(Error, Debug)]
@@ -84,22 +128,25 @@ impl FSError {
     }
 }
 
-impl Into<SysErrCode> for FSError {
-    fn into(self) -> SysErrCode {
-        match self.kind() {
-            FSErrorKind::NotFound => SysErrCode::NoFile,
-            FSErrorKind::PermissionDenied => SysErrCode::AccessDenied,
-            FSErrorKind::AlreadyExists => SysErrCode::FileExists,
-            FSErrorKind::WouldBlock => SysErrCode::WouldBlock,
-            FSErrorKind::NotADir => SysErrCode::NoFile,
-            FSErrorKind::IsADir => SysErrCode::NoFile,
-            FSErrorKind::StorageFull => SysErrCode::DiskFull,
-            FSErrorKind::TimedOut => SysErrCode::TimerExp,
-            FSErrorKind::FileTooLarge => SysErrCode::FileTooBig,
-            FSErrorKind::OOM => SysErrCode::OOM,
-            FSErrorKind::InvalidFilename => SysErrCode::InvalidArg,
-            FSErrorKind::InvalidPath => SysErrCode::InvalidArg,
-            _ => SysErrCode::IO,
+impl From<FSError> for SysErrCode {
+    fn from(err: FSError) -> Self {
+        match err.kind() {
+            FSErrorKind::NotFound => Self::NoFile,
+            FSErrorKind::PermissionDenied => Self::AccessDenied,
+            FSErrorKind::AlreadyExists => Self::FileExists,
+            FSErrorKind::WouldBlock => Self::WouldBlock,
+            FSErrorKind::NotADir => Self::NotADir,
+            FSErrorKind::IsADir => Self::IsADir,
+            FSErrorKind::DirNotEmpty => Self::DirNotEmpty,
+            FSErrorKind::TimedOut => Self::TimerExp,
+            FSErrorKind::StorageFull => Self::DiskFull,
+            FSErrorKind::FileTooLarge => Self::FileTooBig,
+            FSErrorKind::Deadlock => Self::Deadlock,
+            FSErrorKind::InvalidFilename | FSErrorKind::InvalidPath => Self::InvalidArg,
+            FSErrorKind::OOM => Self::OOM,
+            FSErrorKind::InProgress => Self::InProgress,
+            FSErrorKind::NotSupported => Self::NotSupported,
+            FSErrorKind::UnexpectedEOF | FSErrorKind::EOF | FSErrorKind::Other => Self::IO,
         }
     }
 }
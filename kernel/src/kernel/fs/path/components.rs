@@ -0,0 +1,78 @@
+use super::{CUR_DIR, PARENT_DIR, PATH_SEP, Path};
+
+/// a single component of a Path, as yielded by Path::components
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Component<'a> {
+    /// the leading '/' of an absolute path
+    RootDir,
+    /// a leading '.' of a relative path. '.' anywhere else is skipped
+    CurDir,
+    /// '..'. This is NOT resolved, as that requires knowledge about mounts and links
+    ParentDir,
+    Normal(&'a str),
+}
+
+impl<'a> Component<'a> {
+    pub fn as_str(&self) -> &'a str {
+        match self {
+            Self::RootDir => "/",
+            Self::CurDir => CUR_DIR,
+            Self::ParentDir => PARENT_DIR,
+            Self::Normal(s) => s,
+        }
+    }
+}
+
+impl AsRef<Path> for Component<'_> {
+    fn as_ref(&self) -> &Path {
+        Path::new(self.as_str())
+    }
+}
+
+/// iterator over the Components of a Path.
+/// Repeated separators and trailing separators are ignored, such that /foo//bar/ and /foo/bar yield the same components
+#[derive(Debug, Clone)]
+pub struct Components<'a> {
+    rest: &'a str,
+    at_start: bool,
+}
+
+impl<'a> Components<'a> {
+    pub(super) fn new(path: &'a Path) -> Self {
+        Self {
+            rest: path.as_str(),
+            at_start: true,
+        }
+    }
+
+    /// the remaining, not yet yielded part of the path
+    pub fn as_path(&self) -> &'a Path {
+        Path::new(self.rest)
+    }
+}
+
+impl<'a> Iterator for Components<'a> {
+    type Item = Component<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let at_start = core::mem::replace(&mut self.at_start, false);
+        if at_start && self.rest.starts_with(PATH_SEP) {
+            self.rest = self.rest.trim_start_matches(PATH_SEP);
+            return Some(Component::RootDir);
+        }
+        loop {
+            self.rest = self.rest.trim_start_matches(PATH_SEP);
+            if self.rest.is_empty() {
+                return None;
+            }
+            let (segment, rest) = self.rest.split_once(PATH_SEP).unwrap_or((self.rest, ""));
+            self.rest = rest;
+            match segment {
+                CUR_DIR if at_start => return Some(Component::CurDir),
+                CUR_DIR => continue,
+                PARENT_DIR => return Some(Component::ParentDir),
+                normal => return Some(Component::Normal(normal)),
+            }
+        }
+    }
+}
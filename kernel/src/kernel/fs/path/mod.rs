@@ -1,14 +1,16 @@
 use alloc::{borrow::ToOwned, string::String};
 use core::{borrow::Borrow, fmt::Display, ops::Deref};
 
+mod components;
+pub use components::*;
+
 // TODO: migrate this to libtinyos and use as dependancy
 
 const PATH_SEP: char = '/';
 const EXT_SEP: char = '.';
-// currently root dir is an empty str, due to path being represented as &str, without handling of first SEP
-// A Path /foo will yield ROOT_DIR as its parent
-// TODO: add Components, which correctly parse ROOT + Prefix, ...
-const ROOT_DIR: &str = "";
+const ROOT_DIR: &str = "/";
+const CUR_DIR: &str = ".";
+const PARENT_DIR: &str = "..";
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct PathBuf {
@@ -27,25 +29,24 @@ impl PathBuf {
         } else {
             Self::new()
         };
-        let segments = self
-            .inner
-            .split(PATH_SEP)
-            .filter(|&segment| !segment.is_empty() && segment != ".");
-        for segment in segments {
-            if segment == ".." {
-                root.up();
-            } else {
-                root.push(&segment);
+        for component in self.components() {
+            match component {
+                Component::RootDir | Component::CurDir => {}
+                Component::ParentDir => root.up(),
+                Component::Normal(segment) => root.push(segment),
             }
         }
         *self = root;
     }
 
+    /// truncates self to its parent. Does nothing if self has no parent
     pub fn up(&mut self) {
-        let Some((new, _)) = self.inner.rsplit_once(PATH_SEP) else {
+        let Some(parent) = self.parent() else {
             return;
         };
-        self.inner.truncate(new.len());
+        // parent is always a prefix of self
+        let len = parent.as_str().len();
+        self.inner.truncate(len);
     }
 
     /// appends path to self
@@ -56,7 +57,9 @@ impl PathBuf {
             self.clear();
             self.inner.push_str(path.as_ref().as_str());
         } else {
-            self.inner.push(PATH_SEP);
+            if !self.inner.ends_with(PATH_SEP) {
+                self.inner.push(PATH_SEP);
+            }
             self.inner.push_str(path.as_ref().as_str());
         }
     }
@@ -170,43 +173,73 @@ impl Path {
         &self.inner
     }
 
-    pub fn traverse(&self) -> impl Iterator<Item = &str> {
-        // do not filter empty segments, as they are real (ROOT_DIR is empty)
-        self.inner.split(PATH_SEP)
+    pub fn components(&self) -> Components<'_> {
+        Components::new(self)
     }
 
     pub fn is_relative(&self) -> bool {
         // an absolute Path must start with '/'
-        // as currently "" == ROOT_DIR, ROOT_DIR is relative
-        self.inner.chars().next().is_none_or(|c| c != '/')
+        !self.inner.starts_with(PATH_SEP)
+    }
+
+    /// returns true if self ends with a separator, ie it must name a directory
+    pub fn has_trailing_sep(&self) -> bool {
+        self.inner.len() > 1 && self.inner.ends_with(PATH_SEP)
     }
 
+    /// strips all trailing separators, except for the root dir
+    pub fn trim_trailing_sep(&self) -> &Path {
+        let trimmed = self.inner.trim_end_matches(PATH_SEP);
+        if trimmed.is_empty() && !self.is_relative() {
+            Path::new(&self.inner[..ROOT_DIR.len()])
+        } else {
+            Path::new(trimmed)
+        }
+    }
+
+    /// returns self without its last component. Trailing separators are ignored, ie /foo/bar/ has the parent /foo.
+    /// The root dir and the empty path have no parent
     pub fn parent(&self) -> Option<&Path> {
-        self.inner.rsplit_once(PATH_SEP).map(|(s, _)| Path::new(s))
+        let trimmed = self.inner.trim_end_matches(PATH_SEP);
+        if trimmed.is_empty() {
+            return None;
+        }
+        let Some((parent, _)) = trimmed.rsplit_once(PATH_SEP) else {
+            return Some(Path::new(""));
+        };
+        let parent = parent.trim_end_matches(PATH_SEP);
+        if parent.is_empty() {
+            // we split at the leading separator
+            Some(Path::new(&self.inner[..ROOT_DIR.len()]))
+        } else {
+            Some(Path::new(parent))
+        }
     }
 
     pub fn extension(&self) -> &str {
-        let Some((_, e)) = self.inner.rsplit_once(EXT_SEP) else {
+        let Some((_, e)) = self.file().rsplit_once(EXT_SEP) else {
             return "";
         };
         e
     }
 
     pub fn file_prefix(&self) -> &str {
-        let Some((_, f)) = self.inner.rsplit_once(PATH_SEP) else {
-            return "";
-        };
+        let f = self.file();
         let Some((f, _)) = f.split_once(EXT_SEP) else {
             return f;
         };
         f
     }
 
+    /// returns the last component of self, if it is a normal component, else "".
+    /// Trailing separators are ignored, ie /foo/bar/ yields bar
     pub fn file(&self) -> &str {
-        let Some((_, f)) = self.inner.rsplit_once(PATH_SEP) else {
-            return "";
-        };
-        f
+        let trimmed = self.inner.trim_end_matches(PATH_SEP);
+        let f = trimmed.rsplit_once(PATH_SEP).map_or(trimmed, |(_, f)| f);
+        match f {
+            CUR_DIR | PARENT_DIR => "",
+            f => f,
+        }
     }
 
     pub fn ancestors(&self) -> Ancestors<'_> {
@@ -295,11 +328,11 @@ mod tests {
         path.push("bar/baz");
         assert!(!path.is_relative());
 
-        let mut components = path.traverse();
-        assert_eq!(components.next(), Some(""));
-        assert_eq!(components.next(), Some("foo"));
-        assert_eq!(components.next(), Some("bar"));
-        assert_eq!(components.next(), Some("baz"));
+        let mut components = path.components();
+        assert_eq!(components.next(), Some(Component::RootDir));
+        assert_eq!(components.next(), Some(Component::Normal("foo")));
+        assert_eq!(components.next(), Some(Component::Normal("bar")));
+        assert_eq!(components.next(), Some(Component::Normal("baz")));
         assert!(components.next().is_none());
         drop(components);
 
@@ -326,4 +359,39 @@ mod tests {
         assert_eq!(path.file(), "foo.bar");
         assert_eq!(path.parent().unwrap().file(), "foo");
     }
+
+    #[kernel_test]
+    fn path_edge_cases() {
+        let components: alloc::vec::Vec<_> =
+            Path::new("./foo//./bar/../baz/").components().collect();
+        assert_eq!(
+            components,
+            [
+                Component::CurDir,
+                Component::Normal("foo"),
+                Component::Normal("bar"),
+                Component::ParentDir,
+                Component::Normal("baz"),
+            ]
+        );
+        assert_eq!(
+            Path::new("/").components().collect::<alloc::vec::Vec<_>>(),
+            [Component::RootDir]
+        );
+        assert!(Path::new("").components().next().is_none());
+
+        let dir = Path::new("/foo/bar/");
+        assert!(dir.has_trailing_sep());
+        assert_eq!(dir.file(), "bar");
+        assert_eq!(dir.parent(), Some(Path::new("/foo")));
+        assert_eq!(dir.trim_trailing_sep(), Path::new("/foo/bar"));
+        assert_eq!(Path::new("/foo").parent(), Some(Path::new(ROOT_DIR)));
+        assert_eq!(Path::new("//").trim_trailing_sep(), Path::new(ROOT_DIR));
+        assert!(Path::new(ROOT_DIR).parent().is_none());
+        assert!(Path::new("").parent().is_none());
+        assert_eq!(Path::new("foo").parent(), Some(Path::new("")));
+        assert_eq!(Path::new("foo").file(), "foo");
+        assert_eq!(Path::new("/foo/..").file(), "");
+        assert_eq!(Path::new("/foo.d/bar").extension(), "");
+    }
 }
@@ -14,8 +14,18 @@ use tinyos_abi::{flags::NodeType, types::FStat};
 
 use crate::{
     kernel::{
-        fd::{File, FileBuilder, FileRepr, IOCapable, new_fstat},
-        fs::{FS, FSError, FSErrorKind, FSResult, OpenOptions, Path, UnlinkOptions},
+        fd::{File, FileBuilder, FileRepr, IOCapable, NodeTimes, new_fstat},
+        fs::{
+            Component,
+            FS,
+            FSError,
+            FSErrorKind,
+            FSResult,
+            OpenOptions,
+            Path,
+            PathBuf,
+            UnlinkOptions,
+        },
         io::{Read, Write},
     },
     serial_println,
@@ -35,11 +45,15 @@ type ProcFilePtr = Arc<ProcFile>;
 #[derive(Debug)]
 struct ProcFile {
     node: ProcNode,
+    times: NodeTimes,
 }
 
 impl ProcFile {
     pub fn new(node: ProcNode) -> Self {
-        Self { node }
+        Self {
+            node,
+            times: NodeTimes::new(),
+        }
     }
 
     fn is_dir(&self) -> bool {
@@ -57,9 +71,19 @@ impl FileRepr for ProcFile {
             ProcNode::Dir(_) => stat.node_type = NodeType::DIR,
             ProcNode::File(_) => stat.node_type = NodeType::FILE,
         }
+        self.times.apply(&mut stat);
         stat
     }
 
+    fn set_times(
+        &self,
+        access: Option<u64>,
+        modify: Option<u64>,
+    ) -> crate::kernel::io::IOResult<()> {
+        self.times.set(access, modify);
+        Ok(())
+    }
+
     fn as_raw_parts(&self) -> (*mut u8, usize) {
         match &self.node {
             ProcNode::File(f) => f.as_raw_parts(),
@@ -72,6 +96,7 @@ impl IOCapable for ProcFile {}
 
 impl Read for ProcFile {
     fn read(&self, buf: &mut [u8], offset: usize) -> crate::kernel::io::IOResult<usize> {
+        self.times.accessed();
         match &self.node {
             ProcNode::Dir(d) => Ok(read_dir(d, buf)), // the Dir d is lazy and will not be updated by this operation. Call flush() prior to ls TODO: automatic update of d (need access to path)
             ProcNode::File(f) => f.read(buf, offset),
@@ -85,6 +110,7 @@ impl Read for ProcFile {
     ) -> crate::kernel::io::IOResult<usize> {
         match &self.node {
             ProcNode::Dir(d) => {
+                self.times.accessed();
                 let res = format!("{}", d);
                 let bytes = res.as_bytes();
                 buf.extend_from_slice(bytes);
@@ -111,7 +137,11 @@ impl Write for ProcFile {
     fn write(&self, buf: &[u8], offset: usize) -> crate::kernel::io::IOResult<usize> {
         match &self.node {
             ProcNode::Dir(_) => Err(FSError::simple(FSErrorKind::NotSupported)),
-            ProcNode::File(f) => f.write(buf, offset),
+            ProcNode::File(f) => {
+                let written = f.write(buf, offset)?;
+                self.times.modified();
+                Ok(written)
+            }
         }
     }
 }
@@ -366,13 +396,22 @@ impl ProcFS {
 
     fn traverse(&self, path: &Path, create: bool) -> FSResult<ProcFilePtr> {
         let mut current_dir = self.root.clone();
+        let mut current_path = PathBuf::from("/");
         // skip last (target) component
         let Some(parent) = path.parent() else {
             return Ok(current_dir);
         };
 
-        // skip root dir
-        for component in parent.traverse().skip(1) {
+        for component in parent.components() {
+            let component = match component {
+                Component::RootDir | Component::CurDir => continue,
+                // .. must be resolved before the path reaches a fs
+                Component::ParentDir => return Err(FSError::simple(FSErrorKind::InvalidPath)),
+                Component::Normal(component) => component,
+            };
+            current_path.push(component);
+            // directories of registered devices are materialized lazily, such that devices may be registered without opening them
+            let create = create || registry().contains_dir(&current_path);
             let child = if create {
                 with_dir(current_dir.clone(), |dir| {
                     dir.ensure_entry(component.to_string(), proc_dir)
@@ -386,7 +425,7 @@ impl ProcFS {
             current_dir = child;
         }
 
-        if create {
+        if create || registry().contains_dir(path) {
             with_dir(current_dir, |dir| {
                 dir.ensure_entry(path.file().into(), proc_dir)
             })
@@ -413,12 +452,13 @@ impl FS for ProcFS {
             return Err(FSError::simple(FSErrorKind::InvalidPath));
         };
 
-        if path.as_str().ends_with('/') {
-            Ok(as_file(parent).with_perms(options))
-        } else if options.contains(OpenOptions::CREATE_DIR) {
+        if options.contains(OpenOptions::CREATE_DIR) {
             Ok(as_file(parent_dir.ensure_entry(path.file().into(), proc_dir)).with_perms(options))
         } else {
-            let entry = parent_dir.get_or_update(path, path.file())?;
+            let entry = parent_dir.get_or_update(path.trim_trailing_sep(), path.file())?;
+            if path.has_trailing_sep() && !entry.is_dir() {
+                return Err(FSError::simple(FSErrorKind::InvalidPath));
+            }
             Ok(as_file(entry).with_perms(options))
         }
     }
@@ -428,8 +468,7 @@ impl FS for ProcFS {
         path: &super::Path,
         options: super::UnlinkOptions,
     ) -> super::FSResult<crate::kernel::fd::FileBuilder> {
-        let parent = if path.as_str().ends_with('/')
-            && let Some(dir) = path.parent()
+        let parent = if path.has_trailing_sep()
             && let Some(parent) = path.parent()
         {
             if options.contains(UnlinkOptions::RECURSIVE) {
@@ -534,7 +573,7 @@ impl Write for ProcFS {
 mod tests {
     use alloc::{format, vec};
 
-    use os_macros::kernel_test;
+    use os_macros::{FileRepr, kernel_test};
 
     use super::*;
 
@@ -596,21 +635,77 @@ mod tests {
     }
 
     #[kernel_test]
-    fn test_rw() {
+    fn lazy_device_dirs() {
         let procfs = ProcFS::new();
+        registry()
+            .register(NULL_DEVICE, Path::new("/lazy/dir/null").into())
+            .unwrap();
+        // the parent dirs were never created explicitly
+        assert!(
+            procfs
+                .open(Path::new("/lazy/dir/null"), OpenOptions::default())
+                .is_ok()
+        );
+        assert!(
+            procfs
+                .open(Path::new("/lazy/dir/"), OpenOptions::default())
+                .is_ok()
+        );
+        assert!(
+            procfs
+                .open(Path::new("/lazy/other/null"), OpenOptions::default())
+                .is_err()
+        );
+        _ = registry().deregister(Path::new("/lazy/dir/null"));
+    }
 
-        let registry = DEVICE_REGISTRY.get_or_init(|| DeviceRegistry::new());
+    #[kernel_test]
+    fn detached_device() {
+        let procfs = ProcFS::new();
 
-        #[derive(Debug)]
-        struct TestDevice;
+        #[derive(Debug, FileRepr)]
+        #[file(read)]
+        struct Unplugged;
 
-        impl FileRepr for TestDevice {
-            fn fstat(&self) -> FStat {
-                new_fstat()
+        impl Read for Unplugged {
+            fn read(&self, buf: &mut [u8], offset: usize) -> crate::kernel::io::IOResult<usize> {
+                Ok(0)
             }
         }
 
-        impl IOCapable for TestDevice {}
+        let handle = DeviceHandle::new(Arc::new(Unplugged));
+        registry()
+            .register(handle.clone(), Path::new("/unplug/dev").into())
+            .unwrap();
+        let mut file = procfs
+            .open(Path::new("/unplug/dev"), OpenOptions::READ)
+            .unwrap()
+            .finish();
+        let mut buf = vec![0; 8];
+        assert_eq!(file.read_continuous(&mut buf).unwrap(), 0);
+
+        // the open file outlives the device, but no longer reaches it
+        assert!(handle.detach().is_some());
+        assert!(handle.is_detached());
+        assert_eq!(
+            *file.read_continuous(&mut buf).unwrap_err().kind(),
+            FSErrorKind::NotFound
+        );
+
+        assert_eq!(registry().deregister_dir(Path::new("/unplug/")).len(), 1);
+        assert!(registry().get(Path::new("/unplug/dev")).is_err());
+        assert!(!registry().contains_dir(Path::new("/unplug")));
+    }
+
+    #[kernel_test]
+    fn test_rw() {
+        let procfs = ProcFS::new();
+
+        let registry = DEVICE_REGISTRY.get_or_init(|| DeviceRegistry::new());
+
+        #[derive(Debug, FileRepr)]
+        #[file(read)]
+        struct TestDevice;
 
         impl Read for TestDevice {
             fn read(&self, buf: &mut [u8], offset: usize) -> crate::kernel::io::IOResult<usize> {
@@ -621,12 +716,6 @@ mod tests {
             }
         }
 
-        impl Write for TestDevice {
-            fn write(&self, buf: &[u8], offset: usize) -> crate::kernel::io::IOResult<usize> {
-                Err(FSError::simple(FSErrorKind::NotSupported))
-            }
-        }
-
         let test_device = Arc::new(TestDevice);
 
         assert!(
@@ -1,3 +1,5 @@
+use alloc::{format, sync::Arc, vec::Vec};
+
 use conquer_once::spin::OnceCell;
 use hashbrown::HashMap;
 use thiserror::Error;
@@ -5,19 +7,21 @@ use tinyos_abi::{flags::NodeType, types::FStat};
 
 use crate::{
     kernel::{
-        fd::{FileRepr, IOCapable, new_fstat},
+        fd::{FileMetadata, FileRepr, IOCapable, new_fstat},
         fs::{
             FS,
             FSError,
             FSErrorKind,
             FSResult,
+            PROCFS_PATH,
             Path,
             PathBuf,
             UnlinkOptions,
             procfs::MaybeRefCounted,
             vfs::VFS,
         },
-        io::{Read, Write},
+        io::{IOError, IOResult, Read, Write},
+        threading::wait::QueuTypeCondition,
     },
     sync::locks::RwLock,
 };
@@ -110,7 +114,7 @@ impl DeviceRegistry {
             .remove(path)
             .ok_or(FSError::simple(FSErrorKind::InvalidPath))?;
         if let Some(vfs) = VFS.get() {
-            return match vfs.unlink(&path, UnlinkOptions::empty()) {
+            return match vfs.unlink(&mounted_path(path), UnlinkOptions::empty()) {
                 Ok(_) => Ok(device),
                 Err(err) => {
                     match err.kind() {
@@ -142,6 +146,36 @@ impl DeviceRegistry {
         Ok(device)
     }
 
+    /// deregisters all devices below dir and removes dir itself from procfs. Returns the devices, which were deregistered
+    pub fn deregister_dir(&self, dir: &Path) -> Vec<DeviceEntry> {
+        let dir = dir.trim_trailing_sep();
+        let paths: Vec<PathBuf> = self
+            .devices
+            .read()
+            .keys()
+            .filter(|device| device.ancestors().skip(1).any(|ancestor| ancestor == dir))
+            .cloned()
+            .collect();
+        let removed = paths
+            .iter()
+            .filter_map(|path| self.deregister(path).ok())
+            .collect();
+        if let Some(vfs) = VFS.get() {
+            // the dir may never have been materialized
+            _ = vfs.unlink(&mounted_path(dir), UnlinkOptions::RECURSIVE);
+        }
+        removed
+    }
+
+    /// returns true if any registered device lives below dir
+    pub fn contains_dir(&self, dir: &Path) -> bool {
+        let dir = dir.trim_trailing_sep();
+        self.devices
+            .read()
+            .keys()
+            .any(|device| device.ancestors().skip(1).any(|ancestor| ancestor == dir))
+    }
+
     pub fn get(&self, path: &Path) -> FSResult<DeviceEntry> {
         self.devices
             .read()
@@ -194,3 +228,108 @@ impl Write for DeviceEntry {
         todo!()
     }
 }
+
+// the path of a registered device in the vfs
+fn mounted_path(path: &Path) -> PathBuf {
+    PathBuf::from(format!(
+        "{}/{}",
+        PROCFS_PATH,
+        path.as_str().trim_start_matches('/')
+    ))
+}
+
+/// a refcounted handle around a device, which may go away while files are still open on it.
+/// All open files share the handle. Once it is detached, the device is dropped and any further I/O fails with NotFound
+#[derive(Debug)]
+pub struct DeviceHandle {
+    device: RwLock<Option<Arc<dyn FileRepr>>>,
+}
+
+impl DeviceHandle {
+    pub fn new(device: Arc<dyn FileRepr>) -> Arc<Self> {
+        Arc::new(Self {
+            device: RwLock::new(Some(device)),
+        })
+    }
+
+    /// releases the device. Returns it, if it was still attached
+    pub fn detach(&self) -> Option<Arc<dyn FileRepr>> {
+        self.device.write().take()
+    }
+
+    pub fn is_detached(&self) -> bool {
+        self.device.read().is_none()
+    }
+
+    // the lock is not held during I/O, such that detaching never waits for a blocked reader
+    fn device(&self) -> IOResult<Arc<dyn FileRepr>> {
+        self.device.read().clone().ok_or(IOError::with_message(
+            FSErrorKind::NotFound,
+            "the device was removed",
+        ))
+    }
+}
+
+impl FileRepr for DeviceHandle {
+    fn fstat(&self) -> FStat {
+        self.device()
+            .map(|device| device.fstat())
+            .unwrap_or_else(|_| {
+                let mut stat = new_fstat();
+                stat.node_type = NodeType::FILE;
+                stat
+            })
+    }
+
+    fn clear(&self) -> IOResult<()> {
+        self.device()?.clear()
+    }
+
+    fn as_raw_parts(&self) -> (*mut u8, usize) {
+        self.device()
+            .map(|device| device.as_raw_parts())
+            .unwrap_or((core::ptr::null_mut(), 0))
+    }
+
+    fn get_waiter(&self) -> Option<QueuTypeCondition> {
+        self.device().ok()?.get_waiter()
+    }
+
+    fn on_open(&self, meta: FileMetadata) {
+        if let Ok(device) = self.device() {
+            device.on_open(meta);
+        }
+    }
+
+    fn on_clone(&self, meta: FileMetadata) {
+        if let Ok(device) = self.device() {
+            device.on_clone(meta);
+        }
+    }
+
+    fn on_drop(&self, meta: FileMetadata) {
+        if let Ok(device) = self.device() {
+            device.on_drop(meta);
+        }
+    }
+
+    fn on_close(&self, meta: FileMetadata) {
+        if let Ok(device) = self.device() {
+            device.on_close(meta);
+        }
+    }
+}
+
+impl IOCapable for DeviceHandle {}
+
+impl Read for DeviceHandle {
+    fn read(&self, buf: &mut [u8], offset: usize) -> IOResult<usize> {
+        self.device()?.read(buf, offset)
+    }
+}
+
+impl Write for DeviceHandle {
+    fn write(&self, buf: &[u8], offset: usize) -> IOResult<usize> {
+        self.device()?.write(buf, offset)
+    }
+}
@@ -1,26 +1,29 @@
 use alloc::{
+    collections::btree_map::BTreeMap,
     format,
     string::{String, ToString},
     sync::Arc,
     vec,
     vec::Vec,
 };
-use core::{fmt::Display, ops::Sub};
+use core::fmt::Display;
 
 use hashbrown::DefaultHashBuilder;
 use indexmap::IndexMap;
 use thiserror::Error;
 use tinyos_abi::{
+    consts::{XATTR_NAME_MAX, XATTR_SIZE_MAX},
     flags::{NodePermissions, NodeType},
     types::{FStat, PermUpdateStrategy},
 };
 
 use crate::{
-    arch::x86::current_time,
+    arch::mem::{PhysFrame, Size4KiB},
     kernel::{
-        fd::{File, FileBuilder, FileRepr, IOCapable, new_fstat},
+        fd::{File, FileBuilder, FileRepr, IOCapable, NodeTimes, new_fstat},
         fs::{
             self,
+            Component,
             FS,
             FSError,
             FSErrorKind,
@@ -30,6 +33,7 @@ use crate::{
             PathBuf,
             UnlinkOptions,
             fs_util::open,
+            ramfs::pages::Pages,
         },
         io::{Read, Write},
     },
@@ -37,6 +41,8 @@ use crate::{
     sync::locks::RwLock,
 };
 
+mod pages;
+
 #[derive(Error, Debug)]
 pub enum RamFSError {}
 
@@ -47,6 +53,11 @@ type RamFilePtr = Arc<LockedRamFile>;
 struct RamFile {
     node: RamNode,
     stat: FStat,
+    /// part of a snapshot, thus neither its contents nor its permissions change
+    frozen: bool,
+    /// the timestamps of stat are not maintained, they are filled in from times
+    times: NodeTimes,
+    xattrs: BTreeMap<String, Vec<u8>>,
 }
 
 impl RamFile {
@@ -66,7 +77,13 @@ impl RamFile {
             }
             RamNode::File(_) => stat.node_type = NodeType::FILE,
         }
-        Self { node, stat }
+        Self {
+            node,
+            stat,
+            frozen: false,
+            times: NodeTimes::new(),
+            xattrs: BTreeMap::new(),
+        }
     }
 
     fn is_dir(&self) -> bool {
@@ -75,6 +92,35 @@ impl RamFile {
             _ => false,
         }
     }
+
+    /// a copy of self and everything below it. File contents are shared until either side writes to them
+    fn snapshot(&self, frozen: bool) -> Self {
+        let node = match &self.node {
+            RamNode::SoftLink(path) => RamNode::SoftLink(path.clone()),
+            RamNode::File(data) => RamNode::File(FileData {
+                inner: data.inner.clone(),
+            }),
+            RamNode::Dir(dir) => RamNode::Dir(DirData {
+                inner: dir
+                    .inner
+                    .iter()
+                    .map(|(name, child)| {
+                        (
+                            name.clone(),
+                            RamFilePtr::new(LockedRamFile::new(child.read().snapshot(frozen))),
+                        )
+                    })
+                    .collect(),
+            }),
+        };
+        Self {
+            node,
+            stat: self.stat.clone(),
+            frozen,
+            times: self.times.clone(),
+            xattrs: self.xattrs.clone(),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -218,7 +264,8 @@ impl Display for DirData {
 
 #[derive(Debug)]
 struct FileData {
-    inner: Vec<u8>,
+    /// shared with snapshots and borrowing readers, thus copied on write
+    inner: Pages,
 }
 
 impl Default for FileData {
@@ -231,38 +278,78 @@ impl Default for FileData {
 
 impl FileRepr for LockedRamFile {
     fn fstat(&self) -> FStat {
-        self.read().stat.clone()
+        let reader = self.read();
+        let mut stat = reader.stat.clone();
+        reader.times.apply(&mut stat);
+        stat
     }
 
     fn update_perms(&self, perms: NodePermissions, strategy: PermUpdateStrategy) {
         let mut writer = self.write();
+        if writer.frozen {
+            return;
+        }
         match strategy {
             PermUpdateStrategy::AND => writer.stat.permissions &= perms,
             PermUpdateStrategy::OR => writer.stat.permissions |= perms,
             PermUpdateStrategy::OVERWRITE => writer.stat.permissions = perms,
         }
+        writer.times.changed();
     }
 
     fn clear(&self) -> crate::kernel::io::IOResult<()> {
         let mut writer = self.write();
+        if writer.frozen {
+            return Err(FSError::simple(FSErrorKind::PermissionDenied));
+        }
         let r = match &mut writer.node {
             RamNode::SoftLink(_) | RamNode::Dir(_) => {
                 Err(FSError::simple(FSErrorKind::NotSupported))
             }
-            RamNode::File(f) => Ok(f.inner.clear()),
+            RamNode::File(f) => {
+                // snapshots keep the old contents
+                f.inner = Pages::default();
+                Ok(())
+            }
         }?;
 
-        writer.stat.t_mod = current_time().as_secs();
+        writer.times.modified();
         writer.stat.size = 0;
         Ok(r)
     }
+
+    fn set_times(
+        &self,
+        access: Option<u64>,
+        modify: Option<u64>,
+    ) -> crate::kernel::io::IOResult<()> {
+        let reader = self.read();
+        if reader.frozen {
+            return Err(FSError::simple(FSErrorKind::PermissionDenied));
+        }
+        reader.times.set(access, modify);
+        Ok(())
+    }
+
+    fn lend_frames(&self, offset: usize, pages: usize) -> Vec<PhysFrame<Size4KiB>> {
+        let reader = self.read();
+        match reader.node {
+            RamNode::File(ref f) => {
+                reader.times.accessed();
+                f.inner.lend(offset, pages)
+            }
+            _ => Vec::new(),
+        }
+    }
 }
 
 impl IOCapable for LockedRamFile {}
 
 impl Read for LockedRamFile {
     fn read(&self, buf: &mut [u8], offset: usize) -> crate::kernel::io::IOResult<usize> {
-        match self.read().node {
+        let reader = self.read();
+        reader.times.accessed();
+        match reader.node {
             RamNode::SoftLink(ref l) => {
                 let bytes = l.as_str().as_bytes();
                 if offset > bytes.len() {
@@ -277,18 +364,10 @@ impl Read for LockedRamFile {
                 Ok(written)
             }
             RamNode::File(ref f) => {
-                // bail early if we are at end
-                if offset == f.inner.len() {
-                    return Ok(0);
+                if offset > f.inner.len() {
+                    return Err(FSError::simple(FSErrorKind::UnexpectedEOF));
                 }
-                let len = f
-                    .inner
-                    .len()
-                    .checked_sub(offset)
-                    .ok_or(FSError::simple(FSErrorKind::UnexpectedEOF))?
-                    .min(buf.len());
-                buf[..len].copy_from_slice(&f.inner[offset..offset + len]);
-                Ok(len)
+                Ok(f.inner.read(buf, offset))
             }
         }
     }
@@ -299,6 +378,7 @@ impl Read for LockedRamFile {
         mut offset: usize,
     ) -> crate::kernel::io::IOResult<usize> {
         let reader = self.read();
+        reader.times.accessed();
         match reader.node {
             RamNode::SoftLink(ref l) => {
                 let bytes = l.as_str().as_bytes();
@@ -334,6 +414,9 @@ impl Read for LockedRamFile {
 impl Write for LockedRamFile {
     fn write(&self, buf: &[u8], offset: usize) -> crate::kernel::io::IOResult<usize> {
         let mut writer = self.write();
+        if writer.frozen {
+            return Err(FSError::simple(FSErrorKind::PermissionDenied));
+        }
         let r = match writer.node {
             RamNode::SoftLink(ref mut l) => {
                 let str_ =
@@ -346,22 +429,41 @@ impl Write for LockedRamFile {
             RamNode::File(ref mut f) => {
                 // this currently allows to write BELOW end, leaving a 0 initialized region
                 // might want to prohibit this
-                if offset + buf.len() > f.inner.len() {
-                    f.inner.resize(offset + buf.len(), 0);
-                }
-                // no need to validate offset, as we just resized
-                let len = f.inner.len().sub(offset).min(buf.len());
-                f.inner[offset..offset + len].copy_from_slice(&buf[..len]);
+                let len = f.inner.write(buf, offset)?;
                 writer.stat.size = f.inner.len();
                 Ok(len)
             }
         }?;
 
-        writer.stat.t_mod = current_time().as_secs();
+        writer.times.modified();
         Ok(r)
     }
 }
 
+fn chk_xattr(name: &str, value: &[u8]) -> FSResult<()> {
+    if name.is_empty() || name.len() > XATTR_NAME_MAX || name.contains('\0') {
+        Err(FSError::simple(FSErrorKind::InvalidFilename))
+    } else if value.len() > XATTR_SIZE_MAX {
+        Err(FSError::simple(FSErrorKind::FileTooLarge))
+    } else {
+        Ok(())
+    }
+}
+
+/// runs func on the xattrs of node, if they may be changed
+fn with_mut_xattrs<F, R>(node: RamFilePtr, func: F) -> FSResult<R>
+where
+    F: FnOnce(&mut BTreeMap<String, Vec<u8>>) -> FSResult<R>,
+{
+    let mut writer = node.write();
+    if writer.frozen || !writer.stat.permissions.w() {
+        return Err(FSError::simple(FSErrorKind::PermissionDenied));
+    }
+    let r = func(&mut writer.xattrs)?;
+    writer.times.changed();
+    Ok(r)
+}
+
 fn chk_perms(options: OpenOptions, perms: NodePermissions) -> Result<(), FSError> {
     if (options.contains(OpenOptions::READ) && !perms.r())
         || (options.contains(OpenOptions::WRITE) && !perms.w())
@@ -395,8 +497,13 @@ impl RamFS {
             return Ok(current_dir);
         };
 
-        // skip root dir
-        for component in parent.traverse().skip(1) {
+        for component in parent.components() {
+            let component = match component {
+                Component::RootDir | Component::CurDir => continue,
+                // .. must be resolved before the path reaches a fs
+                Component::ParentDir => return Err(FSError::simple(FSErrorKind::InvalidPath)),
+                Component::Normal(component) => component,
+            };
             let child = if options.contains(OpenOptions::CREATE_ALL) {
                 with_mut_dir(current_dir, |dir| {
                     dir.ensure_entry(component.to_string(), ram_dir)
@@ -435,6 +542,99 @@ impl RamFS {
             .flatten()
         }
     }
+
+    /// an immutable copy of the whole fs, see snapshot_dir
+    pub fn snapshot(&self) -> RamSnapshot {
+        RamSnapshot {
+            root: RamFilePtr::new(LockedRamFile::new(self.root.read().snapshot(true))),
+        }
+    }
+
+    /// an immutable copy of the directory at path. Only the nodes are copied, file contents are shared with self until
+    /// they are written to. The snapshot may be mounted, but not modified
+    pub fn snapshot_dir(&self, path: &Path) -> FSResult<RamSnapshot> {
+        let dir = self.traverse(path, OpenOptions::READ)?;
+        let dir = dir.read();
+        if !dir.is_dir() {
+            return Err(FSError::simple(FSErrorKind::NotADir));
+        }
+        Ok(RamSnapshot {
+            root: RamFilePtr::new(LockedRamFile::new(dir.snapshot(true))),
+        })
+    }
+
+    /// resets the whole fs to snapshot, see restore_dir
+    pub fn restore(&self, snapshot: &RamSnapshot) {
+        *self.root.write() = snapshot.root.read().snapshot(false);
+    }
+
+    /// resets the directory at path to snapshot, which stays valid. Files opened below path keep referring to the
+    /// replaced nodes
+    pub fn restore_dir(&self, path: &Path, snapshot: &RamSnapshot) -> FSResult<()> {
+        let dir = self.traverse(path, OpenOptions::READ)?;
+        if !dir.read().is_dir() {
+            return Err(FSError::simple(FSErrorKind::NotADir));
+        }
+        *dir.write() = snapshot.root.read().snapshot(false);
+        Ok(())
+    }
+}
+
+/// an immutable copy of a RamFS or one of its directories. Clones share all nodes.
+/// Mounted, it is read only: creating, writing and unlinking fail with FSErrorKind::PermissionDenied
+#[derive(Debug, Clone)]
+pub struct RamSnapshot {
+    root: RamFilePtr,
+}
+
+impl FS for RamSnapshot {
+    fn open(&self, path: &Path, options: OpenOptions) -> FSResult<FileBuilder> {
+        if options.intersects(
+            OpenOptions::WRITE
+                | OpenOptions::APPEND
+                | OpenOptions::TRUNCATE
+                | OpenOptions::CREATE
+                | OpenOptions::CREATE_DIR
+                | OpenOptions::CREATE_ALL
+                | OpenOptions::CREATE_LINK,
+        ) {
+            return Err(FSError::simple(FSErrorKind::PermissionDenied));
+        }
+        RamFS {
+            root: self.root.clone(),
+        }
+        .open(path, options)
+    }
+
+    fn unlink(&self, _path: &Path, _options: UnlinkOptions) -> FSResult<FileBuilder> {
+        Err(FSError::simple(FSErrorKind::PermissionDenied))
+    }
+
+    fn flush(&self, _path: &Path) -> FSResult<()> {
+        Ok(())
+    }
+
+    fn get_xattr(&self, path: &Path, name: &str) -> FSResult<Vec<u8>> {
+        RamFS {
+            root: self.root.clone(),
+        }
+        .get_xattr(path, name)
+    }
+
+    fn set_xattr(&self, _path: &Path, _name: &str, _value: &[u8]) -> FSResult<()> {
+        Err(FSError::simple(FSErrorKind::PermissionDenied))
+    }
+
+    fn remove_xattr(&self, _path: &Path, _name: &str) -> FSResult<()> {
+        Err(FSError::simple(FSErrorKind::PermissionDenied))
+    }
+
+    fn list_xattr(&self, path: &Path) -> FSResult<Vec<String>> {
+        RamFS {
+            root: self.root.clone(),
+        }
+        .list_xattr(path)
+    }
 }
 
 impl FS for RamFS {
@@ -474,10 +674,17 @@ impl FS for RamFS {
             return Err(FSError::simple(FSErrorKind::PermissionDenied));
         }
 
-        if path.as_str().ends_with('/') {
-            chk_perms(options, perms)?;
+        let file = if path.has_trailing_sep() && !options.contains(OpenOptions::CREATE_DIR) {
+            let entry = entries
+                .inner
+                .get(path.file())
+                .ok_or(FSError::simple(FSErrorKind::NotFound))?;
+            if !entry.read_arc().is_dir() {
+                return Err(FSError::simple(FSErrorKind::NotADir));
+            }
+            chk_perms(options, entry.read_arc().stat.permissions)?;
 
-            Ok(as_file(parent).with_perms(options))
+            Ok(as_file(entry.clone()).with_perms(options))
         } else if options.contains(OpenOptions::CREATE_DIR) {
             let node = entries.ensure_entry(path.file().into(), ram_dir);
             chk_perms(options, node.read_arc().stat.permissions)?;
@@ -508,7 +715,11 @@ impl FS for RamFS {
 
                 Ok(as_file(entry.clone()).with_perms(options))
             }
+        };
+        if is_creating && !target_exists && file.is_ok() {
+            writer.times.modified();
         }
+        file
     }
 
     fn unlink(
@@ -547,7 +758,7 @@ impl FS for RamFS {
         let removed = match &child.read_arc().node {
             RamNode::Dir(_) => {
                 if options.contains(UnlinkOptions::RECURSIVE) {
-                    with_mut_dir(parent, |entries| {
+                    with_mut_dir(parent.clone(), |entries| {
                         entries
                             .inner
                             .swap_remove(path.file())
@@ -559,11 +770,11 @@ impl FS for RamFS {
                 }
             }
             RamNode::SoftLink(_) | RamNode::File(_) => {
-                if path.as_str().ends_with('/') {
+                if path.has_trailing_sep() {
                     return Err(FSError::simple(FSErrorKind::NotADir));
                 }
 
-                with_mut_dir(parent, |entries| {
+                with_mut_dir(parent.clone(), |entries| {
                     entries
                         .inner
                         .swap_remove(path.file())
@@ -572,6 +783,7 @@ impl FS for RamFS {
                 .flatten()
             }
         }?;
+        parent.read().times.modified();
 
         Ok(as_file(removed))
     }
@@ -580,6 +792,44 @@ impl FS for RamFS {
         // nothing to do
         Ok(())
     }
+
+    // xattrs belong to the node at path itself, symlinks are not followed
+
+    fn get_xattr(&self, path: &Path, name: &str) -> FSResult<Vec<u8>> {
+        self.traverse(path, OpenOptions::READ)?
+            .read()
+            .xattrs
+            .get(name)
+            .cloned()
+            .ok_or(FSError::simple(FSErrorKind::NotFound))
+    }
+
+    fn set_xattr(&self, path: &Path, name: &str, value: &[u8]) -> FSResult<()> {
+        chk_xattr(name, value)?;
+        with_mut_xattrs(self.traverse(path, OpenOptions::READ)?, |xattrs| {
+            xattrs.insert(name.into(), value.into());
+            Ok(())
+        })
+    }
+
+    fn remove_xattr(&self, path: &Path, name: &str) -> FSResult<()> {
+        with_mut_xattrs(self.traverse(path, OpenOptions::READ)?, |xattrs| {
+            xattrs
+                .remove(name)
+                .map(|_| ())
+                .ok_or(FSError::simple(FSErrorKind::NotFound))
+        })
+    }
+
+    fn list_xattr(&self, path: &Path) -> FSResult<Vec<String>> {
+        Ok(self
+            .traverse(path, OpenOptions::READ)?
+            .read()
+            .xattrs
+            .keys()
+            .cloned()
+            .collect())
+    }
 }
 
 #[cfg(feature = "test_run")]
@@ -589,10 +839,13 @@ mod tests {
     use os_macros::kernel_test;
 
     use super::*;
+    use crate::{
+        common::fixtures::FreshRamFS,
+        kernel::fs::{MountOptions, vfs::VFS},
+    };
 
     #[kernel_test]
-    fn ramfs_basic() {
-        let ramfs = RamFS::new();
+    fn ramfs_basic(#[fixture] ramfs: &FreshRamFS) {
         assert!(
             ramfs
                 .open(Path::new("/foo/bar"), OpenOptions::default())
@@ -640,8 +893,7 @@ mod tests {
     }
 
     #[kernel_test]
-    fn ramfs_retrieval() {
-        let ramfs = RamFS::new();
+    fn ramfs_retrieval(#[fixture] ramfs: &FreshRamFS) {
         let mut bar = ramfs
             .open(
                 Path::new("/foo/bar.txt"),
@@ -677,6 +929,191 @@ mod tests {
         assert_eq!(foobar.read_continuous(&mut buf).unwrap(), 0)
     }
 
+    #[kernel_test(cases(("/a.txt", ""), ("/foo/bar.txt", "hello world"), ("/x/y/z", "nested\ncontent")))]
+    fn ramfs_roundtrip(path: &str, content: &str, #[fixture] ramfs: &FreshRamFS) {
+        let mut file = ramfs
+            .open(
+                Path::new(path),
+                OpenOptions::CREATE_ALL | OpenOptions::WRITE | OpenOptions::READ,
+            )
+            .unwrap()
+            .finish();
+        assert_eq!(
+            file.write_continuous(content.as_bytes()).unwrap(),
+            content.len()
+        );
+        file.set_cursor(0);
+        let mut buf = vec![0; content.len() + 1];
+        let n = file.read_continuous(&mut buf).unwrap();
+        assert_eq!(&buf[..n], content.as_bytes());
+    }
+
+    fn read_all(fs: &dyn FS, path: &str) -> FSResult<String> {
+        let file = fs.open(Path::new(path), OpenOptions::READ)?.finish();
+        let mut buf = String::new();
+        file.read_to_string(&mut buf, 0)?;
+        Ok(buf)
+    }
+
+    fn write_all(fs: &dyn FS, path: &str, content: &str) {
+        let file = fs
+            .open(
+                Path::new(path),
+                OpenOptions::CREATE_ALL | OpenOptions::WRITE | OpenOptions::TRUNCATE,
+            )
+            .unwrap()
+            .finish();
+        file.write(content.as_bytes(), 0).unwrap();
+    }
+
+    #[kernel_test]
+    fn snapshot_restore(#[fixture] ramfs: &FreshRamFS) {
+        write_all(&**ramfs, "/bin/ls", "ls");
+        write_all(&**ramfs, "/bin/cat", "cat");
+        write_all(&**ramfs, "/home/notes", "notes");
+        let snapshot = ramfs.snapshot_dir(Path::new("/bin")).unwrap();
+
+        // destructive changes do not reach the snapshot
+        write_all(&**ramfs, "/bin/ls", "overwritten");
+        ramfs
+            .unlink(Path::new("/bin/cat"), UnlinkOptions::empty())
+            .unwrap();
+        write_all(&**ramfs, "/bin/new", "new");
+        write_all(&**ramfs, "/home/notes", "changed");
+        assert_eq!(read_all(&snapshot, "/ls").unwrap(), "ls");
+        assert_eq!(read_all(&snapshot, "/cat").unwrap(), "cat");
+        assert!(read_all(&snapshot, "/new").is_err());
+
+        ramfs.restore_dir(Path::new("/bin"), &snapshot).unwrap();
+        assert_eq!(read_all(&**ramfs, "/bin/ls").unwrap(), "ls");
+        assert_eq!(read_all(&**ramfs, "/bin/cat").unwrap(), "cat");
+        assert!(read_all(&**ramfs, "/bin/new").is_err());
+        // only /bin is restored
+        assert_eq!(read_all(&**ramfs, "/home/notes").unwrap(), "changed");
+
+        // the restored files are writable again, without touching the snapshot
+        write_all(&**ramfs, "/bin/ls", "again");
+        assert_eq!(read_all(&snapshot, "/ls").unwrap(), "ls");
+        assert!(ramfs.snapshot_dir(Path::new("/home/notes")).is_err());
+    }
+
+    #[kernel_test]
+    fn snapshot_read_only(#[fixture] ramfs: &FreshRamFS) {
+        write_all(&**ramfs, "/a/b", "b");
+        let snapshot = ramfs.snapshot();
+        for options in [
+            OpenOptions::WRITE,
+            OpenOptions::CREATE,
+            OpenOptions::CREATE_ALL | OpenOptions::READ,
+        ] {
+            assert_eq!(
+                snapshot
+                    .open(Path::new("/a/b"), options)
+                    .err()
+                    .map(|e| *e.kind()),
+                Some(FSErrorKind::PermissionDenied)
+            );
+        }
+        assert!(
+            snapshot
+                .unlink(Path::new("/a/b"), UnlinkOptions::RECURSIVE)
+                .is_err()
+        );
+
+        // mounted elsewhere, the snapshot is reached through the vfs
+        let vfs = VFS::new();
+        vfs.mount(
+            PathBuf::from(Path::new("/snap")),
+            Arc::new(snapshot.clone()) as Arc<dyn FS>,
+            MountOptions::READ_ONLY,
+        )
+        .unwrap();
+        assert_eq!(read_all(&vfs, "/snap/a/b").unwrap(), "b");
+        let file = vfs
+            .open(Path::new("/snap/a/b"), OpenOptions::READ)
+            .unwrap()
+            .finish();
+        assert!(file.write(b"x", 0).is_err());
+        file.update_perms(NodePermissions::empty(), PermUpdateStrategy::OVERWRITE);
+        assert_eq!(read_all(&snapshot, "/a/b").unwrap(), "b");
+    }
+
+    #[kernel_test]
+    fn timestamps(#[fixture] ramfs: &FreshRamFS) {
+        const PAST: u64 = u64::MAX / 4;
+        write_all(&**ramfs, "/t/file", "content");
+        let file = ramfs
+            .open(Path::new("/t/file"), OpenOptions::READ | OpenOptions::WRITE)
+            .unwrap()
+            .finish();
+        file.set_times(Some(PAST), Some(PAST)).unwrap();
+        let stat = file.fstat();
+        assert_eq!((stat.t_access, stat.t_mod), (PAST, PAST));
+        assert!(stat.t_change < PAST);
+
+        // reads only touch the access time
+        read_all(&**ramfs, "/t/file").unwrap();
+        let stat = file.fstat();
+        assert!(stat.t_access < PAST);
+        assert_eq!(stat.t_mod, PAST);
+
+        file.set_times(None, Some(PAST)).unwrap();
+        assert_eq!(file.fstat().t_mod, PAST);
+        file.write(b"x", 0).unwrap();
+        assert!(file.fstat().t_mod < PAST);
+
+        let snapshot = ramfs.snapshot();
+        let frozen = snapshot
+            .open(Path::new("/t/file"), OpenOptions::READ)
+            .unwrap()
+            .finish();
+        assert!(frozen.set_times(Some(0), None).is_err());
+    }
+
+    #[kernel_test]
+    fn xattrs(#[fixture] ramfs: &FreshRamFS) {
+        write_all(&**ramfs, "/x/bin", "bin");
+        let path = Path::new("/x/bin");
+        assert!(ramfs.list_xattr(path).unwrap().is_empty());
+        ramfs.set_xattr(path, "hash", b"1234").unwrap();
+        ramfs.set_xattr(path, "caps", b"net").unwrap();
+        ramfs.set_xattr(path, "hash", b"5678").unwrap();
+        assert_eq!(ramfs.get_xattr(path, "hash").unwrap(), b"5678");
+        assert_eq!(ramfs.list_xattr(path).unwrap(), ["caps", "hash"]);
+        assert_eq!(
+            ramfs.get_xattr(path, "missing").err().map(|e| *e.kind()),
+            Some(FSErrorKind::NotFound)
+        );
+        assert!(ramfs.set_xattr(path, "", b"").is_err());
+        assert!(ramfs.set_xattr(path, "a\0b", b"").is_err());
+        assert!(
+            ramfs
+                .set_xattr(path, "big", &vec![0; XATTR_SIZE_MAX + 1])
+                .is_err()
+        );
+
+        // snapshots carry the xattrs, but cannot change them
+        let snapshot = ramfs.snapshot();
+        ramfs.remove_xattr(path, "caps").unwrap();
+        assert!(ramfs.remove_xattr(path, "caps").is_err());
+        assert_eq!(ramfs.list_xattr(path).unwrap(), ["hash"]);
+        assert_eq!(snapshot.get_xattr(path, "caps").unwrap(), b"net");
+        assert!(snapshot.set_xattr(path, "caps", b"").is_err());
+
+        // reached through the vfs
+        let vfs = VFS::new();
+        vfs.mount(
+            PathBuf::from(Path::new("/snap")),
+            Arc::new(snapshot) as Arc<dyn FS>,
+            MountOptions::READ_ONLY,
+        )
+        .unwrap();
+        assert_eq!(
+            vfs.list_xattr(Path::new("/snap/x/bin")).unwrap(),
+            ["caps", "hash"]
+        );
+    }
+
     #[kernel_test]
     fn read_dir() {
         let dir = ram_dir();
@@ -0,0 +1,336 @@
+use alloc::vec::Vec;
+use core::slice;
+
+use crate::{
+    arch::mem::{FrameAllocator, PageSize, PhysFrame, Size4KiB},
+    kernel::{
+        fs::{FSError, FSErrorKind, FSResult},
+        mem::paging::{
+            GlobalFrameAllocator,
+            frame_refcount,
+            free_frame,
+            get_frame_alloc,
+            get_hhdm_addr,
+            share_frame,
+        },
+    },
+};
+
+const PAGE_SIZE: usize = Size4KiB::SIZE as usize;
+
+/// the contents of a file in whole frames, which are accessed through the hhdm. The frames are refcounted like user pages,
+/// thus clones, like snapshots, and readers, which borrowed them with lend, share them until either side writes to them
+#[derive(Debug, Default)]
+pub struct Pages {
+    frames: Vec<PhysFrame<Size4KiB>>,
+    len: usize,
+}
+
+fn page_ptr(frame: PhysFrame<Size4KiB>) -> *mut u8 {
+    (get_hhdm_addr() + frame.start_address().as_u64()) as *mut u8
+}
+
+impl Pages {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// copies the bytes at offset.. into buf. Returns the number of bytes copied
+    pub fn read(&self, buf: &mut [u8], offset: usize) -> usize {
+        let len = self.len.saturating_sub(offset).min(buf.len());
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done;
+            let in_page = pos % PAGE_SIZE;
+            let n = (PAGE_SIZE - in_page).min(len - done);
+            // SAFETY: the frame is owned by self and n does not cross its end
+            let src = unsafe {
+                slice::from_raw_parts(page_ptr(self.frames[pos / PAGE_SIZE]).add(in_page), n)
+            };
+            buf[done..done + n].copy_from_slice(src);
+            done += n;
+        }
+        len
+    }
+
+    /// writes buf at offset. A gap between the old end and offset reads as zeroes
+    pub fn write(&mut self, buf: &[u8], offset: usize) -> FSResult<usize> {
+        let end = offset
+            .checked_add(buf.len())
+            .ok_or(FSError::simple(FSErrorKind::FileTooLarge))?;
+        let mut alloc = get_frame_alloc().lock();
+        // frames are zeroed by the allocator
+        while self.frames.len() * PAGE_SIZE < end {
+            let frame = alloc
+                .allocate_frame()
+                .ok_or(FSError::simple(FSErrorKind::StorageFull))?;
+            self.frames.push(frame);
+        }
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done;
+            let in_page = pos % PAGE_SIZE;
+            let n = (PAGE_SIZE - in_page).min(buf.len() - done);
+            let frame = self.unshare(pos / PAGE_SIZE, &mut alloc)?;
+            // SAFETY: the frame is owned by self alone now and n does not cross its end
+            unsafe { slice::from_raw_parts_mut(page_ptr(frame).add(in_page), n) }
+                .copy_from_slice(&buf[done..done + n]);
+            done += n;
+        }
+        self.len = self.len.max(end);
+        Ok(buf.len())
+    }
+
+    /// the frame of page idx, copied first, if it is shared with a clone or a borrower
+    fn unshare(
+        &mut self,
+        idx: usize,
+        alloc: &mut GlobalFrameAllocator,
+    ) -> FSResult<PhysFrame<Size4KiB>> {
+        let old = self.frames[idx];
+        // frames are only shared while self is, thus no new reference may appear meanwhile
+        if frame_refcount(old) == 1 {
+            return Ok(old);
+        }
+        let new = alloc
+            .allocate_frame()
+            .ok_or(FSError::simple(FSErrorKind::StorageFull))?;
+        // SAFETY: both frames are reachable through the hhdm and do not overlap
+        unsafe { core::ptr::copy_nonoverlapping(page_ptr(old), page_ptr(new), PAGE_SIZE) };
+        free_frame(old, alloc);
+        self.frames[idx] = new;
+        Ok(new)
+    }
+
+    /// the frames backing up to pages whole pages from offset on, which must be page aligned. Pages past the end of
+    /// the file are not lent. Each frame gets a reference, which the borrower drops with free_frame, usually by
+    /// mapping it copy on write into a user address space
+    pub fn lend(&self, offset: usize, pages: usize) -> Vec<PhysFrame<Size4KiB>> {
+        if !offset.is_multiple_of(PAGE_SIZE) {
+            return Vec::new();
+        }
+        let first = offset / PAGE_SIZE;
+        let last = (self.len / PAGE_SIZE).min(first.saturating_add(pages));
+        let frames = self.frames.get(first..last).unwrap_or_default().to_vec();
+        for &frame in &frames {
+            share_frame(frame);
+        }
+        frames
+    }
+}
+
+impl Clone for Pages {
+    fn clone(&self) -> Self {
+        for &frame in &self.frames {
+            share_frame(frame);
+        }
+        Self {
+            frames: self.frames.clone(),
+            len: self.len,
+        }
+    }
+}
+
+impl Drop for Pages {
+    fn drop(&mut self) {
+        if self.frames.is_empty() {
+            return;
+        }
+        let mut alloc = get_frame_alloc().lock();
+        for frame in self.frames.drain(..) {
+            free_frame(frame, &mut *alloc);
+        }
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use alloc::{sync::Arc, vec};
+
+    use os_macros::kernel_test;
+
+    use super::*;
+    use crate::{
+        arch::{
+            self,
+            mem::{PageTableFlags, VirtAddr},
+        },
+        kernel::{
+            fd::{FileRepr, LEND_MIN_LEN},
+            fs::{self, FS, MountOptions, OpenOptions, Path, mount, ramfs::RamFS, unmount},
+            io::{Read, Write},
+            mem::paging::{COW, active_page_flags, map_region, unmap_region},
+            threading::{task::TaskRepr, tls},
+            time,
+        },
+        println,
+    };
+
+    /// user memory read into, which is mapped into the current address space by the tests
+    const BUF: u64 = 0x5800_0000;
+    const ROUNDS: usize = 32;
+
+    fn map_buf(len: usize) -> &'static mut [u8] {
+        let flags = PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | PageTableFlags::USER_ACCESSIBLE
+            | PageTableFlags::NO_EXECUTE;
+        let pagedir = tls::task_data().current_thread().unwrap().pagedir();
+        map_region(VirtAddr::new(BUF), len, flags, pagedir).unwrap();
+        unsafe { slice::from_raw_parts_mut(BUF as *mut u8, len) }
+    }
+
+    fn unmap_buf(len: usize) {
+        let pagedir = tls::task_data().current_thread().unwrap().pagedir();
+        unmap_region(VirtAddr::new(BUF), len, pagedir).unwrap();
+    }
+
+    #[kernel_test]
+    fn clones_share_frames() {
+        let mut pages = Pages::default();
+        assert_eq!(pages.write(b"abc", PAGE_SIZE - 1).unwrap(), 3);
+        assert_eq!(pages.len(), PAGE_SIZE + 2);
+        let snapshot = pages.clone();
+        assert_eq!(frame_refcount(pages.frames[1]), 2);
+        pages.write(b"x", PAGE_SIZE).unwrap();
+        // only the written page is copied
+        assert_eq!(pages.frames[0], snapshot.frames[0]);
+        assert_ne!(pages.frames[1], snapshot.frames[1]);
+        let mut buf = [0; 4];
+        assert_eq!(snapshot.read(&mut buf, PAGE_SIZE - 2), 4);
+        assert_eq!(&buf, b"\0abc");
+        assert_eq!(pages.read(&mut buf, PAGE_SIZE - 2), 4);
+        assert_eq!(&buf, b"\0axc");
+
+        // the last page is not whole, thus not lent
+        let lent = pages.lend(0, 4);
+        assert_eq!(lent, [pages.frames[0]]);
+        assert_eq!(frame_refcount(lent[0]), 3);
+        free_frame(lent[0], &mut *get_frame_alloc().lock());
+        drop(snapshot);
+        assert_eq!(frame_refcount(pages.frames[0]), 1);
+    }
+
+    #[kernel_test]
+    fn read_lending() {
+        mount(
+            Path::new("/lendfs").into(),
+            Arc::new(RamFS::new()) as Arc<dyn FS>,
+            MountOptions::empty(),
+        )
+        .unwrap();
+        let file = fs::open(
+            Path::new("/lendfs/a"),
+            OpenOptions::CREATE_ALL | OpenOptions::READ | OpenOptions::WRITE,
+        )
+        .unwrap();
+        let len = LEND_MIN_LEN + PAGE_SIZE;
+        let data = (0..len)
+            .map(|i| (i / PAGE_SIZE + i) as u8)
+            .collect::<Vec<_>>();
+        file.write_all(&data, 0).unwrap();
+        let buf = map_buf(len + PAGE_SIZE);
+
+        // buf starts at the same offset into a page as the cursor
+        file.set_cursor(100);
+        assert_eq!(file.read_lending(&mut buf[100..]).unwrap(), len - 100);
+        assert_eq!(&buf[100..len], &data[100..]);
+        assert_eq!(file.cursor(), len);
+        // the partial first page is copied, the whole ones are lent
+        assert!(!active_page_flags(VirtAddr::new(BUF)).unwrap().contains(COW));
+        for page in 1..len / PAGE_SIZE {
+            let addr = VirtAddr::new(BUF + (page * PAGE_SIZE) as u64);
+            assert!(active_page_flags(addr).unwrap().contains(COW));
+        }
+
+        // the reader keeps its contents, when the file is written
+        file.write(&[0xff; 8], PAGE_SIZE).unwrap();
+        assert_eq!(
+            &buf[PAGE_SIZE..PAGE_SIZE + 8],
+            &data[PAGE_SIZE..PAGE_SIZE + 8]
+        );
+
+        unmap_buf(len + PAGE_SIZE);
+        drop(file);
+        unmount(Path::new("/lendfs")).unwrap();
+    }
+
+    #[kernel_test]
+    fn send_lent_pages() {
+        mount(
+            Path::new("/sendfs").into(),
+            Arc::new(RamFS::new()) as Arc<dyn FS>,
+            MountOptions::empty(),
+        )
+        .unwrap();
+        let options = OpenOptions::CREATE_ALL | OpenOptions::READ | OpenOptions::WRITE;
+        let src = fs::open(Path::new("/sendfs/a"), options).unwrap();
+        let dst = fs::open(Path::new("/sendfs/b"), options).unwrap();
+        let len = 2 * PAGE_SIZE + 100;
+        let data = (0..len).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        src.write_all(&data, 0).unwrap();
+
+        // the whole pages are written from the page cache, the partial last one is copied
+        assert_eq!(src.send_to(10, &dst, len).unwrap(), len - 10);
+        let mut sent = vec![0; len];
+        assert_eq!(dst.read(&mut sent, 0).unwrap(), len - 10);
+        assert_eq!(&sent[..len - 10], &data[10..]);
+        // the lent references were dropped again
+        let frame = src.lend_frames(0, 1)[0];
+        assert_eq!(frame_refcount(frame), 2);
+        free_frame(frame, &mut *get_frame_alloc().lock());
+
+        drop((src, dst));
+        unmount(Path::new("/sendfs")).unwrap();
+    }
+
+    #[kernel_test]
+    fn bench_read_lending() {
+        mount(
+            Path::new("/lendbench").into(),
+            Arc::new(RamFS::new()) as Arc<dyn FS>,
+            MountOptions::empty(),
+        )
+        .unwrap();
+        let file = fs::open(
+            Path::new("/lendbench/a"),
+            OpenOptions::CREATE_ALL | OpenOptions::READ | OpenOptions::WRITE,
+        )
+        .unwrap();
+        let len = 16 * LEND_MIN_LEN;
+        file.write_all(&vec![0x5a; len], 0).unwrap();
+        let buf = map_buf(len);
+
+        // the kernel does not write to the lent pages, thus copying has to come first
+        for (name, lend) in [("copy", false), ("lend", true)] {
+            let start = arch::timestamp();
+            for _ in 0..ROUNDS {
+                file.set_cursor(0);
+                let n = if lend {
+                    file.read_lending(buf)
+                } else {
+                    file.read_continuous(buf)
+                };
+                assert_eq!(n.unwrap(), len);
+            }
+            let micros = time::cycles_to_duration(arch::timestamp() - start)
+                .as_micros()
+                .max(1);
+            println!(
+                "bench: ramfs {} read of {} KiB, {} MB/s",
+                name,
+                len / 1024,
+                (ROUNDS * len) as u128 / micros
+            );
+        }
+        assert!(buf.iter().all(|&byte| byte == 0x5a));
+
+        unmap_buf(len);
+        drop(file);
+        unmount(Path::new("/lendbench")).unwrap();
+    }
+}
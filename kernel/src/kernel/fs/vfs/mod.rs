@@ -1,17 +1,44 @@
-use alloc::{collections::btree_map::BTreeMap, string::String, sync::Arc};
-use core::fmt::Display;
+use alloc::{
+    collections::btree_map::BTreeMap,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    fmt::{Display, Write as _},
+    ptr,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
+use bitflags::bitflags;
 use conquer_once::spin::OnceCell;
 use hashbrown::DefaultHashBuilder;
 use indexmap::IndexMap;
+use os_macros::FileRepr;
 use thiserror::Error;
 use tinyos_abi::{flags::NodeType, types::FStat};
 
 use crate::{
     kernel::{
         fd::{FileBuilder, FileRepr, IOCapable, MaybeOwned},
-        fs::{FS, FSError, FSErrorKind, FSResult, OpenOptions, Path, PathBuf, UnlinkOptions},
-        io::{Read, Write},
+        fs::{
+            Component,
+            FS,
+            FSError,
+            FSErrorKind,
+            FSResult,
+            OpenOptions,
+            Path,
+            PathBuf,
+            UnlinkOptions,
+        },
+        io::{IOResult, Read, Write, read_rendered},
+        threading::{
+            self,
+            tls,
+            wait::{QueuTypeCondition, QueueType, WaitEvent, condition::WaitCondition, post_event},
+        },
     },
     serial_println,
     sync::{
@@ -22,12 +49,51 @@ use crate::{
 
 pub static VFS: OnceCell<Arc<VFS>> = OnceCell::uninit();
 
+/// incremented on every mount and unmount in any namespace
+static MOUNT_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+pub const MOUNTS_FILE: &str = "/mounts";
+
+const ROOT: &str = "/";
+// maximum number of symlinks followed while normalizing a single path
+const MAX_LINK_DEPTH: usize = 40;
+
 pub fn init() {
     VFS.init_once(|| VFS::new().into());
 }
 
-pub fn get() -> &'static Arc<VFS> {
-    VFS.get_or_init(|| VFS::new().into())
+/// the vfs of the mount namespace of the current task
+pub fn get() -> Arc<VFS> {
+    if let Some(current) = tls::task_data().current_thread()
+        && let Some(ns) = &current.core.mnt_ns
+    {
+        return ns.clone();
+    }
+    VFS.get_or_init(|| VFS::new().into()).clone()
+}
+
+/// the number of mounts and unmounts so far, in all namespaces
+pub fn mount_generation() -> u64 {
+    MOUNT_GENERATION.load(Ordering::Acquire)
+}
+
+static MOUNTS_CHANGED: fn(u64) -> bool = |seen| mount_generation() != seen;
+
+/// the condition to wait for, until the mount table changes after mount_generation returned seen
+pub fn mounts_changed(seen: u64) -> QueuTypeCondition {
+    QueuTypeCondition::with_cond(
+        QueueType::Mounts,
+        WaitCondition::Generic(seen, ptr::from_ref::<dyn Fn(u64) -> bool>(&MOUNTS_CHANGED)),
+    )
+}
+
+/// wakes the waiters for mount table changes. A lost event only delays them until their next wakeup,
+/// as they recheck the generation
+fn notify_mounts_changed() {
+    MOUNT_GENERATION.fetch_add(1, Ordering::AcqRel);
+    if threading::is_running() {
+        _ = post_event(WaitEvent::new(QueueType::Mounts));
+    }
 }
 
 #[derive(Error, Debug)]
@@ -39,9 +105,77 @@ pub enum VFSError {
     },
 }
 
+bitflags! {
+    /// options of a single mount, enforced by the vfs for all paths below the mount point
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct MountOptions: u8 {
+        /// nothing may be created, written, removed or have its attributes changed
+        const READ_ONLY = 1 << 0;
+        /// files may not be opened for execution
+        const NO_EXEC = 1 << 1;
+        /// executables may not gain privileges. Builtins, which run in kernel mode, may not be spawned from it
+        const NO_SUID = 1 << 2;
+    }
+}
+
+impl Display for MountOptions {
+    /// formats self like the options column of /proc/mounts on linux, e.g. ro,noexec
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(if self.contains(Self::READ_ONLY) {
+            "ro"
+        } else {
+            "rw"
+        })?;
+        if self.contains(Self::NO_EXEC) {
+            f.write_str(",noexec")?;
+        }
+        if self.contains(Self::NO_SUID) {
+            f.write_str(",nosuid")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Mount {
+    fs: Arc<dyn FS>,
+    options: MountOptions,
+}
+
+impl Mount {
+    /// fails with PermissionDenied, if opening a file with options is not allowed on this mount
+    fn check_open(&self, options: OpenOptions) -> FSResult<()> {
+        let modifying = OpenOptions::WRITE
+            | OpenOptions::APPEND
+            | OpenOptions::TRUNCATE
+            | OpenOptions::CREATE
+            | OpenOptions::CREATE_DIR
+            | OpenOptions::CREATE_ALL
+            | OpenOptions::CREATE_LINK;
+        if self.options.contains(MountOptions::READ_ONLY) && options.intersects(modifying) {
+            return Err(FSError::with_message(
+                FSErrorKind::PermissionDenied,
+                "the mount is read only",
+            ));
+        }
+        if self.options.contains(MountOptions::NO_EXEC) && options.contains(OpenOptions::EXECUTE) {
+            return Err(FSError::with_message(
+                FSErrorKind::PermissionDenied,
+                "the mount does not allow execution",
+            ));
+        }
+        Ok(())
+    }
+
+    /// fails with PermissionDenied, if this mount is read only
+    fn check_write(&self) -> FSResult<()> {
+        self.check_open(OpenOptions::WRITE)
+    }
+}
+
 #[derive(Debug)]
 pub struct VFS {
-    mount_table: GenericRwLock<BTreeMap<PathBuf, Arc<dyn FS>>, BlockingWaiter>,
+    mount_table: GenericRwLock<BTreeMap<PathBuf, Mount>, BlockingWaiter>,
 }
 
 impl VFS {
@@ -49,12 +183,14 @@ impl VFS {
         Self::default()
     }
 
-    fn deepest_matching_mount<'a>(&self, path: &'a Path) -> FSResult<(Arc<dyn FS>, &'a Path)> {
+    fn deepest_matching_mount<'a>(&self, path: &'a Path) -> FSResult<(Mount, &'a Path)> {
         let mut target_fs = None;
         let mut postfix_path = path;
         let reader = self.mount_table.read();
 
         for ancestor in path.ancestors() {
+            // only the path itself may carry trailing separators, its parents never do
+            let ancestor = ancestor.trim_trailing_sep();
             if let Some(mount) = reader.get(ancestor) {
                 target_fs.replace(mount.clone());
                 postfix_path = path
@@ -72,42 +208,169 @@ impl VFS {
             ))
     }
 
-    pub fn mount(&self, mount_point: PathBuf, fs: Arc<dyn FS>) -> FSResult<()> {
+    pub fn mount(
+        &self,
+        mount_point: PathBuf,
+        fs: Arc<dyn FS>,
+        options: MountOptions,
+    ) -> FSResult<()> {
+        let replaced = self.mount_table.write().insert(
+            mount_point.trim_trailing_sep().to_owned(),
+            Mount { fs, options },
+        );
+        notify_mounts_changed();
+        replaced.map_or(Ok(()), |node| {
+            Err(FSError::custom(
+                FSErrorKind::AlreadyExists,
+                VFSError::MountExists {
+                    mount: node.fs.into(),
+                    msg: "The old mount was swapped out and returned",
+                }
+                .into(),
+            ))
+        })
+    }
+
+    /// a new mount namespace, starting out with the mounts of self. Later mounts and unmounts are not shared between both,
+    /// the mounted filesystems themselves are
+    pub fn unshare(&self) -> Self {
+        Self {
+            mount_table: GenericRwLock::new(self.mount_table.read().clone()),
+        }
+    }
+
+    /// all mount points, in path order, such that a mount comes before the mounts below it
+    pub fn mount_points(&self) -> Vec<PathBuf> {
+        self.mount_table.read().keys().cloned().collect()
+    }
+
+    /// the options of the mount at mount_point
+    pub fn mount_options(&self, mount_point: &Path) -> Option<MountOptions> {
         self.mount_table
-            .write()
-            .insert(mount_point, fs)
-            .map_or(Ok(()), |node| {
-                Err(FSError::custom(
-                    FSErrorKind::AlreadyExists,
-                    VFSError::MountExists {
-                        mount: node.into(),
-                        msg: "The old mount was swapped out and returned",
-                    }
-                    .into(),
-                ))
-            })
+            .read()
+            .get(mount_point.trim_trailing_sep())
+            .map(|mount| mount.options)
+    }
+
+    /// the options of the mount, which path lies on
+    pub fn options_at(&self, path: &Path) -> FSResult<MountOptions> {
+        self.deepest_matching_mount(path)
+            .map(|(mount, _)| mount.options)
+    }
+
+    /// the mount table formatted like /proc/mounts on linux, one mount point with its options per line
+    pub fn render_mounts(&self) -> String {
+        let mut out = String::new();
+        for (mount_point, mount) in self.mount_table.read().iter() {
+            _ = writeln!(out, "{} {}", mount_point.as_str(), mount.options);
+        }
+        out
     }
 
     pub fn unmount(&self, mount_point: &Path) -> FSResult<Arc<dyn FS>> {
-        self.mount_table
+        let fs = self
+            .mount_table
             .write()
-            .remove(mount_point)
+            .remove(mount_point.trim_trailing_sep())
+            .map(|mount| mount.fs)
             .ok_or(FSError::with_message(
                 FSErrorKind::NotFound,
                 "the mount deos not exist",
-            ))
+            ))?;
+        notify_mounts_changed();
+        Ok(fs)
+    }
+}
+
+/// the components of path as owned strings, last one first
+fn reversed_components(path: &Path) -> Vec<String> {
+    let mut components: Vec<String> = path
+        .components()
+        .map(|component| component.as_str().to_string())
+        .collect();
+    components.reverse();
+    components
+}
+
+impl VFS {
+    /// resolves . and .. in path against the mount tree, such that the resulting path never escapes the fs it names.
+    /// Symlinks are replaced by their target as their component is walked, such that link/.. names the parent of the link target.
+    /// The last component is left as is, as it may name the link itself. Trailing separators are preserved, as they require the target to be a directory
+    pub fn normalize(&self, path: &Path) -> FSResult<PathBuf> {
+        if path.is_relative() && !path.is_empty() {
+            return Err(FSError::with_message(
+                FSErrorKind::InvalidPath,
+                "the vfs can only resolve absolute paths",
+            ));
+        }
+        let mut normalized = PathBuf::from(ROOT);
+        // components which still need to be applied, in reverse order
+        let mut pending = reversed_components(path);
+        let mut links_followed = 0;
+
+        while let Some(component) = pending.pop() {
+            match Path::new(&component).components().next() {
+                None | Some(Component::RootDir) | Some(Component::CurDir) => {}
+                Some(Component::ParentDir) => normalized.up(),
+                Some(Component::Normal(name)) => {
+                    normalized.push(name);
+                    if pending.is_empty() {
+                        continue;
+                    }
+                    let Some(target) = self.read_link(&normalized) else {
+                        continue;
+                    };
+                    links_followed += 1;
+                    if links_followed > MAX_LINK_DEPTH {
+                        return Err(FSError::with_message(
+                            FSErrorKind::InvalidPath,
+                            "too many levels of symbolic links",
+                        ));
+                    }
+                    // the link is replaced by its target, whose components are walked next
+                    normalized.up();
+                    pending.extend(reversed_components(&target));
+                    if !target.is_relative() {
+                        normalized = PathBuf::from(ROOT);
+                    }
+                }
+            }
+        }
+
+        if path.has_trailing_sep() && !normalized.is_empty() {
+            normalized = PathBuf::from(format!("{}/", normalized.as_str()));
+        }
+        Ok(normalized)
+    }
+
+    /// returns the target of the symlink at path, if path names a symlink
+    fn read_link(&self, path: &Path) -> Option<PathBuf> {
+        let (mount, postfix) = self.deepest_matching_mount(path).ok()?;
+        let link = mount
+            .fs
+            .open(postfix, OpenOptions::NO_FOLLOW_LINK | OpenOptions::READ)
+            .ok()?
+            .finish();
+        if link.fstat().node_type != NodeType::SYMLINK {
+            return None;
+        }
+        link.read_all_as_str().ok().map(PathBuf::from)
     }
 }
 
 impl FS for VFS {
     fn open(&self, path: &Path, options: OpenOptions) -> FSResult<crate::kernel::fd::FileBuilder> {
-        if path.is_empty() {
-            return Ok(FileBuilder::new(get().clone() as Arc<dyn FileRepr>)
+        let normalized = self.normalize(path)?;
+        if normalized.is_empty() {
+            return Ok(FileBuilder::new(get() as Arc<dyn FileRepr>)
                 .with_perms(options)
                 .with_path(path.into()));
         }
-        self.deepest_matching_mount(path)
-            .and_then(|(mount, path)| mount.open(path, options))
+        self.deepest_matching_mount(&normalized)
+            .and_then(|(mount, path)| {
+                mount.check_open(options)?;
+                mount.fs.open(path, options)
+            })
     }
 
     fn unlink(
@@ -115,13 +378,48 @@ impl FS for VFS {
         path: &Path,
         options: UnlinkOptions,
     ) -> FSResult<crate::kernel::fd::FileBuilder> {
-        self.deepest_matching_mount(path)
-            .and_then(|(mount, path)| mount.unlink(path, options))
+        let normalized = self.normalize(path)?;
+        self.deepest_matching_mount(&normalized)
+            .and_then(|(mount, path)| {
+                mount.check_write()?;
+                mount.fs.unlink(path, options)
+            })
     }
 
     fn flush(&self, path: &Path) -> FSResult<()> {
-        self.deepest_matching_mount(path)
-            .and_then(|(mount, path)| mount.flush(path))
+        let normalized = self.normalize(path)?;
+        self.deepest_matching_mount(&normalized)
+            .and_then(|(mount, path)| mount.fs.flush(path))
+    }
+
+    fn get_xattr(&self, path: &Path, name: &str) -> FSResult<Vec<u8>> {
+        let normalized = self.normalize(path)?;
+        self.deepest_matching_mount(&normalized)
+            .and_then(|(mount, path)| mount.fs.get_xattr(path, name))
+    }
+
+    fn set_xattr(&self, path: &Path, name: &str, value: &[u8]) -> FSResult<()> {
+        let normalized = self.normalize(path)?;
+        self.deepest_matching_mount(&normalized)
+            .and_then(|(mount, path)| {
+                mount.check_write()?;
+                mount.fs.set_xattr(path, name, value)
+            })
+    }
+
+    fn remove_xattr(&self, path: &Path, name: &str) -> FSResult<()> {
+        let normalized = self.normalize(path)?;
+        self.deepest_matching_mount(&normalized)
+            .and_then(|(mount, path)| {
+                mount.check_write()?;
+                mount.fs.remove_xattr(path, name)
+            })
+    }
+
+    fn list_xattr(&self, path: &Path) -> FSResult<Vec<String>> {
+        let normalized = self.normalize(path)?;
+        self.deepest_matching_mount(&normalized)
+            .and_then(|(mount, path)| mount.fs.list_xattr(path))
     }
 }
 
@@ -158,10 +456,15 @@ impl Read for VFS {
         buf: &mut alloc::vec::Vec<u8>,
         mut offset: usize,
     ) -> crate::kernel::io::IOResult<usize> {
-        let res = alloc::format!("{}", self);
-        let bytes = res.as_bytes();
-        buf.extend_from_slice(bytes);
-        Ok(bytes.len())
+        // same format as a directory listing, such that the vfs root can be listed like any other dir
+        let mut written = 0;
+        for name in self.mount_table.read().keys() {
+            let bytes = name.as_str().as_bytes();
+            buf.extend_from_slice(bytes);
+            buf.push(b'\t');
+            written += bytes.len() + 1;
+        }
+        Ok(written)
     }
 }
 
@@ -228,27 +531,47 @@ impl Display for VFS {
     }
 }
 
+/// procfs listing of the mounts of the mount namespace of the reader, see VFS::render_mounts.
+/// Readers blocking at its end are woken up by the next mount or unmount
+#[derive(Debug, Default, Clone, Copy, FileRepr)]
+#[file(read, waiter = waiter)]
+pub struct MountList;
+
+impl MountList {
+    fn waiter(&self) -> Option<QueuTypeCondition> {
+        Some(mounts_changed(mount_generation()))
+    }
+}
+
+impl Read for MountList {
+    fn read(&self, buf: &mut [u8], offset: usize) -> IOResult<usize> {
+        read_rendered(get().render_mounts(), buf, offset)
+    }
+}
+
 #[cfg(feature = "test_run")]
 mod tests {
 
     use alloc::vec;
 
-    use os_macros::kernel_test;
+    use os_macros::{FileRepr, kernel_test};
 
     use super::*;
-    use crate::kernel::{
-        fd::{FileRepr, IOCapable, new_fstat},
-        fs::{
-            OpenOptions,
-            Path,
-            mount,
-            open,
-            procfs::{DEVICE_REGISTRY, DeviceRegistry, ProcFS},
-            ramfs::RamFS,
-            symlink,
-            unmount,
+    use crate::{
+        drivers::wait_manager,
+        kernel::{
+            fs::{
+                OpenOptions,
+                Path,
+                mount,
+                open,
+                procfs::{DEVICE_REGISTRY, DeviceRegistry, ProcFS},
+                ramfs::RamFS,
+                symlink,
+                unmount,
+            },
+            io::{Read, Write},
         },
-        io::{Read, Write},
     };
 
     #[kernel_test]
@@ -261,7 +584,10 @@ mod tests {
         assert!(vfs.unmount(&Path::new("/foo/bar")).is_err());
 
         let ramfs = Arc::new(RamFS::new());
-        assert!(vfs.mount(Path::new("/foo").into(), ramfs).is_ok());
+        assert!(
+            vfs.mount(Path::new("/foo").into(), ramfs, MountOptions::empty())
+                .is_ok()
+        );
         assert!(
             vfs.open(
                 Path::new("/foo/bar"),
@@ -280,6 +606,12 @@ mod tests {
             vfs.open(Path::new("/foo/bar"), OpenOptions::default())
                 .is_ok()
         );
+        assert!(vfs.open(Path::new("/foo/"), OpenOptions::default()).is_ok());
+        // bar is not a dir
+        assert!(
+            vfs.open(Path::new("/foo/bar/"), OpenOptions::default())
+                .is_err()
+        );
         assert!(vfs.unmount(Path::new("/foo")).is_ok());
         assert!(
             vfs.open(Path::new("/foo/bar"), OpenOptions::default())
@@ -287,6 +619,134 @@ mod tests {
         );
     }
 
+    #[kernel_test]
+    fn unshared_mounts() {
+        let vfs = VFS::new();
+        assert!(
+            vfs.mount(
+                Path::new("/foo").into(),
+                Arc::new(RamFS::new()),
+                MountOptions::empty()
+            )
+            .is_ok()
+        );
+        let ns = vfs.unshare();
+        assert!(
+            ns.mount(
+                Path::new("/bar").into(),
+                Arc::new(RamFS::new()),
+                MountOptions::empty()
+            )
+            .is_ok()
+        );
+        assert!(ns.open(Path::new("/bar/"), OpenOptions::default()).is_ok());
+        assert!(
+            vfs.open(Path::new("/bar/"), OpenOptions::default())
+                .is_err()
+        );
+
+        // the mounted filesystems are shared
+        assert!(
+            vfs.open(
+                Path::new("/foo/baz"),
+                OpenOptions::CREATE | OpenOptions::READ
+            )
+            .is_ok()
+        );
+        assert!(vfs.unmount(Path::new("/foo")).is_ok());
+        assert!(
+            ns.open(Path::new("/foo/baz"), OpenOptions::default())
+                .is_ok()
+        );
+    }
+
+    #[kernel_test]
+    fn mount_options() {
+        let vfs = VFS::new();
+        let ro = Arc::new(RamFS::new());
+        ro.open(Path::new("/a"), OpenOptions::CREATE | OpenOptions::WRITE)
+            .unwrap();
+        assert!(
+            vfs.mount(Path::new("/ro").into(), ro, MountOptions::READ_ONLY)
+                .is_ok()
+        );
+        assert!(
+            vfs.mount(
+                Path::new("/nx").into(),
+                Arc::new(RamFS::new()),
+                MountOptions::NO_EXEC | MountOptions::NO_SUID
+            )
+            .is_ok()
+        );
+        let denied = |res: FSResult<FileBuilder>| {
+            res.err().map(|e| *e.kind()) == Some(FSErrorKind::PermissionDenied)
+        };
+
+        assert!(vfs.open(Path::new("/ro/a"), OpenOptions::READ).is_ok());
+        for options in [
+            OpenOptions::WRITE,
+            OpenOptions::READ | OpenOptions::CREATE,
+            OpenOptions::READ | OpenOptions::TRUNCATE,
+        ] {
+            assert!(denied(vfs.open(Path::new("/ro/a"), options)));
+        }
+        assert!(denied(
+            vfs.unlink(Path::new("/ro/a"), UnlinkOptions::empty())
+        ));
+        assert!(vfs.set_xattr(Path::new("/ro/a"), "user.x", b"").is_err());
+
+        assert!(
+            vfs.open(Path::new("/nx/b"), OpenOptions::CREATE | OpenOptions::WRITE)
+                .is_ok()
+        );
+        assert!(denied(vfs.open(
+            Path::new("/nx/b"),
+            OpenOptions::READ | OpenOptions::EXECUTE
+        )));
+
+        assert_eq!(
+            vfs.mount_options(Path::new("/ro/")),
+            Some(MountOptions::READ_ONLY)
+        );
+        assert_eq!(
+            vfs.options_at(Path::new("/nx/b")).unwrap(),
+            MountOptions::NO_EXEC | MountOptions::NO_SUID
+        );
+        assert_eq!(vfs.render_mounts(), "/nx rw,noexec,nosuid\n/ro ro\n");
+        // the global table lists the mounts made at boot
+        let mut listing = vec![0; 4096];
+        let n = MountList.read(&mut listing, 0).unwrap();
+        assert!(
+            str::from_utf8(&listing[..n])
+                .unwrap()
+                .lines()
+                .any(|line| line == "/proc rw,noexec,nosuid")
+        );
+    }
+
+    #[kernel_test]
+    fn mount_events() {
+        let seen = mount_generation();
+        let waiter = threading::spawn(move || {
+            while mount_generation() == seen {
+                wait_manager::wait_self(&[mounts_changed(seen)]);
+            }
+        })
+        .unwrap();
+        let fs = Arc::new(RamFS::new()) as Arc<dyn FS>;
+        mount(Path::new("/ram3").into(), fs, MountOptions::empty()).unwrap();
+        assert!(waiter.wait().is_ok());
+        assert_eq!(mount_generation(), seen + 1);
+
+        let seen = mount_generation();
+        assert!(!mounts_changed(seen).cond.is_given());
+        unmount(Path::new("/ram3")).unwrap();
+        assert!(mounts_changed(seen).cond.is_given());
+        // failed unmounts change nothing
+        assert!(unmount(Path::new("/ram3")).is_err());
+        assert_eq!(mount_generation(), seen + 1);
+    }
+
     #[kernel_test]
     fn vfs_integration() {
         let vfs = VFS::new();
@@ -294,17 +754,10 @@ mod tests {
         let ramfs = Arc::new(RamFS::new());
         let registry = DEVICE_REGISTRY.get_or_init(|| DeviceRegistry::new());
 
-        #[derive(Debug)]
+        #[derive(Debug, FileRepr)]
+        #[file(read)]
         struct TestDevice;
 
-        impl FileRepr for TestDevice {
-            fn fstat(&self) -> FStat {
-                new_fstat()
-            }
-        }
-
-        impl IOCapable for TestDevice {}
-
         impl Read for TestDevice {
             fn read(&self, buf: &mut [u8], offset: usize) -> crate::kernel::io::IOResult<usize> {
                 let bytes = "Test Device".as_bytes();
@@ -314,16 +767,16 @@ mod tests {
             }
         }
 
-        impl Write for TestDevice {
-            fn write(&self, buf: &[u8], offset: usize) -> crate::kernel::io::IOResult<usize> {
-                Err(FSError::simple(FSErrorKind::NotSupported))
-            }
-        }
-
         let test_device = Arc::new(TestDevice);
 
-        assert!(vfs.mount(Path::new("/proc").into(), procfs).is_ok());
-        assert!(vfs.mount(Path::new("/ram").into(), ramfs).is_ok());
+        assert!(
+            vfs.mount(Path::new("/proc").into(), procfs, MountOptions::NO_EXEC)
+                .is_ok()
+        );
+        assert!(
+            vfs.mount(Path::new("/ram").into(), ramfs, MountOptions::empty())
+                .is_ok()
+        );
 
         let mut ramfile = vfs
             .open(
@@ -381,11 +834,13 @@ mod tests {
         mount(
             Path::new("/ram0").into(),
             Arc::new(RamFS::new()) as Arc<dyn FS>,
+            MountOptions::empty(),
         )
         .unwrap();
         mount(
             Path::new("/ram1").into(),
             Arc::new(RamFS::new()) as Arc<dyn FS>,
+            MountOptions::empty(),
         )
         .unwrap();
 
@@ -448,4 +903,35 @@ mod tests {
         unmount(Path::new("/ram0")).unwrap();
         unmount(Path::new("/ram1")).unwrap();
     }
+
+    #[kernel_test]
+    fn normalize() {
+        mount(
+            Path::new("/ram2").into(),
+            Arc::new(RamFS::new()) as Arc<dyn FS>,
+            MountOptions::empty(),
+        )
+        .unwrap();
+        let vfs = get();
+
+        assert_eq!(
+            vfs.normalize(Path::new("/ram2/a/../../proc/./x")).unwrap(),
+            PathBuf::from("/proc/x")
+        );
+        assert_eq!(
+            vfs.normalize(Path::new("/../ram2//a/")).unwrap(),
+            PathBuf::from("/ram2/a/")
+        );
+        assert!(vfs.normalize(Path::new("ram2/a")).is_err());
+
+        open(Path::new("/ram2/a/b/c"), OpenOptions::CREATE_ALL).unwrap();
+        symlink(Path::new("/ram2/link"), Path::new("/ram2/a/b")).unwrap();
+        // .. applies to the link target, not to the dir containing the link
+        assert_eq!(
+            vfs.normalize(Path::new("/ram2/link/../b/c")).unwrap(),
+            PathBuf::from("/ram2/a/b/c")
+        );
+
+        unmount(Path::new("/ram2")).unwrap();
+    }
 }
@@ -12,6 +12,7 @@ use crate::{
     impl_file_for_wr,
     kernel::{
         mem::{
+            addr::{KernelVirtAddr, UserVirtAddr},
             align_up,
             paging::{PAGETABLE, kernel_map_region, unmap_region, user_map_region},
         },
@@ -332,7 +333,7 @@ impl FrameBuffer for LimineFrameBuffer<'_> {
 
 // 32 bits per pixel
 pub struct GlobalFrameBuffer {
-    inner: &'static limine::framebuffer::Framebuffer<'static>,
+    inner: &'static bootinfo::FramebufferInfo,
 }
 
 impl GlobalFrameBuffer {
@@ -402,6 +403,75 @@ impl FrameBuffer for GlobalFrameBuffer {
     }
 }
 
+/// a view of fb, which starts rows pixel rows further down.
+/// Used as the source, when a framebuffer is scrolled onto itself through BlitTarget::copy_rect
+pub struct ShiftedFrameBuffer<'a, B: FrameBuffer> {
+    fb: &'a B,
+    rows: usize,
+}
+
+impl<'a, B: FrameBuffer> ShiftedFrameBuffer<'a, B> {
+    pub fn new(fb: &'a B, rows: usize) -> Self {
+        assert!(rows <= fb.height());
+        Self { fb, rows }
+    }
+}
+
+impl<B: FrameBuffer> FrameBuffer for ShiftedFrameBuffer<'_, B> {
+    fn set_pixel(&self, value: &RGBColor, x: usize, y: usize) {
+        self.fb.set_pixel(value, x, y + self.rows);
+    }
+
+    fn clear_pixel(&self, x: usize, y: usize) {
+        self.fb.clear_pixel(x, y + self.rows);
+    }
+
+    fn clear_all(&self) {
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                self.clear_pixel(x, y);
+            }
+        }
+    }
+
+    fn fill(&self, value: RGBColor) {
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                self.set_pixel(&value, x, y);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        self.fb.flush();
+    }
+
+    fn width(&self) -> usize {
+        self.fb.width()
+    }
+
+    fn height(&self) -> usize {
+        self.fb.height() - self.rows
+    }
+
+    // offsets are relative to addr, which already includes the shift
+    fn pixel_offset(&self, x: usize, y: usize) -> usize {
+        self.fb.pixel_offset(x, y)
+    }
+
+    fn addr(&self) -> *mut u8 {
+        unsafe { self.fb.addr().add(self.rows * self.fb.pitch()) }
+    }
+
+    fn bpp(&self) -> u16 {
+        self.fb.bpp()
+    }
+
+    fn pitch(&self) -> usize {
+        self.fb.pitch()
+    }
+}
+
 enum MemMapping {
     Kernel,
     User(ThreadID),
@@ -420,12 +490,7 @@ pub struct RawFrameBuffer {
 impl RawFrameBuffer {
     /// SAFETY: will allocate space of at least pitch * height at addr
     /// caller needs to ensure a valid address and valid values for width, height and pitch
-    pub unsafe fn new_user(
-        addr: crate::arch::mem::VirtAddr,
-        width: usize,
-        height: usize,
-        bpp: u16,
-    ) -> Self {
+    pub unsafe fn new_user(addr: UserVirtAddr, width: usize, height: usize, bpp: u16) -> Self {
         let pitch = align_up(width * (bpp / 8) as usize, 64);
         user_map_region(addr, pitch * height).expect("could not map memory");
         Self {
@@ -440,12 +505,7 @@ impl RawFrameBuffer {
 
     /// SAFETY: will allocate space of at least pitch * height at addr
     /// caller needs to ensure a valid address and valid values for width, height and pitch
-    pub unsafe fn new_kernel(
-        addr: crate::arch::mem::VirtAddr,
-        width: usize,
-        height: usize,
-        bpp: u16,
-    ) -> Self {
+    pub unsafe fn new_kernel(addr: KernelVirtAddr, width: usize, height: usize, bpp: u16) -> Self {
         let pitch = align_up(width * (bpp / 8) as usize, 64);
         kernel_map_region(addr, pitch * height).expect("could not map memory");
         Self {
@@ -1,18 +1,19 @@
 use alloc::{string::String, vec::Vec};
 
+use os_macros::init_task;
 use tinyos_abi::{flags::NodePermissions, types::PermUpdateStrategy};
 
 use crate::{
     KernelRes,
+    arch,
     eprintln,
     kernel::{
-        devices,
         fd::FileRepr,
-        fs::{self, OpenOptions, Path, PathBuf, UnlinkOptions, builtin_bins},
+        fs::{self, OpenOptions, Path, PathBuf, UnlinkOptions},
         io::{Read, Write},
-        mem,
         threading::{self, schedule, task::TaskBuilder},
     },
+    serial_debugln,
     serial_println,
 };
 
@@ -23,15 +24,82 @@ pub const INCLUDED_BINS: &str = "/ram/bin";
 
 const ON_STARTUP: &[&str] = &["tinyTerm"];
 
+/// boot stages, in the order in which they run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum InitStage {
+    /// after paging and the terminal are set up. The heap is set up in this stage
+    Early,
+    /// after interrupts are set up: filesystems, devices and the initial binaries
+    Fs,
+    /// from the first kernel task, once threading is finalized: background driver tasks
+    Drivers,
+    /// not during boot, but at the start of kernel::shutdown::poweroff: services stop taking new work,
+    /// before the filesystems are flushed
+    Shutdown,
+}
+
+/// a boot time init task, registered through #[init_task]
+#[derive(Debug)]
+#[repr(C)]
+pub struct InitCall {
+    pub name: &'static str,
+    pub stage: InitStage,
+    pub order: u32,
+    pub func: fn() -> KernelRes<()>,
+}
+
+unsafe extern "C" {
+    static __initcalls_start: InitCall;
+    static __initcalls_end: InitCall;
+}
+
+/// all init tasks in the .initcalls link section, in link order
+pub fn init_calls() -> &'static [InitCall] {
+    // SAFETY: the linker script places all entries contiguously between __initcalls_start and __initcalls_end
+    unsafe {
+        let start = &raw const __initcalls_start;
+        let end = &raw const __initcalls_end;
+        let count = (end.addr() - start.addr()) / size_of::<InitCall>();
+        core::slice::from_raw_parts(start, count)
+    }
+}
+
+/// runs all init tasks of stage by ascending order and logs how long each of them took.
+/// A failing init task is logged, but does not stop the boot
+pub fn run_stage(stage: InitStage) {
+    let calls = init_calls();
+    let frequency = arch::timestamp_frequency().max(1) as u128;
+    // the next call is selected by (order, index) instead of sorting, as the heap does not exist before the early stage
+    let mut last = None;
+    while let Some((idx, call)) = calls
+        .iter()
+        .enumerate()
+        .filter(|(idx, call)| {
+            call.stage == stage && last.is_none_or(|last| (call.order, *idx) > last)
+        })
+        .min_by_key(|(idx, call)| (call.order, *idx))
+    {
+        last = Some((call.order, idx));
+        let start = arch::timestamp();
+        let res = (call.func)();
+        let micros = (arch::timestamp() - start) as u128 * 1_000_000 / frequency;
+        match res {
+            Ok(()) => serial_debugln!("init {:?}: {} ({} us)", stage, call.name, micros),
+            Err(e) => eprintln!(
+                "init {:?}: {} failed after {} us:\n{}",
+                stage, call.name, micros, e
+            ),
+        }
+    }
+}
+
 pub fn early_init() {
-    mem::init();
+    run_stage(InitStage::Early);
 }
 
 pub fn late_init() {
-    fs::init();
-    devices::init();
-    load_init_bins();
-    builtin_bins::init();
+    run_stage(InitStage::Fs);
     threading::init();
 }
 
@@ -65,6 +133,7 @@ pub fn default_task() -> KernelRes<()> {
     Ok(())
 }
 
+#[init_task(stage = "fs", order = 20)]
 fn load_init_bins() {
     let mut binaries: Vec<(String, &'static [u8])> = get_binaries();
 
@@ -72,7 +141,7 @@ fn load_init_bins() {
 
     for (name, bin) in binaries.into_iter() {
         bin_path.push(name.as_str());
-        serial_println!("adding binary to {}", bin_path);
+        serial_debugln!("adding binary to {}", bin_path);
         if let Ok(file) = fs::open(&bin_path, OpenOptions::CREATE_ALL | OpenOptions::WRITE) {
             if let Err(e) = file.write_all(bin, 0) {
                 eprintln!(
@@ -106,3 +175,27 @@ macro_rules! create_device_file {
             .and_then(|_| $crate::kernel::fs::open(&p, $permissions))
     }};
 }
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    #[kernel_test]
+    fn init_calls_registered() {
+        let find = |name: &str| {
+            init_calls()
+                .iter()
+                .find(|call| call.name.ends_with(name))
+                .map(|call| (call.stage, call.order))
+        };
+        assert_eq!(find("mem::init"), Some((InitStage::Early, 0)));
+        assert_eq!(find("fs::init"), Some((InitStage::Fs, 0)));
+        assert_eq!(find("load_init_bins"), Some((InitStage::Fs, 20)));
+        assert_eq!(
+            find("start_wait_managment").map(|(stage, _)| stage),
+            Some(InitStage::Drivers)
+        );
+    }
+}
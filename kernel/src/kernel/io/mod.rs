@@ -2,6 +2,8 @@ use alloc::{string::String, vec::Vec};
 
 use crate::kernel::fs::{FSError, FSErrorKind};
 
+pub mod ring;
+
 pub type IOError = FSError;
 pub type IOResult<T> = Result<T, IOError>;
 
@@ -34,6 +36,12 @@ pub trait Read {
         buf.extend(str_.chars());
         Ok(str_.len())
     }
+
+    /// hands back buf, the unused end of the last read. Sources, which read at the given offset, simply return the
+    /// same bytes again, thus only streams like pipes, which drop what they return, need to implement this
+    fn unread(&self, _buf: &[u8]) -> IOResult<()> {
+        Ok(())
+    }
 }
 
 pub trait Write {
@@ -55,6 +63,17 @@ pub trait Write {
     }
 }
 
+/// copies rendered, starting at offset, into buf. Used by files, which render their whole content on every read
+pub fn read_rendered(rendered: impl AsRef<[u8]>, buf: &mut [u8], offset: usize) -> IOResult<usize> {
+    let bytes = rendered.as_ref();
+    if offset >= bytes.len() {
+        return Ok(0);
+    }
+    let n = buf.len().min(bytes.len() - offset);
+    buf[..n].copy_from_slice(&bytes[offset..offset + n]);
+    Ok(n)
+}
+
 #[macro_export]
 macro_rules! impl_empty_read {
     (@impl [$($impl_generics:tt)*] $name:ty) => {
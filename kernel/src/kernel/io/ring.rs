@@ -0,0 +1,536 @@
+use alloc::{
+    alloc::{alloc_zeroed, dealloc},
+    boxed::Box,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{
+    alloc::Layout,
+    mem::{offset_of, size_of},
+    ptr::{self, NonNull},
+    slice,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use conquer_once::spin::OnceCell;
+use tinyos_abi::{
+    consts::{RING_CURSOR, RING_MAGIC},
+    flags::NodeType,
+    types::{CompletionEntry, FStat, RingHeader, RingOp, SubmissionEntry, SysErrCode},
+};
+
+use super::{Read, Write};
+use crate::{
+    drivers::wait_manager,
+    impl_empty_read,
+    impl_empty_write,
+    kernel::{
+        fd::{FileHandle, FileRepr, IOCapable},
+        fs::{self, OpenOptions, Path, PathBuf},
+        threading::{
+            pool::ThreadPool,
+            schedule::GlobalTaskPtr,
+            task::TaskRepr,
+            tls,
+            wait::{
+                QueuTypeCondition,
+                QueueHandle,
+                QueueType,
+                WaitEvent,
+                condition::WaitCondition,
+                post_event,
+                queues::GenericWaitQueue,
+            },
+        },
+        time::Instant,
+    },
+    sync::{get_next_lock_var, locks::Mutex},
+};
+
+const PAGE_SIZE: usize = 4096;
+
+static WORKQUEUE: OnceCell<Option<ThreadPool>> = OnceCell::uninit();
+
+fn workqueue() -> Option<&'static ThreadPool> {
+    WORKQUEUE
+        .get_or_init(|| ThreadPool::new(2, 64).ok())
+        .as_ref()
+}
+
+// called with the address of a ring, whose wait queue is removed before it is freed
+static COMPLETED: fn(u64) -> bool = |ring| {
+    let ring = unsafe { &*(ring as *const Ring) };
+    ring.completed.load(Ordering::Acquire) >= ring.wanted.load(Ordering::Acquire)
+};
+
+/// a submission operation, which was checked in the context of the submitter
+enum Prepared {
+    Done(i64),
+    Read(FileHandle, u64, u64, u64),
+    Write(FileHandle, u64, u64, u64),
+    Open(GlobalTaskPtr, PathBuf, OpenOptions),
+}
+
+/// a submission and a completion queue shared with a process, laid out as described by RingHeader.
+/// Submissions are taken by ring_enter and run by kernel workers, which append their completions.
+/// Buffers are offsets into the data area of the ring, thus the workers never touch the address space of the process.
+/// The memory is freed with the last handle of the ring. Mappings of it hold a handle in their vma, see RingMapping
+#[derive(Debug)]
+pub struct Ring {
+    this: Weak<Self>,
+    mem: NonNull<u8>,
+    layout: Layout,
+    entries: u32,
+    sq_offset: usize,
+    cq_offset: usize,
+    data_offset: usize,
+    data_len: usize,
+    /// serializes the workers appending completions
+    completing: Mutex<()>,
+    in_flight: AtomicU32,
+    /// completions produced, including the ones dropped on overflow
+    completed: AtomicU64,
+    /// the value of completed ring_enter waits for. Concurrent waiters on a ring may wait for the largest of their targets
+    wanted: AtomicU64,
+    wait_id: u64,
+}
+
+// the memory is only accessed through atomics and volatile copies
+unsafe impl Send for Ring {}
+unsafe impl Sync for Ring {}
+
+impl Ring {
+    /// allocates a ring with entries slots in both queues, which must be a power of two, and a data area of data_len bytes
+    pub fn new(entries: u32, data_len: usize) -> Option<Arc<Self>> {
+        if !entries.is_power_of_two() {
+            return None;
+        }
+        let sq_offset = size_of::<RingHeader>().next_multiple_of(64);
+        let cq_offset = sq_offset + entries as usize * size_of::<SubmissionEntry>();
+        let data_offset =
+            (cq_offset + entries as usize * size_of::<CompletionEntry>()).next_multiple_of(64);
+        let layout = Layout::from_size_align(
+            (data_offset + data_len).next_multiple_of(PAGE_SIZE),
+            PAGE_SIZE,
+        )
+        .ok()?;
+        let mem = NonNull::new(unsafe { alloc_zeroed(layout) })?;
+        unsafe {
+            mem.cast::<RingHeader>().write(RingHeader {
+                magic: RING_MAGIC,
+                entries,
+                sq_offset: sq_offset as u64,
+                cq_offset: cq_offset as u64,
+                data_offset: data_offset as u64,
+                data_len: data_len as u64,
+                ..Default::default()
+            })
+        };
+        let wait_id = get_next_lock_var();
+        wait_manager::add_queue(
+            QueueHandle::from_owned(Box::new(GenericWaitQueue::new())),
+            QueueType::Lock(wait_id),
+        );
+        Some(Arc::new_cyclic(|this| Self {
+            this: this.clone(),
+            mem,
+            layout,
+            entries,
+            sq_offset,
+            cq_offset,
+            data_offset,
+            data_len,
+            completing: Mutex::new(()),
+            in_flight: AtomicU32::new(0),
+            completed: AtomicU64::new(0),
+            wanted: AtomicU64::new(u64::MAX),
+            wait_id,
+        }))
+    }
+
+    /// a handle to self, which keeps the memory alive while it is mapped into a process
+    pub fn mapping(&self) -> Option<RingMapping> {
+        self.this.upgrade().map(RingMapping)
+    }
+
+    fn counter(&self, offset: usize) -> &AtomicU32 {
+        // SAFETY: the counters of the header are aligned u32s, which live as long as self
+        unsafe { AtomicU32::from_ptr(self.mem.as_ptr().add(offset).cast()) }
+    }
+
+    // the counters are written by the process as well, masking keeps any of their values in bounds
+    fn slot<T>(&self, queue_offset: usize, idx: u32) -> *mut T {
+        let idx = (idx & (self.entries - 1)) as usize;
+        unsafe {
+            self.mem
+                .as_ptr()
+                .add(queue_offset + idx * size_of::<T>())
+                .cast()
+        }
+    }
+
+    /// the completions, which were not taken by the process yet
+    pub fn ready(&self) -> u32 {
+        self.counter(offset_of!(RingHeader, cq_tail))
+            .load(Ordering::Acquire)
+            .wrapping_sub(
+                self.counter(offset_of!(RingHeader, cq_head))
+                    .load(Ordering::Acquire),
+            )
+    }
+
+    /// the submissions taken, which did not complete yet
+    pub fn in_flight(&self) -> u32 {
+        self.in_flight.load(Ordering::Acquire)
+    }
+
+    /// the part of the data area at offset..offset + len
+    #[allow(clippy::mut_from_ref)]
+    fn data(&self, offset: u64, len: u64) -> Option<&mut [u8]> {
+        let end = offset.checked_add(len)?;
+        if end > self.data_len as u64 {
+            return None;
+        }
+        // SAFETY: in bounds of the data area. The process may change it concurrently, which only garbles its own data
+        Some(unsafe {
+            slice::from_raw_parts_mut(
+                self.mem.as_ptr().add(self.data_offset + offset as usize),
+                len as usize,
+            )
+        })
+    }
+
+    /// takes up to max submissions out of the submission queue
+    fn take_submissions(&self, max: u32) -> Vec<SubmissionEntry> {
+        let head_counter = self.counter(offset_of!(RingHeader, sq_head));
+        let head = head_counter.load(Ordering::Relaxed);
+        let tail = self
+            .counter(offset_of!(RingHeader, sq_tail))
+            .load(Ordering::Acquire);
+        // a process, which moved its tail past a full queue, overwrote some of its submissions
+        let n = tail.wrapping_sub(head).min(self.entries).min(max);
+        let submissions = (0..n)
+            .map(|i| unsafe {
+                self.slot::<SubmissionEntry>(self.sq_offset, head.wrapping_add(i))
+                    .read_volatile()
+            })
+            .collect();
+        head_counter.store(head.wrapping_add(n), Ordering::Release);
+        submissions
+    }
+
+    /// appends a completion and wakes ring_enter. It is dropped and counted in cq_overflow, if the queue is full
+    fn complete(&self, user_data: u64, res: i64) {
+        // in_flight drops first, such that waiters never expect more completions than will arrive
+        self.in_flight.fetch_sub(1, Ordering::AcqRel);
+        {
+            let _guard = self.completing.lock();
+            let tail_counter = self.counter(offset_of!(RingHeader, cq_tail));
+            let tail = tail_counter.load(Ordering::Relaxed);
+            if self.ready() >= self.entries {
+                self.counter(offset_of!(RingHeader, cq_overflow))
+                    .fetch_add(1, Ordering::Relaxed);
+            } else {
+                unsafe {
+                    self.slot::<CompletionEntry>(self.cq_offset, tail)
+                        .write_volatile(CompletionEntry { user_data, res })
+                };
+                tail_counter.store(tail.wrapping_add(1), Ordering::Release);
+            }
+            self.completed.fetch_add(1, Ordering::AcqRel);
+        }
+        _ = post_event(WaitEvent::new(QueueType::Lock(self.wait_id)));
+    }
+
+    /// checks a submission and resolves its fd or path in the context of task
+    fn prepare(&self, task: &GlobalTaskPtr, sqe: &SubmissionEntry) -> Result<Prepared, SysErrCode> {
+        let op = RingOp::try_from(sqe.op).map_err(|_| SysErrCode::InvalidArg)?;
+        if op != RingOp::Nop && self.data(sqe.buf, sqe.len).is_none() {
+            return Err(SysErrCode::InvalidArg);
+        }
+        Ok(match op {
+            RingOp::Nop => Prepared::Done(0),
+            RingOp::Read => Prepared::Read(
+                task.fd(sqe.fd).ok_or(SysErrCode::BadFd)?,
+                sqe.offset,
+                sqe.buf,
+                sqe.len,
+            ),
+            RingOp::Write => Prepared::Write(
+                task.fd(sqe.fd).ok_or(SysErrCode::BadFd)?,
+                sqe.offset,
+                sqe.buf,
+                sqe.len,
+            ),
+            RingOp::Open => {
+                let flags = OpenOptions::from_bits(sqe.fd).ok_or(SysErrCode::InvalidArg)?;
+                let path = self.data(sqe.buf, sqe.len).ok_or(SysErrCode::InvalidArg)?;
+                let path = str::from_utf8(path).map_err(|_| SysErrCode::InvalidArg)?;
+                Prepared::Open(task.clone(), task.core.resolve(Path::new(path)), flags)
+            }
+        })
+    }
+
+    fn run(&self, op: Prepared) -> Result<i64, SysErrCode> {
+        let n = match op {
+            Prepared::Done(res) => return Ok(res),
+            Prepared::Read(file, offset, buf, len) => {
+                let buf = self.data(buf, len).ok_or(SysErrCode::InvalidArg)?;
+                if offset == RING_CURSOR {
+                    file.read_continuous(buf)
+                } else {
+                    Read::read(&*file, buf, offset as usize)
+                }
+            }
+            Prepared::Write(file, offset, buf, len) => {
+                let buf = self.data(buf, len).ok_or(SysErrCode::InvalidArg)?;
+                if offset == RING_CURSOR {
+                    file.write_continuous(buf)
+                } else {
+                    Write::write(&*file, buf, offset as usize)
+                }
+            }
+            Prepared::Open(task, path, flags) => {
+                let f = fs::open(&path, flags)?;
+                return task
+                    .add_next_file(FileHandle::from(f).with_fd_flags(flags.into()))
+                    .map(|fd| fd as i64)
+                    .map_err(|e| e.into());
+            }
+        };
+        n.map(|n| n as i64).map_err(|e| e.into())
+    }
+
+    /// takes up to max submissions of the current task and hands them to the workers. Returns the number taken.
+    /// Submissions, which fail to resolve, complete right away with their error
+    pub fn submit(&self, max: u32) -> Result<u32, SysErrCode> {
+        let current = tls::task_data()
+            .current_thread()
+            .ok_or(SysErrCode::NoProcess)?;
+        let ring = self.this.upgrade().ok_or(SysErrCode::BadFd)?;
+        let submissions = self.take_submissions(max);
+        for sqe in &submissions {
+            self.in_flight.fetch_add(1, Ordering::AcqRel);
+            let op = match self.prepare(&current, sqe) {
+                Ok(op) => op,
+                Err(e) => {
+                    self.complete(sqe.user_data, -(e as i64));
+                    continue;
+                }
+            };
+            let ring = ring.clone();
+            let user_data = sqe.user_data;
+            let job = move || {
+                let res = ring.run(op).unwrap_or_else(|e| -(e as i64));
+                ring.complete(user_data, res);
+            };
+            match workqueue() {
+                // the pool is never dropped, thus it does not reject jobs
+                Some(pool) => _ = pool.execute(job),
+                None => job(),
+            }
+        }
+        Ok(submissions.len() as u32)
+    }
+
+    /// blocks until min_complete completions are ready or timeout passed. Fewer completions suffice, if not enough
+    /// submissions are in flight. A timeout of None waits without limit
+    pub fn wait(&self, min_complete: u32, timeout: Option<Duration>) -> Result<(), SysErrCode> {
+        let ready = self.ready();
+        if ready >= min_complete {
+            return Ok(());
+        }
+        // completed is read before in_flight, such that a completion in between is not counted twice
+        let completed = self.completed.load(Ordering::Acquire);
+        let target = completed + (min_complete - ready).min(self.in_flight()) as u64;
+        let until = timeout.map(|timeout| Instant::now() + timeout);
+        let mut conditions = Vec::with_capacity(2);
+        conditions.push(QueuTypeCondition::with_cond(
+            QueueType::Lock(self.wait_id),
+            WaitCondition::Generic(
+                ptr::from_ref(self) as u64,
+                ptr::from_ref::<dyn Fn(u64) -> bool>(&COMPLETED),
+            ),
+        ));
+        if let Some(until) = until {
+            conditions.push(QueuTypeCondition::with_cond(
+                QueueType::Timer,
+                WaitCondition::Time(until),
+            ));
+        }
+        while self.completed.load(Ordering::Acquire) < target
+            && until.is_none_or(|until| Instant::now() < until)
+        {
+            self.wanted.store(target, Ordering::Release);
+            wait_manager::wait_self(&conditions).ok_or(SysErrCode::WouldBlock)?;
+        }
+        Ok(())
+    }
+}
+
+/// the handle of a ring held by the vma it is mapped in. The memory of the ring is only freed, once it is unmapped
+#[derive(Debug, Clone)]
+pub struct RingMapping(Arc<Ring>);
+
+impl PartialEq for RingMapping {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for RingMapping {}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        wait_manager::remove_queue(&QueueType::Lock(self.wait_id));
+        unsafe { dealloc(self.mem.as_ptr(), self.layout) };
+    }
+}
+
+impl_empty_read!(Ring);
+impl_empty_write!(Ring);
+
+impl FileRepr for Ring {
+    fn fstat(&self) -> FStat {
+        let mut stat = FStat::default();
+        stat.node_type = NodeType::FILE;
+        stat.size = self.layout.size();
+        stat
+    }
+
+    /// the whole ring, to be mapped by the process
+    fn as_raw_parts(&self) -> (*mut u8, usize) {
+        (self.mem.as_ptr(), self.layout.size())
+    }
+
+    fn as_ring(&self) -> Option<&Ring> {
+        Some(self)
+    }
+}
+
+impl IOCapable for Ring {}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+    use crate::kernel::fs::{FS, MountOptions, mount, ramfs::RamFS, unmount};
+
+    fn push(ring: &Ring, sqe: SubmissionEntry) {
+        let tail_counter = ring.counter(offset_of!(RingHeader, sq_tail));
+        let tail = tail_counter.load(Ordering::Relaxed);
+        unsafe {
+            ring.slot::<SubmissionEntry>(ring.sq_offset, tail)
+                .write(sqe)
+        };
+        tail_counter.store(tail.wrapping_add(1), Ordering::Release);
+    }
+
+    fn pop(ring: &Ring) -> Option<CompletionEntry> {
+        if ring.ready() == 0 {
+            return None;
+        }
+        let head_counter = ring.counter(offset_of!(RingHeader, cq_head));
+        let head = head_counter.load(Ordering::Relaxed);
+        let cqe = unsafe { ring.slot::<CompletionEntry>(ring.cq_offset, head).read() };
+        head_counter.store(head.wrapping_add(1), Ordering::Release);
+        Some(cqe)
+    }
+
+    #[kernel_test]
+    fn submit_and_complete() {
+        assert!(Ring::new(3, 0).is_none());
+        let ring = Ring::new(4, 64).unwrap();
+        let header = unsafe { ring.mem.cast::<RingHeader>().read() };
+        assert_eq!(header.magic, RING_MAGIC);
+        assert_eq!(header.data_offset as usize, ring.data_offset);
+
+        mount(
+            Path::new("/ringfs").into(),
+            Arc::new(RamFS::new()) as Arc<dyn FS>,
+            MountOptions::empty(),
+        )
+        .unwrap();
+        let current = tls::task_data().current_thread().unwrap();
+        let file = fs::open(
+            Path::new("/ringfs/a"),
+            OpenOptions::CREATE_ALL | OpenOptions::READ | OpenOptions::WRITE,
+        )
+        .unwrap();
+        let fd = current.add_next_file(FileHandle::from(file)).unwrap();
+
+        ring.data(0, 5).unwrap().copy_from_slice(b"hello");
+        push(
+            &ring,
+            SubmissionEntry {
+                op: RingOp::Nop as u32,
+                user_data: 1,
+                ..Default::default()
+            },
+        );
+        push(
+            &ring,
+            SubmissionEntry {
+                op: RingOp::Write as u32,
+                fd,
+                offset: 0,
+                buf: 0,
+                len: 5,
+                user_data: 2,
+            },
+        );
+        push(
+            &ring,
+            SubmissionEntry {
+                op: RingOp::Read as u32,
+                fd: fd + 1,
+                len: 1,
+                user_data: 3,
+                ..Default::default()
+            },
+        );
+        assert_eq!(ring.submit(u32::MAX).unwrap(), 3);
+        ring.wait(3, Some(Duration::from_secs(1))).unwrap();
+        let mut results: Vec<_> = (0..3)
+            .filter_map(|_| pop(&ring))
+            .map(|c| (c.user_data, c.res))
+            .collect();
+        results.sort();
+        assert_eq!(results, [(1, 0), (2, 5), (3, -(SysErrCode::BadFd as i64))]);
+
+        push(
+            &ring,
+            SubmissionEntry {
+                op: RingOp::Read as u32,
+                fd,
+                offset: 1,
+                buf: 8,
+                len: 8,
+                user_data: 4,
+            },
+        );
+        assert_eq!(ring.submit(u32::MAX).unwrap(), 1);
+        ring.wait(1, Some(Duration::from_secs(1))).unwrap();
+        let cqe = pop(&ring).unwrap();
+        assert_eq!((cqe.user_data, cqe.res), (4, 4));
+        assert_eq!(ring.data(8, 4).unwrap(), b"ello");
+        assert!(pop(&ring).is_none());
+
+        current.remove_fd(fd);
+        unmount(Path::new("/ringfs")).unwrap();
+    }
+
+    #[kernel_test]
+    fn mapping_keeps_ring_alive() {
+        let ring = Ring::new(4, 0).unwrap();
+        let weak = Arc::downgrade(&ring);
+        let mapping = ring.mapping().unwrap();
+        assert_eq!(mapping, ring.mapping().unwrap());
+        drop(ring);
+        assert!(weak.upgrade().is_some());
+        drop(mapping);
+        assert!(weak.upgrade().is_none());
+    }
+}
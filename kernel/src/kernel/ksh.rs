@@ -0,0 +1,210 @@
+use alloc::{format, string::String, vec::Vec};
+use core::fmt::Write as _;
+
+use os_macros::init_task;
+
+use crate::{
+    KernelError,
+    KernelRes,
+    bootinfo,
+    drivers::wait_manager,
+    kernel::{
+        devices::tty::{TTYSource, source::OwnedStdin},
+        fd::FileRepr,
+        fs::{self, OpenOptions, Path},
+        mem::{
+            alloc::{GLOBAL_ALLOCATOR, alloc_failures},
+            paging::shared_frames,
+            vma,
+        },
+        reboot::{self, BootMode, RebootReason},
+        threading::{
+            self,
+            task::ProcessID,
+            tls::{self, TaskList},
+        },
+    },
+    print,
+    println,
+    term::logic::editor::LineEditor,
+};
+
+/// starts the shell, given as ksh on the kernel command line
+pub const KSH_OPTION: &str = "ksh";
+const PROMPT: &str = "ksh> ";
+
+const HELP: &str = "\
+help\t\tthis text
+ls [dir]\tlist a directory, / by default
+cat <file>...\tprint files
+ps\t\tlist all tasks
+mem\t\theap usage and the memory of every process
+kill <pid>\tkill a process
+reboot [mode]\treset the machine, booting normally or into recovery or test mode
+";
+
+/// a debug shell running in the kernel, which reads from the keyboard and talks to the fs and threading internals
+/// directly. Useful, while userspace cannot be trusted. Always started on recovery boots.
+/// Lines are edited with a LineEditor, which keeps the history of the shell
+#[init_task(stage = "drivers", order = 70)]
+fn start_ksh() {
+    if bootinfo::cmdline_option(KSH_OPTION).is_none() && reboot::boot_mode() != BootMode::Recovery {
+        return;
+    }
+    _ = threading::spawn(run);
+}
+
+fn run() {
+    let stdin = OwnedStdin::new();
+    let mut editor = LineEditor::new();
+    let mut echo = String::new();
+    let mut buf = [0; 64];
+    print!("{}", PROMPT);
+    loop {
+        let n = stdin.read_buf(&mut buf, 0).unwrap_or(0);
+        if n == 0 {
+            if let Some(waiter) = stdin.get_waiter() {
+                wait_manager::wait_self(&[waiter]);
+            }
+            continue;
+        }
+        for &byte in &buf[..n] {
+            if let Some(line) = editor.feed(byte, &mut echo) {
+                print!("{}", echo);
+                echo.clear();
+                match execute(&line) {
+                    Ok(out) => print!("{}", out),
+                    Err(e) => println!("{}", e),
+                }
+                print!("{}", PROMPT);
+            }
+        }
+        print!("{}", echo);
+        echo.clear();
+    }
+}
+
+/// runs a single command line and returns its output
+pub fn execute(line: &str) -> KernelRes<String> {
+    let mut args = line.split_ascii_whitespace();
+    let Some(cmd) = args.next() else {
+        return Ok(String::new());
+    };
+    match cmd {
+        "help" => Ok(HELP.into()),
+        "ls" => ls(args.next().unwrap_or("/")),
+        "cat" => args.map(cat).collect(),
+        "ps" => Ok(TaskList.render()),
+        "mem" => Ok(mem()),
+        "kill" => kill(
+            args.next()
+                .ok_or(KernelError::Unexpected("usage: kill <pid>"))?,
+        ),
+        "reboot" => reboot::reboot(reboot_reason(args.next())?),
+        _ => Err(KernelError::Unexpected("unknown command, try help")),
+    }
+}
+
+fn ls(dir: &str) -> KernelRes<String> {
+    let listing = fs::lsdir(Path::new(dir))?;
+    let mut out = String::new();
+    for name in listing
+        .split('\t')
+        .map(|name| name.trim_start_matches('/'))
+        .filter(|name| !name.is_empty())
+    {
+        out.push_str(name);
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+fn cat(path: &str) -> KernelRes<String> {
+    let file = fs::open(Path::new(path), OpenOptions::READ)?;
+    Ok(file.read_all_as_str()?)
+}
+
+fn mem() -> String {
+    let (used, size) = {
+        let heap = GLOBAL_ALLOCATOR.lock();
+        (heap.used(), heap.size())
+    };
+    let mut out = format!(
+        "heap\t{} / {} bytes\nalloc_failures\t{}\nshared_frames\t{}\n\npid\tresident\tvirt\tvmas\n",
+        used,
+        size,
+        alloc_failures(),
+        shared_frames()
+    );
+    let processes = tls::task_data().processes().read();
+    let mut pids = processes.keys().copied().collect::<Vec<_>>();
+    pids.sort_unstable_by_key(|pid| pid.0);
+    for pid in pids {
+        let info = vma::mem_info(&processes[&pid]);
+        _ = writeln!(
+            out,
+            "{}\t{}\t{}\t{}",
+            pid.0, info.resident, info.virt, info.vmas
+        );
+    }
+    out
+}
+
+fn kill(pid: &str) -> KernelRes<String> {
+    let pid = pid
+        .parse()
+        .map_err(|_| KernelError::Unexpected("the pid must be a number"))?;
+    tls::task_data()
+        .kill_process(&ProcessID(pid))
+        .ok_or(KernelError::Unexpected("no such process"))?;
+    Ok(String::new())
+}
+
+fn reboot_reason(mode: Option<&str>) -> KernelRes<RebootReason> {
+    match mode {
+        None => Ok(RebootReason::Requested),
+        Some("recovery") => Ok(RebootReason::Recovery),
+        Some("test") => Ok(RebootReason::TestRun),
+        Some(_) => Err(KernelError::Unexpected("usage: reboot [recovery|test]")),
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    #[kernel_test]
+    fn commands() {
+        assert!(execute("").unwrap().is_empty());
+        assert!(execute("help").unwrap().contains("kill <pid>"));
+        let root = execute("ls").unwrap();
+        assert!(root.lines().any(|name| name == "proc"));
+        assert!(
+            execute("ls /proc")
+                .unwrap()
+                .lines()
+                .any(|name| name == "tasks")
+        );
+        assert!(
+            execute("cat /proc/tasks")
+                .unwrap()
+                .starts_with(TaskList::HEADER)
+        );
+        assert!(execute("ps").unwrap().starts_with(TaskList::HEADER));
+        assert!(execute("mem").unwrap().starts_with("heap\t"));
+
+        assert!(execute("cat /does/not/exist").is_err());
+        assert!(execute("kill").is_err());
+        assert!(execute("kill x").is_err());
+        assert!(execute("kill 4242424242").is_err());
+        assert!(execute("frobnicate").is_err());
+        // fails before resetting anything
+        assert!(execute("reboot frobnicate").is_err());
+        assert!(matches!(
+            reboot_reason(Some("test")),
+            Ok(RebootReason::TestRun)
+        ));
+    }
+}
@@ -0,0 +1,38 @@
+use os_macros::Addr;
+
+use super::Addr;
+use crate::arch::mem::VirtAddr;
+
+/// the start of the canonical higher half, which holds the kernel, its heap and the direct map
+pub const KERNEL_START: u64 = 0xffff_8000_0000_0000;
+
+/// an address in the higher half, which only the kernel can access. User pointers cannot be turned into one
+#[repr(transparent)]
+#[derive(Addr, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct KernelVirtAddr {
+    inner: u64,
+}
+
+impl KernelVirtAddr {
+    /// addr, if it lies in the higher half
+    pub fn try_new(addr: u64) -> Option<Self> {
+        (addr >= KERNEL_START).then_some(Self { inner: addr })
+    }
+
+    pub fn from_ptr<T>(ptr: *const T) -> Option<Self> {
+        Self::try_new(ptr.addr() as u64)
+    }
+
+    pub fn as_u64(self) -> u64 {
+        self.inner
+    }
+
+    pub fn as_virt(self) -> VirtAddr {
+        // every address above KERNEL_START is canonical
+        VirtAddr::new(self.inner)
+    }
+
+    pub fn as_mut_ptr<T>(self) -> *mut T {
+        self.inner as *mut T
+    }
+}
@@ -1,6 +1,8 @@
 #![allow(dead_code)]
 
+mod kvirt;
 mod phys;
+mod uvirt;
 mod virt;
 
 use core::{
@@ -8,7 +10,9 @@ use core::{
     ops::{Add, Shl, ShlAssign, Shr, ShrAssign, Sub},
 };
 
+pub use kvirt::{KERNEL_START, KernelVirtAddr};
 pub use phys::PhysAddr;
+pub use uvirt::{USER_END, UserVirtAddr};
 pub use virt::VirtAddr;
 
 use crate::bootinfo;
@@ -36,3 +40,32 @@ trait Addr:
 pub fn virt_with_offset(addr: u64) -> VirtAddr {
     VirtAddr::new(addr + bootinfo::get_phys_offset())
 }
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use alloc::boxed::Box;
+
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    #[kernel_test]
+    fn address_halves() {
+        assert!(UserVirtAddr::try_new(0x40_0000).is_some());
+        assert!(UserVirtAddr::try_new(USER_END).is_none());
+        assert!(UserVirtAddr::try_new_range(USER_END - 0x10, 0x10).is_some());
+        assert!(UserVirtAddr::try_new_range(USER_END - 0x10, 0x20).is_none());
+        assert!(UserVirtAddr::try_new_range(0x1000, u64::MAX).is_none());
+        assert!(UserVirtAddr::from_ptr(core::ptr::null::<u8>(), 0).is_none());
+
+        // kernel memory is never a user address and the other way round
+        let kernel = Box::new(0_u64);
+        assert!(UserVirtAddr::from_ptr(&*kernel as *const u64, 1).is_none());
+        let addr = KernelVirtAddr::from_ptr(&*kernel as *const u64).unwrap();
+        assert_eq!(
+            addr.as_mut_ptr::<u64>().cast_const(),
+            &*kernel as *const u64
+        );
+        assert!(KernelVirtAddr::try_new(0x40_0000).is_none());
+    }
+}
@@ -0,0 +1,50 @@
+use os_macros::Addr;
+
+use super::Addr;
+use crate::arch::mem::VirtAddr;
+
+/// the end of the canonical lower half, which holds all user memory
+pub const USER_END: u64 = 1 << 47;
+
+/// an address in the lower half, i.e. one, which user code may have handed in.
+/// Kernel only mapping functions do not accept it
+#[repr(transparent)]
+#[derive(Addr, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct UserVirtAddr {
+    inner: u64,
+}
+
+impl UserVirtAddr {
+    /// addr, if it lies in user space
+    pub fn try_new(addr: u64) -> Option<Self> {
+        (addr < USER_END).then_some(Self { inner: addr })
+    }
+
+    /// addr, if all of addr..addr + len lies in user space
+    pub fn try_new_range(addr: u64, len: u64) -> Option<Self> {
+        let end = addr.checked_add(len)?;
+        (addr < USER_END && end <= USER_END).then_some(Self { inner: addr })
+    }
+
+    /// ptr, if it is not null and the len Ts it points to lie in user space
+    pub fn from_ptr<T>(ptr: *const T, len: usize) -> Option<Self> {
+        let bytes = len.checked_mul(size_of::<T>())?;
+        if ptr.is_null() {
+            return None;
+        }
+        Self::try_new_range(ptr.addr() as u64, bytes as u64)
+    }
+
+    pub fn as_u64(self) -> u64 {
+        self.inner
+    }
+
+    pub fn as_virt(self) -> VirtAddr {
+        // every address below USER_END is canonical
+        VirtAddr::new(self.inner)
+    }
+
+    pub fn as_mut_ptr<T>(self) -> *mut T {
+        self.inner as *mut T
+    }
+}
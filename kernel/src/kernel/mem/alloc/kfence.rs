@@ -0,0 +1,338 @@
+use core::{
+    alloc::Layout,
+    fmt::Display,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicU8, AtomicU64, AtomicUsize, Ordering},
+};
+
+use x86_64::structures::paging::page_table::PageTableEntry;
+
+use crate::{
+    arch::mem::{
+        FrameAllocator,
+        Mapper,
+        Page,
+        PageSize,
+        PageTable,
+        PageTableFlags,
+        Size4KiB,
+        VirtAddr,
+    },
+    kernel::mem::paging::{PAGETABLE, get_frame_alloc, get_hhdm_addr},
+};
+
+/// start of the guarded pool. Objects and guard pages alternate, starting and ending with a guard page.
+/// The pool fits into a single page table, which is shared by all address spaces
+pub const KFENCE_START: u64 = 0xffff_f100_0000_0000;
+pub const KFENCE_OBJECTS: usize = 255;
+const POOL_PAGES: usize = 2 * KFENCE_OBJECTS + 1;
+const PAGE_SIZE: usize = Size4KiB::SIZE as usize;
+
+// fills the unused part of an object page, such that writes before or after the object are caught on free
+const CANARY: u8 = 0xaa;
+const OBJECT_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::NO_EXECUTE)
+    .union(PageTableFlags::GLOBAL);
+
+const UNUSED: u8 = 0;
+const ALLOCATED: u8 = 1;
+const FREED: u8 = 2;
+
+struct Object {
+    state: AtomicU8,
+    addr: AtomicU64,
+    size: AtomicUsize,
+}
+
+static OBJECTS: [Object; KFENCE_OBJECTS] = [const {
+    Object {
+        state: AtomicU8::new(UNUSED),
+        addr: AtomicU64::new(0),
+        size: AtomicUsize::new(0),
+    }
+}; KFENCE_OBJECTS];
+
+// the page table mapping the pool, set once all object pages are mapped
+static P1: AtomicPtr<PageTable> = AtomicPtr::new(ptr::null_mut());
+static READY: AtomicBool = AtomicBool::new(false);
+static INTERVAL: AtomicUsize = AtomicUsize::new(100);
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+static SAMPLED: AtomicU64 = AtomicU64::new(0);
+
+fn page_addr(page: usize) -> u64 {
+    KFENCE_START + (page * PAGE_SIZE) as u64
+}
+
+fn object_page(idx: usize) -> usize {
+    2 * idx + 1
+}
+
+fn entry(page: usize) -> *mut PageTableEntry {
+    let p1 = P1.load(Ordering::Acquire);
+    unsafe { ptr::addr_of_mut!((*p1)[page]) }
+}
+
+/// makes the page of an object accessible or not. The pool table is never freed, thus this needs no lock
+fn set_present(idx: usize, present: bool) {
+    let page = object_page(idx);
+    let entry = unsafe { &mut *entry(page) };
+    let mut flags = entry.flags();
+    flags.set(PageTableFlags::PRESENT, present);
+    entry.set_flags(flags);
+    x86_64::instructions::tlb::flush(VirtAddr::new(page_addr(page)));
+}
+
+/// maps a frame behind every object page, and makes them inaccessible until they are allocated.
+/// Must run before the first address space is cloned, such that the pool is shared by all of them
+pub fn init() {
+    {
+        let mut table = PAGETABLE.lock();
+        let mut alloc = get_frame_alloc().lock();
+        for idx in 0..KFENCE_OBJECTS {
+            let page =
+                Page::<Size4KiB>::containing_address(VirtAddr::new(page_addr(object_page(idx))));
+            let frame = alloc
+                .allocate_frame()
+                .expect("no frames for the kfence pool");
+            unsafe { table.map_to(page, frame, OBJECT_FLAGS, &mut *alloc) }
+                .expect("kfence pool already mapped")
+                .flush();
+        }
+
+        let start = VirtAddr::new(KFENCE_START);
+        let offset = get_hhdm_addr();
+        let mut level = ptr::from_ref(table.level_4_table()).cast_mut();
+        for idx in [start.p4_index(), start.p3_index(), start.p2_index()] {
+            let entry = unsafe { &(*level)[idx] };
+            level = (offset + entry.addr().as_u64()) as *mut PageTable;
+        }
+        P1.store(level, Ordering::Release);
+    }
+    for idx in 0..KFENCE_OBJECTS {
+        set_present(idx, false);
+    }
+    READY.store(true, Ordering::Release);
+}
+
+/// sample every interval-th allocation. 0 disables sampling
+pub fn set_sample_interval(interval: usize) {
+    INTERVAL.store(interval, Ordering::Relaxed);
+}
+
+pub fn contains(ptr: *const u8) -> bool {
+    (KFENCE_START..page_addr(POOL_PAGES)).contains(&(ptr as u64))
+}
+
+/// places the allocation at the end of a free object page, if it was sampled.
+/// Takes no locks, thus it is safe in any context the heap may be used in
+pub fn try_alloc(layout: Layout) -> Option<*mut u8> {
+    if !READY.load(Ordering::Acquire)
+        || layout.size() == 0
+        || layout.size() > PAGE_SIZE
+        || layout.align() > PAGE_SIZE
+    {
+        return None;
+    }
+    let interval = INTERVAL.load(Ordering::Relaxed);
+    if interval == 0
+        || !ALLOCATIONS
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(interval)
+    {
+        return None;
+    }
+    // round robin, such that a freed object stays inaccessible for as long as possible
+    let idx = (0..KFENCE_OBJECTS)
+        .map(|_| NEXT.fetch_add(1, Ordering::Relaxed) % KFENCE_OBJECTS)
+        .find(|idx| {
+            let state = &OBJECTS[*idx].state;
+            state
+                .compare_exchange(FREED, ALLOCATED, Ordering::AcqRel, Ordering::Relaxed)
+                .or_else(|_| {
+                    state.compare_exchange(UNUSED, ALLOCATED, Ordering::AcqRel, Ordering::Relaxed)
+                })
+                .is_ok()
+        })?;
+
+    let page = page_addr(object_page(idx));
+    let end = page + PAGE_SIZE as u64;
+    // right aligned, such that overflows hit the next guard page
+    let addr = (end - layout.size() as u64) & !(layout.align() as u64 - 1);
+    set_present(idx, true);
+    unsafe {
+        ptr::write_bytes(page as *mut u8, CANARY, (addr - page) as usize);
+        let tail = addr + layout.size() as u64;
+        ptr::write_bytes(tail as *mut u8, CANARY, (end - tail) as usize);
+    }
+    OBJECTS[idx].addr.store(addr, Ordering::Relaxed);
+    OBJECTS[idx].size.store(layout.size(), Ordering::Relaxed);
+    SAMPLED.fetch_add(1, Ordering::Relaxed);
+    Some(addr as *mut u8)
+}
+
+/// frees ptr, if it is a guarded allocation, and makes its page inaccessible. Panics on double frees and overwritten canaries
+pub fn free(ptr: *mut u8) -> bool {
+    if !contains(ptr) {
+        return false;
+    }
+    let page = (ptr as u64 - KFENCE_START) as usize / PAGE_SIZE;
+    let idx = page / 2;
+    let object = &OBJECTS[idx];
+    let addr = object.addr.load(Ordering::Relaxed);
+    if page % 2 == 0 || object.state.load(Ordering::Acquire) != ALLOCATED || addr != ptr as u64 {
+        panic!("kfence: invalid free of {:p}", ptr);
+    }
+    let size = object.size.load(Ordering::Relaxed);
+    let start = page_addr(page);
+    let bytes = unsafe { core::slice::from_raw_parts(start as *const u8, PAGE_SIZE) };
+    let object_range = (addr - start) as usize..(addr - start) as usize + size;
+    if let Some(corrupted) = bytes
+        .iter()
+        .enumerate()
+        .position(|(i, b)| !object_range.contains(&i) && *b != CANARY)
+    {
+        panic!(
+            "kfence: memory corruption at {:#x} next to object {:#x} of size {}",
+            start + corrupted as u64,
+            addr,
+            size
+        );
+    }
+    set_present(idx, false);
+    object.state.store(FREED, Ordering::Release);
+    true
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KfenceFaultKind {
+    OutOfBounds,
+    UseAfterFree,
+    /// an object, which was never allocated
+    Invalid,
+}
+
+/// a fault inside the guarded pool, with the object it most likely belongs to
+#[derive(Debug, Clone, Copy)]
+pub struct KfenceFault {
+    pub kind: KfenceFaultKind,
+    pub addr: u64,
+    pub rip: u64,
+    pub object: Option<(u64, usize)>,
+}
+
+impl Display for KfenceFault {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let kind = match self.kind {
+            KfenceFaultKind::OutOfBounds => "out-of-bounds access",
+            KfenceFaultKind::UseAfterFree => "use-after-free",
+            KfenceFaultKind::Invalid => "invalid access",
+        };
+        write!(
+            f,
+            "kfence: {} at {:#x} (rip {:#x})",
+            kind, self.addr, self.rip
+        )?;
+        if let Some((addr, size)) = self.object {
+            write!(f, " to object {:#x} of size {}", addr, size)?;
+        }
+        Ok(())
+    }
+}
+
+fn allocated_object(idx: usize) -> Option<(u64, usize)> {
+    let object = OBJECTS.get(idx)?;
+    (object.state.load(Ordering::Acquire) == ALLOCATED).then(|| {
+        (
+            object.addr.load(Ordering::Relaxed),
+            object.size.load(Ordering::Relaxed),
+        )
+    })
+}
+
+/// decodes a page fault at addr. Returns None, if addr is outside of the pool
+pub fn fault_report(addr: u64, rip: u64) -> Option<KfenceFault> {
+    if !contains(addr as *const u8) {
+        return None;
+    }
+    let page = (addr - KFENCE_START) as usize / PAGE_SIZE;
+    let mut fault = KfenceFault {
+        kind: KfenceFaultKind::Invalid,
+        addr,
+        rip,
+        object: None,
+    };
+    if page % 2 == 1 {
+        let object = &OBJECTS[page / 2];
+        if object.state.load(Ordering::Acquire) == FREED {
+            fault.kind = KfenceFaultKind::UseAfterFree;
+            fault.object = Some((
+                object.addr.load(Ordering::Relaxed),
+                object.size.load(Ordering::Relaxed),
+            ));
+        }
+        return Some(fault);
+    }
+    // a guard page, the closest allocated neighbour was overrun
+    let left = (page / 2)
+        .checked_sub(1)
+        .and_then(allocated_object)
+        .map(|(obj, size)| (addr - (obj + size as u64), (obj, size)));
+    let right = allocated_object(page / 2).map(|(obj, size)| (obj - addr, (obj, size)));
+    if let Some((_, object)) = [left, right].into_iter().flatten().min_by_key(|(d, _)| *d) {
+        fault.kind = KfenceFaultKind::OutOfBounds;
+        fault.object = Some(object);
+    }
+    Some(fault)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KfenceStats {
+    /// allocations placed in the pool since boot
+    pub sampled: u64,
+    /// objects currently allocated
+    pub allocated: usize,
+}
+
+pub fn kfence_stats() -> KfenceStats {
+    KfenceStats {
+        sampled: SAMPLED.load(Ordering::Relaxed),
+        allocated: OBJECTS
+            .iter()
+            .filter(|object| object.state.load(Ordering::Relaxed) == ALLOCATED)
+            .count(),
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use alloc::boxed::Box;
+
+    use os_macros::kernel_test;
+
+    use super::*;
+    use crate::kernel::mem::paging::active_page_flags;
+
+    #[kernel_test]
+    fn guarded_allocation() {
+        set_sample_interval(1);
+        let boxed = Box::new([1u8; 40]);
+        set_sample_interval(100);
+        let addr = ptr::from_ref(&*boxed) as u64;
+        assert!(contains(addr as *const u8));
+        // right aligned to the guard page
+        assert_eq!((addr + 40) % PAGE_SIZE as u64, 0);
+        assert_eq!(
+            fault_report(addr + 40, 0).unwrap().kind,
+            KfenceFaultKind::OutOfBounds
+        );
+        assert_eq!(fault_report(addr + 40, 0).unwrap().object, Some((addr, 40)));
+
+        drop(boxed);
+        assert!(active_page_flags(VirtAddr::new(addr)).is_none());
+        let fault = fault_report(addr, 0).unwrap();
+        assert_eq!(fault.kind, KfenceFaultKind::UseAfterFree);
+        assert!(fault_report(KFENCE_START - 1, 0).is_none());
+    }
+}
@@ -1,13 +1,19 @@
 use core::{
     alloc::{GlobalAlloc, Layout},
     ptr::{NonNull, null_mut},
+    sync::atomic::Ordering,
 };
 
 use linked_list_allocator::Heap;
 
-use crate::sync::{
-    YieldWaiter,
-    locks::{GenericMutex, GenericMutexGuard},
+use super::{FAILURES, IRQ_CHECK};
+use crate::{
+    arch::interrupt,
+    kernel::threading,
+    sync::{
+        YieldWaiter,
+        locks::{GenericMutex, GenericMutexGuard},
+    },
 };
 
 pub(super) const fn get_alloc() -> SafeHeap {
@@ -38,13 +44,30 @@ impl SafeHeap {
 
 unsafe impl GlobalAlloc for SafeHeap {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if IRQ_CHECK.load(Ordering::Relaxed) && threading::is_running() && !interrupt::are_enabled()
+        {
+            // the panic handler may allocate itself
+            IRQ_CHECK.store(false, Ordering::Relaxed);
+            panic!("allocation of {:?} with interrupts disabled", layout);
+        }
+        #[cfg(feature = "kfence")]
+        if let Some(ptr) = super::kfence::try_alloc(layout) {
+            return ptr;
+        }
         match self.lock().allocate_first_fit(layout) {
             Ok(ptr) => ptr.as_ptr(),
-            Err(_) => null_mut(),
+            Err(_) => {
+                FAILURES.inc();
+                null_mut()
+            }
         }
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        #[cfg(feature = "kfence")]
+        if super::kfence::free(ptr) {
+            return;
+        }
         if let Some(nn_ptr) = NonNull::new(ptr) {
             unsafe { self.lock().deallocate(nn_ptr, layout) };
         }
@@ -1,6 +1,109 @@
+use alloc::{
+    boxed::Box,
+    collections::{binary_heap::BinaryHeap, vec_deque::VecDeque},
+    sync::Arc,
+    vec::Vec,
+};
+pub use core::alloc::AllocError;
+use core::sync::atomic::{AtomicBool, Ordering};
+
 use linked_list::SafeHeap;
 
+use crate::sync::counter::PerCpuCounter;
+
+#[cfg(feature = "kfence")]
+pub mod kfence;
 mod linked_list;
 
 #[global_allocator]
 pub static GLOBAL_ALLOCATOR: SafeHeap = linked_list::get_alloc();
+
+static FAILURES: PerCpuCounter = PerCpuCounter::new();
+static IRQ_CHECK: AtomicBool = AtomicBool::new(cfg!(feature = "debug_alloc"));
+
+/// number of allocations the heap could not satisfy
+pub fn alloc_failures() -> u64 {
+    FAILURES.get()
+}
+
+/// enables the debug mode, which panics on any allocation made with interrupts disabled once threading runs.
+/// Such an allocation may deadlock on the heap lock held by the interrupted task.
+/// Enabled from boot with the debug_alloc feature
+pub fn set_irq_alloc_check(enabled: bool) {
+    IRQ_CHECK.store(enabled, Ordering::Relaxed);
+}
+
+pub fn irq_alloc_check() -> bool {
+    IRQ_CHECK.load(Ordering::Relaxed)
+}
+
+/// allocates value on the heap, without aborting if the heap is exhausted
+pub fn try_box<T>(value: T) -> Result<Box<T>, AllocError> {
+    Box::try_new(value)
+}
+
+/// allocates value in an Arc, without aborting if the heap is exhausted
+pub fn try_arc<T>(value: T) -> Result<Arc<T>, AllocError> {
+    Arc::try_new(value)
+}
+
+/// fallible push for the collections used on critical paths. On failure the value is handed back to the caller
+pub trait TryPush<T> {
+    fn try_push(&mut self, value: T) -> Result<(), T>;
+}
+
+impl<T> TryPush<T> for Vec<T> {
+    fn try_push(&mut self, value: T) -> Result<(), T> {
+        if self.try_reserve(1).is_err() {
+            return Err(value);
+        }
+        self.push(value);
+        Ok(())
+    }
+}
+
+impl<T> TryPush<T> for VecDeque<T> {
+    fn try_push(&mut self, value: T) -> Result<(), T> {
+        if self.try_reserve(1).is_err() {
+            return Err(value);
+        }
+        self.push_back(value);
+        Ok(())
+    }
+}
+
+impl<T: Ord> TryPush<T> for BinaryHeap<T> {
+    fn try_push(&mut self, value: T) -> Result<(), T> {
+        if self.try_reserve(1).is_err() {
+            return Err(value);
+        }
+        self.push(value);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    #[kernel_test]
+    fn fallible_alloc() {
+        let failures = alloc_failures();
+        let mut v: Vec<u8> = Vec::new();
+        assert!(v.try_reserve(usize::MAX / 2).is_err());
+        assert!(v.try_push(1).is_ok());
+        let mut huge: Vec<[u8; 4096]> = Vec::new();
+        // larger than the whole heap
+        assert!(huge.try_reserve_exact(1 << 20).is_err());
+        assert!(alloc_failures() > failures);
+        assert_eq!(*try_box(3).unwrap(), 3);
+
+        let was_enabled = irq_alloc_check();
+        set_irq_alloc_check(true);
+        let boxed = try_arc([0u8; 64]).unwrap();
+        set_irq_alloc_check(was_enabled);
+        assert_eq!(boxed.len(), 64);
+    }
+}
@@ -26,6 +26,8 @@ pub fn init() {
         };
     }
     alloc::GLOBAL_ALLOCATOR.init(HEAP_START as *mut u8, HEAP_SIZE);
+    #[cfg(feature = "kfence")]
+    alloc::kfence::init();
 }
 
 pub fn map_heap(tbl: &mut crate::arch::mem::OffsetPageTable) {
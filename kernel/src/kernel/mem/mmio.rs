@@ -0,0 +1,220 @@
+use alloc::{boxed::Box, collections::btree_map::BTreeMap, vec::Vec};
+
+use thiserror::Error;
+
+use crate::{
+    arch::mem::{
+        Mapper,
+        Page,
+        PageSize,
+        PageTableFlags,
+        PhysAddr,
+        PhysFrame,
+        Size4KiB,
+        VirtAddr,
+        mapper::MapToError,
+    },
+    bootinfo,
+    kernel::mem::paging::{PAGETABLE, get_frame_alloc},
+    sync::locks::Mutex,
+};
+
+/// device registers must neither be cached nor have their accesses combined
+const FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::NO_CACHE)
+    .union(PageTableFlags::WRITE_THROUGH)
+    .union(PageTableFlags::NO_EXECUTE);
+
+/// the page aligned physical ranges of all live regions, by start
+static RESERVED: Mutex<BTreeMap<u64, u64>> = Mutex::new(BTreeMap::new());
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MmioError {
+    #[error("the region is empty")]
+    Empty,
+    #[error("the region overlaps the one at {0:#x}")]
+    Overlap(u64),
+    #[error("the region could not be mapped")]
+    Map,
+}
+
+/// the register widths, which may be read and written
+pub trait MmioValue: Copy {}
+
+impl MmioValue for u8 {}
+impl MmioValue for u16 {}
+impl MmioValue for u32 {}
+impl MmioValue for u64 {}
+
+/// device memory mapped uncached into the direct map. Its physical range is reserved, such that no other region
+/// overlaps it, and unmapped again on drop
+#[derive(Debug)]
+pub struct MmioRegion {
+    phys: u64,
+    len: usize,
+    base: *mut u8,
+    /// the pages mapped by this region. Pages, which were mapped before, e.g. by the bootloader, are left alone on drop
+    mapped: Vec<Page<Size4KiB>>,
+}
+
+// SAFETY: all accesses are volatile and the region does not alias any memory of the kernel
+unsafe impl Send for MmioRegion {}
+unsafe impl Sync for MmioRegion {}
+
+/// maps len bytes of device memory at phys
+pub fn ioremap(phys: u64, len: usize) -> Result<MmioRegion, MmioError> {
+    if len == 0 {
+        return Err(MmioError::Empty);
+    }
+    let start = phys & !(Size4KiB::SIZE - 1);
+    let end = (phys + len as u64).next_multiple_of(Size4KiB::SIZE);
+    reserve(start, end)?;
+
+    let offset = bootinfo::get_phys_offset();
+    let mut region = MmioRegion {
+        phys,
+        len,
+        base: (phys + offset) as *mut u8,
+        mapped: Vec::new(),
+    };
+    let mut table = PAGETABLE.lock();
+    let mut frames = get_frame_alloc().lock();
+    let mut result = Ok(());
+    for frame in PhysFrame::<Size4KiB>::range(
+        PhysFrame::containing_address(PhysAddr::new(start)),
+        PhysFrame::containing_address(PhysAddr::new(end)),
+    ) {
+        let page = Page::containing_address(VirtAddr::new(frame.start_address().as_u64() + offset));
+        match unsafe { table.map_to(page, frame, FLAGS, &mut *frames) } {
+            Ok(flush) => {
+                flush.flush();
+                region.mapped.push(page);
+            }
+            // the direct map may already cover the range, its caching is fixed up, if the page is not part of a huge one
+            Err(MapToError::PageAlreadyMapped(_)) => {
+                if let Ok(flush) = unsafe { table.update_flags(page, FLAGS) } {
+                    flush.flush();
+                }
+            }
+            Err(MapToError::ParentEntryHugePage) => {}
+            Err(MapToError::FrameAllocationFailed) => {
+                result = Err(MmioError::Map);
+                break;
+            }
+        }
+    }
+    drop((table, frames));
+    // on failure, dropping the region unmaps what was mapped so far
+    result.map(|()| region)
+}
+
+/// reserves the page aligned physical range from start to end, if it does not overlap any other region
+fn reserve(start: u64, end: u64) -> Result<(), MmioError> {
+    let mut reserved = RESERVED.lock();
+    if let Some((&other, _)) = reserved
+        .range(..end)
+        .next_back()
+        .filter(|(_, other_end)| **other_end > start)
+    {
+        return Err(MmioError::Overlap(other));
+    }
+    reserved.insert(start, end);
+    Ok(())
+}
+
+fn release(start: u64) {
+    RESERVED.lock().remove(&start);
+}
+
+impl MmioRegion {
+    pub fn phys_addr(&self) -> u64 {
+        self.phys
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_mut_ptr(&self) -> *mut u8 {
+        self.base
+    }
+
+    fn ptr<T: MmioValue>(&self, offset: usize) -> *mut T {
+        assert!(
+            offset + size_of::<T>() <= self.len,
+            "mmio access at {:#x} beyond the region of {:#x} bytes",
+            offset,
+            self.len
+        );
+        let ptr = self.base.wrapping_add(offset).cast::<T>();
+        assert!(ptr.is_aligned(), "unaligned mmio access at {:#x}", offset);
+        ptr
+    }
+
+    /// reads the register at offset bytes into the region
+    pub fn read<T: MmioValue>(&self, offset: usize) -> T {
+        unsafe { self.ptr::<T>(offset).read_volatile() }
+    }
+
+    /// writes the register at offset bytes into the region
+    pub fn write<T: MmioValue>(&self, offset: usize, value: T) {
+        unsafe { self.ptr::<T>(offset).write_volatile(value) }
+    }
+
+    /// keeps the region mapped and reserved forever, for registers used until shutdown
+    pub fn leak(self) -> &'static Self {
+        Box::leak(Box::new(self))
+    }
+}
+
+impl Drop for MmioRegion {
+    fn drop(&mut self) {
+        {
+            let mut table = PAGETABLE.lock();
+            for page in self.mapped.drain(..) {
+                // the frames are device memory, they are not returned to the frame allocator
+                if let Ok((_, flush)) = table.unmap(page) {
+                    flush.flush();
+                }
+            }
+        }
+        release(self.phys & !(Size4KiB::SIZE - 1));
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    // far beyond any device, the reservations are tested without mapping anything
+    const BASE: u64 = 0x7fff_0000_0000;
+
+    #[kernel_test]
+    fn reservations() {
+        assert_eq!(ioremap(BASE, 0).unwrap_err(), MmioError::Empty);
+        assert_eq!(reserve(BASE, BASE + 0x2000), Ok(()));
+        assert_eq!(
+            reserve(BASE + 0x1000, BASE + 0x3000),
+            Err(MmioError::Overlap(BASE))
+        );
+        assert_eq!(
+            reserve(BASE - 0x1000, BASE + 0x1000),
+            Err(MmioError::Overlap(BASE))
+        );
+        // neighbours do not overlap
+        assert_eq!(reserve(BASE + 0x2000, BASE + 0x3000), Ok(()));
+        assert_eq!(reserve(BASE - 0x1000, BASE), Ok(()));
+        for start in [BASE - 0x1000, BASE, BASE + 0x2000] {
+            release(start);
+        }
+        assert_eq!(reserve(BASE, BASE + 0x3000), Ok(()));
+        release(BASE);
+    }
+}
@@ -1,16 +1,19 @@
-use os_macros::kernel_test;
+use os_macros::{init_task, kernel_test};
 
 use crate::kernel::mem::paging::init_frame_alloc;
 
 pub mod addr;
 pub mod alloc;
 pub mod heap;
+pub mod mmio;
 pub mod paging;
+pub mod vma;
 
 pub fn init_paging() {
     init_frame_alloc();
 }
 
+#[init_task(stage = "early")]
 pub fn init() {
     heap::init();
 }
@@ -1,8 +1,13 @@
 //TODO
 
-use core::ptr::null_mut;
+use alloc::{format, string::String, vec::Vec};
+use core::{
+    ptr::null_mut,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
 use conquer_once::spin::OnceCell;
+use os_macros::{FileRepr, init_task};
 
 use crate::{
     arch::mem::{
@@ -16,9 +21,22 @@ use crate::{
         align_up,
     },
     bootinfo::{get_phys_offset, usable_mmap_entries},
+    kernel::{
+        io::{IOResult, Read, read_rendered},
+        threading::{self, group::ResourceGroup, schedule::GlobalTaskPtr, tls},
+    },
     sync::locks::Mutex,
 };
 
+// freed frames are filled with this in debug builds, such that stale mappings read garbage rather than old data
+const POISON: u64 = 0x6b6b_6b6b_6b6b_6b6b;
+// frames zeroed per lock acquisition of the background task
+const ZERO_BATCH: usize = 32;
+
+// the background task zeroing dirty frames and whether it is parked, waiting for frames to be freed
+static ZEROING_TASK: OnceCell<GlobalTaskPtr> = OnceCell::uninit();
+static ZEROING_IDLE: AtomicBool = AtomicBool::new(false);
+
 pub type GlobalFrameAllocator = LinkedListFrameAllocator;
 pub static GLOBAL_FRAME_ALLOCATOR: OnceCell<Mutex<GlobalFrameAllocator>> = OnceCell::uninit();
 
@@ -30,17 +48,53 @@ pub fn get_frame_alloc<'a>() -> &'a Mutex<GlobalFrameAllocator> {
     GLOBAL_FRAME_ALLOCATOR.get().unwrap()
 }
 
+/// hands out zeroed frames only.
+/// Freed frames and frames fresh from the memory map go onto the dirty list and are zeroed in batches by a background task,
+/// which moves them onto the clean list. If no clean frame is left, a dirty one is zeroed on allocation
 pub struct LinkedListFrameAllocator {
+    // the frames are linked through their first word, which is the only non zero word of a clean frame
     head: *mut u64,
+    dirty: *mut u64,
     current_batch_end: usize,
+    // frames currently handed out
+    allocated: usize,
+    clean_frames: usize,
+    dirty_frames: usize,
+    // allocations, which found no free frame
+    failures: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FrameStats {
+    pub allocated: usize,
+    /// free frames, including those not taken from the memory map yet
+    pub free: usize,
+    pub clean: usize,
+    pub dirty: usize,
+    /// the most physically contiguous frames, which are all free
+    pub largest_run: usize,
+    pub failures: u64,
+}
+
+impl FrameStats {
+    pub fn render(&self) -> String {
+        format!(
+            "allocated\t{}\nfree\t{}\nclean\t{}\ndirty\t{}\nlargest_run\t{}\nfailures\t{}\n",
+            self.allocated, self.free, self.clean, self.dirty, self.largest_run, self.failures
+        )
+    }
 }
 
 impl LinkedListFrameAllocator {
     fn new() -> Self {
-        let initial = null_mut();
         let mut alloc = Self {
-            head: initial,
+            head: null_mut(),
+            dirty: null_mut(),
             current_batch_end: 0,
+            allocated: 0,
+            clean_frames: 0,
+            dirty_frames: 0,
+            failures: 0,
         };
         alloc.add_batch();
         alloc
@@ -56,50 +110,334 @@ impl LinkedListFrameAllocator {
             .map(|r| PhysFrame::<Size4KiB>::containing_address(PhysAddr::new(r)));
 
         let next_batch = frames.skip(self.current_batch_end).take(10000);
+        // the bootloader does not clear memory
         for frame in next_batch {
             unsafe {
-                self.deallocate_frame(frame);
+                self.push_dirty(frame);
             }
             self.current_batch_end += 1;
         }
     }
-}
 
-impl FrameDeallocator<Size4KiB> for LinkedListFrameAllocator {
-    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+    unsafe fn push_dirty(&mut self, frame: PhysFrame<Size4KiB>) {
         // write current head into frame and point head to frame
+        let addr = frame_ptr(frame);
+        unsafe { addr.write(self.dirty as u64) };
+        self.dirty = addr;
+        self.dirty_frames += 1;
+    }
 
-        let addr = (frame.start_address().as_u64() + get_phys_offset()) as *mut u64;
+    /// frame must be zeroed, apart from its first word
+    unsafe fn push_clean(&mut self, frame: PhysFrame<Size4KiB>) {
+        let addr = frame_ptr(frame);
         unsafe { addr.write(self.head as u64) };
         self.head = addr;
+        self.clean_frames += 1;
     }
-}
 
-unsafe impl FrameAllocator<Size4KiB> for LinkedListFrameAllocator {
-    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
-        // get current frame from head and update head to point to next
+    fn pop_dirty(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        if self.dirty.is_null() {
+            return None;
+        }
+        let addr = self.dirty;
+        self.dirty = unsafe { *addr } as *mut u64;
+        self.dirty_frames -= 1;
+        Some(frame_at(addr))
+    }
+
+    fn pop_clean(&mut self) -> Option<PhysFrame<Size4KiB>> {
         if self.head.is_null() {
-            self.add_batch();
-            if self.head.is_null() {
-                // tried to add more frames, but none are available
-                return None;
+            return None;
+        }
+        let addr = self.head;
+        unsafe {
+            self.head = *addr as *mut u64;
+            // clears the link, the rest of the frame already is zero
+            addr.write(0);
+        }
+        self.clean_frames -= 1;
+        Some(frame_at(addr))
+    }
+
+    /// allocates a frame charged to group. Fails, if group or one of its ancestors reached its memory cap
+    pub fn allocate_frame_for(&mut self, group: &ResourceGroup) -> Option<PhysFrame<Size4KiB>> {
+        if !group.try_charge_frames(1) {
+            return None;
+        }
+        let frame = self.allocate_frame();
+        if frame.is_none() {
+            group.uncharge_frames(1);
+        }
+        frame
+    }
+
+    /// number of frames currently allocated
+    pub fn allocated(&self) -> usize {
+        self.allocated
+    }
+
+    /// number of free frames, which are already zeroed
+    pub fn clean_frames(&self) -> usize {
+        self.clean_frames
+    }
+
+    /// number of free frames, which still need to be zeroed
+    pub fn dirty_frames(&self) -> usize {
+        self.dirty_frames
+    }
+
+    /// walks both free lists and the memory map to find the largest run of free frames
+    pub fn stats(&self) -> FrameStats {
+        // (first frame number, length) of free runs
+        let mut runs = Vec::with_capacity(self.clean_frames + self.dirty_frames);
+        for mut addr in [self.head, self.dirty] {
+            while !addr.is_null() {
+                runs.push((frame_at(addr).start_address().as_u64() / Size4KiB::SIZE, 1));
+                addr = unsafe { *addr } as *mut u64;
+            }
+        }
+        // the frames behind current_batch_end were never handed out
+        let mut seen = 0;
+        let mut untouched = 0;
+        for region in usable_mmap_entries() {
+            let start = align_up(region.start, Size4KiB::SIZE) / Size4KiB::SIZE;
+            let end = align_down(region.start + region.length, Size4KiB::SIZE) / Size4KiB::SIZE;
+            let frames = end.saturating_sub(start) as usize;
+            let taken = self.current_batch_end.saturating_sub(seen).min(frames);
+            if taken < frames {
+                runs.push((start + taken as u64, (frames - taken) as u64));
+                untouched += frames - taken;
             }
+            seen += frames;
+        }
+        FrameStats {
+            allocated: self.allocated,
+            free: self.clean_frames + self.dirty_frames + untouched,
+            clean: self.clean_frames,
+            dirty: self.dirty_frames,
+            largest_run: largest_run(&mut runs) as usize,
+            failures: self.failures,
         }
+    }
+}
 
-        let next_head = unsafe { *self.head };
-        let current_phys = self.head as u64 - get_phys_offset();
-        self.head = next_head as *mut u64;
+/// the length of the longest run of adjacent runs, given as (start, length)
+fn largest_run(runs: &mut [(u64, u64)]) -> u64 {
+    runs.sort_unstable();
+    let mut largest = 0;
+    let mut current: Option<(u64, u64)> = None;
+    for &(start, len) in runs.iter() {
+        current = match current {
+            Some((first, n)) if first + n == start => Some((first, n + len)),
+            _ => Some((start, len)),
+        };
+        largest = largest.max(current.map_or(0, |(_, n)| n));
+    }
+    largest
+}
 
-        let frame = PhysFrame::containing_address(PhysAddr::new(current_phys));
-        unsafe {
-            core::ptr::write_bytes(
-                (frame.start_address().as_u64() + get_phys_offset()) as *mut u8,
-                0,
-                Size4KiB::SIZE as usize,
-            );
+fn frame_ptr(frame: PhysFrame<Size4KiB>) -> *mut u64 {
+    (frame.start_address().as_u64() + get_phys_offset()) as *mut u64
+}
+
+fn frame_at(addr: *mut u64) -> PhysFrame<Size4KiB> {
+    PhysFrame::containing_address(PhysAddr::new(addr as u64 - get_phys_offset()))
+}
+
+fn zero_frame(frame: PhysFrame<Size4KiB>) {
+    unsafe { core::ptr::write_bytes(frame_ptr(frame), 0, Size4KiB::SIZE as usize / 8) };
+}
+
+impl FrameDeallocator<Size4KiB> for LinkedListFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        if cfg!(debug_assertions) {
+            unsafe {
+                core::ptr::write_bytes(
+                    frame_ptr(frame).cast::<u8>(),
+                    POISON as u8,
+                    Size4KiB::SIZE as usize,
+                )
+            };
         }
+        unsafe { self.push_dirty(frame) };
+        self.allocated = self.allocated.saturating_sub(1);
+        wake_frame_zeroing();
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for LinkedListFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        let frame = if let Some(frame) = self.pop_clean() {
+            frame
+        } else {
+            if self.dirty.is_null() {
+                self.add_batch();
+            }
+            // tried to add more frames, but none are available
+            let Some(frame) = self.pop_dirty() else {
+                self.failures += 1;
+                return None;
+            };
+            zero_frame(frame);
+            frame
+        };
+        self.allocated += 1;
         Some(frame)
     }
 }
 
+/// zeroes up to ZERO_BATCH dirty frames and moves them onto the clean list. Returns the number of frames zeroed.
+/// The frames are zeroed without holding the allocator lock
+pub fn zero_dirty_frames() -> usize {
+    let mut batch = [None; ZERO_BATCH];
+    {
+        let mut alloc = get_frame_alloc().lock();
+        for slot in batch.iter_mut() {
+            *slot = alloc.pop_dirty();
+        }
+    }
+    let frames = batch.iter().map_while(|frame| *frame);
+    for frame in frames.clone() {
+        zero_frame(frame);
+    }
+    let mut alloc = get_frame_alloc().lock();
+    let mut zeroed = 0;
+    for frame in frames {
+        unsafe { alloc.push_clean(frame) };
+        zeroed += 1;
+    }
+    zeroed
+}
+
+/// wakes the zeroing task, if it is parked waiting for freed frames. This only touches atomics and the event queue,
+/// thus it may be called with the frame allocator locked
+fn wake_frame_zeroing() {
+    if ZEROING_IDLE.swap(false, Ordering::AcqRel)
+        && let Some(task) = ZEROING_TASK.get()
+    {
+        task.unpark();
+    }
+}
+
+/// keeps the clean list filled, such that allocations rarely need to zero a frame themselves.
+/// Once the dirty list is drained, the task parks until the next frame is freed
+#[init_task(stage = "drivers", order = 30)]
+pub fn start_frame_zeroing() {
+    threading::spawn(|| {
+        if let Some(current) = tls::task_data().current_thread() {
+            ZEROING_TASK.init_once(|| current);
+        }
+        loop {
+            if zero_dirty_frames() > 0 {
+                threading::yield_now();
+                continue;
+            }
+            ZEROING_IDLE.store(true, Ordering::Release);
+            // a frame freed before the flag was set would not wake the task
+            if get_frame_alloc().lock().dirty_frames() == 0 {
+                threading::park();
+            }
+            ZEROING_IDLE.store(false, Ordering::Release);
+        }
+    })
+    .unwrap();
+}
+
 unsafe impl Send for LinkedListFrameAllocator {}
+
+pub const FRAMES_FILE: &str = "/kernel/frames";
+
+/// /proc/kernel/frames: the counters of the frame allocator and the largest run of contiguous free frames
+#[derive(Debug, Default, Clone, Copy, FileRepr)]
+#[file(read)]
+pub struct Frames;
+
+impl Read for Frames {
+    fn read(&self, buf: &mut [u8], offset: usize) -> IOResult<usize> {
+        let stats = get_frame_alloc().lock().stats();
+        read_rendered(stats.render(), buf, offset)
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use alloc::vec::Vec;
+
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    fn frame_words(frame: PhysFrame<Size4KiB>) -> &'static [u64] {
+        unsafe { core::slice::from_raw_parts(frame_ptr(frame), Size4KiB::SIZE as usize / 8) }
+    }
+
+    #[kernel_test]
+    fn freed_frames_are_zeroed() {
+        let frame = get_frame_alloc().lock().allocate_frame().unwrap();
+        assert!(frame_words(frame).iter().all(|word| *word == 0));
+        unsafe { core::ptr::write_bytes(frame_ptr(frame), 0xff, Size4KiB::SIZE as usize / 8) };
+
+        let dirty = get_frame_alloc().lock().dirty_frames();
+        unsafe { get_frame_alloc().lock().deallocate_frame(frame) };
+        assert!(get_frame_alloc().lock().dirty_frames() > dirty);
+        if cfg!(debug_assertions) {
+            assert_eq!(frame_words(frame)[1], POISON);
+        }
+
+        // every frame handed out is zeroed, either in the background or on allocation
+        let mut alloc = get_frame_alloc().lock();
+        let frames: Vec<_> = (0..64).map_while(|_| alloc.allocate_frame()).collect();
+        for frame in &frames {
+            assert!(frame_words(*frame).iter().all(|word| *word == 0));
+        }
+        for frame in frames {
+            unsafe { alloc.deallocate_frame(frame) };
+        }
+    }
+
+    #[kernel_test]
+    fn fragment_and_coalesce() {
+        let mut alloc = get_frame_alloc().lock();
+        let before = alloc.stats();
+        let total = before.allocated + before.free;
+        assert!(before.largest_run <= before.free);
+
+        let frames: Vec<_> = (0..64).map_while(|_| alloc.allocate_frame()).collect();
+        let held = alloc.stats();
+        assert_eq!(held.allocated, before.allocated + frames.len());
+        assert_eq!(held.allocated + held.free, total);
+
+        // every other frame, thus none of the freed frames border each other
+        let (even, odd): (Vec<_>, Vec<_>) =
+            frames.iter().enumerate().partition(|(idx, _)| idx % 2 == 0);
+        for (_, frame) in &even {
+            unsafe { alloc.deallocate_frame(**frame) };
+        }
+        let fragmented = alloc.stats();
+        assert_eq!(fragmented.free, held.free + even.len());
+        assert!(fragmented.largest_run >= held.largest_run);
+        assert!(fragmented.largest_run <= fragmented.free);
+
+        for (_, frame) in &odd {
+            unsafe { alloc.deallocate_frame(**frame) };
+        }
+        let coalesced = alloc.stats();
+        assert_eq!(coalesced.allocated, before.allocated);
+        assert_eq!(coalesced.free, before.free);
+        assert!(coalesced.largest_run >= fragmented.largest_run);
+        // the frames handed out together join again, once all of them are free
+        let mut own = frames
+            .iter()
+            .map(|frame| (frame.start_address().as_u64() / Size4KiB::SIZE, 1))
+            .collect::<Vec<_>>();
+        assert!(coalesced.largest_run as u64 >= largest_run(&mut own));
+        assert_eq!(coalesced.failures, before.failures);
+    }
+
+    #[kernel_test]
+    fn run_lengths() {
+        assert_eq!(largest_run(&mut []), 0);
+        assert_eq!(largest_run(&mut [(7, 1), (3, 2), (5, 2), (10, 1)]), 6);
+        assert_eq!(largest_run(&mut [(0, 4), (8, 1), (9, 1)]), 4);
+    }
+}
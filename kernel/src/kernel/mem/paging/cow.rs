@@ -0,0 +1,337 @@
+use alloc::vec::Vec;
+
+use x86_64::structures::paging::page_table::PageTableEntry;
+
+use super::{
+    BORROWED,
+    FaultResolution,
+    TaskPageTable,
+    active_page_flags,
+    create_new_pagedir,
+    frame::{free_frame, share_frame, try_frame_refcount, try_release_frame},
+    get_frame_alloc,
+    get_hhdm_addr,
+};
+use crate::{
+    arch::{
+        current_page_tbl,
+        mem::{
+            FrameAllocator,
+            FrameDeallocator,
+            Mapper,
+            Page,
+            PageSize,
+            PageTable,
+            PageTableFlags,
+            PhysFrame,
+            Size4KiB,
+            VirtAddr,
+        },
+    },
+    kernel::threading::{task::TaskRepr, tls},
+};
+
+/// marks a user page, which is shared copy on write between address spaces. It is mapped read only until the first write
+pub const COW: PageTableFlags = PageTableFlags::BIT_9;
+
+pub(super) fn table_at(frame: PhysFrame<Size4KiB>) -> *mut PageTable {
+    (get_hhdm_addr() + frame.start_address().as_u64()) as *mut PageTable
+}
+
+/// the table entry points to, allocating an empty one with flags, if it is unused
+pub(super) fn next_table_or_create<A: FrameAllocator<Size4KiB>>(
+    entry: &mut PageTableEntry,
+    flags: PageTableFlags,
+    alloc: &mut A,
+) -> Result<*mut PageTable, &'static str> {
+    if entry.is_unused() {
+        // frames are zeroed by the allocator
+        let frame = alloc.allocate_frame().ok_or("no frame available")?;
+        entry.set_frame(frame, flags);
+    }
+    entry
+        .frame()
+        .map(table_at)
+        .map_err(|_| "unexpected huge page in the lower half")
+}
+
+impl TaskPageTable<'_> {
+    /// creates a new address space, which shares all user pages of this one copy on write.
+    /// Only the page table structure is copied, the shared frames are refcounted and writable pages are remapped read only
+    /// in both address spaces, until one of them writes to them.
+    /// Kernel mappings are set up like in create_new_pagedir. Huge user pages are not supported and result in an error.
+    /// On error the partially built child is torn down again, which drops the references it took. Pages of self, which
+    /// were remapped copy on write already, stay so, as the first write simply makes them writable again
+    pub fn clone_cow(&mut self) -> Result<TaskPageTable<'static>, &'static str> {
+        let child = create_new_pagedir()?;
+        let shared = self.share_cow_into(&child);
+        // writable pages of self were remapped read only, even if sharing failed halfway
+        if current_page_tbl().0 == self.root {
+            x86_64::instructions::tlb::flush_all();
+        }
+        match shared {
+            Ok(()) => Ok(child),
+            Err(e) => {
+                // SAFETY: child was never active and no pointers into it exist
+                unsafe { child.cleanup() };
+                Err(e)
+            }
+        }
+    }
+
+    /// maps every user page of self into child, remapping writable ones copy on write. Each frame mapped into child
+    /// holds a reference, unless it is borrowed
+    fn share_cow_into(&mut self, child: &TaskPageTable<'_>) -> Result<(), &'static str> {
+        let split = ((get_hhdm_addr() >> 39) & 0x1ff) as usize;
+        let mut alloc = get_frame_alloc().lock();
+
+        // SAFETY: both tables are reachable through the hhdm, we own self and child was just created
+        let (src, dst) = unsafe { (&mut *table_at(self.root), &mut *table_at(child.root)) };
+        for i4 in 0..split {
+            let Ok(p3_frame) = src[i4].frame() else {
+                continue;
+            };
+            let p3 = unsafe { &mut *table_at(p3_frame) };
+            for i3 in 0..512 {
+                if p3[i3].is_unused() {
+                    continue;
+                }
+                let p2 = unsafe { &mut *table_at(p3[i3].frame().map_err(|_| "huge user page")?) };
+                for i2 in 0..512 {
+                    if p2[i2].is_unused() {
+                        continue;
+                    }
+                    let p1 =
+                        unsafe { &mut *table_at(p2[i2].frame().map_err(|_| "huge user page")?) };
+                    for i1 in 0..512 {
+                        let leaf = &mut p1[i1];
+                        let mut flags = leaf.flags();
+                        // kernel mappings, like the heap, are already set up in the child
+                        if !flags
+                            .contains(PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE)
+                        {
+                            continue;
+                        }
+                        let borrowed = flags.contains(BORROWED);
+                        // borrowed frames, like mapped files, are shared as they are
+                        if !borrowed && flags.contains(PageTableFlags::WRITABLE) {
+                            flags.remove(PageTableFlags::WRITABLE);
+                            flags.insert(COW);
+                            leaf.set_flags(flags);
+                        }
+                        let frame = leaf.frame().map_err(|_| "invalid user page")?;
+
+                        let d3 = next_table_or_create(&mut dst[i4], src[i4].flags(), &mut *alloc)?;
+                        let d2 = next_table_or_create(
+                            unsafe { &mut (*d3)[i3] },
+                            p3[i3].flags(),
+                            &mut *alloc,
+                        )?;
+                        let d1 = next_table_or_create(
+                            unsafe { &mut (*d2)[i2] },
+                            p2[i2].flags(),
+                            &mut *alloc,
+                        )?;
+                        unsafe { (*d1)[i1].set_frame(frame, flags) };
+                        if !borrowed {
+                            share_frame(frame);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// resolves a write fault on a copy on write page of the active address space.
+/// If the frame is still shared, the page is copied into a new frame, otherwise it is simply made writable again.
+/// This is called from the page fault handler, thus it takes no blocking locks. If one of them is held, the fault is
+/// reported as FaultResolution::Contended and the access has to be retried
+pub fn resolve_cow_fault(addr: VirtAddr) -> FaultResolution {
+    if addr.as_u64() >= get_hhdm_addr() {
+        return FaultResolution::Unresolved;
+    }
+    let (root, _) = current_page_tbl();
+    let indices = [addr.p4_index(), addr.p3_index(), addr.p2_index()];
+    let mut table = table_at(root);
+    for idx in indices {
+        // SAFETY: all page tables are reachable through the hhdm
+        let Ok(frame) = (unsafe { &(*table)[idx] }).frame() else {
+            return FaultResolution::Unresolved;
+        };
+        table = table_at(frame);
+    }
+    let leaf = unsafe { &mut (*table)[addr.p1_index()] };
+    let mut flags = leaf.flags();
+    if !flags.contains(PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | COW) {
+        return FaultResolution::Unresolved;
+    }
+    let Ok(old) = leaf.frame() else {
+        return FaultResolution::Unresolved;
+    };
+    flags.remove(COW);
+    flags.insert(PageTableFlags::WRITABLE);
+
+    match try_frame_refcount(old) {
+        None => return FaultResolution::Contended,
+        Some(1) => leaf.set_flags(flags),
+        Some(_) => {
+            let Some(mut alloc) = get_frame_alloc().try_lock() else {
+                return FaultResolution::Contended;
+            };
+            let Some(new) = alloc.allocate_frame() else {
+                return FaultResolution::Unresolved;
+            };
+            let offset = get_hhdm_addr();
+            // SAFETY: both frames are reachable through the hhdm and do not overlap
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    (offset + old.start_address().as_u64()) as *const u8,
+                    (offset + new.start_address().as_u64()) as *mut u8,
+                    Size4KiB::SIZE as usize,
+                );
+            }
+            match try_release_frame(old) {
+                None => {
+                    unsafe { alloc.deallocate_frame(new) };
+                    return FaultResolution::Contended;
+                }
+                // all other mappings were dropped meanwhile
+                Some(true) => unsafe { alloc.deallocate_frame(old) },
+                Some(false) => {}
+            }
+            leaf.set_frame(new, flags);
+        }
+    }
+    x86_64::instructions::tlb::flush(addr.align_down(Size4KiB::SIZE));
+    FaultResolution::Resolved
+}
+
+/// maps frames copy on write over the pages of the current task from start on, dropping the frames they replace.
+/// Every frame carries a reference, which is taken over by its mapping. Each page must be a present user page, which the
+/// task may write to, and may not be borrowed. Otherwise no page is replaced and the references of frames are dropped
+pub fn lend_into(start: VirtAddr, frames: &[PhysFrame<Size4KiB>]) -> Result<(), &'static str> {
+    const UNSUITABLE: PageTableFlags = BORROWED.union(PageTableFlags::HUGE_PAGE);
+    let mut alloc = get_frame_alloc().lock();
+    let mut leaf_flags = Vec::with_capacity(frames.len());
+    for i in 0..frames.len() as u64 {
+        match active_page_flags(start + i * Size4KiB::SIZE) {
+            Some(flags)
+                if flags.contains(PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE)
+                    && flags.intersects(PageTableFlags::WRITABLE | COW)
+                    && !flags.intersects(UNSUITABLE) =>
+            {
+                leaf_flags.push(
+                    flags.difference(
+                        PageTableFlags::WRITABLE | PageTableFlags::ACCESSED | PageTableFlags::DIRTY,
+                    ) | COW,
+                );
+            }
+            _ => {
+                for &frame in frames {
+                    free_frame(frame, &mut *alloc);
+                }
+                return Err("page can not be lent to");
+            }
+        }
+    }
+    let pagetable = tls::task_data()
+        .current_thread()
+        .ok_or("no current task")?
+        .pagedir();
+    for (i, (&frame, flags)) in frames.iter().zip(leaf_flags).enumerate() {
+        let page = Page::containing_address(start + i as u64 * Size4KiB::SIZE);
+        let (old, flush) = pagetable
+            .unmap(page)
+            .map_err(|_| "checked page was not mapped")?;
+        flush.flush();
+        free_frame(old, &mut *alloc);
+        unsafe { pagetable.map_to(page, frame, flags, &mut *alloc) }
+            .map_err(|_| "failed to map a lent frame")?
+            .flush();
+    }
+    Ok(())
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+    use crate::{
+        arch::mem::{PhysAddr, Translate, mapper::TranslateResult},
+        kernel::mem::paging::{frame_refcount, map_region, unmap_region},
+    };
+
+    #[kernel_test]
+    fn clone_shares_frames_cow() {
+        let addr = VirtAddr::new(0x5000_0000);
+        let page = Page::<Size4KiB>::containing_address(addr);
+        let mut parent = create_new_pagedir().unwrap();
+        map_region(
+            addr,
+            Size4KiB::SIZE as usize,
+            PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::WRITABLE,
+            &mut *parent.table,
+        )
+        .unwrap();
+        let frame = parent.table.translate_page(page).unwrap();
+
+        let mut child = parent.clone_cow().unwrap();
+        assert_eq!(child.table.translate_page(page).ok(), Some(frame));
+        assert_eq!(frame_refcount(frame), 2);
+        for tbl in [&parent, &child] {
+            let TranslateResult::Mapped { flags, .. } = tbl.table.translate(addr) else {
+                panic!("page not mapped");
+            };
+            assert!(flags.contains(COW));
+            assert!(!flags.contains(PageTableFlags::WRITABLE));
+        }
+
+        unmap_region(addr, Size4KiB::SIZE as usize, &mut *child.table).unwrap();
+        assert_eq!(frame_refcount(frame), 1);
+        unmap_region(addr, Size4KiB::SIZE as usize, &mut *parent.table).unwrap();
+        // SAFETY: neither address space was ever active
+        unsafe {
+            child.cleanup();
+            parent.cleanup();
+        }
+    }
+    #[kernel_test]
+    fn failed_clone_drops_its_references() {
+        let addr = VirtAddr::new(0x5000_0000);
+        let page = Page::<Size4KiB>::containing_address(addr);
+        let mut parent = create_new_pagedir().unwrap();
+        map_region(
+            addr,
+            Size4KiB::SIZE as usize,
+            PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::WRITABLE,
+            &mut *parent.table,
+        )
+        .unwrap();
+        let frame = parent.table.translate_page(page).unwrap();
+        // a huge page right after the shared one makes the clone fail halfway
+        let p2 = {
+            let p4 = unsafe { &*table_at(parent.root) };
+            let p3 = unsafe { &*table_at(p4[addr.p4_index()].frame().unwrap()) };
+            unsafe { &mut *table_at(p3[addr.p3_index()].frame().unwrap()) }
+        };
+        let huge = usize::from(addr.p2_index()) + 1;
+        p2[huge].set_addr(
+            PhysAddr::new(0),
+            PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::HUGE_PAGE,
+        );
+        let allocated = get_frame_alloc().lock().allocated();
+
+        assert!(parent.clone_cow().is_err());
+        assert_eq!(frame_refcount(frame), 1);
+        assert_eq!(get_frame_alloc().lock().allocated(), allocated);
+
+        p2[huge].set_unused();
+        unmap_region(addr, Size4KiB::SIZE as usize, &mut *parent.table).unwrap();
+        // SAFETY: the address space was never active
+        unsafe { parent.cleanup() };
+    }
+}
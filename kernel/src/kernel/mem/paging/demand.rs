@@ -0,0 +1,152 @@
+use core::sync::atomic::Ordering;
+
+use super::{
+    FaultResolution,
+    cow::{next_table_or_create, table_at},
+    get_frame_alloc,
+    get_hhdm_addr,
+};
+use crate::{
+    arch::{
+        current_page_tbl,
+        mem::{FrameAllocator, PageTableFlags, VirtAddr},
+    },
+    kernel::{mem::vma::VmaBacking, threading::tls},
+};
+
+/// backs the page containing addr with a zeroed frame, if it lies in an anonymous or stack vma of the current task, but
+/// was never touched. Anonymous memory is charged to the resource group of the process, as mmap would have done.
+/// This is called from the page fault handler, thus no blocking locks are taken. If one of them is held, the fault is
+/// reported as FaultResolution::Contended and the access has to be retried.
+/// A page, which another thread of the process mapped first, counts as resolved
+pub fn resolve_demand_fault(addr: VirtAddr) -> FaultResolution {
+    if addr.as_u64() >= get_hhdm_addr() {
+        return FaultResolution::Unresolved;
+    }
+    // the page fault handler does not swap gs, thus the task must not be looked up through per cpu data
+    let Some(task) = tls::task_data().try_interrupted_thread() else {
+        return FaultResolution::Contended;
+    };
+    let Some(task) = task else {
+        return FaultResolution::Unresolved;
+    };
+    // the vmas stay locked until the page is mapped, as munmap removes the vma before unmapping its pages
+    let Some(vmas) = task.core.vmas.try_read() else {
+        return FaultResolution::Contended;
+    };
+    let Some(vma) = vmas.find(addr) else {
+        return FaultResolution::Unresolved;
+    };
+    let group = match vma.backing() {
+        VmaBacking::Anonymous => match task.core.resource_group.try_read() {
+            Some(group) => Some(group.clone()),
+            None => return FaultResolution::Contended,
+        },
+        VmaBacking::Stack => None,
+        _ => return FaultResolution::Unresolved,
+    };
+    let Some(mut alloc) = get_frame_alloc().try_lock() else {
+        return FaultResolution::Contended;
+    };
+
+    let table_flags =
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+    let (root, _) = current_page_tbl();
+    let mut table = table_at(root);
+    for idx in [addr.p4_index(), addr.p3_index(), addr.p2_index()] {
+        // SAFETY: all page tables are reachable through the hhdm and the frame allocator serializes their creation
+        let entry = unsafe { &mut (*table)[idx] };
+        match next_table_or_create(entry, table_flags, &mut *alloc) {
+            Ok(next) => table = next,
+            Err(_) => return FaultResolution::Unresolved,
+        }
+    }
+    let leaf = unsafe { &mut (*table)[addr.p1_index()] };
+    if !leaf.is_unused() {
+        return if leaf.flags().contains(PageTableFlags::PRESENT) {
+            FaultResolution::Resolved
+        } else {
+            FaultResolution::Unresolved
+        };
+    }
+    // frames are zeroed by the allocator
+    let frame = match &group {
+        Some(group) => alloc.allocate_frame_for(group),
+        None => alloc.allocate_frame(),
+    };
+    let Some(frame) = frame else {
+        return FaultResolution::Unresolved;
+    };
+    leaf.set_frame(frame, vma.flags());
+    if group.is_some() {
+        task.core.charged_frames.fetch_add(1, Ordering::AcqRel);
+    }
+    // the page was not present before, thus no stale translation can be cached
+    FaultResolution::Resolved
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+    use crate::{
+        arch::mem::{PageSize, Size4KiB},
+        kernel::{
+            mem::{
+                paging::{active_page_flags, unmap_region},
+                vma::Vma,
+            },
+            threading::task::TaskRepr,
+        },
+    };
+
+    /// an address, which is not mapped by the test runner
+    const LAZY: u64 = 0x5a00_0000;
+
+    #[kernel_test]
+    fn anonymous_pages_are_backed_on_touch() {
+        let task = tls::task_data().current_thread().unwrap();
+        let addr = VirtAddr::new(LAZY);
+        let flags = PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | PageTableFlags::USER_ACCESSIBLE
+            | PageTableFlags::NO_EXECUTE;
+        task.core.vmas.write().insert(Vma::new(
+            addr,
+            2 * Size4KiB::SIZE as usize,
+            flags,
+            VmaBacking::Anonymous,
+        ));
+        let charged = task.core.charged_frames.load(Ordering::Acquire);
+        assert!(active_page_flags(addr).is_none());
+
+        assert_eq!(resolve_demand_fault(addr + 8), FaultResolution::Resolved);
+        assert!(active_page_flags(addr).unwrap().contains(flags));
+        assert!(
+            unsafe { core::slice::from_raw_parts(LAZY as *const u8, 4096) }
+                .iter()
+                .all(|&byte| byte == 0)
+        );
+        // the page is mapped already and the next one was not touched
+        assert_eq!(resolve_demand_fault(addr), FaultResolution::Resolved);
+        assert!(active_page_flags(addr + Size4KiB::SIZE).is_none());
+        assert_eq!(
+            task.core.charged_frames.load(Ordering::Acquire),
+            charged + 1
+        );
+        // outside of any vma
+        assert_eq!(
+            resolve_demand_fault(addr + 2 * Size4KiB::SIZE),
+            FaultResolution::Unresolved
+        );
+
+        task.core
+            .vmas
+            .write()
+            .remove(addr, 2 * Size4KiB::SIZE as usize);
+        unmap_region(addr, 2 * Size4KiB::SIZE as usize, task.pagedir()).unwrap();
+        task.core.charged_frames.fetch_sub(1, Ordering::AcqRel);
+        task.core.resource_group.read().uncharge_frames(1);
+    }
+}
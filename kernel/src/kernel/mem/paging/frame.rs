@@ -1 +1,80 @@
+use alloc::collections::btree_map::BTreeMap;
 
+use crate::{
+    arch::mem::{FrameDeallocator, PhysFrame, Size4KiB},
+    kernel::threading::schedule::preempt::{preempt_disable, preempt_enable},
+    sync::locks::Mutex,
+};
+
+// Reference counts of frames, which are mapped into more than one address space.
+// Frames without an entry are implicitly referenced once, thus only shared frames are tracked.
+static SHARED_FRAMES: Mutex<BTreeMap<PhysFrame<Size4KiB>, u32>> = Mutex::new(BTreeMap::new());
+
+/// runs f on the locked refcounts. The cow fault handler only try-locks them and retries the access on contention,
+/// thus the holder must not be preempted. Preemption is only disabled once the lock is held, as waiting for it may block
+fn with_frames<R>(f: impl FnOnce(&mut BTreeMap<PhysFrame<Size4KiB>, u32>) -> R) -> R {
+    let mut frames = SHARED_FRAMES.lock();
+    preempt_disable();
+    let res = f(&mut frames);
+    drop(frames);
+    preempt_enable();
+    res
+}
+
+/// adds a reference to frame, which is now mapped one more time
+pub fn share_frame(frame: PhysFrame<Size4KiB>) {
+    with_frames(|frames| *frames.entry(frame).or_insert(1) += 1);
+}
+
+/// drops a reference to frame. Returns true if this was the last one, in which case the caller owns the frame
+pub fn release_frame(frame: PhysFrame<Size4KiB>) -> bool {
+    with_frames(|frames| release_locked(frames, frame))
+}
+
+/// like release_frame, but returns None instead of blocking
+pub fn try_release_frame(frame: PhysFrame<Size4KiB>) -> Option<bool> {
+    SHARED_FRAMES
+        .try_lock()
+        .map(|mut frames| release_locked(&mut frames, frame))
+}
+
+fn release_locked(
+    frames: &mut BTreeMap<PhysFrame<Size4KiB>, u32>,
+    frame: PhysFrame<Size4KiB>,
+) -> bool {
+    let Some(count) = frames.get_mut(&frame) else {
+        return true;
+    };
+    *count -= 1;
+    if *count == 1 {
+        frames.remove(&frame);
+    }
+    false
+}
+
+/// number of mappings of frame
+pub fn frame_refcount(frame: PhysFrame<Size4KiB>) -> u32 {
+    SHARED_FRAMES.lock().get(&frame).copied().unwrap_or(1)
+}
+
+/// like frame_refcount, but returns None instead of blocking
+pub fn try_frame_refcount(frame: PhysFrame<Size4KiB>) -> Option<u32> {
+    SHARED_FRAMES
+        .try_lock()
+        .map(|frames| frames.get(&frame).copied().unwrap_or(1))
+}
+
+/// number of frames currently shared between address spaces
+pub fn shared_frames() -> usize {
+    SHARED_FRAMES.lock().len()
+}
+
+/// drops a reference to frame and deallocates it, if it was the last one
+pub fn free_frame<A: FrameDeallocator<Size4KiB> + ?Sized>(
+    frame: PhysFrame<Size4KiB>,
+    alloc: &mut A,
+) {
+    if release_frame(frame) {
+        unsafe { alloc.deallocate_frame(frame) };
+    }
+}
@@ -19,8 +19,15 @@ use crate::{
     eprintln,
     kernel::{
         mem::{
-            addr::{PhysAddr as paddr, VirtAddr as vaddr},
-            paging::{PAGETABLE, get_frame_alloc, get_hhdm_addr},
+            addr::{KernelVirtAddr, PhysAddr as paddr, UserVirtAddr, VirtAddr as vaddr},
+            paging::{
+                BORROWED,
+                GlobalFrameAllocator,
+                PAGETABLE,
+                free_frame,
+                get_frame_alloc,
+                get_hhdm_addr,
+            },
         },
         threading::{task::TaskRepr, tls},
     },
@@ -41,22 +48,25 @@ impl PageTableMapper {
 
 // TODO all following functions should try to clean up after themselves in case of an error.
 
-pub fn user_map_region(start: VirtAddr, len: usize) -> Result<(), &'static str> {
+/// maps a new region into the address space of the current task
+pub fn user_map_region(start: UserVirtAddr, len: usize) -> Result<(), &'static str> {
+    UserVirtAddr::try_new_range(start.as_u64(), len as u64).ok_or("region exceeds user space")?;
     let flags = PageTableFlags::PRESENT
         | PageTableFlags::USER_ACCESSIBLE
         | PageTableFlags::WRITABLE
         | PageTableFlags::NO_EXECUTE;
     map_region(
-        start,
+        start.as_virt(),
         len,
         flags,
         tls::task_data().current_thread().unwrap().pagedir(),
     )
 }
 
-pub fn kernel_map_region(start: VirtAddr, len: usize) -> Result<(), &'static str> {
+/// maps a new region into the kernel half, which is shared by all address spaces
+pub fn kernel_map_region(start: KernelVirtAddr, len: usize) -> Result<(), &'static str> {
     let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
-    map_region(start, len, flags, &mut *PAGETABLE.lock())
+    map_region(start.as_virt(), len, flags, &mut *PAGETABLE.lock())
 }
 
 /// maps a NEW region from start..start + len into the provided address space. NEW memory will be allocated for this process.
@@ -66,6 +76,17 @@ pub fn map_region<M: Mapper<Size4KiB>>(
     len: usize,
     flags: PageTableFlags,
     pagetable: &mut M,
+) -> Result<(), &'static str> {
+    map_region_with(start, len, flags, pagetable, |alloc| alloc.allocate_frame())
+}
+
+/// like map_region, but the backing frames are allocated by alloc_frame, e.g. to charge them to a resource group
+pub fn map_region_with<M: Mapper<Size4KiB>>(
+    start: VirtAddr,
+    len: usize,
+    flags: PageTableFlags,
+    pagetable: &mut M,
+    mut alloc_frame: impl FnMut(&mut GlobalFrameAllocator) -> Option<PhysFrame<Size4KiB>>,
 ) -> Result<(), &'static str> {
     assert!(flags.contains(PageTableFlags::PRESENT));
     let end_addr = (start + len as u64).align_up(Size4KiB::SIZE);
@@ -77,9 +98,7 @@ pub fn map_region<M: Mapper<Size4KiB>>(
         if pagetable.translate_page(page).is_ok() {
             return Err("a memory region was already mapped, but we tried to map it again.");
         }
-        let frame = alloc
-            .allocate_frame()
-            .ok_or::<&str>("could not allocate frame")?;
+        let frame = alloc_frame(&mut alloc).ok_or::<&str>("could not allocate frame")?;
         unsafe { pagetable.map_to(page, frame, flags, &mut *alloc) }
             .map_err(|_e| "map failed during map_to")?
             .flush();
@@ -89,7 +108,8 @@ pub fn map_region<M: Mapper<Size4KiB>>(
 
 /// unmaps a region from start..start + len from the provided address space and frees the underlying memory.
 /// len should be in BYTES.
-/// Deallocates the backing memory
+/// Deallocates the backing memory, unless it is still shared copy on write with another address space.
+/// Pages, which were never mapped, are skipped
 pub fn unmap_region<M: Mapper<Size4KiB>>(
     start: VirtAddr,
     len: usize,
@@ -101,14 +121,20 @@ pub fn unmap_region<M: Mapper<Size4KiB>>(
     let mut alloc = get_frame_alloc().lock();
 
     for page in Page::range(start, end) {
-        let (frame, flush) = pagetable.unmap(page).map_err(|e| format!("{:?}", e))?;
+        let (frame, flush) = match pagetable.unmap(page) {
+            Ok(unmapped) => unmapped,
+            // pages of a vma are only backed on first touch
+            Err(mapper::UnmapError::PageNotMapped) => continue,
+            Err(e) => return Err(format!("{:?}", e)),
+        };
         flush.flush();
-        unsafe { alloc.deallocate_frame(frame) };
+        free_frame(frame, &mut *alloc);
     }
     Ok(())
 }
 
 /// maps a region from start..start + len into the provided address space. This does not allocate any memory.
+/// The pages are marked BORROWED, as their frames are owned by from_addr_space.
 /// Thus len should be the len in BYTES.
 /// Returns the new address in address space pagetable, where from is mapped to.
 /// This address is not guaranteed to be page aligned.
@@ -121,6 +147,7 @@ pub fn map_region_into<M: Mapper<Size4KiB>, M2: Mapper<Size4KiB>>(
     from_addr_space: &mut M2,
 ) -> Result<VirtAddr, &'static str> {
     assert!(flags.contains(PageTableFlags::PRESENT));
+    let flags = flags | BORROWED;
     let end_addr = (start + len as u64).align_up(Size4KiB::SIZE);
     let start_page = Page::containing_address(start);
     let end = Page::containing_address(end_addr);
@@ -227,7 +254,11 @@ pub fn unmap_region_from<M: Mapper<Size4KiB>>(
     let end = Page::containing_address(end_addr);
 
     for page in Page::range(start, end) {
-        let (frame, flush) = pagetable.unmap(page).map_err(|e| format!("{:?}", e))?;
+        let (_, flush) = match pagetable.unmap(page) {
+            Ok(unmapped) => unmapped,
+            Err(mapper::UnmapError::PageNotMapped) => continue,
+            Err(e) => return Err(format!("{:?}", e)),
+        };
         flush.flush();
     }
     Ok(())
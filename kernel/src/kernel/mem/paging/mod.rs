@@ -1,22 +1,45 @@
 #![allow(dead_code)]
 
 mod alloc;
+mod cow;
+mod demand;
 mod frame;
 mod map;
+mod space;
 mod table;
-pub use alloc::{GlobalFrameAllocator, get_frame_alloc, init_frame_alloc};
+pub use alloc::{
+    FRAMES_FILE,
+    FrameStats,
+    Frames,
+    GlobalFrameAllocator,
+    get_frame_alloc,
+    init_frame_alloc,
+};
 use core::{fmt::Debug, mem::ManuallyDrop};
 
 use conquer_once::spin::OnceCell;
+pub use cow::{COW, lend_into, resolve_cow_fault};
+pub use demand::resolve_demand_fault;
+pub use frame::{
+    frame_refcount,
+    free_frame,
+    release_frame,
+    share_frame,
+    shared_frames,
+    try_frame_refcount,
+    try_release_frame,
+};
 use lazy_static::lazy_static;
 pub use map::{
     kernel_map_region,
     map_region,
     map_region_into,
+    map_region_with,
     unmap_region,
     unmap_region_from,
     user_map_region,
 };
+pub use space::{AddrSpaceStats, BORROWED};
 
 //TODO make arch agnostic / abstract arch stuff away
 use crate::{
@@ -24,16 +47,14 @@ use crate::{
         current_page_tbl,
         mem::{
             FrameAllocator,
-            FrameDeallocator,
             Mapper,
             OffsetPageTable,
-            Page,
             PageSize,
             PageTable,
+            PageTableFlags,
             PhysFrame,
             Size4KiB,
             VirtAddr,
-            mapper::CleanUp,
         },
     },
     bootinfo,
@@ -42,6 +63,25 @@ use crate::{
     sync::locks::Mutex,
 };
 
+/// the outcome of trying to resolve a page fault from the fault handler, which must never spin on a lock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultResolution {
+    /// the page is mapped now
+    Resolved,
+    /// a lock needed to resolve the fault is currently held. Returning from the handler retries the access, which
+    /// faults again once the holder made progress
+    Contended,
+    /// the fault is not of this kind, or it cannot be resolved
+    Unresolved,
+}
+
+impl FaultResolution {
+    /// whether the faulting access may simply be retried
+    pub fn is_handled(self) -> bool {
+        self != Self::Unresolved
+    }
+}
+
 pub static HIGHER_HALF_START: OnceCell<u64> = OnceCell::uninit();
 pub static KERNEL_PAGETABLE_ADDR: OnceCell<PhysFrame<Size4KiB>> = OnceCell::uninit();
 
@@ -63,6 +103,40 @@ unsafe fn active_level_4_table() -> &'static mut PageTable {
     unsafe { &mut *page_table_ptr }
 }
 
+/// the effective flags of the page containing addr in the active address space, or None if it is not mapped.
+/// USER_ACCESSIBLE and WRITABLE are only reported, if every level of the walk allows them.
+/// This only reads the page tables and takes no locks, thus it may be used from any context
+pub fn active_page_flags(addr: VirtAddr) -> Option<PageTableFlags> {
+    const INHERITED: PageTableFlags =
+        PageTableFlags::USER_ACCESSIBLE.union(PageTableFlags::WRITABLE);
+    let offset = get_hhdm_addr();
+    let (root, _) = current_page_tbl();
+    let indices = [
+        addr.p4_index(),
+        addr.p3_index(),
+        addr.p2_index(),
+        addr.p1_index(),
+    ];
+
+    let mut table = (offset + root.start_address().as_u64()) as *const PageTable;
+    let mut allowed = INHERITED;
+    for (level, idx) in indices.into_iter().enumerate() {
+        // SAFETY: all page tables are reachable through the hhdm
+        let entry = unsafe { &(*table)[idx] };
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) {
+            return None;
+        }
+        allowed &= flags;
+        // huge pages are only valid in p3 and p2 entries
+        if level == 3 || ((level == 1 || level == 2) && flags.contains(PageTableFlags::HUGE_PAGE)) {
+            return Some(flags.difference(INHERITED) | allowed);
+        }
+        table = (offset + entry.addr().as_u64()) as *const PageTable;
+    }
+    unreachable!()
+}
+
 // SAFETY: this depends on the safety of physical mem offset
 lazy_static! {
     pub static ref PAGETABLE: Mutex<OffsetPageTable<'static>> = {
@@ -127,23 +201,15 @@ impl TaskPageTable<'_> {
     /// This method may block.
     pub unsafe fn cleanup(mut self) {
         // TODO: ensure that NO ptrs into the dropped address space exist at this point
-
-        // only unmap lower half
-        let hhdm = get_hhdm_addr();
-
-        let mut table = unsafe { ManuallyDrop::take(&mut self.table) };
-        unsafe {
-            table.clean_up_addr_range(
-                Page::range_inclusive(
-                    Page::containing_address(VirtAddr::zero()),
-                    Page::containing_address(VirtAddr::new(hhdm - 1)),
-                ),
-                &mut *get_frame_alloc().lock(),
-            );
-        }
-        unsafe {
-            get_frame_alloc().lock().deallocate_frame(self.root);
-        }
+        let stats = self.stats();
+        let freed = unsafe { self.free_frames() };
+        // shared pages are freed by the last address space mapping them, borrowed ones by their owner
+        debug_assert_eq!(
+            freed,
+            stats.table_frames + stats.private_frames,
+            "address space leaked frames: {:?}",
+            stats
+        );
     }
 }
 
@@ -181,12 +247,11 @@ impl<'a> APageTable<'a> {
         }
     }
 
-    pub fn clone(&self) -> Self {
-        // This should lazily copy the pagedirs, using Cow.
-        // Currently this is not possible, thus we eagerly duplicate all (non-global) mappings
+    /// the global table is shared, owned tables are copied lazily, see TaskPageTable::clone_cow
+    pub fn clone(&self) -> Result<Self, &'static str> {
         match self {
-            Self::Global(g) => Self::Global(g),
-            Self::Owned(o) => todo!(),
+            Self::Global(g) => Ok(Self::Global(g)),
+            Self::Owned(o) => o.lock().clone_cow().map(|tbl| Self::owned(tbl.into())),
         }
     }
 
@@ -0,0 +1,168 @@
+use x86_64::structures::paging::page_table::PageTableEntry;
+
+use super::{
+    TaskPageTable,
+    frame::try_frame_refcount,
+    get_frame_alloc,
+    get_hhdm_addr,
+    release_frame,
+};
+use crate::arch::mem::{FrameDeallocator, PageTable, PageTableFlags, PhysFrame, Size4KiB};
+
+/// marks a user page, whose frame is not owned by the address space, like a mapped file. It is never freed with the address space
+pub const BORROWED: PageTableFlags = PageTableFlags::BIT_10;
+
+/// frame usage of an address space, not counting the kernel mappings shared by all address spaces
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AddrSpaceStats {
+    /// frames used by the page tables themselves, including the root
+    pub table_frames: usize,
+    /// user pages mapped only into this address space
+    pub private_frames: usize,
+    /// user pages shared copy on write with other address spaces
+    pub shared_frames: usize,
+    /// user pages backed by frames owned by someone else, like mapped files
+    pub borrowed_frames: usize,
+}
+
+impl AddrSpaceStats {
+    /// number of user pages mapped into the address space
+    pub fn resident(&self) -> usize {
+        self.private_frames + self.shared_frames + self.borrowed_frames
+    }
+}
+
+enum Visit<'a> {
+    /// a page table in the lower half, visited after all of its entries
+    Table(PhysFrame<Size4KiB>),
+    /// a present 4KiB mapping in the lower half
+    Leaf(&'a mut PageTableEntry),
+}
+
+fn table_at(frame: PhysFrame<Size4KiB>) -> &'static mut PageTable {
+    // SAFETY: all page tables are reachable through the hhdm
+    unsafe { &mut *((get_hhdm_addr() + frame.start_address().as_u64()) as *mut PageTable) }
+}
+
+/// visits all page tables and mappings below level, which are owned by the address space, ie everything in the lower half.
+/// The higher half is shared with the kernel
+fn visit(table: PhysFrame<Size4KiB>, level: u8, f: &mut impl FnMut(Visit)) {
+    let entries = table_at(table);
+    let end = if level == 4 {
+        ((get_hhdm_addr() >> 39) & 0x1ff) as usize
+    } else {
+        512
+    };
+    for entry in entries.iter_mut().take(end) {
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+        if level == 1 {
+            f(Visit::Leaf(entry));
+        } else if let Ok(next) = entry.frame() {
+            visit(next, level - 1, f);
+        }
+    }
+    if level != 4 {
+        f(Visit::Table(table));
+    }
+}
+
+impl TaskPageTable<'_> {
+    /// walks the page tables and sums up the frames used by this address space
+    pub fn stats(&self) -> AddrSpaceStats {
+        let mut stats = AddrSpaceStats {
+            table_frames: 1,
+            ..Default::default()
+        };
+        visit(self.root, 4, &mut |visit| match visit {
+            Visit::Table(_) => stats.table_frames += 1,
+            Visit::Leaf(leaf) => {
+                let flags = leaf.flags();
+                if !flags.contains(PageTableFlags::USER_ACCESSIBLE) {
+                    // kernel mappings, like the heap
+                } else if flags.contains(BORROWED) {
+                    stats.borrowed_frames += 1;
+                } else if leaf
+                    .frame()
+                    .is_ok_and(|frame| try_frame_refcount(frame).is_some_and(|count| count > 1))
+                {
+                    stats.shared_frames += 1;
+                } else {
+                    stats.private_frames += 1;
+                }
+            }
+        });
+        stats
+    }
+
+    /// frees all page tables and owned user pages of the lower half and the root table.
+    /// Shared pages only drop their reference, borrowed ones are left alone.
+    /// Returns the number of frames given back to the frame allocator.
+    /// # SAFETY
+    /// The address space must not be active
+    pub(super) unsafe fn free_frames(&mut self) -> usize {
+        let mut alloc = get_frame_alloc().lock();
+        let mut freed = 0;
+        visit(self.root, 4, &mut |visit| match visit {
+            Visit::Table(frame) => {
+                unsafe { alloc.deallocate_frame(frame) };
+                freed += 1;
+            }
+            Visit::Leaf(leaf) => {
+                let flags = leaf.flags();
+                if flags.contains(PageTableFlags::USER_ACCESSIBLE)
+                    && !flags.contains(BORROWED)
+                    && let Ok(frame) = leaf.frame()
+                    && release_frame(frame)
+                {
+                    unsafe { alloc.deallocate_frame(frame) };
+                    freed += 1;
+                }
+                leaf.set_unused();
+            }
+        });
+        unsafe { alloc.deallocate_frame(self.root) };
+        freed + 1
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+    use crate::{
+        arch::mem::{PageSize, VirtAddr},
+        kernel::mem::paging::{create_new_pagedir, map_region},
+    };
+
+    #[kernel_test]
+    fn addr_space_stats() {
+        let baseline = get_frame_alloc().lock().allocated();
+        let mut parent = create_new_pagedir().unwrap();
+        let kernel_only = parent.stats();
+        assert_eq!(kernel_only.resident(), 0);
+
+        map_region(
+            VirtAddr::new(0x5000_0000),
+            3 * Size4KiB::SIZE as usize,
+            PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::WRITABLE,
+            &mut *parent.table,
+        )
+        .unwrap();
+        let stats = parent.stats();
+        assert_eq!(stats.private_frames, 3);
+        assert_eq!(stats.shared_frames, 0);
+
+        let child = parent.clone_cow().unwrap();
+        assert_eq!(parent.stats().shared_frames, 3);
+        assert_eq!(child.stats().shared_frames, 3);
+
+        // SAFETY: neither address space was ever active
+        unsafe { child.cleanup() };
+        assert_eq!(parent.stats(), stats);
+        unsafe { parent.cleanup() };
+        assert_eq!(get_frame_alloc().lock().allocated(), baseline);
+    }
+}
@@ -0,0 +1,340 @@
+use alloc::{
+    collections::btree_map::{self, BTreeMap},
+    format,
+    string::String,
+    sync::Arc,
+    vec::Vec,
+};
+use core::fmt::{Display, Write as _};
+
+use os_macros::FileRepr;
+use tinyos_abi::types::MemInfo;
+
+use crate::{
+    arch::mem::{PageSize, PageTableFlags, Size4KiB, VirtAddr},
+    kernel::{
+        fd::FileRepr,
+        fs::{FSResult, Path, PathBuf, procfs},
+        io::{IOResult, Read, read_rendered, ring::RingMapping},
+        threading::{
+            task::{ProcessID, TaskCore},
+            tls,
+        },
+    },
+    register_device_file,
+};
+
+/// what a virtual memory area is backed by
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VmaBacking {
+    Anonymous,
+    /// a segment of the binary the process was started from
+    Elf,
+    Stack,
+    /// a file mapped via mmap. The path is unknown for anonymous files, like pipes
+    File(Option<PathBuf>),
+    /// the pages shared with the kernel, see elf::vdso
+    Vdso,
+    /// an io ring mapped via mmap. The vma keeps the memory of the ring alive, until it is unmapped
+    Ring(RingMapping),
+}
+
+impl Display for VmaBacking {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Anonymous => Ok(()),
+            Self::Elf => f.write_str("[elf]"),
+            Self::Stack => f.write_str("[stack]"),
+            Self::File(Some(path)) => f.write_str(path.as_str()),
+            Self::File(None) => f.write_str("[anon file]"),
+            Self::Vdso => f.write_str("[vdso]"),
+            Self::Ring(_) => f.write_str("[ring]"),
+        }
+    }
+}
+
+/// a contiguous, page aligned range [start, end) of the user address space of a process
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Vma {
+    start: VirtAddr,
+    end: VirtAddr,
+    flags: PageTableFlags,
+    backing: VmaBacking,
+}
+
+impl Vma {
+    /// creates a new Vma covering all pages touched by [start, start + len)
+    pub fn new(start: VirtAddr, len: usize, flags: PageTableFlags, backing: VmaBacking) -> Self {
+        Self {
+            start: start.align_down(Size4KiB::SIZE),
+            end: (start + len as u64).align_up(Size4KiB::SIZE),
+            flags,
+            backing,
+        }
+    }
+
+    pub fn start(&self) -> VirtAddr {
+        self.start
+    }
+
+    pub fn end(&self) -> VirtAddr {
+        self.end
+    }
+
+    pub fn len(&self) -> usize {
+        (self.end - self.start) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn flags(&self) -> PageTableFlags {
+        self.flags
+    }
+
+    pub fn backing(&self) -> &VmaBacking {
+        &self.backing
+    }
+
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        self.start <= addr && addr < self.end
+    }
+}
+
+impl Display for Vma {
+    /// formats self like a line of /proc/<pid>/maps on linux
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let perm = |flag: PageTableFlags, c: char| if self.flags.contains(flag) { c } else { '-' };
+        write!(
+            f,
+            "{:012x}-{:012x} {}{}{}{} {}",
+            self.start.as_u64(),
+            self.end.as_u64(),
+            perm(PageTableFlags::PRESENT, 'r'),
+            perm(PageTableFlags::WRITABLE, 'w'),
+            if self.flags.contains(PageTableFlags::NO_EXECUTE) {
+                '-'
+            } else {
+                'x'
+            },
+            if matches!(self.backing, VmaBacking::File(_) | VmaBacking::Ring(_)) {
+                's'
+            } else {
+                'p'
+            },
+            self.backing
+        )
+    }
+}
+
+/// all vmas of an address space, sorted by start address. Vmas never overlap
+#[derive(Debug, Default, Clone)]
+pub struct VmaList {
+    areas: BTreeMap<u64, Vma>,
+}
+
+impl VmaList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// inserts vma. Any existing mappings in its range are replaced, like a fixed mmap would
+    pub fn insert(&mut self, vma: Vma) {
+        if vma.is_empty() {
+            return;
+        }
+        _ = self.remove(vma.start, vma.len());
+        _ = self.areas.insert(vma.start.as_u64(), vma);
+    }
+
+    /// removes the range [start, start + len) from all vmas. Vmas partially covered by the range are shrunk or split.
+    /// Returns the removed parts, which may keep the memory of the range alive, thus they should only be dropped once
+    /// the range is unmapped
+    pub fn remove(&mut self, start: VirtAddr, len: usize) -> Vec<Vma> {
+        let start = start.align_down(Size4KiB::SIZE);
+        let end = (start + len as u64).align_up(Size4KiB::SIZE);
+        let overlapping: Vec<u64> = self
+            .areas
+            .range(..end.as_u64())
+            .rev()
+            .take_while(|(_, vma)| vma.end > start)
+            .map(|(key, _)| *key)
+            .collect();
+
+        let mut removed = Vec::with_capacity(overlapping.len());
+        for key in overlapping {
+            let mut vma = self.areas.remove(&key).unwrap();
+            if vma.start < start {
+                let mut head = vma.clone();
+                head.end = start;
+                _ = self.areas.insert(head.start.as_u64(), head);
+            }
+            if vma.end > end {
+                let mut tail = vma.clone();
+                tail.start = end;
+                _ = self.areas.insert(tail.start.as_u64(), tail);
+            }
+            vma.start = vma.start.max(start);
+            vma.end = vma.end.min(end);
+            removed.push(vma);
+        }
+        removed
+    }
+
+    pub fn find(&self, addr: VirtAddr) -> Option<&Vma> {
+        self.areas
+            .range(..=addr.as_u64())
+            .next_back()
+            .map(|(_, vma)| vma)
+            .filter(|vma| vma.contains(addr))
+    }
+
+    pub fn iter(&self) -> btree_map::Values<'_, u64, Vma> {
+        self.areas.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.areas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.areas.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.areas.clear();
+    }
+}
+
+impl<'a> IntoIterator for &'a VmaList {
+    type IntoIter = btree_map::Values<'a, u64, Vma>;
+    type Item = &'a Vma;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// memory usage of the process owning core, as reported by the mem_info syscall.
+/// Virtual sizes come from its vmas, resident sizes from the frame accounting of its address space
+pub fn mem_info(core: &TaskCore) -> MemInfo {
+    let page = Size4KiB::SIZE;
+    // SAFETY: the pagedir is only replaced while the process is built
+    let stats = unsafe { &*core.pagedir.get() }
+        .try_get_owned()
+        .map(|tbl| tbl.lock().stats())
+        .unwrap_or_default();
+    let vmas = core.vmas.read();
+    MemInfo {
+        resident: stats.resident() as u64 * page,
+        shared: stats.shared_frames as u64 * page,
+        borrowed: stats.borrowed_frames as u64 * page,
+        page_tables: stats.table_frames as u64 * page,
+        virt: vmas.iter().map(|vma| vma.len() as u64).sum(),
+        vmas: vmas.len() as u64,
+    }
+}
+
+/// procfs view of the vmas of a single process, formatted like /proc/<pid>/maps on linux
+#[derive(Debug, Clone, Copy, FileRepr)]
+#[file(read)]
+pub struct ProcessMaps {
+    pid: ProcessID,
+}
+
+impl ProcessMaps {
+    pub fn new(pid: ProcessID) -> Self {
+        Self { pid }
+    }
+
+    /// registers the maps file of pid at /proc/<pid>/maps
+    pub fn register(pid: ProcessID) -> FSResult<()> {
+        let path = format!("/{}/maps", pid.0);
+        register_device_file!(Arc::new(Self::new(pid)) as Arc<dyn FileRepr>, path.as_str())
+    }
+
+    /// removes /proc/<pid> together with the maps file, once the process is gone
+    pub fn deregister(pid: ProcessID) {
+        _ = procfs::registry().deregister_dir(Path::new(&format!("/{}", pid.0)));
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        if let Some(core) = tls::task_data().processes().read().get(&self.pid) {
+            for vma in core.vmas.read().iter() {
+                _ = writeln!(out, "{}", vma);
+            }
+        }
+        out
+    }
+}
+
+impl Read for ProcessMaps {
+    fn read(&self, buf: &mut [u8], offset: usize) -> IOResult<usize> {
+        read_rendered(self.render(), buf, offset)
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    #[kernel_test]
+    fn vma_list() {
+        let page = Size4KiB::SIZE;
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        let mut list = VmaList::new();
+        list.insert(Vma::new(
+            VirtAddr::new(page),
+            4 * page as usize,
+            flags,
+            VmaBacking::Anonymous,
+        ));
+        assert_eq!(list.len(), 1);
+        assert!(list.find(VirtAddr::new(page * 3 + 5)).is_some());
+        assert!(list.find(VirtAddr::new(page * 5)).is_none());
+
+        // punch a hole into the middle
+        let removed = list.remove(VirtAddr::new(page * 2), page as usize);
+        assert_eq!(removed.len(), 1);
+        assert_eq!(
+            (removed[0].start().as_u64(), removed[0].end().as_u64()),
+            (2 * page, 3 * page)
+        );
+        let ranges: Vec<_> = list
+            .iter()
+            .map(|vma| (vma.start().as_u64(), vma.end().as_u64()))
+            .collect();
+        assert_eq!(ranges, [(page, 2 * page), (3 * page, 5 * page)]);
+
+        // a fixed mapping replaces what it overlaps
+        list.insert(Vma::new(
+            VirtAddr::new(page),
+            3 * page as usize,
+            PageTableFlags::PRESENT,
+            VmaBacking::Stack,
+        ));
+        assert_eq!(list.len(), 2);
+        assert_eq!(
+            list.find(VirtAddr::new(page * 3)).unwrap().backing(),
+            &VmaBacking::Stack
+        );
+        assert_eq!(
+            list.find(VirtAddr::new(page * 4)).unwrap().backing(),
+            &VmaBacking::Anonymous
+        );
+    }
+
+    #[kernel_test]
+    fn process_maps_deregistered() {
+        let pid = ProcessID(u64::MAX - 1);
+        let dir = format!("/{}", pid.0);
+        ProcessMaps::register(pid).unwrap();
+        assert!(procfs::registry().contains_dir(Path::new(&dir)));
+        ProcessMaps::deregister(pid);
+        assert!(!procfs::registry().contains_dir(Path::new(&dir)));
+    }
+}
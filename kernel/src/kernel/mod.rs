@@ -1,10 +1,18 @@
 pub mod abi;
+pub mod config;
+pub mod crypto;
 pub mod devices;
 pub mod elf;
 pub mod fd;
+pub mod flight;
 pub mod fs;
 pub mod init;
 pub mod io;
+#[cfg(feature = "ksh")]
+pub mod ksh;
 pub mod mem;
+pub mod reboot;
+pub mod shutdown;
 pub mod threading;
+pub mod time;
 pub mod graphics;
@@ -0,0 +1,171 @@
+use conquer_once::spin::OnceCell;
+use os_macros::init_task;
+
+use crate::{arch, arch::x86::cmos, bootinfo, serial_println};
+
+/// kernel command line option for soak testing: a panic resets the machine instead of killing the task, and the
+/// next boot runs the kernel tests again. Test builds also reset after every passing run
+pub const SOAK_OPTION: &str = "soak";
+
+// nvram, which neither the firmware nor qemu use
+const REASON_REGISTER: u8 = 0x7e;
+const RESETS_REGISTER: u8 = 0x7f;
+/// tags the reason register as written by the kernel, its contents are random after the battery ran out
+const MAGIC: u8 = 0xa0;
+
+/// why the machine was reset. Stored in cmos ram, such that the next boot can act on it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum RebootReason {
+    /// a plain reset, the next boot is a normal one
+    Requested = 1,
+    Panic = 2,
+    /// the watchdog was not pinged in time
+    Watchdog = 3,
+    /// the next boot runs the kernel tests
+    TestRun = 4,
+    /// the next boot only starts the kernel shell
+    Recovery = 5,
+}
+
+impl RebootReason {
+    fn encode(self) -> u8 {
+        MAGIC | self as u8
+    }
+
+    fn decode(value: u8) -> Option<Self> {
+        if value & 0xf0 != MAGIC {
+            return None;
+        }
+        Some(match value & 0x0f {
+            1 => Self::Requested,
+            2 => Self::Panic,
+            3 => Self::Watchdog,
+            4 => Self::TestRun,
+            5 => Self::Recovery,
+            _ => return None,
+        })
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Requested => "requested",
+            Self::Panic => "panic",
+            Self::Watchdog => "watchdog",
+            Self::TestRun => "test run",
+            Self::Recovery => "recovery",
+        }
+    }
+}
+
+/// what the kernel starts once it booted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootMode {
+    /// the startup binaries
+    Normal,
+    /// the kernel tests, which are only built into test_run kernels
+    TestRun,
+    /// nothing but the kernel shell, to look at what went wrong without userspace in the way
+    Recovery,
+}
+
+impl BootMode {
+    fn after(reason: Option<RebootReason>, soaking: bool) -> Self {
+        match reason {
+            Some(RebootReason::TestRun) => Self::TestRun,
+            Some(RebootReason::Recovery) => Self::Recovery,
+            // a soak test retries after crashes
+            Some(RebootReason::Panic | RebootReason::Watchdog) if soaking => Self::TestRun,
+            Some(RebootReason::Panic | RebootReason::Watchdog) => Self::Recovery,
+            Some(RebootReason::Requested) | None => Self::Normal,
+        }
+    }
+}
+
+static LAST_REASON: OnceCell<Option<RebootReason>> = OnceCell::uninit();
+
+/// the reason of the previous reset. It is cleared once read, such that the reset after the next one is a normal
+/// boot again, unless a new reason is set
+pub fn last_reason() -> Option<RebootReason> {
+    *LAST_REASON.get_or_init(|| {
+        let reason = RebootReason::decode(cmos::read(REASON_REGISTER));
+        cmos::write(REASON_REGISTER, 0);
+        reason
+    })
+}
+
+pub fn boot_mode() -> BootMode {
+    BootMode::after(last_reason(), soaking())
+}
+
+pub fn soaking() -> bool {
+    bootinfo::cmdline_option(SOAK_OPTION).is_some()
+}
+
+/// the number of resets with a reason since the last boot without one
+pub fn resets() -> u8 {
+    cmos::read(RESETS_REGISTER)
+}
+
+/// stores reason for the next boot, without resetting the machine
+pub fn set_reason(reason: RebootReason) {
+    cmos::write(REASON_REGISTER, reason.encode());
+}
+
+/// stores reason for the next boot and resets the machine
+pub fn reboot(reason: RebootReason) -> ! {
+    set_reason(reason);
+    cmos::write(RESETS_REGISTER, resets().wrapping_add(1));
+    arch::reboot()
+}
+
+#[init_task(stage = "fs")]
+fn report_reboot_reason() {
+    match last_reason() {
+        Some(reason) => serial_println!(
+            "previous boot ended with reason {} after {} resets, booting in {:?} mode",
+            reason.as_str(),
+            resets(),
+            boot_mode()
+        ),
+        None => cmos::write(RESETS_REGISTER, 0),
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    #[kernel_test]
+    fn reasons() {
+        for reason in [
+            RebootReason::Requested,
+            RebootReason::Panic,
+            RebootReason::Watchdog,
+            RebootReason::TestRun,
+            RebootReason::Recovery,
+        ] {
+            assert_eq!(RebootReason::decode(reason.encode()), Some(reason));
+        }
+        // uninitialized cmos ram
+        assert_eq!(RebootReason::decode(0), None);
+        assert_eq!(RebootReason::decode(0xff), None);
+        assert_eq!(RebootReason::decode(MAGIC), None);
+
+        assert_eq!(BootMode::after(None, true), BootMode::Normal);
+        assert_eq!(
+            BootMode::after(Some(RebootReason::Panic), false),
+            BootMode::Recovery
+        );
+        assert_eq!(
+            BootMode::after(Some(RebootReason::Panic), true),
+            BootMode::TestRun
+        );
+        assert_eq!(
+            BootMode::after(Some(RebootReason::Recovery), true),
+            BootMode::Recovery
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{
+    QemuExitCode,
+    arch,
+    drivers::model,
+    eprintln,
+    exit_qemu,
+    kernel::{
+        fs::{FS, vfs},
+        init::{InitStage, run_stage},
+        threading,
+    },
+    serial_println,
+};
+
+// the machine is powered off in this order:
+// - services are notified through the init tasks of the shutdown stage and stop taking new work
+// - all mounts are flushed, followed by the page caches of the disks below them
+// - the drivers release their devices
+// - the other cpus are parked, such that nothing touches the hardware anymore
+// - the machine is powered off
+
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+/// whether poweroff was called. Services may check it to refuse new work
+pub fn shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::Acquire)
+}
+
+/// shuts the kernel down in order and powers the machine off with code as the exit code of qemu.
+/// Only the first caller shuts down, any later one yields until the machine is off
+pub fn poweroff(code: QemuExitCode) -> ! {
+    if SHUTTING_DOWN.swap(true, Ordering::AcqRel) {
+        loop {
+            threading::yield_now();
+        }
+    }
+    serial_println!("powering off...");
+    run_stage(InitStage::Shutdown);
+    flush_filesystems();
+    model::registry().remove_all();
+    arch::park_other_cpus();
+    exit_qemu(code);
+    // not running in qemu, the machine is left halted
+    unsafe { arch::interrupt::disable() };
+    arch::hcf()
+}
+
+/// flushes all mounts of the global mount table, the deepest first. Mounts, which only exist in another mount
+/// namespace, are not seen, but most filesystems are shared with the global table
+fn flush_filesystems() {
+    if let Some(vfs) = vfs::VFS.get() {
+        for mount in vfs.mount_points().iter().rev() {
+            if let Err(e) = vfs.flush(mount) {
+                eprintln!("could not flush {}: {}", mount, e);
+            }
+        }
+    }
+    #[cfg(feature = "disk")]
+    crate::drivers::block::cache::flush_all();
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+    use crate::kernel::fs::RAMFS_PATH;
+
+    #[kernel_test]
+    fn flush_all_mounts() {
+        let mounts = vfs::VFS.get().unwrap().mount_points();
+        assert!(mounts.iter().any(|mount| mount.as_str() == RAMFS_PATH));
+        assert!(mounts.is_sorted());
+        flush_filesystems();
+        assert!(!shutting_down());
+    }
+}
@@ -0,0 +1,319 @@
+use alloc::{boxed::Box, string::String, vec::Vec};
+
+/// an owned, heap allocated block of encoded values, which is passed to a task in a single Arg, once six registers do not
+/// suffice. The Arg points to the bytes of the block together with their length, see Arg::from_block and Arg::as_block.
+/// Values are encoded back to back, thus a block built from a, b and c decodes as (A, B, C)
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ArgBlock {
+    bytes: Vec<u8>,
+}
+
+impl ArgBlock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// a block holding only val
+    pub fn encode<T: ArgEncode>(val: &T) -> Self {
+        let mut block = Self::new();
+        block.push(val);
+        block
+    }
+
+    /// appends val to the block
+    pub fn push<T: ArgEncode>(&mut self, val: &T) -> &mut Self {
+        val.encode(self);
+        self
+    }
+
+    /// appends raw bytes, without their length
+    pub fn put_bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// reads the values of the block in the order they were pushed
+    pub fn reader(&self) -> ArgReader<'_> {
+        ArgReader { bytes: &self.bytes }
+    }
+
+    /// decodes the whole block as a T. Fails, if bytes are left over
+    pub fn decode<T: ArgEncode>(&self) -> Result<T, ArgDecodeError> {
+        let mut reader = self.reader();
+        let val = reader.read()?;
+        if reader.remaining() != 0 {
+            return Err(ArgDecodeError::TrailingBytes);
+        }
+        Ok(val)
+    }
+
+    pub(crate) fn into_boxed_bytes(self) -> Box<[u8]> {
+        self.bytes.into_boxed_slice()
+    }
+
+    pub(crate) fn from_boxed_bytes(bytes: Box<[u8]>) -> Self {
+        Self {
+            bytes: bytes.into_vec(),
+        }
+    }
+}
+
+/// a cursor over the encoded values of an ArgBlock
+#[derive(Debug, Clone)]
+pub struct ArgReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> ArgReader<'a> {
+    pub fn read<T: ArgEncode>(&mut self) -> Result<T, ArgDecodeError> {
+        T::decode(self)
+    }
+
+    /// the next n raw bytes
+    pub fn take(&mut self, n: usize) -> Result<&'a [u8], ArgDecodeError> {
+        if n > self.bytes.len() {
+            return Err(ArgDecodeError::Truncated);
+        }
+        let (head, tail) = self.bytes.split_at(n);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.bytes.len()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgDecodeError {
+    /// the block ended in the middle of a value
+    Truncated,
+    /// the bytes do not form a valid value, like a string, which is not utf8
+    Invalid,
+    /// the block holds more than the decoded value
+    TrailingBytes,
+}
+
+/// a value, which can be passed to a task in an ArgBlock. Structs derive it with #[derive(ArgEncode)]
+pub trait ArgEncode: Sized {
+    fn encode(&self, block: &mut ArgBlock);
+    fn decode(reader: &mut ArgReader<'_>) -> Result<Self, ArgDecodeError>;
+}
+
+macro_rules! impl_arg_encode_int {
+    ($($ty:ty),*) => {
+        $(
+            impl ArgEncode for $ty {
+                fn encode(&self, block: &mut ArgBlock) {
+                    block.put_bytes(&self.to_le_bytes());
+                }
+
+                fn decode(reader: &mut ArgReader<'_>) -> Result<Self, ArgDecodeError> {
+                    let bytes = reader.take(size_of::<$ty>())?;
+                    Ok(<$ty>::from_le_bytes(bytes.try_into().unwrap()))
+                }
+            }
+        )*
+    };
+}
+
+impl_arg_encode_int!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+impl ArgEncode for bool {
+    fn encode(&self, block: &mut ArgBlock) {
+        (*self as u8).encode(block);
+    }
+
+    fn decode(reader: &mut ArgReader<'_>) -> Result<Self, ArgDecodeError> {
+        match reader.read::<u8>()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(ArgDecodeError::Invalid),
+        }
+    }
+}
+
+impl ArgEncode for String {
+    fn encode(&self, block: &mut ArgBlock) {
+        self.len().encode(block);
+        block.put_bytes(self.as_bytes());
+    }
+
+    fn decode(reader: &mut ArgReader<'_>) -> Result<Self, ArgDecodeError> {
+        let len = reader.read::<usize>()?;
+        let bytes = reader.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| ArgDecodeError::Invalid)
+    }
+}
+
+impl<T: ArgEncode> ArgEncode for Vec<T> {
+    fn encode(&self, block: &mut ArgBlock) {
+        self.len().encode(block);
+        self.iter().for_each(|val| val.encode(block));
+    }
+
+    fn decode(reader: &mut ArgReader<'_>) -> Result<Self, ArgDecodeError> {
+        let len = reader.read::<usize>()?;
+        // every value takes at least a byte, which bounds the allocation by the size of the block
+        if len > reader.remaining() {
+            return Err(ArgDecodeError::Truncated);
+        }
+        (0..len).map(|_| reader.read()).collect()
+    }
+}
+
+impl<T: ArgEncode> ArgEncode for Option<T> {
+    fn encode(&self, block: &mut ArgBlock) {
+        self.is_some().encode(block);
+        if let Some(val) = self {
+            val.encode(block);
+        }
+    }
+
+    fn decode(reader: &mut ArgReader<'_>) -> Result<Self, ArgDecodeError> {
+        if reader.read::<bool>()? {
+            Ok(Some(reader.read()?))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+macro_rules! impl_arg_encode_tuple {
+    ($($name:ident),+) => {
+        impl<$($name: ArgEncode),+> ArgEncode for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn encode(&self, block: &mut ArgBlock) {
+                let ($($name,)+) = self;
+                $($name.encode(block);)+
+            }
+
+            fn decode(reader: &mut ArgReader<'_>) -> Result<Self, ArgDecodeError> {
+                Ok(($(reader.read::<$name>()?,)+))
+            }
+        }
+    };
+}
+
+impl_arg_encode_tuple!(A);
+impl_arg_encode_tuple!(A, B);
+impl_arg_encode_tuple!(A, B, C);
+impl_arg_encode_tuple!(A, B, C, D);
+impl_arg_encode_tuple!(A, B, C, D, E);
+impl_arg_encode_tuple!(A, B, C, D, E, F);
+
+/// builds an ArgBlock from its arguments, which decodes as a tuple of them
+/// ```ignore
+/// let block = arg_block!(String::from("name"), 3u32);
+/// let (name, retries): (String, u32) = block.decode().unwrap();
+/// ```
+#[macro_export]
+macro_rules! arg_block {
+    ($($arg:expr),* $(,)?) => {{
+        #[allow(unused_mut)]
+        let mut block = $crate::kernel::threading::argblock::ArgBlock::new();
+        $(
+            block.push(&$arg);
+        )*
+        block
+    }};
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use alloc::vec;
+
+    use os_macros::{ArgEncode, kernel_test, with_default_args};
+
+    use super::*;
+    use crate::kernel::threading::{
+        ProcessReturn,
+        spawn_fn,
+        task::{Arg, Args},
+    };
+
+    #[derive(ArgEncode, Debug, PartialEq, Eq, Default)]
+    struct Config {
+        name: String,
+        retries: u32,
+        paths: Vec<String>,
+        limit: Option<u64>,
+        verbose: bool,
+    }
+
+    #[derive(ArgEncode, Debug, PartialEq, Eq)]
+    struct Pair(u8, i64);
+
+    fn config() -> Config {
+        Config {
+            name: "worker".into(),
+            retries: 3,
+            paths: vec!["/bin".into(), "/ram".into()],
+            limit: Some(1 << 40),
+            verbose: true,
+        }
+    }
+
+    #[kernel_test]
+    fn encode_decode() {
+        let block = ArgBlock::encode(&config());
+        assert_eq!(block.decode::<Config>(), Ok(config()));
+        assert_eq!(
+            ArgBlock::encode(&Pair(7, -1)).decode::<Pair>(),
+            Ok(Pair(7, -1))
+        );
+
+        // values are laid out back to back
+        let block = arg_block!(1u16, String::from("a"), Pair(2, 3));
+        assert_eq!(
+            block.decode::<(u16, String, Pair)>(),
+            Ok((1, "a".into(), Pair(2, 3)))
+        );
+        let mut reader = block.reader();
+        assert_eq!(reader.read::<u16>(), Ok(1));
+        assert_eq!(reader.remaining(), block.len() - 2);
+
+        assert_eq!(
+            block.decode::<(u16, String)>(),
+            Err(ArgDecodeError::TrailingBytes)
+        );
+        let mut truncated = ArgBlock::new();
+        truncated.put_bytes(&block.as_bytes()[..4]);
+        assert_eq!(
+            truncated.decode::<(u16, String)>(),
+            Err(ArgDecodeError::Truncated)
+        );
+        assert_eq!(
+            arg_block!(2u8).decode::<bool>(),
+            Err(ArgDecodeError::Invalid)
+        );
+    }
+
+    #[with_default_args]
+    extern "C" fn configured(block: Arg, count: Arg) -> ProcessReturn {
+        let config = unsafe { block.as_block() }.decode::<Config>().unwrap();
+        assert_eq!(config, self::config());
+        assert_eq!(unsafe { count.as_val::<usize>() }, 2);
+        config.paths.len()
+    }
+
+    #[kernel_test]
+    fn spawn_with_block() {
+        let mut args: [Arg; 6] = core::array::from_fn(|_| Arg::default());
+        args[0] = Arg::from_block(ArgBlock::encode(&config()));
+        args[1] = Arg::from_val(2usize);
+        let handle = spawn_fn(configured, Args::new(args)).unwrap();
+        assert_eq!(handle.wait(), Ok(2));
+    }
+}
@@ -0,0 +1,697 @@
+use alloc::{borrow::ToOwned, string::String, vec, vec::Vec};
+use core::{ptr, sync::atomic::Ordering};
+
+use thiserror::Error;
+use tinyos_abi::{flags::OpenOptions, types::SysErrCode};
+
+use crate::{
+    arch::{
+        context::UserRegs,
+        mem::{
+            Mapper,
+            Page,
+            PageSize,
+            PageTableFlags,
+            Size4KiB,
+            Translate,
+            VirtAddr,
+            mapper::TranslateResult,
+        },
+    },
+    kernel::{
+        fd::{FDFlags, FPerms, FileDescriptor, FileHandle},
+        fs::{self, FSError, Path, PathBuf},
+        io::{Read, Write},
+        mem::{
+            addr::UserVirtAddr,
+            paging::{TaskPageTable, get_hhdm_addr, map_region_with},
+            vma::{Vma, VmaBacking},
+        },
+        threading::{
+            ThreadingError,
+            schedule::add_built_task,
+            task::{PrivilegeLevel, ProcessID, Task, TaskBuilder, TaskRepr, TaskState},
+            tls,
+        },
+    },
+};
+
+// experimental checkpoints of user processes, a tiny CRIU.
+// A checkpoint holds the registers of the single thread of a process, its vmas together with the contents of all
+// present pages and the open files, which have a path. It is written to a file, usually in the RamFS, and may be
+// restored any number of times as a new process.
+// Not saved are the fpu state, files without a path (pipes, sockets, ...), the vdso, which every process gets anyways,
+// and children, groups and namespaces, which the restored process takes from the restoring one.
+
+const MAGIC: &[u8; 8] = b"TOSCKPT\0";
+const VERSION: u32 = 1;
+const PAGE_SIZE: usize = Size4KiB::SIZE as usize;
+/// int 0x80, through which user threads enter syscall_stub
+const SYSCALL_INSN: [u8; 2] = [0xcd, 0x80];
+
+#[derive(Debug, Error)]
+pub enum CheckpointError {
+    #[error("the task is not a user task, which is blocked in a syscall")]
+    NotStopped,
+    #[error("only single threaded processes can be checkpointed")]
+    MultiThreaded,
+    #[error("malformed checkpoint: {0}")]
+    Malformed(&'static str),
+    #[error("the address space could not be restored: {0}")]
+    Map(&'static str),
+    #[error(transparent)]
+    Fs(#[from] FSError),
+    #[error(transparent)]
+    Threading(#[from] ThreadingError),
+}
+
+/// writes a checkpoint of the process of task to path.
+/// task must be the only thread of a user process and must be blocked in a syscall. The checkpoint is only consistent,
+/// if the thread stays blocked until checkpoint returns
+pub fn checkpoint(task: &Task, path: &Path) -> Result<(), CheckpointError> {
+    if task.privilege() != PrivilegeLevel::User
+        || !matches!(task.state(), TaskState::Blocking | TaskState::Sleeping)
+        || tls::task_data().current_tid() == task.tid()
+    {
+        return Err(CheckpointError::NotStopped);
+    }
+    let pid = task.pid();
+    let mut threads = 0;
+    tls::task_data().get_table().for_each(|_, thread| {
+        if thread.pid() == pid {
+            threads += 1;
+        }
+    });
+    if threads > 1 {
+        return Err(CheckpointError::MultiThreaded);
+    }
+
+    // SAFETY: the thread is blocked, thus its kernel stack is not in use
+    let regs = unsafe { UserRegs::from_syscall_frame(*task.kstack_top()) }
+        .ok_or(CheckpointError::NotStopped)?;
+    // a thread blocked in a fault handler has a different frame below the one pushed by the cpu
+    let mut insn = [0; 2];
+    for (i, byte) in insn.iter_mut().enumerate() {
+        let addr = VirtAddr::try_new(regs.rip.wrapping_sub(2 - i as u64))
+            .map_err(|_| CheckpointError::NotStopped)?;
+        *byte = read_byte(task, addr).ok_or(CheckpointError::NotStopped)?;
+    }
+    if insn != SYSCALL_INSN {
+        return Err(CheckpointError::NotStopped);
+    }
+
+    let image = capture(task, regs)?;
+    let file = fs::open(
+        path,
+        OpenOptions::CREATE_ALL | OpenOptions::WRITE | OpenOptions::TRUNCATE,
+    )?;
+    file.write_all(&image.encode(), 0)?;
+    Ok(())
+}
+
+/// starts a new process from the checkpoint at path and returns its pid.
+/// The syscall the process was blocked in when the checkpoint was taken fails with Cancelled
+pub fn restore(path: &Path) -> Result<ProcessID, CheckpointError> {
+    let file = fs::open(path, OpenOptions::READ)?;
+    let mut bytes = Vec::new();
+    let len = file.read_to_end(&mut bytes, 0)?;
+    bytes.truncate(len);
+
+    let task = build(&Image::decode(&bytes)?)?;
+    let pid = task.pid();
+    add_built_task(task);
+    Ok(pid)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FileRecord {
+    fd: FileDescriptor,
+    fd_flags: FDFlags,
+    perms: FPerms,
+    cursor: usize,
+    path: PathBuf,
+}
+
+impl FileRecord {
+    /// the options to reopen the file with. It is neither truncated, nor created again
+    fn options(&self) -> OpenOptions {
+        let mut options = OpenOptions::empty();
+        for (perm, option) in [
+            (FPerms::READ, OpenOptions::READ),
+            (FPerms::WRITE, OpenOptions::WRITE),
+            (FPerms::APPEND, OpenOptions::APPEND),
+            (FPerms::EXECUTE, OpenOptions::EXECUTE),
+        ] {
+            if self.perms.contains(perm) {
+                options |= option;
+            }
+        }
+        options
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Image {
+    name: Option<String>,
+    cwd: PathBuf,
+    next_free_addr: u64,
+    regs: UserRegs,
+    vmas: Vec<Vma>,
+    /// the contents of all present pages, by address
+    pages: Vec<(u64, Vec<u8>)>,
+    files: Vec<FileRecord>,
+}
+
+/// the hhdm address of addr in tbl, if it is mapped
+fn hhdm_ptr(tbl: &TaskPageTable, addr: VirtAddr) -> Option<*mut u8> {
+    match tbl.table.translate(addr) {
+        TranslateResult::Mapped { frame, offset, .. } => {
+            Some((get_hhdm_addr() + frame.start_address().as_u64() + offset) as *mut u8)
+        }
+        _ => None,
+    }
+}
+
+fn read_byte(task: &Task, addr: VirtAddr) -> Option<u8> {
+    let tbl = task.pagedir().try_get_owned()?.lock();
+    // SAFETY: the frame is mapped in tbl and reachable through the hhdm
+    hhdm_ptr(&tbl, addr).map(|ptr| unsafe { ptr.read_volatile() })
+}
+
+fn capture(task: &Task, regs: UserRegs) -> Result<Image, CheckpointError> {
+    // file mappings are saved as anonymous memory, as their pages are copied
+    let vmas: Vec<Vma> = task
+        .core
+        .vmas
+        .read()
+        .iter()
+        .filter(|vma| vma.backing() != &VmaBacking::Vdso)
+        .map(|vma| match vma.backing() {
+            VmaBacking::File(_) | VmaBacking::Ring(_) => {
+                Vma::new(vma.start(), vma.len(), vma.flags(), VmaBacking::Anonymous)
+            }
+            _ => vma.clone(),
+        })
+        .collect();
+
+    let mut pages = Vec::new();
+    {
+        let tbl = task
+            .pagedir()
+            .try_get_owned()
+            .ok_or(CheckpointError::NotStopped)?
+            .lock();
+        for vma in &vmas {
+            for addr in (vma.start().as_u64()..vma.end().as_u64()).step_by(PAGE_SIZE) {
+                if let Some(src) = hhdm_ptr(&tbl, VirtAddr::new(addr)) {
+                    let mut page = vec![0; PAGE_SIZE];
+                    // SAFETY: the page is mapped in tbl and reachable through the hhdm
+                    unsafe { ptr::copy_nonoverlapping(src, page.as_mut_ptr(), PAGE_SIZE) };
+                    pages.push((addr, page));
+                }
+            }
+        }
+    }
+
+    let files = task
+        .core
+        .fd_table
+        .read()
+        .iter()
+        .filter_map(|(fd, handle)| {
+            Some(FileRecord {
+                fd: *fd,
+                fd_flags: handle.fd_flags(),
+                perms: handle.perms().clone(),
+                cursor: handle.cursor(),
+                path: PathBuf::from(handle.get_path()?),
+            })
+        })
+        .collect();
+
+    Ok(Image {
+        name: task.name().map(String::from),
+        cwd: task.core.cwd.clone(),
+        next_free_addr: task.core.next_free_addr.load(Ordering::Acquire) as u64,
+        regs,
+        vmas,
+        pages,
+        files,
+    })
+}
+
+/// builds a new process from image. It is not yet scheduled
+fn build(image: &Image) -> Result<Task, CheckpointError> {
+    // SAFETY: the entry is replaced by the saved registers before the task runs
+    let mut builder = unsafe { TaskBuilder::from_addr(VirtAddr::new(image.regs.rip)) }?
+        .with_cwd(image.cwd.clone());
+    if let Some(name) = &image.name {
+        builder = builder.with_name(name.clone());
+    }
+    for record in &image.files {
+        let file = fs::open(&record.path, record.options())?;
+        file.set_cursor(record.cursor);
+        builder = builder.with_file(
+            record.fd,
+            FileHandle::from(file).with_fd_flags(record.fd_flags),
+        );
+    }
+    let builder = builder.as_usr()?;
+
+    {
+        let task = builder.task();
+        let group = task.core.resource_group.read().clone();
+        let mut charged = 0;
+        for (addr, contents) in &image.pages {
+            let addr = VirtAddr::new(*addr);
+            let vma = image
+                .vmas
+                .iter()
+                .find(|vma| vma.contains(addr))
+                .ok_or(CheckpointError::Malformed("page outside of the vmas"))?;
+            // the top of the stack was already mapped by as_usr
+            if task
+                .pagedir()
+                .translate_page(Page::containing_address(addr))
+                .is_err()
+            {
+                map_region_with(addr, PAGE_SIZE, vma.flags(), task.pagedir(), |alloc| {
+                    let frame = alloc.allocate_frame_for(&group)?;
+                    charged += 1;
+                    Some(frame)
+                })
+                .map_err(CheckpointError::Map)?;
+            }
+            let tbl = task.pagedir().try_get_owned().unwrap().lock();
+            let dst = hhdm_ptr(&tbl, addr).ok_or(CheckpointError::Map("page not mapped"))?;
+            // SAFETY: the page was just mapped in tbl, which is not active anywhere yet
+            unsafe { ptr::copy_nonoverlapping(contents.as_ptr(), dst, PAGE_SIZE) };
+        }
+        task.core
+            .charged_frames
+            .fetch_add(charged, Ordering::AcqRel);
+        task.core
+            .next_free_addr
+            .store(image.next_free_addr as usize, Ordering::Release);
+        let mut vmas = task.core.vmas.write();
+        image.vmas.iter().for_each(|vma| vmas.insert(vma.clone()));
+    }
+
+    let task = builder.build();
+    // the interrupted syscall returns an error without touching rax
+    let regs = UserRegs {
+        rdx: SysErrCode::Cancelled as u64,
+        ..image.regs
+    };
+    // SAFETY: the task was just built and never ran
+    unsafe { regs.apply_to_initial_frame(task.krsp()) };
+    Ok(task)
+}
+
+impl Image {
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Encoder(Vec::new());
+        out.bytes(MAGIC);
+        out.u32(VERSION);
+        out.str(self.name.as_deref().unwrap_or_default());
+        out.str(self.cwd.as_str());
+        out.u64(self.next_free_addr);
+        for reg in regs_to_array(&self.regs) {
+            out.u64(reg);
+        }
+
+        out.u32(self.vmas.len() as u32);
+        for vma in &self.vmas {
+            out.u64(vma.start().as_u64());
+            out.u64(vma.len() as u64);
+            out.u64(vma.flags().bits());
+            out.u8(match vma.backing() {
+                VmaBacking::Elf => 1,
+                VmaBacking::Stack => 2,
+                _ => 0,
+            });
+        }
+
+        out.u32(self.pages.len() as u32);
+        for (addr, contents) in &self.pages {
+            out.u64(*addr);
+            out.bytes(contents);
+        }
+
+        out.u32(self.files.len() as u32);
+        for file in &self.files {
+            out.u32(file.fd);
+            out.u8(file.fd_flags.bits());
+            out.u8(file.perms.bits());
+            out.u64(file.cursor as u64);
+            out.str(file.path.as_str());
+        }
+        out.0
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, CheckpointError> {
+        let mut input = Decoder(bytes);
+        if input.bytes(MAGIC.len())? != MAGIC {
+            return Err(CheckpointError::Malformed("not a checkpoint"));
+        }
+        if input.u32()? != VERSION {
+            return Err(CheckpointError::Malformed("unsupported version"));
+        }
+        let name = Some(input.str()?).filter(|name| !name.is_empty());
+        let cwd = PathBuf::from(input.str()?);
+        let next_free_addr = input.u64()?;
+        let mut regs = [0; 18];
+        for reg in &mut regs {
+            *reg = input.u64()?;
+        }
+        let regs = regs_from_array(regs);
+        // the thread resumes at rip on rsp, which must thus be user addresses
+        if UserVirtAddr::try_new_range(regs.rip, 0).is_none()
+            || UserVirtAddr::try_new_range(regs.rsp, 0).is_none()
+        {
+            return Err(CheckpointError::Malformed(
+                "registers outside of user space",
+            ));
+        }
+
+        // only pages of user memory are restored, with the permissions of user memory
+        let allowed = PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | PageTableFlags::USER_ACCESSIBLE
+            | PageTableFlags::NO_EXECUTE;
+        let vmas = (0..input.u32()?)
+            .map(|_| {
+                let (start, len) = (input.u64()?, input.u64()?);
+                let flags = (PageTableFlags::from_bits_truncate(input.u64()?) & allowed)
+                    | PageTableFlags::PRESENT
+                    | PageTableFlags::USER_ACCESSIBLE;
+                let backing = match input.u8()? {
+                    0 => VmaBacking::Anonymous,
+                    1 => VmaBacking::Elf,
+                    2 => VmaBacking::Stack,
+                    _ => return Err(CheckpointError::Malformed("unknown vma backing")),
+                };
+                UserVirtAddr::try_new_range(start, len)
+                    .ok_or(CheckpointError::Malformed("vma outside of user space"))?;
+                Ok(Vma::new(VirtAddr::new(start), len as usize, flags, backing))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let pages = (0..input.u32()?)
+            .map(|_| {
+                let addr = input.u64()?;
+                if addr % PAGE_SIZE as u64 != 0 {
+                    return Err(CheckpointError::Malformed("unaligned page"));
+                }
+                Ok((addr, input.bytes(PAGE_SIZE)?.to_vec()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let files = (0..input.u32()?)
+            .map(|_| {
+                Ok(FileRecord {
+                    fd: input.u32()?,
+                    fd_flags: FDFlags::from_bits_truncate(input.u8()?),
+                    perms: FPerms::from_bits_truncate(input.u8()?),
+                    cursor: input.u64()? as usize,
+                    path: PathBuf::from(input.str()?),
+                })
+            })
+            .collect::<Result<Vec<_>, CheckpointError>>()?;
+
+        if !input.0.is_empty() {
+            return Err(CheckpointError::Malformed("trailing data"));
+        }
+        Ok(Self {
+            name: name.map(ToOwned::to_owned),
+            cwd,
+            next_free_addr,
+            regs,
+            vmas,
+            pages,
+            files,
+        })
+    }
+}
+
+fn regs_to_array(regs: &UserRegs) -> [u64; 18] {
+    [
+        regs.rax,
+        regs.rbx,
+        regs.rcx,
+        regs.rdx,
+        regs.rsi,
+        regs.rdi,
+        regs.rbp,
+        regs.rsp,
+        regs.r8,
+        regs.r9,
+        regs.r10,
+        regs.r11,
+        regs.r12,
+        regs.r13,
+        regs.r14,
+        regs.r15,
+        regs.rip,
+        regs.rflags,
+    ]
+}
+
+fn regs_from_array(
+    [
+        rax,
+        rbx,
+        rcx,
+        rdx,
+        rsi,
+        rdi,
+        rbp,
+        rsp,
+        r8,
+        r9,
+        r10,
+        r11,
+        r12,
+        r13,
+        r14,
+        r15,
+        rip,
+        rflags,
+    ]: [u64; 18],
+) -> UserRegs {
+    UserRegs {
+        rax,
+        rbx,
+        rcx,
+        rdx,
+        rsi,
+        rdi,
+        rbp,
+        rsp,
+        r8,
+        r9,
+        r10,
+        r11,
+        r12,
+        r13,
+        r14,
+        r15,
+        rip,
+        rflags,
+    }
+}
+
+/// little endian encoding of a checkpoint
+struct Encoder(Vec<u8>);
+
+impl Encoder {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn u8(&mut self, v: u8) {
+        self.0.push(v);
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.bytes(&v.to_le_bytes());
+    }
+
+    fn u64(&mut self, v: u64) {
+        self.bytes(&v.to_le_bytes());
+    }
+
+    fn str(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.bytes(s.as_bytes());
+    }
+}
+
+struct Decoder<'a>(&'a [u8]);
+
+impl<'a> Decoder<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], CheckpointError> {
+        if self.0.len() < len {
+            return Err(CheckpointError::Malformed("truncated"));
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, CheckpointError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, CheckpointError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, CheckpointError> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn str(&mut self) -> Result<&'a str, CheckpointError> {
+        let len = self.u32()? as usize;
+        core::str::from_utf8(self.bytes(len)?)
+            .map_err(|_| CheckpointError::Malformed("invalid utf8"))
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::{kernel_test, with_default_args};
+    use tinyos_abi::flags::UnlinkOptions;
+    use x86_64::registers::rflags::RFlags;
+
+    use super::*;
+    use crate::kernel::{
+        fs::RAMFS_PATH,
+        mem::paging::map_region,
+        threading::{ProcessReturn, tls::cleanup_task},
+    };
+
+    #[with_default_args]
+    extern "C" fn noop() -> ProcessReturn {
+        0
+    }
+
+    const DATA: u64 = 0x5000_0000;
+
+    fn user_page(task: &Task, addr: u64) -> Vec<u8> {
+        let tbl = task.pagedir().try_get_owned().unwrap().lock();
+        let src = hhdm_ptr(&tbl, VirtAddr::new(addr)).unwrap();
+        let mut page = vec![0; PAGE_SIZE];
+        unsafe { ptr::copy_nonoverlapping(src, page.as_mut_ptr(), PAGE_SIZE) };
+        page
+    }
+
+    #[kernel_test]
+    fn checkpoint_roundtrip() {
+        let task = TaskBuilder::from_fn(noop)
+            .unwrap()
+            .with_name("checkpointed".into())
+            .as_usr()
+            .unwrap()
+            .build();
+        let flags = PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | PageTableFlags::USER_ACCESSIBLE
+            | PageTableFlags::NO_EXECUTE;
+        map_region(VirtAddr::new(DATA), PAGE_SIZE, flags, task.pagedir()).unwrap();
+        task.core.vmas.write().insert(Vma::new(
+            VirtAddr::new(DATA),
+            2 * PAGE_SIZE,
+            flags,
+            VmaBacking::Anonymous,
+        ));
+        {
+            let tbl = task.pagedir().try_get_owned().unwrap().lock();
+            let dst = hhdm_ptr(&tbl, VirtAddr::new(DATA)).unwrap();
+            for i in 0..PAGE_SIZE {
+                unsafe { dst.add(i).write(i as u8) };
+            }
+        }
+        let mut path = PathBuf::from(RAMFS_PATH);
+        path.push("checkpoint-roundtrip");
+        let file = fs::open(&path, OpenOptions::CREATE_ALL | OpenOptions::WRITE).unwrap();
+        file.set_cursor(3);
+        task.core
+            .fd_table
+            .write()
+            .insert(5, FileHandle::from(file).with_fd_flags(FDFlags::CLOEXEC))
+            .unwrap();
+
+        let regs = UserRegs {
+            rax: 1,
+            rbx: 2,
+            rdx: 3,
+            r12: 4,
+            r15: 5,
+            rip: 0x40_0000,
+            rsp: 0x7000,
+            // iopl 3 and nt are not taken over
+            rflags: (RFlags::CARRY_FLAG
+                | RFlags::IOPL_LOW
+                | RFlags::IOPL_HIGH
+                | RFlags::NESTED_TASK)
+                .bits(),
+            ..Default::default()
+        };
+        let image = capture(&task, regs).unwrap();
+        // the second page of the data vma was never mapped and the vdso is not saved
+        assert!(image.pages.iter().any(|(addr, _)| *addr == DATA));
+        assert!(
+            !image
+                .pages
+                .iter()
+                .any(|(addr, _)| *addr == DATA + PAGE_SIZE as u64)
+        );
+        assert!(
+            !image
+                .vmas
+                .iter()
+                .any(|vma| vma.backing() == &VmaBacking::Vdso)
+        );
+        assert_eq!(image.files.len(), 1);
+        assert_eq!(image.files[0].cursor, 3);
+
+        let encoded = image.encode();
+        assert_eq!(Image::decode(&encoded).unwrap(), image);
+        assert!(Image::decode(&encoded[..encoded.len() - 1]).is_err());
+        assert!(Image::decode(&encoded[1..]).is_err());
+        for (rip, rsp) in [(1 << 47, regs.rsp), (regs.rip, u64::MAX - 7)] {
+            let mut crafted = image.clone();
+            crafted.regs.rip = rip;
+            crafted.regs.rsp = rsp;
+            assert!(Image::decode(&crafted.encode()).is_err());
+        }
+
+        let restored = build(&image).unwrap();
+        assert_ne!(restored.pid(), task.pid());
+        assert_eq!(restored.name(), Some("checkpointed"));
+        assert_eq!(user_page(&restored, DATA), user_page(&task, DATA));
+        assert_eq!(
+            restored.core.vmas.read().find(VirtAddr::new(DATA)),
+            task.core.vmas.read().find(VirtAddr::new(DATA))
+        );
+        let file = restored.core.fd_table.read().get(&5).unwrap().clone();
+        assert_eq!(file.cursor(), 3);
+        assert_eq!(file.fd_flags(), FDFlags::CLOEXEC);
+
+        let started = unsafe { UserRegs::from_initial_frame(restored.krsp()) };
+        assert_eq!(started.rip, regs.rip);
+        assert_eq!(started.rsp, regs.rsp);
+        assert_eq!(
+            RFlags::from_bits_truncate(started.rflags),
+            RFlags::CARRY_FLAG | RFlags::INTERRUPT_FLAG
+        );
+        assert_eq!((started.rax, started.rbx), (1, 2));
+        assert_eq!((started.r12, started.r15), (4, 5));
+        assert_eq!(started.rdx, SysErrCode::Cancelled as u64);
+
+        cleanup_task(task.into());
+        cleanup_task(restored.into());
+        fs::rm(&path, UnlinkOptions::empty()).unwrap();
+    }
+}
@@ -0,0 +1,199 @@
+use alloc::collections::btree_map::BTreeMap;
+
+use tinyos_abi::{
+    flags::{TaskStateChange, TaskWaitOptions},
+    types::{ChildInfo, IdType},
+};
+
+use crate::kernel::threading::task::{ProcessGroupID, ProcessID};
+
+/// a state change of a child, which was not yet collected by its parent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChildEvent {
+    Exited(u64),
+    Stopped,
+    Continued,
+}
+
+impl ChildEvent {
+    pub fn change(&self) -> TaskStateChange {
+        match self {
+            Self::Exited(_) => TaskStateChange::EXIT,
+            Self::Stopped => TaskStateChange::STOP,
+            Self::Continued => TaskStateChange::CONTINUE,
+        }
+    }
+
+    fn matches(&self, wanted: TaskWaitOptions) -> bool {
+        match self {
+            Self::Exited(_) => wanted.contains(TaskWaitOptions::W_EXIT),
+            Self::Stopped => wanted.contains(TaskWaitOptions::W_STOP),
+            Self::Continued => wanted.contains(TaskWaitOptions::W_CONTINUE),
+        }
+    }
+
+    pub fn info(&self, pid: ProcessID) -> ChildInfo {
+        ChildInfo {
+            pid: pid.0,
+            change: self.change(),
+            status: match self {
+                Self::Exited(status) => *status,
+                _ => 0,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChildSelector {
+    All,
+    Pid(ProcessID),
+    PGrid(ProcessGroupID),
+}
+
+impl ChildSelector {
+    pub fn new(id_type: IdType, id: u64) -> Self {
+        match id_type {
+            IdType::All => Self::All,
+            IdType::Pid => Self::Pid(id.into()),
+            IdType::PGrid => Self::PGrid(id.into()),
+        }
+    }
+
+    fn selects(&self, pid: &ProcessID, child: &Child) -> bool {
+        match self {
+            Self::All => true,
+            Self::Pid(wanted) => wanted == pid,
+            Self::PGrid(wanted) => *wanted == child.pgrid,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Child {
+    pgrid: ProcessGroupID,
+    pending: Option<ChildEvent>,
+}
+
+/// the children of a single process.
+/// A child stays in the list until its exit was collected. Only the latest stop / continue is kept, an exit replaces both.
+#[derive(Debug, Default)]
+pub struct ChildList {
+    children: BTreeMap<ProcessID, Child>,
+}
+
+impl ChildList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, pid: ProcessID, pgrid: ProcessGroupID) {
+        self.children.insert(
+            pid,
+            Child {
+                pgrid,
+                pending: None,
+            },
+        );
+    }
+
+    /// records a state change of child pid. Returns false if pid is not a child or already exited
+    pub fn notify(&mut self, pid: &ProcessID, event: ChildEvent) -> bool {
+        let Some(child) = self.children.get_mut(pid) else {
+            return false;
+        };
+        if let Some(ChildEvent::Exited(_)) = child.pending {
+            return false;
+        }
+        child.pending = Some(event);
+        true
+    }
+
+    pub fn has_children(&self, selector: &ChildSelector) -> bool {
+        self.children
+            .iter()
+            .any(|(pid, child)| selector.selects(pid, child))
+    }
+
+    pub fn has_pending(&self, wanted: TaskWaitOptions) -> bool {
+        self.children
+            .values()
+            .any(|child| child.pending.is_some_and(|event| event.matches(wanted)))
+    }
+
+    /// returns the first pending state change of a selected child, lowest pid first.
+    /// If consume is set, the change is removed and exited children are reaped
+    pub fn collect(
+        &mut self,
+        selector: &ChildSelector,
+        wanted: TaskWaitOptions,
+        consume: bool,
+    ) -> Option<(ProcessID, ChildEvent)> {
+        let (pid, child) = self.children.iter_mut().find(|(pid, child)| {
+            selector.selects(pid, child) && child.pending.is_some_and(|event| event.matches(wanted))
+        })?;
+        let pid = *pid;
+        let event = if consume {
+            child.pending.take()?
+        } else {
+            child.pending?
+        };
+        if consume && let ChildEvent::Exited(_) = event {
+            self.children.remove(&pid);
+        }
+        Some((pid, event))
+    }
+
+    pub fn len(&self) -> usize {
+        self.children.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.children.is_empty()
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    #[kernel_test]
+    fn child_list() {
+        let mut list = ChildList::new();
+        list.add(ProcessID(3), ProcessGroupID(1));
+        list.add(ProcessID(5), ProcessGroupID(2));
+        let wanted = TaskWaitOptions::W_EXIT | TaskWaitOptions::W_STOP;
+
+        assert!(list.has_children(&ChildSelector::PGrid(ProcessGroupID(2))));
+        assert!(!list.has_children(&ChildSelector::Pid(ProcessID(4))));
+        assert_eq!(list.collect(&ChildSelector::All, wanted, true), None);
+
+        assert!(list.notify(&ProcessID(5), ChildEvent::Stopped));
+        assert!(!list.has_pending(TaskWaitOptions::W_CONTINUE));
+        // peeking does not consume the event
+        assert_eq!(
+            list.collect(&ChildSelector::All, wanted, false),
+            Some((ProcessID(5), ChildEvent::Stopped))
+        );
+        assert_eq!(
+            list.collect(&ChildSelector::Pid(ProcessID(5)), wanted, true),
+            Some((ProcessID(5), ChildEvent::Stopped))
+        );
+        assert_eq!(list.collect(&ChildSelector::All, wanted, true), None);
+
+        assert!(list.notify(&ProcessID(3), ChildEvent::Exited(1)));
+        assert!(!list.notify(&ProcessID(3), ChildEvent::Continued));
+        assert_eq!(
+            list.collect(&ChildSelector::PGrid(ProcessGroupID(2)), wanted, true),
+            None
+        );
+        assert_eq!(
+            list.collect(&ChildSelector::All, wanted, true),
+            Some((ProcessID(3), ChildEvent::Exited(1)))
+        );
+        // exited children are reaped once collected
+        assert_eq!(list.len(), 1);
+    }
+}
@@ -0,0 +1,367 @@
+use alloc::{boxed::Box, collections::btree_map::BTreeMap, sync::Arc, task::Wake};
+use core::{
+    future::Future,
+    pin::Pin,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use conquer_once::spin::OnceCell;
+use crossbeam::queue::ArrayQueue;
+use os_macros::init_task;
+use thiserror::Error;
+
+use super::wait::{
+    QueuTypeCondition,
+    QueueHandle,
+    QueueType,
+    WaitEvent,
+    condition::WaitCondition,
+    post_event,
+    queues::GenericWaitQueue,
+};
+use crate::{
+    drivers::wait_manager,
+    kernel::{threading, time::Instant},
+    sync::{get_next_lock_var, locks::Mutex},
+};
+
+/// the number of futures an executor drives at once
+pub const MAX_TASKS: usize = 256;
+/// upper bound on the delay of a wakeup, whose event was lost
+const IDLE_TIMEOUT: Duration = Duration::from_secs(1);
+
+static EXECUTOR: OnceCell<Arc<Executor>> = OnceCell::uninit();
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ExecutorError {
+    #[error("the executor runs too many tasks")]
+    Full,
+}
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+struct Task {
+    future: BoxFuture,
+    waker: Arc<TaskWaker>,
+}
+
+/// queues its task on the executor and posts an event to wake the executor thread.
+/// Waking neither allocates nor blocks, thus interrupt handlers may wake tasks
+struct TaskWaker {
+    id: u64,
+    /// whether the task is in the ready queue already
+    scheduled: AtomicBool,
+    executor: Arc<Executor>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if self.scheduled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        // every task is queued at most once, thus this cannot overflow
+        _ = self.executor.ready.push(self.id);
+        self.executor.notify();
+    }
+}
+
+/// drives futures on a single kernel thread, which blocks in the wait manager while no future can make progress.
+/// Drivers may thus be written as async state machines instead of spinning on their own threads
+pub struct Executor {
+    tasks: Mutex<BTreeMap<u64, Task>>,
+    ready: ArrayQueue<u64>,
+    /// wakers of sleeping futures by their deadline
+    timers: Mutex<BTreeMap<(Instant, u64), Waker>>,
+    next_id: AtomicU64,
+    /// the executor thread blocks on QueueType::Lock(wait_id)
+    wait_id: u64,
+}
+
+// called with the address of an executor, which is never freed while its thread runs
+static READY: fn(u64) -> bool = |executor| {
+    let executor = unsafe { &*(executor as *const Executor) };
+    !executor.ready.is_empty()
+};
+
+impl Executor {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            tasks: Mutex::new(BTreeMap::new()),
+            ready: ArrayQueue::new(MAX_TASKS),
+            timers: Mutex::new(BTreeMap::new()),
+            next_id: AtomicU64::new(0),
+            wait_id: get_next_lock_var(),
+        })
+    }
+
+    pub fn spawn<F>(self: &Arc<Self>, future: F) -> Result<(), ExecutorError>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let mut tasks = self.tasks.lock();
+        if tasks.len() >= MAX_TASKS {
+            return Err(ExecutorError::Full);
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let waker = Arc::new(TaskWaker {
+            id,
+            scheduled: AtomicBool::new(false),
+            executor: self.clone(),
+        });
+        tasks.insert(
+            id,
+            Task {
+                future: Box::pin(future),
+                waker: waker.clone(),
+            },
+        );
+        drop(tasks);
+        waker.wake();
+        Ok(())
+    }
+
+    /// the number of futures, which did not complete yet
+    pub fn tasks(&self) -> usize {
+        self.tasks.lock().len()
+    }
+
+    /// polls every ready future once and fires expired timers. Returns the number of futures polled
+    pub fn run_ready(&self) -> usize {
+        self.fire_timers();
+        let mut polled = 0;
+        // futures woken while they are polled are polled again on the next round
+        for _ in 0..self.ready.len() {
+            let Some(id) = self.ready.pop() else {
+                break;
+            };
+            self.poll(id);
+            polled += 1;
+        }
+        polled
+    }
+
+    /// runs the executor on the calling thread, forever
+    pub fn run(&self) -> ! {
+        let ready = QueuTypeCondition::with_cond(
+            QueueType::Lock(self.wait_id),
+            WaitCondition::Generic(
+                ptr::from_ref(self) as u64,
+                ptr::from_ref::<dyn Fn(u64) -> bool>(&READY),
+            ),
+        );
+        wait_manager::add_queue(
+            QueueHandle::from_owned(Box::new(GenericWaitQueue::new())),
+            QueueType::Lock(self.wait_id),
+        );
+        loop {
+            if self.run_ready() > 0 {
+                continue;
+            }
+            let deadline = self
+                .next_deadline()
+                .unwrap_or(Instant::now() + IDLE_TIMEOUT);
+            wait_manager::wait_self(&[
+                ready.clone(),
+                QueuTypeCondition::with_cond(QueueType::Timer, WaitCondition::Time(deadline)),
+            ]);
+        }
+    }
+
+    fn poll(&self, id: u64) {
+        // the task is taken out, such that it may spawn further tasks
+        let Some(mut task) = self.tasks.lock().remove(&id) else {
+            return;
+        };
+        task.waker.scheduled.store(false, Ordering::Release);
+        let waker = Waker::from(task.waker.clone());
+        let mut cx = Context::from_waker(&waker);
+        if task.future.as_mut().poll(&mut cx).is_pending() {
+            self.tasks.lock().insert(id, task);
+        }
+    }
+
+    fn notify(&self) {
+        _ = post_event(WaitEvent::new(QueueType::Lock(self.wait_id)));
+    }
+
+    fn add_timer(&self, deadline: Instant, waker: Waker) {
+        let seq = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.timers.lock().insert((deadline, seq), waker);
+        // the executor may be waiting on a later deadline
+        self.notify();
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        self.timers
+            .lock()
+            .first_key_value()
+            .map(|((deadline, _), _)| *deadline)
+    }
+
+    fn fire_timers(&self) {
+        let now = Instant::now();
+        let mut timers = self.timers.lock();
+        while let Some(entry) = timers.first_entry()
+            && entry.key().0 <= now
+        {
+            entry.remove().wake();
+        }
+    }
+}
+
+/// the executor of the kernel, driven by its own thread once the drivers are started
+pub fn executor() -> &'static Arc<Executor> {
+    EXECUTOR.get_or_init(Executor::new)
+}
+
+/// runs future on the kernel executor
+pub fn spawn<F>(future: F) -> Result<(), ExecutorError>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    executor().spawn(future)
+}
+
+#[init_task(stage = "drivers", order = 15)]
+fn start_executor() {
+    _ = threading::spawn(|| executor().run());
+}
+
+/// completes once duration passed. Only valid on the kernel executor, which keeps the timers
+pub fn sleep(duration: Duration) -> Sleep {
+    Sleep {
+        deadline: Instant::now() + duration,
+        registered: false,
+    }
+}
+
+#[derive(Debug)]
+pub struct Sleep {
+    deadline: Instant,
+    registered: bool,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            return Poll::Ready(());
+        }
+        if !self.registered {
+            executor().add_timer(self.deadline, cx.waker().clone());
+            self.registered = true;
+        }
+        Poll::Pending
+    }
+}
+
+/// lets the other ready futures run, before continuing
+pub fn yield_now() -> YieldNow {
+    YieldNow { yielded: false }
+}
+
+#[derive(Debug)]
+pub struct YieldNow {
+    yielded: bool,
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.yielded {
+            return Poll::Ready(());
+        }
+        self.yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    /// completes once set, like a driver waiting for its interrupt
+    #[derive(Default)]
+    struct Flag {
+        set: AtomicBool,
+        waker: Mutex<Option<Waker>>,
+    }
+
+    impl Flag {
+        fn set(&self) {
+            self.set.store(true, Ordering::Release);
+            if let Some(waker) = self.waker.lock().take() {
+                waker.wake();
+            }
+        }
+
+        fn wait(&self) -> impl Future<Output = ()> + '_ {
+            core::future::poll_fn(|cx| {
+                *self.waker.lock() = Some(cx.waker().clone());
+                if self.set.load(Ordering::Acquire) {
+                    Poll::Ready(())
+                } else {
+                    Poll::Pending
+                }
+            })
+        }
+    }
+
+    #[kernel_test]
+    fn futures_are_woken() {
+        // driven by hand, such that the test does not depend on the executor thread
+        let executor = Executor::new();
+        let flag = Arc::new(Flag::default());
+        let steps = Arc::new(AtomicU64::new(0));
+        let (flag_ptr, steps_ptr) = (flag.clone(), steps.clone());
+        executor
+            .spawn(async move {
+                steps_ptr.fetch_add(1, Ordering::Relaxed);
+                yield_now().await;
+                steps_ptr.fetch_add(1, Ordering::Relaxed);
+                flag_ptr.wait().await;
+                sleep(Duration::ZERO).await;
+                steps_ptr.fetch_add(1, Ordering::Relaxed);
+            })
+            .unwrap();
+        assert_eq!(executor.tasks(), 1);
+
+        assert_eq!(executor.run_ready(), 1);
+        assert_eq!(steps.load(Ordering::Relaxed), 1);
+        // woken by yield_now
+        assert_eq!(executor.run_ready(), 1);
+        assert_eq!(steps.load(Ordering::Relaxed), 2);
+        // nothing happens until the flag is set
+        assert_eq!(executor.run_ready(), 0);
+        flag.set();
+        assert_eq!(executor.run_ready(), 1);
+        assert_eq!(steps.load(Ordering::Relaxed), 3);
+        assert_eq!(executor.tasks(), 0);
+    }
+
+    #[kernel_test]
+    fn kernel_executor() {
+        let done = Arc::new(AtomicBool::new(false));
+        let done_ptr = done.clone();
+        spawn(async move {
+            yield_now().await;
+            done_ptr.store(true, Ordering::Release);
+        })
+        .unwrap();
+        while !done.load(Ordering::Acquire) {
+            threading::yield_now();
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use alloc::{format, string::String};
+use core::panic::PanicInfo;
+
+use tinyos_abi::flags::TaskStateChange;
+
+use crate::{
+    arch::{
+        interrupt::fault::{FaultKind, UserFault},
+        mem::VirtAddr,
+    },
+    eprintln,
+    kernel::{
+        elf::ksyms,
+        threading::{
+            self,
+            task::TaskRepr,
+            tls,
+            wait::{QueueType, WaitEvent, post_event},
+        },
+    },
+    serial_println,
+};
+
+/// terminates the current task after it raised fault in user mode.
+/// Runs on the kernel stack of the task, after the exception handler returned into the kernel
+pub fn kill_faulting_task(fault: UserFault) -> ! {
+    let tid = tls::task_data().current_tid();
+    let region = fault.addr.and_then(|addr| {
+        let addr = VirtAddr::try_new(addr).ok()?;
+        let task = tls::task_data().current_thread()?;
+        let vmas = task.core.vmas.read();
+        Some(match vmas.find(addr) {
+            Some(vma) => format!("in {}", vma),
+            None => String::from("outside of any mapping"),
+        })
+    });
+
+    eprintln!("{}", fault);
+    serial_println!("killing task {}: {}", tid.get_inner(), fault);
+    if let Some(region) = region {
+        eprintln!("  address {}", region);
+        serial_println!("  address {}", region);
+    }
+
+    _ = post_event(WaitEvent::with_data(
+        QueueType::Thread(tid),
+        TaskStateChange::EXIT.bits() as u64,
+    ));
+    tls::task_data().kill_with_signal(&tid, fault.kind.signal());
+    loop {
+        threading::yield_now();
+    }
+}
+
+/// the prefix of the panic messages, which rustc emits for failed overflow checks and divisions by zero
+const ARITHMETIC_PANIC: &str = "attempt to ";
+
+/// reports a panic of a failed overflow check or a division by zero with the task, which hit it, and the source
+/// location. Returns false for any other panic, which is left to the caller
+pub fn report_arithmetic_panic(info: &PanicInfo) -> bool {
+    let msg = format!("{}", info.message());
+    if !msg.starts_with(ARITHMETIC_PANIC) {
+        return false;
+    }
+    let location = info
+        .location()
+        .map_or(String::from("unknown location"), |location| {
+            format!(
+                "{}:{}:{}",
+                location.file(),
+                location.line(),
+                location.column()
+            )
+        });
+    let task = describe_current_task();
+    eprintln!("arithmetic trap: {}\n  at {}\n  in {}", msg, location, task);
+    serial_println!("arithmetic trap: {}\n  at {}\n  in {}", msg, location, task);
+    true
+}
+
+/// reports an exception raised in kernel mode, resolving rip to the kernel function, which raised it
+pub fn report_kernel_fault(kind: FaultKind, rip: u64) {
+    let symbol = ksyms::resolve(rip).map_or(String::from("unknown symbol"), |(sym, offset)| {
+        format!("{}+{:#x}", ksyms::demangle(sym.name), offset)
+    });
+    let task = describe_current_task();
+    eprintln!(
+        "kernel {} at rip {:#x} ({})\n  in {}",
+        kind.as_str(),
+        rip,
+        symbol,
+        task
+    );
+    serial_println!(
+        "kernel {} at rip {:#x} ({})\n  in {}",
+        kind.as_str(),
+        rip,
+        symbol,
+        task
+    );
+}
+
+fn describe_current_task() -> String {
+    let tid = tls::task_data().current_tid();
+    match tls::task_data().current_thread() {
+        Some(task) => format!("task {} ({:?})", tid.get_inner(), task.name()),
+        None => format!("task {}", tid.get_inner()),
+    }
+}
@@ -0,0 +1,481 @@
+use alloc::{
+    collections::btree_map::BTreeMap,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    fmt::Write as _,
+    iter,
+    mem,
+    sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering},
+};
+
+use conquer_once::spin::OnceCell;
+use tinyos_abi::flags::NodeType;
+
+use crate::{
+    arch::{
+        interrupt::without_interrupts,
+        mem::{PageSize, Size4KiB},
+    },
+    impl_file_for_wr,
+    kernel::{
+        fd::FileRepr,
+        fs::{FSErrorKind, FSResult},
+        io::{IOError, IOResult, Read, Write, read_rendered},
+        threading::{
+            task::{ProcessID, TaskCore, TaskState},
+            tls,
+        },
+    },
+    register_device_file,
+    sync::locks::RwLock,
+};
+
+// a hierarchy of resource groups, like cgroups on linux.
+// Every process belongs to exactly one group and its children start out in the same one.
+// cpu time is split in epochs: in each epoch a group may run as many time slices as its weight, summed over all of its tasks.
+// A task only runs, while its group and all ancestors have slices left. Once no runnable task may run, a new epoch starts.
+// Memory is charged in frames to a group and all ancestors and allocations fail once any of them reached its cap
+
+pub const GROUPS_DIR: &str = "/groups";
+pub const DEFAULT_WEIGHT: u32 = 100;
+pub const MAX_WEIGHT: u32 = 10000;
+
+const UNLIMITED: usize = usize::MAX;
+// files in the directory of each group
+const WEIGHT_FILE: &str = "weight";
+const MEMORY_MAX_FILE: &str = "memory.max";
+const MEMORY_CURRENT_FILE: &str = "memory.current";
+const PROCS_FILE: &str = "procs";
+const CREATE_FILE: &str = "create";
+
+static EPOCH: AtomicU64 = AtomicU64::new(0);
+static ROOT: OnceCell<Arc<ResourceGroup>> = OnceCell::uninit();
+
+/// the group of all tasks, which were not moved elsewhere
+pub fn root() -> &'static Arc<ResourceGroup> {
+    ROOT.get_or_init(|| Arc::new(ResourceGroup::new(String::new(), None)))
+}
+
+/// starts a new epoch, in which every group may run as many slices as its weight again
+pub fn refill_shares() {
+    EPOCH.fetch_add(1, Ordering::Relaxed);
+}
+
+/// registers the files of the root group at /proc/groups
+pub fn init() {
+    _ = register_files(root());
+}
+
+#[derive(Debug)]
+pub struct ResourceGroup {
+    /// path below /proc/groups, empty for the root
+    path: String,
+    parent: Option<Arc<ResourceGroup>>,
+    weight: AtomicU32,
+    slices: AtomicU32,
+    // epoch, in which slices was last refilled
+    epoch: AtomicU64,
+    max_frames: AtomicUsize,
+    frames: AtomicUsize,
+    children: RwLock<BTreeMap<String, Arc<ResourceGroup>>>,
+}
+
+impl ResourceGroup {
+    fn new(path: String, parent: Option<Arc<ResourceGroup>>) -> Self {
+        Self {
+            path,
+            parent,
+            weight: AtomicU32::new(DEFAULT_WEIGHT),
+            slices: AtomicU32::new(DEFAULT_WEIGHT),
+            epoch: AtomicU64::new(EPOCH.load(Ordering::Relaxed)),
+            max_frames: AtomicUsize::new(UNLIMITED),
+            frames: AtomicUsize::new(0),
+            children: RwLock::default(),
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// creates the child group name and registers its files at /proc/groups/<path>/name
+    pub fn create_child(self: &Arc<Self>, name: &str) -> FSResult<Arc<Self>> {
+        if name.is_empty()
+            || name.contains('/')
+            || [
+                WEIGHT_FILE,
+                MEMORY_MAX_FILE,
+                MEMORY_CURRENT_FILE,
+                PROCS_FILE,
+                CREATE_FILE,
+            ]
+            .contains(&name)
+        {
+            return Err(IOError::with_message(
+                FSErrorKind::InvalidPath,
+                "invalid group name",
+            ));
+        }
+        let mut children = self.children.write();
+        if children.contains_key(name) {
+            return Err(IOError::simple(FSErrorKind::AlreadyExists));
+        }
+        let child = Arc::new(Self::new(
+            format!("{}/{}", self.path, name),
+            Some(self.clone()),
+        ));
+        register_files(&child)?;
+        children.insert(name.to_string(), child.clone());
+        Ok(child)
+    }
+
+    pub fn child(&self, name: &str) -> Option<Arc<Self>> {
+        self.children.read().get(name).cloned()
+    }
+
+    pub fn weight(&self) -> u32 {
+        self.weight.load(Ordering::Relaxed)
+    }
+
+    /// the share of cpu time relative to the other groups, clamped to 1..=MAX_WEIGHT. Takes effect in the next epoch
+    pub fn set_weight(&self, weight: u32) {
+        self.weight
+            .store(weight.clamp(1, MAX_WEIGHT), Ordering::Relaxed);
+    }
+
+    /// cap on the frames charged to this group and its descendants, usize::MAX if unlimited
+    pub fn max_frames(&self) -> usize {
+        self.max_frames.load(Ordering::Relaxed)
+    }
+
+    /// lowering the cap below the current usage does not reclaim memory, it only makes further allocations fail
+    pub fn set_max_frames(&self, max: usize) {
+        self.max_frames.store(max, Ordering::Relaxed);
+    }
+
+    /// frames currently charged to this group and its descendants
+    pub fn frames(&self) -> usize {
+        self.frames.load(Ordering::Relaxed)
+    }
+
+    fn ancestors(&self) -> impl Iterator<Item = &ResourceGroup> + Clone {
+        iter::successors(Some(self), |group| group.parent.as_deref())
+    }
+
+    /// charges n frames to self and all ancestors. Fails without charging anything, if any of them would exceed its cap
+    pub fn try_charge_frames(&self, n: usize) -> bool {
+        for (charged, group) in self.ancestors().enumerate() {
+            let max = group.max_frames();
+            if group
+                .frames
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |frames| {
+                    frames.checked_add(n).filter(|frames| *frames <= max)
+                })
+                .is_err()
+            {
+                for group in self.ancestors().take(charged) {
+                    group.frames.fetch_sub(n, Ordering::AcqRel);
+                }
+                return false;
+            }
+        }
+        true
+    }
+
+    /// charges n frames regardless of the caps, used when memory moves between groups
+    pub fn charge_frames(&self, n: usize) {
+        for group in self.ancestors() {
+            group.frames.fetch_add(n, Ordering::AcqRel);
+        }
+    }
+
+    pub fn uncharge_frames(&self, n: usize) {
+        for group in self.ancestors() {
+            _ = group
+                .frames
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |frames| {
+                    Some(frames.saturating_sub(n))
+                });
+        }
+    }
+
+    fn refresh(&self, epoch: u64) {
+        if self.epoch.swap(epoch, Ordering::AcqRel) != epoch {
+            self.slices.store(self.weight(), Ordering::Relaxed);
+        }
+    }
+
+    /// takes a time slice from self and all ancestors. Fails, if any of them used up its share of the current epoch.
+    /// Does not block or allocate, as it is called while switching
+    pub fn try_take_slice(&self) -> bool {
+        let epoch = EPOCH.load(Ordering::Relaxed);
+        for group in self.ancestors() {
+            group.refresh(epoch);
+            if group.slices.load(Ordering::Relaxed) == 0 {
+                return false;
+            }
+        }
+        for group in self.ancestors() {
+            _ = group
+                .slices
+                .fetch_update(Ordering::AcqRel, Ordering::Relaxed, |slices| {
+                    slices.checked_sub(1)
+                });
+        }
+        true
+    }
+}
+
+/// moves the process owning task into group. The memory charged to the process moves along and may exceed the cap of group
+pub fn move_process(task: &TaskCore, group: Arc<ResourceGroup>) {
+    // the scheduler reads the group while switching, thus it must not be interrupted while holding the lock
+    let old = without_interrupts(|| mem::replace(&mut *task.resource_group.write(), group.clone()));
+    let charged = task.charged_frames.load(Ordering::Acquire);
+    old.uncharge_frames(charged);
+    group.charge_frames(charged);
+}
+
+/// gives the frames charged to the process owning task back to its group, once the process exited.
+/// The frames themselves are freed later, together with the address space
+pub fn release_process(task: &TaskCore) {
+    let charged = task.charged_frames.swap(0, Ordering::AcqRel);
+    task.resource_group.read().uncharge_frames(charged);
+}
+
+fn register_files(group: &Arc<ResourceGroup>) -> FSResult<()> {
+    for kind in [
+        GroupFileKind::Weight,
+        GroupFileKind::MemoryMax,
+        GroupFileKind::MemoryCurrent,
+        GroupFileKind::Procs,
+        GroupFileKind::Create,
+    ] {
+        let path = format!("{}{}/{}", GROUPS_DIR, group.path, kind.name());
+        register_device_file!(
+            Arc::new(GroupFile {
+                group: group.clone(),
+                kind,
+            }) as Arc<dyn FileRepr>,
+            path.as_str()
+        )?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy)]
+enum GroupFileKind {
+    Weight,
+    MemoryMax,
+    MemoryCurrent,
+    Procs,
+    Create,
+}
+
+impl GroupFileKind {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Weight => WEIGHT_FILE,
+            Self::MemoryMax => MEMORY_MAX_FILE,
+            Self::MemoryCurrent => MEMORY_CURRENT_FILE,
+            Self::Procs => PROCS_FILE,
+            Self::Create => CREATE_FILE,
+        }
+    }
+}
+
+/// a control file of a group at /proc/groups/<path>/:
+/// weight: the cpu share of the group, default 100.
+/// memory.max: the memory cap in bytes, rounded up to whole frames, or max if unlimited.
+/// memory.current: the memory charged to the group and its descendants in bytes, read only.
+/// procs: the global pids of the processes in the group, writing a pid moves the process into it.
+/// create: lists the child groups, writing a name creates a new child group
+#[derive(Debug)]
+struct GroupFile {
+    group: Arc<ResourceGroup>,
+    kind: GroupFileKind,
+}
+
+impl GroupFile {
+    fn render(&self) -> String {
+        let frame = Size4KiB::SIZE as usize;
+        match self.kind {
+            GroupFileKind::Weight => format!("{}\n", self.group.weight()),
+            GroupFileKind::MemoryMax => match self.group.max_frames() {
+                UNLIMITED => String::from("max\n"),
+                max => format!("{}\n", max * frame),
+            },
+            GroupFileKind::MemoryCurrent => format!("{}\n", self.group.frames() * frame),
+            GroupFileKind::Procs => {
+                let mut out = String::new();
+                let mut pids: Vec<u64> = tls::task_data()
+                    .processes()
+                    .read()
+                    .iter()
+                    .filter(|(_, core)| {
+                        core.get_process_state() != TaskState::Zombie
+                            && Arc::ptr_eq(&core.resource_group.read(), &self.group)
+                    })
+                    .map(|(pid, _)| pid.0)
+                    .collect();
+                pids.sort_unstable();
+                for pid in pids {
+                    _ = writeln!(out, "{}", pid);
+                }
+                out
+            }
+            GroupFileKind::Create => {
+                let mut out = String::new();
+                for name in self.group.children.read().keys() {
+                    _ = writeln!(out, "{}", name);
+                }
+                out
+            }
+        }
+    }
+}
+
+impl Read for GroupFile {
+    fn read(&self, buf: &mut [u8], offset: usize) -> IOResult<usize> {
+        read_rendered(self.render(), buf, offset)
+    }
+}
+
+impl Write for GroupFile {
+    fn write(&self, buf: &[u8], _offset: usize) -> IOResult<usize> {
+        let invalid = || IOError::with_message(FSErrorKind::Other, "invalid value");
+        let value = str::from_utf8(buf.trim_ascii()).map_err(|_| invalid())?;
+        match self.kind {
+            GroupFileKind::Weight => {
+                self.group.set_weight(value.parse().map_err(|_| invalid())?);
+            }
+            GroupFileKind::MemoryMax => {
+                let max = match value {
+                    "max" => UNLIMITED,
+                    bytes => bytes
+                        .parse::<usize>()
+                        .map_err(|_| invalid())?
+                        .div_ceil(Size4KiB::SIZE as usize),
+                };
+                self.group.set_max_frames(max);
+            }
+            GroupFileKind::MemoryCurrent => {
+                return Err(IOError::simple(FSErrorKind::PermissionDenied));
+            }
+            GroupFileKind::Procs => {
+                let pid = ProcessID(value.parse().map_err(|_| invalid())?);
+                let processes = tls::task_data().processes().read();
+                let core = processes
+                    .get(&pid)
+                    .ok_or(IOError::simple(FSErrorKind::NotFound))?;
+                move_process(core, self.group.clone());
+            }
+            GroupFileKind::Create => {
+                self.group.create_child(value)?;
+            }
+        }
+        Ok(buf.len())
+    }
+}
+
+impl_file_for_wr!(GroupFile: NodeType::FILE);
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+    use crate::{
+        arch::mem::FrameDeallocator,
+        kernel::{
+            fs::{self, OpenOptions, Path},
+            mem::paging::get_frame_alloc,
+        },
+    };
+
+    // groups outside of the hierarchy of root(), such that the running tasks do not interfere
+    fn detached(parent: Option<&Arc<ResourceGroup>>) -> Arc<ResourceGroup> {
+        Arc::new(ResourceGroup::new(String::new(), parent.cloned()))
+    }
+
+    #[kernel_test]
+    fn weighted_shares() {
+        let parent = detached(None);
+        let heavy = detached(Some(&parent));
+        let light = detached(Some(&parent));
+        heavy.set_weight(3);
+        light.set_weight(1);
+
+        // the scheduler starts a new epoch, if it runs out of tasks to run
+        without_interrupts(|| {
+            refill_shares();
+            let heavy_slices = iter::from_fn(|| heavy.try_take_slice().then_some(())).count();
+            let light_slices = iter::from_fn(|| light.try_take_slice().then_some(())).count();
+            assert_eq!((heavy_slices, light_slices), (3, 1));
+
+            // the parent caps the sum of its children
+            parent.set_weight(2);
+            refill_shares();
+            assert!(heavy.try_take_slice());
+            assert!(light.try_take_slice());
+            assert!(!heavy.try_take_slice());
+        });
+    }
+
+    #[kernel_test]
+    fn memory_caps() {
+        let parent = detached(None);
+        let child = detached(Some(&parent));
+        parent.set_max_frames(2);
+        child.set_max_frames(4);
+
+        assert!(child.try_charge_frames(2));
+        // the parent caps its descendants
+        assert!(!child.try_charge_frames(1));
+        assert_eq!((parent.frames(), child.frames()), (2, 2));
+        assert!(
+            get_frame_alloc()
+                .lock()
+                .allocate_frame_for(&child)
+                .is_none()
+        );
+
+        child.uncharge_frames(1);
+        let frame = get_frame_alloc().lock().allocate_frame_for(&child).unwrap();
+        assert_eq!(parent.frames(), 2);
+        unsafe { get_frame_alloc().lock().deallocate_frame(frame) };
+        child.uncharge_frames(2);
+        assert_eq!(parent.frames(), 0);
+    }
+
+    #[kernel_test]
+    fn groups_in_procfs() {
+        let create = fs::open(
+            Path::new("/proc/groups/create"),
+            OpenOptions::READ | OpenOptions::WRITE,
+        )
+        .unwrap();
+        create.write_all(b"procfs_test", 0).unwrap();
+        assert!(create.read_all_as_str().unwrap().contains("procfs_test"));
+
+        let weight = fs::open(
+            Path::new("/proc/groups/procfs_test/weight"),
+            OpenOptions::READ | OpenOptions::WRITE,
+        )
+        .unwrap();
+        assert_eq!(weight.read_all_as_str().unwrap(), "100\n");
+        weight.write_all(b"250\n", 0).unwrap();
+        assert_eq!(root().child("procfs_test").unwrap().weight(), 250);
+
+        let max = fs::open(
+            Path::new("/proc/groups/procfs_test/memory.max"),
+            OpenOptions::READ | OpenOptions::WRITE,
+        )
+        .unwrap();
+        max.write_all(b"4097", 0).unwrap();
+        assert_eq!(max.read_all_as_str().unwrap(), "8192\n");
+    }
+}
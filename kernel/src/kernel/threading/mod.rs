@@ -1,13 +1,16 @@
-use alloc::{boxed::Box, format, string::String, sync::Arc};
+use alloc::{boxed::Box, format, string::String, sync::Arc, vec};
 use core::{
     hint,
+    ptr,
     sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
 };
 
 use schedule::{GlobalTaskPtr, add_task_ptr__};
+pub use scope::scope;
 use task::{Arg, Args, TaskBuilder, TaskState};
 use thiserror::Error;
-use tinyos_abi::flags::TaskWaitOptions;
+use tinyos_abi::{flags::TaskWaitOptions, types::SysErrCode};
 use trampoline::{TaskExitInfo, closure_trampoline};
 
 use crate::{
@@ -16,6 +19,7 @@ use crate::{
     drivers::wait_manager,
     kernel::{
         abi::syscalls::{funcs::exit, utils::__sys_yield},
+        elf::ElfError,
         threading::{
             task::TaskRepr,
             wait::{
@@ -26,12 +30,23 @@ use crate::{
                 queues::GenericWaitQueue,
             },
         },
+        time::Instant,
     },
     sync::locks::RwLock,
 };
 
+pub mod argblock;
+pub mod checkpoint;
+pub mod children;
 pub mod context;
+pub mod executor;
+pub mod fault;
+pub mod group;
+pub mod namespace;
+pub mod pool;
 pub mod schedule;
+pub mod scope;
+pub mod table;
 pub mod task;
 pub mod tls;
 pub mod trampoline;
@@ -62,11 +77,37 @@ pub enum ThreadingError {
     StackNotFreed,
     #[error("the pagedir of a task could not be built")]
     PageDirNotBuilt,
+    #[error("the task did not finish in time")]
+    Timeout,
+    #[error("the task was detached")]
+    Detached,
+    #[error("the image of the task could not be loaded: {0:?}")]
+    Elf(ElfError),
     #[error("unspecified threading error:\n{0}")]
     Unknown(String),
 }
 
+impl From<ThreadingError> for SysErrCode {
+    fn from(err: ThreadingError) -> Self {
+        match err {
+            // frames for the stack or page tables ran out
+            ThreadingError::StackNotBuilt | ThreadingError::PageDirNotBuilt => Self::OOM,
+            ThreadingError::StackNotFreed => Self::AddrNotValid,
+            ThreadingError::Timeout => Self::TimerExp,
+            ThreadingError::Detached => Self::NoChild,
+            ThreadingError::Elf(err) => err.into(),
+            ThreadingError::Unknown(_) => Self::Cancelled,
+        }
+    }
+}
+
 pub fn yield_now() {
+    schedule::stats::count_yield();
+    reschedule();
+}
+
+/// gives up the cpu like yield_now, without counting a yield. For callers, which wait for something
+pub(crate) fn reschedule() {
     //TODO
     use crate::arch::interrupt;
     if interrupt::are_enabled() {
@@ -79,6 +120,36 @@ pub fn yield_now() {
     }
 }
 
+// called with the id of a parked thread. Threads, which no longer exist, are woken, such that their node is dropped
+static UNPARKED: fn(u64) -> bool = |tid| {
+    tls::task_data()
+        .thread(&tid.into())
+        .is_none_or(|task| task.metadata.park_token.load(Ordering::Acquire))
+};
+
+/// blocks the current thread until its token is made available by Task::unpark, and consumes the token.
+/// Returns immediately, if the token is already available. May also return spuriously, thus callers recheck
+/// their condition in a loop
+pub fn park() {
+    // checked before the fast path, such that parking inside a non-blocking section is caught even if it would not block
+    schedule::assert_may_block();
+    let Some(current) = tls::task_data().current_thread() else {
+        yield_now();
+        return;
+    };
+    if current.metadata.park_token.swap(false, Ordering::AcqRel) {
+        return;
+    }
+    wait_manager::wait_self(&[QueuTypeCondition::with_cond(
+        QueueType::Park,
+        WaitCondition::Generic(
+            current.tid().get_inner(),
+            ptr::from_ref::<dyn Fn(u64) -> bool>(&UNPARKED),
+        ),
+    )]);
+    current.metadata.park_token.store(false, Ordering::Release);
+}
+
 #[derive(Debug, Clone)]
 pub struct JoinHandle<R> {
     inner: Arc<RawJoinHandle<R>>,
@@ -87,6 +158,20 @@ pub struct JoinHandle<R> {
 
 impl<R> JoinHandle<R> {
     pub fn wait(&self) -> Result<R, ThreadingError> {
+        self.wait_until(None)
+    }
+
+    /// waits at most timeout for the task to finish. Returns ThreadingError::Timeout if it did not finish in time,
+    /// in which case the handle may be waited on again
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<R, ThreadingError> {
+        self.wait_until(Some(Instant::now() + timeout))
+    }
+
+    fn wait_until(&self, deadline: Option<Instant>) -> Result<R, ThreadingError> {
+        schedule::assert_may_block();
+        if self.inner.is_detached() {
+            return Err(ThreadingError::Detached);
+        }
         if let Some(t) = &self.task
             && !(self.inner.finished() || !self.is_task_alive().is_some_and(|v| v))
         {
@@ -96,22 +181,36 @@ impl<R> JoinHandle<R> {
             );
         }
 
-        let wait_conds = &[QueuTypeCondition::with_cond(
+        let mut wait_conds = vec![QueuTypeCondition::with_cond(
             QueueType::Thread(self.task.as_ref().map(|t| t.tid()).unwrap_or_default()),
             WaitCondition::Thread(
                 self.task.as_ref().map(|t| t.tid()).unwrap_or_default(),
                 TaskWaitOptions::W_EXIT,
             ),
         )];
+        if let Some(deadline) = deadline {
+            wait_conds.push(QueuTypeCondition::with_cond(
+                QueueType::Timer,
+                WaitCondition::Time(deadline),
+            ));
+        }
 
+        let mut timed_out = false;
         while !(self.inner.finished() || !self.is_task_alive().is_some_and(|v| v)) {
-            wait_manager::add_wait(&tls::task_data().current_tid(), wait_conds);
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                timed_out = true;
+                break;
+            }
+            wait_manager::add_wait(&tls::task_data().current_tid(), &wait_conds);
             yield_now();
         }
 
         if let Some(t) = &self.task {
             wait_manager::remove_queue(&QueueType::Thread(t.tid()));
         }
+        if timed_out {
+            return Err(ThreadingError::Timeout);
+        }
 
         let r = self.inner.get_return().map_err(|e| {
             if let TaskState::Zombie = self.task.as_ref().unwrap().state() {
@@ -126,6 +225,18 @@ impl<R> JoinHandle<R> {
         Ok(r)
     }
 
+    /// detaches the task. Its return value is dropped as soon as it exits and this handle no longer keeps the task alive.
+    /// Waiting on any clone of this handle will fail with ThreadingError::Detached afterwards
+    pub fn detach(mut self) {
+        self.inner.detach();
+        if let Some(task) = self.task.take() {
+            task.metadata.detached.store(true, Ordering::Release);
+            let tid = task.tid();
+            drop(task);
+            _ = tls::task_data().reap_detached(&tid);
+        }
+    }
+
     pub fn wait_while<F>(&self, f: F) -> Result<R, ThreadingError>
     where
         F: Fn(&JoinHandle<R>),
@@ -163,6 +274,7 @@ impl<R> Default for JoinHandle<R> {
 #[derive(Debug)]
 struct RawJoinHandle<R> {
     finished: AtomicBool,
+    detached: AtomicBool,
     val: RwLock<Option<R>>,
 }
 
@@ -171,6 +283,27 @@ impl<R> RawJoinHandle<R> {
         self.finished.load(Ordering::Acquire)
     }
 
+    fn is_detached(&self) -> bool {
+        self.detached.load(Ordering::Acquire)
+    }
+
+    /// stores the return value of the task. If the task was detached, the value is dropped right away
+    fn complete(&self, val: R) {
+        self.val.write().replace(val);
+        self.finished.store(true, Ordering::SeqCst);
+        if self.detached.load(Ordering::SeqCst) {
+            drop(self.val.write().take());
+        }
+    }
+
+    fn detach(&self) {
+        self.detached.store(true, Ordering::SeqCst);
+        // the task may already have finished, in which case nobody else drops the value
+        if self.finished.load(Ordering::SeqCst) {
+            drop(self.val.write().take());
+        }
+    }
+
     fn get_return(&self) -> Result<R, ThreadingError> {
         self.finished()
             .then_some(self.val.write().take())
@@ -183,6 +316,7 @@ impl<R> Default for RawJoinHandle<R> {
     fn default() -> Self {
         Self {
             finished: AtomicBool::new(false),
+            detached: AtomicBool::new(false),
             val: RwLock::new(None),
         }
     }
@@ -217,8 +351,7 @@ where
     let raw = handle.inner.clone();
     let wrapper = move || {
         let ret = func();
-        raw.val.write().replace(ret);
-        raw.finished.store(true, Ordering::Release);
+        raw.complete(ret);
     };
 
     let mut args = args!();
@@ -251,8 +384,7 @@ where
 
     let builder = TaskBuilder::from_fn(func)?.with_exit_info(
         TaskExitInfo::new_with_default_trampoline(move |v: usize| {
-            raw.val.write().replace(v);
-            raw.finished.store(true, Ordering::Release);
+            raw.complete(v);
             exit(0)
         }),
     );
@@ -270,7 +402,7 @@ mod tests {
     use os_macros::{kernel_test, with_default_args};
 
     use super::*;
-    use crate::args;
+    use crate::{args, common::fixtures::HelperTask};
 
     #[kernel_test]
     fn join_handle() {
@@ -329,4 +461,77 @@ mod tests {
         );
         assert_eq!(atomic.load(Ordering::Relaxed), true);
     }
+
+    #[kernel_test]
+    fn join_timeout_and_detach() {
+        let go = Arc::new(AtomicBool::new(false));
+        let go_ptr = go.clone();
+        let handle = spawn(move || {
+            while !go_ptr.load(Ordering::Acquire) {
+                yield_now();
+            }
+            42
+        })
+        .unwrap();
+        assert_eq!(
+            handle.wait_timeout(Duration::ZERO),
+            Err(ThreadingError::Timeout)
+        );
+        go.store(true, Ordering::Release);
+        assert_eq!(handle.wait(), Ok(42));
+
+        let value = Arc::new(42);
+        let value_ptr = value.clone();
+        let handle = spawn(move || value_ptr).unwrap();
+        let other = handle.clone();
+        handle.detach();
+        assert_eq!(other.wait(), Err(ThreadingError::Detached));
+        // the returned value is dropped as soon as the task exits
+        while Arc::strong_count(&value) > 1 {
+            yield_now();
+        }
+
+        // a task, which already exited, is reaped when it is detached
+        let handle = spawn(|| 0).unwrap();
+        let task = handle.task.clone().unwrap();
+        while task.state() != TaskState::Zombie {
+            yield_now();
+        }
+        drop(task);
+        let tid = handle.task.as_ref().unwrap().tid();
+        handle.detach();
+        assert!(tls::task_data().thread(&tid).is_none());
+    }
+
+    #[kernel_test]
+    fn park_unpark() {
+        let current = tls::task_data().current_thread().unwrap();
+        // the token of an unpark before the park is kept
+        current.unpark();
+        current.unpark();
+        park();
+        assert!(!current.metadata.park_token.load(Ordering::Acquire));
+
+        let done = Arc::new(AtomicBool::new(false));
+        let done_ptr = done.clone();
+        let parker = current.tid();
+        let handle = spawn(move || {
+            done_ptr.store(true, Ordering::Release);
+            tls::task_data().thread(&parker).unwrap().unpark();
+        })
+        .unwrap();
+        while !done.load(Ordering::Acquire) {
+            park();
+        }
+        assert_eq!(handle.wait(), Ok(()));
+    }
+
+    #[kernel_test]
+    fn helper_task_fixture(#[fixture] helper: &HelperTask) {
+        let start = helper.iterations();
+        for _ in 0..10 {
+            yield_now();
+        }
+        assert!(helper.iterations() > start);
+    }
 }
@@ -0,0 +1,117 @@
+use alloc::{collections::btree_map::BTreeMap, sync::Arc};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{kernel::threading::task::ProcessID, sync::locks::RwLock};
+
+// the initial namespaces are represented by None, such that tasks outside of any namespace do not pay for translation
+
+/// a view of the process table. Processes created inside a namespace get a pid local to it and to each of its ancestors,
+/// in addition to their global one. Processes outside of the namespace are not visible from within.
+/// Entries are never removed, as global pids are never reused
+#[derive(Debug)]
+pub struct PidNamespace {
+    parent: Option<Arc<PidNamespace>>,
+    next: AtomicU64,
+    to_global: RwLock<BTreeMap<ProcessID, ProcessID>>,
+    to_local: RwLock<BTreeMap<ProcessID, ProcessID>>,
+}
+
+impl PidNamespace {
+    pub fn new(parent: Option<Arc<PidNamespace>>) -> Self {
+        Self {
+            parent,
+            // the first process becomes init of the namespace
+            next: AtomicU64::new(1),
+            to_global: RwLock::default(),
+            to_local: RwLock::default(),
+        }
+    }
+
+    /// assigns a local pid to global in self and all ancestors
+    pub fn register(&self, global: ProcessID) {
+        if self.to_local.read().contains_key(&global) {
+            return;
+        }
+        let local = ProcessID(self.next.fetch_add(1, Ordering::Relaxed));
+        self.to_global.write().insert(local, global);
+        self.to_local.write().insert(global, local);
+        if let Some(parent) = &self.parent {
+            parent.register(global);
+        }
+    }
+
+    pub fn local(&self, global: &ProcessID) -> Option<ProcessID> {
+        self.to_local.read().get(global).copied()
+    }
+
+    pub fn global(&self, local: &ProcessID) -> Option<ProcessID> {
+        self.to_global.read().get(local).copied()
+    }
+}
+
+/// pid of the process global as seen from inside ns, None if it is not visible there
+pub fn local_pid(ns: Option<&PidNamespace>, global: ProcessID) -> Option<ProcessID> {
+    match ns {
+        Some(ns) => ns.local(&global),
+        None => Some(global),
+    }
+}
+
+/// global pid of the process with pid local inside ns
+pub fn global_pid(ns: Option<&PidNamespace>, local: ProcessID) -> Option<ProcessID> {
+    match ns {
+        Some(ns) => ns.global(&local),
+        None => Some(local),
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+    use tinyos_abi::flags::CloneFlags;
+
+    use super::*;
+    use crate::kernel::{
+        fs::{self, FS, MountOptions, OpenOptions, Path, PathBuf, ramfs::RamFS},
+        threading::{spawn_with_init, task::TaskRepr, tls},
+    };
+
+    #[kernel_test]
+    fn nested_pid_translation() {
+        let outer = Arc::new(PidNamespace::new(None));
+        let inner = PidNamespace::new(Some(outer.clone()));
+
+        outer.register(ProcessID(40));
+        inner.register(ProcessID(41));
+        inner.register(ProcessID(41));
+
+        assert_eq!(local_pid(Some(&inner), ProcessID(41)), Some(ProcessID(1)));
+        assert_eq!(local_pid(Some(&outer), ProcessID(41)), Some(ProcessID(2)));
+        assert_eq!(local_pid(Some(&inner), ProcessID(40)), None);
+        assert_eq!(global_pid(Some(&outer), ProcessID(1)), Some(ProcessID(40)));
+        assert_eq!(global_pid(Some(&inner), ProcessID(2)), None);
+        assert_eq!(global_pid(None, ProcessID(2)), Some(ProcessID(2)));
+    }
+
+    #[kernel_test]
+    fn isolated_child() {
+        let handle = spawn_with_init(
+            || {
+                let current = tls::task_data().current_thread().unwrap();
+                let pid = local_pid(current.core.pid_ns.as_deref(), current.pid());
+                let mounted = fs::mount(
+                    PathBuf::from("/isolated"),
+                    Arc::new(RamFS::new()) as Arc<dyn FS>,
+                    MountOptions::empty(),
+                )
+                .is_ok();
+                (pid, mounted)
+            },
+            |builder| Ok(builder.with_namespaces(CloneFlags::NEWNS | CloneFlags::NEWPID)),
+        )
+        .unwrap();
+        assert_eq!(handle.wait(), Ok((Some(ProcessID(1)), true)));
+        // the mount stayed inside of the namespace
+        assert!(fs::open(Path::new("/isolated/"), OpenOptions::READ).is_err());
+    }
+}
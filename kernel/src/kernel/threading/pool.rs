@@ -0,0 +1,320 @@
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc, vec::Vec};
+use core::{
+    ptr,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use thiserror::Error;
+
+use super::{
+    JoinHandle,
+    ThreadingError,
+    scope::ScopeCore,
+    spawn,
+    wait::{
+        QueuTypeCondition,
+        QueueHandle,
+        QueueType,
+        WaitEvent,
+        condition::WaitCondition,
+        post_event,
+        queues::GenericWaitQueue,
+    },
+    yield_now,
+};
+use crate::{
+    drivers::wait_manager,
+    kernel::time::Instant,
+    sync::{get_next_lock_var, locks::Mutex},
+};
+
+/// upper bound on the delay of a job, whose wakeup event was lost
+const IDLE_TIMEOUT: Duration = Duration::from_secs(1);
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum PoolError {
+    #[error("the queue of the pool is full")]
+    Full,
+    #[error("the pool is shutting down")]
+    ShutDown,
+}
+
+struct Shared {
+    queue: Mutex<VecDeque<Job>>,
+    capacity: usize,
+    shutdown: AtomicBool,
+    /// idle workers block on QueueType::Lock(wait_id)
+    wait_id: u64,
+}
+
+impl Shared {
+    fn pop(&self) -> Option<Job> {
+        self.queue.lock().pop_front()
+    }
+
+    fn notify(&self) {
+        _ = post_event(WaitEvent::new(QueueType::Lock(self.wait_id)));
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        wait_manager::remove_queue(&QueueType::Lock(self.wait_id));
+    }
+}
+
+// called with the address of the shared state, whose wait queue is removed before it is freed
+static WORK_QUEUED: fn(u64) -> bool = |shared| {
+    let shared = unsafe { &*(shared as *const Shared) };
+    shared.shutdown.load(Ordering::Acquire) || !shared.queue.lock().is_empty()
+};
+
+/// a fixed number of kernel threads running submitted jobs in order.
+/// The queue is bounded, such that producers are slowed down to the pace of the workers.
+/// Dropping the pool runs the queued jobs and joins the workers
+pub struct ThreadPool {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ThreadPool {
+    /// spawns workers threads, which take jobs from a queue of at most capacity jobs
+    pub fn new(workers: usize, capacity: usize) -> Result<Self, ThreadingError> {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity: capacity.max(1),
+            shutdown: AtomicBool::new(false),
+            wait_id: get_next_lock_var(),
+        });
+        wait_manager::add_queue(
+            QueueHandle::from_owned(Box::new(GenericWaitQueue::new())),
+            QueueType::Lock(shared.wait_id),
+        );
+        let mut pool = Self {
+            shared,
+            workers: Vec::with_capacity(workers),
+        };
+        for _ in 0..workers {
+            let shared = pool.shared.clone();
+            pool.workers.push(spawn(move || work(&shared))?);
+        }
+        Ok(pool)
+    }
+
+    pub fn workers(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// the number of jobs, which were not started yet
+    pub fn pending(&self) -> usize {
+        self.shared.queue.lock().len()
+    }
+
+    /// queues job, if there is space left
+    pub fn try_execute<F>(&self, job: F) -> Result<(), PoolError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.try_submit(Box::new(job)).map_err(|(_, e)| e)
+    }
+
+    /// queues job. While the queue is full, the caller runs queued jobs itself
+    pub fn execute<F>(&self, job: F) -> Result<(), PoolError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.submit(Box::new(job))
+    }
+
+    /// runs f with a scope, whose jobs may borrow from the caller. All jobs have finished, once this returns.
+    /// While waiting, the caller runs queued jobs itself, thus scopes may be nested inside of jobs
+    pub fn scope<'env, F, R>(&self, f: F) -> R
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> R,
+    {
+        let scope = Scope {
+            pool: self,
+            core: ScopeCore::new(None),
+        };
+        let r = f(&scope);
+        scope.core.wait(|| {
+            if !self.help() {
+                yield_now();
+            }
+        });
+        r
+    }
+
+    /// lets the workers finish the queued jobs and waits for them to exit. Jobs submitted afterwards are rejected
+    pub fn shutdown(mut self) -> Result<(), ThreadingError> {
+        self.join()
+    }
+
+    fn join(&mut self) -> Result<(), ThreadingError> {
+        self.shared.shutdown.store(true, Ordering::Release);
+        self.shared.notify();
+        if self.workers.is_empty() {
+            while self.help() {}
+        }
+        self.workers.drain(..).try_for_each(|worker| worker.wait())
+    }
+
+    fn try_submit(&self, job: Job) -> Result<(), (Job, PoolError)> {
+        if self.shared.shutdown.load(Ordering::Acquire) {
+            return Err((job, PoolError::ShutDown));
+        }
+        let mut queue = self.shared.queue.lock();
+        if queue.len() >= self.shared.capacity {
+            return Err((job, PoolError::Full));
+        }
+        queue.push_back(job);
+        drop(queue);
+        self.shared.notify();
+        Ok(())
+    }
+
+    fn submit(&self, mut job: Job) -> Result<(), PoolError> {
+        loop {
+            match self.try_submit(job) {
+                Err((rejected, PoolError::Full)) => {
+                    job = rejected;
+                    if !self.help() {
+                        yield_now();
+                    }
+                }
+                res => return res.map_err(|(_, e)| e),
+            }
+        }
+    }
+
+    /// runs a single queued job on the calling thread. Returns whether there was one
+    fn help(&self) -> bool {
+        self.shared.pop().map(|job| job()).is_some()
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        _ = self.join();
+    }
+}
+
+fn work(shared: &Shared) {
+    let waiter = [
+        QueuTypeCondition::with_cond(
+            QueueType::Lock(shared.wait_id),
+            WaitCondition::Generic(
+                ptr::from_ref(shared) as u64,
+                ptr::from_ref::<dyn Fn(u64) -> bool>(&WORK_QUEUED),
+            ),
+        ),
+        QueuTypeCondition::new(QueueType::Timer),
+    ];
+    loop {
+        if let Some(job) = shared.pop() {
+            job();
+            continue;
+        }
+        // the queue is drained before the workers exit
+        if shared.shutdown.load(Ordering::Acquire) {
+            return;
+        }
+        let mut waiter = waiter.clone();
+        waiter[1].cond = WaitCondition::Time(Instant::now() + IDLE_TIMEOUT);
+        wait_manager::wait_self(&waiter);
+    }
+}
+
+/// submits jobs borrowing data of lifetime 'env, see ThreadPool::scope
+pub struct Scope<'scope, 'env: 'scope> {
+    pool: &'scope ThreadPool,
+    core: ScopeCore<'scope, 'env>,
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    /// queues job, waiting for space in the queue
+    pub fn execute<F>(&'scope self, job: F) -> Result<(), PoolError>
+    where
+        F: FnOnce() + Send + 'scope,
+    {
+        self.pool
+            .submit(self.core.wrap(job))
+            .inspect_err(|_| self.core.cancel())
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use alloc::vec;
+    use core::sync::atomic::AtomicUsize;
+
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    #[kernel_test]
+    fn pool_jobs() {
+        let pool = ThreadPool::new(2, 4).unwrap();
+        let done = Arc::new(AtomicUsize::new(0));
+        for _ in 0..16 {
+            let done = done.clone();
+            pool.execute(move || {
+                done.fetch_add(1, Ordering::Relaxed);
+            })
+            .unwrap();
+        }
+        pool.shutdown().unwrap();
+        assert_eq!(done.load(Ordering::Relaxed), 16);
+
+        // a blocked worker lets the queue fill up
+        let pool = ThreadPool::new(1, 1).unwrap();
+        let go = Arc::new(AtomicBool::new(false));
+        let started = Arc::new(AtomicBool::new(false));
+        let (go_ptr, started_ptr) = (go.clone(), started.clone());
+        pool.execute(move || {
+            started_ptr.store(true, Ordering::Release);
+            while !go_ptr.load(Ordering::Acquire) {
+                yield_now();
+            }
+        })
+        .unwrap();
+        while !started.load(Ordering::Acquire) {
+            yield_now();
+        }
+        pool.try_execute(|| {}).unwrap();
+        assert_eq!(pool.try_execute(|| {}), Err(PoolError::Full));
+        assert_eq!(pool.pending(), 1);
+        go.store(true, Ordering::Release);
+        pool.shutdown().unwrap();
+    }
+
+    #[kernel_test]
+    fn scoped_jobs() {
+        let pool = ThreadPool::new(2, 2).unwrap();
+        let data = vec![1u64; 1024];
+        let sums = [const { AtomicUsize::new(0) }; 8];
+        pool.scope(|scope| {
+            for (chunk, sum) in data.chunks(128).zip(&sums) {
+                scope
+                    .execute(move || {
+                        sum.store(chunk.iter().sum::<u64>() as usize, Ordering::Relaxed);
+                    })
+                    .unwrap();
+            }
+        });
+        assert!(sums.iter().all(|sum| sum.load(Ordering::Relaxed) == 128));
+
+        // without workers, the scope runs its jobs itself
+        let pool = ThreadPool::new(0, 1).unwrap();
+        let mut results = vec![0; 4];
+        pool.scope(|scope| {
+            for (i, result) in results.iter_mut().enumerate() {
+                scope.execute(move || *result = i * 2).unwrap();
+            }
+        });
+        assert_eq!(results, [0, 2, 4, 6]);
+    }
+}
@@ -0,0 +1,269 @@
+use alloc::string::String;
+use core::{
+    fmt::Write as _,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use os_macros::FileRepr;
+
+use crate::kernel::io::{IOResult, Read, read_rendered};
+
+pub const SCHED_LAT_FILE: &str = "/schedlat";
+/// bucket i holds values of at least 2^(i - 1) and below 2^i, the last one everything above
+const BUCKETS: usize = 24;
+
+/// the time from waking a task until it runs, in microseconds
+pub static WAKEUP: Histogram = Histogram::new();
+
+/// a histogram with power of two buckets. Recording neither blocks nor allocates, thus it may be used while switching
+#[derive(Debug)]
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKETS],
+    max: AtomicU64,
+}
+
+impl Histogram {
+    pub const fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; BUCKETS],
+            max: AtomicU64::new(0),
+        }
+    }
+
+    fn bucket(value: u64) -> usize {
+        ((u64::BITS - value.leading_zeros()) as usize).min(BUCKETS - 1)
+    }
+
+    pub fn record(&self, value: u64) {
+        self.buckets[Self::bucket(value)].fetch_add(1, Ordering::Relaxed);
+        self.max.fetch_max(value, Ordering::Relaxed);
+    }
+
+    /// the number of recorded values
+    pub fn count(&self) -> u64 {
+        self.buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max.load(Ordering::Relaxed)
+    }
+
+    /// an upper bound of the p-th percentile, which is exact up to the bucket size. 0 without any values
+    pub fn percentile(&self, p: u64) -> u64 {
+        let wanted = (self.count() * p.min(100)).div_ceil(100).max(1);
+        let mut seen = 0;
+        for (idx, bucket) in self.buckets.iter().enumerate().take(BUCKETS - 1) {
+            seen += bucket.load(Ordering::Relaxed);
+            if seen >= wanted {
+                return (1 << idx).min(self.max());
+            }
+        }
+        self.max()
+    }
+
+    /// a summary, followed by the number of values below each bound, up to the last non empty bucket
+    pub fn render(&self, out: &mut String) {
+        _ = writeln!(out, "samples\t{}", self.count());
+        for p in [50, 90, 99] {
+            _ = writeln!(out, "p{}_us\t{}", p, self.percentile(p));
+        }
+        _ = writeln!(out, "max_us\t{}", self.max());
+        out.push('\n');
+
+        out.push_str(SchedLat::HEADER);
+        let used = self
+            .buckets
+            .iter()
+            .rposition(|bucket| bucket.load(Ordering::Relaxed) != 0)
+            .map_or(0, |last| last + 1);
+        for (idx, bucket) in self.buckets.iter().enumerate().take(used) {
+            let count = bucket.load(Ordering::Relaxed);
+            if idx == BUCKETS - 1 {
+                _ = writeln!(out, "inf\t{}", count);
+            } else {
+                _ = writeln!(out, "{}\t{}", 1_u64 << idx, count);
+            }
+        }
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// /proc/schedlat: the wakeup to run latency of all tasks since boot, as percentiles and a histogram.
+/// Each histogram line holds the number of wakeups below a bound in microseconds, which were not counted in an earlier line
+#[derive(Debug, Default, Clone, Copy, FileRepr)]
+#[file(read)]
+pub struct SchedLat;
+
+impl SchedLat {
+    pub const HEADER: &'static str = "lt_us\tcount\n";
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        WAKEUP.render(&mut out);
+        out
+    }
+}
+
+impl Read for SchedLat {
+    fn read(&self, buf: &mut [u8], offset: usize) -> IOResult<usize> {
+        read_rendered(self.render(), buf, offset)
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use alloc::{sync::Arc, vec::Vec};
+
+    use os_macros::kernel_test;
+
+    use super::*;
+    use crate::{
+        arch,
+        kernel::threading::{
+            self,
+            park,
+            schedule::{GlobalTaskPtr, stats},
+            tls,
+            yield_now,
+        },
+        println,
+        sync::locks::Mutex,
+    };
+
+    /// the numbers of tasks competing for the cpu in the benchmarks
+    const TASK_COUNTS: [usize; 3] = [1, 4, 16];
+    const ROUNDS: u64 = 100;
+    // generous bounds, which only a real regression of the scheduler or the wait manager exceeds, even under emulation
+    const MIN_SWITCHES_PER_SEC: u64 = 1_000;
+    const MAX_WAKEUP_P99_US: u64 = 250_000;
+
+    #[kernel_test]
+    fn histogram() {
+        let histogram = Histogram::new();
+        assert_eq!(histogram.percentile(99), 0);
+        for value in [0, 1, 3, 3, 100, 5_000] {
+            histogram.record(value);
+        }
+        histogram.record(u64::MAX);
+        assert_eq!(histogram.count(), 7);
+        assert_eq!(histogram.max(), u64::MAX);
+        // 3 is in the bucket of values below 4
+        assert_eq!(histogram.percentile(50), 4);
+        assert_eq!(histogram.percentile(80), 8192);
+        assert_eq!(histogram.percentile(100), u64::MAX);
+
+        let mut rendered = String::new();
+        histogram.render(&mut rendered);
+        assert!(rendered.starts_with("samples\t7\n"));
+        let lines = rendered
+            .lines()
+            .skip_while(|line| *line != SchedLat::HEADER.trim_end())
+            .skip(1)
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), BUCKETS);
+        assert_eq!(lines[0], "1\t1");
+        assert_eq!(lines[2], "4\t2");
+        assert_eq!(lines[BUCKETS - 1], "inf\t1");
+        assert!(SchedLat.render().contains(SchedLat::HEADER));
+    }
+
+    #[kernel_test]
+    fn bench_switch_throughput() {
+        for tasks in TASK_COUNTS {
+            let start = arch::timestamp();
+            let switches = stats::total_switches();
+            let handles = (0..tasks)
+                .map(|_| {
+                    threading::spawn(|| {
+                        for _ in 0..ROUNDS {
+                            yield_now();
+                        }
+                    })
+                    .unwrap()
+                })
+                .collect::<Vec<_>>();
+            for handle in handles {
+                assert!(handle.wait().is_ok());
+            }
+            let elapsed = stats::cycles_to_micros(arch::timestamp() - start).max(1);
+            let rate = (stats::total_switches() - switches) * 1_000_000 / elapsed;
+            println!("bench: {} tasks, {} switches/s", tasks, rate);
+            assert!(rate >= MIN_SWITCHES_PER_SEC);
+        }
+    }
+
+    #[derive(Default)]
+    struct Wakeups {
+        workers: Mutex<Vec<GlobalTaskPtr>>,
+        round: AtomicU64,
+        woken_at: AtomicU64,
+        done: AtomicU64,
+        latency: Histogram,
+    }
+
+    #[kernel_test]
+    fn bench_wakeup_latency() {
+        for tasks in TASK_COUNTS {
+            let shared = Arc::new(Wakeups::default());
+            let handles = (0..tasks)
+                .map(|_| {
+                    let shared = shared.clone();
+                    threading::spawn(move || {
+                        shared
+                            .workers
+                            .lock()
+                            .push(tls::task_data().current_thread().unwrap());
+                        for round in 1..=ROUNDS {
+                            while shared.round.load(Ordering::Acquire) < round {
+                                park();
+                            }
+                            let woken_at = shared.woken_at.load(Ordering::Acquire);
+                            shared.latency.record(stats::cycles_to_micros(
+                                arch::timestamp().saturating_sub(woken_at),
+                            ));
+                            shared.done.fetch_add(1, Ordering::AcqRel);
+                        }
+                    })
+                    .unwrap()
+                })
+                .collect::<Vec<_>>();
+
+            while shared.workers.lock().len() < tasks {
+                yield_now();
+            }
+            for round in 1..=ROUNDS {
+                // every worker is done with the last round, thus waiting for this one
+                while shared.done.load(Ordering::Acquire) < (round - 1) * tasks as u64 {
+                    yield_now();
+                }
+                shared.woken_at.store(arch::timestamp(), Ordering::Release);
+                shared.round.store(round, Ordering::Release);
+                for worker in shared.workers.lock().iter() {
+                    worker.unpark();
+                }
+            }
+            for handle in handles {
+                assert!(handle.wait().is_ok());
+            }
+
+            let latency = &shared.latency;
+            assert_eq!(latency.count(), ROUNDS * tasks as u64);
+            println!(
+                "bench: {} tasks, wakeup latency p50 {}us p99 {}us max {}us",
+                tasks,
+                latency.percentile(50),
+                latency.percentile(99),
+                latency.max()
+            );
+            assert!(latency.percentile(99) <= MAX_WAKEUP_P99_US);
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use alloc::{string::String, sync::Arc};
+use core::sync::atomic::Ordering;
 
 use conquer_once::spin::OnceCell;
 
@@ -9,24 +10,33 @@ use super::{
 };
 use crate::{
     arch::{
-        context::{TaskState, switch_and_apply},
+        context::{TaskState, fpu, switch_and_apply},
         interrupt::gdt::set_tss_kstack,
         mem::VirtAddr,
+        percpu,
     },
-    kernel::threading::{
-        task::{Task, Uninit},
-        tls,
+    kernel::{
+        flight,
+        threading::{
+            task::{Task, Uninit},
+            tls,
+        },
     },
     serial_println,
 };
 
+pub mod latency;
+pub mod preempt;
 mod round_robin;
+pub mod stats;
 
 pub trait Scheduler {
     fn new() -> Self;
     fn reschedule(&self);
     fn switch(&self) -> Option<ThreadID>;
     fn add_task(&self, id: ThreadID);
+    /// number of tasks currently queued to run
+    fn runqueue_len(&self) -> usize;
 }
 
 pub enum ScheduleOrder {}
@@ -74,18 +84,66 @@ pub fn current_task() -> Result<GlobalTaskPtr, ThreadingError> {
         ))
 }
 
-#[allow(unsafe_op_in_unsafe_fn, dropping_references, dropping_copy_types)]
+/// a section in which the current cpu must not block, such as context_switch_local.
+/// Blocking operations check this through assert_may_block in debug builds
+pub struct NoBlockSection(());
+
+impl NoBlockSection {
+    pub fn enter() -> Self {
+        if let Some(cpu) = percpu::this_cpu() {
+            cpu.enter_no_block();
+        }
+        Self(())
+    }
+}
+
+impl Drop for NoBlockSection {
+    fn drop(&mut self) {
+        if let Some(cpu) = percpu::this_cpu() {
+            cpu.leave_no_block();
+        }
+    }
+}
+
+/// panics in debug builds if the current cpu is inside a NoBlockSection or preemption is disabled
+#[inline]
+pub fn assert_may_block() {
+    debug_assert!(
+        percpu::this_cpu().is_none_or(|cpu| cpu.may_block()),
+        "tried to block inside a non-blocking section"
+    );
+    debug_assert!(
+        preempt::preemptible(),
+        "tried to block with preemption disabled"
+    );
+}
+
+/// switches to the next task after an interrupt preempted the current one
+#[allow(unsafe_op_in_unsafe_fn)]
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn context_switch_local(rsp: u64) {
+    switch_local(rsp, false)
+}
+
+/// switches to the next task, after the current one gave up the cpu through yield_now
+#[allow(unsafe_op_in_unsafe_fn)]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn context_switch_voluntary(rsp: u64) {
+    switch_local(rsp, true)
+}
+
+#[allow(unsafe_op_in_unsafe_fn, dropping_references, dropping_copy_types)]
+unsafe fn switch_local(rsp: u64, voluntary: bool) {
     // heart of context switching logic. Here we get the next task to run, initialize task_data and scheduler and switch.
     // WE CANNOT BLOCK HERE
+    let section = NoBlockSection::enter();
 
     let task_data = tls::task_data();
     let current = if let Some(current) = task_data.try_current_thread() {
         current.set_krsp(&VirtAddr::new(rsp));
         current
     } else if task_data.current_tid() == ThreadID::default() {
-        let Some(current) = task_data.thread(&1.into()) else {
+        let Some(current) = task_data.try_thread(&1.into()) else {
             serial_println!("{:#?}", task_data);
             panic!("could not load initial task");
         };
@@ -103,17 +161,37 @@ pub unsafe extern "C" fn context_switch_local(rsp: u64) {
     if current.state() == super::task::TaskState::Running {
         current.set_state(super::task::TaskState::Ready);
     }
+    if next_task.tid() != current.tid() {
+        stats::record_switch(&current, &next_task, voluntary);
+        flight::record(flight::Event::Switch {
+            from: current.tid().get_inner(),
+            to: next_task.tid().get_inner(),
+            voluntary,
+        });
+    }
     // this is already done in scheduler::switch currently
-    // task_data.update_current(next);
+    // task_data.set_current(&next_task);
 
     let ptr = TaskState::from_task(next_task.as_ref());
 
     set_tss_kstack(*next_task.kstack_top());
+    // preemption is disabled per task, thus a task switching away inside a section must not leak its count
+    if let Some(cpu) = percpu::this_cpu() {
+        let count =
+            cpu.swap_preempt_count(next_task.metadata.preempt_count.load(Ordering::Relaxed));
+        current
+            .metadata
+            .preempt_count
+            .store(count, Ordering::Relaxed);
+    }
+    fpu::switch(&current.metadata.fpu, &next_task.metadata.fpu);
 
     drop(next_task);
     drop(next);
     drop(current);
     drop(task_data);
+    // switch_and_apply does not return, thus the section must be left explicitly
+    drop(section);
 
     switch_and_apply(ptr);
     unreachable!()
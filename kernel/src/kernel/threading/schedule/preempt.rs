@@ -0,0 +1,148 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{
+    arch::{interrupt, percpu},
+    kernel::threading::yield_now,
+};
+
+// Kernel preemption.
+// The timer interrupt preempts kernel tasks just like user tasks, unless preemption is disabled on the current cpu.
+// A preemption arriving while preemption is disabled is deferred and carried out by the outermost preempt_enable.
+// Sections with preemption disabled must not block, which is checked by assert_may_block in debug builds.
+
+/// number of timer preemptions, which were deferred, as preemption was disabled
+static DEFERRED: AtomicU64 = AtomicU64::new(0);
+
+/// disables preemption of the current task until the matching preempt_enable. Calls may be nested
+pub fn preempt_disable() {
+    if let Some(cpu) = percpu::this_cpu() {
+        cpu.preempt_disable();
+    }
+}
+
+/// reenables preemption. If this is the outermost call and a preemption was deferred meanwhile, the task yields
+pub fn preempt_enable() {
+    if let Some(cpu) = percpu::this_cpu()
+        && cpu.preempt_enable()
+        && cpu.take_need_resched()
+        && interrupt::are_enabled()
+    {
+        yield_now();
+    }
+}
+
+/// the preemption nesting depth of the current cpu
+pub fn preempt_count() -> u32 {
+    percpu::this_cpu().map_or(0, |cpu| cpu.preempt_count())
+}
+
+pub fn preemptible() -> bool {
+    preempt_count() == 0
+}
+
+/// called by the timer interrupt instead of switching, if the current task is not preemptible
+pub(crate) fn defer_preemption() {
+    if let Some(cpu) = percpu::this_cpu() {
+        cpu.set_need_resched();
+    }
+    DEFERRED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn deferred_preemptions() -> u64 {
+    DEFERRED.load(Ordering::Relaxed)
+}
+
+/// disables preemption while alive
+#[derive(Debug)]
+pub struct PreemptGuard(());
+
+impl PreemptGuard {
+    pub fn new() -> Self {
+        preempt_disable();
+        Self(())
+    }
+}
+
+impl Default for PreemptGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for PreemptGuard {
+    fn drop(&mut self) {
+        preempt_enable();
+    }
+}
+
+/// runs f with preemption disabled
+pub fn without_preemption<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let _guard = PreemptGuard::new();
+    f()
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use alloc::sync::Arc;
+    use core::sync::atomic::AtomicBool;
+
+    use os_macros::kernel_test;
+
+    use super::*;
+    use crate::{
+        arch::interrupt::handlers::current_tick,
+        kernel::threading::{spawn, tls},
+    };
+
+    #[kernel_test]
+    fn preempt_disable_defers() {
+        let current = tls::task_data().current_thread().unwrap();
+        assert!(preemptible());
+
+        let (ticks, deferred) = without_preemption(|| {
+            assert_eq!(preempt_count(), 1);
+            without_preemption(|| assert_eq!(preempt_count(), 2));
+            assert_eq!(preempt_count(), 1);
+
+            let involuntary = current.metadata.sched_stats.involuntary();
+            let deferred = deferred_preemptions();
+            let start = current_tick();
+            let mut spins = 0_u64;
+            while current_tick() < start + 2 && spins < 1_000_000_000 {
+                core::hint::spin_loop();
+                spins += 1;
+            }
+            assert_eq!(current.metadata.sched_stats.involuntary(), involuntary);
+            (current_tick() - start, deferred_preemptions() - deferred)
+        });
+
+        assert!(preemptible());
+        if ticks >= 2 {
+            assert!(deferred >= 1);
+        }
+    }
+
+    #[kernel_test]
+    fn preempt_count_follows_task() {
+        let ran = Arc::new(AtomicBool::new(false));
+        let handle = spawn({
+            let ran = ran.clone();
+            move || {
+                ran.store(true, Ordering::Release);
+                preempt_count()
+            }
+        })
+        .unwrap();
+        without_preemption(|| {
+            // the other thread runs while this one yields inside the section, but does not inherit its count
+            while !ran.load(Ordering::Acquire) {
+                yield_now();
+            }
+            assert_eq!(preempt_count(), 1);
+        });
+        assert_eq!(handle.wait(), Ok(0));
+    }
+}
@@ -1,27 +1,38 @@
-use alloc::{collections::vec_deque::VecDeque, vec::Vec};
+use alloc::collections::vec_deque::VecDeque;
 use core::fmt::Debug;
 
 use crate::{
-    arch::interrupt,
-    kernel::threading::{
-        schedule::Scheduler,
-        task::{TaskRepr, TaskState, ThreadID},
-        tls,
+    kernel::{
+        mem::alloc::TryPush,
+        threading::{
+            group,
+            schedule::Scheduler,
+            task::{TaskRepr, TaskState, ThreadID},
+            tls,
+        },
     },
     serial_println,
-    sync::{self, NoBlock},
+    sync::locks::SpinLockIrqSave,
 };
 
+/// headroom left in the runqueue on every reschedule, such that add_task does not need to grow it
+const SPARE_SLOTS: usize = 16;
+
 #[derive(Debug)]
 pub struct LazyRoundRobin {
-    queue: sync::locks::GenericMutex<VecDeque<ThreadID>, NoBlock>,
+    // taken by switch from the timer interrupt. Nothing allocates while it is held, as interrupts are disabled
+    queue: SpinLockIrqSave<VecDeque<ThreadID>>,
 }
 
 impl LazyRoundRobin {
     pub fn log_all(&self) {
         serial_println!("LazyRoundRobin: tasks:");
-        for t in self.queue.lock().iter() {
-            serial_println!("{:?}", tls::task_data().thread(t));
+        // the lock is not held while printing, which may block
+        for idx in 0.. {
+            let Some(t) = self.queue.lock().get(idx).copied() else {
+                break;
+            };
+            serial_println!("{:?}", tls::task_data().thread(&t));
         }
     }
 }
@@ -29,57 +40,88 @@ impl LazyRoundRobin {
 impl Scheduler for LazyRoundRobin {
     fn new() -> Self {
         Self {
-            queue: sync::locks::GenericMutex::new(VecDeque::new()),
+            queue: SpinLockIrqSave::new(VecDeque::new()),
         }
     }
 
     fn reschedule(&self) {
         // TODO return if not dirty
-        let manager = tls::task_data();
-        let table = manager.get_table().read();
-
-        let mut extend_with = table
-            .iter()
-            .filter_map(|(_id, task)| {
-                if task.state() == TaskState::Ready || task.state() == TaskState::Running {
-                    Some(task.tid())
-                } else {
-                    None
-                }
-            })
-            .collect::<VecDeque<_>>();
-
-        drop(table);
+        let mut extend_with = VecDeque::new();
+        let mut complete = true;
+        tls::task_data().get_table().for_each(|_id, task| {
+            if (task.state() == TaskState::Ready || task.state() == TaskState::Running)
+                && extend_with.try_push(task.tid()).is_err()
+            {
+                complete = false;
+            }
+        });
+        // keep running the old queue rather than losing tasks
+        if !complete {
+            serial_println!("reschedule: out of memory, keeping the current runqueue");
+            return;
+        }
 
-        let mut queue = self.queue.lock();
-        // make sure to not call alloc in irqsave ctx
-        let cur_len = queue.len();
-        queue.reserve((extend_with.len() as isize - cur_len as isize).max(0) as usize);
+        // a full runqueue is only fixed by the next reschedule, thus a failed reservation is not fatal
+        _ = extend_with.try_reserve(SPARE_SLOTS);
 
-        interrupt::without_interrupts(|| {
-            queue.clear();
-            queue.append(&mut extend_with);
-        })
+        // the queues are swapped instead of copied, such that nothing allocates or frees with the lock held
+        core::mem::swap(&mut *self.queue.lock(), &mut extend_with);
     }
 
     fn switch(&self) -> Option<ThreadID> {
         let mut queue = self.queue.try_lock()?;
-        while let Some(id) = queue.pop_front() {
-            let Some(task) = tls::task_data().try_thread(&id) else {
-                // Task was likely killed and removed from task manager
-                continue;
-            };
-            if task.state() != TaskState::Ready {
-                continue;
+        // a task only runs, while its resource group has slices left in the current epoch.
+        // If every ready task is throttled, a new epoch starts and the queue is walked once more
+        for _ in 0..2 {
+            let mut throttled = false;
+            for _ in 0..queue.len() {
+                let Some(id) = queue.pop_front() else {
+                    break;
+                };
+                let Some(task) = tls::task_data().try_thread(&id) else {
+                    // Task was likely killed and removed from task manager
+                    continue;
+                };
+                if task.state() != TaskState::Ready {
+                    continue;
+                }
+                queue.push_back(id);
+                // the group is only written with interrupts disabled, a contended lock means it is being moved right now
+                if task
+                    .core
+                    .resource_group
+                    .try_read()
+                    .is_none_or(|group| group.try_take_slice())
+                {
+                    tls::task_data().set_current(&task);
+                    return Some(id);
+                }
+                throttled = true;
             }
-            tls::task_data().update_current(id);
-            queue.push_back(id);
-            return Some(id);
+            if !throttled {
+                break;
+            }
+            group::refill_shares();
         }
         None
     }
 
     fn add_task(&self, id: ThreadID) {
-        self.queue.lock().push_back(id);
+        // the queue is not grown here, as that would allocate with interrupts disabled.
+        // The task is still in the task table, thus the next reschedule picks it up
+        let mut queue = self.queue.lock();
+        if queue.len() < queue.capacity() {
+            queue.push_back(id);
+            return;
+        }
+        drop(queue);
+        serial_println!(
+            "could not queue task {}, deferring it to the next reschedule",
+            id
+        );
+    }
+
+    fn runqueue_len(&self) -> usize {
+        self.queue.try_lock().map_or(0, |queue| queue.len())
     }
 }
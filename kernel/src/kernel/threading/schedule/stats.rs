@@ -0,0 +1,340 @@
+use alloc::string::String;
+use core::{
+    fmt::Write as _,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use os_macros::FileRepr;
+
+use super::{GlobalTask, Scheduler, get_scheduler, latency, preempt};
+use crate::{
+    arch,
+    kernel::{
+        io::{IOResult, Read, read_rendered},
+        threading::{
+            task::{TaskRepr, TaskState},
+            tls,
+        },
+        time::{self, Duration},
+    },
+    sync::counter::PerCpuCounter,
+};
+
+pub const SCHED_STAT_FILE: &str = "/schedstat";
+pub const SCHED_TOP_FILE: &str = "/schedtop";
+/// the number of tasks listed in SCHED_TOP_FILE
+pub const TOP_OFFENDERS: usize = 16;
+
+/// total number of context switches between different tasks
+static SWITCHES: PerCpuCounter = PerCpuCounter::new();
+/// total number of syscalls of all tasks
+static SYSCALLS: PerCpuCounter = PerCpuCounter::new();
+/// start of the current one second window and the value of SWITCHES at that point
+static WINDOW_START: AtomicU64 = AtomicU64::new(0);
+static WINDOW_SWITCHES: AtomicU64 = AtomicU64::new(0);
+/// context switches during the last complete window
+static SWITCH_RATE: AtomicU64 = AtomicU64::new(0);
+
+/// per task scheduling statistics. All times are in arch::timestamp cycles
+#[derive(Debug, Default)]
+pub struct TaskSchedStats {
+    voluntary: AtomicU64,
+    involuntary: AtomicU64,
+    runtime: AtomicU64,
+    wait_time: AtomicU64,
+    // timestamp at which the task was last switched in
+    running_since: AtomicU64,
+    // timestamp at which the task was switched out while still runnable, 0 if it is not waiting
+    waiting_since: AtomicU64,
+    // timestamp at which the task was woken from a wait, 0 if it has run since
+    woken_at: AtomicU64,
+    yields: AtomicU64,
+    syscalls: AtomicU64,
+    sleeps: AtomicU64,
+}
+
+impl TaskSchedStats {
+    /// number of switches, in which the task gave up the cpu itself (yield or block)
+    pub fn voluntary(&self) -> u64 {
+        self.voluntary.load(Ordering::Relaxed)
+    }
+
+    /// number of switches, in which the task was preempted by the timer
+    pub fn involuntary(&self) -> u64 {
+        self.involuntary.load(Ordering::Relaxed)
+    }
+
+    /// total time spent running
+    pub fn runtime(&self) -> u64 {
+        self.runtime.load(Ordering::Relaxed)
+    }
+
+    /// total time spent runnable, but waiting for the cpu
+    pub fn wait_time(&self) -> u64 {
+        self.wait_time.load(Ordering::Relaxed)
+    }
+
+    /// number of yields, which did not wait for anything. Tasks polling in a loop yield a lot
+    pub fn yields(&self) -> u64 {
+        self.yields.load(Ordering::Relaxed)
+    }
+
+    pub fn syscalls(&self) -> u64 {
+        self.syscalls.load(Ordering::Relaxed)
+    }
+
+    /// number of waits in the wait manager
+    pub fn sleeps(&self) -> u64 {
+        self.sleeps.load(Ordering::Relaxed)
+    }
+
+    fn switched_in(&self, now: u64) {
+        let since = self.waiting_since.swap(0, Ordering::Relaxed);
+        if since != 0 {
+            self.wait_time
+                .fetch_add(now.saturating_sub(since), Ordering::Relaxed);
+        }
+        let woken = self.woken_at.swap(0, Ordering::Relaxed);
+        if woken != 0 {
+            latency::WAKEUP.record(cycles_to_micros(now.saturating_sub(woken)));
+        }
+        self.running_since.store(now, Ordering::Relaxed);
+    }
+
+    fn switched_out(&self, now: u64, voluntary: bool, runnable: bool) {
+        if voluntary {
+            self.voluntary.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.involuntary.fetch_add(1, Ordering::Relaxed);
+        }
+        let since = self.running_since.swap(0, Ordering::Relaxed);
+        if since != 0 {
+            self.runtime
+                .fetch_add(now.saturating_sub(since), Ordering::Relaxed);
+        }
+        if runnable {
+            self.waiting_since.store(now, Ordering::Relaxed);
+        }
+    }
+}
+
+/// records a switch from current to next. Called from context_switch_local, thus this must neither block nor allocate
+pub(super) fn record_switch(current: &GlobalTask, next: &GlobalTask, voluntary: bool) {
+    let now = arch::timestamp();
+    let runnable = current.state() == TaskState::Ready;
+    current
+        .metadata
+        .sched_stats
+        .switched_out(now, voluntary, runnable);
+    next.metadata.sched_stats.switched_in(now);
+
+    SWITCHES.inc();
+    let window = time::cycles_to_duration(now.saturating_sub(WINDOW_START.load(Ordering::Relaxed)));
+    if window >= Duration::from_secs(1) {
+        let switches = SWITCHES.get();
+        let done = switches - WINDOW_SWITCHES.swap(switches, Ordering::Relaxed);
+        WINDOW_START.store(now, Ordering::Relaxed);
+        // the window may be longer than a second, if no switch happened for a while
+        SWITCH_RATE.store(
+            (done as u128 * 1_000_000_000 / window.as_nanos()) as u64,
+            Ordering::Relaxed,
+        );
+    }
+}
+
+fn count(counter: impl FnOnce(&TaskSchedStats) -> &AtomicU64) {
+    if let Some(current) = tls::task_data().try_current_thread() {
+        counter(&current.metadata.sched_stats).fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// marks task as woken from a wait, such that the time until it runs is recorded as wakeup latency.
+/// Only the first wakeup counts, if it is woken again before running
+pub fn record_wakeup(task: &GlobalTask) {
+    _ = task.metadata.sched_stats.woken_at.compare_exchange(
+        0,
+        arch::timestamp(),
+        Ordering::Relaxed,
+        Ordering::Relaxed,
+    );
+}
+
+/// counts a yield of the current task
+pub fn count_yield() {
+    count(|stats| &stats.yields);
+}
+
+/// counts a syscall of the current task
+pub fn count_syscall() {
+    SYSCALLS.inc();
+    count(|stats| &stats.syscalls);
+}
+
+/// counts a wait of the current task in the wait manager
+pub fn count_sleep() {
+    count(|stats| &stats.sleeps);
+}
+
+/// total number of context switches since boot
+pub fn total_switches() -> u64 {
+    SWITCHES.get()
+}
+
+/// total number of syscalls since boot
+pub fn total_syscalls() -> u64 {
+    SYSCALLS.get()
+}
+
+/// context switches per second, measured over the last complete window of at least one second
+pub fn switch_rate() -> u64 {
+    SWITCH_RATE.load(Ordering::Relaxed)
+}
+
+pub(super) fn cycles_to_micros(cycles: u64) -> u64 {
+    time::cycles_to_duration(cycles).as_micros() as u64
+}
+
+/// /proc/schedstat: global scheduler counters, followed by one line of statistics per thread.
+/// Times are in microseconds
+#[derive(Debug, Default, Clone, Copy, FileRepr)]
+#[file(read)]
+pub struct SchedStat;
+
+impl SchedStat {
+    pub const HEADER: &'static str = "tid\tvoluntary\tinvoluntary\truntime_us\twait_us\tname\n";
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        _ = writeln!(out, "switches\t{}", total_switches());
+        _ = writeln!(out, "switches_per_sec\t{}", switch_rate());
+        _ = writeln!(out, "syscalls\t{}", total_syscalls());
+        _ = writeln!(out, "preempt_deferred\t{}", preempt::deferred_preemptions());
+        _ = writeln!(out, "runqueue\t{}", get_scheduler().runqueue_len());
+        out.push('\n');
+
+        let mut tasks = tls::task_data().get_table().snapshot();
+        tasks.sort_by_key(|task| task.tid().get_inner());
+
+        out.push_str(Self::HEADER);
+        for task in tasks {
+            let stats = &task.metadata.sched_stats;
+            _ = writeln!(
+                out,
+                "{}\t{}\t{}\t{}\t{}\t{}",
+                task.tid().get_inner(),
+                stats.voluntary(),
+                stats.involuntary(),
+                cycles_to_micros(stats.runtime()),
+                cycles_to_micros(stats.wait_time()),
+                task.name().unwrap_or("-")
+            );
+        }
+        out
+    }
+}
+
+impl Read for SchedStat {
+    fn read(&self, buf: &mut [u8], offset: usize) -> IOResult<usize> {
+        read_rendered(self.render(), buf, offset)
+    }
+}
+
+/// /proc/schedtop: the tasks, which yield and call into the kernel the most, ie the candidates for spinning in a
+/// polling loop instead of blocking in the wait manager. Runtime is in microseconds
+#[derive(Debug, Default, Clone, Copy, FileRepr)]
+#[file(read)]
+pub struct SchedTop;
+
+impl SchedTop {
+    pub const HEADER: &'static str = "tid\tyields\tsyscalls\tsleeps\truntime_us\tname\n";
+
+    fn render(&self) -> String {
+        let mut tasks = tls::task_data().get_table().snapshot();
+        tasks.sort_by_key(|task| {
+            let stats = &task.metadata.sched_stats;
+            core::cmp::Reverse(stats.yields() + stats.syscalls())
+        });
+
+        let mut out = String::from(Self::HEADER);
+        for task in tasks.iter().take(TOP_OFFENDERS) {
+            let stats = &task.metadata.sched_stats;
+            _ = writeln!(
+                out,
+                "{}\t{}\t{}\t{}\t{}\t{}",
+                task.tid().get_inner(),
+                stats.yields(),
+                stats.syscalls(),
+                stats.sleeps(),
+                cycles_to_micros(stats.runtime()),
+                task.name().unwrap_or("-")
+            );
+        }
+        out
+    }
+}
+
+impl Read for SchedTop {
+    fn read(&self, buf: &mut [u8], offset: usize) -> IOResult<usize> {
+        read_rendered(self.render(), buf, offset)
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use alloc::{format, vec::Vec};
+
+    use os_macros::kernel_test;
+
+    use super::*;
+    use crate::kernel::threading::yield_now;
+
+    #[kernel_test]
+    fn sched_stat() {
+        let current = tls::task_data().current_thread().unwrap();
+        let before = current.metadata.sched_stats.voluntary();
+        let switches = total_switches();
+        for _ in 0..10 {
+            yield_now();
+        }
+        // with only a single runnable task yield_now returns to the same task, which is not counted
+        assert!(current.metadata.sched_stats.voluntary() >= before);
+        assert!(total_switches() >= switches);
+
+        let rendered = SchedStat.render();
+        assert!(rendered.starts_with("switches\t"));
+        assert!(rendered.lines().any(|line| line.starts_with("syscalls\t")));
+        let line = rendered
+            .lines()
+            .skip_while(|line| *line != SchedStat::HEADER.trim_end())
+            .skip(1)
+            .find(|line| {
+                line.split('\t').next() == Some(format!("{}", current.tid().get_inner()).as_str())
+            });
+        assert!(line.is_some());
+    }
+
+    #[kernel_test]
+    fn sched_top() {
+        let current = tls::task_data().current_thread().unwrap();
+        let stats = &current.metadata.sched_stats;
+        let (yields, sleeps) = (stats.yields(), stats.sleeps());
+        for _ in 0..100 {
+            yield_now();
+        }
+        assert!(stats.yields() >= yields + 100);
+        assert_eq!(stats.sleeps(), sleeps);
+
+        let rendered = SchedTop.render();
+        assert!(rendered.starts_with(SchedTop::HEADER));
+        assert!(rendered.lines().count() <= TOP_OFFENDERS + 1);
+        let polls = rendered
+            .lines()
+            .skip(1)
+            .map(|line| {
+                let mut fields = line.split('\t').skip(1).map(|f| f.parse::<u64>().unwrap());
+                fields.next().unwrap() + fields.next().unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert!(polls.is_sorted_by(|a, b| a >= b));
+    }
+}
@@ -0,0 +1,157 @@
+use alloc::{boxed::Box, sync::Arc};
+use core::{
+    marker::PhantomData,
+    mem,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use super::{JoinHandle, ThreadingError, park, schedule::GlobalTaskPtr, spawn, tls};
+use crate::sync::locks::Mutex;
+
+/// runs f with a scope, whose tasks may borrow from the caller. Every task spawned in the scope has finished, once this
+/// returns, even if its handle was dropped. The caller parks meanwhile
+pub fn scope<'env, F, R>(f: F) -> R
+where
+    F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> R,
+{
+    let scope = Scope {
+        core: ScopeCore::new(tls::task_data().current_thread()),
+    };
+    let r = f(&scope);
+    scope.core.wait(park);
+    r
+}
+
+/// the part of a scope shared by scope and ThreadPool::scope. It counts the running jobs of the scope and erases
+/// their lifetime, which is sound as long as the owner of the scope calls wait before returning
+pub(super) struct ScopeCore<'scope, 'env: 'scope> {
+    running: Arc<AtomicUsize>,
+    /// woken by the last job of the scope to finish
+    owner: Option<GlobalTaskPtr>,
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+impl<'scope, 'env> ScopeCore<'scope, 'env> {
+    pub(super) fn new(owner: Option<GlobalTaskPtr>) -> Self {
+        Self {
+            running: Arc::new(AtomicUsize::new(0)),
+            owner,
+            scope: PhantomData,
+            env: PhantomData,
+        }
+    }
+
+    /// counts job as running until it finished. A job, which is never run, has to be handed back with cancel
+    pub(super) fn wrap<F>(&self, job: F) -> Box<dyn FnOnce() + Send + 'static>
+    where
+        F: FnOnce() + Send + 'scope,
+    {
+        let running = self.running.clone();
+        let owner = self.owner.clone();
+        running.fetch_add(1, Ordering::AcqRel);
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || {
+            job();
+            if running.fetch_sub(1, Ordering::AcqRel) == 1
+                && let Some(owner) = owner
+            {
+                owner.unpark();
+            }
+        });
+        // SAFETY: the owner of the scope waits for every job of the scope before returning,
+        // thus nothing borrowed by the job goes out of scope while it runs
+        unsafe { mem::transmute(job) }
+    }
+
+    /// uncounts a job returned by wrap, which was never run
+    pub(super) fn cancel(&self) {
+        self.running.fetch_sub(1, Ordering::AcqRel);
+    }
+
+    /// calls idle until every job of the scope finished
+    pub(super) fn wait(&self, mut idle: impl FnMut()) {
+        while self.running.load(Ordering::Acquire) > 0 {
+            idle();
+        }
+    }
+}
+
+/// spawns kernel tasks borrowing data of lifetime 'env, see scope
+pub struct Scope<'scope, 'env: 'scope> {
+    core: ScopeCore<'scope, 'env>,
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    /// spawns a kernel task running f, which may borrow anything outliving the scope
+    pub fn spawn<F, R>(&'scope self, f: F) -> Result<ScopedJoinHandle<'scope, R>, ThreadingError>
+    where
+        F: FnOnce() -> R + Send + 'scope,
+        R: Send + 'scope,
+    {
+        let result = Arc::new(Mutex::new(None));
+        let slot = result.clone();
+        let job = self.core.wrap(move || {
+            slot.lock().replace(f());
+            // the result may borrow from the scope, thus this reference to it is dropped before the scope may return
+            drop(slot);
+        });
+        let handle = spawn(job).inspect_err(|_| self.core.cancel())?;
+        Ok(ScopedJoinHandle {
+            handle,
+            result,
+            scope: PhantomData,
+        })
+    }
+}
+
+/// the handle of a task spawned with Scope::spawn
+pub struct ScopedJoinHandle<'scope, R> {
+    handle: JoinHandle<()>,
+    result: Arc<Mutex<Option<R>>>,
+    scope: PhantomData<&'scope ()>,
+}
+
+impl<R> ScopedJoinHandle<'_, R> {
+    /// waits for the task to finish and returns what it returned
+    pub fn join(self) -> Result<R, ThreadingError> {
+        self.handle.wait()?;
+        self.result.lock().take().ok_or(ThreadingError::Unknown(
+            "the scoped task returned nothing".into(),
+        ))
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.result.lock().is_some()
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use alloc::vec::Vec;
+
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    #[kernel_test]
+    fn scoped_tasks_borrow() {
+        let data = (0..1024u64).collect::<Vec<_>>();
+        let mut counts = [0usize; 4];
+        let total = scope(|s| {
+            let handles = data
+                .chunks(256)
+                .map(|chunk| s.spawn(move || chunk.iter().sum::<u64>()).unwrap())
+                .collect::<Vec<_>>();
+            // handles, which are dropped, are still waited for by the scope
+            for (i, count) in counts.iter_mut().enumerate() {
+                drop(s.spawn(move || *count = i + 1).unwrap());
+            }
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .sum::<u64>()
+        });
+        assert_eq!(total, data.iter().sum::<u64>());
+        assert_eq!(counts, [1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,123 @@
+use alloc::vec::Vec;
+
+use hashbrown::HashMap;
+
+use crate::{
+    kernel::threading::{schedule::GlobalTaskPtr, task::ThreadID},
+    sync::locks::RwLock,
+};
+
+const N_SHARDS: usize = 16;
+
+/// the table of all live threads.
+/// The map is split into N_SHARDS independently locked shards (by tid), such that lookups, insertions and removals of
+/// different threads rarely contend. try_* methods never wait and may be used where blocking is forbidden.
+#[derive(Debug)]
+pub struct TaskTable {
+    shards: [RwLock<HashMap<ThreadID, GlobalTaskPtr>>; N_SHARDS],
+}
+
+impl TaskTable {
+    pub fn new() -> Self {
+        Self {
+            shards: core::array::from_fn(|_| RwLock::default()),
+        }
+    }
+
+    fn shard(&self, id: &ThreadID) -> &RwLock<HashMap<ThreadID, GlobalTaskPtr>> {
+        &self.shards[id.get_inner() as usize % N_SHARDS]
+    }
+
+    pub fn get(&self, id: &ThreadID) -> Option<GlobalTaskPtr> {
+        self.shard(id).read().get(id).cloned()
+    }
+
+    /// returns None if the task does not exist or its shard is currently write locked
+    pub fn try_get(&self, id: &ThreadID) -> Option<GlobalTaskPtr> {
+        self.shard(id).try_read()?.get(id).cloned()
+    }
+
+    /// like try_get, but tells a contended shard (None) apart from a missing task (Some(None))
+    pub fn try_lookup(&self, id: &ThreadID) -> Option<Option<GlobalTaskPtr>> {
+        Some(self.shard(id).try_read()?.get(id).cloned())
+    }
+
+    /// inserts task. If a task with the same id existed, it is returned in Some
+    pub fn insert(&self, id: ThreadID, task: GlobalTaskPtr) -> Option<GlobalTaskPtr> {
+        self.shard(&id).write().insert(id, task)
+    }
+
+    pub fn remove(&self, id: &ThreadID) -> Option<GlobalTaskPtr> {
+        self.shard(id).write().remove(id)
+    }
+
+    /// calls f on every task. Only a single shard is locked at a time, thus tasks added or removed concurrently may or may not be visited
+    pub fn for_each<F>(&self, mut f: F)
+    where
+        F: FnMut(&ThreadID, &GlobalTaskPtr),
+    {
+        for shard in &self.shards {
+            for (id, task) in shard.read().iter() {
+                f(id, task);
+            }
+        }
+    }
+
+    /// all tasks currently in the table, in no particular order
+    pub fn snapshot(&self) -> Vec<GlobalTaskPtr> {
+        let mut tasks = Vec::new();
+        self.for_each(|_, task| tasks.push(task.clone()));
+        tasks
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.read().len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for TaskTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+    use crate::kernel::threading::tls;
+
+    #[kernel_test]
+    fn task_table_shards() {
+        let current = tls::task_data().current_thread().unwrap();
+        let table = TaskTable::new();
+        assert!(table.is_empty());
+
+        // ids in different shards and in the same shard
+        let ids = [1, 2, 1 + N_SHARDS as u64].map(ThreadID::from);
+        for id in ids {
+            assert!(table.insert(id, current.clone()).is_none());
+        }
+        assert_eq!(table.len(), ids.len());
+        assert!(table.insert(ids[0], current.clone()).is_some());
+
+        for id in &ids {
+            assert!(table.get(id).is_some());
+            assert!(table.try_get(id).is_some());
+        }
+        let guard = table.shard(&ids[0]).write();
+        assert!(table.try_get(&ids[2]).is_none());
+        assert!(table.try_get(&ids[1]).is_some());
+        drop(guard);
+
+        assert!(table.remove(&ids[2]).is_some());
+        assert!(table.get(&ids[2]).is_none());
+        assert!(table.get(&ids[0]).is_some());
+        assert_eq!(table.snapshot().len(), 2);
+    }
+}
@@ -1,14 +1,17 @@
-use alloc::{boxed::Box, format, string::String, sync::Arc, vec};
+use alloc::{boxed::Box, string::String, sync::Arc, vec, vec::Vec};
 use core::{
     cell::UnsafeCell,
     fmt::{Debug, Display, LowerHex},
     marker::PhantomData,
+    mem::offset_of,
     pin::Pin,
     ptr::null,
-    sync::atomic::{AtomicU8, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+    sync::atomic::{AtomicBool, AtomicU8, AtomicU32, AtomicU64, AtomicUsize, Ordering},
 };
 
-use super::{ProcessEntry, ThreadingError};
+use tinyos_abi::{flags::CloneFlags, types::AuxEntry};
+
+use super::{ProcessEntry, ThreadingError, argblock::ArgBlock};
 use crate::{
     arch::{
         self,
@@ -20,18 +23,21 @@ use crate::{
             allocate_kstack,
             allocate_userstack,
             copy_ustack_mappings_into,
+            fpu::FpuState,
             init_kernel_task,
             init_usr_task,
             unmap_ustack_mappings,
         },
         interrupt,
-        mem::{Cr3, Cr3Flags, PageSize, PhysFrame, Size4KiB, VirtAddr},
+        mem::{Cr3, Cr3Flags, PageSize, PageTableFlags, PhysFrame, Size4KiB, VirtAddr},
     },
     eprintln,
     kernel::{
-        elf::apply,
+        elf::{ElfImage, ElfSource, vdso},
         fd::{
-            FDMap,
+            FDFlags,
+            FDTable,
+            FDTableError,
             File,
             FileDescriptor,
             FileHandle,
@@ -40,7 +46,13 @@ use crate::{
             STDIN_FILENO,
             STDOUT_FILENO,
         },
-        fs::{self, Path},
+        fs::{
+            self,
+            Path,
+            PathBuf,
+            vfs::{self, VFS},
+        },
+        init::INCLUDED_BINS,
         mem::{
             align_up,
             paging::{
@@ -51,11 +63,22 @@ use crate::{
                 get_frame_alloc,
                 get_kernel_pagetbl_root,
             },
+            vma::{Vma, VmaBacking, VmaList},
+        },
+        threading::{
+            group::{self, ResourceGroup},
+            namespace::{self, PidNamespace},
+            schedule::stats::{self, TaskSchedStats},
+            tls,
+            trampoline::TaskExitInfo,
+            wait::{QueueType, WaitEvent, post_event},
         },
-        threading::{tls, trampoline::TaskExitInfo},
     },
     serial_println,
-    sync::locks::{Mutex, RwLock},
+    sync::{
+        SpinWaiter,
+        locks::{GenericRwLock, Mutex, RwLock},
+    },
 };
 
 pub const USER_MMAP_START: usize = 0x9000_000_0000;
@@ -77,10 +100,20 @@ pub trait TaskRepr: Debug + Sized {
     fn exit_info(&self) -> &TaskExitInfo;
     fn kstack_top(&self) -> &VirtAddr;
     fn fd(&self, descriptor: FileDescriptor) -> Option<FileHandle>;
-    fn add_fd(&self, descriptor: FileDescriptor, f: impl Into<FileHandle>) -> Option<FileHandle>;
+    fn add_fd(
+        &self,
+        descriptor: FileDescriptor,
+        f: impl Into<FileHandle>,
+    ) -> Result<Option<FileHandle>, FDTableError>;
     fn remove_fd(&self, descriptor: FileDescriptor) -> Option<FileHandle>;
-    fn add_next_file(&self, f: impl Into<FileHandle>) -> FileDescriptor;
-    fn next_fd(&self) -> FileDescriptor;
+    fn add_next_file(&self, f: impl Into<FileHandle>) -> Result<FileDescriptor, FDTableError>;
+    fn dup_fd(
+        &self,
+        old: FileDescriptor,
+        new: Option<FileDescriptor>,
+        flags: FDFlags,
+    ) -> Result<FileDescriptor, FDTableError>;
+    fn next_fd(&self) -> Result<FileDescriptor, FDTableError>;
     fn next_addr(&self) -> &AtomicUsize;
     fn ensure_ready(self) -> Result<Self, ThreadingError>;
 }
@@ -94,6 +127,16 @@ pub enum PrivilegeLevel {
     Unset,
 }
 
+impl PrivilegeLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Kernel => "kernel",
+            Self::User => "user",
+            Self::Unset => "-",
+        }
+    }
+}
+
 // a task represents a single thread in some process (where this thread may be the only one).
 // Core contains data shared across threads in a process
 // metadata contains data owned by each thread
@@ -114,10 +157,25 @@ pub struct TaskCore {
     pub heap_size: AtomicUsize,
     pub pid: ProcessID,
     pub pgrid: ProcessGroupID,
-    pub fd_table: RwLock<FDMap>,
+    pub fd_table: RwLock<FDTable>,
+    pub vmas: RwLock<VmaList>,
     pub next_free_addr: AtomicUsize,
     pub name: Option<String>,
     pub parent: Option<ThreadID>,
+    /// the process, which created this process
+    pub ppid: Option<ProcessID>,
+    /// directory, against which relative paths of the process are resolved
+    pub cwd: PathBuf,
+    /// directories searched for binaries, which are spawned by name, taken from PATH in the environment of the process
+    pub search_path: Vec<PathBuf>,
+    /// mount namespace, None for the initial one
+    pub mnt_ns: Option<Arc<VFS>>,
+    /// pid namespace, None for the initial one
+    pub pid_ns: Option<Arc<PidNamespace>>,
+    /// resource group, which the cpu time and memory of the process are accounted to
+    pub resource_group: GenericRwLock<Arc<ResourceGroup>, SpinWaiter>,
+    /// frames charged to resource_group on behalf of the process
+    pub charged_frames: AtomicUsize,
     pub state: AtomicU8,
     pub tidx: AtomicUsize,
     _private: PhantomData<()>,
@@ -137,6 +195,16 @@ pub struct TaskMetadata {
     pub krsp: AtomicU64,
     pub kernel_stack_top: VirtAddr,
     pub privilege: PrivilegeLevel,
+    /// set once the thread was detached. Detached threads can no longer be joined and are reaped without a join,
+    /// see TaskManager::reap_detached
+    pub detached: AtomicBool,
+    /// set by Task::unpark and consumed by threading::park, such that an unpark before a park is not lost
+    pub park_token: AtomicBool,
+    pub sched_stats: TaskSchedStats,
+    /// simd and floating point registers, while the thread is not running
+    pub fpu: FpuState,
+    /// the preemption nesting depth of the thread, while it is not running
+    pub preempt_count: AtomicU32,
     _private: PhantomData<()>,
 }
 
@@ -147,6 +215,19 @@ impl Task {
             core: TaskCore::new().into(),
         }
     }
+
+    /// makes the token of the thread available. If the thread is parked it is woken up, otherwise its next call to
+    /// threading::park returns immediately. Tokens do not accumulate
+    pub fn unpark(&self) {
+        self.metadata.park_token.store(true, Ordering::Release);
+        if post_event(WaitEvent::new(QueueType::Park)).is_err() {
+            // the event was dropped. Wake the thread directly, parked threads handle spurious wakeups
+            if self.state() == TaskState::Blocking {
+                self.set_state(TaskState::Ready);
+                stats::record_wakeup(self);
+            }
+        }
+    }
 }
 
 impl TaskCore {
@@ -164,19 +245,41 @@ impl TaskCore {
             parent: tls::task_data()
                 .current_thread()
                 .map(|current| current.tid()),
+            ppid: tls::task_data()
+                .current_thread()
+                .map(|current| current.pid()),
+            cwd: tls::task_data()
+                .current_thread()
+                .map_or_else(|| PathBuf::from("/"), |current| current.core.cwd.clone()),
+            search_path: tls::task_data().current_thread().map_or_else(
+                || vec![PathBuf::from(INCLUDED_BINS)],
+                |current| current.core.search_path.clone(),
+            ),
+            mnt_ns: tls::task_data()
+                .current_thread()
+                .and_then(|current| current.core.mnt_ns.clone()),
+            pid_ns: tls::task_data()
+                .current_thread()
+                .and_then(|current| current.core.pid_ns.clone()),
+            resource_group: GenericRwLock::new(tls::task_data().current_thread().map_or_else(
+                || group::root().clone(),
+                |current| current.core.resource_group.read().clone(),
+            )),
+            charged_frames: AtomicUsize::new(0),
             pid,   // copied from parent if thread
             pgrid, // copied from parent if exists or thread
             pagedir: APageTable::global().into(),
             heap_size: 0.into(),
             next_free_addr: AtomicUsize::new(0),
             fd_table: RwLock::default(),
+            vmas: RwLock::default(),
             state: (TaskState::default() as u8).into(),
             tidx: 1.into(), // this is initalized at 1, as the first thread will not use this number. thus we must "pre increment" it
             _private: PhantomData,
         }
     }
 
-    fn with_fd_table(mut self, table: FDMap) -> Self {
+    fn with_fd_table(mut self, table: FDTable) -> Self {
         self.fd_table = table.into();
         self
     }
@@ -191,6 +294,37 @@ impl TaskCore {
         self
     }
 
+    /// path relative to the working directory of the process, or path itself if it is absolute
+    pub fn resolve(&self, path: &Path) -> PathBuf {
+        if !path.is_relative() {
+            return path.to_owned();
+        }
+        let mut resolved = self.cwd.clone();
+        resolved.push(path);
+        resolved.canonicalize();
+        if resolved.is_empty() {
+            // the root itself
+            resolved.push("/");
+        }
+        resolved
+    }
+
+    /// the binary name refers to. A bare name is looked up in the search path, other paths are resolved like any
+    /// other path. None, if no directory of the search path contains name
+    pub fn resolve_binary(&self, name: &Path) -> Option<PathBuf> {
+        if name.as_str().contains('/') || matches!(name.as_str(), "" | "." | "..") {
+            return Some(self.resolve(name));
+        }
+        self.search_path
+            .iter()
+            .map(|dir| {
+                let mut path = self.resolve(dir);
+                path.push(name);
+                path
+            })
+            .find(|path| fs::open(path, fs::OpenOptions::empty()).is_ok())
+    }
+
     pub fn get_process_state(&self) -> TaskState {
         self.state.load(Ordering::Acquire).into()
     }
@@ -212,6 +346,11 @@ impl TaskMetadata {
             kernel_stack_top: VirtAddr::zero(),
             user_stack_top: None,
             ursp: None,
+            detached: AtomicBool::new(false),
+            park_token: AtomicBool::new(false),
+            sched_stats: TaskSchedStats::default(),
+            fpu: FpuState::new(),
+            preempt_count: AtomicU32::new(0),
             _private: PhantomData,
         }
     }
@@ -287,7 +426,11 @@ impl TaskRepr for Task {
     }
 
     /// inserts a K, V pair into fd table. If K was present, old V is returned in Some
-    fn add_fd(&self, descriptor: FileDescriptor, f: impl Into<FileHandle>) -> Option<FileHandle> {
+    fn add_fd(
+        &self,
+        descriptor: FileDescriptor,
+        f: impl Into<FileHandle>,
+    ) -> Result<Option<FileHandle>, FDTableError> {
         self.core.fd_table.write().insert(descriptor, f.into())
     }
 
@@ -295,19 +438,35 @@ impl TaskRepr for Task {
         self.core.fd_table.write().remove(&(descriptor as u32))
     }
 
-    fn add_next_file(&self, f: impl Into<FileHandle>) -> FileDescriptor {
-        let next_fd = self.next_fd();
-        self.add_fd(next_fd, f);
-        next_fd
+    fn add_next_file(&self, f: impl Into<FileHandle>) -> Result<FileDescriptor, FDTableError> {
+        self.core.fd_table.write().insert_next(f.into())
+    }
+
+    /// duplicates old onto new (or the lowest free fd) while holding the table lock, so that the target is replaced atomically.
+    fn dup_fd(
+        &self,
+        old: FileDescriptor,
+        new: Option<FileDescriptor>,
+        flags: FDFlags,
+    ) -> Result<FileDescriptor, FDTableError> {
+        let mut table = self.core.fd_table.write();
+        let f = table
+            .get(&old)
+            .ok_or(FDTableError::NotOpen(old))?
+            .clone()
+            .with_fd_flags(flags);
+        let (new, replaced) = match new {
+            Some(new) => (new, table.insert(new, f)?),
+            None => (table.insert_next(f)?, None),
+        };
+        drop(table);
+        // the replaced handle may run on_drop hooks, which should not happen while we hold the table
+        drop(replaced);
+        Ok(new)
     }
 
-    fn next_fd(&self) -> FileDescriptor {
-        self.core
-            .fd_table
-            .read()
-            .last_key_value()
-            .map(|(k, _)| *k + 1)
-            .unwrap_or(0)
+    fn next_fd(&self) -> Result<FileDescriptor, FDTableError> {
+        self.core.fd_table.read().lowest_free()
     }
 
     fn next_addr(&self) -> &AtomicUsize {
@@ -363,6 +522,16 @@ impl Arg {
     pub unsafe fn as_closure(&self) -> Box<dyn FnOnce() + 'static + Send> {
         unsafe { *Box::from_raw(self.0 as *mut Box<dyn FnOnce() + 'static + Send>) }
     }
+
+    /// passes block in a single Arg, which points to its bytes together with their length
+    pub fn from_block(block: ArgBlock) -> Self {
+        Self::from_val(block.into_boxed_bytes())
+    }
+
+    /// takes back the block passed with from_block. Like as_val, this may only be called once
+    pub unsafe fn as_block(&self) -> ArgBlock {
+        ArgBlock::from_boxed_bytes(unsafe { self.as_val::<Box<[u8]>>() })
+    }
 }
 
 impl Default for Arg {
@@ -418,21 +587,19 @@ macro_rules! args {
 
 pub struct Uninit;
 pub struct Init<'data> {
-    elf_data: Option<&'data [u8]>,
+    elf: Option<ElfSource<'data>>,
 }
 
 impl<'data> Init<'data> {
-    fn new(bytes: &'data [u8]) -> Self {
-        Self {
-            elf_data: Some(bytes),
-        }
+    fn new(elf: ElfSource<'data>) -> Self {
+        Self { elf: Some(elf) }
     }
 }
 
 #[allow(clippy::derivable_impls)]
 impl Default for Init<'_> {
     fn default() -> Self {
-        Self { elf_data: None }
+        Self { elf: None }
     }
 }
 
@@ -474,6 +641,17 @@ pub struct TaskData {
     args: Args,
 }
 
+impl TaskData {
+    pub fn new(args: Args) -> Self {
+        Self { args }
+    }
+
+    /// the offset of argument idx, which init_kernel_task and init_usr_task load into the argument registers
+    pub const fn arg_offset(idx: usize) -> usize {
+        offset_of!(TaskData, args) + idx * size_of::<Arg>()
+    }
+}
+
 pub struct TaskBuilder<T: TaskRepr, S> {
     inner: T,
     entry: VirtAddr,
@@ -502,6 +680,48 @@ impl<S> TaskBuilder<Task, S> {
         self
     }
 
+    pub fn with_cwd(mut self, cwd: PathBuf) -> TaskBuilder<Task, S> {
+        self.inner.core.try_mut().unwrap().cwd = cwd;
+        self
+    }
+
+    /// takes the search path from the PATH entry of env, a list of NUL separated KEY=value entries.
+    /// Without one, the search path of the creator is kept
+    pub fn with_env(mut self, env: &[u8]) -> TaskBuilder<Task, S> {
+        let path = env
+            .split(|b| *b == 0)
+            .find_map(|entry| entry.strip_prefix(b"PATH="))
+            .and_then(|path| core::str::from_utf8(path).ok());
+        if let Some(path) = path {
+            self.inner.core.try_mut().unwrap().search_path = path
+                .split(':')
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from)
+                .collect();
+        }
+        self
+    }
+
+    /// moves the new process into new namespaces, nested in the ones of its creator.
+    /// A new mount namespace starts out with a copy of the mounts of the creator
+    pub fn with_namespaces(mut self, flags: CloneFlags) -> TaskBuilder<Task, S> {
+        let core = self.inner.core.try_mut().unwrap();
+        if flags.contains(CloneFlags::NEWNS) {
+            core.mnt_ns.replace(Arc::new(vfs::get().unshare()));
+        }
+        if flags.contains(CloneFlags::NEWPID) {
+            core.pid_ns
+                .replace(Arc::new(PidNamespace::new(core.pid_ns.clone())));
+        }
+        self
+    }
+
+    /// moves the new process into group pgrid, instead of the group of its creator
+    pub fn with_pgrid(mut self, pgrid: ProcessGroupID) -> TaskBuilder<Task, S> {
+        self.inner.core.try_mut().unwrap().pgrid = pgrid;
+        self
+    }
+
     pub fn with_exit_info(mut self, exit_info: TaskExitInfo) -> TaskBuilder<Task, S> {
         *self.inner.metadata.exit_info = exit_info;
         self
@@ -524,14 +744,7 @@ impl<S> TaskBuilder<Task, S> {
     /// adds open files of current into the new process, if current is accessible, else uses defaults for stdin, stderr and stdout
     pub fn with_default_files(self, clone_these: bool) -> TaskBuilder<Task, S> {
         if clone_these && let Some(current) = tls::task_data().current_thread() {
-            self.override_files(
-                current
-                    .core
-                    .fd_table
-                    .read()
-                    .iter()
-                    .map(|(&fd, f)| (fd, f.clone())),
-            )
+            self.inherit_files(&current)
         } else {
             let stdin = fs::open(
                 Path::new("/proc/kernel/io/stateful_keyboard"),
@@ -560,6 +773,24 @@ impl<S> TaskBuilder<Task, S> {
         }
     }
 
+    /// copies the fd table of task into the new task. The new descriptors share their open file descriptions
+    /// (and thus cursors) with the ones in task, as with fork. The fd limit is inherited as well
+    pub fn inherit_files(self, task: &Task) -> TaskBuilder<Task, S> {
+        let table = task.core.fd_table.read();
+        _ = self.inner.core.fd_table.write().set_limit(table.limit());
+        self.override_files(table.iter().map(|(&fd, f)| (fd, f.clone())))
+    }
+
+    /// drops all files marked close-on-exec. Should be called once the task is about to run a new image
+    pub fn close_exec_files(self) -> TaskBuilder<Task, S> {
+        self.inner
+            .core
+            .fd_table
+            .write()
+            .retain(|_, f| !f.close_on_exec());
+        self
+    }
+
     pub fn clear_files(self) -> TaskBuilder<Task, S> {
         self.inner.core.fd_table.write().clear();
         self
@@ -571,10 +802,7 @@ impl<S> TaskBuilder<Task, S> {
     ) -> TaskBuilder<Task, S> {
         let mut table = self.inner.core.fd_table.write();
         for (fd, f) in files {
-            table
-                .entry(fd)
-                .and_modify(|v| *v = f.clone().into())
-                .or_insert(f.clone().into());
+            _ = table.insert(fd, f);
         }
         drop(table);
         self
@@ -609,7 +837,17 @@ impl TaskBuilder<Task, Uninit> {
             inner: Task::new(),
             entry: VirtAddr::zero(),
             data: TaskData::default(),
-            _marker: Init::new(bytes),
+            _marker: Init::new(ElfSource::Bytes(bytes)),
+        })
+    }
+
+    /// like from_bytes, but the image is read from file while it is loaded
+    pub fn from_file<'a>(file: File) -> Result<TaskBuilder<Task, Init<'a>>, ThreadingError> {
+        Ok(TaskBuilder::<Task, Init> {
+            inner: Task::new(),
+            entry: VirtAddr::zero(),
+            data: TaskData::default(),
+            _marker: Init::new(ElfSource::File(file)),
         })
     }
 }
@@ -662,12 +900,23 @@ impl TaskBuilder<Task, Init<'_>> {
             .next_free_addr
             .store(USER_MMAP_START, Ordering::Relaxed);
 
-        if let Some(data) = self._marker.elf_data {
-            let bytes = elf::ElfBytes::minimal_parse(data)
-                .map_err(|e| ThreadingError::Unknown(format!("{:#?}", e)))?;
-            self.entry = VirtAddr::new(bytes.ehdr.e_entry);
-            apply(&bytes, data, &mut tbl)
-                .map_err(|e| ThreadingError::Unknown(format!("{:#?}", e)))?;
+        let mut vmas = VmaList::new();
+        vmas.insert(stack_vma(usr_end));
+
+        // the local pid is assigned on registration with the namespace, which is idempotent
+        let pid = self.inner.pid();
+        if let Some(ns) = &self.inner.core.pid_ns {
+            ns.register(pid);
+        }
+        let local = namespace::local_pid(self.inner.core.pid_ns.as_deref(), pid).unwrap_or(pid);
+        vdso::map_into(&mut tbl, local.0)?;
+        vdso::vmas().for_each(|vma| vmas.insert(vma));
+
+        if let Some(source) = self._marker.elf.take() {
+            let image = ElfImage::parse(&source).map_err(ThreadingError::Elf)?;
+            self.entry = image.entry.as_virt();
+            image.load(&source, &mut tbl).map_err(ThreadingError::Elf)?;
+            image.vmas().into_iter().for_each(|vma| vmas.insert(vma));
         }
 
         let info = UsrTaskInfo::new(
@@ -686,6 +935,7 @@ impl TaskBuilder<Task, Init<'_>> {
         unsafe {
             self.inner.core.try_mut().unwrap().pagedir.replace(tbl);
         }
+        *self.inner.core.vmas.write() = vmas;
 
         Ok(TaskBuilder {
             inner: self.inner,
@@ -717,6 +967,7 @@ impl TaskBuilder<Task, Init<'_>> {
 
         self.inner.metadata.krsp = AtomicU64::new(kstack.as_u64());
         self.inner.metadata.kernel_stack_top = kstack;
+        self.inner.core.vmas.write().insert(stack_vma(usr_end));
 
         self.inner.metadata.user_stack_top.replace(usr_end);
         self.inner
@@ -753,6 +1004,13 @@ impl TaskBuilder<Task, Init<'_>> {
 }
 
 impl<T: TaskRepr> TaskBuilder<T, Ready<ExtendedUsrTaskInfo<'_>>> {
+    /// the task being built. Its address space exists at this point, such that it may be filled before the task starts
+    pub fn task(&self) -> &T {
+        &self.inner
+    }
+
+    /// copies argv and env onto the user stack, followed by the auxiliary vector.
+    /// The task is started with (argc, argv, envc, envp, auxv)
     pub fn allocate_arg_env(
         mut self,
         argc: usize,
@@ -824,6 +1082,14 @@ impl<T: TaskRepr> TaskBuilder<T, Ready<ExtendedUsrTaskInfo<'_>>> {
             core::ptr::null()
         };
 
+        // the auxiliary vector is aligned like the stack, below argv and env
+        let auxv = vdso::auxv(self.entry);
+        current_top = (current_top - size_of_val(&auxv) as u64).align_down(16u64);
+        let auxv_ptr = current_top.as_mut_ptr::<AuxEntry>();
+        unsafe {
+            core::ptr::copy_nonoverlapping(auxv.as_ptr(), auxv_ptr, auxv.len());
+        }
+
         unmap_ustack_mappings(&mut tbl);
         unsafe {
             Cr3::write(active_table_root, Cr3Flags::empty());
@@ -835,7 +1101,7 @@ impl<T: TaskRepr> TaskBuilder<T, Ready<ExtendedUsrTaskInfo<'_>>> {
             Arg::from_ptr(argv_ptr as *mut u8),
             Arg::from_usize(envc),
             Arg::from_ptr(env_ptr as *mut u8),
-            Arg::default(),
+            Arg::from_ptr(auxv_ptr),
             Arg::default(),
         ]);
 
@@ -891,6 +1157,20 @@ impl<T: TaskRepr> TaskBuilder<T, Ready<KTaskInfo>> {
     }
 }
 
+/// the vma of the user stack with top stack_top, as allocated by allocate_userstack. Only its top is mapped up front
+pub fn stack_vma(stack_top: VirtAddr) -> Vma {
+    let end = (stack_top + 1).align_up(Size4KiB::SIZE);
+    let len = align_up(USER_STACK_SIZE, Size4KiB::SIZE as usize) as u64;
+    // the lowest page is left unmapped as a guard page
+    let start = end - len + Size4KiB::SIZE;
+    Vma::new(
+        start,
+        (end - start) as usize,
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE,
+        VmaBacking::Stack,
+    )
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
 #[repr(u8)]
 pub enum TaskState {
@@ -900,12 +1180,25 @@ pub enum TaskState {
     Blocking,
     Sleeping,
     Zombie,
+    /// stopped for job control, until the process is continued
+    Stopped,
 }
 
 impl TaskState {
     pub fn new() -> Self {
         Self::Ready
     }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Running => "running",
+            Self::Ready => "ready",
+            Self::Blocking => "blocking",
+            Self::Sleeping => "sleeping",
+            Self::Zombie => "zombie",
+            Self::Stopped => "stopped",
+        }
+    }
 }
 
 impl From<u8> for TaskState {
@@ -916,6 +1209,7 @@ impl From<u8> for TaskState {
             2 => Self::Blocking,
             3 => Self::Sleeping,
             4 => Self::Zombie,
+            5 => Self::Stopped,
             _ => panic!("invalid enum variant"),
         }
     }
@@ -1059,6 +1353,83 @@ mod tests {
         }
     }
 
+    #[kernel_test]
+    fn dup_and_cloexec() {
+        let null = || {
+            fs::open(Path::new("/proc/kernel/null"), fs::OpenOptions::READ)
+                .map(FileHandle::from)
+                .unwrap()
+        };
+        let builder = TaskBuilder::from_fn(foo)
+            .unwrap()
+            .clear_files()
+            .with_file(0, null())
+            .with_file(1, null().with_fd_flags(FDFlags::CLOEXEC));
+
+        assert_eq!(builder.inner.dup_fd(1, None, FDFlags::empty()), Ok(2));
+        assert_eq!(builder.inner.dup_fd(0, Some(1), FDFlags::CLOEXEC), Ok(1));
+        assert_eq!(
+            builder.inner.dup_fd(3, Some(0), FDFlags::empty()),
+            Err(FDTableError::NotOpen(3))
+        );
+        assert!(!builder.get_file(2).unwrap().close_on_exec());
+
+        let builder = builder.close_exec_files();
+        assert!(builder.get_file(0).is_some());
+        assert!(builder.get_file(1).is_none());
+        assert!(builder.get_file(2).is_some());
+    }
+
+    #[kernel_test]
+    fn spawn_attributes() {
+        let pgrid = tls::task_data().next_pgrid();
+        assert_ne!(pgrid, ProcessGroupID(0));
+        let builder = TaskBuilder::from_fn(foo)
+            .unwrap()
+            .with_cwd(PathBuf::from("/ram/bin"))
+            .with_pgrid(pgrid);
+
+        assert_eq!(builder.inner.pgrid(), pgrid);
+        let core = &builder.inner.core;
+        assert_eq!(core.resolve(Path::new("ls")).as_str(), "/ram/bin/ls");
+        assert_eq!(core.resolve(Path::new("../lib/./x")).as_str(), "/ram/lib/x");
+        assert_eq!(core.resolve(Path::new("../../..")).as_str(), "/");
+        assert_eq!(core.resolve(Path::new("/proc")).as_str(), "/proc");
+    }
+
+    #[kernel_test]
+    fn search_path() {
+        let builder = TaskBuilder::from_fn(foo)
+            .unwrap()
+            .with_cwd(PathBuf::from("/ram"))
+            .with_env(b"HOME=/\0PATH=/nowhere::bin\0");
+        let core = &builder.inner.core;
+        assert_eq!(
+            core.search_path,
+            [PathBuf::from("/nowhere"), PathBuf::from("bin")]
+        );
+        // relative directories are resolved against the working directory
+        assert_eq!(
+            core.resolve_binary(Path::new("shutdown")).unwrap().as_str(),
+            "/ram/bin/shutdown"
+        );
+        assert!(core.resolve_binary(Path::new("no_such_binary")).is_none());
+        assert_eq!(
+            core.resolve_binary(Path::new("./shutdown"))
+                .unwrap()
+                .as_str(),
+            "/ram/shutdown"
+        );
+
+        // an environment without PATH keeps the inherited search path
+        let builder = TaskBuilder::from_fn(foo).unwrap().with_env(b"HOME=/\0");
+        let inherited = tls::task_data().current_thread().map_or_else(
+            || vec![PathBuf::from(INCLUDED_BINS)],
+            |current| current.core.search_path.clone(),
+        );
+        assert_eq!(builder.inner.core.search_path, inherited);
+    }
+
     #[with_default_args]
     extern "C" fn foo() -> ProcessReturn {
         _arg0.0 + _arg1.0 + _arg2.0 + _arg3.0 + _arg4.0 + _arg5.0
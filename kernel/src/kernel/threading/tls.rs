@@ -1,25 +1,36 @@
 use alloc::{
     boxed::Box,
     collections::{btree_map::BTreeMap, vec_deque::VecDeque},
+    string::String,
     sync::Arc,
     vec::Vec,
 };
 use core::{
-    fmt::Debug,
+    fmt::{Debug, Write},
     sync::atomic::{AtomicU64, Ordering},
 };
 
 use conquer_once::spin::OnceCell;
 use hashbrown::HashMap;
-use tinyos_abi::flags::TaskStateChange;
+use os_macros::FileRepr;
+use tinyos_abi::flags::{TaskStateChange, TaskWaitOptions};
 
 use crate::{
-    arch::context::{free_kstack, free_user_stack},
+    arch::{
+        context::{free_kstack, free_user_stack},
+        percpu,
+    },
     eprintln,
     kernel::{
         fd::MaybeOwned,
+        io::{IOResult, Read, read_rendered},
+        mem::vma::ProcessMaps,
         threading::{
-            schedule::{GlobalTaskPtr, Scheduler},
+            children::{ChildEvent, ChildList, ChildSelector},
+            group,
+            namespace::local_pid,
+            schedule::{GlobalTask, GlobalTaskPtr, Scheduler, assert_may_block, stats},
+            table::TaskTable,
             task::{
                 ExitInfo,
                 ProcessGroupID,
@@ -29,6 +40,7 @@ use crate::{
                 TaskState,
                 TaskStateData,
                 ThreadID,
+                stack_vma,
             },
             wait::{QueueType, WaitEvent, post_event},
         },
@@ -128,38 +140,47 @@ impl Process {
 
 #[derive(Debug)]
 pub struct TaskManager {
-    current_running: AtomicU64, // TaskID of the curently active thread
-    lut: RwLock<HashMap<ThreadID, GlobalTaskPtr>>, // LUT for thread id --> thread
+    lut: TaskTable, // LUT for thread id --> thread
     processes: RwLock<HashMap<ProcessID, MaybeOwned<TaskCore>>>, // MaybeOwned here is never owned, as each core is shared with at least one thread. This is enforced by TaskBuilder
     tree: RwLock<BTreeMap<ProcessGroupID, Arc<RwLock<ProcessGroup>>>>,
     zombies: Mutex<VecDeque<ThreadID>>,
+    children: RwLock<HashMap<ProcessID, ChildList>>, // per parent lists of children, which were not yet reaped
 }
 
 impl TaskManager {
     fn new() -> Self {
         Self {
-            current_running: ThreadID::default().get_inner().into(),
-            lut: RwLock::default(),
+            lut: TaskTable::new(),
             processes: RwLock::default(),
             tree: RwLock::default(),
             zombies: Mutex::default(),
+            children: RwLock::default(),
         }
     }
 
     pub fn thread(&self, task: &ThreadID) -> Option<GlobalTaskPtr> {
-        self.lut.read().get(task).cloned()
+        assert_may_block();
+        self.lut.get(task)
     }
 
+    /// the thread running on this cpu. This does not touch the task table, unless per cpu data is not set up yet
     pub fn current_thread(&self) -> Option<GlobalTaskPtr> {
-        self.thread(&self.current_tid())
+        current_from_cpu().or_else(|| self.thread(&self.current_tid()))
     }
 
     pub fn try_thread(&self, task: &ThreadID) -> Option<GlobalTaskPtr> {
-        self.lut.try_read()?.get(task).cloned()
+        self.lut.try_get(task)
     }
 
     pub fn try_current_thread(&self) -> Option<GlobalTaskPtr> {
-        self.try_thread(&self.current_tid())
+        current_from_cpu().or_else(|| self.try_thread(&self.current_tid()))
+    }
+
+    /// the thread, which was interrupted on this cpu. Unlike try_current_thread this does not touch gs, thus it may be
+    /// called from the x86-interrupt handlers, which run with the user gs base if they interrupted ring 3.
+    /// Returns None if the task table is locked, and Some(None) if no task was interrupted
+    pub fn try_interrupted_thread(&self) -> Option<Option<GlobalTaskPtr>> {
+        self.lut.try_lookup(&percpu::interrupted_tid().into())
     }
 
     pub fn current_pgr(&self) -> Option<Arc<RwLock<ProcessGroup>>> {
@@ -178,18 +199,39 @@ impl TaskManager {
     }
 
     pub fn current_tid(&self) -> ThreadID {
-        (&self.current_running).into()
+        percpu::current_tid().into()
     }
 
-    pub fn update_current(&self, task: ThreadID) {
-        self.current_running
-            .store(task.get_inner(), Ordering::Release);
+    /// marks task as running on this cpu. This only touches per cpu data and is thus safe to call while switching
+    pub fn set_current(&self, task: &GlobalTaskPtr) {
+        if let Some(cpu) = percpu::this_cpu() {
+            cpu.set_current(task.tid().get_inner(), Arc::as_ptr(task) as *const ());
+        }
     }
 
     /// thread
     pub fn add(&self, task: GlobalTaskPtr) -> Option<GlobalTaskPtr> {
         let pid = task.pid();
-        _ = self.processes.write().insert(pid, task.core.try_clone()?);
+        let is_new = self
+            .processes
+            .write()
+            .insert(pid, task.core.try_clone()?)
+            .is_none();
+        if is_new {
+            _ = ProcessMaps::register(pid);
+            if let Some(ns) = &task.core.pid_ns {
+                ns.register(pid);
+            }
+            if let Some(ppid) = task.core.ppid
+                && ppid != pid
+            {
+                self.children
+                    .write()
+                    .entry(ppid)
+                    .or_default()
+                    .add(pid, task.pgrid());
+            }
+        }
         _ = self
             .tree
             .write()
@@ -199,33 +241,44 @@ impl TaskManager {
             })
             .or_insert(RwLock::new(ProcessGroup::new(pid, Process::new(task.clone()))).into());
 
-        self.lut.write().insert(task.tid(), task)
+        self.lut.insert(task.tid(), task)
     }
 
     pub fn cleanup(&self) {
-        let tasks = self.lut.read();
-        for (id, task) in tasks.iter() {
+        self.lut.for_each(|id, task| {
             if task.state() == TaskState::Zombie {
                 self.zombies.lock().push_back(*id);
             }
-        }
+        });
 
-        drop(tasks);
         // cleanup zombies and remove them from self.tasks
         self.cleanup_tree();
         while let Some(zombie) = self.zombies.lock().pop_front() {
-            let Some(task) = self.lut.write().remove(&zombie) else {
+            let Some(task) = self.lut.remove(&zombie) else {
                 continue;
             };
             cleanup_task(task);
         }
     }
 
+    /// thread, which was detached. If it already exited, it is reaped right away together with every other zombie,
+    /// as nobody joins it. Otherwise it is reaped by the cleanup after its exit, like any other thread.
+    /// No task locks may be held by the caller
+    pub fn reap_detached(&self, id: &ThreadID) -> Option<()> {
+        let task = self.thread(id)?;
+        debug_assert!(task.metadata.detached.load(Ordering::Acquire));
+        let exited = task.state() == TaskState::Zombie;
+        // the reference held here would keep the process resources of the thread alive
+        drop(task);
+        exited.then(|| self.cleanup())
+    }
+
     fn cleanup_tree(&self) {
         let groups = self.tree.read();
         let mut empty_groups = Vec::new();
         let mut empty_members = Vec::new();
         let mut dead_threads = Vec::new();
+        let mut exited = Vec::new();
 
         for (group_id, group_arc) in groups.iter() {
             let group = group_arc.read_arc();
@@ -233,6 +286,7 @@ impl TaskManager {
             for (pid, process_arc) in group.members.iter() {
                 let process = process_arc.read_arc();
                 let mut leader_dead = false;
+                let mut exit_code = 0;
 
                 for (tid, thread) in process.threads.iter() {
                     if thread.state() == TaskState::Zombie {
@@ -242,6 +296,9 @@ impl TaskManager {
                         ));
                         if tid == &process.leader {
                             leader_dead = true;
+                            if let TaskStateData::Exit(info) = &*thread.state_data().lock() {
+                                exit_code = info.exit_code as u64;
+                            }
                         }
                         dead_threads.push(*tid);
                     }
@@ -255,11 +312,18 @@ impl TaskManager {
                 }
 
                 if process.threads.is_empty() || leader_dead {
-                    self.processes
-                        .read()
-                        .get(pid)
-                        .map(|p| p.set_process_state(TaskState::Zombie));
+                    let ppid = self.processes.read().get(pid).and_then(|p| {
+                        p.set_process_state(TaskState::Zombie);
+                        group::release_process(p);
+                        p.ppid
+                    });
+                    if let Some(ppid) = ppid {
+                        self.notify_parent(ppid, *pid, ChildEvent::Exited(exit_code));
+                    }
+                    // children of this process can no longer be waited for
+                    self.children.write().remove(pid);
                     empty_members.push(*pid);
+                    exited.push(*pid);
                 }
             }
 
@@ -282,6 +346,52 @@ impl TaskManager {
         for gid in empty_groups.drain(..) {
             groups.remove(&gid);
         }
+        drop(groups);
+        // the entry in processes outlives the process, thus its procfs files are removed here and not in
+        // cleanup_process. Unlinking them goes through the vfs, so no task locks may be held
+        for pid in exited {
+            ProcessMaps::deregister(pid);
+        }
+    }
+
+    /// records a state change of child in the child list of parent and wakes up waiters of parent
+    pub fn notify_parent(&self, parent: ProcessID, child: ProcessID, event: ChildEvent) {
+        if self
+            .children
+            .write()
+            .get_mut(&parent)
+            .is_some_and(|list| list.notify(&child, event))
+        {
+            _ = post_event(WaitEvent::with_data(
+                QueueType::Children(parent),
+                event.change().bits() as u64,
+            ));
+        }
+    }
+
+    /// returns the first pending state change of a child of parent matching selector and wanted.
+    /// If consume is set, the change is removed and exited children are reaped.
+    /// Returns None if parent has no matching children at all
+    pub fn collect_child(
+        &self,
+        parent: &ProcessID,
+        selector: &ChildSelector,
+        wanted: TaskWaitOptions,
+        consume: bool,
+    ) -> Option<Option<(ProcessID, ChildEvent)>> {
+        let mut children = self.children.write();
+        let list = children.get_mut(parent)?;
+        if !list.has_children(selector) {
+            return None;
+        }
+        Some(list.collect(selector, wanted, consume))
+    }
+
+    pub fn has_pending_child(&self, parent: &ProcessID, wanted: TaskWaitOptions) -> bool {
+        self.children
+            .read()
+            .get(parent)
+            .is_some_and(|list| list.has_pending(wanted))
     }
 
     /// thread
@@ -294,7 +404,7 @@ impl TaskManager {
         }
     }
 
-    pub fn get_table(&self) -> &RwLock<HashMap<ThreadID, GlobalTaskPtr>> {
+    pub fn get_table(&self) -> &TaskTable {
         &self.lut
     }
 
@@ -314,6 +424,18 @@ impl TaskManager {
         Some(())
     }
 
+    /// thread, terminated by signal. Its exit code is 128 + signal, like a shell reports it
+    pub fn kill_with_signal(&self, id: &ThreadID, signal: u8) -> Option<()> {
+        let task = self.thread(id)?;
+        task.set_state(TaskState::Zombie);
+        *task.state_data().lock() = TaskStateData::Exit(ExitInfo {
+            exit_code: 128 + signal as u32,
+            signal: Some(signal),
+        });
+        self.update(&task);
+        Some(())
+    }
+
     /// thread
     pub fn block(&self, id: &ThreadID) -> Option<()> {
         let task = self.thread(id)?;
@@ -341,6 +463,7 @@ impl TaskManager {
         let task = self.try_thread(id)?;
         if task.state() == TaskState::Blocking || task.state() == TaskState::Sleeping {
             task.set_state(TaskState::Ready);
+            stats::record_wakeup(&task);
         }
         Some(())
     }
@@ -350,6 +473,7 @@ impl TaskManager {
         let task = self.thread(id)?;
         if task.state() == TaskState::Blocking || task.state() == TaskState::Sleeping {
             task.set_state(TaskState::Ready);
+            stats::record_wakeup(&task);
         }
         Some(())
     }
@@ -374,18 +498,87 @@ impl TaskManager {
         Some(())
     }
 
+    /// process, stopped for job control. Its threads are not scheduled again until continue_process
+    pub fn stop_process(&self, pid: &ProcessID) -> Option<()> {
+        self.set_stopped(pid, true)
+    }
+
+    /// process, continued after stop_process. Blocked threads were stopped as well and see a spurious wakeup
+    pub fn continue_process(&self, pid: &ProcessID) -> Option<()> {
+        self.set_stopped(pid, false)
+    }
+
+    fn set_stopped(&self, pid: &ProcessID, stop: bool) -> Option<()> {
+        let ppid = {
+            let processes = self.processes.read();
+            let process = processes.get(pid)?;
+            let tree = self.tree.read();
+            let group = tree.get(&process.pgrid)?.read();
+            let thread_list = group.members.get(pid)?.read();
+            let mut changed = false;
+            for thread in thread_list.threads.values() {
+                match thread.state() {
+                    TaskState::Zombie => {}
+                    TaskState::Stopped if !stop => {
+                        thread.set_state(TaskState::Ready);
+                        stats::record_wakeup(thread);
+                        changed = true;
+                    }
+                    TaskState::Stopped => {}
+                    _ if stop => {
+                        thread.set_state(TaskState::Stopped);
+                        changed = true;
+                    }
+                    _ => {}
+                }
+            }
+            if !changed {
+                return Some(());
+            }
+            process.ppid
+        };
+        let event = if stop {
+            ChildEvent::Stopped
+        } else {
+            ChildEvent::Continued
+        };
+        if let Some(ppid) = ppid {
+            self.notify_parent(ppid, *pid, event);
+        }
+        _ = post_event(WaitEvent::with_data(
+            QueueType::Process(*pid),
+            event.change().bits() as u64,
+        ));
+        Some(())
+    }
+
     pub fn next_pgrid(&self) -> ProcessGroupID {
-        static CURRENT_PGRID: AtomicU64 = AtomicU64::new(0);
+        // group 0 is the one of the initial tasks
+        static CURRENT_PGRID: AtomicU64 = AtomicU64::new(1);
         let current = CURRENT_PGRID.fetch_add(1, Ordering::AcqRel);
         ProcessGroupID(current)
     }
 }
 
+/// the task running on this cpu, read from per cpu data
+fn current_from_cpu() -> Option<GlobalTaskPtr> {
+    let ptr = percpu::this_cpu()?.current_task() as *const GlobalTask;
+    if ptr.is_null() {
+        return None;
+    }
+    // SAFETY: the running task is kept alive by the task table, as it is only cleaned up after it was switched away from.
+    // The pointer is replaced on every switch
+    unsafe {
+        Arc::increment_strong_count(ptr);
+        Some(Arc::from_raw(ptr))
+    }
+}
+
 pub fn task_data<'a>() -> &'a TaskManager {
     GLOBAL_TASK_MANAGER.get_or_init(TaskManager::new)
 }
 
-fn cleanup_task(task: GlobalTaskPtr) {
+pub(crate) fn cleanup_task(task: GlobalTaskPtr) {
     // This should
     // a) clean TaskMetadata
     // TaskCore should handle its own drop.
@@ -423,6 +616,8 @@ fn cleanup_thread(task: GlobalTaskPtr) {
                 task.tid()
             )
         });
+        let stack = stack_vma(stack_top);
+        task.core.vmas.write().remove(stack.start(), stack.len());
         task.metadata
             .ursp
             .as_ref()
@@ -443,6 +638,7 @@ fn cleanup_thread(task: GlobalTaskPtr) {
 fn cleanup_process(task: TaskCore) {
     // clear shared process resources
     task.fd_table.write().clear();
+    task.vmas.write().clear();
     // SAFETY:
     // we checked that we are the last one holding a ref to this address space.
     // It is not being used and we are currently in the kernels address space.
@@ -450,3 +646,160 @@ fn cleanup_process(task: TaskCore) {
         task.pagedir.into_inner().cleanup();
     }
 }
+
+/// procfs listing of all threads, one line per thread ordered by tid.
+/// The format is stable, such that userspace tools like ps can rely on it: a header line followed by tab separated rows of
+/// tid, pid, pgrid, state, priority, owner and name. Priority is always 0, as the scheduler does not support priorities yet.
+/// Owner is the privilege level the thread runs at (kernel or user). Unnamed threads are listed with name -.
+/// Readers inside a pid namespace only see the processes of their namespace, with their local pids
+#[derive(Debug, Default, Clone, Copy, FileRepr)]
+#[file(read)]
+pub struct TaskList;
+
+impl TaskList {
+    pub const HEADER: &'static str = "tid\tpid\tpgrid\tstate\tprio\towner\tname\n";
+
+    pub fn render(&self) -> String {
+        let mut tasks = task_data().get_table().snapshot();
+        tasks.sort_by_key(|task| task.tid().get_inner());
+        let ns = task_data()
+            .current_thread()
+            .and_then(|current| current.core.pid_ns.clone());
+
+        let mut out = String::from(Self::HEADER);
+        for task in tasks {
+            let Some(pid) = local_pid(ns.as_deref(), task.pid()) else {
+                continue;
+            };
+            _ = writeln!(
+                out,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                task.tid().get_inner(),
+                pid.0,
+                task.pgrid().0,
+                task.state().as_str(),
+                0,
+                task.privilege().as_str(),
+                task.name().unwrap_or("-")
+            );
+        }
+        out
+    }
+}
+
+impl Read for TaskList {
+    fn read(&self, buf: &mut [u8], offset: usize) -> IOResult<usize> {
+        read_rendered(self.render(), buf, offset)
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::{kernel_test, with_default_args};
+
+    use super::*;
+    use crate::kernel::{
+        mem::{paging::get_frame_alloc, vma::mem_info},
+        threading::{ProcessReturn, schedule::add_task_ptr__, task::TaskBuilder, yield_now},
+    };
+
+    #[with_default_args]
+    extern "C" fn noop() -> ProcessReturn {
+        0
+    }
+
+    #[with_default_args]
+    extern "C" fn spin() -> ProcessReturn {
+        loop {
+            yield_now();
+        }
+    }
+
+    fn spawn_and_destroy_usr_task() {
+        let task = TaskBuilder::from_fn(noop)
+            .unwrap()
+            .as_usr()
+            .unwrap()
+            .build();
+        cleanup_task(task.into());
+    }
+
+    #[kernel_test]
+    fn usr_tasks_do_not_leak_frames() {
+        // the first task may allocate page tables for the kernel stack area, which stay around
+        spawn_and_destroy_usr_task();
+        let baseline = get_frame_alloc().lock().allocated();
+        for _ in 0..64 {
+            spawn_and_destroy_usr_task();
+        }
+        assert_eq!(get_frame_alloc().lock().allocated(), baseline);
+    }
+
+    #[kernel_test]
+    fn usr_task_mem_info() {
+        let kernel_task = task_data().current_thread().unwrap();
+        assert_eq!(mem_info(&kernel_task.core).resident, 0);
+
+        let task = TaskBuilder::from_fn(noop)
+            .unwrap()
+            .as_usr()
+            .unwrap()
+            .build();
+        let info = mem_info(&task.core);
+        assert!(info.vmas >= 1);
+        assert!(info.resident > 0);
+        assert!(info.page_tables > 0);
+        assert_eq!(info.shared, 0);
+        cleanup_task(task.into());
+    }
+
+    #[kernel_test]
+    fn task_list() {
+        let rendered = TaskList.render();
+        let mut lines = rendered.lines();
+        assert_eq!(Some(TaskList::HEADER.trim_end()), lines.next());
+
+        let current = task_data().current_thread().unwrap();
+        let row = lines
+            .map(|line| line.split('\t').collect::<Vec<_>>())
+            .find(|row| row[0].parse::<u64>() == Ok(current.tid().get_inner()))
+            .unwrap();
+        assert_eq!(row.len(), 7);
+        assert_eq!(row[1].parse::<u64>(), Ok(current.pid().0));
+        assert_eq!(row[3], "running");
+        assert_eq!(row[5], current.privilege().as_str());
+    }
+
+    #[kernel_test]
+    fn stop_and_continue_notify_parent() {
+        let parent = task_data().current_thread().unwrap().pid();
+        let task: GlobalTaskPtr = TaskBuilder::from_fn(spin)
+            .unwrap()
+            .as_kernel()
+            .unwrap()
+            .build()
+            .into();
+        let child = task.pid();
+        add_task_ptr__(task.clone());
+        let selector = ChildSelector::Pid(child);
+        let wanted = TaskWaitOptions::W_STOP | TaskWaitOptions::W_CONTINUE;
+
+        task_data().stop_process(&child).unwrap();
+        assert_eq!(task.state(), TaskState::Stopped);
+        assert_eq!(
+            task_data().collect_child(&parent, &selector, wanted, true),
+            Some(Some((child, ChildEvent::Stopped)))
+        );
+        // stopping twice does not post a second event
+        task_data().stop_process(&child).unwrap();
+        assert!(!task_data().has_pending_child(&parent, wanted));
+
+        task_data().continue_process(&child).unwrap();
+        assert_ne!(task.state(), TaskState::Stopped);
+        assert_eq!(
+            task_data().collect_child(&parent, &selector, wanted, true),
+            Some(Some((child, ChildEvent::Continued)))
+        );
+        task_data().kill_process(&child).unwrap();
+    }
+}
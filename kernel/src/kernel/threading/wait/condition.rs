@@ -1,20 +1,21 @@
 use alloc::boxed::Box;
-use core::time::Duration;
 
 use tinyos_abi::flags::{TaskStateChange, TaskWaitOptions};
 
 use crate::{
-    arch::x86::current_time,
     drivers::keyboard::KEYBOARD_BUFFER,
-    kernel::threading::{
-        task::{TaskRepr, TaskState, ThreadID},
-        tls,
+    kernel::{
+        threading::{
+            task::{TaskRepr, TaskState, ThreadID},
+            tls,
+        },
+        time::Instant,
     },
 };
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone)]
 pub enum WaitCondition {
-    Time(Duration),
+    Time(Instant),
     Keyboard,
     Thread(ThreadID, TaskWaitOptions),
     Generic(u64, *const dyn Fn(u64) -> bool),
@@ -28,7 +29,7 @@ impl WaitCondition {
     //TODO add msg: T, where msg is the msg passed in WaitEvent
     pub fn is_given(&self) -> bool {
         match self {
-            Self::Time(t) => *t <= current_time(),
+            Self::Time(t) => *t <= Instant::now(),
             Self::Keyboard => !KEYBOARD_BUFFER.is_empty(),
             Self::Thread(id, config) => tls::task_data()
                 .thread(id)
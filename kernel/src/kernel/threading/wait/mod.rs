@@ -4,6 +4,7 @@ use core::{
     hash::{BuildHasher, BuildHasherDefault, Hash, Hasher},
     ops::Deref,
     ptr::NonNull,
+    sync::atomic::{AtomicU64, Ordering},
 };
 
 use conquer_once::spin::OnceCell;
@@ -32,14 +33,27 @@ pub const MAX_WAIT_EVENTS: usize = 20;
 pub static MESSAGE_QUEUE: OnceCell<PooledStaticQueue<WaitEvent<u64>, MAX_WAIT_EVENTS, Tagged64>> =
     OnceCell::uninit();
 
+static DROPPED_EVENTS: AtomicU64 = AtomicU64::new(0);
+
 pub fn init() {
     MESSAGE_QUEUE.init_once(|| PooledStaticQueue::with_slot());
 }
 
+/// posts an event to the wait manager. Never allocates, as it is called from interrupt handlers.
+/// The event is handed back, if the queue is full or was not initialized yet
 pub fn post_event(event: WaitEvent<u64>) -> Result<(), WaitEvent<u64>> {
-    MESSAGE_QUEUE
-        .get_or_init(|| PooledStaticQueue::with_slot())
-        .push(event)
+    let Some(queue) = MESSAGE_QUEUE.get() else {
+        DROPPED_EVENTS.fetch_add(1, Ordering::Relaxed);
+        return Err(event);
+    };
+    queue.push(event).inspect_err(|_| {
+        DROPPED_EVENTS.fetch_add(1, Ordering::Relaxed);
+    })
+}
+
+/// number of events, which could not be posted
+pub fn dropped_events() -> u64 {
+    DROPPED_EVENTS.load(Ordering::Relaxed)
 }
 
 pub fn get_event() -> Option<WaitEvent<u64>> {
@@ -109,8 +123,13 @@ impl<'a> WaitObserver<'a> {
             if q.cond.is_given() {
                 return;
             }
-            map.get(&q.q_type)
-                .map(|queue| queue.enqueue(id, q.cond.clone()));
+            if let Some(queue) = map.get(&q.q_type)
+                && queue.enqueue(id, q.cond.clone()).is_none()
+            {
+                // the queue could not take the task, thus nobody would wake it up.
+                // Returning without blocking is a spurious wakeup, which callers already handle
+                return;
+            }
         }
         drop(map);
         // at this point the task is enqueued, but not blocked.
@@ -154,8 +173,16 @@ pub enum QueueType {
     KeyBoard,
     Thread(ThreadID),
     Process(ProcessID),
+    /// state changes of any child of the process
+    Children(ProcessID),
     File(u64),
     Lock(u64),
+    /// output appended to a tty
+    TTYOutput,
+    /// threads blocked in threading::park
+    Park,
+    /// mounts and unmounts in any mount namespace
+    Mounts,
 }
 
 impl QueueType {
@@ -4,15 +4,20 @@ use core::{cmp::Reverse, fmt::Debug};
 use conquer_once::spin::OnceCell;
 
 use crate::{
-    arch::x86::current_time,
     eprintln,
-    kernel::threading::{task::ThreadID, tls, wait::condition::WaitCondition},
+    kernel::{
+        mem::alloc::TryPush,
+        threading::{task::ThreadID, tls, wait::condition::WaitCondition},
+        time::Instant,
+    },
     serial_println,
     sync::locks::Mutex,
 };
 
 pub static TIMERQUEUE: OnceCell<TimeWaitQueue> = OnceCell::uninit();
 pub static KEYBOARDQUEUE: OnceCell<KeyBoardQueue> = OnceCell::uninit();
+pub static PARKQUEUE: OnceCell<ParkQueue> = OnceCell::uninit();
+pub static MOUNTQUEUE: OnceCell<GenericWaitQueue> = OnceCell::uninit();
 
 pub(crate) trait WaitQueue {
     fn enqueue(&self, id: &ThreadID, condition: WaitCondition) -> Option<()>;
@@ -81,8 +86,8 @@ impl WaitQueue for TimeWaitQueue {
         };
         self.inner
             .lock()
-            .push(Reverse(WaitNode::new(*id, condition)));
-        Some(())
+            .try_push(Reverse(WaitNode::new(*id, condition)))
+            .ok()
     }
 
     fn signal(&self) {
@@ -92,7 +97,7 @@ impl WaitQueue for TimeWaitQueue {
                 id,
                 cond: WaitCondition::Time(t),
             }) = w
-            && *t <= current_time()
+            && *t <= Instant::now()
         {
             if tls::task_data().wake(id).is_none() {
                 eprintln!("could not wake up task with id {}", id);
@@ -123,8 +128,7 @@ impl KeyBoardQueue {
 impl WaitQueue for KeyBoardQueue {
     fn enqueue(&self, id: &ThreadID, condition: WaitCondition) -> Option<()> {
         let node = WaitNode::new(*id, condition);
-        self.q.lock().push_back(node);
-        Some(())
+        self.q.lock().try_push(node).ok()
     }
 
     fn signal(&self) {
@@ -155,8 +159,7 @@ impl GenericWaitQueue {
 impl WaitQueue for GenericWaitQueue {
     fn enqueue(&self, id: &ThreadID, condition: WaitCondition) -> Option<()> {
         let node = WaitNode::new(*id, condition);
-        self.q.lock().push_back(node);
-        Some(())
+        self.q.lock().try_push(node).ok()
     }
 
     fn signal(&self) {
@@ -175,3 +178,44 @@ impl Default for GenericWaitQueue {
         }
     }
 }
+
+/// threads blocked in threading::park. Unlike GenericWaitQueue, threads whose token is not set yet stay enqueued,
+/// as an unpark of one thread signals the queue of all of them
+pub struct ParkQueue {
+    q: Mutex<VecDeque<WaitNode>>,
+}
+
+impl ParkQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl WaitQueue for ParkQueue {
+    fn enqueue(&self, id: &ThreadID, condition: WaitCondition) -> Option<()> {
+        let mut q = self.q.lock();
+        // a thread woken spuriously parks again with the same node
+        q.retain(|node| node.id != *id);
+        q.try_push(WaitNode::new(*id, condition)).ok()
+    }
+
+    fn signal(&self) {
+        self.q.lock().retain(|node| {
+            if !node.cond.is_given() {
+                return true;
+            }
+            if tls::task_data().wake(&node.id).is_none() {
+                eprintln!("could not wake up task with id {}", node.id);
+            }
+            false
+        });
+    }
+}
+
+impl Default for ParkQueue {
+    fn default() -> Self {
+        Self {
+            q: Mutex::new(VecDeque::new()),
+        }
+    }
+}
@@ -0,0 +1,185 @@
+pub use core::time::Duration;
+use core::{
+    ops::{Add, AddAssign, Sub, SubAssign},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use os_macros::init_task;
+
+use crate::arch;
+
+/// arch::timestamp at boot
+static BOOT_CYCLES: AtomicU64 = AtomicU64::new(0);
+/// frequency of arch::timestamp, cached, as querying it is expensive. 0 until the clock is initialized
+static FREQUENCY: AtomicU64 = AtomicU64::new(0);
+/// the latest instant handed out, in ns since boot. The timestamps of different cpus may be slightly apart,
+/// thus each one is clamped to this, such that time never goes backwards
+static LATEST: AtomicU64 = AtomicU64::new(0);
+
+#[init_task(stage = "early")]
+fn init() {
+    BOOT_CYCLES.store(arch::timestamp(), Ordering::Relaxed);
+    FREQUENCY.store(arch::timestamp_frequency().max(1), Ordering::Release);
+}
+
+/// a point in time, measured by the monotonic clock of the kernel.
+/// It stands still at boot until the clock is initialized and never goes backwards afterwards
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    pub const BOOT: Self = Self(0);
+    /// an instant, which is never reached. Used for deadlines, which overflow
+    pub const MAX: Self = Self(u64::MAX);
+
+    pub fn now() -> Self {
+        let frequency = FREQUENCY.load(Ordering::Acquire);
+        if frequency == 0 {
+            return Self::BOOT;
+        }
+        let cycles = arch::timestamp().saturating_sub(BOOT_CYCLES.load(Ordering::Relaxed));
+        let nanos = (cycles as u128 * 1_000_000_000 / frequency as u128) as u64;
+        let latest = LATEST.fetch_max(nanos, Ordering::AcqRel);
+        Self(nanos.max(latest))
+    }
+
+    pub const fn from_nanos(nanos: u64) -> Self {
+        Self(nanos)
+    }
+
+    /// the nanoseconds since boot
+    pub const fn as_nanos(&self) -> u64 {
+        self.0
+    }
+
+    pub const fn since_boot(&self) -> Duration {
+        Duration::from_nanos(self.0)
+    }
+
+    /// the time from earlier to self, 0 if earlier is later
+    pub fn duration_since(&self, earlier: Self) -> Duration {
+        self.checked_duration_since(earlier).unwrap_or_default()
+    }
+
+    pub fn checked_duration_since(&self, earlier: Self) -> Option<Duration> {
+        self.0.checked_sub(earlier.0).map(Duration::from_nanos)
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Self::now().duration_since(*self)
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<Self> {
+        u64::try_from(duration.as_nanos())
+            .ok()
+            .and_then(|nanos| self.0.checked_add(nanos))
+            .map(Self)
+    }
+
+    pub fn checked_sub(&self, duration: Duration) -> Option<Self> {
+        u64::try_from(duration.as_nanos())
+            .ok()
+            .and_then(|nanos| self.0.checked_sub(nanos))
+            .map(Self)
+    }
+}
+
+/// saturates at Instant::MAX, such that a huge timeout never expires
+impl Add<Duration> for Instant {
+    type Output = Self;
+
+    fn add(self, rhs: Duration) -> Self::Output {
+        self.checked_add(rhs).unwrap_or(Self::MAX)
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, rhs: Duration) {
+        *self = *self + rhs;
+    }
+}
+
+/// saturates at Instant::BOOT
+impl Sub<Duration> for Instant {
+    type Output = Self;
+
+    fn sub(self, rhs: Duration) -> Self::Output {
+        self.checked_sub(rhs).unwrap_or(Self::BOOT)
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, rhs: Duration) {
+        *self = *self - rhs;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Instant) -> Self::Output {
+        self.duration_since(rhs)
+    }
+}
+
+/// the time since boot
+pub fn uptime() -> Duration {
+    Instant::now().since_boot()
+}
+
+/// the arch::timestamp, at which Instant::BOOT lies. Lets clocks outside of the kernel, like the one of the vDSO, agree with Instant
+pub fn boot_timestamp() -> u64 {
+    BOOT_CYCLES.load(Ordering::Relaxed)
+}
+
+/// the frequency of arch::timestamp in Hz
+pub fn timestamp_frequency() -> u64 {
+    FREQUENCY.load(Ordering::Relaxed).max(1)
+}
+
+/// converts a difference of arch::timestamp values
+pub fn cycles_to_duration(cycles: u64) -> Duration {
+    Duration::from_nanos((cycles as u128 * 1_000_000_000 / timestamp_frequency() as u128) as u64)
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+    use crate::kernel::threading::yield_now;
+
+    #[kernel_test]
+    fn monotonic() {
+        let mut last = Instant::now();
+        assert!(last > Instant::BOOT);
+        for i in 0..1000 {
+            if i % 100 == 0 {
+                // possibly moves to another cpu
+                yield_now();
+            }
+            let now = Instant::now();
+            assert!(now >= last);
+            last = now;
+        }
+        assert!(uptime() >= last.since_boot());
+        assert_eq!(
+            cycles_to_duration(FREQUENCY.load(Ordering::Relaxed)),
+            Duration::from_secs(1)
+        );
+    }
+
+    #[kernel_test]
+    fn arithmetic() {
+        let start = Instant::from_nanos(1_000);
+        let later = start + Duration::from_micros(2);
+        assert_eq!(later.as_nanos(), 3_000);
+        assert_eq!(later - start, Duration::from_micros(2));
+        assert_eq!(start - later, Duration::ZERO);
+        assert_eq!(start.checked_duration_since(later), None);
+        assert_eq!(later - Duration::from_micros(2), start);
+        assert_eq!(start - Duration::from_secs(1), Instant::BOOT);
+        assert_eq!(start + Duration::MAX, Instant::MAX);
+        assert_eq!(start.checked_add(Duration::MAX), None);
+    }
+}
@@ -5,6 +5,7 @@
 #![feature(stmt_expr_attributes)]
 #![feature(str_from_raw_parts)]
 #![feature(box_into_inner)]
+#![feature(allocator_api)]
 #![allow(
     unreachable_code,
     unused_doc_comments,
@@ -29,6 +30,8 @@ cfg_if! {
             common::{get_kernel_tests, KernelTest},
             drivers::start_drivers,
             kernel::{
+                reboot::{self, RebootReason},
+                shutdown,
                 threading::{
                     self,
                     ProcessReturn,
@@ -48,6 +51,7 @@ cfg_if! {
 use cfg_if::cfg_if;
 use os_macros::kernel_test;
 use thiserror::Error;
+use tinyos_abi::types::SysErrCode;
 pub use utils::*;
 
 use crate::kernel::{io::IOError, threading::ThreadingError};
@@ -71,7 +75,11 @@ struct TestLogger {}
 #[cfg(feature = "test_run")]
 impl tiny_os_common::logging::Logger for TestLogger {
     fn log(&self, msg: ::core::fmt::Arguments) {
-        serial_print!("{}", msg);
+        // test output is never rate limited
+        crate::kernel::devices::tty::io::__serial_stub(format_args!(
+            "\x1b[34m[KINFO]\x1b[0m {}",
+            msg
+        ));
     }
 }
 
@@ -115,10 +123,7 @@ extern "C" fn kernel_test_runner() -> ProcessReturn {
     let mut tests_failed = false;
     let max_len = tests.iter().map(|t| t.name().len()).max().unwrap_or(0);
     for test in tests {
-        use crate::{
-            arch::x86::current_time,
-            kernel::{fd::FileHandle, threading::spawn_fn_with_init},
-        };
+        use crate::kernel::{fd::FileHandle, threading::spawn_fn_with_init, time::Instant};
 
         let dots = ".".repeat(max_len - test.name().len() + 3);
         print!("{}{} ", test.name(), dots);
@@ -148,9 +153,9 @@ extern "C" fn kernel_test_runner() -> ProcessReturn {
             continue;
         };
 
-        let start_time = current_time();
+        let start_time = Instant::now();
         match handle.wait_while(|handle| {
-            let now = current_time();
+            let now = Instant::now();
             if now - start_time >= MAX_TEST_TIME {
                 arch::interrupt::without_interrupts(|| {
                     print!("\x1b[31m[TASK TIMEOUT] \x1b[0m");
@@ -182,12 +187,15 @@ extern "C" fn kernel_test_runner() -> ProcessReturn {
     }
     // to allow background threads to clean up remaining resources
     threading::yield_now();
-    exit_qemu(if tests_failed {
+    if !tests_failed && reboot::soaking() {
+        println!("soak: run {} passed, rebooting", reboot::resets());
+        reboot::reboot(RebootReason::TestRun);
+    }
+    shutdown::poweroff(if tests_failed {
         QemuExitCode::Failed
     } else {
         QemuExitCode::Success
-    });
-    0
+    })
 }
 
 #[cfg(feature = "test_run")]
@@ -228,6 +236,16 @@ pub enum KernelError {
     Unexpected(&'static str),
 }
 
+impl From<KernelError> for SysErrCode {
+    fn from(err: KernelError) -> Self {
+        match err {
+            KernelError::IO(err) => err.into(),
+            KernelError::Threading(err) => err.into(),
+            KernelError::Unexpected(_) => Self::Cancelled,
+        }
+    }
+}
+
 #[kernel_test(should_panic, silent)]
 fn should_panic_err() {
     // works
@@ -19,7 +19,6 @@ use tiny_os::{
         self,
         hcf,
         interrupt::{self, enable_threading_interrupts},
-        x86::current_time,
     },
     bootinfo,
     cross_println,
@@ -27,14 +26,18 @@ use tiny_os::{
     eprintln,
     kernel::{
         self,
+        flight,
         init,
+        reboot::{self, BootMode, RebootReason},
         threading::{
             self,
+            fault,
             schedule::{Scheduler, add_named_ktask, current_task, get_scheduler},
             task::TaskRepr,
             tls,
             wait::{QueuTypeCondition, QueueType, condition::WaitCondition},
         },
+        time::{self, Instant},
     },
     serial_println,
     term,
@@ -76,23 +79,30 @@ extern "C" fn chore() -> usize {
 
     cross_println!("startup tasks started");
 
-    init::default_task().unwrap();
-
-    serial_println!("default bins started");
+    match reboot::boot_mode() {
+        BootMode::Recovery => cross_println!("recovery boot, the default bins are not started"),
+        mode => {
+            if mode == BootMode::TestRun {
+                serial_println!("the kernel tests are not built in, booting normally");
+            }
+            init::default_task().unwrap();
+            serial_println!("default bins started");
+        }
+    }
 
     get_scheduler().reschedule();
 
     serial_println!("entering idle loop...");
 
     loop {
-        serial_println!("idle, time: {:?}", current_time());
+        serial_println!("idle, uptime: {:?}", time::uptime());
         // cleanup any dead tasks and reschedule active tasks.
         // TODO We may want to do this more often and at different intervals
         // tls::task_data().cleanup();
         // get_scheduler().reschedule();
         let conditions = &[QueuTypeCondition::with_cond(
             QueueType::Timer,
-            WaitCondition::Time(Duration::from_secs(5) + current_time()),
+            WaitCondition::Time(Instant::now() + Duration::from_secs(5)),
         )];
         wait_manager::add_wait(&tls::task_data().current_tid(), conditions);
         threading::yield_now();
@@ -116,7 +126,15 @@ fn rust_panic(info: &core::panic::PanicInfo) -> ! {
     #[cfg(feature = "test_run")]
     tiny_os::test_panic_handler(info);
 
-    eprintln!("panic: {:#?}", info);
+    if !fault::report_arithmetic_panic(info) {
+        eprintln!("panic: {:#?}", info);
+    }
+    flight::dump();
+    if reboot::soaking() {
+        reboot::reboot(RebootReason::Panic);
+    }
+    // a reset after this boots into the recovery shell
+    reboot::set_reason(RebootReason::Panic);
 
     if let Some(task) = tls::task_data().current_thread() {
         eprintln!(
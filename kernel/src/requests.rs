@@ -3,9 +3,12 @@ use limine::{
     paging,
     request::{
         BootTimeRequest,
+        ExecutableCmdlineRequest,
+        ExecutableFileRequest,
         FramebufferRequest,
         HhdmRequest,
         MemoryMapRequest,
+        ModuleRequest,
         PagingModeRequest,
         RequestsEndMarker,
         RequestsStartMarker,
@@ -60,6 +63,18 @@ pub static RSDP_REQUEST: RsdpRequest = RsdpRequest::new();
 #[unsafe(link_section = ".requests")]
 pub static BOOT_TIME_REQUEST: BootTimeRequest = BootTimeRequest::new();
 
+#[used]
+#[unsafe(link_section = ".requests")]
+pub static MODULE_REQUEST: ModuleRequest = ModuleRequest::new();
+
+#[used]
+#[unsafe(link_section = ".requests")]
+pub static CMDLINE_REQUEST: ExecutableCmdlineRequest = ExecutableCmdlineRequest::new();
+
+#[used]
+#[unsafe(link_section = ".requests")]
+pub static EXECUTABLE_FILE_REQUEST: ExecutableFileRequest = ExecutableFileRequest::new();
+
 // #[used]
 // #[unsafe(link_section = ".requests")]
 // pub static BOOT_DATE_REQUEST: DateAtBootRequest = DateAtBootRequest::new();
@@ -0,0 +1,439 @@
+use alloc::{
+    collections::vec_deque::VecDeque,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt::Write;
+
+const HISTORY_LEN: usize = 64;
+const KILL_RING_LEN: usize = 8;
+
+const ESC: u8 = 0x1b;
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7f;
+
+// ctrl + letter, as decoded with HandleControl::MapLettersToUnicode
+const fn ctrl(letter: u8) -> u8 {
+    letter - b'a' + 1
+}
+const CTRL_A: u8 = ctrl(b'a');
+const CTRL_B: u8 = ctrl(b'b');
+const CTRL_D: u8 = ctrl(b'd');
+const CTRL_E: u8 = ctrl(b'e');
+const CTRL_F: u8 = ctrl(b'f');
+const CTRL_K: u8 = ctrl(b'k');
+const CTRL_N: u8 = ctrl(b'n');
+const CTRL_P: u8 = ctrl(b'p');
+const CTRL_U: u8 = ctrl(b'u');
+const CTRL_W: u8 = ctrl(b'w');
+const CTRL_Y: u8 = ctrl(b'y');
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    #[default]
+    None,
+    /// ESC was read
+    Start,
+    /// ESC [ or ESC O was read, followed by the numeric parameter so far
+    Seq(u16),
+}
+
+/// the last edit, which decides whether a kill appends to the previous one and whether a yank may be rotated
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum LastEdit {
+    #[default]
+    Other,
+    Kill,
+    /// the text yanked, as a range of chars in the line
+    Yank(usize, usize),
+}
+
+/// a line editor in the style of readline, which is fed the bytes read from a tty and answers with the bytes to echo.
+/// Understands the arrow, home, end and delete keys as sent by the keyboard driver and the emacs bindings:
+/// ctrl + a/e/b/f move, ctrl + d deletes, ctrl + k/u/w kill into the kill ring, ctrl + y yanks from it and esc y
+/// rotates the yanked text, ctrl + p/n and the arrows browse the history.
+/// The echo assumes, that the line fits into a single row of the terminal, starting at the position of the cursor
+/// when the line was begun
+#[derive(Debug, Default)]
+pub struct LineEditor {
+    line: Vec<char>,
+    cursor: usize,
+    history: VecDeque<String>,
+    /// the entry of the history shown, history.len() is the line being edited
+    browsing: usize,
+    /// the line being edited, while the history is browsed
+    stash: Vec<char>,
+    /// the most recent kill is at the front
+    kill_ring: VecDeque<String>,
+    /// the kill ring entry yanked last
+    yanked: usize,
+    last: LastEdit,
+    escape: Escape,
+    /// the bytes of a utf-8 char, which is not complete yet
+    partial: Vec<u8>,
+}
+
+impl LineEditor {
+    pub const fn new() -> Self {
+        Self {
+            line: Vec::new(),
+            cursor: 0,
+            history: VecDeque::new(),
+            browsing: 0,
+            stash: Vec::new(),
+            kill_ring: VecDeque::new(),
+            yanked: 0,
+            last: LastEdit::Other,
+            escape: Escape::None,
+            partial: Vec::new(),
+        }
+    }
+
+    /// the line being edited
+    pub fn line(&self) -> String {
+        self.line.iter().collect()
+    }
+
+    /// the position of the cursor in the line, in chars
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// the entered lines, oldest first
+    pub fn history(&self) -> impl Iterator<Item = &str> {
+        self.history.iter().map(String::as_str)
+    }
+
+    /// feeds a single byte of input and appends what needs to be echoed to echo.
+    /// Returns the line, without the newline, once it is complete
+    pub fn feed(&mut self, byte: u8, echo: &mut String) -> Option<String> {
+        match self.escape {
+            Escape::None => {}
+            Escape::Start => {
+                self.escape = Escape::None;
+                match byte {
+                    b'[' | b'O' => self.escape = Escape::Seq(0),
+                    b'y' => self.yank_pop(echo),
+                    _ => self.last = LastEdit::Other,
+                }
+                return None;
+            }
+            Escape::Seq(param) => {
+                self.escape = Escape::None;
+                if !matches!(byte, b'0'..=b'9' | b';') {
+                    self.last = LastEdit::Other;
+                }
+                match byte {
+                    b'0'..=b'9' => {
+                        self.escape = Escape::Seq(
+                            param
+                                .saturating_mul(10)
+                                .saturating_add((byte - b'0') as u16),
+                        )
+                    }
+                    b';' => self.escape = Escape::Seq(0),
+                    b'A' => self.browse(-1, echo),
+                    b'B' => self.browse(1, echo),
+                    b'C' => self.move_to(self.cursor + 1, echo),
+                    b'D' => self.move_to(self.cursor.saturating_sub(1), echo),
+                    b'H' => self.move_to(0, echo),
+                    b'F' => self.move_to(self.line.len(), echo),
+                    b'~' => match param {
+                        1 | 7 => self.move_to(0, echo),
+                        3 => self.delete_forward(echo),
+                        4 | 8 => self.move_to(self.line.len(), echo),
+                        // page up/down, insert and the function keys
+                        _ => {}
+                    },
+                    _ => {}
+                }
+                return None;
+            }
+        }
+        if byte == ESC {
+            // the yank is kept for esc y
+            self.escape = Escape::Start;
+            return None;
+        }
+        let edit = self.last;
+        self.last = LastEdit::Other;
+        match byte {
+            b'\n' | b'\r' => return Some(self.finish(echo)),
+            BACKSPACE | DELETE => {
+                if self.cursor > 0 {
+                    let old = self.cursor;
+                    self.line.remove(self.cursor - 1);
+                    self.cursor -= 1;
+                    self.redraw(old, echo);
+                }
+            }
+            CTRL_A => self.move_to(0, echo),
+            CTRL_E => self.move_to(self.line.len(), echo),
+            CTRL_B => self.move_to(self.cursor.saturating_sub(1), echo),
+            CTRL_F => self.move_to(self.cursor + 1, echo),
+            CTRL_D => self.delete_forward(echo),
+            CTRL_P => self.browse(-1, echo),
+            CTRL_N => self.browse(1, echo),
+            CTRL_K => self.kill(self.cursor, self.line.len(), edit, echo),
+            CTRL_U => self.kill(0, self.cursor, edit, echo),
+            CTRL_W => self.kill(self.word_start(), self.cursor, edit, echo),
+            CTRL_Y => {
+                self.yanked = 0;
+                if let Some(text) = self.kill_ring.front().cloned() {
+                    let start = self.cursor;
+                    self.insert(&text, echo);
+                    self.last = LastEdit::Yank(start, self.cursor);
+                }
+            }
+            b if b.is_ascii_control() => {}
+            b => {
+                self.partial.push(b);
+                match core::str::from_utf8(&self.partial) {
+                    Ok(s) => {
+                        let s = s.to_string();
+                        self.partial.clear();
+                        self.insert(&s, echo);
+                    }
+                    // invalid input is dropped, an incomplete char is kept until the next byte
+                    Err(e) if e.error_len().is_some() => self.partial.clear(),
+                    Err(_) => {}
+                }
+            }
+        }
+        None
+    }
+
+    fn finish(&mut self, echo: &mut String) -> String {
+        let line = self.line();
+        self.move_to(self.line.len(), echo);
+        echo.push('\n');
+        if !line.trim().is_empty() && self.history.back() != Some(&line) {
+            if self.history.len() == HISTORY_LEN {
+                self.history.pop_front();
+            }
+            self.history.push_back(line.clone());
+        }
+        self.line.clear();
+        self.stash.clear();
+        self.cursor = 0;
+        self.browsing = self.history.len();
+        line
+    }
+
+    fn insert(&mut self, text: &str, echo: &mut String) {
+        let old = self.cursor;
+        let at_end = self.cursor == self.line.len();
+        for c in text.chars() {
+            self.line.insert(self.cursor, c);
+            self.cursor += 1;
+        }
+        if at_end {
+            echo.push_str(text);
+        } else {
+            self.redraw(old, echo);
+        }
+    }
+
+    fn delete_forward(&mut self, echo: &mut String) {
+        if self.cursor < self.line.len() {
+            self.line.remove(self.cursor);
+            self.redraw(self.cursor, echo);
+        }
+    }
+
+    fn move_to(&mut self, cursor: usize, echo: &mut String) {
+        let cursor = cursor.min(self.line.len());
+        if cursor < self.cursor {
+            _ = write!(echo, "\x1b[{}D", self.cursor - cursor);
+        } else if cursor > self.cursor {
+            _ = write!(echo, "\x1b[{}C", cursor - self.cursor);
+        }
+        self.cursor = cursor;
+    }
+
+    /// the start of the word before the cursor, skipping the whitespace in between
+    fn word_start(&self) -> usize {
+        let before = &self.line[..self.cursor];
+        let end = before
+            .iter()
+            .rposition(|c| !c.is_whitespace())
+            .map_or(0, |i| i + 1);
+        before[..end]
+            .iter()
+            .rposition(|c| c.is_whitespace())
+            .map_or(0, |i| i + 1)
+    }
+
+    /// removes the chars in start..end into the kill ring. Consecutive kills are merged into a single entry
+    fn kill(&mut self, start: usize, end: usize, last: LastEdit, echo: &mut String) {
+        self.last = LastEdit::Kill;
+        if start == end {
+            return;
+        }
+        let old = self.cursor;
+        let killed = self.line.drain(start..end).collect::<String>();
+        self.cursor = start;
+        if last == LastEdit::Kill
+            && let Some(previous) = self.kill_ring.front_mut()
+        {
+            // killing backwards prepends
+            if end == old {
+                previous.insert_str(0, &killed);
+            } else {
+                previous.push_str(&killed);
+            }
+        } else {
+            if self.kill_ring.len() == KILL_RING_LEN {
+                self.kill_ring.pop_back();
+            }
+            self.kill_ring.push_front(killed);
+        }
+        self.redraw(old, echo);
+    }
+
+    /// replaces the text just yanked with the next older entry of the kill ring
+    fn yank_pop(&mut self, echo: &mut String) {
+        let LastEdit::Yank(start, end) = self.last else {
+            return;
+        };
+        if self.kill_ring.len() < 2 {
+            return;
+        }
+        self.yanked = (self.yanked + 1) % self.kill_ring.len();
+        let old = self.cursor;
+        let text = self.kill_ring[self.yanked].clone();
+        let tail = self.line.split_off(end);
+        self.line.truncate(start);
+        self.line.extend(text.chars());
+        self.line.extend(tail);
+        self.cursor = start + text.chars().count();
+        self.last = LastEdit::Yank(start, self.cursor);
+        self.redraw(old, echo);
+    }
+
+    /// shows the history entry offset entries away from the one shown
+    fn browse(&mut self, offset: isize, echo: &mut String) {
+        let Some(target) = self
+            .browsing
+            .checked_add_signed(offset)
+            .filter(|target| *target <= self.history.len())
+        else {
+            return;
+        };
+        if self.browsing == self.history.len() {
+            self.stash = core::mem::take(&mut self.line);
+        }
+        self.browsing = target;
+        let old = self.cursor;
+        self.line = match self.history.get(target) {
+            Some(entry) => entry.chars().collect(),
+            None => core::mem::take(&mut self.stash),
+        };
+        self.cursor = self.line.len();
+        self.redraw(old, echo);
+    }
+
+    /// redraws the line after an edit, where old was the column of the cursor before it
+    fn redraw(&self, old: usize, echo: &mut String) {
+        if old > 0 {
+            _ = write!(echo, "\x1b[{}D", old);
+        }
+        echo.extend(self.line.iter());
+        echo.push_str("\x1b[K");
+        if self.line.len() > self.cursor {
+            _ = write!(echo, "\x1b[{}D", self.line.len() - self.cursor);
+        }
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    fn feed(editor: &mut LineEditor, input: &[u8]) -> Option<String> {
+        let mut echo = String::new();
+        input
+            .iter()
+            .fold(None, |line, byte| line.or(editor.feed(*byte, &mut echo)))
+    }
+
+    #[kernel_test]
+    fn cursor_movement() {
+        let mut editor = LineEditor::new();
+        let mut echo = String::new();
+        for byte in b"held" {
+            editor.feed(*byte, &mut echo);
+        }
+        assert_eq!(echo, "held");
+        feed(&mut editor, b"\x1b[D\x1b[Dl");
+        assert_eq!((editor.line().as_str(), editor.cursor()), ("helld", 3));
+        feed(&mut editor, &[CTRL_A, CTRL_D, b'H', CTRL_E]);
+        assert_eq!((editor.line().as_str(), editor.cursor()), ("Helld", 5));
+        feed(&mut editor, b"\x1b[D\x1b[3~o\x08");
+        assert_eq!(editor.line(), "Hell");
+        assert_eq!(feed(&mut editor, b"o\r").as_deref(), Some("Hello"));
+        assert_eq!((editor.line().as_str(), editor.cursor()), ("", 0));
+    }
+
+    #[kernel_test]
+    fn history() {
+        let mut editor = LineEditor::new();
+        feed(&mut editor, b"one\ntwo\n\ntwo\n");
+        assert_eq!(editor.history().collect::<Vec<_>>(), ["one", "two"]);
+
+        feed(&mut editor, b"thr");
+        feed(&mut editor, b"\x1b[A\x1b[A\x1b[A");
+        assert_eq!(editor.line(), "one");
+        feed(&mut editor, &[CTRL_N]);
+        assert_eq!(editor.line(), "two");
+        // back to the line being edited
+        feed(&mut editor, b"\x1b[B\x1b[B");
+        assert_eq!(feed(&mut editor, b"ee\n").as_deref(), Some("three"));
+        assert_eq!(feed(&mut editor, b"\x1b[A\n").as_deref(), Some("three"));
+    }
+
+    #[kernel_test]
+    fn kill_ring() {
+        let mut editor = LineEditor::new();
+        feed(&mut editor, b"cat  /proc/tasks");
+        // consecutive kills merge into one entry
+        feed(&mut editor, &[CTRL_W, CTRL_W]);
+        assert_eq!(editor.line(), "");
+        feed(&mut editor, b"ls ");
+        feed(&mut editor, &[CTRL_Y]);
+        assert_eq!(editor.line(), "ls cat  /proc/tasks");
+
+        feed(&mut editor, &[CTRL_U, CTRL_A]);
+        feed(&mut editor, b"abc def");
+        feed(&mut editor, &[CTRL_W, CTRL_A, CTRL_K]);
+        assert_eq!(
+            editor.kill_ring,
+            ["abc ", "def", "ls cat  /proc/tasks", "cat  /proc/tasks"]
+        );
+        feed(&mut editor, &[CTRL_Y]);
+        assert_eq!(editor.line(), "abc ");
+        // replaces the yanked text with older kills
+        feed(&mut editor, b"\x1by");
+        assert_eq!(editor.line(), "def");
+        feed(&mut editor, b"\x1by\x1by");
+        assert_eq!(editor.line(), "cat  /proc/tasks");
+        feed(&mut editor, b"\x1by");
+        assert_eq!(editor.line(), "abc ");
+        // only right after a yank
+        feed(&mut editor, b"x\x1by");
+        assert_eq!(editor.line(), "abc x");
+    }
+
+    #[kernel_test]
+    fn utf8_input() {
+        let mut editor = LineEditor::new();
+        assert_eq!(
+            feed(&mut editor, "grüße\x08\n".as_bytes()).as_deref(),
+            Some("grüß")
+        );
+        // an invalid byte is dropped
+        assert_eq!(feed(&mut editor, b"a\xffb\n").as_deref(), Some("ab"));
+    }
+}
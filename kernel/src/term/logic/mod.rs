@@ -1 +1 @@
-
+pub mod editor;
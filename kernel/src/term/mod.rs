@@ -1,5 +1,6 @@
 #![allow(dead_code)]
 
+use alloc::string::String;
 use core::fmt::{Arguments, Write};
 
 use conquer_once::spin::OnceCell;
@@ -14,7 +15,7 @@ use crate::{
     sync::locks::Mutex,
 };
 
-mod logic;
+pub mod logic;
 mod parse;
 mod render;
 
@@ -60,3 +61,20 @@ pub fn _print(args: Arguments) {
     // SAFETY must make sure that this is not calles prior to init_term()
     unsafe { _ = write!(FOOBAR.get_unchecked().lock(), "{}", args) }
 }
+
+/// moves the head of the terminal selection by dx cols and dy rows, starting a new selection at the cursor if there is none.
+/// Returns the selected text
+pub fn move_selection(dx: isize, dy: isize) -> Option<String> {
+    let mut term = FOOBAR.get()?.lock();
+    let backend = term.backend;
+    term.move_selection(dx, dy, &mut backend.lock());
+    term.selected_text()
+}
+
+pub fn clear_selection() {
+    if let Some(term) = FOOBAR.get() {
+        let mut term = term.lock();
+        let backend = term.backend;
+        term.clear_selection(&mut backend.lock());
+    }
+}
@@ -1,25 +1,27 @@
 #![allow(dead_code, unused_variables)]
 #![cfg_attr(feature = "test_run", allow(static_mut_refs))]
 
+use alloc::{string::String, vec::Vec};
 use core::{
     fmt::{Debug, Write},
     ops::{Add, Range},
 };
 
 use embedded_graphics::{
-    mono_font::{MonoTextStyle, MonoTextStyleBuilder, ascii},
+    mono_font::{MonoTextStyle, MonoTextStyleBuilder, iso_8859_1},
     prelude::{DrawTarget, Point, Size},
     primitives::Rectangle,
-    text::Baseline,
+    text::{Baseline, renderer::TextRenderer},
 };
 use os_macros::kernel_test;
 use thiserror::Error;
 
 use crate::{
     kernel::graphics::{
+        BlitTarget,
         GraphicsError,
         colors::{ColorCode, RGBColor},
-        text::CharRenderer,
+        framebuffers::{BoundingBox, FrameBuffer, ShiftedFrameBuffer},
     },
     sync::locks::Mutex,
 };
@@ -172,26 +174,20 @@ impl<const X: usize, const Y: usize> TermCharBuffer<X, Y> {
         self.clear_line(&TermPixel { inner: Y - 1 });
     }
 
-    fn shift_up_and_redraw<B>(&mut self, gfx: &mut B, style: &MonoTextStyle<'_, RGBColor>)
+    fn shift_up_and_redraw<B>(&mut self, gfx: &mut B)
     where
-        B: DrawTarget<Color = RGBColor, Error = GraphicsError>,
+        B: DrawTarget<Color = RGBColor, Error = GraphicsError> + BlitTarget + FrameBuffer,
     {
-        for row in 0..Y - 1 {
-            let pixel = TermPixel { inner: row };
-            let range1 = self.get_range_from_row(&pixel);
-            let range2 = self.get_range_from_row(&TermPixel { inner: row + 1 });
-            let range = if range1.len() > range2.len() {
-                range1
-            } else {
-                range2
-            };
-            self.inner[row] = self.inner[row + 1];
-            self.redraw_row_with_range(&pixel, gfx, style, range);
-        }
-        self.clear_line(&TermPixel { inner: Y - 1 });
+        self.shift_up();
+        // the text region is moved up by one line in place, thus only the exposed line is drawn
+        let area = BoundingBox {
+            x: 0,
+            y: 0,
+            width: (X * CHAR_WIDTH).min(gfx.width()),
+            height: (Y - 1) * CHAR_HEIGHT,
+        };
+        gfx.copy_rect(&area, &ShiftedFrameBuffer::new(&*gfx, CHAR_HEIGHT));
         self.redraw_empty_row(&TermPixel { inner: Y - 1 }, gfx);
-        // self.shift_up();
-        // self.redraw(&mut TermPosition::new(0, 0, X, Y), gfx, style);
     }
 
     fn shift_down(&mut self) {
@@ -247,14 +243,26 @@ impl<const X: usize, const Y: usize> TermCharBuffer<X, Y> {
     ) where
         B: DrawTarget<Color = RGBColor, Error = GraphicsError>,
     {
+        let mut range = range;
+        // the right half of a wide char is drawn together with its left half
+        if range.start > 0
+            && range.start < range.end
+            && self.inner[row.inner][range.start] == Some(text::CONTINUATION)
+        {
+            range.start -= 1;
+        }
         for col in range {
-            _ = style.draw_char(
-                self.inner[row.inner][col].unwrap_or(' '),
+            let c = self.inner[row.inner][col].unwrap_or(' ');
+            if c == text::CONTINUATION {
+                continue;
+            }
+            text::draw_cell(
+                c,
                 Point::new(
                     TermPixel { inner: col }.as_ipixel(CHAR_WIDTH),
                     row.as_ipixel(CHAR_HEIGHT),
                 ),
-                Baseline::Top,
+                style,
                 gfx,
             );
         }
@@ -279,15 +287,11 @@ impl<const X: usize, const Y: usize> TermCharBuffer<X, Y> {
             cursor.row.inner = y;
             for x in 0..X {
                 cursor.col.inner = x;
-                match self.inner[y][x] {
-                    None => {
-                        // assuming all cols up to the last filled one are filled, ie ch, ch, None, None, None, ...
-                        // this simply optimizes the loop slightly
-                        break;
-                    }
-                    Some(c) => {
-                        _ = style.draw_char(c, (*cursor).into(), Baseline::Top, gfx);
-                    }
+                // rows may have gaps, as the cursor can be positioned freely
+                if let Some(c) = self.inner[y][x]
+                    && c != text::CONTINUATION
+                {
+                    text::draw_cell(c, (*cursor).into(), style, gfx);
                 }
             }
         }
@@ -331,6 +335,26 @@ impl<const X: usize, const Y: usize> TermCharBuffer<X, Y> {
         })
     }
 
+    /// stores c, which takes width cells, at cursor. Wide chars, which are partly overwritten, are removed entirely.
+    /// Returns the cols of the removed halves, which need to be redrawn
+    fn put(&mut self, c: char, width: usize, cursor: &TermPosition) -> [Option<usize>; 2] {
+        let row = &mut self.inner[cursor.row.inner];
+        let start = cursor.col.inner;
+        let end = (start + width).min(X);
+        let mut orphans = [None; 2];
+        if start > 0 && row[start] == Some(text::CONTINUATION) {
+            row[start - 1] = None;
+            orphans[0] = Some(start - 1);
+        }
+        if end < X && row[end] == Some(text::CONTINUATION) {
+            row[end] = None;
+            orphans[1] = Some(end);
+        }
+        row[start] = Some(c);
+        row[start + 1..end].fill(Some(text::CONTINUATION));
+        orphans
+    }
+
     fn is_empty(&self) -> bool {
         !self
             .inner
@@ -339,19 +363,49 @@ impl<const X: usize, const Y: usize> TermCharBuffer<X, Y> {
     }
 }
 
+const MAX_PARAMS: usize = 4;
+
+/// an escape sequence, which is not complete yet. It is kept across writes, as a sequence may be split between them
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct EscapeSeq {
+    /// ESC [ was read, ie this is a control sequence
+    csi: bool,
+    /// the parameters are prefixed with ?
+    private: bool,
+    params: [u16; MAX_PARAMS],
+    /// index of the parameter being read
+    current: usize,
+}
+
+impl EscapeSeq {
+    /// parameter i, where 0 or a missing parameter is replaced by default
+    fn param(&self, i: usize, default: usize) -> usize {
+        match self.params.get(i) {
+            Some(0) | None => default,
+            Some(p) => *p as usize,
+        }
+    }
+}
+
 pub struct BasicTermRender<'a, B, const X: usize, const Y: usize>
 where
-    B: DrawTarget<Color = RGBColor, Error = GraphicsError> + 'a,
+    B: DrawTarget<Color = RGBColor, Error = GraphicsError> + BlitTarget + FrameBuffer + 'a,
 {
-    backend: &'a Mutex<B>,
+    pub(super) backend: &'a Mutex<B>,
     cursor: TermPosition,
     str_style: MonoTextStyle<'a, RGBColor>,
+    selected_style: MonoTextStyle<'a, RGBColor>,
     buffer: &'a mut TermCharBuffer<X, Y>,
+    /// anchor and head of the selection, as (row, col)
+    selection: Option<((usize, usize), (usize, usize))>,
+    escape: Option<EscapeSeq>,
+    /// the main screen and its cursor, while the alternate screen is shown
+    saved_screen: Option<(Vec<[Option<char>; X]>, TermPosition)>,
 }
 
 impl<'a, B, const X: usize, const Y: usize> BasicTermRender<'a, B, X, Y>
 where
-    B: DrawTarget<Color = RGBColor, Error = GraphicsError>,
+    B: DrawTarget<Color = RGBColor, Error = GraphicsError> + BlitTarget + FrameBuffer,
 {
     pub(super) fn new(gfx: &'a Mutex<B>, buffer: &'a mut TermCharBuffer<X, Y>) -> Self {
         let bounds = { gfx.lock().bounding_box() };
@@ -364,21 +418,220 @@ where
                 (bounds.size.height as usize) / CHAR_HEIGHT, // y
             ),
             str_style: MonoTextStyleBuilder::new()
-                .font(&ascii::FONT_10X20)
+                .font(&iso_8859_1::FONT_10X20)
                 .background_color(ColorCode::Black.into())
                 .text_color(ColorCode::White.into())
                 .build(),
+            selected_style: MonoTextStyleBuilder::new()
+                .font(&iso_8859_1::FONT_10X20)
+                .background_color(ColorCode::White.into())
+                .text_color(ColorCode::Black.into())
+                .build(),
             buffer,
+            selection: None,
+            escape: None,
+            saved_screen: None,
         }
     }
 
-    pub(super) fn line_clear(&mut self) {
-        self.buffer.clear_line(&self.cursor.row);
+    /// feeds c to the pending escape sequence and runs it once complete
+    fn feed_escape(&mut self, c: char, gfx: &mut B) {
+        let Some(seq) = self.escape.as_mut() else {
+            return;
+        };
+        if !seq.csi {
+            // only control sequences are supported, others are dropped
+            if c == '[' {
+                seq.csi = true;
+            } else {
+                self.escape = None;
+            }
+            return;
+        }
+        match c {
+            '?' if seq.current == 0 && seq.params[0] == 0 => seq.private = true,
+            '0'..='9' => {
+                if let Some(param) = seq.params.get_mut(seq.current) {
+                    *param = param
+                        .saturating_mul(10)
+                        .saturating_add(c as u16 - '0' as u16);
+                }
+            }
+            ';' => seq.current += 1,
+            '\x40'..='\x7e' => {
+                let seq = *seq;
+                self.escape = None;
+                self.run_csi(&seq, c, gfx);
+            }
+            // malformed
+            _ => self.escape = None,
+        }
+    }
+
+    fn run_csi(&mut self, seq: &EscapeSeq, action: char, gfx: &mut B) {
+        let row = self.cursor.row.inner;
+        let col = self.cursor.col.inner;
+        match (seq.private, action) {
+            (true, 'h') if seq.param(0, 0) == 1049 => self.enter_alt_screen(gfx),
+            (true, 'l') if seq.param(0, 0) == 1049 => self.leave_alt_screen(gfx),
+            (false, 'H' | 'f') => self.move_to(seq.param(0, 1) - 1, seq.param(1, 1) - 1),
+            (false, 'A') => self.move_to(row.saturating_sub(seq.param(0, 1)), col),
+            (false, 'B') => self.move_to(row.saturating_add(seq.param(0, 1)), col),
+            (false, 'C') => self.move_to(row, col.saturating_add(seq.param(0, 1))),
+            (false, 'D') => self.move_to(row, col.saturating_sub(seq.param(0, 1))),
+            (false, 'G') => self.move_to(row, seq.param(0, 1) - 1),
+            (false, 'd') => self.move_to(seq.param(0, 1) - 1, col),
+            (false, 'J') => self.erase_display(seq.param(0, 0), gfx),
+            (false, 'K') => self.erase_line(seq.param(0, 0), gfx),
+            // graphic renditions and other sequences are not supported, but still consumed
+            _ => {}
+        }
+    }
+
+    /// moves the cursor to the 0 based row and col, clamped to the screen
+    fn move_to(&mut self, row: usize, col: usize) {
+        self.cursor.row.inner = row.min(Y - 1);
+        self.cursor.col.inner = col.min(X - 1);
+    }
+
+    /// erases from the cursor to the end of the row (0), from its start to the cursor (1) or the whole row (2)
+    fn erase_line(&mut self, mode: usize, gfx: &mut B) {
+        let row = self.cursor.row;
+        let col = self.cursor.col.inner.min(X - 1);
+        let mut range = match mode {
+            0 => col..X,
+            1 => 0..col + 1,
+            _ => 0..X,
+        };
+        // wide chars are erased as a whole
+        let cells = &self.buffer.inner[row.inner];
+        if range.start > 0 && cells[range.start] == Some(text::CONTINUATION) {
+            range.start -= 1;
+        }
+        if range.end < X && cells[range.end] == Some(text::CONTINUATION) {
+            range.end += 1;
+        }
+        self.buffer.inner[row.inner][range.clone()].fill(None);
         self.buffer
-            .redraw_empty_row(&self.cursor.row, &mut *self.backend.lock());
+            .redraw_row_with_range(&row, gfx, &self.str_style, range);
+    }
+
+    /// erases from the cursor to the end of the screen (0), from its start to the cursor (1) or the whole screen (2)
+    fn erase_display(&mut self, mode: usize, gfx: &mut B) {
+        let row = self.cursor.row.inner;
+        let rows = match mode {
+            0 => row + 1..Y,
+            1 => 0..row,
+            _ => 0..Y,
+        };
+        if mode < 2 {
+            self.erase_line(mode, gfx);
+        }
+        for row in rows {
+            let row = TermPixel { inner: row };
+            self.buffer.clear_line(&row);
+            self.buffer.redraw_empty_row(&row, gfx);
+        }
+    }
+
+    /// switches to an empty screen, keeping the main screen until the alternate one is left
+    fn enter_alt_screen(&mut self, gfx: &mut B) {
+        if self.saved_screen.is_some() {
+            return;
+        }
+        self.saved_screen = Some((self.buffer.inner.to_vec(), self.cursor));
+        self.erase_display(2, gfx);
+        self.move_to(0, 0);
+    }
+
+    /// restores the main screen and its cursor
+    fn leave_alt_screen(&mut self, gfx: &mut B) {
+        let Some((rows, cursor)) = self.saved_screen.take() else {
+            return;
+        };
+        self.buffer.inner.copy_from_slice(&rows);
+        self.cursor = cursor;
+        self.buffer.redraw(&mut self.cursor, gfx, &self.str_style);
+    }
+
+    /// the selected cells in reading order, as (row, col) of the first and last one
+    fn selection_bounds(&self) -> Option<((usize, usize), (usize, usize))> {
+        self.selection
+            .map(|(anchor, head)| (anchor.min(head), anchor.max(head)))
+    }
+
+    /// redraws the rows first..=last, highlighting the selected cells
+    fn redraw_rows(&mut self, first: usize, last: usize, gfx: &mut B) {
+        let bounds = self.selection_bounds();
+        for row in first..=last.min(Y - 1) {
+            let selected = bounds
+                .filter(|(start, end)| (start.0..=end.0).contains(&row))
+                .map_or(0..0, |(start, end)| {
+                    let from = if start.0 == row { start.1 } else { 0 };
+                    let to = if end.0 == row { end.1 + 1 } else { X };
+                    from..to
+                });
+            let row = TermPixel { inner: row };
+            self.buffer
+                .redraw_row_with_range(&row, gfx, &self.str_style, 0..selected.start);
+            self.buffer
+                .redraw_row_with_range(&row, gfx, &self.selected_style, selected.clone());
+            self.buffer
+                .redraw_row_with_range(&row, gfx, &self.str_style, selected.end..X);
+        }
+    }
+
+    /// moves the head of the selection by dx cols and dy rows. A new selection starts at the cursor
+    pub(super) fn move_selection(&mut self, dx: isize, dy: isize, gfx: &mut B) {
+        let cursor = (
+            self.cursor.row.inner.min(Y - 1),
+            self.cursor.col.inner.min(X - 1),
+        );
+        let (anchor, head) = self.selection.unwrap_or((cursor, cursor));
+        let new_head = (
+            head.0.saturating_add_signed(dy).min(Y - 1),
+            head.1.saturating_add_signed(dx).min(X - 1),
+        );
+        self.selection = Some((anchor, new_head));
+        let first = anchor.0.min(head.0).min(new_head.0);
+        let last = anchor.0.max(head.0).max(new_head.0);
+        self.redraw_rows(first, last, gfx);
+    }
+
+    /// drops the selection and removes its highlight
+    pub(super) fn clear_selection(&mut self, gfx: &mut B) {
+        if let Some((start, end)) = self.selection_bounds() {
+            self.selection = None;
+            self.redraw_rows(start.0, end.0, gfx);
+        }
     }
 
-    pub(super) fn clear_one(&mut self) {
+    /// the text of the selected cells. Rows are separated by newlines, empty cells are skipped
+    pub(super) fn selected_text(&self) -> Option<String> {
+        let (start, end) = self.selection_bounds()?;
+        let mut text = String::new();
+        for row in start.0..=end.0 {
+            if row != start.0 {
+                text.push('\n');
+            }
+            let from = if row == start.0 { start.1 } else { 0 };
+            let to = if row == end.0 { end.1 + 1 } else { X };
+            text.extend(
+                self.buffer.inner[row][from..to]
+                    .iter()
+                    .flatten()
+                    .filter(|c| **c != text::CONTINUATION),
+            );
+        }
+        Some(text)
+    }
+
+    pub(super) fn line_clear(&mut self, gfx: &mut B) {
+        self.buffer.clear_line(&self.cursor.row);
+        self.buffer.redraw_empty_row(&self.cursor.row, gfx);
+    }
+
+    pub(super) fn clear_one(&mut self, gfx: &mut B) {
         loop {
             if self.cursor.col.inner > 0 {
                 self.cursor.col.inner -= 1;
@@ -392,68 +645,94 @@ where
                 break;
             }
         }
+        if self.cursor.col.inner > 0
+            && self.buffer.get(&self.cursor).unwrap() == Some(&text::CONTINUATION)
+        {
+            self.cursor.col.inner -= 1;
+        }
+        let width = self
+            .buffer
+            .get(&self.cursor)
+            .unwrap()
+            .map_or(1, |c| text::width(*c).max(1));
 
-        _ = self.backend.lock().fill_solid(
+        _ = gfx.fill_solid(
             &Rectangle::new(
                 self.cursor.into(),
-                Size::new(CHAR_WIDTH as u32, CHAR_HEIGHT as u32),
+                Size::new((width * CHAR_WIDTH) as u32, CHAR_HEIGHT as u32),
             ),
             ColorCode::default().into(),
         );
     }
 
-    fn write_tab(&mut self) {
+    fn write_tab(&mut self, gfx: &mut B) {
         // tab == 3 spaces TODO add dynamic tab
-        for _ in 0..3 {
-            self.write_char(' ');
-        }
+        self.write_run("   ", gfx);
     }
 
-    fn write_char(&mut self, c: char) {
+    fn write_char(&mut self, c: char, gfx: &mut B) {
         match c {
             '\n' => {
-                self.newline();
+                self.newline(gfx);
             }
-            '\t' => self.write_tab(),
-            '\r' => self.line_clear(),
-            '\u{08}' => self.clear_one(),
+            '\t' => self.write_tab(gfx),
+            '\r' => self.line_clear(gfx),
+            '\u{08}' => self.clear_one(gfx),
+            '\u{1b}' => self.escape = Some(EscapeSeq::default()),
             _ => {
-                match self.buffer.force_push_smart(c, &mut self.cursor) {
-                    Err(PositionError::NewLine) => {
-                        self.buffer.redraw_row_with_range(
-                            &self.cursor.row,
-                            &mut *self.backend.lock(),
-                            &self.str_style,
-                            self.buffer.get_range_from_row(&self.cursor.row),
-                        );
-                        self.cursor.col.inner += 1;
-                    }
-                    Err(PositionError::PrevLine) => {
-                        self.buffer.redraw(
-                            &mut self.cursor,
-                            &mut *self.backend.lock(),
-                            &self.str_style,
-                        );
-                        self.cursor.col.inner += 1;
-                    }
-                    Ok(()) => {
-                        let res = self.str_style.draw_char(
-                            c,
-                            self.cursor.into(),
-                            Baseline::Top,
-                            &mut *self.backend.lock(),
-                        );
-                        self.cursor.col.inner += 1;
-                    }
-                    _ => {}
-                };
+                let mut buf = [0; 4];
+                self.write_run(c.encode_utf8(&mut buf), gfx);
             }
         }
     }
 
-    fn write_char_iter(&mut self, chars: impl Iterator<Item = char>) {
-        for c in chars {
-            self.write_char(c);
+    /// writes chars without any control chars. Each part of narrow chars of the font fitting onto the current row is
+    /// drawn with a single call, other chars are drawn one by one. Zero width chars are dropped
+    fn write_run(&mut self, run: &str, gfx: &mut B) {
+        // the bytes of run, which are stored, but not drawn yet, and the position of the first one
+        let mut pending = 0..0;
+        let mut pending_at = self.cursor;
+        for (i, c) in run.char_indices() {
+            let width = text::width(c);
+            let batched = width == 1 && text::has_glyph(c);
+            if !batched || self.cursor.col.inner + width > X {
+                self.draw_pending(&run[pending], pending_at, gfx);
+                pending = i..i;
+            }
+            if width == 0 {
+                continue;
+            }
+            if self.cursor.col.inner + width > X {
+                self.newline(gfx);
+            }
+            let orphans = self.buffer.put(c, width, &self.cursor);
+            if batched {
+                if pending.is_empty() {
+                    pending = i..i;
+                    pending_at = self.cursor;
+                }
+                pending.end = i + c.len_utf8();
+            } else {
+                text::draw_cell(c, self.cursor.into(), &self.str_style, gfx);
+            }
+            for col in orphans.into_iter().flatten() {
+                self.buffer.redraw_row_with_range(
+                    &self.cursor.row,
+                    gfx,
+                    &self.str_style,
+                    col..col + 1,
+                );
+            }
+            self.cursor.col.inner += width;
+        }
+        self.draw_pending(&run[pending], pending_at, gfx);
+    }
+
+    fn draw_pending(&self, pending: &str, at: TermPosition, gfx: &mut B) {
+        if !pending.is_empty() {
+            _ = self
+                .str_style
+                .draw_string(pending, at.into(), Baseline::Top, gfx);
         }
     }
 
@@ -462,12 +741,13 @@ where
         // TODO
         todo!();
         return;
+        let backend = self.backend;
         match draw_res {
             Ok(p) => match self.cursor.shift_checked(p) {
                 Ok(()) => {}
                 Err(e) => match e {
-                    PositionError::NewLine => self.newline(),
-                    PositionError::PrevLine => self.prevline(),
+                    PositionError::NewLine => self.newline(&mut backend.lock()),
+                    PositionError::PrevLine => self.prevline(&mut backend.lock()),
                     _ => {}
                 },
             },
@@ -475,38 +755,58 @@ where
         }
     }
 
-    pub(super) fn newline(&mut self) {
+    pub(super) fn newline(&mut self, gfx: &mut B) {
         if self.cursor.row.inner >= Y - 1 {
-            self.buffer
-                .shift_up_and_redraw(&mut *self.backend.lock(), &self.str_style);
+            self.buffer.shift_up_and_redraw(gfx);
         } else {
             self.cursor.row.inner += 1;
         }
         self.cursor.col = 0.into();
     }
 
-    pub(super) fn prevline(&mut self) {
+    pub(super) fn prevline(&mut self, gfx: &mut B) {
         // TODO
         // shifts all content down by one line
         self.buffer.shift_down();
-        self.buffer
-            .redraw(&mut self.cursor, &mut *self.backend.lock(), &self.str_style);
+        self.buffer.redraw(&mut self.cursor, gfx, &self.str_style);
     }
 }
 
 impl<B, const X: usize, const Y: usize> Write for BasicTermRender<'_, B, X, Y>
 where
-    B: DrawTarget<Color = RGBColor, Error = GraphicsError>,
+    B: DrawTarget<Color = RGBColor, Error = GraphicsError> + BlitTarget + FrameBuffer,
 {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        self.write_char_iter(s.chars());
+        // the backend is locked once per call rather than once per char
+        let backend = self.backend;
+        let mut gfx = backend.lock();
+        // output moves the text below the selection, thus it is dropped
+        self.clear_selection(&mut gfx);
+        let mut rest = s;
+        while !rest.is_empty() {
+            if self.escape.is_some() {
+                let c = rest.chars().next().unwrap();
+                self.feed_escape(c, &mut gfx);
+                rest = &rest[c.len_utf8()..];
+                continue;
+            }
+            let Some(idx) = rest.find(char::is_control) else {
+                self.write_run(rest, &mut gfx);
+                break;
+            };
+            let (run, tail) = rest.split_at(idx);
+            self.write_run(run, &mut gfx);
+            let c = tail.chars().next().unwrap();
+            self.write_char(c, &mut gfx);
+            rest = &tail[c.len_utf8()..];
+        }
         Ok(())
     }
 }
 
 impl<B, const X: usize, const Y: usize> Debug for BasicTermRender<'_, B, X, Y>
 where
-    B: DrawTarget<Color = RGBColor, Error = GraphicsError>,
+    B: DrawTarget<Color = RGBColor, Error = GraphicsError> + BlitTarget + FrameBuffer,
 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         writeln!(f, "X: {}, Y: {}", X, Y)?;
@@ -515,20 +815,31 @@ where
     }
 }
 
+#[cfg(feature = "test_run")]
 mod tests {
     use super::*;
-    use crate::{kernel::threading, serial_println};
+    use crate::{common::fixtures::Fixture, kernel::threading, serial_println};
+
+    /// resets the shared char buffer and cursor, so that each test starts on an empty screen
+    struct CleanTerm;
+
+    impl Fixture for CleanTerm {
+        fn setup() -> Self {
+            // SAFETY This is safe, as long it is not run parallely to some other functionality accessing FOOBAR / BAR, and init_term() was run in the same execution context
+            unsafe { super::super::BAR.clear() };
+            unsafe { assert!(super::super::BAR.is_empty()) };
+            unsafe {
+                super::super::FOOBAR.get_unchecked().lock().cursor.row.inner = 0;
+                super::super::FOOBAR.get_unchecked().lock().cursor.col.inner = 0;
+            };
+            Self
+        }
+    }
 
     #[kernel_test(files = [1 = "/proc/kernel/io/fbbackend"])]
-    fn print_to_buffer() {
+    fn print_to_buffer(#[fixture] _term: &CleanTerm) {
         // SAFETY This is safe, as long it is not run parallely to some other functionality accessing FOOBAR / BAR, and init_term() was run in the same execution context
         use crate::{print, println};
-        unsafe { super::super::BAR.clear() };
-        unsafe { assert!(super::super::BAR.is_empty()) };
-        unsafe {
-            super::super::FOOBAR.get_unchecked().lock().cursor.row.inner = 0;
-            super::super::FOOBAR.get_unchecked().lock().cursor.col.inner = 0;
-        };
         println!("test");
         for _ in 0..3 {
             threading::yield_now();
@@ -593,15 +904,9 @@ mod tests {
     }
 
     #[kernel_test(files = [1 = "/proc/kernel/io/fbbackend"])]
-    fn buf_shifts() {
+    fn buf_shifts(#[fixture] _term: &CleanTerm) {
         // SAFETY This is safe, as long it is not run parallely to some other functionality accessing FOOBAR / BAR, and init_term() was run in the same execution context
         use crate::println;
-        unsafe { super::super::BAR.clear() };
-        unsafe { assert!(super::super::BAR.is_empty()) };
-        unsafe {
-            super::super::FOOBAR.get_unchecked().lock().cursor.row.inner = 0;
-            super::super::FOOBAR.get_unchecked().lock().cursor.col.inner = 0;
-        };
 
         println!();
         println!("test");
@@ -641,6 +946,143 @@ mod tests {
         //TODO also test/implement shift_down
     }
 
+    #[kernel_test(files = [1 = "/proc/kernel/io/fbbackend"])]
+    fn scroll_blits(#[fixture] _term: &CleanTerm) {
+        use crate::{
+            kernel::graphics::{
+                GLOBAL_FRAMEBUFFER,
+                framebuffers::{get_config, get_rgb_pixel},
+            },
+            println,
+        };
+
+        for i in 0..super::super::MAX_CHARS_Y {
+            println!("line{}", i);
+        }
+        for _ in 0..10 {
+            threading::yield_now();
+        }
+        // the first line scrolled out of view
+        unsafe { assert_eq!(super::super::BAR.inner[0][4], Some('1')) };
+        unsafe { assert!(super::super::BAR.inner[super::super::MAX_CHARS_Y - 1][0].is_none()) };
+
+        // the glyphs of the second line were moved into the first text row
+        let background = get_rgb_pixel(&ColorCode::default().into(), get_config());
+        let fb = &*GLOBAL_FRAMEBUFFER;
+        let pixel = |x: usize, y: usize| unsafe {
+            fb.addr().add(fb.pixel_offset(x, y)).cast::<u32>().read()
+        };
+        assert!((0..CHAR_HEIGHT).any(|y| (0..CHAR_WIDTH).any(|x| pixel(x, y) != background)));
+        let last = (super::super::MAX_CHARS_Y - 1) * CHAR_HEIGHT;
+        assert!(
+            (last..last + CHAR_HEIGHT).all(|y| (0..CHAR_WIDTH).all(|x| pixel(x, y) == background))
+        );
+    }
+
+    #[kernel_test(files = [1 = "/proc/kernel/io/fbbackend"])]
+    fn select_text(#[fixture] _term: &CleanTerm) {
+        use crate::print;
+
+        print!("hello\nworld");
+        for _ in 0..3 {
+            threading::yield_now();
+        }
+        let term = unsafe { super::super::FOOBAR.get_unchecked() };
+        let mut term = term.lock();
+        let backend = term.backend;
+        let mut gfx = backend.lock();
+        term.move_selection(-5, 0, &mut gfx);
+        assert_eq!(term.selected_text().as_deref(), Some("world"));
+        term.move_selection(0, -1, &mut gfx);
+        assert_eq!(term.selected_text().as_deref(), Some("hello\nworld"));
+        // the head moves past the end of the first row
+        term.move_selection(10, 0, &mut gfx);
+        assert_eq!(term.selected_text().as_deref(), Some("\nworld"));
+        term.clear_selection(&mut gfx);
+        assert_eq!(term.selected_text(), None);
+    }
+
+    #[kernel_test(files = [1 = "/proc/kernel/io/fbbackend"])]
+    fn cursor_addressing(#[fixture] _term: &CleanTerm) {
+        use crate::print;
+
+        print!("\x1b[31mred\x1b[0m\x1b[3;5Hab\x1b[2Dc\x1b[1;2H\x1b[K");
+        for _ in 0..3 {
+            threading::yield_now();
+        }
+        unsafe {
+            let rows = &super::super::BAR.inner;
+            assert_eq!(rows[0][..3], [Some('r'), None, None]);
+            assert_eq!(rows[2][4..6], [Some('c'), Some('b')]);
+        }
+        let cursor = unsafe { super::super::FOOBAR.get_unchecked().lock().cursor };
+        assert_eq!((cursor.row.inner, cursor.col.inner), (0, 1));
+    }
+
+    #[kernel_test(files = [1 = "/proc/kernel/io/fbbackend"])]
+    fn alternate_screen(#[fixture] _term: &CleanTerm) {
+        use crate::print;
+
+        print!("main");
+        print!("\x1b[?1049h\x1b[10;10Halt");
+        for _ in 0..3 {
+            threading::yield_now();
+        }
+        unsafe {
+            assert!(super::super::BAR.inner[0].iter().all(Option::is_none));
+            assert_eq!(super::super::BAR.inner[9][9], Some('a'));
+        }
+        print!("\x1b[?1049l");
+        for _ in 0..3 {
+            threading::yield_now();
+        }
+        unsafe {
+            assert_eq!(
+                super::super::BAR.inner[0][..4],
+                [Some('m'), Some('a'), Some('i'), Some('n')]
+            );
+            assert!(super::super::BAR.inner[9][9].is_none());
+        }
+        let cursor = unsafe { super::super::FOOBAR.get_unchecked().lock().cursor };
+        assert_eq!((cursor.row.inner, cursor.col.inner), (0, 4));
+    }
+
+    #[kernel_test(files = [1 = "/proc/kernel/io/fbbackend"])]
+    fn wide_glyphs(#[fixture] _term: &CleanTerm) {
+        use crate::print;
+
+        const X: usize = super::super::MAX_CHARS_X;
+        let cont = Some(text::CONTINUATION);
+        print!("a中e\u{301}b\u{200b}c");
+        for _ in 0..3 {
+            threading::yield_now();
+        }
+        unsafe {
+            assert_eq!(
+                super::super::BAR.inner[0][..6],
+                [Some('a'), Some('中'), cont, Some('e'), Some('b'), Some('c')]
+            );
+        }
+        let cursor = unsafe { super::super::FOOBAR.get_unchecked().lock().cursor };
+        assert_eq!((cursor.row.inner, cursor.col.inner), (0, 6));
+
+        // overwriting half of a wide char removes all of it
+        print!("\x1b[1;3Hx");
+        // a wide char does not fit into the last col and moves to the next row
+        print!("\x1b[1;{}H한", X);
+        for _ in 0..3 {
+            threading::yield_now();
+        }
+        unsafe {
+            let rows = &super::super::BAR.inner;
+            assert_eq!(rows[0][1..3], [None, Some('x')]);
+            assert_eq!(rows[0][X - 1], None);
+            assert_eq!(rows[1][..2], [Some('한'), cont]);
+        }
+        let cursor = unsafe { super::super::FOOBAR.get_unchecked().lock().cursor };
+        assert_eq!((cursor.row.inner, cursor.col.inner), (1, 2));
+    }
+
     #[kernel_test(files = [1 = "/proc/kernel/io/fbbackend"])]
     fn print_many() {
         return;
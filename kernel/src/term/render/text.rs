@@ -1 +1,130 @@
+use embedded_graphics::{
+    Drawable,
+    mono_font::MonoTextStyle,
+    prelude::{DrawTarget, Point, Primitive, Size},
+    primitives::{PrimitiveStyle, Rectangle},
+    text::Baseline,
+};
 
+use super::{CHAR_HEIGHT, CHAR_WIDTH};
+use crate::kernel::graphics::{GraphicsError, colors::RGBColor, text::CharRenderer};
+
+/// stored in the cell right of a wide char, which covers both cells
+pub(super) const CONTINUATION: char = '\0';
+
+/// combining marks, variation selectors and zero width spaces. They are dropped, as cells hold a single char
+const ZERO_WIDTH: &[(u32, u32)] = &[
+    (0x0300, 0x036f),
+    (0x0483, 0x0489),
+    (0x0591, 0x05bd),
+    (0x0610, 0x061a),
+    (0x064b, 0x065f),
+    (0x0e31, 0x0e31),
+    (0x0e34, 0x0e3a),
+    (0x0e47, 0x0e4e),
+    (0x1ab0, 0x1aff),
+    (0x1dc0, 0x1dff),
+    (0x200b, 0x200f),
+    (0x202a, 0x202e),
+    (0x2060, 0x2064),
+    (0x20d0, 0x20ff),
+    (0x302a, 0x302d),
+    (0x3099, 0x309a),
+    (0xfe00, 0xfe0f),
+    (0xfe20, 0xfe2f),
+    (0xfeff, 0xfeff),
+    (0xe0100, 0xe01ef),
+];
+
+/// east asian wide and fullwidth chars and emoji, which take two cells
+const WIDE: &[(u32, u32)] = &[
+    (0x1100, 0x115f),
+    (0x231a, 0x231b),
+    (0x2329, 0x232a),
+    (0x23e9, 0x23ec),
+    (0x25fd, 0x25fe),
+    (0x2614, 0x2615),
+    (0x2e80, 0x303e),
+    (0x3041, 0x33ff),
+    (0x3400, 0x4dbf),
+    (0x4e00, 0x9fff),
+    (0xa000, 0xa4cf),
+    (0xa960, 0xa97f),
+    (0xac00, 0xd7a3),
+    (0xf900, 0xfaff),
+    (0xfe10, 0xfe19),
+    (0xfe30, 0xfe6f),
+    (0xff00, 0xff60),
+    (0xffe0, 0xffe6),
+    (0x1f300, 0x1f64f),
+    (0x1f900, 0x1f9ff),
+    (0x20000, 0x2fffd),
+    (0x30000, 0x3fffd),
+];
+
+fn in_table(c: char, table: &[(u32, u32)]) -> bool {
+    let c = c as u32;
+    let idx = table.partition_point(|(_, end)| *end < c);
+    table.get(idx).is_some_and(|(start, _)| *start <= c)
+}
+
+/// the number of cells c takes
+pub(super) fn width(c: char) -> usize {
+    if in_table(c, ZERO_WIDTH) {
+        0
+    } else if in_table(c, WIDE) {
+        2
+    } else {
+        1
+    }
+}
+
+/// whether the iso 8859-1 font of the terminal has a glyph for c
+pub(super) fn has_glyph(c: char) -> bool {
+    matches!(c, ' '..='~' | '\u{a0}'..='\u{ff}')
+}
+
+/// draws c into the cell at, or a hollow box covering its cells, if the font has no glyph for it
+pub(super) fn draw_cell<D>(c: char, at: Point, style: &MonoTextStyle<'_, RGBColor>, gfx: &mut D)
+where
+    D: DrawTarget<Color = RGBColor, Error = GraphicsError>,
+{
+    if has_glyph(c) {
+        _ = style.draw_char(c, at, Baseline::Top, gfx);
+        return;
+    }
+    let cell = Rectangle::new(
+        at,
+        Size::new((width(c).max(1) * CHAR_WIDTH) as u32, CHAR_HEIGHT as u32),
+    );
+    if let Some(background) = style.background_color {
+        _ = gfx.fill_solid(&cell, background);
+    }
+    if let Some(color) = style.text_color {
+        _ = cell
+            .offset(-2)
+            .into_styled(PrimitiveStyle::with_stroke(color, 1))
+            .draw(gfx);
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    #[kernel_test]
+    fn char_widths() {
+        assert_eq!(width('a'), 1);
+        assert_eq!(width('é'), 1);
+        assert_eq!(width('中'), 2);
+        assert_eq!(width('한'), 2);
+        assert_eq!(width('\u{1f600}'), 2);
+        assert_eq!(width('\u{301}'), 0);
+        assert_eq!(width('\u{200b}'), 0);
+        assert!(has_glyph('é'));
+        assert!(!has_glyph('中'));
+        assert!(!has_glyph(CONTINUATION));
+    }
+}
@@ -0,0 +1,337 @@
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+use core::{
+    mem,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use thiserror::Error;
+
+use crate::{
+    kernel::threading::{self, schedule::GlobalTaskPtr, task::TaskRepr, tls},
+    sync::locks::Mutex,
+};
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    #[error("the channel is full")]
+    Full(T),
+    #[error("all receivers were dropped")]
+    Disconnected(T),
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("all receivers were dropped")]
+pub struct SendError<T>(pub T);
+
+#[derive(Error, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TryRecvError {
+    #[error("the channel is empty")]
+    Empty,
+    #[error("all senders were dropped and the channel is empty")]
+    Disconnected,
+}
+
+#[derive(Error, Debug, PartialEq, Eq, Clone, Copy)]
+#[error("all senders were dropped and the channel is empty")]
+pub struct RecvError;
+
+/// threads parked on one side of a channel
+#[derive(Default)]
+struct Waiters(Mutex<Vec<GlobalTaskPtr>>);
+
+impl Waiters {
+    fn register(&self) {
+        let Some(current) = tls::task_data().current_thread() else {
+            return;
+        };
+        let mut waiters = self.0.lock();
+        if !waiters.iter().any(|task| task.tid() == current.tid()) {
+            waiters.push(current);
+        }
+    }
+
+    fn unregister(&self) {
+        let tid = tls::task_data().current_tid();
+        self.0.lock().retain(|task| task.tid() != tid);
+    }
+
+    /// unparks every waiter. Each one retries, thus a wakeup cannot get lost on a waiter, which is already done
+    fn wake_all(&self) {
+        let waiters = mem::take(&mut *self.0.lock());
+        for task in waiters {
+            task.unpark();
+        }
+    }
+}
+
+struct Shared<T> {
+    queue: Mutex<VecDeque<T>>,
+    /// None for unbounded channels
+    capacity: Option<usize>,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+    /// receivers waiting for a message
+    recv_waiters: Waiters,
+    /// senders waiting for space
+    send_waiters: Waiters,
+}
+
+/// retries op until it returns Some, parking the current thread in between
+fn block_on<R>(waiters: &Waiters, mut op: impl FnMut() -> Option<R>) -> R {
+    if let Some(r) = op() {
+        return r;
+    }
+    loop {
+        // registered before retrying, such that a wakeup in between is not missed
+        waiters.register();
+        if let Some(r) = op() {
+            waiters.unregister();
+            return r;
+        }
+        threading::park();
+    }
+}
+
+/// creates a channel, which holds at most capacity messages. Senders block while it is full
+pub fn bounded<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    let capacity = capacity.max(1);
+    channel(Some(capacity), VecDeque::with_capacity(capacity))
+}
+
+/// creates a channel, whose senders never block
+pub fn unbounded<T>() -> (Sender<T>, Receiver<T>) {
+    channel(None, VecDeque::new())
+}
+
+fn channel<T>(capacity: Option<usize>, queue: VecDeque<T>) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        queue: Mutex::new(queue),
+        capacity,
+        senders: AtomicUsize::new(1),
+        receivers: AtomicUsize::new(1),
+        recv_waiters: Waiters::default(),
+        send_waiters: Waiters::default(),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+/// the sending half of a channel. It may be cloned, to send from multiple threads
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// queues value, if there is space left. Never blocks
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        if self.shared.receivers.load(Ordering::Acquire) == 0 {
+            return Err(TrySendError::Disconnected(value));
+        }
+        let mut queue = self.shared.queue.lock();
+        if self
+            .shared
+            .capacity
+            .is_some_and(|capacity| queue.len() >= capacity)
+        {
+            return Err(TrySendError::Full(value));
+        }
+        queue.push_back(value);
+        drop(queue);
+        self.shared.recv_waiters.wake_all();
+        Ok(())
+    }
+
+    /// queues value, blocking while the channel is full
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let mut value = Some(value);
+        block_on(&self.shared.send_waiters, || {
+            match self.try_send(value.take().unwrap()) {
+                Ok(()) => Some(Ok(())),
+                Err(TrySendError::Full(v)) => {
+                    value = Some(v);
+                    None
+                }
+                Err(TrySendError::Disconnected(v)) => Some(Err(SendError(v))),
+            }
+        })
+    }
+
+    /// the number of queued messages
+    pub fn len(&self) -> usize {
+        self.shared.queue.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// None for unbounded channels
+    pub fn capacity(&self) -> Option<usize> {
+        self.shared.capacity
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::AcqRel);
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            // receivers blocked on an empty channel now fail
+            self.shared.recv_waiters.wake_all();
+        }
+    }
+}
+
+/// the receiving half of a channel. It may be cloned, each message is received by a single receiver
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// takes the oldest message, if there is one. Never blocks
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        // loaded before looking at the queue, as the last sender may send and drop in between
+        let disconnected = self.shared.senders.load(Ordering::Acquire) == 0;
+        let value = self.shared.queue.lock().pop_front();
+        match value {
+            Some(value) => {
+                if self.shared.capacity.is_some() {
+                    self.shared.send_waiters.wake_all();
+                }
+                Ok(value)
+            }
+            None if disconnected => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// takes the oldest message, blocking while the channel is empty.
+    /// Fails once all senders were dropped and all messages were received
+    pub fn recv(&self) -> Result<T, RecvError> {
+        block_on(&self.shared.recv_waiters, || match self.try_recv() {
+            Ok(value) => Some(Ok(value)),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(RecvError)),
+        })
+    }
+
+    /// the messages, which are queued right now
+    pub fn try_iter(&self) -> impl Iterator<Item = T> + '_ {
+        core::iter::from_fn(|| self.try_recv().ok())
+    }
+
+    /// the number of queued messages
+    pub fn len(&self) -> usize {
+        self.shared.queue.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.receivers.fetch_add(1, Ordering::AcqRel);
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        if self.shared.receivers.fetch_sub(1, Ordering::AcqRel) == 1 {
+            // senders blocked on a full channel now fail
+            self.shared.send_waiters.wake_all();
+        }
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use alloc::vec::Vec;
+
+    use os_macros::kernel_test;
+
+    use super::*;
+
+    #[kernel_test]
+    fn bounded_channel() {
+        let (tx, rx) = bounded(2);
+        assert_eq!(tx.capacity(), Some(2));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        assert_eq!(tx.try_send(1), Ok(()));
+        assert_eq!(tx.try_send(2), Ok(()));
+        assert_eq!(tx.try_send(3), Err(TrySendError::Full(3)));
+        assert_eq!(rx.len(), 2);
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert_eq!(tx.send(3), Ok(()));
+        drop(tx);
+        // queued messages are still received after all senders are gone
+        assert_eq!(rx.recv(), Ok(2));
+        assert_eq!(rx.recv(), Ok(3));
+        assert_eq!(rx.recv(), Err(RecvError));
+
+        let (tx, rx) = unbounded();
+        assert!((0..100).all(|i| tx.try_send(i).is_ok()));
+        assert!(rx.try_iter().eq(0..100));
+        drop(rx);
+        assert_eq!(tx.send(0), Err(SendError(0)));
+    }
+
+    #[kernel_test]
+    fn blocking_handoff() {
+        const PER_PRODUCER: usize = 50;
+        let (tx, rx) = bounded(4);
+        let (result_tx, result_rx) = unbounded();
+        let producers: Vec<_> = (0..3)
+            .map(|_| {
+                let tx = tx.clone();
+                threading::spawn(move || {
+                    for i in 0..PER_PRODUCER {
+                        tx.send(i).unwrap();
+                    }
+                })
+                .unwrap()
+            })
+            .collect();
+        let consumers: Vec<_> = (0..2)
+            .map(|_| {
+                let rx = rx.clone();
+                let result_tx = result_tx.clone();
+                threading::spawn(move || {
+                    while let Ok(i) = rx.recv() {
+                        result_tx.send(i).unwrap();
+                    }
+                })
+                .unwrap()
+            })
+            .collect();
+        drop((tx, rx, result_tx));
+        for producer in producers {
+            assert!(producer.wait().is_ok());
+        }
+        // the consumers finish once the last producer dropped its sender
+        for consumer in consumers {
+            assert!(consumer.wait().is_ok());
+        }
+        let mut received: Vec<_> = result_rx.try_iter().collect();
+        received.sort();
+        let mut expected: Vec<_> = (0..3).flat_map(|_| 0..PER_PRODUCER).collect();
+        expected.sort();
+        assert_eq!(received, expected);
+        assert_eq!(result_rx.try_recv(), Err(TryRecvError::Disconnected));
+    }
+}
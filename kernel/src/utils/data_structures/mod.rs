@@ -1 +1 @@
-
+pub mod channel;
@@ -0,0 +1,93 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::arch;
+
+/// cpus beyond this share the slot of the last one
+pub const MAX_CPUS: usize = 8;
+
+/// a counter of a single cpu, which has a cacheline to itself
+#[derive(Debug)]
+#[repr(align(64))]
+struct Slot(AtomicU64);
+
+/// an event counter, which every cpu increments in a cacheline of its own. Increments are wait-free and never contend with
+/// other cpus, reads sum up all slots instead. A read is not a snapshot, increments racing with it may or may not be
+/// counted. Safe to use from interrupt handlers, as it neither blocks nor allocates
+#[derive(Debug)]
+pub struct PerCpuCounter {
+    slots: [Slot; MAX_CPUS],
+}
+
+impl PerCpuCounter {
+    pub const fn new() -> Self {
+        Self {
+            slots: [const { Slot(AtomicU64::new(0)) }; MAX_CPUS],
+        }
+    }
+
+    pub fn add(&self, n: u64) {
+        self.slots[arch::cpu_slot().min(MAX_CPUS - 1)]
+            .0
+            .fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    /// the sum over all cpus
+    pub fn get(&self) -> u64 {
+        self.slots
+            .iter()
+            .map(|slot| slot.0.load(Ordering::Relaxed))
+            .fold(0, u64::wrapping_add)
+    }
+
+    /// the count of the cpu with index cpu in the cpu topology
+    pub fn per_cpu(&self, cpu: usize) -> u64 {
+        self.slots
+            .get(cpu)
+            .map_or(0, |slot| slot.0.load(Ordering::Relaxed))
+    }
+}
+
+impl Default for PerCpuCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "test_run")]
+mod tests {
+    use os_macros::kernel_test;
+
+    use super::*;
+    use crate::kernel::threading;
+
+    #[kernel_test]
+    fn counts_per_cpu() {
+        static COUNTER: PerCpuCounter = PerCpuCounter::new();
+        assert_eq!(size_of::<Slot>(), 64);
+        COUNTER.inc();
+        COUNTER.add(41);
+        assert_eq!(COUNTER.get(), 42);
+        assert_eq!(COUNTER.per_cpu(arch::cpu_slot()), 42);
+        assert_eq!(COUNTER.per_cpu(MAX_CPUS), 0);
+
+        // increments of concurrent tasks are not lost
+        let handles = (0..4)
+            .map(|_| {
+                threading::spawn(|| {
+                    for _ in 0..1000 {
+                        COUNTER.inc();
+                    }
+                })
+                .unwrap()
+            })
+            .collect::<alloc::vec::Vec<_>>();
+        handles
+            .into_iter()
+            .for_each(|handle| handle.wait().unwrap());
+        assert_eq!(COUNTER.get(), 4042);
+    }
+}
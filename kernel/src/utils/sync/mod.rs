@@ -5,13 +5,15 @@ use thiserror::Error;
 
 use crate::{
     arch::{self},
-    kernel::threading::{self, task::ThreadID, tls},
+    kernel::threading::{self, schedule, task::ThreadID, tls},
 };
 
+pub mod counter;
 mod primitive;
 
 pub mod locks {
 
+    pub use crate::sync::primitive::irqsave::{SpinLockIrqSave, SpinLockIrqSaveGuard};
     use crate::sync::{WaitStrategy, YieldWaiter, primitive::semaphore::StaticSemaphore};
 
     pub type GenericMutex<T, S: WaitStrategy> = lock_api::Mutex<StaticSemaphore<1, S>, T>;
@@ -91,6 +93,7 @@ impl StatelessWaitStrategy for YieldWaiter {
         // if !interrupt::are_enabled() {
         //     serial_println!("tried to block on a yield-waiter in a no-interrupt context. This is likely a deadlock");
         // }
+        schedule::assert_may_block();
         threading::yield_now();
     }
 }
@@ -109,6 +112,7 @@ impl WaitStrategy for BlockingWaiter {
         //     serial_println!("tried to block on a sleep-waiter in a no-interrupt context. This is likely a deadlock");
         // }
 
+        schedule::assert_may_block();
         self.queue.push(tls::task_data().current_tid());
         tls::task_data().block(&tls::task_data().current_tid());
         threading::yield_now();
@@ -266,4 +270,54 @@ mod tests {
 
         assert_eq!(*lock.lock(), 500);
     }
+
+    #[kernel_test]
+    fn irqsave_restores_interrupts() {
+        let lock = locks::SpinLockIrqSave::new(0);
+        let enabled = arch::interrupt::are_enabled();
+
+        let mut guard = lock.lock();
+        assert!(!arch::interrupt::are_enabled());
+        assert!(lock.try_lock().is_none());
+        // a failed try_lock leaves interrupts disabled, as the guard still holds them
+        assert!(!arch::interrupt::are_enabled());
+        *guard = 42;
+        drop(guard);
+        assert_eq!(arch::interrupt::are_enabled(), enabled);
+
+        // nested locks only restore the state of the outermost one
+        let other = locks::SpinLockIrqSave::new(());
+        let outer = lock.try_lock().unwrap();
+        let inner = other.lock();
+        drop(inner);
+        assert!(!arch::interrupt::are_enabled());
+        assert_eq!(*outer, 42);
+        drop(outer);
+        assert_eq!(arch::interrupt::are_enabled(), enabled);
+        assert!(!lock.is_locked());
+    }
+
+    #[kernel_test]
+    fn irqsave_concurrent() {
+        let lock: Arc<locks::SpinLockIrqSave<i32>> = Arc::new(locks::SpinLockIrqSave::new(0));
+
+        let threads: Vec<_> = (0..5)
+            .map(|_| {
+                let lock = lock.clone();
+                threading::spawn(move || {
+                    for _ in 0..10 {
+                        *lock.lock() += 10;
+                        threading::yield_now();
+                    }
+                })
+                .unwrap()
+            })
+            .collect();
+
+        for t in threads {
+            assert!(t.wait().is_ok());
+        }
+
+        assert_eq!(*lock.lock(), 500);
+    }
 }
@@ -0,0 +1,142 @@
+use core::{
+    cell::UnsafeCell,
+    fmt::Debug,
+    hint,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::arch::interrupt;
+
+/// a spinning lock, which disables interrupts on the current cpu while it is held and restores their previous state,
+/// once the guard is dropped. Locks, which are taken in interrupt handlers, must be of this kind, otherwise the
+/// handler spins forever on a lock held by the task it interrupted.
+/// Nothing may block or allocate while the lock is held. Nested guards must be dropped in reverse order
+pub struct SpinLockIrqSave<T: ?Sized> {
+    locked: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for SpinLockIrqSave<T> {}
+unsafe impl<T: ?Sized + Send> Sync for SpinLockIrqSave<T> {}
+
+impl<T> SpinLockIrqSave<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> SpinLockIrqSave<T> {
+    pub fn lock(&self) -> SpinLockIrqSaveGuard<'_, T> {
+        let were_enabled = save_and_disable();
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            while self.locked.load(Ordering::Relaxed) {
+                hint::spin_loop();
+            }
+        }
+        SpinLockIrqSaveGuard {
+            lock: self,
+            were_enabled,
+        }
+    }
+
+    /// takes the lock, if it is free. Interrupts are left as they were otherwise
+    pub fn try_lock(&self) -> Option<SpinLockIrqSaveGuard<'_, T>> {
+        let were_enabled = save_and_disable();
+        if self
+            .locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            restore(were_enabled);
+            return None;
+        }
+        Some(SpinLockIrqSaveGuard {
+            lock: self,
+            were_enabled,
+        })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+impl<T: Default> Default for SpinLockIrqSave<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: ?Sized + Debug> Debug for SpinLockIrqSave<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.try_lock() {
+            Some(guard) => f
+                .debug_struct("SpinLockIrqSave")
+                .field("data", &&*guard)
+                .finish(),
+            None => f
+                .debug_struct("SpinLockIrqSave")
+                .field("data", &"<locked>")
+                .finish(),
+        }
+    }
+}
+
+pub struct SpinLockIrqSaveGuard<'a, T: ?Sized> {
+    lock: &'a SpinLockIrqSave<T>,
+    // whether interrupts were enabled before the lock was taken
+    were_enabled: bool,
+}
+
+impl<T: ?Sized> Deref for SpinLockIrqSaveGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: the guard holds the lock
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for SpinLockIrqSaveGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: the guard holds the lock
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for SpinLockIrqSaveGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+        restore(self.were_enabled);
+    }
+}
+
+fn save_and_disable() -> bool {
+    let were_enabled = interrupt::are_enabled();
+    if were_enabled {
+        unsafe { interrupt::disable() };
+    }
+    were_enabled
+}
+
+fn restore(were_enabled: bool) {
+    if were_enabled {
+        unsafe { interrupt::enable() };
+    }
+}
@@ -1,3 +1,4 @@
+pub(super) mod irqsave;
 pub(super) mod mutex;
 pub(super) mod rwlock;
 pub(super) mod semaphore;
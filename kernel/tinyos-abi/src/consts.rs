@@ -4,4 +4,44 @@ pub const STDIN_FILENO: FileDescriptor = 0;
 pub const STDOUT_FILENO: FileDescriptor = 1;
 pub const STDERR_FILENO: FileDescriptor = 2;
 
-pub const MAX_SYSCALL: u64 = 30;
+pub const MAX_SYSCALL: u64 = 50;
+
+/// the vDSO is mapped at this address into every user process. Its code page is passed as AT_SYSINFO_EHDR and starts with a types::VdsoHeader
+pub const VDSO_START: u64 = 0x7fff_ff00_0000;
+pub const VDSO_PAGES: u64 = 3;
+pub const VDSO_MAGIC: u64 = u64::from_le_bytes(*b"tinyvdso");
+
+// keys of the auxiliary vector, which is passed to a new process in its fifth argument. The values match linux
+pub const AT_NULL: u64 = 0;
+pub const AT_PAGESZ: u64 = 6;
+pub const AT_ENTRY: u64 = 9;
+pub const AT_SYSINFO_EHDR: u64 = 33;
+
+// clocks of the vDSO clock_gettime
+pub const CLOCK_REALTIME: u64 = 0;
+pub const CLOCK_MONOTONIC: u64 = 1;
+
+// signals of the kill syscall, which stop and continue a process instead of terminating it. The values match linux
+pub const SIGCONT: i64 = 18;
+pub const SIGSTOP: i64 = 19;
+
+// special values of types::FileTimes
+pub const UTIME_NOW: u64 = u64::MAX;
+pub const UTIME_OMIT: u64 = u64::MAX - 1;
+
+/// RingHeader::magic
+pub const RING_MAGIC: u64 = u64::from_le_bytes(*b"tinyring");
+/// SubmissionEntry::offset, which reads or writes at the cursor of the file
+pub const RING_CURSOR: u64 = u64::MAX;
+// limits of a ring, in entries and bytes of its data area
+pub const RING_MAX_ENTRIES: u32 = 4096;
+pub const RING_MAX_DATA: usize = 1 << 20;
+
+// limits of extended attributes, in bytes
+pub const XATTR_NAME_MAX: usize = 255;
+pub const XATTR_SIZE_MAX: usize = 65536;
+
+// limits of strings passed to syscalls, in bytes. ARG_MAX bounds the arguments and the environment of a new process each
+pub const PATH_MAX: usize = 4096;
+pub const ARG_MAX: usize = 131072;
+pub const DBG_MAX: usize = 65536;
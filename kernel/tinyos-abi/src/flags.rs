@@ -17,6 +17,7 @@ bitflags! {
         const CREATE_LINK = 1 << 7;
         const NO_FOLLOW_LINK = 1 << 8;
         const EXECUTE = 1 << 9;
+        const CLOEXEC = 1 << 10;
     }
 }
 
@@ -44,6 +45,10 @@ impl OpenOptions {
     pub fn with_exec(self) -> Self {
         self | Self::EXECUTE
     }
+
+    pub fn with_cloexec(self) -> Self {
+        self | Self::CLOEXEC
+    }
 }
 
 impl Default for OpenOptions {
@@ -83,6 +88,10 @@ bitflags! {
     #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub struct WaitOptions: u16 {
         const NOBLOCK = 1 << 0;
+        /// only valid for thread_join: detaches the thread instead of waiting for it
+        const DETACH = 1 << 1;
+        /// only valid for wait_id: reports a child state change without consuming it
+        const NOWAIT = 1 << 2;
     }
 }
 
@@ -93,6 +102,8 @@ bitflags! {
         const W_EXIT = 1 << 0;
         const W_WAKEUP = 1 << 1;
         const W_BLOCK = 1 << 2;
+        const W_STOP = 1 << 3;
+        const W_CONTINUE = 1 << 4;
     }
 }
 
@@ -103,6 +114,41 @@ bitflags! {
         const WAKEUP = 1 << 0;
         const BLOCK = 1 << 1;
         const EXIT = 1 << 2;
+        const STOP = 1 << 3;
+        const CONTINUE = 1 << 4;
+    }
+}
+
+bitflags! {
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct SpawnFlags: u32 {
+        /// the child joins the process group SpawnAttr::pgroup
+        const SETPGROUP = 1 << 0;
+        /// the child becomes the leader of a new process group
+        const NEWPGROUP = 1 << 1;
+        /// the child runs in the working directory SpawnAttr::cwd
+        const SETCWD = 1 << 2;
+    }
+}
+
+bitflags! {
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct RandomFlags: u32 {
+        /// fail with WouldBlock instead of waiting for the entropy pool to be seeded
+        const NONBLOCK = 1 << 0;
+    }
+}
+
+bitflags! {
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct CloneFlags: u32 {
+        /// a new mount namespace, starting with a copy of the mounts of the creator
+        const NEWNS = 1 << 0;
+        /// a new pid namespace, nested in the one of the creator
+        const NEWPID = 1 << 1;
     }
 }
 
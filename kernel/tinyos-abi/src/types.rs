@@ -1,4 +1,11 @@
-use crate::flags::{NodePermissions, NodeType, OpenOptions};
+use crate::flags::{
+    CloneFlags,
+    NodePermissions,
+    NodeType,
+    OpenOptions,
+    SpawnFlags,
+    TaskStateChange,
+};
 
 #[repr(u64)]
 pub enum SysCallDispatch {
@@ -32,6 +39,26 @@ pub enum SysCallDispatch {
     SpawnProcess = 28,
     FStat = 29,
     SetPerm = 30,
+    Dup2 = 31,
+    Dup3 = 32,
+    GetRLimit = 33,
+    SetRLimit = 34,
+    SendFile = 35,
+    GetCpu = 36,
+    WaitId = 37,
+    MemInfo = 38,
+    TcGetPgrp = 39,
+    TcSetPgrp = 40,
+    GetRandom = 41,
+    SetTimes = 42,
+    GetXattr = 43,
+    SetXattr = 44,
+    RemoveXattr = 45,
+    ListXattr = 46,
+    SendFd = 47,
+    RecvFd = 48,
+    RingSetup = 49,
+    RingEnter = 50,
 }
 
 #[repr(u64)]
@@ -63,19 +90,52 @@ pub enum SysErrCode {
     NoProcess = 24,
     TimerExp = 25,
     WouldBlock = 26,
+    TooManyFiles = 27,
+    NotADir = 28,
+    IsADir = 29,
+    NotSupported = 30,
+    InProgress = 31,
 }
 
-const MAX_ERRNO: u64 = 26;
-
 impl TryFrom<u64> for SysErrCode {
     type Error = i64;
 
     fn try_from(value: u64) -> Result<Self, Self::Error> {
-        if value > MAX_ERRNO {
-            Err(-1)
-        } else {
-            Ok(unsafe { core::mem::transmute::<u64, Self>(value) })
-        }
+        // 4 is not assigned, thus the codes are not contiguous
+        Ok(match value {
+            0 => Self::NoErr,
+            1 => Self::AccessDenied,
+            2 => Self::OpDenied,
+            3 => Self::AddrInUse,
+            5 => Self::AddrNotAvail,
+            6 => Self::AddrNotValid,
+            7 => Self::BadFd,
+            8 => Self::BadMsg,
+            9 => Self::BadRqstD,
+            10 => Self::Cancelled,
+            11 => Self::NoChild,
+            12 => Self::SendErr,
+            13 => Self::Deadlock,
+            14 => Self::DiskFull,
+            15 => Self::FileExists,
+            16 => Self::FileTooBig,
+            17 => Self::InvalidArg,
+            18 => Self::IO,
+            19 => Self::NoDevice,
+            20 => Self::NoFile,
+            21 => Self::OOM,
+            22 => Self::DirNotEmpty,
+            23 => Self::InvalidSeek,
+            24 => Self::NoProcess,
+            25 => Self::TimerExp,
+            26 => Self::WouldBlock,
+            27 => Self::TooManyFiles,
+            28 => Self::NotADir,
+            29 => Self::IsADir,
+            30 => Self::NotSupported,
+            31 => Self::InProgress,
+            _ => return Err(-1),
+        })
     }
 }
 
@@ -127,6 +187,17 @@ pub struct FDOpen {
     pub flags: OpenOptions,
 }
 
+/// attributes of a process created by spawn_process. pgroup and cwd are only read, if flags selects them
+#[repr(C)]
+#[derive(Debug)]
+pub struct SpawnAttr {
+    pub flags: SpawnFlags,
+    pub pgroup: u64,
+    /// relative to the working directory of the caller
+    pub cwd: FatPtr<u8>,
+    pub clone_flags: CloneFlags,
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FStat {
@@ -134,6 +205,10 @@ pub struct FStat {
     pub t_create: u64,
     /// time of last modifcation in secs since startup
     pub t_mod: u64,
+    /// time of last read in secs since startup
+    pub t_access: u64,
+    /// time of last change of the contents or the metadata, like permissions, in secs since startup
+    pub t_change: u64,
     pub size: usize,
     pub permissions: NodePermissions,
     pub node_type: NodeType,
@@ -144,6 +219,8 @@ impl Default for FStat {
         Self {
             t_create: 0,
             t_mod: 0,
+            t_access: 0,
+            t_change: 0,
             size: usize::MAX,
             permissions: NodePermissions::default(),
             node_type: NodeType::VOID,
@@ -151,6 +228,60 @@ impl Default for FStat {
     }
 }
 
+/// selects the children a wait_id call waits for
+#[repr(u64)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdType {
+    /// any child
+    All = 0,
+    /// the child with the given pid
+    Pid = 1,
+    /// any child in the given process group
+    PGrid = 2,
+}
+
+impl TryFrom<u64> for IdType {
+    type Error = u64;
+
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => Self::All,
+            1 => Self::Pid,
+            2 => Self::PGrid,
+            _ => Err(value)?,
+        })
+    }
+}
+
+/// the state change of a child as reported by wait_id
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChildInfo {
+    pub pid: u64,
+    /// one of EXIT, STOP or CONTINUE
+    pub change: TaskStateChange,
+    /// the exit code if change is EXIT, 0 otherwise
+    pub status: u64,
+}
+
+/// the memory usage of a process as reported by mem_info. Sizes are in bytes
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MemInfo {
+    /// user memory mapped into the process
+    pub resident: u64,
+    /// the part of resident, which is shared copy on write with other processes
+    pub shared: u64,
+    /// the part of resident, which is owned by someone else, like mapped files
+    pub borrowed: u64,
+    /// memory used by the page tables of the process
+    pub page_tables: u64,
+    /// total size of all vmas
+    pub virt: u64,
+    /// number of vmas
+    pub vmas: u64,
+}
+
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum PermUpdateStrategy {
@@ -172,3 +303,130 @@ impl TryFrom<u64> for PermUpdateStrategy {
         })
     }
 }
+
+#[repr(u64)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    /// the maximum number of open fds of a process
+    NoFile = 0,
+}
+
+impl TryFrom<u64> for Resource {
+    type Error = u64;
+
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => Self::NoFile,
+            _ => Err(value)?,
+        })
+    }
+}
+
+/// an entry of the auxiliary vector, which ends with an entry of key consts::AT_NULL
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AuxEntry {
+    pub key: u64,
+    pub val: u64,
+}
+
+/// the times passed to set_times, in secs since startup. Either may be consts::UTIME_NOW or consts::UTIME_OMIT
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileTimes {
+    pub access: u64,
+    pub modify: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Timespec {
+    pub secs: u64,
+    pub nanos: u64,
+}
+
+/// the start of a submission ring, as mapped by mmap on the fd returned by ring_setup. The head and tail counters run freely
+/// and are masked with entries - 1, they must be accessed atomically. Offsets are relative to the start of the mapping
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RingHeader {
+    /// consts::RING_MAGIC
+    pub magic: u64,
+    /// the number of slots of both queues, a power of two
+    pub entries: u32,
+    /// the next submission taken by the kernel, written by the kernel
+    pub sq_head: u32,
+    /// the next free submission slot, written by the process
+    pub sq_tail: u32,
+    /// the next completion taken by the process, written by the process
+    pub cq_head: u32,
+    /// the next free completion slot, written by the kernel
+    pub cq_tail: u32,
+    /// completions dropped, as the completion queue was full
+    pub cq_overflow: u32,
+    pub sq_offset: u64,
+    pub cq_offset: u64,
+    /// the area all buffers and paths of submissions point into
+    pub data_offset: u64,
+    pub data_len: u64,
+}
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RingOp {
+    Nop = 0,
+    Read = 1,
+    Write = 2,
+    /// opens the path in buf with the OpenOptions in fd and completes with the new fd
+    Open = 3,
+}
+
+impl TryFrom<u32> for RingOp {
+    type Error = u32;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Nop),
+            1 => Ok(Self::Read),
+            2 => Ok(Self::Write),
+            3 => Ok(Self::Open),
+            _ => Err(value),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SubmissionEntry {
+    /// a RingOp
+    pub op: u32,
+    pub fd: u32,
+    /// the offset in the file, or consts::RING_CURSOR to use and advance its cursor
+    pub offset: u64,
+    /// offset of the buffer in the data area of the ring
+    pub buf: u64,
+    pub len: u64,
+    /// passed back in the completion
+    pub user_data: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CompletionEntry {
+    pub user_data: u64,
+    /// the number of bytes transferred, the new fd for RingOp::Open, or a negated SysErrCode
+    pub res: i64,
+}
+
+/// the start of the vDSO code page. The functions are located at the given offsets from the header and follow the
+/// SysV calling convention:
+/// - `clock_gettime(clock: u64, ts: *mut Timespec) -> i64` returns 0, or -1 for an unknown clock
+/// - `getpid() -> u64`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct VdsoHeader {
+    /// consts::VDSO_MAGIC
+    pub magic: u64,
+    pub clock_gettime: u64,
+    pub getpid: u64,
+}